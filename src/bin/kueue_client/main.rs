@@ -8,16 +8,21 @@ use anyhow::{anyhow, bail, Result};
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
 use kueue_lib::{
-    client::{cli, cli::Cli, Client},
+    client::{cli, cli::Cli, doctor, init_config, run_local, standalone, Client, ClientError},
     config::Config,
 };
 use simple_logger::SimpleLogger;
-use std::io::stdout;
+use std::{ffi::OsString, io::stdout};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Read command line arguments.
-    let args = Cli::parse();
+    // Read command line arguments. Aliases from the config file are expanded
+    // first. Errors in the config file are reported when it is loaded below.
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if let Ok(config) = Config::new(cli::config_path_from_args(&args)) {
+        args = cli::expand_aliases(args, &config.client_settings)?;
+    }
+    let args = Cli::parse_from(args);
 
     // Generate shell completion scripts.
     if let cli::Command::Complete { shell } = args.command {
@@ -27,20 +32,64 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Create config file interactively. This must happen before
+    // loading the config, which creates a template if there is none.
+    if let cli::Command::Config {
+        command: cli::ConfigCommand::Init { force },
+    } = args.command
+    {
+        return init_config(args.config.clone(), force);
+    }
+
     // Read configuration from file or defaults.
-    let config =
+    let mut config =
         Config::new(args.config.clone()).map_err(|e| anyhow!("Failed to load config: {}", e))?;
     // If there is no config file, create template.
     if let Err(e) = config.create_template(args.config.clone()) {
         bail!("Could not create config file: {}", e);
     }
+    // Select server profile, if requested.
+    if let Some(profile) = &args.profile {
+        config.apply_profile(profile)?;
+    }
 
     // Initialize logger.
     SimpleLogger::new()
         .with_level(config.get_log_level()?.to_level_filter())
         .init()?;
 
-    // Run client.
-    let mut client = Client::new(args, config).await?;
-    client.run().await
+    // Check setup instead of connecting right away.
+    if let cli::Command::Config {
+        command: cli::ConfigCommand::Doctor,
+    } = args.command
+    {
+        return doctor(args, config).await;
+    }
+
+    // Run job definition locally without contacting the server.
+    if let cli::Command::RunLocal { path } = args.command {
+        let exit_code = run_local(path, config).await?;
+        // Negative exit codes of Kueue, e.g., for spawn failures, become 1.
+        std::process::exit(if (0..=255).contains(&exit_code) {
+            exit_code
+        } else {
+            1
+        });
+    }
+
+    // Run server and worker in this process instead of connecting to a server.
+    if let cli::Command::Standalone = args.command {
+        return standalone(config).await;
+    }
+
+    // Run client. Distinct exit codes let scripts branch on the outcome.
+    let result = match Client::new(args, config).await {
+        Ok(mut client) => client.run().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(ClientError::exit_code_of(&e));
+    }
+    Ok(())
 }
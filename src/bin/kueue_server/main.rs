@@ -10,6 +10,8 @@ use kueue_lib::{config::Config, server::TcpServer};
 use simple_logger::SimpleLogger;
 use std::path::PathBuf;
 use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 
 /// Command line interface for the server.
 #[derive(Parser, Debug)]
@@ -33,10 +35,12 @@ async fn main() -> Result<()> {
         bail!("Could not create config file: {}", e);
     }
 
-    // Initialize logger.
+    // Initialize logger. Messages are filtered by the maximum level only,
+    // so that it can be raised with `kueue admin log-level` at runtime.
     SimpleLogger::new()
-        .with_level(config.get_log_level()?.to_level_filter())
+        .with_level(log::LevelFilter::Trace)
         .init()?;
+    log::set_max_level(config.get_log_level()?.to_level_filter());
 
     // Start server and listen for incoming connections.
     let mut server = TcpServer::new(config);
@@ -45,9 +49,26 @@ async fn main() -> Result<()> {
         .await
         .map_err(|e| anyhow!("Failed to start server: {}", e))?;
 
-    // Shutdown when receiving interrupt signal.
-    ctrl_c().await?;
+    // Shutdown when receiving interrupt or termination signal.
+    shutdown_signal().await?;
     server.stop().await?;
 
     Ok(())
 }
+
+/// Wait for an interrupt or termination signal.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = ctrl_c() => result?,
+        _ = terminate.recv() => log::info!("Received termination signal!"),
+    }
+    Ok(())
+}
+
+/// Wait for an interrupt signal. Termination signals are Unix-specific.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    Ok(ctrl_c().await?)
+}
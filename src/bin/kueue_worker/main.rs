@@ -18,6 +18,9 @@ pub struct Cli {
     /// Path to config file.
     #[arg(short, long, id = "PATH")]
     pub config: Option<PathBuf>,
+    /// Run the configured self-test probes before accepting any jobs.
+    #[arg(long)]
+    pub self_test: bool,
 }
 
 #[tokio::main]
@@ -40,6 +43,9 @@ async fn main() -> Result<()> {
 
     // Start worker and connect to server.
     let mut worker = TcpWorker::new(config);
+    if args.self_test {
+        worker.enable_self_test();
+    }
     worker.start().await.map_err(|e| anyhow!("Failed to start worker: {}", e))?;
 
     // Shutdown when receiving interrupt signal.
@@ -0,0 +1,152 @@
+//! Interactive allocations with a terminal relayed through the server.
+
+use super::{error::ClientError, Client};
+use crate::{
+    messages::{ClientToServerMessage, ServerToClientMessage},
+    structs::{JobInfo, JobStatus},
+};
+use anyhow::{bail, Result};
+use crossterm::terminal;
+use std::io::IsTerminal;
+use tokio::{
+    io::{stdin, stdout, AsyncReadExt, AsyncWriteExt},
+    time::{interval, Duration},
+};
+
+/// Puts the local terminal into raw mode while in scope, so that
+/// all key strokes, including Ctrl-C, are passed to the remote job.
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn new() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawModeGuard)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if let Err(e) = terminal::disable_raw_mode() {
+            log::error!("Failed to restore terminal: {}", e);
+        }
+    }
+}
+
+impl Client {
+    /// Issue an interactive job, wait until it is running,
+    /// and relay the job's terminal to the local terminal.
+    pub(super) async fn alloc(&mut self, job_info: JobInfo) -> Result<()> {
+        // Issue new job.
+        let message = ClientToServerMessage::IssueJob(job_info);
+        self.stream.send(&message).await?;
+
+        // Await acceptance.
+        let job_id = self.await_job_acceptance().await?.job_id;
+
+        // Attach to the job. This also notifies us about status updates.
+        let message = ClientToServerMessage::AttachJob { job_id };
+        self.stream.send(&message).await?;
+        eprintln!("Waiting for allocation of job {}...", job_id);
+
+        // Await start of the job.
+        let mut early_output = Vec::new();
+        loop {
+            match self.receive().await? {
+                ServerToClientMessage::JobUpdated(job_info) => match job_info.status {
+                    JobStatus::Running { worker, .. } => {
+                        eprintln!("Allocation granted on worker {}.", worker);
+                        break;
+                    }
+                    JobStatus::Finished { comment, .. } => {
+                        bail!(
+                            "Job {} finished before it could be attached: {}",
+                            job_id,
+                            comment
+                        )
+                    }
+                    JobStatus::Canceled { .. } => bail!("Job {} has been canceled!", job_id),
+                    _ => {}
+                },
+                // Output might overtake the status update.
+                ServerToClientMessage::JobOutput { data, .. } => early_output.extend(data),
+                ServerToClientMessage::RequestResponse { success, text } if !success => {
+                    bail!("Failed to attach to job {}: {}", job_id, text)
+                }
+                other => bail!("Expected JobUpdated, received: {other:?}"),
+            }
+        }
+
+        // Relay the terminal until the job ends.
+        let raw_mode = if std::io::stdin().is_terminal() {
+            Some(RawModeGuard::new()?)
+        } else {
+            None
+        };
+        let mut stdout = stdout();
+        stdout.write_all(&early_output).await?;
+        stdout.flush().await?;
+
+        let mut stdin = stdin();
+        let mut stdin_open = true;
+        let mut input_buffer = [0u8; 1024];
+        let mut terminal_size = None;
+        let mut resize_timer = interval(Duration::from_millis(500));
+
+        let job_info = loop {
+            tokio::select! {
+                // Forward local input to the job.
+                bytes_read = stdin.read(&mut input_buffer), if stdin_open => {
+                    match bytes_read? {
+                        0 => stdin_open = false,
+                        n => {
+                            let data = input_buffer[..n].to_vec();
+                            let message = ClientToServerMessage::JobInput { job_id, data };
+                            self.stream.send(&message).await?;
+                        }
+                    }
+                }
+                // Print output of the job and watch for the job to end.
+                message = self.receive() => {
+                    match message? {
+                        ServerToClientMessage::JobOutput { data, .. } => {
+                            stdout.write_all(&data).await?;
+                            stdout.flush().await?;
+                        }
+                        ServerToClientMessage::JobUpdated(job_info) => match job_info.status {
+                            JobStatus::Finished { .. } | JobStatus::Canceled { .. } => {
+                                break Some(job_info)
+                            }
+                            _ => {}
+                        },
+                        ServerToClientMessage::Bye => break None,
+                        other => log::warn!("Unexpected message: {:?}", other),
+                    }
+                }
+                // Keep the size of the remote terminal in sync.
+                _ = resize_timer.tick() => {
+                    if let Ok((cols, rows)) = terminal::size() {
+                        if terminal_size != Some((cols, rows)) {
+                            terminal_size = Some((cols, rows));
+                            let message = ClientToServerMessage::ResizeTerminal { job_id, rows, cols };
+                            self.stream.send(&message).await?;
+                        }
+                    }
+                }
+            }
+        };
+
+        // Restore terminal before printing the outcome.
+        drop(raw_mode);
+        match &job_info {
+            Some(JobInfo {
+                status: JobStatus::Finished { return_code, .. },
+                ..
+            }) => eprintln!("Allocation ended with exit code {}.", return_code),
+            _ => eprintln!("Allocation has been canceled."),
+        }
+        match job_info {
+            Some(job_info) => Ok(ClientError::check_job(&job_info)?),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 //! Command line interface for the client application.
 
-use clap::{Parser, Subcommand};
+use crate::{config::ClientSettings, structs::GpuMode};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf};
 
 /// Command line interface for the client.
 #[derive(Clone, Parser, Debug)]
@@ -11,13 +14,133 @@ pub struct Cli {
     /// Path to config file.
     #[arg(short, long, id = "PATH")]
     pub config: Option<PathBuf>,
+    /// Name of the server profile to use, as defined in the config file.
+    #[arg(short = 'P', long, id = "NAME")]
+    pub profile: Option<String>,
+    /// How to display dates and times. Overrides the config setting.
+    #[arg(long, value_enum, id = "FORMAT")]
+    pub time_format: Option<TimeFormat>,
+    /// When to use colors in the output. Overrides the config setting.
+    #[arg(long, value_enum, id = "WHEN")]
+    pub color: Option<ColorChoice>,
+    /// Color theme of the output. Overrides the config setting.
+    #[arg(long, value_enum, id = "THEME")]
+    pub theme: Option<ColorTheme>,
+    /// Print only IDs instead of tables, receipts, and progress, e.g., for
+    /// shell scripts.
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Format of the output of commands that show jobs, workers, resources,
+    /// or usage. JSON is printed as sent by the server, e.g., for scripts.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        id = "OUTPUT",
+        conflicts_with = "quiet"
+    )]
+    pub output: OutputFormat,
     /// Subcommands for Kueue.
     #[command(subcommand)]
     pub command: Command,
 }
 
+/// Display format of dates and times in the client's output. All timestamps
+/// are stored and transmitted in UTC and only converted for display.
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Local time zone, e.g., "today 14:03:12" or "2023-05-17 09:12:44".
+    #[default]
+    Local,
+    /// UTC, e.g., "2023-05-17 07:12:44 UTC".
+    Utc,
+    /// ISO-8601 in UTC, e.g., "2023-05-17T07:12:44Z".
+    Iso,
+    /// Relative to now, e.g., "3h ago".
+    Relative,
+}
+
+/// Format of the client's output.
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tables and text for humans.
+    #[default]
+    Table,
+    /// JSON derived from the messages of the server.
+    Json,
+}
+
+/// When to use colors in the client's output.
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Use colors if writing to a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    /// Always use colors, even if the output is piped.
+    Always,
+    /// Never use colors.
+    Never,
+}
+
+/// Color theme of the client's output.
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq)]
+pub enum ColorTheme {
+    /// Standard terminal colors.
+    #[default]
+    Default,
+    /// Bright colors, better readable on dark backgrounds.
+    Bright,
+    /// No colors, only text attributes like bold and dim.
+    Monochrome,
+}
+
+/// Columns of the job list.
+#[derive(Clone, Copy, ValueEnum, Debug, PartialEq, Eq)]
+pub enum JobColumn {
+    /// Job ID.
+    Id,
+    /// Working directory.
+    Cwd,
+    /// Command line.
+    Cmd,
+    /// Required CPU cores.
+    Cpus,
+    /// Required RAM memory.
+    Memory,
+    /// Additional (global) resources.
+    Resources,
+    /// Assigned worker.
+    Worker,
+    /// Job status.
+    Status,
+    /// Run time of running and finished jobs.
+    Runtime,
+    /// Scheduling priority.
+    Priority,
+    /// Quality of service class.
+    Qos,
+}
+
+/// Sort criteria of the worker list.
+#[derive(Clone, Copy, ValueEnum, Debug, PartialEq, Eq)]
+pub enum WorkerSort {
+    /// Worker ID.
+    Id,
+    /// Worker name.
+    Name,
+    /// Occupation of resources by jobs.
+    Busy,
+    /// System load (1 minute average), relative to the number of CPU cores.
+    Load,
+    /// Number of jobs offered to or running on the worker.
+    Jobs,
+    /// Time since the worker connected to the server.
+    Uptime,
+}
+
 /// Subcommands for the command line interface.
 #[derive(Clone, Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)] // parsed only once
 pub enum Command {
     /// Issue command to be off-loaded to remote workers.
     ///
@@ -25,9 +148,13 @@ pub enum Command {
     /// printed to stdout. If `--wait` has been given as additional argument,
     /// the return code of the remotely executed job is printed to stdout instead.
     Cmd {
+        /// Apply the defaults of a job template published by the server, see
+        /// `kueue templates list`. Options given here take precedence.
+        #[arg(long)]
+        template: Option<String>,
         /// Job slots occupied by this command.
-        #[arg(short, long, default_value_t = 1)]
-        job_slots: u64,
+        #[arg(short, long)]
+        job_slots: Option<u64>,
         /// Required CPU cores to run the command.
         #[arg(short, long)]
         cpus: Option<u64>,
@@ -46,10 +173,167 @@ pub enum Command {
         /// Block until the job has been finished or canceled.
         #[arg(short, long)]
         wait: bool,
+        /// Command to run before the main command, within the same resource
+        /// allocation. Can be given multiple times to define several steps.
+        #[arg(long, id = "SETUP_CMD")]
+        setup: Vec<String>,
+        /// Command to run after the main command, even if it failed. Can be
+        /// given multiple times to define several steps.
+        #[arg(long, id = "TEARDOWN_CMD")]
+        teardown: Vec<String>,
+        /// Kill the job when a process of the job has consumed more CPU time
+        /// than given, e.g., "90m" or "10h". Other than wall-clock time, CPU
+        /// time accumulates over all threads of a process.
+        #[arg(long, value_parser = parse_duration, id = "DURATION")]
+        cpu_time: Option<u64>,
+        /// Kill the job when it has been running for longer than given, e.g.,
+        /// "30m" or "2h".
+        #[arg(long, value_parser = parse_duration, id = "TIMEOUT")]
+        timeout: Option<u64>,
+        /// Limit each process of the job, e.g., "nofile=1024", "nproc=100",
+        /// or "core=0" (core dump size in bytes). Can be given multiple times.
+        /// Defaults to the worker's `default_process_limits`.
+        #[arg(long, value_parser = parse_process_limit, id = "LIMIT")]
+        limit: Vec<(String, u64)>,
+        /// Only run the job on workers with the given label, e.g.,
+        /// "arch=avx512". Can be given multiple times.
+        #[arg(long, value_parser = parse_label, id = "LABEL")]
+        require: Vec<(String, String)>,
+        /// Prefer the given worker, e.g., because it has staged inputs. The
+        /// job runs elsewhere if the worker is busy or offline.
+        #[arg(long, id = "WORKER", conflicts_with = "JOB")]
+        prefer_worker: Option<String>,
+        /// Prefer the worker the given job runs or has run on, e.g., to reuse
+        /// its warm caches. The job runs elsewhere if the worker is busy.
+        #[arg(long, id = "JOB")]
+        prefer_worker_of: Option<u64>,
+        /// Requeue the job if it fails with the given exit code, e.g., on
+        /// temporary failures. Use "any" to match any non-zero exit code.
+        #[arg(long, value_name = "EXIT_CODE")]
+        retry_on: Vec<String>,
+        /// Requeue the job to run on a different worker if it fails with the
+        /// given exit code. Use "any" to match any non-zero exit code.
+        #[arg(long, value_name = "EXIT_CODE")]
+        retry_elsewhere_on: Vec<String>,
+        /// Never retry the job if it fails with the given exit code. This
+        /// takes precedence over retries on "any" exit code.
+        #[arg(long, value_name = "EXIT_CODE")]
+        fail_on: Vec<String>,
+        /// Maximum number of retries triggered by exit codes.
+        #[arg(long, default_value_t = 3)]
+        max_retries: u32,
+        /// Requeue the job up to N times if it fails with any non-zero exit
+        /// code or its worker is lost. Shorthand for "--retry-on any
+        /// --max-retries N".
+        #[arg(long, value_name = "N", conflicts_with = "max_retries")]
+        retries: Option<u32>,
+        /// Start the job only after the given jobs have succeeded.
+        #[arg(
+            long,
+            visible_alias = "after",
+            value_delimiter = ',',
+            value_name = "JOB_IDS"
+        )]
+        after_ok: Vec<u64>,
+        /// Start the job only after the given jobs have failed.
+        #[arg(long, value_delimiter = ',', value_name = "JOB_IDS")]
+        after_failed: Vec<u64>,
+        /// Start the job only after the given jobs have finished or
+        /// been canceled, regardless of their outcome.
+        #[arg(long, value_delimiter = ',', value_name = "JOB_IDS")]
+        after_any: Vec<u64>,
+        /// Let the server adjust CPU cores and RAM to the measured usage of
+        /// similar jobs in the past, i.e., the same program run by the same
+        /// user in the same working directory.
+        #[arg(long)]
+        auto_resources: bool,
+        /// Run the job within the named resource pool of a worker, e.g., "ci".
+        /// The job is only offered to workers that define the pool. Defaults
+        /// to `job_default_pool` of the client config, if set.
+        #[arg(long, id = "POOL")]
+        pool: Option<String>,
+        /// Add the job to a named submission group, e.g., a parameter sweep.
+        /// All jobs of a group can be followed with `kueue observe`.
+        #[arg(long)]
+        group: Option<String>,
+        /// Account the job to a project configured on the server, e.g., a
+        /// grant or a team. Defaults to `job_default_project` of the client
+        /// config, if set.
+        #[arg(long)]
+        project: Option<String>,
+        /// Quality of service class configured on the server, e.g., "urgent".
+        /// Defaults to the server's `default_qos`, if set.
+        #[arg(long)]
+        qos: Option<String>,
+        /// Jobs with higher priority are started first, e.g., "10". Negative
+        /// values let other jobs go first.
+        #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+        priority: i32,
+        /// Let the worker create the working directory if it does not exist,
+        /// e.g., when submitting from a directory that is local to this machine.
+        #[arg(long)]
+        create_cwd: bool,
+        /// Record the environment variables and umask of the current shell
+        /// with the job. Use `kueue show-job --env` to view them later.
+        #[arg(long)]
+        snapshot_env: bool,
+        /// Encrypt the command and its outputs with the payload key, so that
+        /// only workers with the same key can run the job.
+        #[arg(long)]
+        encrypt: bool,
+        /// Number of GPU devices required by the job.
+        #[arg(long, default_value_t = 0)]
+        gpus: u64,
+        /// How the job uses its GPUs: "exclusive", "shared" (with other shared
+        /// jobs, e.g., via MPS), or "mig:<profile>" for slices of partitioned
+        /// devices, e.g., "mig:1g.5gb".
+        #[arg(long, default_value = "exclusive", id = "MODE")]
+        gpu_mode: GpuMode,
+        /// Pin the job to whole physical cores, one for each requested CPU,
+        /// including their SMT siblings. No other bound job runs on them.
+        #[arg(long, conflicts_with = "no_smt")]
+        physical_cores: bool,
+        /// Pin the job to one hardware thread of each of its physical cores,
+        /// keeping the SMT siblings idle, e.g., for reproducible benchmarks.
+        #[arg(long)]
+        no_smt: bool,
         /// Positional arguments that define the command.
         #[command(subcommand)]
         args: CmdArgs,
     },
+    /// Issue the jobs of job definition files (YAML) in a batch.
+    ///
+    /// Each file holds a single job definition, as for `kueue run-local`, or
+    /// a list of them. The IDs of accepted jobs are printed to stdout. Jobs
+    /// rejected by the server are summarized at the end.
+    Submit {
+        /// Paths to the job definition files.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Allocate resources for an interactive session on a remote worker.
+    ///
+    /// Once the allocation is granted, the command runs in a terminal on the
+    /// worker, which is relayed through the server to the local terminal.
+    /// If no command is given, the user's shell ($SHELL) is started. The
+    /// allocation ends when the command exits or the connection is lost.
+    Alloc {
+        /// Job slots occupied by the allocation.
+        #[arg(short, long)]
+        job_slots: Option<u64>,
+        /// Required CPU cores for the allocation.
+        #[arg(short, long)]
+        cpus: Option<u64>,
+        /// Required RAM memory (in megabytes) for the allocation.
+        #[arg(short, long)]
+        ram_mb: Option<u64>,
+        /// Additional resources, such as licenses.
+        #[arg(id = "resource", long)]
+        resources: Vec<String>,
+        /// Positional arguments that define the command.
+        #[command(subcommand)]
+        args: Option<CmdArgs>,
+    },
     /// Query information about scheduled, running, and finished jobs.
     ListJobs {
         /// Number of most recent jobs to query.
@@ -73,16 +357,105 @@ pub enum Command {
         /// Show canceled jobs.
         #[arg(short, long)]
         canceled: bool,
+        /// Comma-separated list of columns to show, e.g., "id,status,cmd".
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Vec<JobColumn>,
+        /// Wrap long texts into multiple lines instead of shortening them.
+        #[arg(long)]
+        wrap: bool,
     },
     /// Query information about a specific job.
     ShowJob {
         /// ID of the job to be queried.
         job_id: u64,
+        /// Print the environment recorded with `kueue cmd --snapshot-env`
+        /// as shell commands instead.
+        #[arg(long)]
+        env: bool,
+        /// Print the signed provenance record of the finished job as JSON
+        /// and verify its signature.
+        #[arg(long, conflicts_with = "env")]
+        provenance: bool,
+        /// Print the execution environment of the worker the job finished on,
+        /// if the worker captures it.
+        #[arg(long, conflicts_with_all = ["env", "provenance"])]
+        environment: bool,
+    },
+    /// Print the output of a job.
+    Log {
+        /// ID of the job.
+        job_id: u64,
+        /// Follow the output of the running job until it has concluded.
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Download the outputs of a job into files.
+    ///
+    /// Writes `<job_id>.stdout` and `<job_id>.stderr`. The transfer is
+    /// verified with checksums before anything is written.
+    Fetch {
+        /// ID of the job.
+        job_id: u64,
+        /// Directory to write the outputs to.
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+    /// Compare the outputs of two jobs, e.g., of a failing and a passing run.
+    ///
+    /// Prints a unified diff of stdout and stderr, computed by the server.
+    Diff {
+        /// ID of the first job.
+        job_a: u64,
+        /// ID of the second job.
+        job_b: u64,
     },
     /// Block until a certain job has finished.
+    #[command(visible_alias = "wait")]
     WaitJob {
         /// ID of the job to be waited for.
         job_id: u64,
+        /// Shell command run on this machine when the job has concluded, e.g.,
+        /// to copy results. The placeholders {id}, {status}, and {exit_code}
+        /// are replaced by the job's ID, its final status ("succeeded",
+        /// "failed", or "canceled"), and its exit code (empty if canceled).
+        #[arg(long, id = "CMD")]
+        on_finish: Option<String>,
+    },
+    /// Follow all jobs of a submission group until they have concluded.
+    ///
+    /// Jobs issued to the group while observing are followed as well.
+    Observe {
+        /// Name of the group, as given to `kueue cmd --group`.
+        #[arg(long)]
+        group: String,
+    },
+    /// Forward a local port to a port opened by a running job.
+    ///
+    /// Connections to the local port are relayed through the server to the
+    /// worker, where they are connected to the given port on localhost. This
+    /// is useful for web interfaces, e.g., of Jupyter or TensorBoard, since
+    /// workers usually cannot be reached directly. Forwarding continues until
+    /// the job ends or the client is interrupted with Ctrl-C.
+    ForwardPort {
+        /// ID of the running job.
+        job_id: u64,
+        /// Port opened by the job on the worker.
+        port: u16,
+        /// Local port to listen on. Defaults to the same port as on the worker.
+        #[arg(short, long)]
+        local_port: Option<u16>,
+    },
+    /// Raise the priority of a pending job.
+    ///
+    /// The job is offered to workers before the jobs of the same priority
+    /// issued earlier. Requires the admin secret, unless the server lets
+    /// owners boost their own jobs.
+    Boost {
+        /// ID of the pending job.
+        job_id: u64,
+        /// Move the job to the front of the queue, before all other jobs.
+        #[arg(long)]
+        to_front: bool,
     },
     /// Remove a job from the queue.
     ///
@@ -95,6 +468,17 @@ pub enum Command {
         /// worker. Otherwise, the job will continue without any effect.
         #[arg(short, long, default_value_t = false)]
         kill: bool,
+        /// Also cancel all jobs depending on the job, directly or indirectly,
+        /// whatever their dependency conditions.
+        #[arg(long, conflicts_with = "orphan")]
+        cascade: bool,
+        /// Leave jobs depending on the job pending, even if their dependencies
+        /// can no longer be met. By default, such jobs are canceled.
+        #[arg(long)]
+        orphan: bool,
+        /// Why the job is removed. Shown in job listings.
+        #[arg(long)]
+        reason: Option<String>,
     },
     /// Remove finished and canceled jobs from the server.
     ///
@@ -106,14 +490,98 @@ pub enum Command {
         all: bool,
     },
     /// Query information about available workers.
-    ListWorkers,
+    ListWorkers {
+        /// Sort workers by the given criterion. Load-related criteria sort the
+        /// busiest workers first.
+        #[arg(short, long, value_enum, default_value_t = WorkerSort::Id)]
+        sort: WorkerSort,
+        /// Reverse the sort order.
+        #[arg(short, long)]
+        reverse: bool,
+        /// Show only workers that regularly send updates to the server.
+        #[arg(long, conflicts_with = "offline_only")]
+        online_only: bool,
+        /// Show only workers that stopped sending updates to the server.
+        #[arg(long)]
+        offline_only: bool,
+        /// Show only workers with the given tag. Can be given multiple times
+        /// to show workers that have all of the given tags.
+        #[arg(short, long)]
+        tag: Vec<String>,
+    },
     /// Query information about a specific worker.
     ShowWorker {
         /// ID of the worker to be queried.
         worker_id: u64,
     },
-    /// Show global resources configured on the server.
+    /// Show global resources configured on the server and worker resources.
     ListResources,
+    /// Show the usage of the cluster, i.e., jobs and consumed CPU hours.
+    Stats {
+        /// Break down the usage by project, including the projects' quotas.
+        #[arg(long)]
+        by_project: bool,
+        /// Show resources of workers that sat idle while pending jobs did not
+        /// fit into them, per worker and hour.
+        #[arg(long, conflicts_with = "by_project")]
+        idle: bool,
+    },
+    /// Show the accounting of finished jobs per user, i.e., jobs and consumed
+    /// core hours. Requires a database on the server.
+    Acct {
+        /// Show only the usage of the given user.
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Include jobs that finished at or after this time, e.g.,
+        /// "2024-03-01", "2024-03-01 14:00", or "7d" for seven days ago.
+        #[arg(long, value_parser = parse_time, id = "TIME")]
+        since: Option<DateTime<Utc>>,
+        /// Include jobs that finished before this time. Accepts the same
+        /// formats as `--since`.
+        #[arg(long, value_parser = parse_time, id = "UNTIL")]
+        until: Option<DateTime<Utc>>,
+    },
+    /// Job templates published by the server.
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+    /// Show a live dashboard of workers, resources, and jobs.
+    ///
+    /// Select jobs with the arrow keys and press enter to inspect them. Press
+    /// 'c' to cancel, 'K' to kill, and 'C' to clean up finished jobs.
+    #[command(visible_alias = "tui")]
+    Top {
+        /// Seconds between refreshes of the dashboard.
+        #[arg(short, long, default_value_t = 2)]
+        interval: u64,
+    },
+    /// Administrative commands for server operators.
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Run a job definition (YAML) on the local machine for debugging.
+    ///
+    /// The job is run exactly like on a worker, without contacting the
+    /// server. The definition supports the options of `kueue cmd`, e.g.:
+    /// `cmd: ./run.sh`, `cpus: 4`, `setup: ["mkdir -p out"]`.
+    RunLocal {
+        /// Path to the job definition file.
+        path: PathBuf,
+    },
+    /// Run a server and a worker on this machine until interrupted.
+    ///
+    /// No further setup is needed: Jobs can be issued right away from other
+    /// terminals, e.g., with `kueue cmd`. The server only accepts connections
+    /// from this machine.
+    #[command(visible_alias = "local")]
+    Standalone,
+    /// Set up and check the client's config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
     /// Generate shell completion script for bash, zsh, etc.
     ///
     /// An easy long-term solution is to put `eval "$(kueue complete bash)"`
@@ -127,6 +595,314 @@ pub enum Command {
     },
 }
 
+/// Subcommands of `kueue templates`.
+#[derive(Clone, Subcommand, Debug)]
+pub enum TemplatesCommand {
+    /// List the job templates and their defaults.
+    List,
+}
+
+/// Administrative subcommands. All of them require authentication, with the
+/// `admin_secret` if it is configured on the server.
+#[derive(Clone, Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Set the message of the day, shown whenever a client connects.
+    ///
+    /// Omit the text to clear the message. The message is kept until the
+    /// server restarts. Use the `motd` server setting to set it permanently.
+    Motd {
+        /// Text of the message.
+        text: Option<String>,
+    },
+    /// Send a message to all connected clients.
+    ///
+    /// Clients connecting later receive the message as well, until it expires.
+    Broadcast {
+        /// Text of the message.
+        text: String,
+        /// Minutes after which the message is no longer shown to new clients.
+        #[arg(short, long, default_value_t = 60)]
+        minutes: u64,
+    },
+    /// Drop the connection to a dead or misbehaving worker immediately.
+    ///
+    /// Jobs running on the worker are killed, if the worker still responds.
+    /// By default, they are requeued to run on other workers. The worker is
+    /// blocked from reconnecting for a cooldown period.
+    RemoveWorker {
+        /// ID of the worker to be removed.
+        worker_id: u64,
+        /// Mark running jobs as failed instead of requeuing them.
+        #[arg(long, conflicts_with = "requeue_jobs")]
+        kill_jobs: bool,
+        /// Requeue running jobs to be run on other workers (default).
+        #[arg(long)]
+        requeue_jobs: bool,
+        /// Minutes before the worker is allowed to reconnect.
+        #[arg(long, default_value_t = 10)]
+        cooldown: u64,
+    },
+    /// List all live client and worker connections.
+    ///
+    /// Shows the remote address, connect time, Kueue version, and bytes
+    /// transferred of each connection, e.g., to debug stuck sessions.
+    Connections,
+    /// Show the scheduling decisions recorded for a job.
+    ///
+    /// Lists the workers the job has been evaluated for, whether it has been
+    /// offered, and why it has been skipped. Requires the `scheduling_trace`
+    /// server setting.
+    Trace {
+        /// ID of the job.
+        job_id: u64,
+    },
+    /// Give a connected worker a new name.
+    ///
+    /// Useful if several workers connected with the same name. The new name
+    /// is kept until the worker reconnects. To rename a worker permanently,
+    /// change the `worker_name` in the worker's config file.
+    RenameWorker {
+        /// ID of the worker to be renamed.
+        worker_id: u64,
+        /// New name of the worker.
+        name: String,
+    },
+    /// Stop offering new jobs to a worker, e.g., before maintenance.
+    ///
+    /// Running jobs are not affected. The worker stays drained if it
+    /// reconnects, until `--undo` is given.
+    Drain {
+        /// ID of the worker to be drained.
+        worker_id: u64,
+        /// Offer new jobs to the worker again.
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Offer a worker only jobs of the given user.
+    ///
+    /// Jobs already offered to or running on the worker are not affected.
+    /// Omit the user to offer any jobs to the worker again.
+    Reserve {
+        /// ID of the worker to be reserved.
+        worker_id: u64,
+        /// User to reserve the worker for.
+        user: Option<String>,
+    },
+    /// Stop a running job cleanly and requeue it on another worker.
+    ///
+    /// The job receives the signal, e.g., to write a checkpoint, and is
+    /// killed if it has not exited after the grace period. Jobs resuming from
+    /// a checkpoint find its path in `KUEUE_CHECKPOINT`.
+    Migrate {
+        /// ID of the job to be moved.
+        job_id: u64,
+        /// Signal sent to the job, e.g., 15 for SIGTERM or 10 for SIGUSR1.
+        #[arg(long, default_value_t = 15)]
+        signal: i32,
+        /// Time the job may take to exit after the signal, e.g., "30s" or "5m".
+        #[arg(long, value_parser = parse_duration, default_value = "60")]
+        grace_period: u64,
+        /// Checkpoint to resume from on the next worker. Without checkpoint,
+        /// the job is run again from the start.
+        #[arg(long)]
+        checkpoint: Option<PathBuf>,
+    },
+    /// Save a copy of the job database while the server is running.
+    ///
+    /// The path refers to the server's file system and must not exist yet.
+    /// Requires the `database` server setting.
+    Snapshot {
+        /// Path of the copy on the server.
+        path: PathBuf,
+    },
+    /// Change the verbosity of the server's log until the server restarts.
+    LogLevel {
+        /// New log level: off, error, warn, info, debug, or trace.
+        level: String,
+    },
+    /// Show the administrative actions requested since the server started,
+    /// including unauthorized attempts.
+    Audit,
+}
+
+/// Subcommands to set up the client.
+#[derive(Clone, Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Create a minimal config file with the server's address and secret.
+    ///
+    /// The values are asked for interactively. Press enter to keep the value
+    /// shown in brackets.
+    Init {
+        /// Overwrite an existing config file.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the config file and the connection to the server step by step.
+    Doctor,
+}
+
+/// Parses a duration like "90", "90s", "30m", "10h", "2d", or "1h30m"
+/// into seconds. Numbers without unit are interpreted as seconds.
+pub fn parse_duration(text: &str) -> Result<u64> {
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let factor = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => bail!("Invalid unit '{}' in duration '{}'!", c, text),
+        };
+        if number.is_empty() {
+            bail!("Missing number before unit '{}' in duration '{}'!", c, text);
+        }
+        seconds += number.parse::<u64>()? * factor;
+        number.clear();
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<u64>()?;
+    } else if seconds == 0 {
+        bail!("Invalid duration '{}'!", text);
+    }
+    Ok(seconds)
+}
+
+/// Parses a point in time like "2024-03-01" (midnight), "2024-03-01 14:00"
+/// in local time, or an RFC 3339 timestamp. A duration like "7d" is taken as
+/// that long ago.
+pub fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").or_else(|_| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+    });
+    if let Ok(local) = local {
+        return match Local.from_local_datetime(&local).earliest() {
+            Some(time) => Ok(time.with_timezone(&Utc)),
+            None => bail!("Time '{}' does not exist in the local time zone!", text),
+        };
+    }
+    match parse_duration(text) {
+        Ok(seconds) => Ok(Utc::now() - chrono::Duration::seconds(seconds as i64)),
+        Err(_) => bail!(
+            "Invalid time '{}', expected a date like 2024-03-01 or a duration like 7d!",
+            text
+        ),
+    }
+}
+
+/// Parses a process limit like "nofile=1024" into its name and value.
+pub fn parse_process_limit(text: &str) -> Result<(String, u64)> {
+    let Some((name, value)) = text.split_once('=') else {
+        bail!("Invalid process limit '{}', expected NAME=VALUE!", text);
+    };
+    if !["nofile", "nproc", "core"].contains(&name) {
+        bail!(
+            "Unknown process limit '{}', expected nofile, nproc, or core!",
+            name
+        );
+    }
+    let value = value
+        .parse()
+        .map_err(|e| anyhow!("Invalid value of process limit '{}': {}", text, e))?;
+    Ok((name.to_string(), value))
+}
+
+/// Parse a worker label constraint like "arch=avx512".
+pub fn parse_label(text: &str) -> Result<(String, String)> {
+    match text.split_once('=') {
+        Some((label, value)) if !label.is_empty() => Ok((label.to_string(), value.to_string())),
+        _ => bail!("Invalid label '{}', expected LABEL=VALUE!", text),
+    }
+}
+
+/// Position of the subcommand in the command line `args`, which start with
+/// the program name. Global options and their values are skipped.
+fn subcommand_position(args: &[OsString]) -> Option<usize> {
+    // Options like "--config PATH", whose value is a separate argument.
+    let options_with_value: Vec<String> = Cli::command()
+        .get_arguments()
+        .filter(|arg| arg.get_action().takes_values())
+        .flat_map(|arg| {
+            let short = arg.get_short().map(|short| format!("-{}", short));
+            let long = arg.get_long().map(|long| format!("--{}", long));
+            short.into_iter().chain(long)
+        })
+        .collect();
+
+    let mut index = 1;
+    while index < args.len() {
+        let arg = args[index].to_string_lossy();
+        if arg == "--" {
+            return (index + 1 < args.len()).then_some(index + 1);
+        } else if !arg.starts_with('-') {
+            return Some(index);
+        } else if options_with_value.iter().any(|option| option == &arg) {
+            index += 1; // skip value
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Path given with the `--config` option, found before the command line is
+/// parsed, since aliases defined in the config file must be expanded first.
+pub fn config_path_from_args(args: &[OsString]) -> Option<PathBuf> {
+    let end = subcommand_position(args).unwrap_or(args.len());
+    let mut path = None;
+    for (index, arg) in args[..end].iter().enumerate().skip(1) {
+        let text = arg.to_string_lossy();
+        if text == "-c" || text == "--config" {
+            path = args.get(index + 1).map(PathBuf::from);
+        } else if let Some(value) = text.strip_prefix("--config=") {
+            path = Some(PathBuf::from(value));
+        } else if let Some(value) = text.strip_prefix("-c").filter(|_| !text.starts_with("--")) {
+            path = Some(PathBuf::from(value.trim_start_matches('=')));
+        }
+    }
+    path
+}
+
+/// Replaces a user-defined alias in the command line `args` by the command
+/// it stands for. Built-in subcommands take precedence over aliases. If no
+/// subcommand is given, the configured default subcommand is inserted,
+/// unless help or version information is requested.
+pub fn expand_aliases(mut args: Vec<OsString>, settings: &ClientSettings) -> Result<Vec<OsString>> {
+    let expand = |text: &str| -> Result<Vec<OsString>> {
+        let words = shell_words::split(text)
+            .map_err(|e| anyhow!("Failed to parse alias '{}': {}", text, e))?;
+        Ok(words.into_iter().map(OsString::from).collect())
+    };
+
+    match subcommand_position(&args) {
+        Some(position) => {
+            let name = args[position].to_string_lossy().to_string();
+            let builtin = Cli::command().find_subcommand(&name).is_some();
+            if let (false, Some(alias)) = (builtin, settings.aliases.get(&name)) {
+                let expansion = expand(alias)?;
+                args.splice(position..=position, expansion);
+            }
+        }
+        None => {
+            let info_requested = args.iter().skip(1).any(|arg| {
+                let arg = arg.to_string_lossy();
+                ["-h", "--help", "-V", "--version"].contains(&arg.as_ref())
+            });
+            if let (false, Some(command)) = (info_requested, &settings.default_command) {
+                args.extend(expand(command)?);
+            }
+        }
+    }
+    Ok(args)
+}
+
 /// Arbitrary command captured from positional arguments.
 #[derive(Clone, Subcommand, Debug)]
 pub enum CmdArgs {
@@ -134,3 +910,80 @@ pub enum CmdArgs {
     /// Captured arguments. This comment does not show up in help.
     Args(Vec<String>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert_eq!(parse_duration("30m").unwrap(), 30 * 60);
+        assert_eq!(parse_duration("1h30m").unwrap(), 90 * 60);
+        assert_eq!(parse_duration("2d").unwrap(), 2 * 24 * 60 * 60);
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("10x").is_err());
+    }
+
+    #[test]
+    fn output_format() {
+        let cli = Cli::try_parse_from(["kueue", "--output", "json", "list-workers"]).unwrap();
+        assert_eq!(cli.output, OutputFormat::Json);
+        let cli = Cli::try_parse_from(["kueue", "list-workers"]).unwrap();
+        assert_eq!(cli.output, OutputFormat::Table);
+        assert!(Cli::try_parse_from(["kueue", "-q", "--output", "json", "list-workers"]).is_err());
+    }
+
+    #[test]
+    fn times() {
+        let time = parse_time("2024-03-01T12:00:00+01:00").unwrap();
+        assert_eq!(time.to_rfc3339(), "2024-03-01T11:00:00+00:00");
+        let date = parse_time("2024-03-01").unwrap();
+        assert_eq!(parse_time("2024-03-01 00:00").unwrap(), date);
+        let ago = Utc::now() - parse_time("2d").unwrap();
+        assert!((ago.num_seconds() - 2 * 24 * 60 * 60).abs() < 60);
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn process_limits() {
+        assert_eq!(
+            parse_process_limit("nofile=1024").unwrap(),
+            ("nofile".to_string(), 1024)
+        );
+        assert!(parse_process_limit("nofile").is_err());
+        assert!(parse_process_limit("stack=8").is_err());
+        assert!(parse_process_limit("core=-1").is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let mut settings = Config::new(Some("no-config".into()))
+            .unwrap()
+            .client_settings;
+        settings
+            .aliases
+            .insert("q".into(), "list-jobs --pending".into());
+        settings.aliases.insert("cmd".into(), "list-workers".into());
+        settings.default_command = Some("list-workers".into());
+        let args =
+            |text: &str| -> Vec<OsString> { text.split_whitespace().map(OsString::from).collect() };
+
+        let expanded = expand_aliases(args("kueue -c my.toml q --running"), &settings).unwrap();
+        assert_eq!(
+            expanded,
+            args("kueue -c my.toml list-jobs --pending --running")
+        );
+        assert_eq!(config_path_from_args(&expanded), Some("my.toml".into()));
+        // Built-in subcommands are not shadowed by aliases.
+        let expanded = expand_aliases(args("kueue cmd ls"), &settings).unwrap();
+        assert_eq!(expanded, args("kueue cmd ls"));
+        // The default subcommand is used if none is given.
+        let expanded = expand_aliases(args("kueue --color never"), &settings).unwrap();
+        assert_eq!(expanded, args("kueue --color never list-workers"));
+        let expanded = expand_aliases(args("kueue --help"), &settings).unwrap();
+        assert_eq!(expanded, args("kueue --help"));
+    }
+}
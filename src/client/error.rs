@@ -0,0 +1,85 @@
+//! Errors of the client with distinct exit codes, so that shell scripts can
+//! branch on the outcome of a command.
+
+use crate::structs::{JobInfo, JobStatus};
+use thiserror::Error;
+
+/// Errors that exit the client with a specific exit code. Other errors exit
+/// with 1, invalid command lines with 2.
+#[derive(Debug, Error, PartialEq)]
+pub enum ClientError {
+    /// The server cannot be reached. Exit code 3.
+    #[error("Failed to connect to the server: {0}")]
+    ConnectionFailed(String),
+    /// The server has rejected the secret. Exit code 4.
+    #[error("Authentication failed!")]
+    AuthenticationFailed,
+    /// The requested job or worker does not exist. Exit code 5.
+    #[error("{0}")]
+    NotFound(String),
+    /// The awaited job has failed or has been canceled. Exit code 6.
+    #[error("{0}")]
+    JobFailed(String),
+}
+
+impl ClientError {
+    /// Exit code of the client for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClientError::ConnectionFailed(_) => 3,
+            ClientError::AuthenticationFailed => 4,
+            ClientError::NotFound(_) => 5,
+            ClientError::JobFailed(_) => 6,
+        }
+    }
+
+    /// Exit code of the client for any error.
+    pub fn exit_code_of(error: &anyhow::Error) -> i32 {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<ClientError>())
+            .map_or(1, ClientError::exit_code)
+    }
+
+    /// Error for a request the server has refused. Unknown jobs and workers
+    /// are reported by the server as "Job not found!" or "Worker not found!".
+    pub fn refused(text: String) -> anyhow::Error {
+        if text.ends_with("not found!") {
+            ClientError::NotFound(text).into()
+        } else {
+            anyhow::Error::msg(text)
+        }
+    }
+
+    /// Returns an error if the concluded job has not succeeded.
+    pub fn check_job(job_info: &JobInfo) -> Result<(), ClientError> {
+        match job_info.status {
+            JobStatus::Finished { return_code: 0, .. } => Ok(()),
+            JobStatus::Finished { return_code, .. } => Err(ClientError::JobFailed(format!(
+                "Job {} failed with exit code {}!",
+                job_info.job_id, return_code
+            ))),
+            _ => Err(ClientError::JobFailed(format!(
+                "Job {} has been canceled!",
+                job_info.job_id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        let error = ClientError::refused("Job not found!".into());
+        assert_eq!(ClientError::exit_code_of(&error), 5);
+        let error = ClientError::refused("Not authenticated!".into());
+        assert_eq!(ClientError::exit_code_of(&error), 1);
+
+        // The exit code is kept if context is added to the error.
+        let error = anyhow::Error::from(ClientError::AuthenticationFailed).context("admin");
+        assert_eq!(ClientError::exit_code_of(&error), 4);
+    }
+}
@@ -0,0 +1,99 @@
+//! Download of job outputs in checksummed chunks.
+
+use super::{error::ClientError, Client};
+use crate::{
+    crypto::sha256_hex,
+    messages::{ClientToServerMessage, ServerToClientMessage},
+    structs::OutputStream,
+};
+use anyhow::{bail, Result};
+use std::{fs, path::Path};
+
+impl Client {
+    /// Download the outputs of the job into the directory. Nothing is written
+    /// unless all outputs match the checksums sent by the server.
+    pub(super) async fn fetch(&mut self, job_id: u64, directory: &Path) -> Result<()> {
+        let message = ClientToServerMessage::FetchJob { job_id };
+        self.stream.send(&message).await?;
+
+        // Collect chunks until the server completes the transfer.
+        let mut outputs = [Vec::new(), Vec::new()];
+        let (encrypted, checksums) = loop {
+            match self.receive().await? {
+                ServerToClientMessage::FetchChunk {
+                    stream,
+                    offset,
+                    data,
+                    ..
+                } => {
+                    let output = &mut outputs[stream as usize];
+                    if offset != output.len() as u64 {
+                        bail!(
+                            "Expected chunk at offset {}, received offset {}!",
+                            output.len(),
+                            offset
+                        );
+                    }
+                    output.extend(data);
+                }
+                ServerToClientMessage::FetchComplete {
+                    encrypted,
+                    stdout_sha256,
+                    stderr_sha256,
+                    ..
+                } => break (encrypted, [stdout_sha256, stderr_sha256]),
+                ServerToClientMessage::RequestResponse { text, .. } => {
+                    return Err(ClientError::refused(text))
+                }
+                other => bail!("Expected FetchChunk, received: {:?}", other),
+            }
+        };
+
+        // Verify all outputs before writing any of them.
+        for (output, checksum) in outputs.iter().zip(&checksums) {
+            match checksum {
+                Some(checksum) if sha256_hex(output) != *checksum => {
+                    bail!(
+                        "Checksum mismatch, the download of job {} is corrupted!",
+                        job_id
+                    )
+                }
+                None if !output.is_empty() => bail!("Received output without checksum!"),
+                _ => {}
+            }
+        }
+
+        if checksums.iter().all(Option::is_none) {
+            if !self.args.quiet {
+                println!("Job {} has no outputs.", job_id);
+            }
+            return Ok(());
+        }
+        fs::create_dir_all(directory)?;
+        let streams = [
+            (OutputStream::Stdout, "stdout"),
+            (OutputStream::Stderr, "stderr"),
+        ];
+        for ((stream, extension), checksum) in streams.into_iter().zip(checksums) {
+            if checksum.is_none() {
+                continue; // job has no such output
+            }
+            let mut output = std::mem::take(&mut outputs[stream as usize]);
+            if encrypted {
+                let text = String::from_utf8(output)?;
+                let cipher = self.payload_cipher()?;
+                output = cipher.decrypt(stream.into(), job_id, &text)?.into_bytes();
+            }
+            let path = directory.join(format!("{}.{}", job_id, extension));
+            fs::write(&path, &output)?;
+            if !self.args.quiet {
+                println!(
+                    "Fetched {} ({} bytes)",
+                    path.to_string_lossy(),
+                    output.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Forwarding of local ports to ports opened by running jobs.
+
+use super::Client;
+use crate::{
+    messages::{ClientToServerMessage, ServerToClientMessage},
+    structs::JobStatus,
+    tunnel,
+};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use tokio::{
+    net::TcpListener,
+    signal::ctrl_c,
+    sync::mpsc::{channel, Sender},
+};
+
+impl Client {
+    /// Listen on `local_port` and relay incoming connections to
+    /// `port` on the worker, until the job ends or Ctrl-C is pressed.
+    pub(super) async fn forward_port(
+        &mut self,
+        job_id: u64,
+        port: u16,
+        local_port: u16,
+    ) -> Result<()> {
+        // Observe the job to stop forwarding when it ends.
+        let message = ClientToServerMessage::ObserveJob { job_id };
+        self.stream.send(&message).await?;
+        match self.receive().await? {
+            ServerToClientMessage::JobUpdated(job_info) => {
+                if !job_info.status.is_running() {
+                    bail!("Job {} is not running!", job_id);
+                }
+            }
+            ServerToClientMessage::RequestResponse { success, text } if !success => {
+                bail!("{}", text)
+            }
+            other => bail!("Expected JobUpdated, received: {other:?}"),
+        }
+
+        let listener = TcpListener::bind(("localhost", local_port)).await?;
+        eprintln!(
+            "Forwarding localhost:{} to port {} of job {}. Press Ctrl-C to stop.",
+            local_port, port, job_id
+        );
+
+        // Messages from relay tasks, to be sent to the server.
+        let (message_tx, mut message_rx) = channel::<ClientToServerMessage>(64);
+        let mut tunnels: BTreeMap<u64, Sender<Vec<u8>>> = BTreeMap::new();
+        let mut next_tunnel_id = 0;
+
+        loop {
+            tokio::select! {
+                // Open a tunnel for each new local connection.
+                connection = listener.accept() => {
+                    let (stream, addr) = connection?;
+                    let tunnel_id = next_tunnel_id;
+                    next_tunnel_id += 1;
+                    log::debug!("Opening tunnel {} for {}.", tunnel_id, addr);
+
+                    let (data_tx, data_rx) = channel(64);
+                    tunnels.insert(tunnel_id, data_tx);
+                    tokio::spawn(tunnel::relay(
+                        stream,
+                        tunnel_id,
+                        data_rx,
+                        message_tx.clone(),
+                        |tunnel_id, data| ClientToServerMessage::TunnelData { tunnel_id, data },
+                        |tunnel_id| ClientToServerMessage::CloseTunnel { tunnel_id },
+                    ));
+
+                    let message = ClientToServerMessage::OpenTunnel { job_id, tunnel_id, port };
+                    self.stream.send(&message).await?;
+                }
+                // Send data of local connections to the server.
+                Some(message) = message_rx.recv() => {
+                    if let ClientToServerMessage::CloseTunnel { tunnel_id } = &message {
+                        tunnels.remove(tunnel_id); // closed locally
+                    }
+                    self.stream.send(&message).await?;
+                }
+                // Pass data from the server to local connections.
+                message = self.receive() => {
+                    match message? {
+                        ServerToClientMessage::TunnelData { tunnel_id, data } => {
+                            if let Some(data_tx) = tunnels.get(&tunnel_id) {
+                                if data_tx.send(data).await.is_err() {
+                                    tunnels.remove(&tunnel_id);
+                                }
+                            }
+                        }
+                        ServerToClientMessage::CloseTunnel { tunnel_id } => {
+                            // Dropping the channel ends the relay.
+                            tunnels.remove(&tunnel_id);
+                        }
+                        ServerToClientMessage::JobUpdated(job_info) => {
+                            if matches!(job_info.status, JobStatus::Finished { .. } | JobStatus::Canceled { .. }) {
+                                eprintln!("Job {} has ended.", job_id);
+                                break;
+                            }
+                        }
+                        ServerToClientMessage::RequestResponse { success, text } if !success => {
+                            eprintln!("Failed to open tunnel: {}", text);
+                        }
+                        ServerToClientMessage::Bye => break,
+                        other => log::warn!("Unexpected message: {:?}", other),
+                    }
+                }
+                // Stop forwarding when interrupted.
+                _ = ctrl_c() => break,
+            }
+        }
+        Ok(())
+    }
+}
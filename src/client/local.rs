@@ -0,0 +1,205 @@
+//! Running job definitions on the local machine for debugging.
+
+use super::{default_resources, parse_global_resources, parse_steps, print};
+use crate::{
+    client::cli::parse_duration,
+    config::Config,
+    structs::{JobInfo, JobStatus, ProcessLimits},
+    worker::run_locally,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::{canonicalize, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// Command line of a job definition, either as a single string, which is
+/// split like a shell would do, or as list of program and arguments.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CommandLine {
+    Text(String),
+    Args(Vec<String>),
+}
+
+/// Job definition file, describing a job with the options of `kueue cmd`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobDefinition {
+    /// Command to run.
+    cmd: CommandLine,
+    /// Working directory, relative to the directory of the definition file.
+    /// Defaults to the directory of the definition file.
+    #[serde(default)]
+    cwd: Option<PathBuf>,
+    #[serde(default)]
+    job_slots: Option<u64>,
+    #[serde(default)]
+    cpus: Option<u64>,
+    #[serde(default)]
+    ram_mb: Option<u64>,
+    /// Global resources, such as licenses.
+    #[serde(default)]
+    resources: BTreeMap<String, u64>,
+    #[serde(default)]
+    stdout: Option<String>,
+    #[serde(default)]
+    stderr: Option<String>,
+    #[serde(default)]
+    setup: Vec<String>,
+    #[serde(default)]
+    teardown: Vec<String>,
+    /// CPU time limit, e.g., "10h".
+    #[serde(default)]
+    cpu_time: Option<String>,
+    /// Wall-time limit, e.g., "2h".
+    #[serde(default)]
+    timeout: Option<String>,
+    /// Process limits, e.g., `{ nofile: 1024, core: 0 }`.
+    #[serde(default)]
+    limits: ProcessLimits,
+    #[serde(default)]
+    group: Option<String>,
+}
+
+impl JobDefinition {
+    /// Read a job definition from a YAML file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.to_string_lossy(), e))?;
+        serde_yaml::from_str(&text)
+            .map_err(|e| anyhow!("Invalid job definition {}: {}", path.to_string_lossy(), e))
+    }
+
+    /// Read a batch file, holding either a single job definition or a list
+    /// of job definitions.
+    pub fn load_batch(path: &Path) -> Result<Vec<Self>> {
+        let text = read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.to_string_lossy(), e))?;
+        let value: serde_yaml::Value = serde_yaml::from_str(&text)
+            .map_err(|e| anyhow!("Invalid job definition {}: {}", path.to_string_lossy(), e))?;
+        let definitions = if value.is_sequence() {
+            serde_yaml::from_value(value)
+        } else {
+            serde_yaml::from_value(value).map(|definition| vec![definition])
+        };
+        definitions.map_err(|e| anyhow!("Invalid job definition {}: {}", path.to_string_lossy(), e))
+    }
+
+    /// Create the job described by the definition. Relative paths are
+    /// resolved against `base_dir` and missing resources are taken from
+    /// the client's defaults.
+    pub fn to_job_info(&self, base_dir: &Path, config: &Config) -> Result<JobInfo> {
+        let cmd = match &self.cmd {
+            CommandLine::Text(text) => shell_words::split(text)
+                .map_err(|e| anyhow!("Failed to parse command '{}': {}", text, e))?,
+            CommandLine::Args(args) => args.clone(),
+        };
+        if cmd.is_empty() {
+            bail!("Empty command!");
+        }
+
+        let cwd = match &self.cwd {
+            Some(cwd) => base_dir.join(cwd),
+            None => base_dir.to_path_buf(),
+        };
+        let cwd = canonicalize(&cwd)
+            .map_err(|e| anyhow!("Invalid working directory {}: {}", cwd.to_string_lossy(), e))?;
+        let worker_resources = default_resources(config, self.job_slots, self.cpus, self.ram_mb);
+        let global_resources = parse_global_resources(
+            self.resources
+                .iter()
+                .map(|(name, amount)| format!("{}={}", name, amount))
+                .collect(),
+        )?;
+
+        let mut job_info = JobInfo::new(
+            cmd,
+            cwd,
+            worker_resources,
+            global_resources,
+            self.stdout.clone(),
+            self.stderr.clone(),
+        );
+        job_info.setup_steps = parse_steps(self.setup.clone())?;
+        job_info.teardown_steps = parse_steps(self.teardown.clone())?;
+        job_info.cpu_time_limit_seconds =
+            self.cpu_time.as_deref().map(parse_duration).transpose()?;
+        job_info.wall_time_limit_seconds =
+            self.timeout.as_deref().map(parse_duration).transpose()?;
+        job_info.process_limits = self.limits;
+        job_info.group = self.group.clone();
+        Ok(job_info)
+    }
+}
+
+/// Run the job defined in the given file on the local machine, the same way
+/// a worker would run it, without contacting the server. Returns the job's
+/// exit code.
+pub async fn run_local(path: PathBuf, config: Config) -> Result<i32> {
+    let definition = JobDefinition::load(&path)?;
+    let base_dir = canonicalize(&path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut job_info = definition.to_job_info(&base_dir, &config)?;
+
+    // Pretend to run on a worker named after the local host.
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    let now = Utc::now();
+    job_info.status = JobStatus::Running {
+        issued: now,
+        started: now,
+        worker: hostname.split('.').next().unwrap_or_default().to_string(),
+    };
+
+    let thread_count_variables = config.worker_settings.thread_count_variables.clone();
+    let result = run_locally(job_info, thread_count_variables).await?;
+    print::local_job_result(&result);
+    Ok(result.exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_definition() {
+        let yaml = "cmd: ./run.sh --input 'my data'\n\
+                    cpus: 4\n\
+                    resources: { license: 1 }\n\
+                    setup: [\"mkdir -p out\"]\n\
+                    cpu_time: 1h30m\n";
+        let definition: JobDefinition = serde_yaml::from_str(yaml).unwrap();
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let job_info = definition
+            .to_job_info(&std::env::temp_dir(), &config)
+            .unwrap();
+
+        assert_eq!(job_info.cmd, vec!["./run.sh", "--input", "my data"]);
+        assert_eq!(job_info.worker_resources.cpus, 4);
+        assert_eq!(job_info.global_resources.unwrap()["license"], 1);
+        assert_eq!(job_info.setup_steps, vec![vec!["mkdir", "-p", "out"]]);
+        assert_eq!(job_info.cpu_time_limit_seconds, Some(90 * 60));
+
+        // Typos are not silently ignored.
+        assert!(serde_yaml::from_str::<JobDefinition>("cmd: ls\ncpu: 4\n").is_err());
+    }
+
+    #[test]
+    fn batch_file() {
+        let path = std::env::temp_dir().join(format!("kueue_batch_{}.yaml", std::process::id()));
+        std::fs::write(&path, "- cmd: ./a.sh\n- cmd: [./b.sh, x]\n  cpus: 2\n").unwrap();
+        let definitions = JobDefinition::load_batch(&path).unwrap();
+        assert_eq!(definitions.len(), 2);
+        assert_eq!(definitions[1].cpus, Some(2));
+
+        // A single definition is a batch of one job.
+        std::fs::write(&path, "cmd: ./a.sh\n").unwrap();
+        assert_eq!(JobDefinition::load_batch(&path).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,56 +1,189 @@
+mod alloc;
 pub mod cli;
+mod error;
+mod fetch;
+mod forward;
+mod local;
 mod print;
+mod setup;
+mod standalone;
+mod top;
+
+pub use error::ClientError;
+pub use local::run_local;
+use local::JobDefinition;
+pub use setup::{doctor, init_config};
+pub use standalone::standalone;
 
 use crate::{
     config::Config,
-    messages::stream::MessageStream,
-    messages::{ClientToServerMessage, HelloMessage, ServerToClientMessage},
-    structs::{JobInfo, JobStatus, Resources},
+    crypto::PayloadCipher,
+    messages::stream::{MessageError, MessageStream},
+    messages::{
+        version::{
+            capabilities, check_remote_version, CAP_ACCOUNTING, CAP_ADMIN_COMMANDS,
+            CAP_CANCEL_REASONS, CAP_CONNECTION_INFO, CAP_DEPENDENCY_CHAINS, CAP_ENCRYPTED_PAYLOADS,
+            CAP_FETCH_OUTPUTS, CAP_IDLE_REPORT, CAP_JOB_BOOST, CAP_JOB_DIFF, CAP_JOB_GROUPS,
+            CAP_JOB_MIGRATION, CAP_JOB_TEMPLATES, CAP_LOG_FOLLOW, CAP_OBSERVER_ROLE, CAP_PROJECTS,
+            CAP_QOS_CLASSES, CAP_RESERVED_JOB_IDS, CAP_SCHEDULING_TRACE, CAP_WORKER_AFFINITY,
+            CAP_WORKER_LABELS, CAP_WORKER_RENAMING, KUEUE_VERSION,
+        },
+        ClientToServerMessage, HelloMessage, ServerToClientMessage,
+    },
+    structs::{
+        current_user, CoreBinding, DependencyCondition, DependentsAction, EnvSnapshot,
+        ExitCodeAction, ExitCodePolicy, JobDependency, JobInfo, JobMigration, JobStatus,
+        JobTemplate, OutputStream, Resources, WorkerAffinity, WorkerInfo,
+    },
+    tls::{self, ServerStream},
 };
 use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine};
-use cli::{Cli, CmdArgs, Command};
+use clap::ValueEnum;
+use cli::{
+    AdminCommand, Cli, CmdArgs, ColorChoice, ColorTheme, Command, JobColumn, OutputFormat,
+    TemplatesCommand, TimeFormat, WorkerSort,
+};
 use sha2::{Digest, Sha256};
-use std::{collections::BTreeMap, fs::canonicalize};
-use tokio::net::TcpStream;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::canonicalize,
+    path::{Path, PathBuf},
+};
 
 pub struct Client {
     args: Cli,
     config: Config,
-    stream: MessageStream<TcpStream>,
+    stream: MessageStream<ServerStream>,
+    /// Version of the server, known after the welcome handshake.
+    server_version: String,
+    /// Optional protocol features supported by the server.
+    server_capabilities: BTreeSet<String>,
+    /// The server has accepted the client's secret.
+    authenticated: bool,
 }
 
 impl Client {
     /// Set up a new client instance and connect to the server.
     pub async fn new(args: Cli, config: Config) -> Result<Self> {
+        configure_output(&args, &config)?;
+
         // Connect to server.
-        let server_addr = config.get_server_address().await?;
-        let stream = TcpStream::connect(server_addr).await?;
+        let stream = tls::connect(&config)
+            .await
+            .map_err(|e| ClientError::ConnectionFailed(format!("{:#}", e)))?;
         let stream = MessageStream::new(stream);
 
         Ok(Client {
             args,
             config,
             stream,
+            server_version: String::new(),
+            server_capabilities: BTreeSet::new(),
+            authenticated: false,
         })
     }
 
-    /// Perform request and handle messages.
-    pub async fn run(&mut self) -> Result<()> {
+    /// Receive the next message from the server. Announcements, such as the
+    /// message of the day, may arrive at any time and are printed right away.
+    async fn receive(&mut self) -> Result<ServerToClientMessage, MessageError> {
+        loop {
+            match self.stream.receive::<ServerToClientMessage>().await? {
+                ServerToClientMessage::Announcement { text, broadcast } => {
+                    print::announcement(&text, broadcast)
+                }
+                message => return Ok(message),
+            }
+        }
+    }
+
+    /// Await the server's response to an issued job. The validation report
+    /// sent ahead of the acceptance is printed to stderr.
+    async fn await_job_acceptance(&mut self) -> Result<JobInfo> {
+        let print_receipt = !self.args.quiet;
+        match self.await_job_response(print_receipt).await? {
+            Ok(job_info) => Ok(job_info),
+            Err(reason) => bail!("Job rejected by server: {reason}"),
+        }
+    }
+
+    /// Await the server's response to an issued job. Returns the accepted
+    /// job or the reason of the rejection. The validation report is printed
+    /// if `print_receipt` is set.
+    async fn await_job_response(
+        &mut self,
+        print_receipt: bool,
+    ) -> Result<std::result::Result<JobInfo, String>> {
+        loop {
+            match self.receive().await? {
+                ServerToClientMessage::JobReceipt(receipt) => {
+                    if print_receipt {
+                        print::job_receipt(&receipt);
+                    }
+                }
+                ServerToClientMessage::AcceptJob(job_info) => return Ok(Ok(job_info)),
+                ServerToClientMessage::RejectJob {
+                    job_info: _,
+                    reason,
+                } => return Ok(Err(reason)),
+                other => {
+                    bail!("Expected AcceptJob or RejectJob, received: {other:?}");
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if JSON output has been requested.
+    fn json(&self) -> bool {
+        self.args.output == OutputFormat::Json
+    }
+
+    /// Perform hello/welcome handshake with the server.
+    async fn handshake(&mut self) -> Result<()> {
         // Send hello from client.
-        self.stream.send(&HelloMessage::HelloFromClient).await?;
+        let hello = HelloMessage::HelloFromClient {
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tenant: self.config.common_settings.tenant.clone(),
+        };
+        self.stream.send(&hello).await?;
 
         // Await welcoming response from server.
-        match self.stream.receive::<ServerToClientMessage>().await? {
-            ServerToClientMessage::WelcomeClient => {
-                log::debug!("Established connection to server!")
-            } // continue
+        match self.receive().await? {
+            ServerToClientMessage::WelcomeClient {
+                kueue_version,
+                capabilities,
+            } => {
+                log::debug!("Established connection to server!");
+                check_remote_version("server", &kueue_version);
+                self.stream.negotiate_format(&capabilities);
+                self.server_version = kueue_version;
+                self.server_capabilities = capabilities;
+                Ok(())
+            }
+            ServerToClientMessage::RequestResponse { text, .. } => {
+                bail!("Connection rejected by server: {}", text)
+            }
             other => bail!("Expected WelcomeClient, received: {:?}", other),
         }
+    }
+
+    /// Perform request and handle messages.
+    pub async fn run(&mut self) -> Result<()> {
+        self.handshake().await?;
+
+        // Servers with an observer secret only answer authenticated clients.
+        // Commands that modify anything report a rejected secret themselves.
+        if self.server_capabilities.contains(CAP_OBSERVER_ROLE) {
+            if let Err(e) = self.authenticate().await {
+                log::debug!("Continue without authentication: {}", e);
+            }
+        }
 
         // Process subcommands.
         match self.args.command.clone() {
             Command::Cmd {
+                template,
                 job_slots,
                 cpus,
                 ram_mb,
@@ -58,6 +191,35 @@ impl Client {
                 stdout,
                 stderr,
                 wait,
+                setup,
+                teardown,
+                cpu_time,
+                timeout,
+                limit,
+                require,
+                prefer_worker,
+                prefer_worker_of,
+                retry_on,
+                retry_elsewhere_on,
+                fail_on,
+                max_retries,
+                retries,
+                after_ok,
+                after_failed,
+                after_any,
+                auto_resources,
+                pool,
+                group,
+                project,
+                qos,
+                priority,
+                create_cwd,
+                snapshot_env,
+                encrypt,
+                gpus,
+                gpu_mode,
+                physical_cores,
+                no_smt,
                 args,
             } => {
                 let CmdArgs::Args(cmd) = args;
@@ -69,59 +231,146 @@ impl Client {
                 // This command requires authentication.
                 self.authenticate().await?;
 
+                // Options given by the user take precedence over the template.
+                let template = match template {
+                    Some(name) => self.get_template(&name).await?,
+                    None => JobTemplate::default(),
+                };
+                let job_slots = job_slots.or(template.job_slots);
+                let cpus = cpus.or(template.cpus);
+                let ram_mb = ram_mb.or(template.ram_mb);
+                let pool = pool.or(template.pool);
+                let timeout = timeout.or(template.wall_time_limit_seconds);
+
                 // Collect job parameters.
-                let cwd = std::env::current_dir()?;
-                let cwd = canonicalize(cwd)?;
-                let worker_resources = Resources::new(
-                    job_slots,
-                    cpus.unwrap_or(self.config.client_settings.job_default_cpus),
-                    ram_mb.unwrap_or(self.config.client_settings.job_default_ram_mb),
-                );
-
-                // Parse resource parameters into map.
-                let mut global_resources: BTreeMap<String, u64> = BTreeMap::new();
-                for resource in resources {
-                    let parts: Vec<_> = resource.split('=').collect();
-                    if parts.len() == 1 {
-                        global_resources.insert(resource, 1);
-                    } else if parts.len() == 2 {
-                        let res_key = parts.first().unwrap().to_string();
-                        let amount: u64 = parts.last().unwrap().parse().map_err(|err| {
-                            anyhow!("Failed to parse resource: '{}', {}", resource, err)
-                        })?;
-                        global_resources.insert(res_key, amount);
-                    } else {
-                        bail!("Failed to parse resource: {}", resource);
-                    }
+                let cwd = submission_cwd(&self.config)?;
+                if !create_cwd && is_local_path(&cwd) {
+                    eprintln!(
+                        "Warning: The working directory {} is probably local to this machine \
+                        and might not exist on workers. Consider --create-cwd.",
+                        cwd.to_string_lossy()
+                    );
+                }
+                let worker_resources = default_resources(&self.config, job_slots, cpus, ram_mb);
+
+                let mut global_resources = parse_global_resources(resources)?;
+                for (name, amount) in template.global_resources {
+                    let global_resources = global_resources.get_or_insert_with(BTreeMap::new);
+                    global_resources.entry(name).or_insert(amount);
                 }
-                let global_resources = if global_resources.is_empty() {
-                    None
-                } else {
-                    Some(global_resources)
-                };
 
                 // Issue new job.
-                let job_info =
+                let mut job_info =
                     JobInfo::new(cmd, cwd, worker_resources, global_resources, stdout, stderr);
+                job_info.setup_steps = parse_steps(setup)?;
+                job_info.teardown_steps = parse_steps(teardown)?;
+                job_info.cpu_time_limit_seconds = cpu_time;
+                job_info.wall_time_limit_seconds = timeout;
+                for (name, value) in limit {
+                    match name.as_str() {
+                        "nofile" => job_info.process_limits.nofile = Some(value),
+                        "nproc" => job_info.process_limits.nproc = Some(value),
+                        _ => job_info.process_limits.core = Some(value),
+                    }
+                }
+                if !require.is_empty() && !self.server_capabilities.contains(CAP_WORKER_LABELS) {
+                    bail!("The server does not support --require!");
+                }
+                job_info.constraints = template.constraints;
+                job_info.constraints.extend(require);
+                job_info.affinity = match (prefer_worker, prefer_worker_of) {
+                    (Some(worker), _) => Some(WorkerAffinity::Worker(worker)),
+                    (None, Some(job_id)) => Some(WorkerAffinity::WorkerOf(job_id)),
+                    (None, None) => None,
+                };
+                if job_info.affinity.is_some()
+                    && !self.server_capabilities.contains(CAP_WORKER_AFFINITY)
+                {
+                    bail!("The server does not support worker affinity!");
+                }
+                job_info.exit_code_policies = [
+                    (retry_on, ExitCodeAction::Retry),
+                    (retry_elsewhere_on, ExitCodeAction::RetryElsewhere),
+                    (fail_on, ExitCodeAction::Fail),
+                ]
+                .into_iter()
+                .map(|(exit_codes, action)| parse_exit_code_policies(exit_codes, action))
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+                let any_exit_code = |policy: &ExitCodePolicy| policy.exit_code.is_none();
+                if retries.is_some() && !job_info.exit_code_policies.iter().any(any_exit_code) {
+                    job_info.exit_code_policies.push(ExitCodePolicy {
+                        exit_code: None,
+                        action: ExitCodeAction::Retry,
+                    });
+                }
+                job_info.max_retries = retries.unwrap_or(max_retries);
+                job_info.dependencies = [
+                    (after_ok, DependencyCondition::Success),
+                    (after_failed, DependencyCondition::Failure),
+                    (after_any, DependencyCondition::Any),
+                ]
+                .into_iter()
+                .flat_map(|(job_ids, condition)| {
+                    job_ids
+                        .into_iter()
+                        .map(move |job_id| JobDependency { job_id, condition })
+                })
+                .collect();
+                job_info.auto_resources = auto_resources;
+                job_info.pool = pool.or(self.config.client_settings.job_default_pool.clone());
+                job_info.group = group;
+                job_info.priority = priority;
+                job_info.project =
+                    project.or(self.config.client_settings.job_default_project.clone());
+                if job_info.project.is_some() && !self.server_capabilities.contains(CAP_PROJECTS) {
+                    bail!("The server does not support projects!");
+                }
+                if qos.is_some() && !self.server_capabilities.contains(CAP_QOS_CLASSES) {
+                    bail!("The server does not support QoS classes!");
+                }
+                job_info.qos = qos;
+                job_info.create_cwd = create_cwd;
+                if snapshot_env {
+                    job_info.env_snapshot = Some(snapshot_environment());
+                }
+                job_info.gpus = gpus;
+                job_info.gpu_mode = gpu_mode;
+                job_info.core_binding = if physical_cores {
+                    Some(CoreBinding::PhysicalCores)
+                } else if no_smt {
+                    Some(CoreBinding::NoSmt)
+                } else {
+                    None
+                };
+                if encrypt {
+                    if !self.server_capabilities.contains(CAP_ENCRYPTED_PAYLOADS)
+                        || !self.server_capabilities.contains(CAP_RESERVED_JOB_IDS)
+                    {
+                        bail!("The server does not support encrypted jobs!");
+                    }
+                    // The payload is bound to the ID of the job.
+                    job_info.job_id = self.reserve_job_id().await?;
+                    self.payload_cipher()?.seal_job(&mut job_info)?;
+                }
                 let message = ClientToServerMessage::IssueJob(job_info);
                 self.stream.send(&message).await?;
 
                 // Await acceptance.
-                let job_id = match self.stream.receive::<ServerToClientMessage>().await? {
-                    ServerToClientMessage::AcceptJob(job_info) => {
-                        log::debug!("Job submitted successfully!");
-                        job_info.job_id
-                    }
-                    ServerToClientMessage::RejectJob {
-                        job_info: _,
-                        reason,
-                    } => {
-                        bail!("Job rejected by server: {reason}");
-                    }
-                    other => {
-                        bail!("Expected AcceptJob or RejectJob, received: {other:?}");
-                    }
-                };
+                let job_info = self.await_job_acceptance().await?;
+                log::debug!("Job submitted successfully!");
+                if let Some(suggested) = job_info
+                    .suggested_resources
+                    .as_ref()
+                    .filter(|_| !self.args.quiet)
+                {
+                    eprintln!(
+                        "Hint: Based on similar jobs, this job should fit into {} CPU cores \
+                        and {} MB of RAM. Consider --auto-resources.",
+                        suggested.cpus, suggested.ram_mb
+                    );
+                }
+                let job_id = job_info.job_id;
 
                 // Block until the job has been finished or canceled.
                 if wait {
@@ -130,17 +379,17 @@ impl Client {
                     self.stream.send(&message).await?;
 
                     // Await results.
-                    loop {
-                        match self.stream.receive::<ServerToClientMessage>().await? {
+                    let job_info = loop {
+                        match self.receive().await? {
                             ServerToClientMessage::JobUpdated(job_info) => {
                                 log::debug!("Job updated: {:?}", job_info.status);
                                 match job_info.status {
                                     JobStatus::Finished { return_code, .. } => {
                                         // Print return code to stdout.
                                         println!("{}", return_code);
-                                        break;
+                                        break job_info;
                                     }
-                                    JobStatus::Canceled { .. } => break,
+                                    JobStatus::Canceled { .. } => break job_info,
                                     _ => {}
                                 }
                             }
@@ -148,12 +397,103 @@ impl Client {
                                 bail!("Expected NotifyJob, received: {other:?}");
                             }
                         }
-                    }
+                    };
+                    ClientError::check_job(&job_info)?;
                 } else {
                     // Print job ID to stdout.
                     println!("{}", job_id);
                 }
             }
+            Command::Submit { paths } => {
+                // Read all definitions before issuing any job.
+                let mut jobs = Vec::new();
+                for path in paths {
+                    let definitions = JobDefinition::load_batch(&path)?;
+                    let base_dir = canonicalize(&path)?
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .unwrap_or_default();
+                    let batch = definitions.len() > 1;
+                    for (index, definition) in definitions.iter().enumerate() {
+                        let label = if batch {
+                            format!("{}[{}]", path.to_string_lossy(), index)
+                        } else {
+                            path.to_string_lossy().to_string()
+                        };
+                        let mut job_info = definition
+                            .to_job_info(&base_dir, &self.config)
+                            .map_err(|e| anyhow!("{}: {}", label, e))?;
+                        job_info.pool = self.config.client_settings.job_default_pool.clone();
+                        if self.server_capabilities.contains(CAP_PROJECTS) {
+                            job_info.project =
+                                self.config.client_settings.job_default_project.clone();
+                        }
+                        jobs.push((label, job_info));
+                    }
+                }
+
+                // This command requires authentication.
+                self.authenticate().await?;
+
+                // Issue jobs one after another and show the progress.
+                let total = jobs.len();
+                let (mut accepted, mut rejected) = (Vec::new(), Vec::new());
+                let quiet = self.args.quiet;
+                for (submitted, (label, job_info)) in jobs.into_iter().enumerate() {
+                    if !quiet {
+                        print::submission_progress(submitted, total, rejected.len(), false);
+                    }
+                    let message = ClientToServerMessage::IssueJob(job_info);
+                    self.stream.send(&message).await?;
+                    match self.await_job_response(false).await? {
+                        Ok(job_info) => accepted.push(job_info.job_id),
+                        Err(reason) => rejected.push((label, reason)),
+                    }
+                }
+                if !quiet {
+                    print::submission_progress(total, total, rejected.len(), true);
+                }
+
+                // Print job IDs to stdout.
+                for job_id in accepted {
+                    println!("{}", job_id);
+                }
+
+                if !rejected.is_empty() {
+                    print::rejected_jobs(&rejected);
+                    bail!("{} of {} jobs have been rejected!", rejected.len(), total);
+                }
+            }
+            Command::Alloc {
+                job_slots,
+                cpus,
+                ram_mb,
+                resources,
+                args,
+            } => {
+                // Run the given command or the user's shell.
+                let cmd = match args {
+                    Some(CmdArgs::Args(cmd)) if !cmd.is_empty() => cmd,
+                    _ => vec![std::env::var("SHELL").unwrap_or("/bin/sh".into())],
+                };
+
+                // This command requires authentication.
+                self.authenticate().await?;
+
+                // Collect job parameters.
+                let cwd = submission_cwd(&self.config)?;
+                let worker_resources = default_resources(&self.config, job_slots, cpus, ram_mb);
+                let global_resources = parse_global_resources(resources)?;
+
+                let mut job_info =
+                    JobInfo::new(cmd, cwd, worker_resources, global_resources, None, None);
+                job_info.interactive = true;
+                job_info.pool = self.config.client_settings.job_default_pool.clone();
+                if self.server_capabilities.contains(CAP_PROJECTS) {
+                    job_info.project = self.config.client_settings.job_default_project.clone();
+                }
+                self.alloc(job_info).await?;
+            }
             Command::ListJobs {
                 num_jobs,
                 pending,
@@ -162,11 +502,26 @@ impl Client {
                 succeeded,
                 failed,
                 canceled,
+                columns,
+                wrap,
             } => {
+                // Columns to show, falling back to the config.
+                let columns = if columns.is_empty() {
+                    parse_job_columns(&self.config.client_settings.job_list_columns)?
+                } else {
+                    columns
+                };
+
                 // Query jobs.
+                // Current space (height) in the terminal to show jobs. JSON
+                // output is not limited to the terminal.
+                let default_num_jobs = if self.json() {
+                    u64::MAX
+                } else {
+                    print::term_size().1 as u64 - 4
+                };
                 let message = ClientToServerMessage::ListJobs {
-                    // Current space (height) in the terminal to show jobs.
-                    num_jobs: num_jobs.unwrap_or(print::term_size().1 as u64 - 4),
+                    num_jobs: num_jobs.unwrap_or(default_num_jobs),
                     pending,
                     offered,
                     running,
@@ -177,7 +532,36 @@ impl Client {
                 self.stream.send(&message).await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
+                    ServerToClientMessage::JobList { job_infos, .. } if self.args.quiet => {
+                        // Print only job IDs, e.g., for scripts.
+                        for job_info in job_infos {
+                            println!("{}", job_info.job_id);
+                        }
+                    }
+                    ServerToClientMessage::JobList {
+                        job_infos,
+                        jobs_pending,
+                        jobs_offered,
+                        jobs_running,
+                        jobs_succeeded,
+                        jobs_failed,
+                        jobs_canceled,
+                        job_avg_run_time_seconds,
+                        remaining_jobs_eta_seconds,
+                    } if self.json() => {
+                        print::json(&serde_json::json!({
+                            "jobs": job_infos,
+                            "jobs_pending": jobs_pending,
+                            "jobs_offered": jobs_offered,
+                            "jobs_running": jobs_running,
+                            "jobs_succeeded": jobs_succeeded,
+                            "jobs_failed": jobs_failed,
+                            "jobs_canceled": jobs_canceled,
+                            "job_avg_run_time_seconds": job_avg_run_time_seconds,
+                            "remaining_jobs_eta_seconds": remaining_jobs_eta_seconds,
+                        }))?;
+                    }
                     ServerToClientMessage::JobList {
                         job_infos,
                         jobs_pending,
@@ -191,6 +575,8 @@ impl Client {
                     } => {
                         print::job_list(
                             job_infos,
+                            &columns,
+                            wrap,
                             jobs_pending,
                             jobs_offered,
                             jobs_running,
@@ -206,58 +592,336 @@ impl Client {
                     }
                 }
             }
-            Command::ShowJob { job_id } => {
+            Command::ShowJob {
+                job_id,
+                env,
+                provenance,
+                environment,
+            } => {
+                // The server only sends the environment to authenticated clients.
+                if env {
+                    self.authenticate().await?;
+                }
+
                 // Query job.
                 let message = ClientToServerMessage::ShowJob { job_id };
                 self.stream.send(&message).await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
+                    ServerToClientMessage::JobInfo {
+                        mut job_info,
+                        mut stdout_text,
+                        mut stderr_text,
+                    } => {
+                        if provenance {
+                            let Some(record) = &job_info.provenance else {
+                                bail!(
+                                    "No provenance record for job {}! Records are created \
+                                    when the job finishes.",
+                                    job_id
+                                );
+                            };
+                            println!("{}", serde_json::to_string_pretty(record)?);
+                            if !record.verify(&self.config.common_settings.shared_secret) {
+                                bail!("The signature of the provenance record is invalid!");
+                            }
+                        } else if environment {
+                            match &job_info.environment {
+                                Some(environment) if self.json() => print::json(environment)?,
+                                Some(environment) => print::execution_environment(environment),
+                                None => bail!(
+                                    "No execution environment has been recorded for job {}! \
+                                    Environments are recorded when the job finishes on a worker \
+                                    with capture_environment enabled.",
+                                    job_id
+                                ),
+                            }
+                        } else if env {
+                            if job_info.encrypted_payload.is_some() {
+                                let payload = self.payload_cipher()?.open_job(&job_info)?;
+                                job_info.env_snapshot = payload.env_snapshot;
+                            }
+                            match &job_info.env_snapshot {
+                                Some(env_snapshot) if self.json() => print::json(env_snapshot)?,
+                                Some(env_snapshot) => print::env_snapshot(env_snapshot),
+                                None => {
+                                    bail!("No environment has been recorded for job {}!", job_id)
+                                }
+                            }
+                        } else {
+                            if job_info.encrypted_payload.is_some() {
+                                let cipher = self.payload_cipher()?;
+                                let payload = cipher.open_job(&job_info)?;
+                                job_info.cmd = payload.cmd;
+                                job_info.setup_steps = payload.setup_steps;
+                                job_info.teardown_steps = payload.teardown_steps;
+                                let streams = [OutputStream::Stdout, OutputStream::Stderr];
+                                for (stream, text) in streams
+                                    .into_iter()
+                                    .zip([&mut stdout_text, &mut stderr_text])
+                                {
+                                    if let Some(text) = text {
+                                        *text = cipher.decrypt(stream.into(), job_id, text)?;
+                                    }
+                                }
+                            }
+                            if self.json() {
+                                print::json(&serde_json::json!({
+                                    "job": job_info,
+                                    "stdout_text": stdout_text,
+                                    "stderr_text": stderr_text,
+                                }))?;
+                            } else {
+                                print::job_info(job_info, stdout_text, stderr_text);
+                            }
+                        }
+                    }
+                    ServerToClientMessage::RequestResponse { success, text } if !success => {
+                        return Err(ClientError::refused(text));
+                    }
+                    other => {
+                        bail!("Expected JobInfo, received: {:?}", other);
+                    }
+                }
+            }
+            Command::Log { job_id, follow } => {
+                // Bytes of stdout and stderr printed while following the job.
+                let mut printed = [0, 0];
+                if follow {
+                    if !self.server_capabilities.contains(CAP_LOG_FOLLOW) {
+                        bail!("The server does not support following jobs!");
+                    }
+                    let message = ClientToServerMessage::FollowJob { job_id };
+                    self.stream.send(&message).await?;
+                    let mut job_info = match self.receive().await? {
+                        ServerToClientMessage::JobUpdated(job_info) => job_info,
+                        ServerToClientMessage::RequestResponse { text, .. } => {
+                            return Err(ClientError::refused(text))
+                        }
+                        other => bail!("Expected JobUpdated, received: {other:?}"),
+                    };
+
+                    // Print output as it arrives until the job has concluded.
+                    while !job_info.status.is_finished() && !job_info.status.is_canceled() {
+                        match self.receive().await? {
+                            ServerToClientMessage::JobLog { stream, data, .. } => {
+                                printed[stream as usize] += data.len();
+                                print::job_log(stream, &data)?;
+                            }
+                            ServerToClientMessage::JobUpdated(update) => job_info = update,
+                            other => bail!("Expected JobLog, received: {other:?}"),
+                        }
+                    }
+                }
+
+                // Print the output of the concluded job, or what remains of it.
+                let message = ClientToServerMessage::ShowJob { job_id };
+                self.stream.send(&message).await?;
+                let (job_info, mut outputs) = match self.receive().await? {
                     ServerToClientMessage::JobInfo {
                         job_info,
                         stdout_text,
                         stderr_text,
-                    } => print::job_info(job_info, stdout_text, stderr_text),
-                    ServerToClientMessage::RequestResponse { success, text } if !success => {
-                        println!("{}", text);
+                    } => (job_info, [stdout_text, stderr_text]),
+                    ServerToClientMessage::RequestResponse { text, .. } => {
+                        return Err(ClientError::refused(text))
+                    }
+                    other => bail!("Expected JobInfo, received: {other:?}"),
+                };
+                if job_info.encrypted_payload.is_some() {
+                    let cipher = self.payload_cipher()?;
+                    let streams = [OutputStream::Stdout, OutputStream::Stderr];
+                    for (stream, text) in streams.into_iter().zip(outputs.iter_mut()) {
+                        if let Some(text) = text {
+                            *text = cipher.decrypt(stream.into(), job_info.job_id, text)?;
+                        }
+                    }
+                }
+                let redirected = [&job_info.stdout_path, &job_info.stderr_path];
+                for (stream, text) in [OutputStream::Stdout, OutputStream::Stderr]
+                    .into_iter()
+                    .zip(outputs)
+                {
+                    // Redirected output has been printed while following.
+                    let index = stream as usize;
+                    if follow && redirected[index].is_some() {
+                        continue;
+                    }
+                    let text = text.unwrap_or_default();
+                    if let Some(rest) = text.as_bytes().get(printed[index]..) {
+                        print::job_log(stream, rest)?;
+                    }
+                }
+            }
+            Command::Fetch { job_id, output } => {
+                if !self.server_capabilities.contains(CAP_FETCH_OUTPUTS) {
+                    bail!("The server does not support fetching outputs!");
+                }
+                self.fetch(job_id, &output).await?;
+            }
+            Command::Diff { job_a, job_b } => {
+                if !self.server_capabilities.contains(CAP_JOB_DIFF) {
+                    bail!("The server does not support comparing jobs!");
+                }
+                let message = ClientToServerMessage::DiffJobs { job_a, job_b };
+                self.stream.send(&message).await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::JobDiff {
+                        job_a,
+                        job_b,
+                        diff,
+                        truncated,
+                    } => print::job_diff(job_a, job_b, &diff, truncated),
+                    ServerToClientMessage::RequestResponse { text, .. } => {
+                        return Err(ClientError::refused(text))
                     }
                     other => {
-                        bail!("Expected JobInfo, received: {:?}", other);
+                        bail!("Expected JobDiff, received: {:?}", other);
                     }
                 }
             }
-            Command::WaitJob { job_id } => {
+            Command::WaitJob { job_id, on_finish } => {
                 // Get notified when job is updated.
                 let message = ClientToServerMessage::ObserveJob { job_id };
                 self.stream.send(&message).await?;
 
                 // Await results.
-                loop {
-                    match self.stream.receive::<ServerToClientMessage>().await? {
+                let job_info = loop {
+                    match self.receive().await? {
                         ServerToClientMessage::JobUpdated(job_info) => {
                             log::debug!("Job updated: {:?}", job_info.status);
                             match job_info.status {
-                                JobStatus::Finished { .. } => break,
-                                JobStatus::Canceled { .. } => break,
+                                JobStatus::Finished { .. } => break job_info,
+                                JobStatus::Canceled { .. } => break job_info,
                                 _ => {}
                             }
                         }
+                        ServerToClientMessage::RequestResponse { success, text } if !success => {
+                            return Err(ClientError::refused(text));
+                        }
                         other => {
                             bail!("Expected NotifyJob, received: {other:?}");
                         }
                     }
+                };
+
+                // Run local follow-up action.
+                if let Some(command) = on_finish {
+                    run_on_finish(&command, &job_info).await?;
                 }
+                ClientError::check_job(&job_info)?;
             }
-            Command::RemoveJob { job_id, kill } => {
+            Command::Observe { group } => {
+                if !self.server_capabilities.contains(CAP_JOB_GROUPS) {
+                    bail!("The server does not support observing groups of jobs!");
+                }
+                let message = ClientToServerMessage::ObserveGroup { group };
+                self.stream.send(&message).await?;
+
+                // Get jobs of the group at this point.
+                let (group, job_infos) = match self.receive().await? {
+                    ServerToClientMessage::GroupJobs { group, job_infos } => (group, job_infos),
+                    other => bail!("Expected GroupJobs, received: {other:?}"),
+                };
+                if job_infos.is_empty() {
+                    bail!("No jobs found in group '{}'!", group);
+                }
+                let mut jobs: BTreeMap<u64, JobInfo> = job_infos
+                    .into_iter()
+                    .map(|job_info| (job_info.job_id, job_info))
+                    .collect();
+                jobs.values().for_each(print::group_job_update);
+
+                // Follow updates until all jobs have concluded.
+                let concluded = |job_info: &JobInfo| {
+                    job_info.status.is_finished() || job_info.status.is_canceled()
+                };
+                while !jobs.values().all(concluded) {
+                    match self.receive().await? {
+                        ServerToClientMessage::JobUpdated(job_info) => {
+                            let known = jobs.get(&job_info.job_id);
+                            if known.map(|known| &known.status) != Some(&job_info.status) {
+                                print::group_job_update(&job_info);
+                                jobs.insert(job_info.job_id, job_info);
+                            }
+                        }
+                        other => bail!("Expected JobUpdated, received: {other:?}"),
+                    }
+                }
+                print::group_summary(&group, jobs.values());
+            }
+            Command::ForwardPort {
+                job_id,
+                port,
+                local_port,
+            } => {
+                // This command requires authentication.
+                self.authenticate().await?;
+                self.forward_port(job_id, port, local_port.unwrap_or(port))
+                    .await?;
+            }
+            Command::Boost { job_id, to_front } => {
+                if !self.server_capabilities.contains(CAP_JOB_BOOST) {
+                    bail!("The server does not support boosting jobs!");
+                }
+                // Admins may boost any job, owners only their own.
+                self.authenticate_admin().await?;
+
+                let message = ClientToServerMessage::BoostJob {
+                    job_id,
+                    to_front,
+                    user: current_user(),
+                };
+                self.stream.send(&message).await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::RequestResponse { success: _, text } => {
+                        println!("{}", text)
+                    }
+                    other => {
+                        bail!("Expected RequestResponse, received: {:?}", other);
+                    }
+                }
+            }
+            Command::RemoveJob {
+                job_id,
+                kill,
+                cascade,
+                orphan,
+                reason,
+            } => {
+                let dependents = match (cascade, orphan) {
+                    (true, _) => DependentsAction::Cascade,
+                    (_, true) => DependentsAction::Orphan,
+                    _ => DependentsAction::Resolve,
+                };
+                if dependents != DependentsAction::Resolve
+                    && !self.server_capabilities.contains(CAP_DEPENDENCY_CHAINS)
+                {
+                    bail!("The server does not support --cascade and --orphan!");
+                }
+                if reason.is_some() && !self.server_capabilities.contains(CAP_CANCEL_REASONS) {
+                    bail!("The server does not support --reason!");
+                }
+
                 // This command requires authentication.
                 self.authenticate().await?;
 
                 // Remove job from queue.
-                let message = ClientToServerMessage::RemoveJob { job_id, kill };
+                let message = ClientToServerMessage::RemoveJob {
+                    job_id,
+                    kill,
+                    dependents,
+                    reason,
+                };
                 self.stream.send(&message).await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
                     ServerToClientMessage::RequestResponse { success: _, text } => {
                         println!("{}", text)
                     }
@@ -275,7 +939,7 @@ impl Client {
                 self.stream.send(&message).await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
                     ServerToClientMessage::RequestResponse { success: _, text } => {
                         println!("{}", text)
                     }
@@ -284,16 +948,41 @@ impl Client {
                     }
                 }
             }
-            Command::ListWorkers => {
+            Command::ListWorkers {
+                sort,
+                reverse,
+                online_only,
+                offline_only,
+                tag,
+            } => {
                 // Query workers.
                 self.stream
                     .send(&ClientToServerMessage::ListWorkers)
                     .await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
-                    ServerToClientMessage::WorkerList(worker_list) => {
-                        print::worker_list(worker_list);
+                match self.receive().await? {
+                    ServerToClientMessage::WorkerList(mut worker_list) => {
+                        // Filter and sort workers locally.
+                        worker_list.retain(|info| {
+                            (!online_only || !info.offline)
+                                && (!offline_only || info.offline)
+                                && tag.iter().all(|tag| info.tags.contains(tag))
+                        });
+                        sort_workers(&mut worker_list, sort);
+                        if reverse {
+                            worker_list.reverse();
+                        }
+                        if self.args.quiet {
+                            // Print only worker IDs, e.g., for scripts.
+                            for worker_info in worker_list {
+                                println!("{}", worker_info.worker_id);
+                            }
+                        } else if self.json() {
+                            print::json(&worker_list)?;
+                        } else {
+                            print::worker_list(worker_list, &self.server_version);
+                        }
                     }
                     other => {
                         bail!("Expected WorkerList, received: {:?}", other);
@@ -306,12 +995,15 @@ impl Client {
                 self.stream.send(&message).await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
+                    ServerToClientMessage::WorkerInfo(worker_info) if self.json() => {
+                        print::json(&worker_info)?;
+                    }
                     ServerToClientMessage::WorkerInfo(worker_info) => {
-                        print::worker_info(worker_info)
+                        print::worker_info(worker_info, &self.server_version)
                     }
                     ServerToClientMessage::RequestResponse { success, text } if !success => {
-                        println!("{}", text);
+                        return Err(ClientError::refused(text));
                     }
                     other => {
                         bail!("Expected WorkerInfo, received: {:?}", other);
@@ -325,20 +1017,220 @@ impl Client {
                     .await?;
 
                 // Await results.
-                match self.stream.receive::<ServerToClientMessage>().await? {
+                match self.receive().await? {
+                    ServerToClientMessage::ResourceList {
+                        used_resources,
+                        total_resources,
+                        used_worker_resources,
+                        total_worker_resources,
+                    } if self.json() => {
+                        print::json(&serde_json::json!({
+                            "used_resources": used_resources,
+                            "total_resources": total_resources,
+                            "used_worker_resources": used_worker_resources,
+                            "total_worker_resources": total_worker_resources,
+                        }))?;
+                    }
                     ServerToClientMessage::ResourceList {
                         used_resources,
                         total_resources,
+                        used_worker_resources,
+                        total_worker_resources,
                     } => {
-                        print::resource_list(used_resources, total_resources);
+                        print::resource_list(
+                            used_resources,
+                            total_resources,
+                            used_worker_resources,
+                            total_worker_resources,
+                        );
                     }
                     other => {
                         bail!("Expected ResourceList, received: {:?}", other);
                     }
                 }
             }
+            Command::Stats { idle: true, .. } => {
+                if !self.server_capabilities.contains(CAP_IDLE_REPORT) {
+                    bail!("The server does not support reporting idle resources!");
+                }
+                self.stream
+                    .send(&ClientToServerMessage::GetIdleResources)
+                    .await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::IdleResources(report) if self.json() => {
+                        print::json(&report)?;
+                    }
+                    ServerToClientMessage::IdleResources(report) => {
+                        print::idle_resources(&report);
+                    }
+                    other => {
+                        bail!("Expected IdleResources, received: {:?}", other);
+                    }
+                }
+            }
+            Command::Stats { by_project, .. } => {
+                if !self.server_capabilities.contains(CAP_PROJECTS) {
+                    bail!("The server does not support usage statistics!");
+                }
+                self.stream
+                    .send(&ClientToServerMessage::GetProjectUsage)
+                    .await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::ProjectUsage(usage) if self.json() => {
+                        print::json(&usage)?;
+                    }
+                    ServerToClientMessage::ProjectUsage(usage) => {
+                        print::project_usage(usage, by_project);
+                    }
+                    other => {
+                        bail!("Expected ProjectUsage, received: {:?}", other);
+                    }
+                }
+            }
+            Command::Acct { user, since, until } => {
+                if !self.server_capabilities.contains(CAP_ACCOUNTING) {
+                    bail!("The server does not support accounting!");
+                }
+                let message = ClientToServerMessage::GetAccounting { user, since, until };
+                self.stream.send(&message).await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::UserUsage(usage) if self.json() => print::json(&usage)?,
+                    ServerToClientMessage::UserUsage(usage) => print::user_usage(&usage),
+                    ServerToClientMessage::RequestResponse { text, .. } => {
+                        return Err(ClientError::refused(text));
+                    }
+                    other => {
+                        bail!("Expected UserUsage, received: {:?}", other);
+                    }
+                }
+            }
+            Command::Templates { command } => match command {
+                TemplatesCommand::List => {
+                    let templates = self.list_templates().await?;
+                    if self.json() {
+                        print::json(&templates)?;
+                    } else {
+                        print::job_templates(&templates);
+                    }
+                }
+            },
+            Command::Top { interval } => {
+                // Cancel and clean actions require authentication.
+                self.authenticate().await?;
+                self.top(interval).await?;
+            }
             // Shell completion is already handled in main function.
-            Command::Complete { .. } => unreachable!(),
+            Command::Admin { command } => {
+                // Admin commands require authentication.
+                self.authenticate_admin().await?;
+                let admin_commands = self.server_capabilities.contains(CAP_ADMIN_COMMANDS);
+
+                let message = match command {
+                    AdminCommand::Motd { text } => ClientToServerMessage::SetMotd { text },
+                    AdminCommand::Broadcast { text, minutes } => ClientToServerMessage::Broadcast {
+                        text,
+                        expires_in_minutes: minutes,
+                    },
+                    AdminCommand::RemoveWorker {
+                        worker_id,
+                        kill_jobs,
+                        requeue_jobs: _, // default
+                        cooldown,
+                    } => ClientToServerMessage::RemoveWorker {
+                        worker_id,
+                        fail_jobs: kill_jobs,
+                        cooldown_minutes: cooldown,
+                    },
+                    AdminCommand::Connections => {
+                        if !self.server_capabilities.contains(CAP_CONNECTION_INFO) {
+                            bail!("The server does not support listing connections!");
+                        }
+                        ClientToServerMessage::ListConnections
+                    }
+                    AdminCommand::Trace { job_id } => {
+                        if !self.server_capabilities.contains(CAP_SCHEDULING_TRACE) {
+                            bail!("The server does not support scheduling traces!");
+                        }
+                        ClientToServerMessage::ShowSchedulingTrace { job_id }
+                    }
+                    AdminCommand::RenameWorker { worker_id, name } => {
+                        if !self.server_capabilities.contains(CAP_WORKER_RENAMING) {
+                            bail!("The server does not support renaming workers!");
+                        }
+                        ClientToServerMessage::RenameWorker {
+                            worker_id,
+                            worker_name: name,
+                        }
+                    }
+                    AdminCommand::Drain { .. }
+                    | AdminCommand::Reserve { .. }
+                    | AdminCommand::Snapshot { .. }
+                    | AdminCommand::LogLevel { .. }
+                    | AdminCommand::Audit
+                        if !admin_commands =>
+                    {
+                        bail!("The server does not support this administrative command!");
+                    }
+                    AdminCommand::Drain { worker_id, undo } => ClientToServerMessage::DrainWorker {
+                        worker_id,
+                        drain: !undo,
+                    },
+                    AdminCommand::Reserve { worker_id, user } => {
+                        ClientToServerMessage::ReserveWorker { worker_id, user }
+                    }
+                    AdminCommand::Migrate {
+                        job_id,
+                        signal,
+                        grace_period,
+                        checkpoint,
+                    } => {
+                        if !self.server_capabilities.contains(CAP_JOB_MIGRATION) {
+                            bail!("The server does not support job migration!");
+                        }
+                        let migration = JobMigration {
+                            signal,
+                            grace_period_seconds: grace_period,
+                            checkpoint,
+                        };
+                        ClientToServerMessage::MigrateJob { job_id, migration }
+                    }
+                    AdminCommand::Snapshot { path } => ClientToServerMessage::SaveSnapshot { path },
+                    AdminCommand::LogLevel { level } => {
+                        ClientToServerMessage::SetLogLevel { level }
+                    }
+                    AdminCommand::Audit => ClientToServerMessage::ShowAuditLog,
+                };
+                self.stream.send(&message).await?;
+
+                // Await results.
+                match self.receive().await? {
+                    ServerToClientMessage::RequestResponse { success: _, text } => {
+                        println!("{}", text)
+                    }
+                    ServerToClientMessage::ConnectionList(connection_list) => {
+                        print::connection_list(connection_list);
+                    }
+                    ServerToClientMessage::SchedulingTrace { job_id, events } => {
+                        print::scheduling_trace(job_id, &events);
+                    }
+                    ServerToClientMessage::AuditLog(entries) => print::audit_log(&entries),
+                    other => {
+                        bail!("Expected RequestResponse, received: {:?}", other);
+                    }
+                }
+            }
+            Command::RunLocal { .. }
+            | Command::Standalone
+            | Command::Config { .. }
+            | Command::Complete { .. } => {
+                unreachable!()
+            }
         }
 
         // Say bye to gracefully shut down connection.
@@ -348,16 +1240,36 @@ impl Client {
     }
 
     async fn authenticate(&mut self) -> Result<()> {
+        if self.authenticated {
+            return Ok(());
+        }
+        let secret = self.config.common_settings.shared_secret.clone();
+        self.authenticate_with(&secret).await
+    }
+
+    /// Authenticate for administrative commands, with the admin secret if
+    /// configured.
+    async fn authenticate_admin(&mut self) -> Result<()> {
+        let settings = &self.config.common_settings;
+        let secret = settings
+            .admin_secret
+            .as_ref()
+            .unwrap_or(&settings.shared_secret);
+        let secret = secret.clone();
+        self.authenticate_with(&secret).await
+    }
+
+    async fn authenticate_with(&mut self, secret: &str) -> Result<()> {
         // Request authentication.
         self.stream
             .send(&ClientToServerMessage::AuthRequest)
             .await?;
 
         // Await authentication challenge.
-        match self.stream.receive::<ServerToClientMessage>().await? {
+        match self.receive().await? {
             ServerToClientMessage::AuthChallenge { salt } => {
                 // Calculate response.
-                let salted_secret = self.config.common_settings.shared_secret.clone() + &salt;
+                let salted_secret = secret.to_string() + &salt;
                 let salted_secret = salted_secret.into_bytes();
                 let mut hasher = Sha256::new();
                 hasher.update(salted_secret);
@@ -374,15 +1286,337 @@ impl Client {
         }
 
         // Await authentication confirmation.
-        match self.stream.receive::<ServerToClientMessage>().await? {
+        match self.receive().await? {
             ServerToClientMessage::AuthAccepted(accepted) => {
                 if accepted {
+                    self.authenticated = true;
                     Ok(())
                 } else {
-                    bail!("Authentication failed!")
+                    bail!(ClientError::AuthenticationFailed)
                 }
             }
             other => bail!("Expected AuthAccepted, received: {:?}", other),
         }
     }
+
+    /// Cipher for job payloads, based on the configured payload key.
+    /// Get the job templates published by the server.
+    async fn list_templates(&mut self) -> Result<Vec<JobTemplate>> {
+        if !self.server_capabilities.contains(CAP_JOB_TEMPLATES) {
+            bail!("The server does not support job templates!");
+        }
+        self.stream
+            .send(&ClientToServerMessage::ListTemplates)
+            .await?;
+        match self.receive().await? {
+            ServerToClientMessage::JobTemplates(templates) => Ok(templates),
+            ServerToClientMessage::RequestResponse { text, .. } => Err(ClientError::refused(text)),
+            other => bail!("Expected JobTemplates, received: {:?}", other),
+        }
+    }
+
+    /// Get the job template with the given name from the server.
+    async fn get_template(&mut self, name: &str) -> Result<JobTemplate> {
+        let templates = self.list_templates().await?;
+        match templates.into_iter().find(|template| template.name == name) {
+            Some(template) => Ok(template),
+            None => bail!(ClientError::NotFound(format!(
+                "Template '{}' not found!",
+                name
+            ))),
+        }
+    }
+
+    /// Reserve the ID of a job to be issued on the server.
+    async fn reserve_job_id(&mut self) -> Result<u64> {
+        self.authenticate().await?;
+        self.stream
+            .send(&ClientToServerMessage::ReserveJobId)
+            .await?;
+        match self.receive().await? {
+            ServerToClientMessage::JobIdReserved(job_id) => Ok(job_id),
+            ServerToClientMessage::RequestResponse { text, .. } => Err(ClientError::refused(text)),
+            other => bail!("Expected JobIdReserved, received: {other:?}"),
+        }
+    }
+
+    fn payload_cipher(&self) -> Result<PayloadCipher> {
+        let settings = &self.config.common_settings;
+        match &settings.payload_key {
+            Some(key) => Ok(PayloadCipher::new(key, settings.tenant.as_deref())),
+            None => bail!("No payload_key configured to encrypt or decrypt job payloads!"),
+        }
+    }
+}
+
+/// Apply output settings from command line and config to the print module.
+fn configure_output(args: &Cli, config: &Config) -> Result<()> {
+    // Select display format of dates and times.
+    let time_format = match args.time_format {
+        Some(time_format) => time_format,
+        None => TimeFormat::from_str(&config.client_settings.time_format, true)
+            .map_err(|e| anyhow!("Invalid time format in config: {}", e))?,
+    };
+    print::set_time_format(time_format);
+
+    // Select colorization of the output.
+    let color = match args.color {
+        Some(color) => color,
+        None => ColorChoice::from_str(&config.client_settings.color, true)
+            .map_err(|e| anyhow!("Invalid color setting in config: {}", e))?,
+    };
+    match color {
+        // The console crate already checks for a terminal and `CLICOLOR`.
+        ColorChoice::Auto => {
+            if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+                console::set_colors_enabled(false);
+            }
+        }
+        ColorChoice::Always => console::set_colors_enabled(true),
+        ColorChoice::Never => console::set_colors_enabled(false),
+    }
+    let theme = match args.theme {
+        Some(theme) => theme,
+        None => ColorTheme::from_str(&config.client_settings.theme, true)
+            .map_err(|e| anyhow!("Invalid color theme in config: {}", e))?,
+    };
+    print::set_theme(theme);
+    Ok(())
+}
+
+/// Worker resources of a job, falling back to the defaults of the
+/// client config for resources not given on the command line.
+fn default_resources(
+    config: &Config,
+    job_slots: Option<u64>,
+    cpus: Option<u64>,
+    ram_mb: Option<u64>,
+) -> Resources {
+    let settings = &config.client_settings;
+    Resources::new(
+        job_slots.unwrap_or(settings.job_default_job_slots),
+        cpus.unwrap_or(settings.job_default_cpus),
+        ram_mb.unwrap_or(settings.job_default_ram_mb),
+    )
+}
+
+/// Working directory of jobs submitted from the current directory. Unless
+/// `canonicalize_cwd` is disabled, symbolic links are resolved.
+fn submission_cwd(config: &Config) -> Result<PathBuf> {
+    let cwd = canonicalize(std::env::current_dir()?)?;
+    if !config.client_settings.canonicalize_cwd {
+        // The shell keeps track of the path including symbolic links.
+        if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from) {
+            if pwd.is_absolute() && canonicalize(&pwd).is_ok_and(|pwd| pwd == cwd) {
+                return Ok(pwd);
+            }
+        }
+    }
+    Ok(cwd)
+}
+
+/// Returns `true` if the path is within a directory that is usually local to
+/// each machine, such as `/tmp`, rather than on a shared file system.
+fn is_local_path(path: &Path) -> bool {
+    const LOCAL_DIRECTORIES: &[&str] = &["/tmp", "/var/tmp", "/dev/shm", "/run/user"];
+    LOCAL_DIRECTORIES.iter().any(|dir| path.starts_with(dir))
+}
+
+/// Run the `--on-finish` command of `kueue wait-job` in a shell, after
+/// replacing the placeholders with information about the concluded job.
+async fn run_on_finish(command: &str, job_info: &JobInfo) -> Result<()> {
+    let (status, exit_code) = match &job_info.status {
+        JobStatus::Finished { return_code, .. } if *return_code == 0 => ("succeeded", "0".into()),
+        JobStatus::Finished { return_code, .. } => ("failed", return_code.to_string()),
+        _ => ("canceled", String::new()),
+    };
+    let command = command
+        .replace("{id}", &job_info.job_id.to_string())
+        .replace("{status}", status)
+        .replace("{exit_code}", &exit_code);
+
+    let exit_status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .status()
+        .await
+        .map_err(|e| anyhow!("Failed to run '{}': {}", command, e))?;
+    if !exit_status.success() {
+        bail!("Command '{}' failed with {}!", command, exit_status);
+    }
+    Ok(())
+}
+
+/// Parse resource parameters, e.g., "license=2", into a map.
+fn parse_global_resources(resources: Vec<String>) -> Result<Option<BTreeMap<String, u64>>> {
+    let mut global_resources: BTreeMap<String, u64> = BTreeMap::new();
+    for resource in resources {
+        let parts: Vec<_> = resource.split('=').collect();
+        if parts.len() == 1 {
+            global_resources.insert(resource, 1);
+        } else if parts.len() == 2 {
+            let res_key = parts.first().unwrap().to_string();
+            let amount: u64 = parts
+                .last()
+                .unwrap()
+                .parse()
+                .map_err(|err| anyhow!("Failed to parse resource: '{}', {}", resource, err))?;
+            global_resources.insert(res_key, amount);
+        } else {
+            bail!("Failed to parse resource: {}", resource);
+        }
+    }
+    if global_resources.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(global_resources))
+    }
+}
+
+/// Split setup and teardown steps into programs and arguments.
+fn parse_steps(steps: Vec<String>) -> Result<Vec<Vec<String>>> {
+    steps
+        .iter()
+        .map(|step| match shell_words::split(step) {
+            Ok(cmd) if !cmd.is_empty() => Ok(cmd),
+            Ok(_) => bail!("Empty step command!"),
+            Err(e) => bail!("Failed to parse step '{}': {}", step, e),
+        })
+        .collect()
+}
+
+/// Parse exit codes, e.g., "75" or "any", into policies with the given action.
+fn parse_exit_code_policies(
+    exit_codes: Vec<String>,
+    action: ExitCodeAction,
+) -> Result<Vec<ExitCodePolicy>> {
+    exit_codes
+        .iter()
+        .map(|exit_code| {
+            let exit_code = match exit_code.trim() {
+                "any" => None,
+                code => Some(
+                    code.parse()
+                        .map_err(|err| anyhow!("Invalid exit code '{}': {}", code, err))?,
+                ),
+            };
+            Ok(ExitCodePolicy { exit_code, action })
+        })
+        .collect()
+}
+
+/// Upper limit of the size of the environment recorded with a job, so that
+/// huge environments do not bloat the server's memory.
+const ENV_SNAPSHOT_MAX_BYTES: usize = 64 * 1024;
+
+/// Parts of variable names that suggest a secret value, e.g., "GITHUB_TOKEN".
+const SECRET_VARIABLE_PATTERNS: [&str; 9] = [
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "PRIVATE",
+    "KEY",
+    "AUTH",
+    "COOKIE",
+];
+
+/// Placeholder recorded in place of the value of secret-looking variables.
+const REDACTED_VALUE: &str = "<redacted>";
+
+/// Returns `true` if the name of the variable suggests that it holds a secret.
+fn is_secret_variable(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_VARIABLE_PATTERNS
+        .iter()
+        .any(|pattern| name.contains(pattern))
+}
+
+/// Record environment variables and umask of the current process. Values of
+/// variables that look like secrets are redacted.
+fn snapshot_environment() -> EnvSnapshot {
+    let mut variables = BTreeMap::new();
+    let mut size = 0;
+    let mut truncated = false;
+    for (key, value) in std::env::vars_os() {
+        let key = key.to_string_lossy().to_string();
+        let value = if is_secret_variable(&key) {
+            REDACTED_VALUE.to_string()
+        } else {
+            value.to_string_lossy().to_string()
+        };
+        if size + key.len() + value.len() > ENV_SNAPSHOT_MAX_BYTES {
+            truncated = true;
+            continue;
+        }
+        size += key.len() + value.len();
+        variables.insert(key, value);
+    }
+    EnvSnapshot {
+        variables,
+        umask: current_umask(),
+        truncated,
+    }
+}
+
+/// Returns the file mode creation mask of the current process.
+#[cfg(unix)]
+fn current_umask() -> Option<u32> {
+    // Safety: umask cannot fail. The mask is restored right away.
+    let mask = unsafe { libc::umask(0o022) };
+    unsafe { libc::umask(mask) };
+    Some(mask as u32)
+}
+
+#[cfg(not(unix))]
+fn current_umask() -> Option<u32> {
+    None
+}
+
+/// Parse a comma-separated list of job list columns.
+fn parse_job_columns(columns: &str) -> Result<Vec<JobColumn>> {
+    columns
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .map(|column| {
+            JobColumn::from_str(column, true)
+                .map_err(|e| anyhow!("Invalid job list column in config: {}", e))
+        })
+        .collect()
+}
+
+/// Sort workers by the given criterion. Load-related criteria sort the busiest
+/// workers first, so that problematic workers show up on top of the list.
+fn sort_workers(worker_list: &mut [WorkerInfo], sort: WorkerSort) {
+    match sort {
+        WorkerSort::Id => worker_list.sort_by_key(|info| info.worker_id),
+        WorkerSort::Name => worker_list.sort_by(|a, b| a.worker_name.cmp(&b.worker_name)),
+        WorkerSort::Busy => {
+            worker_list.sort_by(|a, b| b.resource_load().total_cmp(&a.resource_load()))
+        }
+        WorkerSort::Load => {
+            let relative_load = |info: &WorkerInfo| {
+                info.system_info.load_info.one / info.system_info.cpu_cores.max(1) as f64
+            };
+            worker_list.sort_by(|a, b| relative_load(b).total_cmp(&relative_load(a)))
+        }
+        WorkerSort::Jobs => worker_list.sort_by_key(|info| std::cmp::Reverse(info.jobs_total())),
+        WorkerSort::Uptime => worker_list.sort_by_key(|info| info.connected_since),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_variables() {
+        assert!(is_secret_variable("GITHUB_TOKEN"));
+        assert!(is_secret_variable("aws_secret_access_key"));
+        assert!(is_secret_variable("PGPASSWORD"));
+        assert!(!is_secret_variable("PATH"));
+        assert!(!is_secret_variable("HOME"));
+    }
 }
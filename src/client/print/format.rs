@@ -1,7 +1,16 @@
-use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
-use std::cmp::max;
+use crate::client::cli::TimeFormat;
+use chrono::{DateTime, Datelike, Local, SecondsFormat, Utc};
+use std::{cmp::max, sync::OnceLock};
 use terminal_size::terminal_size;
 
+/// Display format of dates, set once on client start.
+static TIME_FORMAT: OnceLock<TimeFormat> = OnceLock::new();
+
+/// Set the display format of dates. Only the first call has an effect.
+pub fn set_time_format(time_format: TimeFormat) {
+    let _ = TIME_FORMAT.set(time_format);
+}
+
 /// Returns the terminal's width and height.
 pub fn term_size() -> (usize, usize) {
     // Try to detect terminal size
@@ -70,27 +79,86 @@ pub fn col_widths(min_col_widths: Vec<usize>, max_col_widths: Vec<usize>) -> Vec
 
 /// Format a text with leading dots, if `len` is exceeded.
 pub fn dots_front(text: String, len: usize) -> String {
-    if text.len() <= len {
+    let text_len = text.chars().count();
+    if text_len <= len {
         text
+    } else if len <= 3 {
+        ".".repeat(len)
     } else {
-        "...".to_string() + &text[(text.len() - (len - 3))..]
+        let skip = text_len - (len - 3);
+        "...".to_string() + &text.chars().skip(skip).collect::<String>()
     }
 }
 
 /// Format a text with trailing dots, if `len` is exceeded.
 pub fn dots_back(text: String, len: usize) -> String {
-    if text.len() <= len {
+    if text.chars().count() <= len {
         text
+    } else if len <= 3 {
+        ".".repeat(len)
+    } else {
+        text.chars().take(len - 3).collect::<String>() + "..."
+    }
+}
+
+/// Render a bar of `len` characters, filled according to `used` of `total`.
+pub fn bar(used: u64, total: u64, len: usize) -> String {
+    let filled = if total == 0 {
+        0
     } else {
-        text[..(len - 3)].to_owned() + "..."
+        // Round up, so that any allocation is visible.
+        let filled = (used as u128 * len as u128).div_ceil(total as u128);
+        std::cmp::min(filled as usize, len)
+    };
+    "█".repeat(filled) + &"░".repeat(len - filled)
+}
+
+/// Wrap a text into lines of at most `len` characters. Lines are broken at
+/// whitespace where possible and at `len` otherwise.
+pub fn wrap(text: &str, len: usize) -> Vec<String> {
+    let len = max(len, 1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+
+    for word in text.split(' ') {
+        let mut word: Vec<char> = word.chars().collect();
+        // Start a new line if the word does not fit into the current one.
+        if line_len > 0 && line_len + 1 + word.len() > len {
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+        }
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        // Break words that are longer than a whole line.
+        while line_len + word.len() > len {
+            let rest = word.split_off(len - line_len);
+            line.extend(word);
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+            word = rest;
+        }
+        line_len += word.len();
+        line.extend(word);
+    }
+    lines.push(line);
+    lines
+}
+
+/// Format a date according to the configured time format.
+pub fn date(date: &DateTime<Utc>) -> String {
+    match TIME_FORMAT.get().copied().unwrap_or_default() {
+        TimeFormat::Local => local_date(date),
+        TimeFormat::Utc => date.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        TimeFormat::Iso => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+        TimeFormat::Relative => relative_date(date, &Utc::now()),
     }
 }
 
 /// Format a date in local time zone.
-pub fn date<Tz: TimeZone>(date: &DateTime<Tz>) -> String
-where
-    DateTime<Tz>: std::convert::Into<DateTime<Local>>,
-{
+fn local_date(date: &DateTime<Utc>) -> String {
     let date: DateTime<Local> = date.to_owned().into();
     let today = Local::now();
 
@@ -105,6 +173,26 @@ where
     date.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
+/// Format a date relative to `now`, e.g., "3h ago".
+fn relative_date(date: &DateTime<Utc>, now: &DateTime<Utc>) -> String {
+    let seconds = (*now - *date).num_seconds();
+    let (amount, suffix) = if seconds < 0 {
+        (-seconds, "from now")
+    } else {
+        (seconds, "ago")
+    };
+
+    if amount < 60 {
+        format!("{}s {}", amount, suffix)
+    } else if amount < 3600 {
+        format!("{}m {}", amount / 60, suffix)
+    } else if amount < 24 * 3600 {
+        format!("{}h {}", amount / 3600, suffix)
+    } else {
+        format!("{}d {}", amount / (24 * 3600), suffix)
+    }
+}
+
 /// Format a date into elapsed time from now.
 pub fn elapsed_since(started: &DateTime<Utc>) -> String {
     let seconds = (Utc::now() - *started).num_seconds();
@@ -118,3 +206,44 @@ pub fn elapsed_seconds(seconds: i64) -> String {
     let s = seconds % 60;
     format!("{}h:{:02}m:{:02}s", h, m, s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn shorten_and_wrap() {
+        assert_eq!(dots_back("äöü-long-text".into(), 8), "äöü-l...");
+        assert_eq!(dots_front("/home/user/dir".into(), 8), "...r/dir");
+        assert_eq!(wrap("echo hello world", 10), vec!["echo hello", "world"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn resource_bars() {
+        assert_eq!(bar(0, 8, 4), "░░░░");
+        assert_eq!(bar(1, 8, 4), "█░░░");
+        assert_eq!(bar(8, 8, 4), "████");
+        assert_eq!(bar(9, 8, 4), "████");
+        assert_eq!(bar(1, 0, 4), "░░░░");
+    }
+
+    #[test]
+    fn relative_dates() {
+        let now = Utc::now();
+        assert_eq!(
+            relative_date(&(now - Duration::seconds(42)), &now),
+            "42s ago"
+        );
+        assert_eq!(
+            relative_date(&(now - Duration::minutes(90)), &now),
+            "1h ago"
+        );
+        assert_eq!(relative_date(&(now - Duration::days(3)), &now), "3d ago");
+        assert_eq!(
+            relative_date(&(now + Duration::minutes(5)), &now),
+            "5m from now"
+        );
+    }
+}
@@ -1,13 +1,28 @@
 mod format;
+mod theme;
 
-use crate::structs::{JobInfo, JobStatus, WorkerInfo};
+use crate::{
+    client::cli::JobColumn,
+    messages::version::versions_compatible,
+    structs::{
+        AuditEntry, ConnectionInfo, EnvSnapshot, ExecutionEnvironment, FailureKind, IdleResources,
+        JobAttempt, JobInfo, JobReceipt, JobStatus, JobTemplate, OutputStream, ProcessLimits,
+        ProjectUsage, SchedulingEvent, SchedulingOutcome, StepResult, UserUsage, WorkerInfo,
+    },
+    worker::JobResult,
+};
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use console::{style, StyledObject};
-pub use format::term_size;
+pub use format::{set_time_format, term_size};
+use serde::Serialize;
 use std::{
     cmp::max,
     collections::{BTreeMap, BTreeSet},
+    io::Write,
 };
+pub use theme::set_theme;
+use theme::{paint, Role};
 
 fn format_cpu_cores(cpu_cores: u64) -> String {
     format!("{} x", cpu_cores)
@@ -17,22 +32,23 @@ fn format_memory_mb(memory_mb: u64) -> String {
     format!("{} MB", memory_mb)
 }
 
-fn format_global_resources(global_resources: &Option<BTreeMap<String, u64>>) -> String {
-    if let Some(resources) = global_resources {
-        let mut global_resources_string = String::new();
-        for (resource, amount) in resources {
-            if !global_resources_string.is_empty() {
-                global_resources_string += ", ";
-            }
-            global_resources_string += &format!("{amount}x {resource}");
-        }
-        global_resources_string
-    } else {
+fn format_global_resources(
+    global_resources: &Option<BTreeMap<String, u64>>,
+    named_resources: &BTreeMap<String, u64>,
+) -> String {
+    let resources = global_resources.iter().flatten().chain(named_resources);
+    let resources_string = resources
+        .map(|(resource, amount)| format!("{amount}x {resource}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if resources_string.is_empty() {
         "---".to_string()
+    } else {
+        resources_string
     }
 }
 
-fn format_worker(job_status: &JobStatus) -> String {
+pub(super) fn format_worker(job_status: &JobStatus) -> String {
     match job_status {
         JobStatus::Offered { worker, .. } => worker.clone(),
         JobStatus::Running { worker, .. } => worker.clone(),
@@ -41,7 +57,7 @@ fn format_worker(job_status: &JobStatus) -> String {
     }
 }
 
-fn format_status(job_status: &JobStatus) -> String {
+pub(super) fn format_status(job_status: &JobStatus) -> String {
     match job_status {
         JobStatus::Pending { issued } => {
             format!("pending since {}", format::date(issued))
@@ -55,27 +71,354 @@ fn format_status(job_status: &JobStatus) -> String {
         JobStatus::Finished {
             return_code,
             run_time_seconds,
+            failure,
             ..
-        } => {
+        } => match failure {
+            _ if *return_code == 0 => format!(
+                "finished after {}",
+                format::elapsed_seconds(*run_time_seconds)
+            ),
+            Some(FailureKind::NonzeroExit) | None => format!("failed with code {}", return_code),
+            Some(failure) => format!("failed: {}", failure),
+        },
+        JobStatus::Canceled {
+            canceled, reason, ..
+        } => match reason {
+            Some(reason) => format!("canceled on {}: {}", format::date(canceled), reason),
+            None => format!("canceled on {}", format::date(canceled)),
+        },
+    }
+}
+
+fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}={}", label, value))
+        .collect();
+    labels.join(", ")
+}
+
+fn format_runtime(job_status: &JobStatus) -> String {
+    match job_status {
+        JobStatus::Running { started, .. } => format::elapsed_since(started),
+        JobStatus::Finished {
+            run_time_seconds, ..
+        } => format::elapsed_seconds(*run_time_seconds),
+        _ => "---".to_string(),
+    }
+}
+
+fn job_column_header(column: JobColumn) -> &'static str {
+    match column {
+        JobColumn::Id => "id",
+        JobColumn::Cwd => "working directory",
+        JobColumn::Cmd => "command",
+        JobColumn::Cpus => "cpus",
+        JobColumn::Memory => "memory",
+        JobColumn::Resources => "other",
+        JobColumn::Worker => "worker",
+        JobColumn::Status => "status",
+        JobColumn::Runtime => "runtime",
+        JobColumn::Priority => "prio",
+        JobColumn::Qos => "qos",
+    }
+}
+
+fn job_column_text(column: JobColumn, job_info: &JobInfo) -> String {
+    match column {
+        JobColumn::Id => job_info.job_id.to_string(),
+        JobColumn::Cwd => job_info.cwd.to_string_lossy().to_string(),
+        JobColumn::Cmd => job_info.cmd.join(" "),
+        JobColumn::Cpus => format_cpu_cores(job_info.worker_resources.cpus),
+        JobColumn::Memory => format_memory_mb(job_info.worker_resources.ram_mb),
+        JobColumn::Resources => {
+            format_global_resources(&job_info.global_resources, &job_info.worker_resources.named)
+        }
+        JobColumn::Worker => format_worker(&job_info.status),
+        JobColumn::Status if job_info.stalled && job_info.status.is_running() => {
+            format!("{}, stalled", format_status(&job_info.status))
+        }
+        JobColumn::Status => format_status(&job_info.status),
+        JobColumn::Runtime => format_runtime(&job_info.status),
+        JobColumn::Priority => job_info.priority.to_string(),
+        JobColumn::Qos => job_info.qos.clone().unwrap_or("---".to_string()),
+    }
+}
+
+/// Columns that are never shortened, because their content is always short.
+fn job_column_fixed(column: JobColumn) -> bool {
+    matches!(
+        column,
+        JobColumn::Id
+            | JobColumn::Cpus
+            | JobColumn::Memory
+            | JobColumn::Runtime
+            | JobColumn::Priority
+    )
+}
+
+fn job_status_role(job_status: &JobStatus) -> Role {
+    match job_status {
+        JobStatus::Pending { .. } => Role::Pending,
+        JobStatus::Offered { .. } => Role::Offered,
+        JobStatus::Running { .. } => Role::Running,
+        JobStatus::Finished { return_code, .. } => {
             if *return_code == 0 {
-                format!(
-                    "finished after {}",
-                    format::elapsed_seconds(*run_time_seconds)
-                )
+                Role::Succeeded
             } else {
-                format!("failed with code {}", return_code)
+                Role::Failed
             }
         }
-        JobStatus::Canceled { canceled, .. } => {
-            format!("canceled on {}", format::date(canceled))
+        JobStatus::Canceled { .. } => Role::Canceled,
+    }
+}
+
+/// Print message of the day or broadcast from the server to stderr.
+pub fn announcement(text: &str, broadcast: bool) {
+    let title = if broadcast {
+        "Broadcast"
+    } else {
+        "Message of the day"
+    };
+    eprintln!(
+        "{}: {}",
+        paint(Role::Warning, title).for_stderr().bold(),
+        text
+    );
+}
+
+/// Name, command, and outcome of a job step.
+fn format_step_result(step: &StepResult) -> String {
+    let outcome = match step.exit_code {
+        Some(0) => paint(Role::Succeeded, "exit code 0".to_string()),
+        Some(code) => paint(Role::Failed, format!("exit code {code}")),
+        None => paint(Role::Canceled, "skipped".to_string()),
+    };
+    format!(
+        "{}: {} ({}, {})",
+        step.name,
+        step.cmd.join(" "),
+        outcome,
+        format::elapsed_seconds(step.run_time_seconds)
+    )
+}
+
+fn format_attempt(number: usize, attempt: &JobAttempt) -> String {
+    let outcome = match attempt.exit_code {
+        Some(code) => paint(Role::Failed, format!("exit code {code}")),
+        None => paint(Role::Canceled, "interrupted".to_string()),
+    };
+    let run_time = (attempt.finished - attempt.started).num_seconds();
+    let mut text = format!(
+        "{}. on {}, started {} ({}, {})",
+        number,
+        attempt.worker,
+        format::date(&attempt.started),
+        outcome,
+        format::elapsed_seconds(run_time)
+    );
+    if !attempt.comment.is_empty() {
+        text += &format!(": {}", attempt.comment);
+    }
+    text
+}
+
+/// Print the outcome of a job run with `kueue run-local`. The job's output
+/// is passed through to stdout and stderr, the summary goes to stderr.
+pub fn local_job_result(result: &JobResult) {
+    print!("{}", result.stdout_text);
+    eprint!("{}", result.stderr_text);
+    if result.step_results.len() > 1 {
+        for step in &result.step_results {
+            eprintln!("{}", format_step_result(step));
         }
     }
+    let run_time = format::elapsed_seconds(result.run_time.num_seconds());
+    let outcome = match result.failure {
+        None => paint(Role::Succeeded, format!("finished after {}", run_time)),
+        Some(failure) => paint(
+            Role::Failed,
+            format!("failed with exit code {} ({})", result.exit_code, failure),
+        ),
+    };
+    eprintln!("job {}: {}", outcome, result.comment);
+    if let Some(usage) = &result.resource_usage {
+        eprintln!(
+            "measured usage: {:.1} CPU cores on average, {} megabytes of RAM at peak",
+            usage.avg_cpus, usage.peak_ram_mb
+        );
+    }
+}
+
+/// Print a recorded environment as shell commands, so that it can be
+/// restored with `eval "$(kueue show-job <id> --env)"`.
+pub fn env_snapshot(env_snapshot: &EnvSnapshot) {
+    if env_snapshot.truncated {
+        println!("# Some variables have been left out to limit the size!");
+    }
+    if let Some(umask) = env_snapshot.umask {
+        println!("umask {:04o}", umask);
+    }
+    for (key, value) in &env_snapshot.variables {
+        println!("export {}={}", key, shell_words::quote(value));
+    }
+}
+
+/// Print the value as pretty JSON, for `--output json`.
+pub fn json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print the execution environment recorded with a finished job.
+pub fn execution_environment(environment: &ExecutionEnvironment) {
+    println!("captured: {}", format::date(&environment.captured));
+    println!("os: {}", environment.os);
+    println!("kernel: {}", environment.kernel);
+    println!("cpu: {}", environment.cpu_model);
+    if let Some(probe_output) = &environment.probe_output {
+        println!("{}", style("--- probe output ---").bold());
+        println!("{}", probe_output.trim_end());
+    }
+}
+
+/// Print the status of a job of an observed group.
+pub fn group_job_update(job_info: &JobInfo) {
+    let status = format_status(&job_info.status);
+    println!(
+        "job {}: {} ({})",
+        job_info.job_id,
+        paint(job_status_role(&job_info.status), status),
+        job_info.cmd.join(" ")
+    );
+}
+
+/// Print the outcome of all jobs of an observed group.
+pub fn group_summary<'a>(group: &str, job_infos: impl Iterator<Item = &'a JobInfo>) {
+    let (mut succeeded, mut failed, mut canceled) = (0, 0, 0);
+    for job_info in job_infos {
+        if job_info.status.has_succeeded() {
+            succeeded += 1;
+        } else if job_info.status.has_failed() {
+            failed += 1;
+        } else if job_info.status.is_canceled() {
+            canceled += 1;
+        }
+    }
+    println!(
+        "All jobs of group '{}' have concluded: {} succeeded, {} failed, {} canceled",
+        group,
+        paint(Role::Succeeded, succeeded.to_string()),
+        paint(Role::Failed, failed.to_string()),
+        paint(Role::Canceled, canceled.to_string())
+    );
+}
+
+/// Print a passed check of `kueue config doctor`.
+pub fn check_passed(check: &str, detail: &str) {
+    println!("{} {}: {}", paint(Role::Good, "[ ok ]"), check, detail);
+}
+
+/// Print a failed check of `kueue config doctor`.
+pub fn check_failed(check: &str, error: &anyhow::Error) {
+    println!("{} {}: {}", paint(Role::Bad, "[fail]"), check, error);
+}
+
+/// Print validation report of a newly issued job to stderr.
+pub fn job_receipt(receipt: &JobReceipt) {
+    let resources = &receipt.worker_resources;
+    eprintln!(
+        "Job {} queued in '{}' with {} job slots, {} CPU cores, {} RAM, global resources: {}",
+        receipt.job_id,
+        receipt.queue,
+        resources.job_slots,
+        resources.cpus,
+        format_memory_mb(resources.ram_mb),
+        format_global_resources(&receipt.global_resources, &resources.named)
+    );
+    let estimated_wait = match receipt.estimated_wait_seconds {
+        Some(seconds) => format::elapsed_seconds(seconds),
+        None => "unknown".to_string(),
+    };
+    eprintln!(
+        "Matching workers: {} ({} with free resources now), estimated wait: {}",
+        receipt.candidate_workers, receipt.available_workers, estimated_wait
+    );
+    for warning in &receipt.warnings {
+        eprintln!(
+            "{}: {}",
+            paint(Role::Warning, "Warning").for_stderr().bold(),
+            warning
+        );
+    }
+}
+
+/// Show the progress of a batch submission on a single line on stderr. The
+/// line is only drawn if stderr is a terminal and finished with `done`.
+pub fn submission_progress(submitted: usize, total: usize, rejected: usize, done: bool) {
+    let term = console::Term::stderr();
+    if !term.is_term() {
+        return;
+    }
+    let _ = term.clear_line();
+    eprint!(
+        "{} {}/{} jobs submitted, {} accepted, {} rejected",
+        format::bar(submitted as u64, total as u64, 30),
+        submitted,
+        total,
+        paint(Role::Succeeded, (submitted - rejected).to_string()).for_stderr(),
+        paint(Role::Failed, rejected.to_string()).for_stderr()
+    );
+    if done {
+        eprintln!();
+    }
+}
+
+/// Print the jobs of a batch submission that have been rejected, together
+/// with the server's reasons, to stderr.
+pub fn rejected_jobs(rejected: &[(String, String)]) {
+    let widths = [
+        rejected
+            .iter()
+            .map(|(job, _)| job.len())
+            .max()
+            .unwrap_or(0)
+            .max(3),
+        rejected
+            .iter()
+            .map(|(_, reason)| reason.len())
+            .max()
+            .unwrap_or(0)
+            .max(6),
+    ];
+    eprintln!(
+        "| {: <job_width$} | {: <reason_width$} |",
+        style("job").for_stderr().bold().underlined(),
+        style("reason").for_stderr().bold().underlined(),
+        job_width = widths[0],
+        reason_width = widths[1]
+    );
+    for (job, reason) in rejected {
+        eprintln!(
+            "| {: <job_width$} | {: <reason_width$} |",
+            job,
+            reason,
+            job_width = widths[0],
+            reason_width = widths[1]
+        );
+    }
 }
 
 /// Print jobs to screen.
+///
+/// Only the given `columns` are shown. If the table does not fit into the
+/// terminal, long texts are shortened with dots or, if `wrap` is set, wrapped
+/// into multiple lines.
 #[allow(clippy::too_many_arguments)]
 pub fn job_list(
     job_infos: Vec<JobInfo>,
+    columns: &[JobColumn],
+    wrap: bool,
     jobs_pending: u64,
     jobs_offered: u64,
     jobs_running: u64,
@@ -87,143 +430,90 @@ pub fn job_list(
 ) {
     let mut footer_width = term_size().0;
 
-    if !job_infos.is_empty() {
-        // Get maximum column widths for space calculation.
-        let max_id_col_width = format!("{}", job_infos.last().unwrap().job_id).len();
-        let max_cwd_col_width = job_infos
-            .iter()
-            .map(|job_info| job_info.cwd.to_string_lossy().len())
-            .max()
-            .unwrap();
-        let max_cmd_col_width = job_infos
-            .iter()
-            .map(|job_info| job_info.cmd.join(" ").len())
-            .max()
-            .unwrap();
-        let max_cores_col_width = job_infos
-            .iter()
-            .map(|job_info| format_cpu_cores(job_info.worker_resources.cpus).len())
-            .max()
-            .unwrap();
-        let max_memory_col_width = job_infos
-            .iter()
-            .map(|job_info| format_memory_mb(job_info.worker_resources.ram_mb).len())
-            .max()
-            .unwrap();
-        let max_glob_res_col_width = job_infos
-            .iter()
-            .map(|job_info| format_global_resources(&job_info.global_resources).len())
-            .max()
-            .unwrap();
-        let max_worker_col_width = job_infos
-            .iter()
-            .map(|job_info| format_worker(&job_info.status).len())
-            .max()
-            .unwrap();
-        let max_status_col_width = job_infos
+    if !job_infos.is_empty() && !columns.is_empty() {
+        // Prepare cell contents for space calculation.
+        let rows: Vec<Vec<String>> = job_infos
             .iter()
-            .map(|job_info| format_status(&job_info.status).len())
-            .max()
-            .unwrap();
+            .map(|job_info| {
+                columns
+                    .iter()
+                    .map(|column| job_column_text(*column, job_info))
+                    .collect()
+            })
+            .collect();
 
-        let min_col_widths = vec![
-            max("id".len(), max_id_col_width),
-            "working directory".len(),
-            "command".len(),
-            max("cpus".len(), max_cores_col_width),
-            max("memory".len(), max_memory_col_width),
-            "other".len(), // global resources
-            "worker".len(),
-            "status".len(),
-        ];
-        let max_col_widths = vec![
-            0, // job id
-            max_cwd_col_width,
-            max_cmd_col_width,
-            0, // cpu cores
-            0, // memory
-            max_glob_res_col_width,
-            max_worker_col_width,
-            max_status_col_width,
-        ];
+        // Get minimum and maximum column widths.
+        let mut min_col_widths = Vec::new();
+        let mut max_col_widths = Vec::new();
+        for (index, column) in columns.iter().enumerate() {
+            let header_width = job_column_header(*column).chars().count();
+            let max_text_width = rows
+                .iter()
+                .map(|row| row[index].chars().count())
+                .max()
+                .unwrap();
+            if job_column_fixed(*column) {
+                min_col_widths.push(max(header_width, max_text_width));
+                max_col_widths.push(0);
+            } else {
+                min_col_widths.push(header_width);
+                max_col_widths.push(max_text_width);
+            }
+        }
 
         let col_widths = format::col_widths(min_col_widths, max_col_widths);
-        let (id_col, cwd_col, cmd_col, cores_col, memory_col, glob_res_col, worker_col, status_col) = (
-            col_widths[0],
-            col_widths[1],
-            col_widths[2],
-            col_widths[3],
-            col_widths[4],
-            col_widths[5],
-            col_widths[6],
-            col_widths[7],
-        );
-
         footer_width = col_widths.iter().sum::<usize>() + 3 * col_widths.len() + 1;
 
         // Print header
-        println!(
-            "| {: <id_col$} | {: <cwd_col$} | {: <cmd_col$} | \
-            {: <cores_col$} | {: <memory_col$} | {: <glob_res_col$} | \
-            {: <worker_col$} | {: <status_col$} |",
-            style("id").bold().underlined(),
-            style("working directory").bold().underlined(),
-            style("command").bold().underlined(),
-            style("cpus").bold().underlined(),
-            style("memory").bold().underlined(),
-            style("other").bold().underlined(),
-            style("worker").bold().underlined(),
-            style("status").bold().underlined(),
-        );
+        let mut line = String::from("|");
+        for (column, width) in columns.iter().zip(col_widths.iter()) {
+            let header = style(job_column_header(*column)).bold().underlined();
+            line += &format!(" {: <width$} |", header);
+        }
+        println!("{}", line);
 
-        for job_info in job_infos {
-            // working directory
-            let working_dir = job_info.cwd.to_string_lossy();
-            let working_dir = format::dots_front(working_dir.to_string(), cwd_col);
-
-            // command
-            let command = job_info.cmd.join(" ");
-            let command = format::dots_back(command, cmd_col);
-
-            let cpu_cores = format_cpu_cores(job_info.worker_resources.cpus);
-            let memory_mb = format_memory_mb(job_info.worker_resources.ram_mb);
-            let glob_res = format_global_resources(&job_info.global_resources);
-
-            // worker
-            let worker = format_worker(&job_info.status);
-            let worker = format::dots_back(worker, worker_col);
-
-            // status
-            let status = format_status(&job_info.status);
-            let status = format::dots_back(status, status_col);
-            let status = match job_info.status {
-                JobStatus::Pending { .. } => style(status),
-                JobStatus::Offered { .. } => style(status).dim(),
-                JobStatus::Running { .. } => style(status).blue(),
-                JobStatus::Finished { return_code, .. } => {
-                    if return_code == 0 {
-                        style(status).green()
+        for (job_info, row) in job_infos.iter().zip(rows) {
+            // Shorten or wrap cell contents to fit the column widths.
+            let cells: Vec<Vec<String>> = columns
+                .iter()
+                .zip(row)
+                .zip(col_widths.iter())
+                .map(|((column, text), width)| {
+                    if wrap {
+                        format::wrap(&text, *width)
+                    } else if *column == JobColumn::Cwd {
+                        vec![format::dots_front(text, *width)]
                     } else {
-                        style(status).red()
+                        vec![format::dots_back(text, *width)]
                     }
-                }
-                JobStatus::Canceled { .. } => style(status).yellow(),
-            };
+                })
+                .collect();
+            let num_lines = cells.iter().map(|cell| cell.len()).max().unwrap_or(1);
 
-            // Print line.
-            println!(
-                "| {: >id_col$} | {: <cwd_col$} | {: <cmd_col$} | \
-                {: >cores_col$} | {: >memory_col$} | {: <glob_res_col$} | \
-                {: <worker_col$} | {: <status_col$} |",
-                job_info.job_id,
-                working_dir,
-                command,
-                cpu_cores,
-                memory_mb,
-                glob_res,
-                worker,
-                status
-            );
+            // Print line(s).
+            for line_index in 0..num_lines {
+                let mut line = String::from("|");
+                for ((column, cell), width) in columns.iter().zip(&cells).zip(&col_widths) {
+                    let text = cell.get(line_index).map(String::as_str).unwrap_or("");
+                    // Short, fixed-width columns are aligned to the right.
+                    let text = if job_column_fixed(*column) {
+                        format!("{: >width$}", text)
+                    } else {
+                        format!("{: <width$}", text)
+                    };
+                    if *column == JobColumn::Status {
+                        let role = if job_info.stalled && job_info.status.is_running() {
+                            Role::Warning
+                        } else {
+                            job_status_role(&job_info.status)
+                        };
+                        line += &format!(" {} |", paint(role, text));
+                    } else {
+                        line += &format!(" {} |", text);
+                    }
+                }
+                println!("{}", line);
+            }
         }
     }
 
@@ -251,23 +541,23 @@ pub fn job_list(
     print!("pending: {}", jobs_pending);
     let mut printed = format!("pending: {}", jobs_pending).len();
     if jobs_offered > 0 {
-        print!(", offered: {}", style(jobs_offered).dim());
+        print!(", offered: {}", paint(Role::Offered, jobs_offered));
         printed += format!(", offered: {}", jobs_offered).len();
     }
     if jobs_running > 0 {
-        print!(", running: {}", style(jobs_running).blue());
+        print!(", running: {}", paint(Role::Running, jobs_running));
         printed += format!(", running: {}", jobs_running).len();
     }
     if jobs_succeeded > 0 {
-        print!(", succeeded: {}", style(jobs_succeeded).green());
+        print!(", succeeded: {}", paint(Role::Succeeded, jobs_succeeded));
         printed += format!(", succeeded: {}", jobs_succeeded).len();
     }
     if jobs_failed > 0 {
-        print!(", failed: {}", style(jobs_failed).red());
+        print!(", failed: {}", paint(Role::Failed, jobs_failed));
         printed += format!(", failed: {}", jobs_failed).len();
     }
     if jobs_canceled > 0 {
-        print!(", canceled: {}", style(jobs_canceled).yellow());
+        print!(", canceled: {}", paint(Role::Canceled, jobs_canceled));
         printed += format!(", canceled: {}", jobs_canceled).len();
     }
 
@@ -287,20 +577,74 @@ pub fn job_list(
 pub fn job_info(job_info: JobInfo, stdout_text: Option<String>, stderr_text: Option<String>) {
     println!("=== {} ===", style("job information").bold().underlined());
     println!("job id: {}", job_info.job_id);
+    if !job_info.user.is_empty() {
+        println!("user: {}", job_info.user);
+    }
     println!("command: {}", job_info.cmd.join(" "));
     println!("working directory: {}", job_info.cwd.to_string_lossy());
+    if let Some(pool) = &job_info.pool {
+        println!("resource pool: {}", pool);
+    }
+    if let Some(project) = &job_info.project {
+        println!("project: {}", project);
+    }
+    if let Some(qos) = &job_info.qos {
+        println!("qos class: {}", qos);
+    }
+    if job_info.priority != 0 {
+        println!("priority: {}", job_info.priority);
+    }
     println!(
         "required job slots: {}",
         job_info.worker_resources.job_slots
     );
     println!("required CPU cores: {}", job_info.worker_resources.cpus);
+    if let Some(binding) = job_info.core_binding {
+        println!("core binding: {}", binding);
+    }
+    if job_info.gpus > 0 {
+        println!("required GPUs: {} ({})", job_info.gpus, job_info.gpu_mode);
+    }
     println!(
         "required RAM: {} megabytes",
         job_info.worker_resources.ram_mb
     );
+    if let Some(seconds) = job_info.cpu_time_limit_seconds {
+        println!("CPU time limit: {} seconds", seconds);
+    }
+    if let Some(seconds) = job_info.wall_time_limit_seconds {
+        println!("wall-time limit: {} seconds", seconds);
+    }
+    if job_info.process_limits != ProcessLimits::default() {
+        println!("process limits: {}", job_info.process_limits);
+    }
+    if !job_info.constraints.is_empty() {
+        println!("required labels: {}", format_labels(&job_info.constraints));
+    }
+    if let Some(affinity) = &job_info.affinity {
+        println!("preferred: {}", affinity);
+    }
+    if let Some(usage) = &job_info.resource_usage {
+        println!(
+            "measured usage: {:.1} CPU cores on average, {} megabytes of RAM at peak",
+            usage.avg_cpus, usage.peak_ram_mb
+        );
+    }
+    if !job_info.exit_code_policies.is_empty() {
+        println!("retries: {} of {}", job_info.retries, job_info.max_retries);
+    }
+    if job_info.migrations > 0 {
+        println!("migrations: {}", job_info.migrations);
+    }
+    if !job_info.attempts.is_empty() {
+        println!("attempt: {}", job_info.attempt());
+    }
+    if let Some(checkpoint) = &job_info.checkpoint {
+        println!("checkpoint: {}", checkpoint.to_string_lossy());
+    }
     println!(); // line break
 
-    if let Some(global_resources) = job_info.global_resources {
+    if let Some(global_resources) = &job_info.global_resources {
         println!("{}", style("additional resources:").bold());
         for (resource, amount) in global_resources {
             println!("   {amount}x {resource}");
@@ -308,17 +652,74 @@ pub fn job_info(job_info: JobInfo, stdout_text: Option<String>, stderr_text: Opt
         println!(); // line break
     }
 
+    if !job_info.worker_resources.named.is_empty() {
+        println!("{}", style("worker resources:").bold());
+        for (resource, amount) in &job_info.worker_resources.named {
+            println!("   {amount}x {resource}");
+        }
+        println!(); // line break
+    }
+
+    if !job_info.dependencies.is_empty() {
+        println!("{}", style("dependencies:").bold());
+        for dependency in &job_info.dependencies {
+            println!("   {} job {}", dependency.condition, dependency.job_id);
+        }
+        println!(); // line break
+    }
+
+    if job_info.has_steps() {
+        println!("{}", style("job steps:").bold());
+        if job_info.step_results.is_empty() {
+            for (index, cmd) in job_info.setup_steps.iter().enumerate() {
+                println!("   setup {}: {}", index + 1, cmd.join(" "));
+            }
+            println!("   run: {}", job_info.cmd.join(" "));
+            for (index, cmd) in job_info.teardown_steps.iter().enumerate() {
+                println!("   teardown {}: {}", index + 1, cmd.join(" "));
+            }
+        } else {
+            for step in &job_info.step_results {
+                println!("   {}", format_step_result(step));
+            }
+        }
+        println!(); // line break
+    }
+
+    if !job_info.attempts.is_empty() {
+        println!("{}", style("previous attempts:").bold());
+        for (index, attempt) in job_info.attempts.iter().enumerate() {
+            println!("   {}", format_attempt(index + 1, attempt));
+        }
+        println!(); // line break
+    }
+
+    if !job_info.events.is_empty() {
+        println!("{}", style("events:").bold());
+        for event in &job_info.events {
+            println!("   {}: {}", format::date(&event.time), event.text);
+        }
+        println!(); // line break
+    }
+
     match &job_info.status {
         JobStatus::Pending { issued } => {
             println!("{}: pending", style("job status").bold());
             println!("   issued on: {}", format::date(issued));
+            if let Some(reason) = &job_info.pending_reason {
+                println!("   pending reason: {}", paint(Role::Warning, reason));
+            }
         }
         JobStatus::Offered {
             issued,
             offered,
             worker,
         } => {
-            println!("{}: {}", style("job status").bold(), style("pending").dim());
+            println!(
+                "{}: {}",
+                style("job status").bold(),
+                paint(Role::Offered, "pending")
+            );
             println!("   issued on: {}", format::date(issued));
             println!("   offered on: {}", offered);
             println!("   offered to: {}", worker);
@@ -328,11 +729,12 @@ pub fn job_info(job_info: JobInfo, stdout_text: Option<String>, stderr_text: Opt
             started,
             worker,
         } => {
-            println!(
-                "{}: {}",
-                style("job status").bold(),
-                style("running").blue()
-            );
+            let status = if job_info.stalled {
+                paint(Role::Warning, "running, stalled")
+            } else {
+                paint(Role::Running, "running")
+            };
+            println!("{}: {}", style("job status").bold(), status);
             println!("   issued on: {}", format::date(issued));
             println!("   started on: {}", format::date(started));
             println!("   running on: {}", worker);
@@ -345,32 +747,47 @@ pub fn job_info(job_info: JobInfo, stdout_text: Option<String>, stderr_text: Opt
             worker,
             run_time_seconds,
             comment,
+            failure,
         } => {
             if *return_code == 0 {
                 println!(
                     "{}: {}",
                     style("job status").bold(),
-                    style("finished").green()
+                    paint(Role::Succeeded, "finished")
                 );
             } else {
-                println!("{}: {}", style("job status").bold(), style("failed").red());
+                println!(
+                    "{}: {}",
+                    style("job status").bold(),
+                    paint(Role::Failed, "failed")
+                );
             }
             println!("   issued on: {}", format::date(issued));
             println!("   started on: {}", format::date(started));
             println!("   finished on: {}", format::date(finished));
             println!("   return code: {}", return_code);
+            if let Some(failure) = failure {
+                println!("   failure: {}", paint(Role::Failed, failure.to_string()));
+            }
             println!("   executed on: {}", worker);
             println!("   runtime: {}", format::elapsed_seconds(*run_time_seconds));
             println!("   comment: {}", comment);
         }
-        JobStatus::Canceled { issued, canceled } => {
+        JobStatus::Canceled {
+            issued,
+            canceled,
+            reason,
+        } => {
             println!(
                 "{}: {}",
                 style("job status").bold(),
-                style("canceled").yellow()
+                paint(Role::Canceled, "canceled")
             );
             println!("   issued on: {}", format::date(issued));
             println!("   canceled on: {}", format::date(canceled));
+            if let Some(reason) = reason {
+                println!("   reason: {}", reason);
+            }
         }
     }
 
@@ -379,7 +796,7 @@ pub fn job_info(job_info: JobInfo, stdout_text: Option<String>, stderr_text: Opt
     }
 
     if let Some(text) = stderr_text {
-        println!("\n=== {} ===\n{}", style("stderr").red(), text);
+        println!("\n=== {} ===\n{}", paint(Role::Bad, "stderr"), text);
     }
 }
 
@@ -400,23 +817,68 @@ fn format_jobs(jobs_offered: &BTreeSet<u64>, jobs_running: &BTreeSet<u64>) -> St
     }
 }
 
+fn load_role(load: f64) -> Role {
+    match load {
+        x if x < 0.25 => Role::Good,
+        x if x < 0.75 => Role::Warning,
+        _ => Role::Bad, // else
+    }
+}
+
 fn format_resource_load(load: f64, decimal: usize) -> StyledObject<String> {
     let load_fmt = format!("{:.decimal$} %", load * 100.0);
-    match load {
-        x if x < 0.25 => style(load_fmt).green(),
-        x if x < 0.75 => style(load_fmt).yellow(),
-        _ => style(load_fmt).red(), // else
+    paint(load_role(load), load_fmt)
+}
+
+/// Width of a single resource bar in the worker list.
+const BAR_WIDTH: usize = 6;
+
+/// Allocated and total job slots, cpus, and ram (in this order) of the worker.
+/// Allocated resources are not available for new jobs, either because they
+/// are reserved by jobs or, depending on the worker's settings, occupied by
+/// other processes on the machine.
+pub(super) fn worker_allocation(info: &WorkerInfo) -> [(u64, u64); 3] {
+    let system = &info.system_info;
+    let free = &info.free_resources;
+    [
+        (
+            system.max_job_slots.saturating_sub(free.job_slots),
+            system.max_job_slots,
+        ),
+        (system.cpu_cores.saturating_sub(free.cpus), system.cpu_cores),
+        (
+            system.total_ram_mb.saturating_sub(free.ram_mb),
+            system.total_ram_mb,
+        ),
+    ]
+}
+
+fn format_allocation_bar(used: u64, total: u64, len: usize) -> StyledObject<String> {
+    let bar = format::bar(used, total, len);
+    if total == 0 {
+        style(bar) // unknown
+    } else {
+        paint(load_role(used as f64 / total as f64), bar)
     }
 }
 
+/// Job slot, cpu, and ram bars of the worker, separated by spaces.
+fn format_allocation_bars(info: &WorkerInfo) -> String {
+    let bars: Vec<String> = worker_allocation(info)
+        .iter()
+        .map(|(used, total)| format_allocation_bar(*used, *total, BAR_WIDTH).to_string())
+        .collect();
+    bars.join(" ")
+}
+
 fn format_cpu_load(load: f64, cpu_cores: u64) -> StyledObject<String> {
     let load_fmt = format!("{:.1}", load);
     if load < (0.25 * cpu_cores as f64) {
-        style(load_fmt).green()
+        paint(Role::Good, load_fmt)
     } else if load < (0.75 * cpu_cores as f64) {
-        style(load_fmt).yellow()
+        paint(Role::Warning, load_fmt)
     } else {
-        style(load_fmt).red()
+        paint(Role::Bad, load_fmt)
     }
 }
 
@@ -426,8 +888,36 @@ fn format_uptime(connected_since: DateTime<Utc>) -> String {
     format!("{}d {:02}h", uptime.num_days(), hours)
 }
 
+/// Uptime of the worker, or "unreachable" if the worker timed out.
+pub(super) fn format_worker_uptime(info: &WorkerInfo) -> String {
+    if info.unreachable_since.is_some() {
+        "unreachable".to_string()
+    } else if info.offline {
+        "offline".to_string()
+    } else if info.self_test_failed() {
+        "self-test failed".to_string()
+    } else if info.draining {
+        "draining".to_string()
+    } else if let Some(user) = &info.reserved_for {
+        format!("reserved for {}", user)
+    } else {
+        format_uptime(info.connected_since)
+    }
+}
+
+fn format_version(kueue_version: &str, server_version: &str) -> StyledObject<String> {
+    if versions_compatible(kueue_version, server_version) {
+        style(kueue_version.to_string())
+    } else {
+        paint(
+            Role::Bad,
+            format!("{kueue_version} (incompatible with server {server_version})"),
+        )
+    }
+}
+
 /// Print workers to screen.
-pub fn worker_list(worker_list: Vec<WorkerInfo>) {
+pub fn worker_list(worker_list: Vec<WorkerInfo>, server_version: &str) {
     if worker_list.is_empty() {
         println!("No workers registered on server!");
     } else {
@@ -485,7 +975,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             .unwrap();
         let max_uptime_col_width = worker_list
             .iter()
-            .map(|info| format_uptime(info.connected_since).len())
+            .map(|info| format_worker_uptime(info).len())
             .max()
             .unwrap();
 
@@ -497,6 +987,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             max("avg freq".len(), max_freq_col_width),
             max("memory".len(), max_memory_col_width),
             "jobs".len(),
+            max("slots/cpus/ram".len(), 3 * BAR_WIDTH + 2),
             max("busy".len(), max_busy_col_width),
             max_load_1_col_width,
             max_load_5_col_width,
@@ -511,6 +1002,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             0, // cpu frequency
             0, // memory
             max_jobs_col_width,
+            0, // allocation bars
             0, // busy
             0, // load 1
             0, // load 5
@@ -527,6 +1019,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             freq_col,
             memory_col,
             jobs_col,
+            alloc_col,
             busy_col,
             load_1_col,
             load_5_col,
@@ -545,6 +1038,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             col_widths[9],
             col_widths[10],
             col_widths[11],
+            col_widths[12],
         );
 
         let load_col = max(
@@ -563,7 +1057,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
         println!(
             "| {: <id_col$} | {: <worker_col$} | {: <os_col$} \
             | {: <cores_col$} | {: <freq_col$} | {: <memory_col$} \
-            | {: <jobs_col$} | {: <busy_col$} | {: <load_col$} \
+            | {: <jobs_col$} | {: <alloc_col$} | {: <busy_col$} | {: <load_col$} \
             | {: <uptime_col$} |",
             style("id").bold().underlined(),
             style("name").bold().underlined(),
@@ -572,11 +1066,17 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
             style("avg freq").bold().underlined(),
             style("memory").bold().underlined(),
             style("jobs").bold().underlined(),
+            style("slots/cpus/ram").bold().underlined(),
             style("busy").bold().underlined(),
             style("load 1/5/15m").bold().underlined(),
             style("uptime").bold().underlined(),
         );
 
+        let incompatible_workers = worker_list
+            .iter()
+            .filter(|info| !versions_compatible(&info.kueue_version, server_version))
+            .count();
+
         for info in worker_list {
             let worker_name = format::dots_back(info.worker_name.clone(), worker_col);
             let operation_system = format::dots_back(info.system_info.distribution.clone(), os_col);
@@ -589,6 +1089,10 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
                 jobs_col,
             );
 
+            // Bars contain escape codes for colors, so we pad them manually.
+            let alloc_bars = format_allocation_bars(&info);
+            let alloc_bars = alloc_bars + &" ".repeat(alloc_col - (3 * BAR_WIDTH + 2));
+
             let busy = format_resource_load(info.resource_load(), 0);
 
             let load_one =
@@ -600,13 +1104,18 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
                 info.system_info.cpu_cores,
             );
 
-            let uptime = format_uptime(info.connected_since);
+            let uptime = format_worker_uptime(&info);
+            let uptime = if info.offline || info.self_test_failed() {
+                paint(Role::Bad, uptime)
+            } else {
+                style(uptime)
+            };
 
             // Print line
             println!(
                 "| {: >id_col$} | {: <worker_col$} | {: <os_col$} \
                 | {: >cores_col$} | {: >freq_col$} | {: >memory_col$} \
-                | {: <jobs_col$} | {: >busy_col$} \
+                | {: <jobs_col$} | {} | {: >busy_col$} \
                 | {: >load_1_col$} {: >load_5_col$} {: >load_15_col$} \
                 | {: >uptime_col$} |",
                 info.worker_id,
@@ -616,6 +1125,7 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
                 cpu_frequency,
                 memory_mb,
                 jobs,
+                alloc_bars,
                 busy,
                 load_one,
                 load_five,
@@ -623,26 +1133,87 @@ pub fn worker_list(worker_list: Vec<WorkerInfo>) {
                 uptime
             );
         }
+
+        // Point out workers that might misbehave due to version differences.
+        if incompatible_workers > 0 {
+            println!(
+                "{}",
+                paint(
+                    Role::Bad,
+                    format!(
+                        "{incompatible_workers} worker(s) run a Kueue version incompatible \
+                        with the server ({server_version}). See `show-worker` for details."
+                    )
+                )
+            );
+        }
     }
 }
 
-pub fn worker_info(worker_info: WorkerInfo) {
+pub fn worker_info(worker_info: WorkerInfo, server_version: &str) {
     println!(
         "=== {} ===",
         style("worker information").bold().underlined()
     );
     println!("worker id: {}", worker_info.worker_id);
     println!("name: {}", worker_info.worker_name);
+    println!(
+        "kueue version: {}",
+        format_version(&worker_info.kueue_version, server_version)
+    );
+    if !worker_info.tags.is_empty() {
+        let tags: Vec<&str> = worker_info.tags.iter().map(String::as_str).collect();
+        println!("tags: {}", tags.join(", "));
+    }
+    if !worker_info.labels.is_empty() {
+        println!("labels: {}", format_labels(&worker_info.labels));
+    }
     println!(
         "connected since: {}",
         format::date(&worker_info.connected_since)
     );
+    if let Some(remote_address) = &worker_info.remote_address {
+        println!("remote address: {}", remote_address);
+    }
+    if let Some(unreachable_since) = &worker_info.unreachable_since {
+        println!(
+            "status: {}",
+            paint(
+                Role::Bad,
+                format!("unreachable since {}", format::date(unreachable_since))
+            )
+        );
+    } else if worker_info.offline {
+        println!(
+            "status: {}",
+            paint(Role::Bad, "offline (no updates received recently)")
+        );
+    } else if worker_info.draining {
+        println!(
+            "status: {}",
+            paint(Role::Warning, "draining (no new jobs are offered)")
+        );
+    }
+    if let Some(user) = &worker_info.reserved_for {
+        println!("reserved for: {}", user);
+    }
     println!(); // line break
 
     println!("{}", style("system information").bold().underlined());
     println!("   kernel: {}", worker_info.system_info.kernel);
     println!("   distribution: {}", worker_info.system_info.distribution);
     println!("   cpu cores: {}", worker_info.system_info.cpu_cores);
+    let system = &worker_info.system_info;
+    if system.physical_cores > 0 {
+        print!("   physical cores: {}", system.physical_cores);
+        if system.efficiency_cores > 0 {
+            print!(
+                " ({} performance, {} efficiency)",
+                system.performance_cores, system.efficiency_cores
+            );
+        }
+        println!();
+    }
     println!(
         "   cpu frequency: {} megahertz",
         worker_info.system_info.cpu_frequency
@@ -693,58 +1264,510 @@ pub fn worker_info(worker_info: WorkerInfo) {
     };
     println!("   jobs running: {}", jobs_running);
 
+    let [slots, cpus, ram] = worker_allocation(&worker_info);
+    if slots.1 > 0 {
+        println!(
+            "   free jobs slots: {} / {}  {}",
+            worker_info.free_resources.job_slots,
+            slots.1,
+            format_allocation_bar(slots.0, slots.1, 20)
+        );
+    } else {
+        println!(
+            "   free jobs slots: {}",
+            worker_info.free_resources.job_slots
+        );
+    }
     println!(
-        "   free jobs slots: {}",
-        worker_info.free_resources.job_slots
-    );
-    println!(
-        "   free cpus: {} / {}",
-        worker_info.free_resources.cpus, worker_info.system_info.cpu_cores
+        "   free cpus: {} / {}  {}",
+        worker_info.free_resources.cpus,
+        cpus.1,
+        format_allocation_bar(cpus.0, cpus.1, 20)
     );
     println!(
-        "   free ram: {} / {} megabytes",
-        worker_info.free_resources.ram_mb, worker_info.system_info.total_ram_mb
+        "   free ram: {} / {} megabytes  {}",
+        worker_info.free_resources.ram_mb,
+        ram.1,
+        format_allocation_bar(ram.0, ram.1, 20)
     );
+    for (resource, total) in &worker_info.system_info.resources {
+        let free = worker_info.free_resources.named.get(resource);
+        println!(
+            "   free {}: {} / {}",
+            resource,
+            free.copied().unwrap_or(0),
+            total
+        );
+    }
+    for (pool, free) in &worker_info.pool_resources {
+        println!(
+            "   pool {}: {} free job slots, {} free cpus, {} megabytes free ram",
+            pool, free.job_slots, free.cpus, free.ram_mb
+        );
+    }
 
     println!(
         "{}: {}",
         style("total occupation").bold(),
         format_resource_load(worker_info.resource_load(), 2)
     );
+
+    if !worker_info.self_test.is_empty() {
+        println!(); // line break
+        println!("{}", style("self-test").bold().underlined());
+        for probe in &worker_info.self_test {
+            let outcome = if probe.passed {
+                paint(Role::Good, "passed")
+            } else {
+                paint(Role::Bad, "failed")
+            };
+            println!("   {}: {} ({})", probe.name, outcome, probe.output);
+        }
+    }
 }
 
 pub fn resource_list(
     used_resources: Option<BTreeMap<String, u64>>,
     total_resources: Option<BTreeMap<String, u64>>,
+    used_worker_resources: BTreeMap<String, u64>,
+    total_worker_resources: BTreeMap<String, u64>,
 ) {
     if let Some(total_res) = total_resources {
-        let max_res_len = total_res.keys().map(|key| key.len()).max().unwrap_or(0);
-        let res_col_width = max("resource".len(), max_res_len);
+        resource_table("resource", &used_resources.unwrap_or_default(), &total_res);
+    } else {
+        println!("No resources configured on the server.");
+    }
 
-        // Print header
+    if !total_worker_resources.is_empty() {
+        println!();
+        resource_table(
+            "worker resource",
+            &used_worker_resources,
+            &total_worker_resources,
+        );
+    }
+}
+
+/// Print a table of used and total amounts of resources.
+fn resource_table(
+    title: &str,
+    used_res: &BTreeMap<String, u64>,
+    total_res: &BTreeMap<String, u64>,
+) {
+    let max_res_len = total_res.keys().map(|key| key.len()).max().unwrap_or(0);
+    let res_col_width = max(title.len(), max_res_len);
+
+    // Print header
+    println!(
+        "| {: <res_col_width$} | {: <4} | {: <5} |",
+        style(title).bold().underlined(),
+        style("used").bold().underlined(),
+        style("total").bold().underlined(),
+    );
+
+    for (resource, total) in total_res {
+        let used = match used_res.get(resource) {
+            Some(0) | None => paint(Role::Good, "0".to_string()),
+            Some(used) if used >= total => paint(Role::Bad, format!("{used}")),
+            Some(used) => paint(Role::Warning, format!("{used}")),
+        };
         println!(
-            "| {: <res_col_width$} | {: <4} | {: <5} |",
-            style("resource").bold().underlined(),
-            style("used").bold().underlined(),
-            style("total").bold().underlined(),
+            "| {: <res_col_width$} | {: >4} | {: >5} |",
+            resource, used, total
         );
+    }
+}
 
-        for (resource, total) in total_res {
-            let used = if let Some(used_res) = &used_resources {
-                match used_res.get(&resource) {
-                    Some(used) if *used == total => style(format!("{used}")).red(),
-                    Some(used) => style(format!("{used}")).yellow(),
-                    None => style("0".to_string()).green(),
+/// Print the usage of the cluster, either in total or per project.
+pub fn project_usage(usage: Vec<ProjectUsage>, by_project: bool) {
+    let usage = if by_project {
+        usage
+    } else {
+        let total = usage
+            .iter()
+            .fold(ProjectUsage::default(), |total, usage| ProjectUsage {
+                jobs_pending: total.jobs_pending + usage.jobs_pending,
+                jobs_running: total.jobs_running + usage.jobs_running,
+                jobs_succeeded: total.jobs_succeeded + usage.jobs_succeeded,
+                jobs_failed: total.jobs_failed + usage.jobs_failed,
+                cpu_hours: total.cpu_hours + usage.cpu_hours,
+                ..total
+            });
+        vec![total]
+    };
+
+    let limit = |limit: Option<String>| limit.unwrap_or_else(|| "-".into());
+    let rows: Vec<Vec<String>> = usage
+        .iter()
+        .map(|usage| {
+            let project = match (&usage.project, by_project) {
+                (Some(project), _) => project.clone(),
+                (None, true) => "(none)".into(),
+                (None, false) => "all".into(),
+            };
+            vec![
+                project,
+                usage.jobs_pending.to_string(),
+                format!(
+                    "{} / {}",
+                    usage.jobs_running,
+                    limit(usage.max_running_jobs.map(|max| max.to_string()))
+                ),
+                usage.jobs_succeeded.to_string(),
+                usage.jobs_failed.to_string(),
+                format!("{:.1}", usage.cpu_hours),
+                limit(usage.cpu_hours_quota.map(|quota| format!("{:.1}", quota))),
+            ]
+        })
+        .collect();
+    let header = [
+        "project",
+        "pending",
+        "running",
+        "succeeded",
+        "failed",
+        "CPU hours",
+        "quota",
+    ];
+    table(&header, &rows);
+}
+
+/// Print the accounting of finished jobs per user.
+pub fn user_usage(usage: &[UserUsage]) {
+    if usage.is_empty() {
+        println!("No finished jobs have been accounted in this time range.");
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = usage
+        .iter()
+        .map(|usage| {
+            vec![
+                usage.user.clone(),
+                usage.jobs_succeeded.to_string(),
+                usage.jobs_failed.to_string(),
+                format!("{:.1}", usage.core_hours),
+                format::date(&usage.first_started),
+                format::date(&usage.last_finished),
+            ]
+        })
+        .collect();
+    let header = [
+        "user",
+        "succeeded",
+        "failed",
+        "core hours",
+        "first started",
+        "last finished",
+    ];
+    table(&header, &rows);
+}
+
+/// Print a simple table with the header in bold.
+fn table(header: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain([header[col].len()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let header: Vec<String> = header
+        .iter()
+        .zip(&widths)
+        .map(|(title, &width)| format!("{: <width$}", style(title).bold().underlined()))
+        .collect();
+    println!("| {} |", header.join(" | "));
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{: <width$}", cell))
+            .collect();
+        println!("| {} |", cells.join(" | "));
+    }
+}
+
+pub fn job_templates(templates: &[JobTemplate]) {
+    if templates.is_empty() {
+        println!("The server does not publish any job templates.");
+        return;
+    }
+
+    for template in templates {
+        if template.description.is_empty() {
+            println!("{}", style(&template.name).bold());
+        } else {
+            println!("{}: {}", style(&template.name).bold(), template.description);
+        }
+        let mut defaults = Vec::new();
+        if let Some(job_slots) = template.job_slots {
+            defaults.push(format!("{} job slots", job_slots));
+        }
+        if let Some(cpus) = template.cpus {
+            defaults.push(format!("{} CPUs", cpus));
+        }
+        if let Some(ram_mb) = template.ram_mb {
+            defaults.push(format!("{} MB RAM", ram_mb));
+        }
+        for (name, amount) in &template.global_resources {
+            defaults.push(format!("{}x {}", amount, name));
+        }
+        if let Some(pool) = &template.pool {
+            defaults.push(format!("pool {}", pool));
+        }
+        if !template.constraints.is_empty() {
+            defaults.push(format!("requires {}", format_labels(&template.constraints)));
+        }
+        if let Some(seconds) = template.wall_time_limit_seconds {
+            defaults.push(format!(
+                "timeout {}",
+                format::elapsed_seconds(seconds as i64)
+            ));
+        }
+        if !defaults.is_empty() {
+            println!("   {}", defaults.join(", "));
+        }
+    }
+}
+
+pub fn idle_resources(report: &[IdleResources]) {
+    if report.is_empty() {
+        println!("No resources sat idle while jobs were pending!");
+        return;
+    }
+
+    let rows: Vec<[String; 6]> = report
+        .iter()
+        .map(|idle| {
+            let limited_by = match idle.limiting_resource() {
+                Some(resource) => {
+                    let share = idle.limited_by[resource] / idle.idle_hours;
+                    format!("{} ({:.0} %)", resource, share * 100.0)
                 }
-            } else {
-                style("0".to_string()).green()
+                None => "-".into(),
             };
-            println!(
-                "| {: <res_col_width$} | {: >4} | {: >5} |",
-                resource, used, total
-            );
+            [
+                idle.worker_name.clone(),
+                format::date(&idle.hour),
+                format!("{:.0} min", idle.idle_hours * 60.0),
+                format!("{:.1}", idle.cpu_hours),
+                format!("{:.1}", idle.ram_gb_hours),
+                limited_by,
+            ]
+        })
+        .collect();
+    let header = [
+        "worker",
+        "hour",
+        "idle",
+        "CPU hours",
+        "RAM GB hours",
+        "limited by",
+    ];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain([header[col].len()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let header: Vec<String> = header
+        .iter()
+        .zip(&widths)
+        .map(|(title, &width)| format!("{: <width$}", style(title).bold().underlined()))
+        .collect();
+    println!("| {} |", header.join(" | "));
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{: <width$}", cell))
+            .collect();
+        println!("| {} |", cells.join(" | "));
+    }
+
+    let cpu_hours: f64 = report.iter().map(|idle| idle.cpu_hours).sum();
+    let ram_gb_hours: f64 = report.iter().map(|idle| idle.ram_gb_hours).sum();
+    println!(
+        "In total, {:.1} CPU hours and {:.1} GB hours of RAM sat idle while jobs were pending.",
+        cpu_hours, ram_gb_hours
+    );
+}
+
+/// Human-readable amount of transferred data.
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=9_999 => format!("{} B", bytes),
+        10_000..=9_999_999 => format!("{} KB", bytes / 1024),
+        _ => format!("{} MB", bytes / (1024 * 1024)),
+    }
+}
+
+/// Print live connections to screen.
+pub fn connection_list(connection_list: Vec<ConnectionInfo>) {
+    if connection_list.is_empty() {
+        println!("No connections to the server!");
+        return;
+    }
+
+    let rows: Vec<[String; 8]> = connection_list
+        .iter()
+        .map(|info| {
+            [
+                info.connection_id.to_string(),
+                info.kind.to_string(),
+                info.name.clone().unwrap_or_else(|| "-".into()),
+                info.remote_address.clone(),
+                format::date(&info.connected_since),
+                info.kueue_version.clone(),
+                format_bytes(info.bytes_sent),
+                format_bytes(info.bytes_received),
+            ]
+        })
+        .collect();
+    let header = [
+        "id",
+        "kind",
+        "name",
+        "address",
+        "connected",
+        "version",
+        "sent",
+        "received",
+    ];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain([header[col].len()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let header: Vec<String> = header
+        .iter()
+        .zip(&widths)
+        .map(|(title, &width)| format!("{: <width$}", style(title).bold().underlined()))
+        .collect();
+    println!("| {} |", header.join(" | "));
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{: <width$}", cell))
+            .collect();
+        println!("| {} |", cells.join(" | "));
+    }
+}
+
+/// Print the scheduling decisions recorded for a job.
+/// Write output of a job to the corresponding stream.
+pub fn job_log(stream: OutputStream, data: &[u8]) -> std::io::Result<()> {
+    match stream {
+        OutputStream::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(data)?;
+            stdout.flush()
         }
-    } else {
-        println!("No resources configured on the server.");
+        OutputStream::Stderr => {
+            let mut stderr = std::io::stderr().lock();
+            stderr.write_all(data)?;
+            stderr.flush()
+        }
+    }
+}
+
+pub fn job_diff(job_a: u64, job_b: u64, diff: &str, truncated: bool) {
+    if diff.is_empty() {
+        println!("The outputs of jobs {} and {} are identical.", job_a, job_b);
+        return;
+    }
+
+    for line in diff.lines() {
+        let role = if line.starts_with("+++") || line.starts_with("---") {
+            None
+        } else if line.starts_with('+') {
+            Some(Role::Good)
+        } else if line.starts_with('-') {
+            Some(Role::Bad)
+        } else if line.starts_with("@@") {
+            Some(Role::Warning)
+        } else {
+            None
+        };
+        match role {
+            Some(role) => println!("{}", paint(role, line)),
+            None => println!("{}", line),
+        }
+    }
+    if truncated {
+        println!("(The diff has been shortened to the server's size limit.)");
+    }
+}
+
+pub fn scheduling_trace(job_id: u64, events: &[SchedulingEvent]) {
+    if events.is_empty() {
+        println!("No scheduling decisions recorded for job {}!", job_id);
+        return;
+    }
+
+    let max_worker_len = events
+        .iter()
+        .map(|event| event.worker_name.len())
+        .max()
+        .unwrap_or(0);
+    for event in events {
+        let role = match event.outcome {
+            SchedulingOutcome::Skipped
+            | SchedulingOutcome::Deferred
+            | SchedulingOutcome::Withdrawn => Role::Warning,
+            SchedulingOutcome::Rejected => Role::Bad,
+            _ => Role::Good,
+        };
+        let outcome = if event.reason.is_empty() {
+            event.outcome.to_string()
+        } else {
+            format!("{: <9}  {}", event.outcome.to_string(), event.reason)
+        };
+        println!(
+            "{}  {: <max_worker_len$}  {}",
+            format::date(&event.time),
+            event.worker_name,
+            paint(role, outcome)
+        );
+    }
+}
+
+pub fn audit_log(entries: &[AuditEntry]) {
+    if entries.is_empty() {
+        println!("No administrative actions recorded!");
+        return;
+    }
+
+    let max_address_len = entries
+        .iter()
+        .map(|entry| entry.remote_address.len())
+        .max()
+        .unwrap_or(0);
+    for entry in entries {
+        let action = if entry.authorized {
+            style(entry.action.clone())
+        } else {
+            paint(Role::Bad, format!("{} (unauthorized)", entry.action))
+        };
+        println!(
+            "{}  {: <max_address_len$}  {}",
+            format::date(&entry.time),
+            entry.remote_address,
+            action
+        );
     }
 }
@@ -0,0 +1,74 @@
+//! Color themes for the client's output.
+//!
+//! Output is styled by semantic role (e.g., "failed") rather than by color,
+//! so that the actual colors can be selected by the user.
+
+use crate::client::cli::ColorTheme;
+use console::{Style, StyledObject};
+use std::sync::OnceLock;
+
+/// Color theme, set once on client start.
+static THEME: OnceLock<ColorTheme> = OnceLock::new();
+
+/// Set the color theme. Only the first call has an effect.
+pub fn set_theme(theme: ColorTheme) {
+    let _ = THEME.set(theme);
+}
+
+/// Semantic roles of styled output.
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    /// Job is waiting for assignment.
+    Pending,
+    /// Job has been offered to a worker.
+    Offered,
+    /// Job is running.
+    Running,
+    /// Job has finished successfully.
+    Succeeded,
+    /// Job has failed.
+    Failed,
+    /// Job has been canceled.
+    Canceled,
+    /// Low utilization or otherwise good value.
+    Good,
+    /// Medium utilization or otherwise noteworthy value.
+    Warning,
+    /// High utilization or otherwise problematic value.
+    Bad,
+}
+
+/// Returns the style of the given role in the given theme.
+fn role_style(theme: ColorTheme, role: Role) -> Style {
+    let style = Style::new();
+    match theme {
+        ColorTheme::Default => match role {
+            Role::Pending => style,
+            Role::Offered => style.dim(),
+            Role::Running => style.blue(),
+            Role::Succeeded | Role::Good => style.green(),
+            Role::Failed | Role::Bad => style.red(),
+            Role::Canceled | Role::Warning => style.yellow(),
+        },
+        ColorTheme::Bright => match role {
+            Role::Pending => style,
+            Role::Offered => style.dim(),
+            Role::Running => style.cyan().bright(),
+            Role::Succeeded | Role::Good => style.green().bright(),
+            Role::Failed | Role::Bad => style.red().bright().bold(),
+            Role::Canceled | Role::Warning => style.yellow().bright(),
+        },
+        ColorTheme::Monochrome => match role {
+            Role::Pending | Role::Succeeded | Role::Good | Role::Warning => style,
+            Role::Offered | Role::Canceled => style.dim(),
+            Role::Running => style.italic(),
+            Role::Failed | Role::Bad => style.bold(),
+        },
+    }
+}
+
+/// Apply the style of the given role in the current theme.
+pub fn paint<D>(role: Role, val: D) -> StyledObject<D> {
+    let theme = THEME.get().copied().unwrap_or_default();
+    role_style(theme, role).apply_to(val)
+}
@@ -0,0 +1,163 @@
+//! Creating and checking the client's config file.
+
+use super::{configure_output, print, Client};
+use crate::{
+    client::cli::Cli,
+    config::{default_path, Config},
+    messages::{
+        stream::MessageStream,
+        version::{versions_compatible, KUEUE_VERSION},
+        ClientToServerMessage,
+    },
+    tls,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::BTreeSet,
+    fs::{create_dir_all, File},
+    io::{stderr, stdin, Write},
+    path::PathBuf,
+};
+use tokio::time::{timeout, Duration};
+
+/// Time to wait for the server to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Ask the user for a value on the terminal. Returns `default`
+/// if the user just presses enter.
+fn prompt(question: &str, default: &str, hint: &str) -> Result<String> {
+    eprint!("{} [{}]: ", question, hint);
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+/// Interactively create a minimal config file containing
+/// the server's address and the shared secret.
+pub fn init_config(config_path: Option<PathBuf>, force: bool) -> Result<()> {
+    let config_path = config_path.unwrap_or(default_path());
+    if config_path.is_file() && !force {
+        bail!(
+            "Config file {} already exists! Use --force to overwrite it.",
+            config_path.to_string_lossy()
+        );
+    }
+
+    // Suggest the current settings, if any.
+    let config = Config::new(Some(config_path.clone()))
+        .map_err(|e| anyhow!("Failed to load config: {}", e))?;
+    let common = &config.common_settings;
+    let server_name = prompt("Server address", &common.server_name, &common.server_name)?;
+    let server_port = common.server_port.to_string();
+    let server_port: u16 = prompt("Server port", &server_port, &server_port)?
+        .parse()
+        .map_err(|e| anyhow!("Invalid port: {}", e))?;
+    let shared_secret = prompt("Shared secret", &common.shared_secret, "keep current")?;
+
+    let mut common_settings = toml::Table::new();
+    common_settings.insert("server_name".into(), server_name.into());
+    common_settings.insert("server_port".into(), i64::from(server_port).into());
+    common_settings.insert("shared_secret".into(), shared_secret.into());
+    let mut table = toml::Table::new();
+    table.insert("common_settings".into(), common_settings.into());
+
+    if let Some(config_dir) = config_path.parent() {
+        if !config_dir.as_os_str().is_empty() && !config_dir.is_dir() {
+            create_dir_all(config_dir)?;
+        }
+    }
+    let mut file = File::create(&config_path)?;
+    file.write_all(toml::to_string(&table)?.as_bytes())?;
+    eprintln!("Written config file {}", config_path.to_string_lossy());
+    eprintln!("Run `kueue config doctor` to check the connection to the server.");
+    Ok(())
+}
+
+/// Print the outcome of a single check and pass it on.
+fn report<T>(check: &str, outcome: Result<T>, describe: impl FnOnce(&T) -> String) -> Result<T> {
+    match &outcome {
+        Ok(value) => print::check_passed(check, &describe(value)),
+        Err(e) => print::check_failed(check, e),
+    }
+    outcome
+}
+
+/// Check the config file and the connection to the server step by step,
+/// reporting the outcome of every step. Stops at the first failed step.
+pub async fn doctor(args: Cli, config: Config) -> Result<()> {
+    configure_output(&args, &config)?;
+
+    // A missing config file is not fatal, as the defaults might work.
+    let config_path = args.config.clone().unwrap_or(default_path());
+    if config_path.is_file() {
+        print::check_passed("config file", &config_path.to_string_lossy());
+    } else {
+        let error = anyhow!(
+            "{} not found, using defaults",
+            config_path.to_string_lossy()
+        );
+        print::check_failed("config file", &error);
+    }
+
+    report(
+        "name resolution",
+        config.get_server_addresses().await,
+        |addresses| {
+            let addresses: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+            format!(
+                "{} resolves to {}",
+                config.common_settings.server_name,
+                addresses.join(", ")
+            )
+        },
+    )?;
+
+    let stream = match timeout(CONNECT_TIMEOUT, tls::connect(&config)).await {
+        Ok(stream) => stream,
+        Err(_) => Err(anyhow!("No response within {:?}", CONNECT_TIMEOUT)),
+    };
+    let stream = report("connection", stream, |stream| {
+        format!("reached {}", tls::describe_peer(stream))
+    })?;
+    let mut client = Client {
+        args,
+        config,
+        stream: MessageStream::new(stream),
+        server_version: String::new(),
+        server_capabilities: BTreeSet::new(),
+        authenticated: false,
+    };
+
+    let handshake = client.handshake().await;
+    report("handshake", handshake, |_| {
+        "server speaks the Kueue protocol".into()
+    })?;
+
+    let server_version = client.server_version.clone();
+    let compatible = if versions_compatible(KUEUE_VERSION, &server_version) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Server {} is not compatible with client {}",
+            server_version,
+            KUEUE_VERSION
+        ))
+    };
+    report("version", compatible, |_| {
+        format!("server {} and client {}", server_version, KUEUE_VERSION)
+    })?;
+
+    let authentication = client.authenticate().await;
+    report("authentication", authentication, |_| {
+        "shared secret accepted".into()
+    })?;
+
+    client.stream.send(&ClientToServerMessage::Bye).await?;
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Running a server and a worker in the client's process on a single machine.
+
+use crate::{config::Config, server::TcpServer, worker::TcpWorker};
+use anyhow::{anyhow, Result};
+use tokio::signal::ctrl_c;
+
+/// Loopback addresses the standalone server listens on. The server is not
+/// reachable from other machines.
+const LOOPBACK_ADDRESSES: &str = "127.0.0.1 [::1]";
+
+/// Run an embedded server and a local worker until interrupted. Jobs can be
+/// issued from other terminals with the same config file, e.g., to try Kueue
+/// on a laptop before setting up a cluster.
+pub async fn standalone(config: Config) -> Result<()> {
+    let mut server_config = config.clone();
+    server_config.server_settings.bind_addresses = LOOPBACK_ADDRESSES.into();
+    let mut server = TcpServer::new(server_config);
+    server
+        .start()
+        .await
+        .map_err(|e| anyhow!("Failed to start server: {}", e))?;
+
+    let mut worker_config = config.clone();
+    worker_config.common_settings.server_name = "127.0.0.1".into();
+    let mut worker = TcpWorker::new(worker_config);
+    if let Err(e) = worker.start().await {
+        server.stop().await?;
+        return Err(anyhow!("Failed to start worker: {}", e));
+    }
+
+    let server_name = &config.common_settings.server_name;
+    if !["localhost", "127.0.0.1", "::1"].contains(&server_name.as_str()) {
+        println!(
+            "Warning: Clients connect to the server_name '{}' of the config file, \
+            not to this machine.",
+            server_name
+        );
+    }
+    println!(
+        "Running server and worker on port {}. Issue jobs from another terminal, \
+        e.g., with 'kueue cmd'. Press Ctrl+C to stop.",
+        config.common_settings.server_port
+    );
+
+    // Shutdown when receiving interrupt signal.
+    ctrl_c().await?;
+    worker.stop().await?;
+    server.stop().await?;
+    Ok(())
+}
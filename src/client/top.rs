@@ -0,0 +1,587 @@
+//! Live dashboard of workers, resources, and jobs in the terminal.
+
+use super::{print, Client};
+use crate::{
+    messages::{ClientToServerMessage, ServerToClientMessage},
+    structs::{DependentsAction, JobInfo, JobStatus, WorkerInfo},
+};
+use anyhow::{bail, Result};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    widgets::{Block, Borders, Clear, Paragraph, Row, Table, TableState, Wrap},
+    Frame, Terminal,
+};
+use std::{
+    collections::BTreeMap,
+    io::{stdout, IsTerminal},
+};
+use tokio::{
+    sync::mpsc,
+    time::{interval, Duration},
+};
+
+/// Number of most recent jobs shown on the dashboard.
+const MAX_JOBS: u64 = 500;
+
+/// Lines of a job's outputs shown when inspecting the job.
+const OUTPUT_TAIL_LEN: usize = 10;
+
+/// Help shown in the footer of the dashboard.
+const HELP: &str =
+    "up/down: select | enter: inspect | c: cancel | K: kill | C: clean | r: refresh | q: quit";
+
+/// Switches to the alternate screen in raw mode while in scope, so that the
+/// terminal is restored even if the dashboard fails.
+struct ScreenGuard;
+
+impl ScreenGuard {
+    fn new() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        if let Err(e) = execute!(stdout(), EnterAlternateScreen) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e.into());
+        }
+        Ok(ScreenGuard)
+    }
+}
+
+impl Drop for ScreenGuard {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), LeaveAlternateScreen);
+        if let Err(e) = terminal::disable_raw_mode() {
+            log::error!("Failed to restore terminal: {}", e);
+        }
+    }
+}
+
+/// Changes to the queue that need to be confirmed by the user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Cancel(u64),
+    Kill(u64),
+    Clean,
+}
+
+impl Action {
+    fn question(&self) -> String {
+        match self {
+            Action::Cancel(job_id) => format!("Cancel job {}? [y/n]", job_id),
+            Action::Kill(job_id) => format!("Kill job {}? [y/n]", job_id),
+            Action::Clean => "Remove finished and canceled jobs? [y/n]".into(),
+        }
+    }
+}
+
+/// State of the dashboard, refreshed periodically from the server.
+#[derive(Default)]
+struct Dashboard {
+    workers: Vec<WorkerInfo>,
+    jobs: Vec<JobInfo>,
+    /// Number of pending, offered, running, succeeded, failed, and canceled jobs.
+    job_counts: [u64; 6],
+    used_resources: BTreeMap<String, u64>,
+    total_resources: BTreeMap<String, u64>,
+    /// ID of the selected job. Kept by ID, so that the selection does not
+    /// jump around when jobs are added or removed.
+    selected: Option<u64>,
+    /// Details of the inspected job, shown on top of the tables.
+    details: Option<(u64, Vec<String>)>,
+    /// Action awaiting the user's confirmation.
+    confirm: Option<Action>,
+    /// Last response of the server or announcement.
+    status: String,
+}
+
+impl Dashboard {
+    /// Index of the selected job in the job list.
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected?;
+        self.jobs.iter().position(|job| job.job_id == selected)
+    }
+
+    /// Move the selection by the given number of rows, e.g., -1 for up.
+    fn move_selection(&mut self, rows: isize) {
+        if self.jobs.is_empty() {
+            self.selected = None;
+            return;
+        }
+        let index = match self.selected_index() {
+            Some(index) => index.saturating_add_signed(rows),
+            None => 0,
+        };
+        let index = index.min(self.jobs.len() - 1);
+        self.selected = Some(self.jobs[index].job_id);
+    }
+
+    /// Keep a valid selection after the job list has been refreshed.
+    fn update_jobs(&mut self, jobs: Vec<JobInfo>) {
+        let previous = self.selected_index();
+        self.jobs = jobs;
+        if self.selected_index().is_none() {
+            self.selected = match previous {
+                Some(index) if !self.jobs.is_empty() => {
+                    Some(self.jobs[index.min(self.jobs.len() - 1)].job_id)
+                }
+                _ => self.jobs.first().map(|job| job.job_id),
+            };
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let worker_rows = self.workers.len().max(1) as u16 + 3;
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(4),
+                Constraint::Max(worker_rows.min(frame.size().height / 3)),
+                Constraint::Min(5),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        self.draw_summary(frame, areas[0]);
+        self.draw_workers(frame, areas[1]);
+        self.draw_jobs(frame, areas[2]);
+
+        let footer = match (&self.confirm, self.status.is_empty()) {
+            (Some(action), _) => Paragraph::new(action.question()).style(Style::new().bold()),
+            (None, false) => Paragraph::new(format!("{} | {}", self.status, HELP)),
+            (None, true) => Paragraph::new(HELP),
+        };
+        frame.render_widget(footer, areas[3]);
+
+        if let Some((job_id, lines)) = &self.details {
+            let area = centered(frame.size(), 80, 80);
+            let details = Paragraph::new(lines.join("\n"))
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!(" job {} (esc to close) ", job_id)),
+                );
+            frame.render_widget(Clear, area);
+            frame.render_widget(details, area);
+        }
+    }
+
+    fn draw_summary(&self, frame: &mut Frame, area: Rect) {
+        let [pending, offered, running, succeeded, failed, canceled] = self.job_counts;
+        let jobs = format!(
+            "jobs: {} pending, {} offered, {} running, {} succeeded, {} failed, {} canceled",
+            pending, offered, running, succeeded, failed, canceled
+        );
+        let resources: Vec<String> = self
+            .total_resources
+            .iter()
+            .map(|(name, total)| {
+                let used = self.used_resources.get(name).copied().unwrap_or(0);
+                format!("{} {}/{}", name, used, total)
+            })
+            .collect();
+        let resources = if resources.is_empty() {
+            "global resources: ---".to_string()
+        } else {
+            format!("global resources: {}", resources.join(", "))
+        };
+        let summary = Paragraph::new(format!("{}\n{}", jobs, resources))
+            .block(Block::default().borders(Borders::ALL).title(" kueue "));
+        frame.render_widget(summary, area);
+    }
+
+    fn draw_workers(&self, frame: &mut Frame, area: Rect) {
+        let header = Row::new(vec![
+            "id", "name", "jobs", "slots", "cpus", "ram (MB)", "busy", "load", "status",
+        ])
+        .style(Style::new().bold());
+        let rows = self.workers.iter().map(|info| {
+            let [slots, cpus, ram] = print::worker_allocation(info);
+            let load = info.resource_load();
+            let color = match load {
+                x if x < 0.25 => Color::Green,
+                x if x < 0.75 => Color::Yellow,
+                _ => Color::Red,
+            };
+            Row::new(vec![
+                info.worker_id.to_string(),
+                info.worker_name.clone(),
+                (info.jobs_running.len() + info.jobs_offered.len()).to_string(),
+                format!("{}/{}", slots.0, slots.1),
+                format!("{}/{}", cpus.0, cpus.1),
+                format!("{}/{}", ram.0, ram.1),
+                format!("{:.0} %", load * 100.0),
+                format!("{:.1}", info.system_info.load_info.one),
+                print::format_worker_uptime(info),
+            ])
+            .style(Style::new().fg(color))
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Min(12),
+            Constraint::Length(5),
+            Constraint::Length(7),
+            Constraint::Length(9),
+            Constraint::Length(15),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Length(20),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title(" workers "));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_jobs(&self, frame: &mut Frame, area: Rect) {
+        let header = Row::new(vec!["id", "user", "prio", "worker", "status", "command"])
+            .style(Style::new().bold());
+        let rows = self.jobs.iter().map(|info| {
+            let color = match &info.status {
+                JobStatus::Pending { .. } => Color::Reset,
+                JobStatus::Offered { .. } => Color::Cyan,
+                JobStatus::Running { .. } => Color::Blue,
+                JobStatus::Finished { return_code, .. } if *return_code == 0 => Color::Green,
+                JobStatus::Finished { .. } => Color::Red,
+                JobStatus::Canceled { .. } => Color::DarkGray,
+            };
+            Row::new(vec![
+                info.job_id.to_string(),
+                info.user.clone(),
+                info.priority.to_string(),
+                print::format_worker(&info.status),
+                print::format_status(&info.status),
+                info.cmd.join(" "),
+            ])
+            .style(Style::new().fg(color))
+        });
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Length(4),
+            Constraint::Length(16),
+            Constraint::Length(28),
+            Constraint::Min(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::default().borders(Borders::ALL).title(" jobs "));
+        let mut state = TableState::default().with_selected(self.selected_index());
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+}
+
+/// Area of the given percentage of width and height in the middle of `area`.
+fn centered(area: Rect, width_percent: u16, height_percent: u16) -> Rect {
+    let width = area.width * width_percent / 100;
+    let height = area.height * height_percent / 100;
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Last lines of a job's output.
+fn tail(text: &str, lines: usize) -> Vec<String> {
+    let all: Vec<&str> = text.lines().collect();
+    let start = all.len().saturating_sub(lines);
+    all[start..]
+        .iter()
+        .map(|line| format!("   {}", line))
+        .collect()
+}
+
+/// Details of a job, as shown when inspecting it.
+fn job_details(
+    job_info: &JobInfo,
+    stdout_text: Option<String>,
+    stderr_text: Option<String>,
+) -> Vec<String> {
+    let resources = &job_info.worker_resources;
+    let mut lines = vec![
+        format!("user: {}", job_info.user),
+        format!("command: {}", job_info.cmd.join(" ")),
+        format!("working directory: {}", job_info.cwd.to_string_lossy()),
+        format!(
+            "resources: {} job slots, {} CPU cores, {} MB RAM",
+            resources.job_slots, resources.cpus, resources.ram_mb
+        ),
+        format!("priority: {}", job_info.priority),
+        format!("status: {}", print::format_status(&job_info.status)),
+        format!("worker: {}", print::format_worker(&job_info.status)),
+    ];
+    if let JobStatus::Finished { comment, .. } = &job_info.status {
+        lines.push(format!("comment: {}", comment));
+    }
+    if let Some(reason) = &job_info.pending_reason {
+        lines.push(format!("pending reason: {}", reason));
+    }
+    for event in &job_info.events {
+        lines.push(format!("event: {}", event.text));
+    }
+    for (name, text) in [("stdout", stdout_text), ("stderr", stderr_text)] {
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+            lines.push(String::new());
+            lines.push(format!("{} (last {} lines):", name, OUTPUT_TAIL_LEN));
+            lines.extend(tail(&text, OUTPUT_TAIL_LEN));
+        }
+    }
+    lines
+}
+
+/// Forward key presses to the dashboard until it is closed.
+fn read_keys(keys: mpsc::Sender<KeyEvent>) {
+    while !keys.is_closed() {
+        match event::poll(std::time::Duration::from_millis(200)) {
+            Ok(true) => match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    if keys.blocking_send(key).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            },
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+impl Client {
+    /// Show a live dashboard of workers, resources, and jobs until the user
+    /// quits. Jobs can be inspected, canceled, and cleaned up.
+    pub(super) async fn top(&mut self, refresh_seconds: u64) -> Result<()> {
+        if !std::io::stdout().is_terminal() {
+            bail!("The dashboard requires a terminal!");
+        }
+
+        let guard = ScreenGuard::new()?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        let (key_tx, mut key_rx) = mpsc::channel(16);
+        std::thread::spawn(move || read_keys(key_tx));
+
+        let mut dashboard = Dashboard::default();
+        let mut refresh = interval(Duration::from_secs(refresh_seconds.max(1)));
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => self.refresh_dashboard(&mut dashboard).await?,
+                key = key_rx.recv() => match key {
+                    Some(key) => {
+                        if !self.on_dashboard_key(&mut dashboard, key).await? {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+            }
+            terminal.draw(|frame| dashboard.draw(frame))?;
+        }
+
+        drop(terminal);
+        drop(guard);
+        Ok(())
+    }
+
+    /// Receive the next response. Announcements are shown in the footer
+    /// instead of being printed, which would garble the dashboard.
+    async fn receive_for_dashboard(
+        &mut self,
+        dashboard: &mut Dashboard,
+    ) -> Result<ServerToClientMessage> {
+        loop {
+            match self.stream.receive::<ServerToClientMessage>().await? {
+                ServerToClientMessage::Announcement { text, .. } => dashboard.status = text,
+                message => return Ok(message),
+            }
+        }
+    }
+
+    /// Query workers, jobs, and global resources from the server.
+    async fn refresh_dashboard(&mut self, dashboard: &mut Dashboard) -> Result<()> {
+        self.stream
+            .send(&ClientToServerMessage::ListWorkers)
+            .await?;
+        match self.receive_for_dashboard(dashboard).await? {
+            ServerToClientMessage::WorkerList(workers) => dashboard.workers = workers,
+            other => bail!("Expected WorkerList, received: {:?}", other),
+        }
+
+        let message = ClientToServerMessage::ListJobs {
+            num_jobs: MAX_JOBS,
+            pending: true,
+            offered: true,
+            running: true,
+            succeeded: true,
+            failed: true,
+            canceled: true,
+        };
+        self.stream.send(&message).await?;
+        match self.receive_for_dashboard(dashboard).await? {
+            ServerToClientMessage::JobList {
+                job_infos,
+                jobs_pending,
+                jobs_offered,
+                jobs_running,
+                jobs_succeeded,
+                jobs_failed,
+                jobs_canceled,
+                ..
+            } => {
+                dashboard.job_counts = [
+                    jobs_pending,
+                    jobs_offered,
+                    jobs_running,
+                    jobs_succeeded,
+                    jobs_failed,
+                    jobs_canceled,
+                ];
+                dashboard.update_jobs(job_infos);
+            }
+            other => bail!("Expected JobList, received: {:?}", other),
+        }
+
+        self.stream
+            .send(&ClientToServerMessage::ListResources)
+            .await?;
+        match self.receive_for_dashboard(dashboard).await? {
+            ServerToClientMessage::ResourceList {
+                used_resources,
+                total_resources,
+                ..
+            } => {
+                dashboard.used_resources = used_resources.unwrap_or_default();
+                dashboard.total_resources = total_resources.unwrap_or_default();
+            }
+            other => bail!("Expected ResourceList, received: {:?}", other),
+        }
+        Ok(())
+    }
+
+    /// Handle a key press. Returns `false` if the dashboard should be closed.
+    async fn on_dashboard_key(&mut self, dashboard: &mut Dashboard, key: KeyEvent) -> Result<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(false);
+        }
+
+        // Confirm or dismiss the pending action.
+        if let Some(action) = dashboard.confirm.take() {
+            if key.code == KeyCode::Char('y') {
+                self.run_dashboard_action(dashboard, action).await?;
+                self.refresh_dashboard(dashboard).await?;
+            } else {
+                dashboard.status = "Nothing changed.".into();
+            }
+            return Ok(true);
+        }
+
+        // Close the details of the inspected job.
+        if dashboard.details.is_some() {
+            if matches!(key.code, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q')) {
+                dashboard.details = None;
+            }
+            return Ok(true);
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Up | KeyCode::Char('k') => dashboard.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => dashboard.move_selection(1),
+            KeyCode::PageUp => dashboard.move_selection(-10),
+            KeyCode::PageDown => dashboard.move_selection(10),
+            KeyCode::Char('r') => self.refresh_dashboard(dashboard).await?,
+            KeyCode::Char('C') => dashboard.confirm = Some(Action::Clean),
+            KeyCode::Char('c') => dashboard.confirm = dashboard.selected.map(Action::Cancel),
+            KeyCode::Char('K') => dashboard.confirm = dashboard.selected.map(Action::Kill),
+            KeyCode::Enter | KeyCode::Char('i') => {
+                if let Some(job_id) = dashboard.selected {
+                    self.inspect_job(dashboard, job_id).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Show the details of a job, including the end of its outputs.
+    async fn inspect_job(&mut self, dashboard: &mut Dashboard, job_id: u64) -> Result<()> {
+        let message = ClientToServerMessage::ShowJob { job_id };
+        self.stream.send(&message).await?;
+        match self.receive_for_dashboard(dashboard).await? {
+            ServerToClientMessage::JobInfo {
+                job_info,
+                stdout_text,
+                stderr_text,
+            } => {
+                let lines = job_details(&job_info, stdout_text, stderr_text);
+                dashboard.details = Some((job_id, lines));
+            }
+            ServerToClientMessage::RequestResponse { text, .. } => dashboard.status = text,
+            other => bail!("Expected JobInfo, received: {:?}", other),
+        }
+        Ok(())
+    }
+
+    /// Cancel, kill, or clean up jobs as confirmed by the user.
+    async fn run_dashboard_action(
+        &mut self,
+        dashboard: &mut Dashboard,
+        action: Action,
+    ) -> Result<()> {
+        let message = match action {
+            Action::Cancel(job_id) | Action::Kill(job_id) => ClientToServerMessage::RemoveJob {
+                job_id,
+                kill: action == Action::Kill(job_id),
+                dependents: DependentsAction::Resolve,
+                reason: None,
+            },
+            Action::Clean => ClientToServerMessage::CleanJobs { all: false },
+        };
+        self.stream.send(&message).await?;
+        match self.receive_for_dashboard(dashboard).await? {
+            ServerToClientMessage::RequestResponse { text, .. } => dashboard.status = text,
+            other => bail!("Expected RequestResponse, received: {:?}", other),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+
+    #[test]
+    fn dashboard_selection() {
+        let job = |job_id| {
+            let cmd = vec!["true".to_string()];
+            let resources = Resources::new(1, 1, 1);
+            let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+            job_info.job_id = job_id;
+            job_info
+        };
+        let jobs = vec![job(3), job(2), job(1)];
+        let ids = [3, 2, 1];
+
+        let mut dashboard = Dashboard::default();
+        dashboard.update_jobs(jobs.clone());
+        assert_eq!(dashboard.selected, Some(ids[0]));
+        dashboard.move_selection(5);
+        assert_eq!(dashboard.selected, Some(ids[2]));
+
+        // The selection follows the job, or stays in place if it is gone.
+        dashboard.move_selection(-1);
+        dashboard.update_jobs(jobs[1..].to_vec());
+        assert_eq!(dashboard.selected, Some(ids[1]));
+        dashboard.update_jobs(vec![jobs[0].clone(), jobs[2].clone()]);
+        assert_eq!(dashboard.selected, Some(ids[0]));
+    }
+}
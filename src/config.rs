@@ -4,20 +4,31 @@
 //! groups. The "common_settings" group contains settings related to multiple
 //! crates while "server_settings", "worker_settings", "client_settings", and
 //! "restart_workers" contain settings associated with their respective crates.
+//! Named "profile" sections can override the server address and secret, e.g.,
+//! for users who submit to more than one Kueue deployment.
 
-use anyhow::{bail, Result};
+use crate::structs::{JobTemplate, ProcessLimits, Resources};
+use anyhow::{anyhow, bail, Result};
 use config::{builder::BuilderState, ConfigBuilder};
 use directories::ProjectDirs;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
     fs::{create_dir_all, File},
     io::Write,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
 };
-use tokio::net::lookup_host;
+use tokio::{
+    net::{lookup_host, TcpStream},
+    task::JoinSet,
+    time::{sleep, Duration},
+};
+
+/// Delay before the next address of the server is tried, while a previous
+/// connection attempt is still pending.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
 
 /// The Config struct represents the read TOML config file
 /// and holds the settings for all individual crates.
@@ -35,6 +46,9 @@ pub struct Config {
     pub restart_workers: Option<RestartWorkers>,
     /// Custom global resources defined in the config.
     pub global_resources: Option<BTreeMap<String, u64>>,
+    /// Named server profiles, e.g., `[profile.cluster_a]`. A profile can be
+    /// selected on the command line of the client to override common settings.
+    pub profile: Option<BTreeMap<String, Profile>>,
 }
 
 /// Common settings shared among all crates.
@@ -42,6 +56,17 @@ pub struct Config {
 pub struct CommonSettings {
     /// Shared secret used to authenticate client and worker against the server.
     pub shared_secret: String,
+    /// Secret required for administrative commands (`kueue admin`). If set on
+    /// the server, the shared secret no longer suffices for these commands.
+    /// Clients use it, if set, to authenticate administrative commands.
+    #[serde(default)]
+    pub admin_secret: Option<String>,
+    /// Secret of the read-only observer role, e.g., for dashboards. If set on
+    /// the server, clients must authenticate to list jobs and workers, and
+    /// clients authenticated with this secret cannot submit, modify, or
+    /// remove anything. Observers use it as their `shared_secret`.
+    #[serde(default)]
+    pub observer_secret: Option<String>,
     /// Host name (or IP address) of the server, used by client and worker.
     pub server_name: String,
     /// Network port used by the server.
@@ -49,6 +74,25 @@ pub struct CommonSettings {
     /// Verbosity level of log messages.
     /// Options: `trace`, `debug`, `info`, `warn`, and `error`.
     pub log_level: String,
+    /// Key to encrypt commands and outputs of jobs issued with `--encrypt`.
+    /// Clients and workers must use the same key. Do not configure it on the
+    /// server, so that the server only relays ciphertext.
+    #[serde(default)]
+    pub payload_key: Option<String>,
+    /// Connect to the server with TLS. The server must be configured with
+    /// a certificate.
+    #[serde(default)]
+    pub tls: bool,
+    /// Certificate (PEM) of the authority that signed the server's
+    /// certificate, or the server's self-signed certificate itself. Without,
+    /// the server's certificate must be signed by a public authority.
+    #[serde(default)]
+    pub tls_ca_certificate: Option<PathBuf>,
+    /// Name of the tenant to connect to, if the server hosts several tenants
+    /// behind one listener. Without, the tenant is selected by the
+    /// `server_name`, or the server's own queue is used.
+    #[serde(default)]
+    pub tenant: Option<String>,
 }
 
 impl CommonSettings {
@@ -90,16 +134,269 @@ pub struct ServerSettings {
     /// The server performs maintenance every `maintenance_interval_seconds`
     /// to recover jobs from disconnected workers and clean up finished jobs.
     pub maintenance_interval_seconds: u64,
+    /// Workers are offered jobs as soon as jobs become assignable. In
+    /// addition, all workers look for assignable jobs every
+    /// `scheduling_interval_seconds`, e.g., for jobs whose start time has come.
+    pub scheduling_interval_seconds: u64,
     /// Time in seconds before a worker connection is considered timed-out.
     pub worker_timeout_seconds: u64,
+    /// Time in seconds the running jobs of a lost worker are kept assigned to
+    /// it, so that the worker can reconnect and report them as still running.
+    pub worker_loss_grace_seconds: u64,
+    /// Put running jobs of lost workers back into the queue after the grace
+    /// period. Otherwise, they are marked as failed.
+    pub requeue_on_worker_loss: bool,
     /// Time in seconds before a job offer to a worker is considered timed-out.
     pub job_offer_timeout_seconds: u64,
+    /// Number of job offers sent to a worker without waiting for its answer
+    /// to the previous ones. Hides the round-trip time on high-latency links.
+    pub offer_pipeline_depth: u64,
+    /// Number of worker connections that may look for jobs to offer or
+    /// reconcile their jobs with the server at the same time. Others wait
+    /// for their turn, which keeps lock hold times short when many workers
+    /// reconnect at once, e.g., after a network outage. Zero means no limit.
+    pub max_concurrent_offer_scans: u64,
+    /// Worker connections established at the same time, e.g., after a
+    /// network outage, are served one after another, this many milliseconds
+    /// apart plus random jitter. Until then, their messages, such as system
+    /// updates, are left waiting and no jobs are offered. Zero disables it.
+    pub reconnect_stagger_ms: u64,
+    /// Time in seconds other workers leave a job to its preferred worker, as
+    /// long as that worker has room for the job. Busy or offline preferred
+    /// workers are not waited for.
+    pub affinity_wait_seconds: u64,
+    /// Withdraw job offers that workers have not answered yet if a better
+    /// placement has appeared meanwhile, e.g., a job of higher priority.
+    /// Checked during maintenance.
+    pub rebalance_offers: bool,
+    /// Time in minutes an interactive job may run without a client attached
+    /// to it, e.g., after the client has gone before the job started. Such
+    /// jobs are canceled afterwards. Zero means no limit.
+    pub unattached_job_timeout_minutes: u64,
     /// Time in minutes before a finished job is removed from the list of jobs.
     pub job_cleanup_after_minutes: u64,
+    /// SQLite database to persist jobs in, so that the queue survives a
+    /// restart of the server. Without a database, jobs are only kept in memory.
+    #[serde(default)]
+    pub database: Option<PathBuf>,
+    /// Directory to store the outputs of jobs in, one file per output.
+    /// Without spool directory, outputs are kept in memory (and the database).
+    #[serde(default)]
+    pub spool_directory: Option<PathBuf>,
+    /// Outputs larger than this (in KiB) are shortened to their end when
+    /// spooled.
+    #[serde(default)]
+    pub max_spooled_output_kib: Option<u64>,
+    /// Time in minutes after which spooled outputs are deleted, even if the
+    /// job is still listed.
+    #[serde(default)]
+    pub spool_retention_minutes: Option<u64>,
+    /// Certificate chain (PEM) of the server. If set, together with
+    /// `tls_private_key`, clients and workers must connect with TLS.
+    #[serde(default)]
+    pub tls_certificate: Option<PathBuf>,
+    /// Private key (PEM) of the server's certificate.
+    #[serde(default)]
+    pub tls_private_key: Option<PathBuf>,
+    /// Time in minutes an unreachable worker is still listed, unless it
+    /// reconnects earlier.
+    pub unreachable_worker_retention_minutes: u64,
     /// Defines an global upper limit of parallel jobs across all workers. If
     /// this limit is reached, no more jobs will be started on any worker, even
     /// if enough other resources would be available.
     pub global_max_parallel_jobs: u64,
+    /// Message of the day, shown to users whenever the client connects.
+    /// Can be changed at runtime with `kueue admin motd`.
+    #[serde(default)]
+    pub motd: Option<String>,
+    /// Upper limit of jobs from the same user running concurrently on a
+    /// single worker. This spreads large job sweeps across the cluster.
+    /// The user name is reported by the client and not verified, so the
+    /// limit can be bypassed by a client sending a different `$USER`.
+    #[serde(default)]
+    pub max_jobs_per_user_per_worker: Option<u64>,
+    /// Rules to adjust jobs when they are issued, e.g., to add a license
+    /// resource to all jobs running a certain tool.
+    #[serde(default)]
+    pub routing_rules: Vec<RoutingRule>,
+    /// Job templates published to clients, so that common tools are run
+    /// with the same settings by everyone.
+    #[serde(default)]
+    pub job_templates: Vec<JobTemplate>,
+    /// Shell command run for every newly issued job to enforce site policy.
+    /// It receives the job as JSON on stdin and may print the adjusted job
+    /// as JSON on stdout. If it exits with an error, the job is rejected with
+    /// the hook's error output as reason.
+    #[serde(default)]
+    pub submission_hook: Option<String>,
+    /// Time in seconds the submission hook may take before the job is rejected.
+    pub submission_hook_timeout_seconds: u64,
+    /// Projects jobs can be accounted to with `kueue cmd --project`, along
+    /// with their quotas. Jobs of unknown projects are rejected.
+    #[serde(default)]
+    pub projects: Vec<Project>,
+    /// Quality of service classes jobs can select with `kueue cmd --qos`,
+    /// along with their limits. Jobs of unknown classes are rejected.
+    #[serde(default)]
+    pub qos_classes: Vec<QosClass>,
+    /// Class of jobs issued without `--qos`. Without default class, such
+    /// jobs are not subject to any class.
+    #[serde(default)]
+    pub default_qos: Option<String>,
+    /// Upper limit of the server's memory usage (resident set size) in
+    /// megabytes. Warnings are logged above 80% of the limit. Above the limit,
+    /// the `emergency_policies` are applied.
+    #[serde(default)]
+    pub memory_limit_mb: Option<u64>,
+    /// Upper limit of jobs kept by the server, including finished ones.
+    /// Warnings and emergency policies apply like for `memory_limit_mb`.
+    #[serde(default)]
+    pub job_limit: Option<u64>,
+    /// Measures taken while the server exceeds its memory or job limit.
+    pub emergency_policies: Vec<EmergencyPolicy>,
+    /// Number of pending jobs above which newly issued jobs are accepted
+    /// with a warning.
+    #[serde(default)]
+    pub pending_jobs_soft_limit: Option<u64>,
+    /// Number of pending jobs at which newly issued jobs are rejected. This
+    /// protects the server from runaway submission scripts.
+    #[serde(default)]
+    pub pending_jobs_hard_limit: Option<u64>,
+    /// Like `pending_jobs_soft_limit`, but for the pending jobs of each user.
+    #[serde(default)]
+    pub pending_jobs_per_user_soft_limit: Option<u64>,
+    /// Like `pending_jobs_hard_limit`, but for the pending jobs of each user.
+    #[serde(default)]
+    pub pending_jobs_per_user_hard_limit: Option<u64>,
+    /// Record every scheduling decision, i.e., which workers a job has been
+    /// evaluated for and why it has been skipped. The decisions are logged
+    /// and can be shown with `kueue admin trace <job_id>`.
+    #[serde(default)]
+    pub scheduling_trace: bool,
+    /// Handling of a worker that connects with the name of another connected
+    /// worker. Workers can also be renamed with `kueue admin rename-worker`.
+    pub duplicate_worker_names: DuplicateWorkerNames,
+    /// Handling of running jobs reported as stalled by their worker.
+    pub stalled_job_action: StalledJobAction,
+    /// Let users boost their own pending jobs with `kueue boost`. Otherwise,
+    /// boosting jobs requires the admin secret.
+    #[serde(default)]
+    pub owners_can_boost: bool,
+    /// Further logical instances of Kueue served by this server, each with
+    /// its own secrets, jobs, and workers. Clients and workers select their
+    /// tenant with `tenant` in their common settings, or by the host name
+    /// they connect to (TLS only).
+    #[serde(default)]
+    pub tenants: Vec<Tenant>,
+}
+
+/// Measure taken by the server to not run out of memory.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyPolicy {
+    /// Remove finished and canceled jobs, oldest first, before their
+    /// regular cleanup time.
+    EvictFinishedJobs,
+    /// Reject newly issued jobs until the server is below its limits again.
+    RefuseNewJobs,
+}
+
+/// Handling of workers connecting with a name that is already in use.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateWorkerNames {
+    /// Accept the worker and append its worker ID to its name, e.g., "vm#7".
+    Rename,
+    /// Refuse the worker until the other worker has disconnected.
+    Reject,
+}
+
+/// Logical instance of Kueue hosted by the server, e.g., for another group
+/// sharing the infrastructure. Settings not given here are taken from the
+/// server's own settings.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Tenant {
+    pub name: String,
+    /// Host names that select this tenant, if clients and workers connect
+    /// with TLS and without `tenant` in their config.
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    /// Shared secret of the tenant's clients and workers.
+    pub shared_secret: String,
+    #[serde(default)]
+    pub admin_secret: Option<String>,
+    #[serde(default)]
+    pub observer_secret: Option<String>,
+    /// Database of the tenant's jobs. Without, its jobs are kept in memory.
+    #[serde(default)]
+    pub database: Option<PathBuf>,
+    /// Spool directory of the tenant's outputs. Without, its outputs are
+    /// kept in memory.
+    #[serde(default)]
+    pub spool_directory: Option<PathBuf>,
+}
+
+/// Project that jobs can be accounted to, e.g., a grant or a team.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Project {
+    pub name: String,
+    /// Upper limit of running jobs of the project across all workers.
+    #[serde(default)]
+    pub max_running_jobs: Option<u64>,
+    /// CPU hours the project may consume. Once used up, new jobs of the
+    /// project are rejected and pending ones are not started anymore.
+    #[serde(default)]
+    pub cpu_hours: Option<f64>,
+}
+
+/// Quality of service class of jobs, e.g., "best-effort" or "urgent".
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct QosClass {
+    pub name: String,
+    /// Priority added to the priority of jobs in the class.
+    #[serde(default)]
+    pub priority: i32,
+    /// Running jobs of the class can be stopped and requeued to make room
+    /// for pending jobs of non-preemptible classes with higher priority.
+    #[serde(default)]
+    pub preemptible: bool,
+    /// Seconds preempted jobs may take to exit after SIGTERM, before they
+    /// are killed.
+    #[serde(default)]
+    pub preemption_grace_seconds: u64,
+    /// Upper limit of the run time of jobs in the class, in seconds. Jobs
+    /// with a longer `--timeout` are limited to it.
+    #[serde(default)]
+    pub max_runtime_seconds: Option<u64>,
+    /// Upper limit of running jobs per user in the class, across all workers.
+    #[serde(default)]
+    pub max_running_jobs_per_user: Option<u64>,
+}
+
+/// Handling of jobs that make no progress, as detected by their worker.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StalledJobAction {
+    /// Only mark the job as stalled in listings.
+    Flag,
+    /// Kill the job. It fails with exit code -49.
+    Kill,
+    /// Kill the job and requeue it to run on another worker.
+    Requeue,
+}
+
+/// Routing rule applied by the server to every newly issued job.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RoutingRule {
+    /// Regular expression matched against the job's command line.
+    pub command: String,
+    /// Resource pool assigned to matching jobs, unless the user chose one.
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// Global resources added to matching jobs, unless the user requested
+    /// the same resources explicitly.
+    #[serde(default)]
+    pub global_resources: BTreeMap<String, u64>,
 }
 
 impl ServerSettings {
@@ -115,10 +412,30 @@ impl ServerSettings {
         builder
             .set_default("server_settings.bind_addresses", default_binds)?
             .set_default("server_settings.maintenance_interval_seconds", 60)?
+            .set_default("server_settings.scheduling_interval_seconds", 30)?
             .set_default("server_settings.worker_timeout_seconds", 5 * 60)?
+            .set_default("server_settings.worker_loss_grace_seconds", 2 * 60)?
+            .set_default("server_settings.requeue_on_worker_loss", true)?
             .set_default("server_settings.job_offer_timeout_seconds", 60)?
+            .set_default("server_settings.offer_pipeline_depth", 2)?
+            .set_default("server_settings.affinity_wait_seconds", 60)?
+            .set_default("server_settings.rebalance_offers", true)?
+            .set_default("server_settings.unattached_job_timeout_minutes", 10)?
+            .set_default("server_settings.max_concurrent_offer_scans", 8)?
+            .set_default("server_settings.reconnect_stagger_ms", 10)?
             .set_default("server_settings.job_cleanup_after_minutes", 48 * 60)?
-            .set_default("server_settings.global_max_parallel_jobs", 100)
+            .set_default(
+                "server_settings.unreachable_worker_retention_minutes",
+                24 * 60,
+            )?
+            .set_default("server_settings.global_max_parallel_jobs", 100)?
+            .set_default("server_settings.submission_hook_timeout_seconds", 10)?
+            .set_default(
+                "server_settings.emergency_policies",
+                vec!["evict_finished_jobs", "refuse_new_jobs"],
+            )?
+            .set_default("server_settings.duplicate_worker_names", "rename")?
+            .set_default("server_settings.stalled_job_action", "flag")
     }
 }
 
@@ -134,6 +451,14 @@ pub struct WorkerSettings {
     /// this limit is reached, no more jobs will be started on the worker, even
     /// if enough other resources would be available.
     pub worker_max_parallel_jobs: u64,
+    /// Upper limit of jobs started within `job_start_window_seconds`. This
+    /// smooths I/O and license server spikes when a large batch of jobs lands
+    /// on an idle worker. The worker reports no more free job slots to the
+    /// server than jobs it may still start.
+    #[serde(default)]
+    pub max_job_starts_per_window: Option<u64>,
+    /// Length of the window for `max_job_starts_per_window` in seconds.
+    pub job_start_window_seconds: u64,
     /// When set to `true`, the current system utilization is considered when
     /// calculating available resources for job scheduling. Available resources
     /// will be calculated as "total system resources - max(busy resources,
@@ -143,12 +468,112 @@ pub struct WorkerSettings {
     /// resources are simply calculated as "total resources - resources reserved
     /// by running jobs".
     pub dynamic_check_free_resources: bool,
+    /// Free-form tags describing the worker, e.g., `["gpu", "bigmem"]`. Tags
+    /// are shown to users and can be used to filter the list of workers.
+    pub worker_tags: Vec<String>,
+    /// Labels of the worker, e.g., `{ arch = "avx512", storage = "fast" }`.
+    /// Jobs require labels with `--require`, e.g., `--require arch=avx512`.
+    #[serde(default)]
+    pub worker_labels: BTreeMap<String, String>,
     /// When calculating the amount of available CPUs based on current system
     /// occupation, this factor is applied to the measured CPU utilization. For
     /// instance, with a value of `2.0`, 50% CPU utilization would raise the
     /// calculated system occupation to 100%, leaving no room for any jobs.
     /// This setting has no effect if `dynamic_check_free_resources` is `false`.
     pub dynamic_cpu_load_scale_factor: f64,
+    /// Factor applied to the worker's CPU cores when reserving them for jobs,
+    /// e.g., `1.5` to accept jobs requesting up to 150% of the cores. Useful
+    /// for bursty workloads that rarely use all their cores at once. With
+    /// `dynamic_check_free_resources`, busy cores still limit new jobs.
+    pub cpu_overcommit_factor: f64,
+    /// Factor applied to the worker's memory when reserving it for jobs. With
+    /// `dynamic_check_free_resources`, memory in use still limits new jobs.
+    /// Both factors must be at least `1.0`.
+    pub ram_overcommit_factor: f64,
+    /// Named shares of the worker's capacity, e.g., 8 cores for "ci". Jobs
+    /// issued to a pool only run within the pool's share, which is not
+    /// available to other jobs.
+    #[serde(default)]
+    pub resource_pools: BTreeMap<String, Resources>,
+    /// Named resources of the worker, e.g., `{ matlab_licenses = 2 }`. Jobs
+    /// request them with `--resource`, like global resources of the server.
+    #[serde(default)]
+    pub resources: BTreeMap<String, u64>,
+    /// GPU devices assigned to jobs that require GPUs, e.g., `["0", "1"]`.
+    /// Jobs see their devices in `CUDA_VISIBLE_DEVICES`.
+    #[serde(default)]
+    pub gpu_devices: Vec<String>,
+    /// UUIDs of Multi-Instance GPU slices by profile, e.g., `{ "1g.5gb" =
+    /// ["MIG-..."] }`. Partitioned devices should not be listed in
+    /// `gpu_devices` as well.
+    #[serde(default)]
+    pub gpu_mig_slices: BTreeMap<String, Vec<String>>,
+    /// Time in seconds an accepted job offer may wait for the server's
+    /// confirmation. Afterwards, the job is dropped and its reserved resources
+    /// are released. This should be larger than the server's
+    /// `job_offer_timeout_seconds` setting.
+    pub accepted_job_timeout_seconds: u64,
+    /// Probes run by `kueue_worker --self-test` before the worker accepts any
+    /// jobs. The results are reported to the server, which does not offer
+    /// jobs to workers with failed probes.
+    #[serde(default)]
+    pub self_test_probes: Vec<SelfTestProbe>,
+    /// Time in seconds a single self-test probe may take before it fails.
+    pub self_test_timeout_seconds: u64,
+    /// Environment variables set to the number of CPU cores reserved for a
+    /// job. This keeps multi-threaded libraries, which default to one thread
+    /// per core of the system, from oversubscribing the worker.
+    pub thread_count_variables: Vec<String>,
+    /// Limits applied to processes of jobs that do not set their own, e.g.,
+    /// `{ nofile = 4096, core = 0 }`.
+    #[serde(default)]
+    pub default_process_limits: ProcessLimits,
+    /// Running jobs that neither consume CPU time nor produce output for the
+    /// given time are reported to the server as stalled, e.g., when hanging on
+    /// a dead network mount. Disabled, if not set.
+    #[serde(default)]
+    pub stalled_job_timeout_minutes: Option<u64>,
+    /// Time in seconds before the first attempt to reconnect to the server
+    /// after the connection has been lost. The time doubles with each failed
+    /// attempt, up to `reconnect_max_delay_seconds`.
+    pub reconnect_initial_delay_seconds: u64,
+    /// Upper bound of the time in seconds between attempts to reconnect.
+    pub reconnect_max_delay_seconds: u64,
+    /// Limits the bandwidth used to send job outputs and other data to the
+    /// server, in KiB per second. This keeps workers that share a slow uplink
+    /// from starving each other. Unlimited, if not set.
+    #[serde(default)]
+    pub max_upload_kib_per_second: Option<u64>,
+    /// Directory in which each job gets a private scratch directory, exported
+    /// as `KUEUE_SANDBOX`. Disabled, if not set.
+    #[serde(default)]
+    pub sandbox_directory: Option<PathBuf>,
+    /// Keep the sandboxes of successful jobs as well. By default, they are
+    /// removed as soon as the job has finished.
+    pub keep_succeeded_sandboxes: bool,
+    /// Days the sandboxes of failed and canceled jobs are kept for inspection.
+    pub sandbox_retention_days: u64,
+    /// Disk quota of all retained sandboxes in MiB. When exceeded, the oldest
+    /// sandboxes are removed first. Unlimited, if not set.
+    #[serde(default)]
+    pub max_retained_sandbox_mib: Option<u64>,
+    /// Record the worker's OS, kernel, and CPU model with each finished job,
+    /// shown by `kueue show-job --environment`.
+    pub capture_environment: bool,
+    /// Shell command whose output is recorded with the environment, e.g.,
+    /// `module list 2>&1; gcc --version`. Requires `capture_environment`.
+    #[serde(default)]
+    pub environment_probe: Option<String>,
+}
+
+/// Probe of the worker's environment, e.g., whether a compiler is available,
+/// a license server can be reached or the scratch directory is writable.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SelfTestProbe {
+    /// Name of the probe, as shown to users.
+    pub name: String,
+    /// Shell command that exits successfully if the probe passes.
+    pub command: String,
 }
 
 impl WorkerSettings {
@@ -159,18 +584,72 @@ impl WorkerSettings {
         builder
             .set_default("worker_settings.system_update_interval_seconds", 60)?
             .set_default("worker_settings.worker_max_parallel_jobs", 10)?
+            .set_default("worker_settings.job_start_window_seconds", 10)?
             .set_default("worker_settings.dynamic_check_free_resources", true)?
-            .set_default("worker_settings.dynamic_cpu_load_scale_factor", 1.0)
+            .set_default("worker_settings.worker_tags", Vec::<String>::new())?
+            .set_default("worker_settings.dynamic_cpu_load_scale_factor", 1.0)?
+            .set_default("worker_settings.cpu_overcommit_factor", 1.0)?
+            .set_default("worker_settings.ram_overcommit_factor", 1.0)?
+            .set_default("worker_settings.accepted_job_timeout_seconds", 5 * 60)?
+            .set_default("worker_settings.self_test_timeout_seconds", 60)?
+            .set_default("worker_settings.reconnect_initial_delay_seconds", 1)?
+            .set_default("worker_settings.reconnect_max_delay_seconds", 60)?
+            .set_default("worker_settings.keep_succeeded_sandboxes", false)?
+            .set_default("worker_settings.sandbox_retention_days", 7)?
+            .set_default("worker_settings.capture_environment", false)?
+            .set_default(
+                "worker_settings.thread_count_variables",
+                vec![
+                    "OMP_NUM_THREADS",
+                    "MKL_NUM_THREADS",
+                    "OPENBLAS_NUM_THREADS",
+                    "NUMEXPR_NUM_THREADS",
+                ],
+            )
     }
 }
 
 /// Settings related to the client crate.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ClientSettings {
+    /// Default number of job slots a job occupies, if not specified.
+    pub job_default_job_slots: u64,
     /// Default number of cpu cores a job requires, if not specified.
     pub job_default_cpus: u64,
     /// Default amount of RAM memory a job requires, if not specified.
     pub job_default_ram_mb: u64,
+    /// Default resource pool of jobs, if not specified.
+    #[serde(default)]
+    pub job_default_pool: Option<String>,
+    /// Default project jobs are accounted to, if not specified.
+    #[serde(default)]
+    pub job_default_project: Option<String>,
+    /// Resolve the working directory of submitted jobs to its canonical path
+    /// without symbolic links. If disabled, the path is kept as seen by the
+    /// shell ($PWD), e.g., to use a symbolic link that points to different
+    /// locations on different workers.
+    pub canonicalize_cwd: bool,
+    /// Display format of dates and times: "local", "utc", "iso", or "relative".
+    pub time_format: String,
+    /// When to use colors in the output: "auto", "always", or "never". With
+    /// "auto", colors are disabled if the output is piped or `NO_COLOR` is set.
+    pub color: String,
+    /// Color theme of the output: "default", "bright", or "monochrome".
+    pub theme: String,
+    /// Comma-separated list of columns shown by "list-jobs", if not given on
+    /// the command line. Available columns: id, cwd, cmd, cpus, memory,
+    /// resources, worker, status, runtime, priority, and qos.
+    pub job_list_columns: String,
+    /// Subcommand run when `kueue` is called without any, e.g., "list-jobs".
+    /// It may include arguments, just like an alias.
+    #[serde(default)]
+    pub default_command: Option<String>,
+    /// User-defined shortcuts for subcommands and their arguments, e.g.,
+    /// `q = "list-jobs --pending --running"`. Additional arguments given on
+    /// the command line are appended. Aliases cannot replace built-in
+    /// subcommands.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
 }
 
 impl ClientSettings {
@@ -179,11 +658,49 @@ impl ClientSettings {
         builder: ConfigBuilder<St>,
     ) -> Result<ConfigBuilder<St>, config::ConfigError> {
         builder
+            .set_default("client_settings.job_default_job_slots", 1)?
             .set_default("client_settings.job_default_cpus", 8)?
-            .set_default("client_settings.job_default_ram_mb", 8 * 1024)
+            .set_default("client_settings.job_default_ram_mb", 8 * 1024)?
+            .set_default("client_settings.canonicalize_cwd", true)?
+            .set_default("client_settings.time_format", "local")?
+            .set_default("client_settings.color", "auto")?
+            .set_default("client_settings.theme", "default")?
+            .set_default(
+                "client_settings.job_list_columns",
+                "id,priority,cwd,cmd,cpus,memory,resources,worker,status",
+            )
     }
 }
 
+/// Named server profile to override common settings. All settings are
+/// optional. Unset settings are taken from the "common_settings" group.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Profile {
+    /// Shared secret used to authenticate against the server of this profile.
+    pub shared_secret: Option<String>,
+    /// Secret for administrative commands on the server of this profile.
+    pub admin_secret: Option<String>,
+    /// Host name (or IP address) of the server of this profile.
+    pub server_name: Option<String>,
+    /// Network port used by the server of this profile.
+    pub server_port: Option<u16>,
+    /// Tenant on the server of this profile.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Secret of observers on the server of this profile.
+    #[serde(default)]
+    pub observer_secret: Option<String>,
+    /// Key to encrypt job payloads for the workers of this profile.
+    #[serde(default)]
+    pub payload_key: Option<String>,
+    /// Connect to the server of this profile with TLS.
+    #[serde(default)]
+    pub tls: Option<bool>,
+    /// Certificate to verify the server of this profile with.
+    #[serde(default)]
+    pub tls_ca_certificate: Option<PathBuf>,
+}
+
 /// Setting related to the optional "restart_workers" crate.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct RestartWorkers {
@@ -217,7 +734,27 @@ impl Config {
             .build()?;
 
         // Deserialize into Config.
-        s.try_deserialize()
+        let config: Config = s.try_deserialize()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject settings that cannot be used, even though they parse.
+    fn validate(&self) -> Result<(), config::ConfigError> {
+        let settings = &self.worker_settings;
+        for (name, factor) in [
+            ("cpu_overcommit_factor", settings.cpu_overcommit_factor),
+            ("ram_overcommit_factor", settings.ram_overcommit_factor),
+        ] {
+            // Also rejects NaN, which fails every comparison.
+            if !(factor.is_finite() && factor >= 1.0) {
+                return Err(config::ConfigError::Message(format!(
+                    "worker_settings.{} must be at least 1.0, found {}",
+                    name, factor
+                )));
+            }
+        }
+        Ok(())
     }
 
     /// If `config_path` does not exist, write the current config with all
@@ -241,6 +778,56 @@ impl Config {
         Ok(())
     }
 
+    /// Override common settings with the settings of the given profile.
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let profile = match self.profile.as_ref().and_then(|p| p.get(name)) {
+            Some(profile) => profile.clone(),
+            None => bail!("Profile '{}' not found in config!", name),
+        };
+
+        if let Some(shared_secret) = profile.shared_secret {
+            self.common_settings.shared_secret = shared_secret;
+        }
+        if profile.admin_secret.is_some() {
+            self.common_settings.admin_secret = profile.admin_secret;
+        }
+        if let Some(server_name) = profile.server_name {
+            self.common_settings.server_name = server_name;
+        }
+        if let Some(server_port) = profile.server_port {
+            self.common_settings.server_port = server_port;
+        }
+        if profile.tenant.is_some() {
+            self.common_settings.tenant = profile.tenant;
+        }
+        if profile.observer_secret.is_some() {
+            self.common_settings.observer_secret = profile.observer_secret;
+        }
+        if profile.payload_key.is_some() {
+            self.common_settings.payload_key = profile.payload_key;
+        }
+        if let Some(tls) = profile.tls {
+            self.common_settings.tls = tls;
+        }
+        if profile.tls_ca_certificate.is_some() {
+            self.common_settings.tls_ca_certificate = profile.tls_ca_certificate;
+        }
+        Ok(())
+    }
+
+    /// Settings of the server for the given tenant: the tenant's secrets,
+    /// database, and spool directory, and the server's other settings.
+    pub fn for_tenant(&self, tenant: &Tenant) -> Config {
+        let mut config = self.clone();
+        config.common_settings.shared_secret = tenant.shared_secret.clone();
+        config.common_settings.admin_secret = tenant.admin_secret.clone();
+        config.common_settings.observer_secret = tenant.observer_secret.clone();
+        config.server_settings.database = tenant.database.clone();
+        config.server_settings.spool_directory = tenant.spool_directory.clone();
+        config.server_settings.tenants.clear();
+        config
+    }
+
     /// Get `log::Level` from the config.
     pub fn get_log_level(&self) -> Result<log::Level> {
         match self.common_settings.log_level.to_lowercase().as_str() {
@@ -253,24 +840,79 @@ impl Config {
         }
     }
 
-    /// Build server address from `server_name` and `server_port`
-    /// and return it as `SocketAddr`.
-    pub async fn get_server_address(&self) -> Result<SocketAddr> {
-        let host = format!(
-            "{}:{}",
-            self.common_settings.server_name, self.common_settings.server_port
-        );
-        let mut addr_iter = lookup_host(host).await?;
-        match addr_iter.next() {
-            Some(socket_address) => Ok(socket_address),
-            None => bail!(
-                "Could not resolve server address: {}",
-                self.common_settings.server_name
-            ),
+    /// Resolve `server_name` and `server_port` to all addresses of the
+    /// server. IP addresses are used as they are, IPv6 addresses with or
+    /// without brackets. Resolved addresses alternate between IPv6 and IPv4,
+    /// starting with the family of the first result, so that connection
+    /// attempts quickly fall back to the other family.
+    pub async fn get_server_addresses(&self) -> Result<Vec<SocketAddr>> {
+        let name = self.common_settings.server_name.trim();
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+        let port = self.common_settings.server_port;
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
         }
+        let addresses: Vec<SocketAddr> = lookup_host((name, port))
+            .await
+            .map_err(|e| anyhow!("Could not resolve server address {}: {}", name, e))?
+            .collect();
+        if addresses.is_empty() {
+            bail!("Could not resolve server address: {}", name);
+        }
+        Ok(interleave_address_families(addresses))
+    }
+
+    /// Connect to the server. Connection attempts to the server's addresses
+    /// are started one after another, each after the previous attempt failed
+    /// or has not succeeded within a short delay ("happy eyeballs"). The
+    /// first established connection is used.
+    pub async fn connect_to_server(&self) -> Result<TcpStream> {
+        let mut addresses = self.get_server_addresses().await?.into_iter().peekable();
+        let mut attempts = JoinSet::new();
+        let mut errors = Vec::new();
+        loop {
+            if let Some(address) = addresses.next() {
+                attempts.spawn(async move { (address, TcpStream::connect(address).await) });
+            }
+            if attempts.is_empty() {
+                break; // all attempts failed
+            }
+            let more_addresses = addresses.peek().is_some();
+            tokio::select! {
+                Some(attempt) = attempts.join_next() => {
+                    match attempt? {
+                        (address, Ok(stream)) => {
+                            log::debug!("Connected to server at {}", address);
+                            return Ok(stream);
+                        }
+                        (address, Err(e)) => errors.push(format!("{}: {}", address, e)),
+                    }
+                }
+                _ = sleep(CONNECTION_ATTEMPT_DELAY), if more_addresses => {}
+            }
+        }
+        bail!("Failed to connect to server ({})", errors.join(", "))
     }
 }
 
+/// Reorder addresses to alternate between address families, starting with
+/// the family of the first address, as recommended by RFC 8305.
+fn interleave_address_families(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return addresses;
+    };
+    let prefer_ipv6 = first.is_ipv6();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == prefer_ipv6);
+    let mut interleaved = Vec::new();
+    while !preferred.is_empty() || !other.is_empty() {
+        interleaved.extend(preferred.pop_front());
+        interleaved.extend(other.pop_front());
+    }
+    interleaved
+}
+
 /// Returns the system-specific default path of the config file.
 pub fn default_path() -> PathBuf {
     let config_file_name = if cfg!(debug_assertions) {
@@ -285,3 +927,89 @@ pub fn default_path() -> PathBuf {
         config_file_name.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_profile() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "cluster_b".to_string(),
+            Profile {
+                server_name: Some("cluster-b.example.com".into()),
+                ..Default::default()
+            },
+        );
+        config.profile = Some(profiles);
+
+        assert!(config.apply_profile("cluster_a").is_err());
+        assert!(config.apply_profile("cluster_b").is_ok());
+        assert_eq!(config.common_settings.server_name, "cluster-b.example.com");
+        assert_eq!(config.common_settings.server_port, 11236);
+        assert!(!config.common_settings.tls);
+
+        // A profile of a TLS server switches a plain default config to TLS.
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "cluster_tls".to_string(),
+            Profile {
+                server_name: Some("cluster-tls.example.com".into()),
+                tls: Some(true),
+                tls_ca_certificate: Some("/etc/kueue/ca.pem".into()),
+                observer_secret: Some("observe".into()),
+                payload_key: Some("payload".into()),
+                ..Default::default()
+            },
+        );
+        config.profile = Some(profiles);
+        assert!(config.apply_profile("cluster_tls").is_ok());
+        let settings = &config.common_settings;
+        assert!(settings.tls);
+        assert_eq!(
+            settings.tls_ca_certificate,
+            Some("/etc/kueue/ca.pem".into())
+        );
+        assert_eq!(settings.observer_secret.as_deref(), Some("observe"));
+        assert_eq!(settings.payload_key.as_deref(), Some("payload"));
+    }
+
+    #[test]
+    fn overcommit_factors() {
+        let path =
+            std::env::temp_dir().join(format!("kueue-overcommit-{}.toml", std::process::id()));
+        let load = |settings: &str| {
+            std::fs::write(&path, format!("[worker_settings]\n{}\n", settings)).unwrap();
+            Config::new(Some(path.clone()))
+        };
+
+        let config = load("cpu_overcommit_factor = 1.5\nram_overcommit_factor = 1.0").unwrap();
+        assert_eq!(config.worker_settings.cpu_overcommit_factor, 1.5);
+        assert!(load("cpu_overcommit_factor = 0.5").is_err());
+        assert!(load("cpu_overcommit_factor = -2.0").is_err());
+        assert!(load("ram_overcommit_factor = nan").is_err());
+        assert!(load("ram_overcommit_factor = 0.0").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_addresses() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.common_settings.server_port = 11236;
+        config.common_settings.server_name = "[::1]".into();
+        let addresses = config.get_server_addresses().await.unwrap();
+        assert_eq!(addresses, vec!["[::1]:11236".parse().unwrap()]);
+
+        let addresses = ["10.0.0.1:1", "10.0.0.2:1", "[fe80::1]:1", "[fe80::2]:1"]
+            .map(|address| address.parse().unwrap())
+            .to_vec();
+        let interleaved = interleave_address_families(addresses.clone());
+        assert_eq!(
+            interleaved,
+            vec![addresses[0], addresses[2], addresses[1], addresses[3]]
+        );
+    }
+}
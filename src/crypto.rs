@@ -0,0 +1,267 @@
+//! Cryptography on job data. Commands and outputs of encrypted jobs are only
+//! readable by clients and workers that know the payload key, so that the
+//! server, which might be operated by a different party, only relays
+//! ciphertext. Provenance records of finished jobs are signed with the shared
+//! secret.
+
+use crate::structs::{EnvSnapshot, JobInfo, OutputStream};
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Placeholder command shown in place of the command of encrypted jobs.
+pub const ENCRYPTED_CMD: &str = "<encrypted>";
+
+/// Length of the random nonce prepended to each ciphertext.
+const NONCE_LENGTH: usize = 12;
+
+/// Salt of the key derivation from the `payload_key`. It is fixed, so that
+/// clients and workers derive the same key without exchanging anything.
+const KEY_SALT: &[u8] = b"kueue payload key v1";
+
+/// Parts of a job that are encrypted separately. Each part is bound to its
+/// kind, its job, and the tenant of the job, so that the server cannot swap
+/// ciphertexts between jobs or parts without being detected.
+#[derive(Clone, Copy, Debug)]
+pub enum Sealed {
+    Payload,
+    Stdout,
+    Stderr,
+}
+
+impl From<OutputStream> for Sealed {
+    fn from(stream: OutputStream) -> Self {
+        match stream {
+            OutputStream::Stdout => Sealed::Stdout,
+            OutputStream::Stderr => Sealed::Stderr,
+        }
+    }
+}
+
+/// Associated data that binds a ciphertext to a part of a job.
+fn associated_data(sealed: Sealed, job_id: u64, tenant: Option<&str>) -> Vec<u8> {
+    let kind = match sealed {
+        Sealed::Payload => "payload",
+        Sealed::Stdout => "stdout",
+        Sealed::Stderr => "stderr",
+    };
+    format!(
+        "kueue {} of job {} of tenant {}",
+        kind,
+        job_id,
+        tenant.unwrap_or_default()
+    )
+    .into_bytes()
+}
+
+/// Parts of a job that are encrypted.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobPayload {
+    pub cmd: Vec<String>,
+    pub setup_steps: Vec<Vec<String>>,
+    pub teardown_steps: Vec<Vec<String>>,
+    /// Environment recorded with `--snapshot-env`, if any.
+    #[serde(default)]
+    pub env_snapshot: Option<EnvSnapshot>,
+}
+
+impl JobPayload {
+    /// Returns `true` if the job consists of more than the main command.
+    pub fn has_steps(&self) -> bool {
+        !self.setup_steps.is_empty() || !self.teardown_steps.is_empty()
+    }
+}
+
+/// Authenticated encryption with a key derived from the configured
+/// `payload_key`.
+#[derive(Clone)]
+pub struct PayloadCipher {
+    cipher: ChaCha20Poly1305,
+    /// Tenant the jobs are issued to, bound to all ciphertexts.
+    tenant: Option<String>,
+}
+
+impl PayloadCipher {
+    /// Derive the encryption key from the given passphrase with Argon2id and
+    /// the fixed `KEY_SALT`. This is deliberately slow, so the cipher should
+    /// be created once and reused.
+    pub fn new(payload_key: &str, tenant: Option<&str>) -> Self {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(payload_key.as_bytes(), KEY_SALT, &mut key)
+            .expect("default Argon2 parameters and salt are valid");
+        PayloadCipher {
+            cipher: ChaCha20Poly1305::new(&key),
+            tenant: tenant.map(String::from),
+        }
+    }
+
+    /// Encrypt the given part of a job. Returns base64-encoded nonce and
+    /// ciphertext.
+    pub fn encrypt(&self, sealed: Sealed, job_id: u64, plaintext: &str) -> Result<String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(sealed, job_id, self.tenant.as_deref());
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: &aad,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow!("Failed to encrypt payload!"))?;
+        Ok(general_purpose::STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    /// Decrypt text produced by `encrypt` for the same part of the same job.
+    /// Fails if the text has been encrypted with a different key, for
+    /// another job or tenant, or has been tampered with.
+    pub fn decrypt(&self, sealed: Sealed, job_id: u64, text: &str) -> Result<String> {
+        let data = general_purpose::STANDARD
+            .decode(text)
+            .map_err(|e| anyhow!("Invalid encrypted payload: {}", e))?;
+        if data.len() < NONCE_LENGTH {
+            bail!("Invalid encrypted payload: too short");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let aad = associated_data(sealed, job_id, self.tenant.as_deref());
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| anyhow!("Failed to decrypt payload! Is the payload key correct?"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Move the command and steps of the job into its encrypted payload. The
+    /// job must already have its final ID, as reserved from the server.
+    pub fn seal_job(&self, job_info: &mut JobInfo) -> Result<()> {
+        let payload = JobPayload {
+            cmd: std::mem::replace(&mut job_info.cmd, vec![ENCRYPTED_CMD.into()]),
+            setup_steps: std::mem::take(&mut job_info.setup_steps),
+            teardown_steps: std::mem::take(&mut job_info.teardown_steps),
+            env_snapshot: job_info.env_snapshot.take(),
+        };
+        let text = serde_json::to_string(&payload)?;
+        job_info.encrypted_payload = Some(self.encrypt(Sealed::Payload, job_info.job_id, &text)?);
+        Ok(())
+    }
+
+    /// Decrypt the payload of an encrypted job.
+    pub fn open_job(&self, job_info: &JobInfo) -> Result<JobPayload> {
+        let text = job_info
+            .encrypted_payload
+            .as_deref()
+            .ok_or_else(|| anyhow!("Job {} is not encrypted!", job_info.job_id))?;
+        let text = self.decrypt(Sealed::Payload, job_info.job_id, text)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+/// HMAC-SHA256 of the data with the given secret, base64-encoded.
+pub fn sign(secret: &str, data: &[u8]) -> String {
+    let mut mac = hmac(secret);
+    mac.update(data);
+    general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Check a signature created by `sign` in constant time.
+pub fn verify(secret: &str, data: &[u8], signature: &str) -> bool {
+    let Ok(signature) = general_purpose::STANDARD.decode(signature) else {
+        return false;
+    };
+    let mut mac = hmac(secret);
+    mac.update(data);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// HMAC-SHA256 instance keyed with the given secret.
+fn hmac(secret: &str) -> Hmac<Sha256> {
+    <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length")
+}
+
+/// SHA-256 hash of the data, hex-encoded.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+
+    #[test]
+    fn encrypted_jobs() {
+        let cipher = PayloadCipher::new("secret key", None);
+        let mut job_info = JobInfo::new(
+            vec!["echo".into(), "hello".into()],
+            "/tmp".into(),
+            Resources::new(1, 1, 8),
+            None,
+            None,
+            None,
+        );
+        job_info.setup_steps = vec![vec!["true".into()]];
+        cipher.seal_job(&mut job_info).unwrap();
+
+        // Nothing of the payload is visible to the server.
+        assert_eq!(job_info.cmd, vec![ENCRYPTED_CMD]);
+        assert!(job_info.setup_steps.is_empty());
+        assert!(!job_info
+            .encrypted_payload
+            .as_ref()
+            .unwrap()
+            .contains("hello"));
+
+        let payload = cipher.open_job(&job_info).unwrap();
+        assert_eq!(payload.cmd, vec!["echo", "hello"]);
+        assert_eq!(payload.setup_steps, vec![vec!["true"]]);
+
+        // Another key cannot decrypt the payload.
+        let other_key = PayloadCipher::new("other key", None);
+        assert!(other_key.open_job(&job_info).is_err());
+
+        // Neither can the same key for another tenant.
+        let other_tenant = PayloadCipher::new("secret key", Some("other"));
+        assert!(other_tenant.open_job(&job_info).is_err());
+
+        // The payload cannot be moved to another job.
+        let mut other_job = job_info.clone();
+        other_job.job_id += 1;
+        assert!(cipher.open_job(&other_job).is_err());
+
+        // Outputs are bound to their job and stream as well.
+        let job_id = job_info.job_id;
+        let stdout = cipher.encrypt(Sealed::Stdout, job_id, "output").unwrap();
+        assert_eq!(
+            cipher.decrypt(Sealed::Stdout, job_id, &stdout).unwrap(),
+            "output"
+        );
+        assert!(cipher.decrypt(Sealed::Stderr, job_id, &stdout).is_err());
+        assert!(cipher.decrypt(Sealed::Stdout, job_id + 1, &stdout).is_err());
+        assert!(cipher.decrypt(Sealed::Payload, job_id, &stdout).is_err());
+    }
+
+    #[test]
+    fn signatures() {
+        let signature = sign("secret", b"record");
+        assert!(verify("secret", b"record", &signature));
+        assert!(!verify("secret", b"altered record", &signature));
+        assert!(!verify("other secret", b"record", &signature));
+        assert!(!verify("secret", b"record", "not base64!"));
+        assert_eq!(sha256_hex(b"").len(), 64);
+    }
+}
@@ -19,19 +19,26 @@
 
 pub mod client;
 pub mod config;
+pub mod crypto;
 pub mod messages;
 pub mod server;
 pub mod structs;
+pub mod testing;
+pub mod tls;
+pub mod tunnel;
 pub mod worker;
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        config::Config,
-        messages::{ClientToServerMessage, HelloMessage},
+        messages::{
+            version::{capabilities, KUEUE_VERSION},
+            ClientToServerMessage, HelloMessage, LegacyWelcome, ServerToClientMessage,
+            ServerToWorkerMessage,
+        },
+        structs::OutputStream,
     };
-    use simple_logger::SimpleLogger;
-    use tokio::time::{sleep, Duration};
+    use std::collections::BTreeSet;
 
     #[tokio::test]
     async fn general_test_setup() {
@@ -71,7 +78,11 @@ mod tests {
     fn serde_message() {
         // Run tests with `cargo test --lib -- --nocapture` to see output.
 
-        let message = HelloMessage::HelloFromClient;
+        let message = HelloMessage::HelloFromClient {
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tenant: None,
+        };
         let buffer = serde_json::to_vec(&message).unwrap();
         println!("Hello: {}", String::from_utf8(buffer).unwrap());
 
@@ -83,4 +94,51 @@ mod tests {
         let buffer = serde_json::to_vec(&message).unwrap();
         println!("Bye: {}", String::from_utf8(buffer).unwrap());
     }
+
+    #[test]
+    fn legacy_handshake() {
+        // Peers that predate the version exchange send unit variants.
+        let hello: HelloMessage = serde_json::from_str(r#""HelloFromClient""#).unwrap();
+        assert_eq!(
+            hello,
+            HelloMessage::HelloFromClient {
+                kueue_version: String::new(),
+                capabilities: BTreeSet::new(),
+                tenant: None,
+            }
+        );
+        let hello: HelloMessage =
+            serde_json::from_str(r#"{"HelloFromWorker":{"worker_name":"old"}}"#).unwrap();
+        assert!(matches!(
+            hello,
+            HelloMessage::HelloFromWorker { kueue_version, .. } if kueue_version.is_empty()
+        ));
+        let welcome: ServerToClientMessage = serde_json::from_str(r#""WelcomeClient""#).unwrap();
+        assert!(matches!(
+            welcome,
+            ServerToClientMessage::WelcomeClient { .. }
+        ));
+        let welcome: ServerToWorkerMessage = serde_json::from_str(r#""WelcomeWorker""#).unwrap();
+        assert!(matches!(
+            welcome,
+            ServerToWorkerMessage::WelcomeWorker { .. }
+        ));
+
+        // Such peers are welcomed in the form they expect.
+        let welcome = serde_json::to_string(&LegacyWelcome::WelcomeClient).unwrap();
+        assert_eq!(welcome, r#""WelcomeClient""#);
+
+        // Other messages are not affected, neither in JSON nor in binary frames.
+        let bye: ServerToClientMessage = serde_json::from_str(r#""Bye""#).unwrap();
+        assert_eq!(bye, ServerToClientMessage::Bye);
+        let message = ServerToClientMessage::JobLog {
+            job_id: 1,
+            stream: OutputStream::Stdout,
+            data: b"output".to_vec(),
+        };
+        let mut buffer = Vec::new();
+        ciborium::into_writer(&message, &mut buffer).unwrap();
+        let decoded: ServerToClientMessage = ciborium::from_reader(&buffer[..]).unwrap();
+        assert_eq!(decoded, message);
+    }
 }
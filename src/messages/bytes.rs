@@ -0,0 +1,68 @@
+//! Serialize binary data as Base64 strings, which is much more compact than
+//! the default representation as JSON array of numbers. Binary formats store
+//! the bytes as they are.
+
+use base64::{engine::general_purpose, Engine};
+use serde::{
+    de::{Error, Visitor},
+    Deserializer, Serializer,
+};
+use std::fmt;
+
+pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(data))
+    } else {
+        serializer.serialize_bytes(data)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+/// Accepts Base64 strings as well as raw bytes.
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("Base64 string or bytes")
+    }
+
+    fn visit_str<E: Error>(self, text: &str) -> Result<Vec<u8>, E> {
+        general_purpose::STANDARD.decode(text).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: Error>(self, data: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(data.to_vec())
+    }
+
+    fn visit_byte_buf<E: Error>(self, data: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::messages::ClientToServerMessage;
+
+    #[test]
+    fn base64_payload() {
+        let message = ClientToServerMessage::JobInput {
+            job_id: 7,
+            data: vec![0, 3, 255],
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(json, r#"{"JobInput":{"job_id":7,"data":"AAP/"}}"#);
+        assert_eq!(
+            serde_json::from_str::<ClientToServerMessage>(&json).unwrap(),
+            message
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Chunked transfer of job results. Large outputs are split into numbered
+//! segments, so that a single message does not block the connection and the
+//! receive buffers stay small. The server reassembles the segments.
+
+use super::WorkerToServerMessage;
+use crate::structs::OutputStream;
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+/// Maximum size of the data in a single segment, in bytes.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Split the results of a job into JobResultsChunk messages. Segments are
+/// numbered across both streams; the last one is marked as such. Results
+/// without any output are sent as a single UpdateJobResults message.
+pub fn split_results(
+    job_id: u64,
+    stdout_text: Option<&str>,
+    stderr_text: Option<&str>,
+) -> Vec<WorkerToServerMessage> {
+    let mut segments = Vec::new();
+    for (stream, text) in [
+        (OutputStream::Stdout, stdout_text),
+        (OutputStream::Stderr, stderr_text),
+    ] {
+        if let Some(mut text) = text {
+            // Empty outputs are sent as an empty segment.
+            loop {
+                let mut end = text.len().min(CHUNK_SIZE);
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                segments.push((stream, &text[..end]));
+                text = &text[end..];
+                if text.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    if segments.is_empty() {
+        return vec![WorkerToServerMessage::UpdateJobResults {
+            job_id,
+            stdout_text: None,
+            stderr_text: None,
+        }];
+    }
+
+    let count = segments.len();
+    segments
+        .into_iter()
+        .enumerate()
+        .map(
+            |(index, (stream, data))| WorkerToServerMessage::JobResultsChunk {
+                job_id,
+                sequence: index as u64,
+                stream,
+                data: data.to_string(),
+                last: index + 1 == count,
+            },
+        )
+        .collect()
+}
+
+/// Results of a job that have been received partially.
+#[derive(Default)]
+struct PartialResults {
+    next_sequence: u64,
+    stdout_text: Option<String>,
+    stderr_text: Option<String>,
+}
+
+/// Reassembles the segments of job results received on a connection.
+#[derive(Default)]
+pub struct ResultsAssembler {
+    transfers: BTreeMap<u64, PartialResults>,
+}
+
+impl ResultsAssembler {
+    /// Add a segment. Returns stdout and stderr of the job once the last
+    /// segment has been added. Segments must arrive in order; otherwise, the
+    /// transfer is dropped.
+    pub fn add(
+        &mut self,
+        job_id: u64,
+        sequence: u64,
+        stream: OutputStream,
+        data: String,
+        last: bool,
+    ) -> Result<Option<(Option<String>, Option<String>)>> {
+        // A transfer starts over with the first segment.
+        if sequence == 0 {
+            self.transfers.remove(&job_id);
+        }
+        let transfer = self.transfers.entry(job_id).or_default();
+        if sequence != transfer.next_sequence {
+            let expected = transfer.next_sequence;
+            self.transfers.remove(&job_id);
+            bail!(
+                "Expected segment {} of job {}'s results, received {}!",
+                expected,
+                job_id,
+                sequence
+            );
+        }
+        transfer.next_sequence += 1;
+
+        let text = match stream {
+            OutputStream::Stdout => &mut transfer.stdout_text,
+            OutputStream::Stderr => &mut transfer.stderr_text,
+        };
+        text.get_or_insert_with(String::new).push_str(&data);
+
+        if last {
+            let transfer = self.transfers.remove(&job_id).unwrap_or_default();
+            Ok(Some((transfer.stdout_text, transfer.stderr_text)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_reassemble_results() {
+        let stdout = String::from("a") + &"ä".repeat(CHUNK_SIZE);
+        let messages = split_results(7, Some(&stdout), Some(""));
+        assert_eq!(messages.len(), 4);
+
+        let mut assembler = ResultsAssembler::default();
+        let mut results = None;
+        for message in messages {
+            match message {
+                WorkerToServerMessage::JobResultsChunk {
+                    job_id,
+                    sequence,
+                    stream,
+                    data,
+                    last,
+                } => {
+                    assert!(data.len() <= CHUNK_SIZE);
+                    results = assembler.add(job_id, sequence, stream, data, last).unwrap();
+                }
+                message => panic!("Unexpected message: {:?}", message),
+            }
+        }
+        assert_eq!(results, Some((Some(stdout), Some(String::new()))));
+
+        // Segments out of order drop the transfer.
+        let data = String::from("x");
+        assert!(assembler
+            .add(7, 0, OutputStream::Stdout, data.clone(), false)
+            .is_ok());
+        assert!(assembler
+            .add(7, 2, OutputStream::Stdout, data, true)
+            .is_err());
+
+        assert!(matches!(
+            split_results(7, None, None)[..],
+            [WorkerToServerMessage::UpdateJobResults { .. }]
+        ));
+    }
+}
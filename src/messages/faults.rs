@@ -0,0 +1,161 @@
+//! Fault injection for testing. Message streams can randomly delay messages,
+//! write only part of a message, or drop the connection, to exercise the
+//! recovery paths of clients, workers, and server (reconnects, withdrawn job
+//! offers, requeued jobs).
+//!
+//! Faults are configured per stream with [`MessageStream::with_fault_injection`](
+//! super::stream::MessageStream::with_fault_injection). Builds with the
+//! `fault-injection` feature additionally read a profile for all streams from
+//! the `KUEUE_FAULT_INJECTION` environment variable, for example:
+//!
+//! ```text
+//! KUEUE_FAULT_INJECTION="delay=0.1,max_delay_ms=500,truncate=0.01,drop=0.01"
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use rand::Rng;
+use std::time::Duration;
+
+/// Name of the environment variable with the fault profile.
+pub const FAULT_INJECTION_ENV: &str = "KUEUE_FAULT_INJECTION";
+
+/// Probabilities of faults, checked for each message sent or received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultInjection {
+    /// Probability of delaying a message.
+    pub delay: f64,
+    /// Upper bound of injected delays.
+    pub max_delay: Duration,
+    /// Probability of writing only part of a message before the connection
+    /// is closed.
+    pub truncate: f64,
+    /// Probability of dropping the connection.
+    pub drop: f64,
+}
+
+/// Fault to inject into the current operation.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Fault {
+    Delay(Duration),
+    /// Write only the given number of bytes, then close the connection.
+    Truncate(usize),
+    Drop,
+}
+
+impl Default for FaultInjection {
+    fn default() -> Self {
+        FaultInjection {
+            delay: 0.0,
+            max_delay: Duration::from_millis(100),
+            truncate: 0.0,
+            drop: 0.0,
+        }
+    }
+}
+
+impl FaultInjection {
+    /// Parse a profile like "delay=0.1,max_delay_ms=500,truncate=0.01,drop=0.01".
+    /// Omitted faults are not injected.
+    pub fn parse(profile: &str) -> Result<Self> {
+        let mut faults = FaultInjection::default();
+        for setting in profile.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, found: {}", setting))?;
+            let value = value.trim();
+            match key.trim() {
+                "max_delay_ms" => faults.max_delay = Duration::from_millis(value.parse()?),
+                key => {
+                    let probability: f64 = value.parse()?;
+                    if !(0.0..=1.0).contains(&probability) {
+                        bail!("Probability of {} must be between 0 and 1!", key);
+                    }
+                    match key {
+                        "delay" => faults.delay = probability,
+                        "truncate" => faults.truncate = probability,
+                        "drop" => faults.drop = probability,
+                        _ => bail!("Unknown fault: {}", key),
+                    }
+                }
+            }
+        }
+        Ok(faults)
+    }
+
+    /// Profile from the environment, if built with the `fault-injection`
+    /// feature. An invalid profile is reported once and ignored.
+    pub fn from_env() -> Option<Self> {
+        #[cfg(feature = "fault-injection")]
+        {
+            static FAULTS: std::sync::OnceLock<Option<FaultInjection>> = std::sync::OnceLock::new();
+            FAULTS
+                .get_or_init(|| {
+                    let profile = std::env::var(FAULT_INJECTION_ENV).ok()?;
+                    match FaultInjection::parse(&profile) {
+                        Ok(faults) => {
+                            log::warn!("Fault injection enabled: {:?}", faults);
+                            Some(faults)
+                        }
+                        Err(e) => {
+                            log::error!("Invalid {}: {}", FAULT_INJECTION_ENV, e);
+                            None
+                        }
+                    }
+                })
+                .clone()
+        }
+        #[cfg(not(feature = "fault-injection"))]
+        None
+    }
+
+    /// Roll the dice for sending a message of `len` bytes.
+    pub(crate) fn on_send(&self, len: usize) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.drop) {
+            Some(Fault::Drop)
+        } else if len > 1 && rng.gen_bool(self.truncate) {
+            Some(Fault::Truncate(rng.gen_range(1..len)))
+        } else {
+            self.on_delay(&mut rng)
+        }
+    }
+
+    /// Roll the dice for receiving data.
+    pub(crate) fn on_receive(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.drop) {
+            Some(Fault::Drop)
+        } else {
+            self.on_delay(&mut rng)
+        }
+    }
+
+    fn on_delay(&self, rng: &mut impl Rng) -> Option<Fault> {
+        if rng.gen_bool(self.delay) {
+            let max_delay = self.max_delay.as_millis() as u64;
+            Some(Fault::Delay(Duration::from_millis(
+                rng.gen_range(0..=max_delay),
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fault_profile() {
+        let faults = FaultInjection::parse("delay=0.5, max_delay_ms=20,drop=1").unwrap();
+        assert_eq!(faults.delay, 0.5);
+        assert_eq!(faults.max_delay, Duration::from_millis(20));
+        assert_eq!(faults.truncate, 0.0);
+        assert_eq!(faults.on_send(10), Some(Fault::Drop));
+
+        assert!(FaultInjection::parse("drop=2").is_err());
+        assert!(FaultInjection::parse("explode=0.1").is_err());
+        assert!(FaultInjection::parse("delay").is_err());
+    }
+}
@@ -1,32 +1,172 @@
 //! Contains a collection of structs that are transferred as messages between
 //! client and server, and worker and server.
+//!
+//! Messages should evolve in a backward-compatible way, so that different
+//! versions of clients, workers, and server can be connected at the same time
+//! (e.g., during a rolling upgrade of workers):
+//!
+//! - New fields in existing messages and structs must be marked with
+//!   `#[serde(default)]`, so that messages from older peers can still be read.
+//!   Unknown fields from newer peers are ignored by serde.
+//! - New message variants are skipped by older peers with the
+//!   [version::CAP_SKIP_UNKNOWN_MESSAGES] capability. Newer messages should
+//!   only be sent if the peer announced the respective capability.
+//! - The hello/welcome handshake is always sent as JSON. Afterwards, peers
+//!   with the [version::CAP_BINARY_FRAMES] capability switch to binary frames,
+//!   see [stream::WireFormat]. Other peers keep using JSON.
+//! - Hello and welcome messages have been unit variants before peers
+//!   exchanged their versions. The unit form is still accepted and sent to
+//!   peers that do not announce their version, see [LegacyWelcome].
 
+mod bytes;
+pub mod chunks;
+pub mod faults;
 pub mod stream;
+pub mod version;
 
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
-use crate::structs::{JobInfo, Resources, SystemInfo, WorkerInfo};
-use serde::{Deserialize, Serialize};
+use crate::structs::{
+    AuditEntry, ConnectionInfo, DependentsAction, IdleResources, JobInfo, JobMigration, JobReceipt,
+    JobTemplate, OutputStream, ProbeResult, ProjectUsage, Resources, SchedulingEvent, SystemInfo,
+    UserUsage, WorkerInfo,
+};
+use chrono::{DateTime, Utc};
+use serde::{
+    de::{
+        value::{MapAccessDeserializer, StrDeserializer},
+        Error, MapAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, marker::PhantomData};
 
 /// Communication to the server is initialized with HelloFromClient or
 /// HelloFromWorker. The variants help the server to distinguish between client
 /// and worker connections. The server will respond with the corresponding
-/// "welcome" message.
+/// "welcome" message. Both sides exchange their Kueue versions to detect
+/// incompatible components early.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(remote = "Self")]
 pub enum HelloMessage {
     /// Initiate a new client connection with the HelloFromClient message.
     /// The server confirms the connection with the WelcomeClient message.
-    HelloFromClient,
+    HelloFromClient {
+        /// Version of the connecting client. Empty for older clients.
+        #[serde(default)]
+        kueue_version: String,
+        /// Optional protocol features supported by the client.
+        #[serde(default)]
+        capabilities: BTreeSet<String>,
+        /// Tenant to connect to, if the server hosts several.
+        #[serde(default)]
+        tenant: Option<String>,
+    },
     /// Initiate a new worker connection with the HelloFromWorker message.
     /// The server confirms the connection with the WelcomeWorker message.
     HelloFromWorker {
         /// Name of the worker. Can be helpful for the
         /// user to identify where jobs are running.
         worker_name: String,
+        /// Version of the connecting worker. Empty for older workers.
+        #[serde(default)]
+        kueue_version: String,
+        /// Optional protocol features supported by the worker.
+        #[serde(default)]
+        capabilities: BTreeSet<String>,
+        /// Tags describing the worker, as configured on the worker.
+        #[serde(default)]
+        tags: BTreeSet<String>,
+        /// Labels of the worker, as configured on the worker.
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
+        /// Tenant to connect to, if the server hosts several.
+        #[serde(default)]
+        tenant: Option<String>,
     },
 }
 
+impl LegacyUnitVariants for HelloMessage {
+    fn from_unit_variant(variant: &str) -> Option<Self> {
+        (variant == "HelloFromClient").then(|| HelloMessage::HelloFromClient {
+            kueue_version: String::new(),
+            capabilities: BTreeSet::new(),
+            tenant: None,
+        })
+    }
+
+    fn deserialize_derived<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HelloMessage::deserialize(deserializer)
+    }
+}
+
+/// Welcome messages in the unit form expected by peers that did not send
+/// their version with their hello message.
+#[derive(Debug, Serialize)]
+pub enum LegacyWelcome {
+    WelcomeClient,
+    WelcomeWorker,
+}
+
+/// Handshake messages that peers sent as unit variants before versions were
+/// exchanged. The unit form is read as the variant with default fields.
+trait LegacyUnitVariants: Sized {
+    /// Message read from the given unit variant, if it used to be one.
+    fn from_unit_variant(variant: &str) -> Option<Self>;
+
+    /// Deserialize the message as derived by serde.
+    fn deserialize_derived<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+/// Reads either form of a message with legacy unit variants.
+struct LegacyUnitVisitor<T>(PhantomData<T>);
+
+impl<'de, T: LegacyUnitVariants> Visitor<'de> for LegacyUnitVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a message")
+    }
+
+    fn visit_str<E: Error>(self, variant: &str) -> Result<T, E> {
+        match T::from_unit_variant(variant) {
+            Some(message) => Ok(message),
+            None => T::deserialize_derived(StrDeserializer::new(variant)),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+        T::deserialize_derived(MapAccessDeserializer::new(map))
+    }
+}
+
+/// Implements Serialize and Deserialize for messages derived with
+/// `#[serde(remote = "Self")]`, accepting their legacy unit variants.
+macro_rules! serde_with_legacy_unit_variants {
+    ($message:ident) => {
+        impl Serialize for $message {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $message::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $message {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_any(LegacyUnitVisitor(PhantomData))
+            }
+        }
+    };
+}
+
+serde_with_legacy_unit_variants!(HelloMessage);
+serde_with_legacy_unit_variants!(ServerToClientMessage);
+serde_with_legacy_unit_variants!(ServerToWorkerMessage);
+
 /// Contains all messages sent by the client to the server.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub enum ClientToServerMessage {
     /// Request authentication challenge. This is required to issue or remove
@@ -37,10 +177,15 @@ pub enum ClientToServerMessage {
     /// server. The server responds with a AuthAccepted(bool) to indicate if the
     /// authentication was successful.
     AuthResponse(String),
-    /// Issue a new job. The job's ID and status will be ignored by the server.
+    /// Issue a new job. The job's ID and status will be ignored by the server,
+    /// unless the ID has been reserved with ReserveJobId on this connection.
     /// The server responds with a AcceptJob message and provide updated
     /// details. This command requires authentication.
     IssueJob(JobInfo),
+    /// Reserve the ID of a job to be issued, e.g., to bind its encrypted
+    /// payload to it. The server responds with JobIdReserved. This command
+    /// requires authentication.
+    ReserveJobId,
     ListJobs {
         num_jobs: u64,
         pending: bool,
@@ -56,9 +201,21 @@ pub enum ClientToServerMessage {
     ObserveJob {
         job_id: u64,
     },
+    /// Observe all jobs of a submission group, including jobs issued to the
+    /// group later on. The server responds with GroupJobs and sends JobUpdated
+    /// messages afterward.
+    ObserveGroup {
+        group: String,
+    },
     RemoveJob {
         job_id: u64,
         kill: bool,
+        /// Handling of the jobs depending on the removed job.
+        #[serde(default)]
+        dependents: DependentsAction,
+        /// Stored in the job's canceled status.
+        #[serde(default)]
+        reason: Option<String>,
     },
     CleanJobs {
         all: bool,
@@ -68,21 +225,176 @@ pub enum ClientToServerMessage {
         worker_id: u64,
     },
     ListResources,
+    /// Attach to an interactive job. The server forwards the job's terminal
+    /// output with JobOutput messages and notifies about status changes with
+    /// JobUpdated messages. The job is killed if the client disconnects.
+    AttachJob {
+        job_id: u64,
+    },
+    /// Terminal input for an attached, interactive job.
+    JobInput {
+        job_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Window size of the client's terminal for an attached, interactive job.
+    ResizeTerminal {
+        job_id: u64,
+        rows: u16,
+        cols: u16,
+    },
+    /// Open a TCP tunnel to a port opened by a running job. The tunnel ID is
+    /// chosen by the client and used for subsequent TunnelData messages.
+    OpenTunnel {
+        job_id: u64,
+        tunnel_id: u64,
+        port: u16,
+    },
+    /// Data sent through a tunnel.
+    TunnelData {
+        tunnel_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Close a tunnel. Sent by either side when its connection has ended.
+    CloseTunnel {
+        tunnel_id: u64,
+    },
+    /// Set or clear (`None`) the message of the day. This
+    /// command requires authentication.
+    SetMotd {
+        text: Option<String>,
+    },
+    /// Send a message to all connected clients and to clients connecting
+    /// within the next minutes. This command requires authentication.
+    Broadcast {
+        text: String,
+        expires_in_minutes: u64,
+    },
+    /// Drop the connection to a worker immediately. Its running jobs are
+    /// killed and either requeued or marked as failed (`fail_jobs`). The
+    /// worker cannot reconnect for the given cooldown. This command
+    /// requires authentication.
+    RemoveWorker {
+        worker_id: u64,
+        fail_jobs: bool,
+        cooldown_minutes: u64,
+    },
+    /// List all live client and worker connections. This command
+    /// requires authentication.
+    ListConnections,
+    /// Show the recorded scheduling decisions about a job. This command
+    /// requires authentication.
+    ShowSchedulingTrace {
+        job_id: u64,
+    },
+    /// Give a connected worker a new name. This command requires
+    /// authentication.
+    RenameWorker {
+        worker_id: u64,
+        worker_name: String,
+    },
+    /// Query the usage of the cluster per project.
+    GetProjectUsage,
+    /// Query resources that sat idle while pending jobs did not fit. The
+    /// server responds with IdleResources.
+    GetIdleResources,
+    /// Query the job templates published by the server. The server responds
+    /// with JobTemplates.
+    ListTemplates,
+    /// Query the accounting of finished jobs per user, optionally only of the
+    /// given user and within a time range of the jobs' end. The server
+    /// responds with UserUsage.
+    GetAccounting {
+        user: Option<String>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    },
+    /// Stop (`drain`) or resume offering new jobs to a worker. Running jobs
+    /// are not affected. This command requires admin authentication.
+    DrainWorker {
+        worker_id: u64,
+        drain: bool,
+    },
+    /// Offer a worker only jobs of the given user, or any jobs again
+    /// (`None`). This command requires admin authentication.
+    ReserveWorker {
+        worker_id: u64,
+        user: Option<String>,
+    },
+    /// Stop a running job cleanly and requeue it on another worker. This
+    /// command requires admin authentication.
+    MigrateJob {
+        job_id: u64,
+        migration: JobMigration,
+    },
+    /// Write a consistent copy of the job database to the given path on the
+    /// server. This command requires admin authentication.
+    SaveSnapshot {
+        path: PathBuf,
+    },
+    /// Change the verbosity of the server's log at runtime. This command
+    /// requires admin authentication.
+    SetLogLevel {
+        level: String,
+    },
+    /// Show the recently requested administrative actions. This command
+    /// requires admin authentication.
+    ShowAuditLog,
+    /// Raise the priority of a pending job, or move it to the front of the
+    /// queue. Requires admin authentication, unless the server lets owners
+    /// boost their own jobs.
+    BoostJob {
+        job_id: u64,
+        to_front: bool,
+        /// Name of the requesting user.
+        user: String,
+    },
+    /// Compare the outputs of two jobs. The server responds with JobDiff.
+    DiffJobs {
+        job_a: u64,
+        job_b: u64,
+    },
+    /// Follow the output of a job. The server responds with JobUpdated,
+    /// forwards output of the running job with JobLog messages, and notifies
+    /// about status changes with JobUpdated messages.
+    FollowJob {
+        job_id: u64,
+    },
+    /// Download the outputs of a job. The server responds with FetchChunk
+    /// messages, followed by FetchComplete.
+    FetchJob {
+        job_id: u64,
+    },
     Bye,
 }
 
 /// Contains all messages sent by the server to a client.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(remote = "Self")]
 pub enum ServerToClientMessage {
     /// Respond with WelcomeClient after HelloFromClient.
-    WelcomeClient,
+    WelcomeClient {
+        /// Version of the server. Empty for older servers.
+        #[serde(default)]
+        kueue_version: String,
+        /// Optional protocol features supported by the server.
+        #[serde(default)]
+        capabilities: BTreeSet<String>,
+    },
     /// AuthChallenge sends a random salt to the client.
     AuthChallenge {
         salt: String,
     },
     /// Let client know if authentication succeeded.
     AuthAccepted(bool),
+    /// Validation report of a newly issued job. Sent right before AcceptJob,
+    /// if the client supports submission receipts.
+    JobReceipt(JobReceipt),
     AcceptJob(JobInfo),
+    /// ID reserved for the next job issued on this connection.
+    JobIdReserved(u64),
     RejectJob {
         job_info: JobInfo,
         reason: String,
@@ -104,11 +416,85 @@ pub enum ServerToClientMessage {
         stderr_text: Option<String>,
     },
     JobUpdated(JobInfo),
+    /// Output of a followed job, in the order it has been produced.
+    JobLog {
+        job_id: u64,
+        stream: OutputStream,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Part of a job's output, starting at the given offset.
+    FetchChunk {
+        job_id: u64,
+        stream: OutputStream,
+        offset: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Concludes the download of a job's outputs with the SHA-256 checksum of
+    /// each output. Outputs without checksum do not exist. Outputs of
+    /// encrypted jobs are sent as ciphertext.
+    FetchComplete {
+        job_id: u64,
+        encrypted: bool,
+        stdout_sha256: Option<String>,
+        stderr_sha256: Option<String>,
+    },
+    /// Unified diff of the outputs of two jobs, empty if they are equal.
+    JobDiff {
+        job_a: u64,
+        job_b: u64,
+        diff: String,
+        /// The diff has been shortened to the server's size limit.
+        truncated: bool,
+    },
+    /// Jobs of an observed submission group at the time of subscription.
+    GroupJobs {
+        group: String,
+        job_infos: Vec<JobInfo>,
+    },
+    /// Terminal output of an attached, interactive job.
+    JobOutput {
+        job_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Data received through a tunnel.
+    TunnelData {
+        tunnel_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// The tunnel has been closed by the worker or could not be opened.
+    CloseTunnel {
+        tunnel_id: u64,
+    },
     WorkerList(Vec<WorkerInfo>),
     WorkerInfo(WorkerInfo),
+    ConnectionList(Vec<ConnectionInfo>),
+    SchedulingTrace {
+        job_id: u64,
+        events: Vec<SchedulingEvent>,
+    },
     ResourceList {
         used_resources: Option<BTreeMap<String, u64>>,
         total_resources: Option<BTreeMap<String, u64>>,
+        /// Named resources of all connected workers.
+        #[serde(default)]
+        used_worker_resources: BTreeMap<String, u64>,
+        #[serde(default)]
+        total_worker_resources: BTreeMap<String, u64>,
+    },
+    ProjectUsage(Vec<ProjectUsage>),
+    IdleResources(Vec<IdleResources>),
+    JobTemplates(Vec<JobTemplate>),
+    UserUsage(Vec<UserUsage>),
+    AuditLog(Vec<AuditEntry>),
+    /// Message of the day or broadcast. Sent after WelcomeClient and whenever
+    /// a new broadcast is issued, if the client supports announcements.
+    Announcement {
+        text: String,
+        broadcast: bool,
     },
     /// Generic response, signaling the client if the requested action has
     /// succeeded or if something went wrong. This is used, for instance, when
@@ -122,6 +508,19 @@ pub enum ServerToClientMessage {
     Bye,
 }
 
+impl LegacyUnitVariants for ServerToClientMessage {
+    fn from_unit_variant(variant: &str) -> Option<Self> {
+        (variant == "WelcomeClient").then(|| ServerToClientMessage::WelcomeClient {
+            kueue_version: String::new(),
+            capabilities: BTreeSet::new(),
+        })
+    }
+
+    fn deserialize_derived<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ServerToClientMessage::deserialize(deserializer)
+    }
+}
+
 /// Contains all messages sent by the worker to the server.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub enum WorkerToServerMessage {
@@ -135,31 +534,153 @@ pub enum WorkerToServerMessage {
         stdout_text: Option<String>,
         stderr_text: Option<String>,
     },
+    /// Segment of the results of a finished job, sent instead of
+    /// UpdateJobResults if the server supports chunked results. Segments are
+    /// numbered from zero across both streams, see [chunks::split_results].
+    JobResultsChunk {
+        job_id: u64,
+        sequence: u64,
+        stream: OutputStream,
+        data: String,
+        last: bool,
+    },
     // Update server about available resources on the worker. The worker
     // might reply with new job offers based on the provided information.
     UpdateResources(Resources),
+    /// Available resources of each resource pool on the worker. Sent before
+    /// UpdateResources, if the server supports resource pools.
+    UpdatePoolResources(BTreeMap<String, Resources>),
     AcceptJobOffer(JobInfo),
     DeferJobOffer(JobInfo),
     RejectJobOffer(JobInfo),
+    /// IDs of all jobs accepted or running on the worker. Sent regularly, if
+    /// the server supports job reconciliation, so that the server can resolve
+    /// differences to its own bookkeeping.
+    ReportJobs {
+        accepted: BTreeSet<u64>,
+        running: BTreeSet<u64>,
+        /// Running jobs without progress for the worker's stalled job timeout.
+        #[serde(default)]
+        stalled: BTreeSet<u64>,
+    },
+    /// Results of the worker's self-test. Sent once after authentication,
+    /// before the first resource update, if the server supports it.
+    ReportSelfTest(Vec<ProbeResult>),
+    /// Terminal output of an interactive job.
+    JobOutput {
+        job_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Data received from the job's port through a tunnel.
+    TunnelData {
+        tunnel_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Output of a followed job. The offset is the position of the data in
+    /// the stream. When following begins, the output captured so far is
+    /// sent with `catch_up` set.
+    JobLog {
+        job_id: u64,
+        stream: OutputStream,
+        offset: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+        catch_up: bool,
+    },
+    /// The job's side of the tunnel has been closed or could not be opened.
+    CloseTunnel {
+        tunnel_id: u64,
+    },
     Bye,
 }
 
 /// Contains all messages sent by the server to a worker.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(remote = "Self")]
 pub enum ServerToWorkerMessage {
     // Respond with WelcomeWorker after HelloFromWorker
-    WelcomeWorker,
+    WelcomeWorker {
+        /// Version of the server. Empty for older servers.
+        #[serde(default)]
+        kueue_version: String,
+        /// Optional protocol features supported by the server.
+        #[serde(default)]
+        capabilities: BTreeSet<String>,
+    },
     // AuthChallenge sends a random salt to the client.
     AuthChallenge {
         salt: String,
     },
     /// Let worker know if authentication succeeded.
     AuthAccepted(bool),
+    /// Respond with RejectWorker instead of WelcomeWorker if the worker is
+    /// not allowed to connect, e.g., after it has been removed by an admin.
+    RejectWorker {
+        reason: String,
+    },
     OfferJob(JobInfo),
     ConfirmJobOffer(JobInfo),
     WithdrawJobOffer(JobInfo),
     KillJob(JobInfo),
-    /// Close connection to the worker. The server will only actively
-    /// close the connection when the server is shutting down.
+    /// Send the signal to the running job and kill it, unless it exits within
+    /// the grace period. The job is reported as finished with exit code -51.
+    StopJob {
+        job_id: u64,
+        signal: i32,
+        grace_period_seconds: u64,
+    },
+    /// Terminal input for an interactive job, forwarded from the client.
+    JobInput {
+        job_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// Window size of the client's terminal, forwarded from the client.
+    ResizeTerminal {
+        job_id: u64,
+        rows: u16,
+        cols: u16,
+    },
+    /// Connect to a local port opened by a running job. Tunnel IDs are
+    /// assigned by the server and are unique among all connected clients.
+    OpenTunnel {
+        job_id: u64,
+        tunnel_id: u64,
+        port: u16,
+    },
+    /// Data to be sent to the job's port through a tunnel.
+    TunnelData {
+        tunnel_id: u64,
+        #[serde(with = "bytes")]
+        data: Vec<u8>,
+    },
+    /// The client's side of the tunnel has been closed.
+    CloseTunnel {
+        tunnel_id: u64,
+    },
+    /// Start or stop forwarding the output of a running job with JobLog
+    /// messages, while clients follow the job.
+    FollowJob {
+        job_id: u64,
+        follow: bool,
+    },
+    /// Close connection to the worker, when the server is shutting down or
+    /// the worker has been removed. The worker keeps its jobs running and
+    /// reports them once it has reconnected.
     Bye,
 }
+
+impl LegacyUnitVariants for ServerToWorkerMessage {
+    fn from_unit_variant(variant: &str) -> Option<Self> {
+        (variant == "WelcomeWorker").then(|| ServerToWorkerMessage::WelcomeWorker {
+            kueue_version: String::new(),
+            capabilities: BTreeSet::new(),
+        })
+    }
+
+    fn deserialize_derived<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ServerToWorkerMessage::deserialize(deserializer)
+    }
+}
@@ -1,15 +1,34 @@
 //! Read and write messages from and to the an underlying stream.
 
+use crate::messages::{
+    faults::{Fault, FaultInjection},
+    version::CAP_BINARY_FRAMES,
+};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
 
 /// MessageStream builds a high-level abstraction of sending messages over the
 /// network on top of a stream, e.g. `tokio::net::TcpStream`. It takes ownership
 /// of a given stream and instantiates buffers to account for caching (yet)
 /// incomplete messages. The implementation is generalized for any stream type,
 /// so it can be re-used in tests without requiring TCP network connections.
+///
+/// Messages start out as JSON, so that any version can read the hello/welcome
+/// handshake. If both peers support it, they switch to binary frames after the
+/// handshake, see [MessageStream::negotiate_format].
 pub struct MessageStream<Stream> {
     /// The underlying stream object.
     stream: Stream,
@@ -20,14 +39,103 @@ pub struct MessageStream<Stream> {
     /// Holds received message chunks from previous read operations.
     /// The buffer grows dynamically until it fits a complete message.
     msg_buffer: Vec<u8>,
+    /// Bytes transferred over the stream so far.
+    traffic: Arc<Traffic>,
+    /// Encoding of the messages.
+    format: WireFormat,
+    /// Faults to inject for testing, see [FaultInjection].
+    faults: Option<FaultInjection>,
+    /// Set after an injected fault closed the connection.
+    broken: bool,
+    /// Limits the bytes sent per second, see [MessageStream::with_rate_limit].
+    rate_limit: Option<RateLimit>,
 }
 
+/// Counts the bytes sent and received over a message stream. The counters
+/// are shared, so they can be read while the stream is in use elsewhere.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Traffic {
+    /// Total number of bytes sent.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Total number of bytes received.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// Token bucket limiting the bytes written per second. Up to one second worth
+/// of bytes can be sent at once, so that small messages, e.g., system updates,
+/// are not delayed while the link is otherwise idle.
+struct RateLimit {
+    bytes_per_second: u64,
+    /// Bytes that may be sent right away. Negative after a large write, until
+    /// the debt has been paid off.
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimit {
+    fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        RateLimit {
+            bytes_per_second,
+            available: bytes_per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `len` bytes from the bucket. Returns the time to wait before
+    /// writing them.
+    fn acquire(&mut self, len: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        let capacity = self.bytes_per_second as f64;
+        self.available = (self.available + elapsed * capacity).min(capacity) - len as f64;
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / capacity)
+        }
+    }
+}
+
+/// Rate-limited messages are written in chunks of this size, so that large
+/// messages flow steadily instead of in bursts.
+const RATE_LIMIT_CHUNK_LEN: usize = 16 * 1024;
+
 /// Initial size of the read buffer. Whenever its size was insufficient to read
 /// all data available on the network, its capacity is doubled. This avoids too
 /// many parsing attempts on (yet) incomplete messages at the cost of higher
 /// memory consumption.
 const INIT_READ_BUFFER_LEN: usize = 32 * 1024;
 
+/// Length of the frame header, holding the payload length as big-endian u32.
+const FRAME_HEADER_LEN: usize = 4;
+
+/// Frames larger than this are considered corrupt, so that garbage on the
+/// stream does not make us wait for gigabytes of data.
+const MAX_FRAME_LEN: usize = 512 * 1024 * 1024;
+
+/// Encoding of messages on the wire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WireFormat {
+    /// Concatenated JSON values, parsed incrementally. Used for the handshake
+    /// and with peers that do not support binary frames.
+    Json,
+    /// Each message is a CBOR value, prefixed with its length.
+    BinaryFrames,
+}
+
 impl<Stream> MessageStream<Stream> {
     /// Create a high-level message stream abstraction on top of a stream.
     pub fn new(stream: Stream) -> Self {
@@ -35,6 +143,58 @@ impl<Stream> MessageStream<Stream> {
             stream,
             read_buffer: vec![0; INIT_READ_BUFFER_LEN],
             msg_buffer: Vec::new(),
+            traffic: Arc::new(Traffic::default()),
+            format: WireFormat::Json,
+            faults: FaultInjection::from_env(),
+            broken: false,
+            rate_limit: None,
+        }
+    }
+
+    /// Inject faults into the stream, for testing.
+    pub fn with_fault_injection(mut self, faults: FaultInjection) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Limit the bytes sent per second, e.g., so that large job outputs do not
+    /// saturate a slow uplink shared with other workers.
+    pub fn with_rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limit = Some(RateLimit::new(bytes_per_second));
+        self
+    }
+
+    /// Returns the traffic counters of the stream.
+    pub fn traffic(&self) -> Arc<Traffic> {
+        self.traffic.clone()
+    }
+
+    /// Current encoding of the messages.
+    pub fn format(&self) -> WireFormat {
+        self.format
+    }
+
+    /// Switch to binary frames if the peer supports them. Both peers call this
+    /// right after the hello/welcome handshake: the server after sending its
+    /// welcome, the client or worker after receiving it. Data that has already
+    /// been received is interpreted in the new format.
+    pub fn negotiate_format(&mut self, peer_capabilities: &BTreeSet<String>) {
+        if peer_capabilities.contains(CAP_BINARY_FRAMES) {
+            self.format = WireFormat::BinaryFrames;
+        }
+    }
+
+    /// Encode the message in the current format.
+    fn encode<T: Serialize>(&self, message: &T) -> Vec<u8> {
+        match self.format {
+            WireFormat::Json => serde_json::to_vec(message).unwrap(),
+            WireFormat::BinaryFrames => {
+                let mut buffer = vec![0; FRAME_HEADER_LEN];
+                ciborium::into_writer(message, &mut buffer).unwrap();
+                let len = (buffer.len() - FRAME_HEADER_LEN) as u32;
+                buffer[..FRAME_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+                buffer
+            }
         }
     }
 }
@@ -43,16 +203,60 @@ impl<Stream: AsyncWriteExt + Unpin> MessageStream<Stream> {
     /// Send a message over the stream.
     pub async fn send<T: Serialize + Debug>(&mut self, message: &T) -> Result<(), MessageError> {
         log::trace!("Sending message: {:?}", message);
-        let buffer = serde_json::to_vec(message).unwrap();
+        let buffer = self.encode(message);
+
+        if self.broken {
+            return Err(MessageError::SendFailed);
+        }
+        match self.faults.as_ref().and_then(|f| f.on_send(buffer.len())) {
+            Some(Fault::Delay(delay)) => sleep(delay).await,
+            Some(Fault::Truncate(len)) => {
+                log::warn!("Injected fault: Message truncated after {} bytes.", len);
+                let _ = self.stream.write_all(&buffer[..len]).await;
+                return Err(self.inject_drop().await);
+            }
+            Some(Fault::Drop) => {
+                log::warn!("Injected fault: Connection dropped while sending.");
+                return Err(self.inject_drop().await);
+            }
+            None => {}
+        }
 
-        match self.stream.write_all(&buffer).await {
-            Ok(()) => Ok(()),
+        match self.write(&buffer).await {
+            Ok(()) => {
+                let bytes_sent = buffer.len() as u64;
+                self.traffic.sent.fetch_add(bytes_sent, Ordering::Relaxed);
+                Ok(())
+            }
             Err(e) => {
                 log::error!("Write error: {}", e);
                 Err(MessageError::SendFailed)
             }
         }
     }
+
+    /// Write the buffer to the stream, respecting the rate limit if set.
+    async fn write(&mut self, buffer: &[u8]) -> std::io::Result<()> {
+        let rate_limit = match self.rate_limit.as_mut() {
+            Some(rate_limit) => rate_limit,
+            None => return self.stream.write_all(buffer).await,
+        };
+        for chunk in buffer.chunks(RATE_LIMIT_CHUNK_LEN) {
+            let delay = rate_limit.acquire(chunk.len());
+            if !delay.is_zero() {
+                sleep(delay).await;
+            }
+            self.stream.write_all(chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Close the connection as if it had been dropped.
+    async fn inject_drop(&mut self) -> MessageError {
+        let _ = self.stream.shutdown().await;
+        self.broken = true;
+        MessageError::SendFailed
+    }
 }
 
 impl<Stream: AsyncReadExt + Unpin> MessageStream<Stream> {
@@ -67,12 +271,30 @@ impl<Stream: AsyncReadExt + Unpin> MessageStream<Stream> {
                 }
                 Err(ParseError::EofWhileParsing) => {} // no return -> continue reading from stream and try again later.
                 Err(ParseError::ParsingFailed) => return Err(MessageError::ReceiveFailed), // give up and propagate error.
+                Err(ParseError::UnknownMessage) => return Err(MessageError::UnknownMessage), // message skipped, stream still usable.
             }
 
             // Read more data from stream.
+            if self.broken {
+                return Err(MessageError::StreamClosed);
+            }
+            match self.faults.as_ref().and_then(FaultInjection::on_receive) {
+                Some(Fault::Delay(delay)) => sleep(delay).await,
+                Some(Fault::Drop) => {
+                    log::warn!("Injected fault: Connection dropped while receiving.");
+                    self.broken = true;
+                    return Err(MessageError::StreamClosed);
+                }
+                _ => {}
+            }
             match self.stream.read(&mut self.read_buffer).await {
                 Ok(0) => return Err(MessageError::StreamClosed),
                 Ok(bytes_read) => {
+                    let bytes_received = bytes_read as u64;
+                    self.traffic
+                        .received
+                        .fetch_add(bytes_received, Ordering::Relaxed);
+
                     // Move read bytes into message buffer and continue loop.
                     self.msg_buffer.extend(&self.read_buffer[..bytes_read]);
 
@@ -98,16 +320,55 @@ impl<Stream: AsyncReadExt + Unpin> MessageStream<Stream> {
 impl<Stream> MessageStream<Stream> {
     /// Deserialize the next message.
     fn parse_message<T: for<'a> Deserialize<'a>>(&mut self) -> Result<T, ParseError> {
-        // Try to parse T from msg_buffer
+        match self.format {
+            WireFormat::Json => self.parse_json_message(),
+            WireFormat::BinaryFrames => self.parse_frame(),
+        }
+    }
+
+    /// Deserialize the next frame. An unknown message is skipped as a whole,
+    /// since its length is known.
+    fn parse_frame<T: for<'a> Deserialize<'a>>(&mut self) -> Result<T, ParseError> {
+        if self.msg_buffer.len() < FRAME_HEADER_LEN {
+            return Err(ParseError::EofWhileParsing);
+        }
+        let mut header = [0; FRAME_HEADER_LEN];
+        header.copy_from_slice(&self.msg_buffer[..FRAME_HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > MAX_FRAME_LEN {
+            log::error!("Frame of {} bytes exceeds the limit!", len);
+            return Err(ParseError::ParsingFailed);
+        }
+        if self.msg_buffer.len() < FRAME_HEADER_LEN + len {
+            return Err(ParseError::EofWhileParsing);
+        }
+
+        let frame: Vec<u8> = self.msg_buffer.drain(..FRAME_HEADER_LEN + len).collect();
+        ciborium::from_reader(&frame[FRAME_HEADER_LEN..]).map_err(|e| {
+            log::warn!("Skipping unknown message: {}", e);
+            ParseError::UnknownMessage
+        })
+    }
+
+    /// Deserialize the next JSON value.
+    fn parse_json_message<T: for<'a> Deserialize<'a>>(&mut self) -> Result<T, ParseError> {
+        // Try to parse a complete JSON value from msg_buffer first. This way,
+        // a syntactically valid message is always consumed from the buffer,
+        // even if it turns out to be a message unknown to this version.
         let de = serde_json::Deserializer::from_slice(&self.msg_buffer);
-        let mut message_iterator = de.into_iter::<T>();
+        let mut message_iterator = de.into_iter::<serde_json::Value>();
         match message_iterator.next() {
             Some(result) => match result {
-                Ok(message) => {
+                Ok(value) => {
                     // Successfully read message. Remove consumed bytes from buffer.
                     let bytes_consumed = message_iterator.byte_offset();
                     self.msg_buffer.drain(..bytes_consumed);
-                    Ok(message)
+
+                    // Now, interpret the value as message of type T.
+                    serde_json::from_value::<T>(value).map_err(|e| {
+                        log::warn!("Skipping unknown message: {}", e);
+                        ParseError::UnknownMessage
+                    })
                 }
                 Err(e) if e.is_eof() => {
                     // Incomplete message. We need to read more data from the stream.
@@ -140,6 +401,11 @@ pub enum MessageError {
     /// Stream has been closed.
     #[error("stream closed")]
     StreamClosed,
+    /// A well-formed message has been received but it is not known to this
+    /// version of Kueue, e.g., because it has been sent by a newer version.
+    /// The message has been skipped and the stream can still be used.
+    #[error("received unknown message")]
+    UnknownMessage,
 }
 
 /// ParseError is used internally to distinguish between
@@ -152,4 +418,150 @@ enum ParseError {
     /// Parsing of the message failed.
     #[error("failed to parse message")]
     ParsingFailed,
+    /// The message is valid JSON but does not match the expected message type.
+    #[error("unknown message")]
+    UnknownMessage,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::ClientToServerMessage;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn skip_unknown_message() {
+        let (server_stream, mut client_stream) = duplex(1024);
+        let mut stream = MessageStream::new(server_stream);
+
+        // Simulate a newer client sending a message we do not know yet.
+        client_stream
+            .write_all(br#"{"FancyNewRequest":{"answer":42}}"ListWorkers""#)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stream.receive::<ClientToServerMessage>().await,
+            Err(MessageError::UnknownMessage)
+        );
+        assert_eq!(
+            stream.receive::<ClientToServerMessage>().await,
+            Ok(ClientToServerMessage::ListWorkers)
+        );
+    }
+
+    #[tokio::test]
+    async fn count_traffic() {
+        let (server_stream, client_stream) = duplex(1024);
+        let mut server_stream = MessageStream::new(server_stream);
+        let mut client_stream = MessageStream::new(client_stream);
+        let traffic = server_stream.traffic();
+
+        client_stream
+            .send(&ClientToServerMessage::ListWorkers)
+            .await
+            .unwrap();
+        server_stream
+            .receive::<ClientToServerMessage>()
+            .await
+            .unwrap();
+        server_stream
+            .send(&ClientToServerMessage::Bye)
+            .await
+            .unwrap();
+
+        assert_eq!(traffic.received(), r#""ListWorkers""#.len() as u64);
+        assert_eq!(traffic.sent(), r#""Bye""#.len() as u64);
+        assert_eq!(client_stream.traffic().sent(), traffic.received());
+    }
+
+    #[tokio::test]
+    async fn inject_faults() {
+        let (server_stream, client_stream) = duplex(1024);
+        let mut server_stream = MessageStream::new(server_stream);
+        let drop = FaultInjection {
+            drop: 1.0,
+            ..Default::default()
+        };
+        let mut client_stream = MessageStream::new(client_stream).with_fault_injection(drop);
+
+        // The dropped connection is noticed on both ends.
+        assert_eq!(
+            client_stream
+                .send(&ClientToServerMessage::ListWorkers)
+                .await,
+            Err(MessageError::SendFailed)
+        );
+        assert_eq!(
+            server_stream.receive::<ClientToServerMessage>().await,
+            Err(MessageError::StreamClosed)
+        );
+        assert_eq!(
+            client_stream.receive::<ClientToServerMessage>().await,
+            Err(MessageError::StreamClosed)
+        );
+
+        // Truncated messages are never received.
+        let (server_stream, client_stream) = duplex(1024);
+        let mut server_stream = MessageStream::new(server_stream);
+        let truncate = FaultInjection {
+            truncate: 1.0,
+            ..Default::default()
+        };
+        let mut client_stream = MessageStream::new(client_stream).with_fault_injection(truncate);
+        assert!(client_stream
+            .send(&ClientToServerMessage::ListWorkers)
+            .await
+            .is_err());
+        assert_eq!(
+            server_stream.receive::<ClientToServerMessage>().await,
+            Err(MessageError::StreamClosed)
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_frames() {
+        let (server_stream, mut client_stream) = duplex(64 * 1024);
+        let mut stream = MessageStream::new(server_stream);
+        stream.negotiate_format(&crate::messages::version::capabilities());
+        assert_eq!(stream.format(), WireFormat::BinaryFrames);
+
+        // An unknown message is skipped by its length.
+        let unknown = ciborium::Value::Map(vec![("FancyNewRequest".into(), 42.into())]);
+        let mut frame = Vec::new();
+        ciborium::into_writer(&unknown, &mut frame).unwrap();
+        client_stream
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .await
+            .unwrap();
+        client_stream.write_all(&frame).await.unwrap();
+        assert_eq!(
+            stream.receive::<ClientToServerMessage>().await,
+            Err(MessageError::UnknownMessage)
+        );
+
+        // Binary payloads are not encoded as Base64.
+        let mut client_stream = MessageStream::new(client_stream);
+        client_stream.negotiate_format(&crate::messages::version::capabilities());
+        let message = ClientToServerMessage::JobInput {
+            job_id: 7,
+            data: vec![255; 1000],
+        };
+        client_stream.send(&message).await.unwrap();
+        assert_eq!(stream.receive::<ClientToServerMessage>().await, Ok(message));
+        assert!(client_stream.traffic().sent() < 1100);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut rate_limit = RateLimit::new(1000);
+
+        // One second worth of bytes is sent right away.
+        assert!(rate_limit.acquire(600).is_zero());
+        assert!(rate_limit.acquire(300).is_zero());
+
+        // Larger writes wait until the bucket has been refilled.
+        let delay = rate_limit.acquire(1100);
+        assert!(delay > Duration::from_millis(900) && delay <= Duration::from_secs(1));
+    }
 }
@@ -0,0 +1,235 @@
+//! Version information exchanged during the hello/welcome handshake.
+
+use std::collections::BTreeSet;
+
+/// Version of this Kueue build, as given in `Cargo.toml`.
+pub const KUEUE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Capability: Unknown messages are skipped instead of closing the connection.
+/// Peers with this capability can be sent newer message variants safely.
+pub const CAP_SKIP_UNKNOWN_MESSAGES: &str = "skip-unknown-messages";
+
+/// Capability: Interactive jobs with a terminal relayed through the server.
+pub const CAP_INTERACTIVE_JOBS: &str = "interactive-jobs";
+
+/// Capability: TCP tunnels to ports opened by running jobs.
+pub const CAP_PORT_FORWARDING: &str = "port-forwarding";
+
+/// Capability: Announcements (message of the day and broadcasts) are printed.
+pub const CAP_ANNOUNCEMENTS: &str = "announcements";
+
+/// Capability: Workers partition their capacity into named resource pools.
+pub const CAP_RESOURCE_POOLS: &str = "resource-pools";
+
+/// Capability: Submission receipts with a validation report are printed.
+pub const CAP_SUBMISSION_RECEIPTS: &str = "submission-receipts";
+
+/// Capability: Workers regularly report their jobs to detect diverging views.
+pub const CAP_JOB_RECONCILIATION: &str = "job-reconciliation";
+
+/// Capability: All jobs of a submission group can be observed at once.
+pub const CAP_JOB_GROUPS: &str = "job-groups";
+
+/// Capability: Workers report the results of their self-test.
+pub const CAP_WORKER_SELF_TEST: &str = "worker-self-test";
+
+/// Capability: Live connections can be listed by administrators.
+pub const CAP_CONNECTION_INFO: &str = "connection-info";
+
+/// Capability: Scheduling decisions about a job can be shown by administrators.
+pub const CAP_SCHEDULING_TRACE: &str = "scheduling-trace";
+
+/// Capability: Connected workers can be renamed by administrators.
+pub const CAP_WORKER_RENAMING: &str = "worker-renaming";
+
+/// Capability: Encrypted job payloads are relayed between client and worker.
+pub const CAP_ENCRYPTED_PAYLOADS: &str = "encrypted-payloads";
+
+/// Capability: Workers assign GPU devices to jobs that require them.
+pub const CAP_GPU_SCHEDULING: &str = "gpu-scheduling";
+
+/// Capability: Workers pin jobs with core binding to whole physical cores.
+pub const CAP_CORE_BINDING: &str = "core-binding";
+
+/// Capability: Workers apply process limits (rlimits) of jobs.
+pub const CAP_PROCESS_LIMITS: &str = "process-limits";
+
+/// Capability: Jobs can be accounted to projects, reported by `kueue stats`.
+pub const CAP_PROJECTS: &str = "projects";
+
+/// Capability: After the hello/welcome handshake, messages are sent as
+/// length-prefixed CBOR frames instead of concatenated JSON values.
+pub const CAP_BINARY_FRAMES: &str = "binary-frames";
+
+/// Capability: Administrators can drain and reserve workers, save database
+/// snapshots, change the log level, and show the audit log of admin actions.
+pub const CAP_ADMIN_COMMANDS: &str = "admin-commands";
+
+/// Capability: Pending jobs can be boosted with `kueue boost`.
+pub const CAP_JOB_BOOST: &str = "job-boost";
+
+/// Capability: Removing a job can cancel or orphan all jobs depending on it.
+pub const CAP_DEPENDENCY_CHAINS: &str = "dependency-chains";
+
+/// Capability: The outputs of two jobs can be compared with `kueue diff`.
+pub const CAP_JOB_DIFF: &str = "job-diff";
+
+/// Capability: The output of running jobs can be followed with `kueue log -f`.
+pub const CAP_LOG_FOLLOW: &str = "log-follow";
+
+/// Capability: The server reports idle resources with `kueue stats --idle`.
+pub const CAP_IDLE_REPORT: &str = "idle-report";
+
+/// Capability: Running jobs can be stopped cleanly and moved to another
+/// worker with `kueue migrate`.
+pub const CAP_JOB_MIGRATION: &str = "job-migration";
+
+/// Capability: Jobs can select a quality of service class with `--qos`.
+pub const CAP_QOS_CLASSES: &str = "qos-classes";
+
+/// Capability: Reading requests may require authentication, e.g., by the
+/// read-only observer role.
+pub const CAP_OBSERVER_ROLE: &str = "observer-role";
+
+/// Capability: Results of finished jobs can be sent in segments.
+pub const CAP_CHUNKED_RESULTS: &str = "chunked-results";
+
+/// Capability: Removed jobs can be given a reason with `--reason`.
+pub const CAP_CANCEL_REASONS: &str = "cancel-reasons";
+
+/// Capability: Outputs of jobs can be downloaded with `kueue fetch`.
+pub const CAP_FETCH_OUTPUTS: &str = "fetch-outputs";
+
+/// Capability: Jobs can require worker labels with `--require`.
+pub const CAP_WORKER_LABELS: &str = "worker-labels";
+
+/// Capability: The server publishes job templates.
+pub const CAP_JOB_TEMPLATES: &str = "job-templates";
+
+/// Capability: The server keeps the accounting of finished jobs per user.
+pub const CAP_ACCOUNTING: &str = "accounting";
+
+/// Capability: Jobs can prefer the worker of another job (`--prefer-worker-of`).
+pub const CAP_WORKER_AFFINITY: &str = "worker-affinity";
+
+/// Capability: Job IDs can be reserved before issuing a job, so that
+/// encrypted payloads are bound to the ID of their job.
+pub const CAP_RESERVED_JOB_IDS: &str = "reserved-job-ids";
+
+/// Optional protocol features supported by this build. Capabilities are
+/// exchanged during the hello/welcome handshake, so that new features can be
+/// used only with peers that support them. This allows rolling upgrades where
+/// old and new versions are connected at the same time.
+pub const CAPABILITIES: &[&str] = &[
+    CAP_SKIP_UNKNOWN_MESSAGES,
+    CAP_INTERACTIVE_JOBS,
+    CAP_PORT_FORWARDING,
+    CAP_ANNOUNCEMENTS,
+    CAP_RESOURCE_POOLS,
+    CAP_SUBMISSION_RECEIPTS,
+    CAP_JOB_RECONCILIATION,
+    CAP_JOB_GROUPS,
+    CAP_WORKER_SELF_TEST,
+    CAP_CONNECTION_INFO,
+    CAP_SCHEDULING_TRACE,
+    CAP_WORKER_RENAMING,
+    CAP_ENCRYPTED_PAYLOADS,
+    CAP_GPU_SCHEDULING,
+    CAP_CORE_BINDING,
+    CAP_PROCESS_LIMITS,
+    CAP_PROJECTS,
+    CAP_BINARY_FRAMES,
+    CAP_ADMIN_COMMANDS,
+    CAP_JOB_BOOST,
+    CAP_DEPENDENCY_CHAINS,
+    CAP_JOB_DIFF,
+    CAP_LOG_FOLLOW,
+    CAP_IDLE_REPORT,
+    CAP_JOB_MIGRATION,
+    CAP_QOS_CLASSES,
+    CAP_OBSERVER_ROLE,
+    CAP_CHUNKED_RESULTS,
+    CAP_CANCEL_REASONS,
+    CAP_FETCH_OUTPUTS,
+    CAP_WORKER_LABELS,
+    CAP_JOB_TEMPLATES,
+    CAP_ACCOUNTING,
+    CAP_WORKER_AFFINITY,
+    CAP_RESERVED_JOB_IDS,
+];
+
+/// Returns the set of capabilities supported by this build.
+pub fn capabilities() -> BTreeSet<String> {
+    CAPABILITIES.iter().map(|cap| cap.to_string()).collect()
+}
+
+/// Splits a semantic version string into (major, minor, patch).
+/// Missing or malformed components are returned as `None`.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    // Ignore pre-release and build metadata, e.g. "1.2.3-beta+abc".
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().unwrap_or("0").parse().ok()?;
+    let patch = parts.next().unwrap_or("0").parse().ok()?;
+    Some((major, minor, patch))
+}
+
+/// Returns `true` if two Kueue versions can be expected to understand each
+/// other's messages. Following semantic versioning, this is the case if the
+/// major versions match. For pre-1.0 releases, the minor versions must match
+/// as well, since breaking changes are indicated by the minor version.
+pub fn versions_compatible(version_a: &str, version_b: &str) -> bool {
+    match (parse_version(version_a), parse_version(version_b)) {
+        (Some((0, minor_a, _)), Some((0, minor_b, _))) => minor_a == minor_b,
+        (Some((major_a, _, _)), Some((major_b, _, _))) => major_a == major_b,
+        _ => false, // unknown versions are never compatible
+    }
+}
+
+/// Logs a warning if the `remote` version is not compatible with this build.
+/// Returns `true` if the versions are compatible.
+pub fn check_remote_version(remote_name: &str, remote_version: &str) -> bool {
+    if remote_version.is_empty() {
+        log::warn!(
+            "Version of {} is unknown, it predates the version exchange! \
+            Please update all Kueue components to the same version.",
+            remote_name
+        );
+        false
+    } else if versions_compatible(KUEUE_VERSION, remote_version) {
+        if remote_version != KUEUE_VERSION {
+            log::debug!(
+                "Version of {} ({}) differs from local version ({}) but is compatible.",
+                remote_name,
+                remote_version,
+                KUEUE_VERSION
+            );
+        }
+        true
+    } else {
+        log::warn!(
+            "Version of {} ({}) is not compatible with local version ({})! \
+            Please update all Kueue components to the same version.",
+            remote_name,
+            remote_version,
+            KUEUE_VERSION
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatibility_window() {
+        assert!(versions_compatible("0.19.0", "0.19.3"));
+        assert!(!versions_compatible("0.19.0", "0.20.0"));
+        assert!(versions_compatible("1.2.0", "1.5.1"));
+        assert!(!versions_compatible("1.2.0", "2.0.0"));
+        assert!(versions_compatible("1.0.0-beta", "1.1.0"));
+        assert!(!versions_compatible("unknown", "0.19.0"));
+    }
+}
@@ -1,17 +1,32 @@
 use crate::{
     config::Config,
-    messages::stream::MessageStream,
-    messages::{ClientToServerMessage, ServerToClientMessage},
-    server::shared_state::Manager,
-    structs::{JobInfo, JobStatus},
+    crypto::sha256_hex,
+    messages::stream::{MessageError, MessageStream},
+    messages::{
+        chunks::CHUNK_SIZE,
+        version::{
+            CAP_ANNOUNCEMENTS, CAP_LOG_FOLLOW, CAP_PORT_FORWARDING, CAP_SUBMISSION_RECEIPTS,
+        },
+        ClientToServerMessage, ServerToClientMessage, ServerToWorkerMessage,
+    },
+    server::{
+        shared_state::{job::LogFollower, Manager, TunnelRoute},
+        submission_hook::run_submission_hook,
+    },
+    structs::{
+        reserve_job_id, AuditEntry, DependentsAction, JobInfo, JobMigration, JobStatus,
+        OutputStream, WorkerAffinity,
+    },
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use sha2::{Digest, Sha256};
 use std::{
     cmp::max,
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
     sync::{Arc, RwLock},
 };
 use tokio::{
@@ -22,25 +37,52 @@ use tokio_util::sync::CancellationToken;
 
 pub struct ClientConnection<Stream> {
     stream: MessageStream<Stream>,
+    /// Network address of the client, as seen by the server.
+    remote_address: String,
+    /// Optional protocol features supported by the client.
+    capabilities: BTreeSet<String>,
     config: Arc<RwLock<Config>>,
     manager: Arc<RwLock<Manager>>,
     cancel: CancellationToken,
     job_updated_tx: Sender<u64>,
     job_updated_rx: Receiver<u64>,
+    /// Messages forwarded from other connections to the remote client.
+    forward_tx: Sender<ServerToClientMessage>,
+    forward_rx: Receiver<ServerToClientMessage>,
+    /// Interactive jobs this client is attached to.
+    attached_jobs: BTreeSet<u64>,
+    /// Job IDs reserved by the client for jobs it is about to issue.
+    reserved_job_ids: BTreeSet<u64>,
+    /// Open tunnels by client tunnel ID: server tunnel ID and worker channel.
+    tunnels: BTreeMap<u64, (u64, Sender<ServerToWorkerMessage>)>,
     authenticated: bool,
+    /// Authenticated for administrative commands.
+    admin: bool,
+    /// Authenticated with the observer secret, i.e., read-only.
+    observer: bool,
     salt: String,
     connection_closed: bool,
 }
 
+/// Expected answer to an authentication challenge: Base64(Sha256(secret + salt)).
+fn auth_response(secret: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((secret.to_string() + salt).into_bytes());
+    general_purpose::STANDARD_NO_PAD.encode(hasher.finalize())
+}
+
 impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
     /// Construct a new ClientConnection.
     pub fn new(
         stream: MessageStream<Stream>,
+        remote_address: String,
+        capabilities: BTreeSet<String>,
         config: Arc<RwLock<Config>>,
         manager: Arc<RwLock<Manager>>,
         cancel: CancellationToken,
     ) -> Self {
         let (job_updated_tx, job_updated_rx) = channel::<u64>(100);
+        let (forward_tx, forward_rx) = channel::<ServerToClientMessage>(64);
 
         // Salt is generated for each client connection.
         let salt: String = thread_rng()
@@ -51,12 +93,21 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
         ClientConnection {
             stream,
+            remote_address,
+            capabilities,
             config,
             manager,
             cancel,
             job_updated_tx,
             job_updated_rx,
+            forward_tx,
+            forward_rx,
+            attached_jobs: BTreeSet::new(),
+            reserved_job_ids: BTreeSet::new(),
+            tunnels: BTreeMap::new(),
             authenticated: false,
+            admin: false,
+            observer: false,
             salt,
             connection_closed: false,
         }
@@ -66,6 +117,22 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
     pub async fn run(&mut self) {
         // Hello/Welcome messages are already exchanged at this point.
 
+        // Send message of the day and active broadcasts.
+        if self.capabilities.contains(CAP_ANNOUNCEMENTS) {
+            let announcements = self
+                .manager
+                .write()
+                .unwrap()
+                .subscribe_announcements(self.forward_tx.clone());
+            for message in announcements {
+                if let Err(e) = self.stream.send(&message).await {
+                    log::error!("Failed to send announcement: {}", e);
+                    self.connection_closed = true; // end client session
+                    break;
+                }
+            }
+        }
+
         while !self.connection_closed {
             tokio::select! {
                 // Read and handle incoming messages.
@@ -77,6 +144,17 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                                 self.connection_closed = true; // end client session
                             }
                         }
+                        Err(MessageError::UnknownMessage) => {
+                            // Let the client know that we cannot serve the request.
+                            let message = ServerToClientMessage::RequestResponse {
+                                success: false,
+                                text: "Request not supported by the server!".into(),
+                            };
+                            if let Err(e) = self.stream.send(&message).await {
+                                log::error!("Failed to send response: {}", e);
+                                self.connection_closed = true; // end client session
+                            }
+                        }
                         Err(e) => {
                             log::error!("Error while receiving message: {}", e);
                             self.connection_closed = true; // end client session
@@ -88,9 +166,18 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                     // Get job.
                     let job = self.manager.read().unwrap().get_job(job_id);
 
+                    // Deliver pending terminal output before the update,
+                    // so that clients see all output before the job ends.
+                    while let Ok(message) = self.forward_rx.try_recv() {
+                        if let Err(e) = self.stream.send(&message).await {
+                            log::error!("Failed to forward message: {}", e);
+                            self.connection_closed = true; // end client session
+                        }
+                    }
+
                     if let Some(job) = job {
                         // Send job update to client.
-                        let message = ServerToClientMessage::JobUpdated(job.lock().unwrap().info.clone());
+                        let message = ServerToClientMessage::JobUpdated(job.lock().unwrap().info.without_env_snapshot());
                         if let Err(e) = self.stream.send(&message).await {
                             log::error!("Failed to send job notification: {}", e);
                             self.connection_closed = true; // end client session
@@ -100,6 +187,16 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                         self.connection_closed = true; // end client session
                     };
                 }
+                // Or, forward messages from other connections to the client.
+                Some(message) = self.forward_rx.recv() => {
+                    if let ServerToClientMessage::CloseTunnel { tunnel_id } = &message {
+                        self.tunnels.remove(tunnel_id); // closed by the worker
+                    }
+                    if let Err(e) = self.stream.send(&message).await {
+                        log::error!("Failed to forward message: {}", e);
+                        self.connection_closed = true; // end client session
+                    }
+                }
                 // Or, close the connection if server is shutting down.
                 _ = self.cancel.cancelled() => {
                     log::info!("Closing connection to client!");
@@ -110,6 +207,42 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                 }
             }
         }
+
+        // Interactive jobs cannot continue without their client.
+        self.kill_attached_jobs().await;
+        self.close_tunnels().await;
+    }
+
+    /// Close all tunnels opened by this client.
+    async fn close_tunnels(&mut self) {
+        for (_, (tunnel_id, worker_tx)) in std::mem::take(&mut self.tunnels) {
+            self.manager.write().unwrap().remove_tunnel(tunnel_id);
+            let _ = worker_tx
+                .send(ServerToWorkerMessage::CloseTunnel { tunnel_id })
+                .await;
+        }
+    }
+
+    /// Cancel and kill all interactive jobs attached to this client.
+    async fn kill_attached_jobs(&mut self) {
+        for job_id in std::mem::take(&mut self.attached_jobs) {
+            let result = {
+                let mut manager = self.manager.write().unwrap();
+                let reason = Some("The attached client disconnected.".into());
+                let result = manager.cancel_job(job_id, true, reason);
+                manager.resolve_dependencies();
+                result
+            };
+            match result {
+                Ok(Some(tx)) => {
+                    if let Err(e) = tx.send(job_id).await {
+                        log::error!("Failed to kill interactive job {}: {}", job_id, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("Interactive job {} not canceled: {}", job_id, e),
+            }
+        }
     }
 
     /// Dispatch incoming message based on variant.
@@ -118,6 +251,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
             ClientToServerMessage::AuthRequest => self.on_auth_request().await,
             ClientToServerMessage::AuthResponse(response) => self.on_auth_response(response).await,
             ClientToServerMessage::IssueJob(job_info) => self.on_issue_job(job_info).await,
+            ClientToServerMessage::ReserveJobId => self.on_reserve_job_id().await,
             ClientToServerMessage::ListJobs {
                 num_jobs,
                 pending,
@@ -134,13 +268,89 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
             }
             ClientToServerMessage::ShowJob { job_id } => self.on_show_job(job_id).await,
             ClientToServerMessage::ObserveJob { job_id } => self.on_observe_job(job_id).await,
-            ClientToServerMessage::RemoveJob { job_id, kill } => {
-                self.on_remove_job(job_id, kill).await
-            }
+            ClientToServerMessage::ObserveGroup { group } => self.on_observe_group(group).await,
+            ClientToServerMessage::RemoveJob {
+                job_id,
+                kill,
+                dependents,
+                reason,
+            } => self.on_remove_job(job_id, kill, dependents, reason).await,
             ClientToServerMessage::CleanJobs { all } => self.on_clean_jobs(all).await,
             ClientToServerMessage::ListWorkers => self.on_list_workers().await,
             ClientToServerMessage::ShowWorker { worker_id } => self.on_show_worker(worker_id).await,
             ClientToServerMessage::ListResources => self.on_list_resources().await,
+            ClientToServerMessage::AttachJob { job_id } => self.on_attach_job(job_id).await,
+            ClientToServerMessage::JobInput { job_id, data } => {
+                self.forward_to_worker(job_id, ServerToWorkerMessage::JobInput { job_id, data })
+                    .await
+            }
+            ClientToServerMessage::ResizeTerminal { job_id, rows, cols } => {
+                self.forward_to_worker(
+                    job_id,
+                    ServerToWorkerMessage::ResizeTerminal { job_id, rows, cols },
+                )
+                .await
+            }
+            ClientToServerMessage::OpenTunnel {
+                job_id,
+                tunnel_id,
+                port,
+            } => self.on_open_tunnel(job_id, tunnel_id, port).await,
+            ClientToServerMessage::TunnelData { tunnel_id, data } => {
+                self.on_tunnel_data(tunnel_id, data).await
+            }
+            ClientToServerMessage::CloseTunnel { tunnel_id } => {
+                self.on_close_tunnel(tunnel_id).await
+            }
+            ClientToServerMessage::SetMotd { text } => self.on_set_motd(text).await,
+            ClientToServerMessage::Broadcast {
+                text,
+                expires_in_minutes,
+            } => self.on_broadcast(text, expires_in_minutes).await,
+            ClientToServerMessage::RemoveWorker {
+                worker_id,
+                fail_jobs,
+                cooldown_minutes,
+            } => {
+                self.on_remove_worker(worker_id, fail_jobs, cooldown_minutes)
+                    .await
+            }
+            ClientToServerMessage::ListConnections => self.on_list_connections().await,
+            ClientToServerMessage::ShowSchedulingTrace { job_id } => {
+                self.on_show_scheduling_trace(job_id).await
+            }
+            ClientToServerMessage::RenameWorker {
+                worker_id,
+                worker_name,
+            } => self.on_rename_worker(worker_id, worker_name).await,
+            ClientToServerMessage::GetProjectUsage => self.on_get_project_usage().await,
+            ClientToServerMessage::GetIdleResources => self.on_get_idle_resources().await,
+            ClientToServerMessage::ListTemplates => self.on_list_templates().await,
+            ClientToServerMessage::GetAccounting { user, since, until } => {
+                self.on_get_accounting(user, since, until).await
+            }
+            ClientToServerMessage::DrainWorker { worker_id, drain } => {
+                self.on_drain_worker(worker_id, drain).await
+            }
+            ClientToServerMessage::ReserveWorker { worker_id, user } => {
+                self.on_reserve_worker(worker_id, user).await
+            }
+            ClientToServerMessage::MigrateJob { job_id, migration } => {
+                self.on_migrate_job(job_id, migration).await
+            }
+            ClientToServerMessage::SaveSnapshot { path } => self.on_save_snapshot(path).await,
+            ClientToServerMessage::SetLogLevel { level } => self.on_set_log_level(level).await,
+            ClientToServerMessage::ShowAuditLog => self.on_show_audit_log().await,
+            ClientToServerMessage::BoostJob {
+                job_id,
+                to_front,
+                user,
+            } => self.on_boost_job(job_id, to_front, user).await,
+            ClientToServerMessage::DiffJobs { job_a, job_b } => {
+                self.on_diff_jobs(job_a, job_b).await
+            }
+            ClientToServerMessage::FollowJob { job_id } => self.on_follow_job(job_id).await,
+            ClientToServerMessage::FetchJob { job_id } => self.on_fetch_job(job_id).await,
             ClientToServerMessage::Bye => {
                 log::trace!("Connection closed by client!");
                 self.connection_closed = true;
@@ -156,9 +366,14 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
         } else {
             // We are nice to the client and let
             // them know why their request failed.
+            let text = if self.observer {
+                "Observers cannot submit, modify, or remove anything!"
+            } else {
+                "Not authenticated!"
+            };
             let message = ServerToClientMessage::RequestResponse {
                 success: false,
-                text: "Not authenticated!".into(),
+                text: text.into(),
             };
             self.stream.send(&message).await?;
             // Close connection with an error message.
@@ -166,6 +381,49 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
         }
     }
 
+    /// Returns error if the client is not allowed to read jobs and workers.
+    /// Servers with an observer secret only answer authenticated clients,
+    /// including observers.
+    async fn is_reader(&mut self) -> Result<()> {
+        let restricted = {
+            let config = self.config.read().unwrap();
+            config.common_settings.observer_secret.is_some()
+        };
+        if !restricted || self.authenticated || self.observer {
+            return Ok(());
+        }
+
+        let message = ServerToClientMessage::RequestResponse {
+            success: false,
+            text: "Not authenticated!".into(),
+        };
+        self.stream.send(&message).await?;
+        bail!("Client is not authenticated for reading!")
+    }
+
+    /// Returns error if client is not authenticated for administrative
+    /// commands. Each request is recorded in the audit log.
+    async fn is_admin(&mut self, action: String) -> Result<()> {
+        let entry = AuditEntry {
+            time: Utc::now(),
+            remote_address: self.remote_address.clone(),
+            action,
+            authorized: self.admin,
+        };
+        self.manager.write().unwrap().record_admin_action(entry);
+        if self.admin {
+            return Ok(());
+        }
+
+        self.is_authenticated().await?;
+        let message = ServerToClientMessage::RequestResponse {
+            success: false,
+            text: "Administrative commands require the admin secret!".into(),
+        };
+        self.stream.send(&message).await?;
+        bail!("Client is not authenticated for administrative commands!")
+    }
+
     /// Called upon receiving ClientToServerMessage::AuthRequest.
     async fn on_auth_request(&mut self) -> Result<()> {
         // Send salt to client.
@@ -178,24 +436,31 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::AuthResponse.
     async fn on_auth_response(&mut self, response: String) -> Result<()> {
-        // Calculate baseline result.
-        let salted_secret = self
-            .config
-            .read()
-            .unwrap()
-            .common_settings
-            .shared_secret
-            .clone()
-            + &self.salt;
-        let salted_secret = salted_secret.into_bytes();
-        let mut hasher = Sha256::new();
-        hasher.update(salted_secret);
-        let baseline = hasher.finalize().to_vec();
-        let baseline = general_purpose::STANDARD_NO_PAD.encode(baseline);
+        // Calculate baseline results. Without admin secret, the shared
+        // secret also authenticates administrative commands.
+        let (baseline, admin_baseline, observer_baseline) = {
+            let config = self.config.read().unwrap();
+            let settings = &config.common_settings;
+            let baseline = auth_response(&settings.shared_secret, &self.salt);
+            let optional_baseline = |secret: &Option<String>| {
+                secret
+                    .as_ref()
+                    .map(|secret| auth_response(secret, &self.salt))
+            };
+            let admin_baseline = optional_baseline(&settings.admin_secret);
+            let observer_baseline = optional_baseline(&settings.observer_secret);
+            (baseline, admin_baseline, observer_baseline)
+        };
 
         // Update status and send reply.
-        if response == baseline {
+        if admin_baseline.as_ref() == Some(&response) {
             self.authenticated = true;
+            self.admin = true;
+        } else if response == baseline {
+            self.authenticated = true;
+            self.admin = admin_baseline.is_none();
+        } else if observer_baseline.as_ref() == Some(&response) {
+            self.observer = true;
         } else {
             // After failed attempt, change the salt.
             self.salt = thread_rng()
@@ -205,15 +470,104 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                 .collect();
         }
 
-        let message = ServerToClientMessage::AuthAccepted(self.authenticated);
+        let message = ServerToClientMessage::AuthAccepted(self.authenticated || self.observer);
         self.stream.send(&message).await?;
         Ok(())
     }
 
+    /// Called upon receiving ClientToServerMessage::ReserveJobId.
+    async fn on_reserve_job_id(&mut self) -> Result<()> {
+        self.is_authenticated().await?;
+
+        let job_id = reserve_job_id();
+        self.reserved_job_ids.insert(job_id);
+        self.stream
+            .send(&ServerToClientMessage::JobIdReserved(job_id))
+            .await?;
+        Ok(())
+    }
+
     /// Called upon receiving ClientToServerMessage::IssueJob.
-    async fn on_issue_job(&mut self, job_info: JobInfo) -> Result<()> {
+    async fn on_issue_job(&mut self, mut job_info: JobInfo) -> Result<()> {
         self.is_authenticated().await?;
 
+        // Keep the ID of the job, if it has been reserved by this client.
+        let reserved_id = self
+            .reserved_job_ids
+            .remove(&job_info.job_id)
+            .then_some(job_info.job_id);
+
+        // Protect the server from running out of memory.
+        if self.manager.read().unwrap().refuses_new_jobs() {
+            let message = ServerToClientMessage::RejectJob {
+                job_info,
+                reason: "The server exceeds its memory or job limit and does not accept \
+                    new jobs at the moment. Please try again later."
+                    .to_string(),
+            };
+            self.stream.send(&message).await?;
+            return Ok(());
+        }
+
+        // Adjust job according to the server's routing rules.
+        let routed = self
+            .manager
+            .read()
+            .unwrap()
+            .apply_routing_rules(&mut job_info);
+        if let Err(e) = routed {
+            log::error!("{}", e);
+            let message = ServerToClientMessage::RejectJob {
+                job_info,
+                reason: e.to_string(),
+            };
+            self.stream.send(&message).await?;
+            return Ok(());
+        }
+
+        // Let the site's submission hook adjust or reject the job.
+        let (hook, hook_timeout) = {
+            let config = self.config.read().unwrap();
+            let settings = &config.server_settings;
+            (
+                settings.submission_hook.clone(),
+                settings.submission_hook_timeout_seconds,
+            )
+        };
+        if let Some(hook) = hook {
+            if let Err(e) = run_submission_hook(&hook, hook_timeout, &mut job_info).await {
+                log::debug!("Submission hook rejected job: {}", e);
+                let message = ServerToClientMessage::RejectJob {
+                    job_info,
+                    reason: e.to_string(),
+                };
+                self.stream.send(&message).await?;
+                return Ok(());
+            }
+        }
+
+        // Check the job's project and its quota.
+        let checked = self.manager.read().unwrap().check_project(&job_info);
+        if let Err(e) = checked {
+            let message = ServerToClientMessage::RejectJob {
+                job_info,
+                reason: e.to_string(),
+            };
+            self.stream.send(&message).await?;
+            return Ok(());
+        }
+
+        // Apply the job's QoS class.
+        let applied = self.manager.read().unwrap().apply_qos_class(&mut job_info);
+        if let Err(e) = applied {
+            let message = ServerToClientMessage::RejectJob {
+                job_info,
+                reason: e.to_string(),
+            };
+            self.stream.send(&message).await?;
+            return Ok(());
+        }
+
         // Check if job can ever be processed. (job slots)
         if job_info.worker_resources.job_slots
             > self
@@ -232,6 +586,12 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
             return Ok(());
         }
 
+        // Resources not configured on the server are provided by workers.
+        self.manager
+            .read()
+            .unwrap()
+            .assign_named_resources(&mut job_info);
+
         // Check if job can ever be processed. (global resources)
         let mut reject_reason = None;
         if let Some(job_global_resources) = &job_info.global_resources {
@@ -261,6 +621,24 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                 ));
             }
         };
+        // Check if all dependencies are known.
+        if reject_reason.is_none() {
+            let manager = self.manager.read().unwrap();
+            for dependency in &job_info.dependencies {
+                if manager.get_job(dependency.job_id).is_none() {
+                    reject_reason =
+                        Some(format!("Dependency not found: job {}", dependency.job_id));
+                    break;
+                }
+            }
+            if let Some(WorkerAffinity::WorkerOf(job_id)) = job_info.affinity {
+                if manager.get_job(job_id).is_none() {
+                    reject_reason =
+                        Some(format!("Job for worker affinity not found: job {}", job_id));
+                }
+            }
+        }
+
         if let Some(reason) = reject_reason {
             // Send reject to client.
             let message = ServerToClientMessage::RejectJob { job_info, reason };
@@ -270,21 +648,59 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
         // If we passed all the checks, we can process the job request.
 
-        // Add new job.
-        let job_info = {
+        // Add new job, unless too many jobs are pending.
+        let added = {
             let mut manager = self.manager.write().unwrap();
-            let job = manager.add_new_job(job_info);
-            let job_info = job.lock().unwrap().info.clone();
+            match manager.check_pending_job_limits(&job_info) {
+                Ok(warnings) => {
+                    // Suggest or apply resources based on similar jobs in the past.
+                    let mut job_info = job_info;
+                    let suggested = manager.suggest_resources(&job_info);
+                    if let Some(resources) = &suggested {
+                        if job_info.auto_resources {
+                            job_info.worker_resources = resources.clone();
+                        }
+                    }
 
-            // Notify workers.
-            manager.notify_new_jobs.notify_waiters();
+                    let job = match reserved_id {
+                        Some(job_id) => manager.add_new_job_with_reserved_id(job_info, job_id),
+                        None => manager.add_new_job(job_info),
+                    };
+                    let mut job_lock = job.lock().unwrap();
+                    if suggested.as_ref() != Some(&job_lock.info.worker_resources) {
+                        job_lock.info.suggested_resources = suggested;
+                    }
+                    let job_info = job_lock.info.clone();
+                    drop(job_lock);
 
-            job_info
+                    // Notify workers.
+                    manager.notify_new_jobs.notify_waiters();
+
+                    let mut receipt = manager.job_receipt(&job_info);
+                    receipt.warnings.extend(warnings);
+                    Ok((job_info, receipt))
+                }
+                Err(e) => Err((job_info, e.to_string())),
+            }
+        };
+        let (job_info, receipt) = match added {
+            Ok(added) => added,
+            Err((job_info, reason)) => {
+                // Send reject to client.
+                let message = ServerToClientMessage::RejectJob { job_info, reason };
+                self.stream.send(&message).await?;
+                return Ok(());
+            }
         };
 
         log::debug!("New job {} received from client!", job_info.job_id);
 
-        // Send response to client.
+        // Send validation report and response to client.
+        if self.capabilities.contains(CAP_SUBMISSION_RECEIPTS) {
+            self.stream
+                .send(&ServerToClientMessage::JobReceipt(receipt))
+                .await?;
+        }
         self.stream
             .send(&ServerToClientMessage::AcceptJob(job_info))
             .await?;
@@ -292,6 +708,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
     }
 
     /// Called upon receiving ClientToServerMessage::ListJobs.
+    #[allow(clippy::too_many_arguments)]
     async fn on_list_jobs(
         &mut self,
         num_jobs: u64,
@@ -302,8 +719,12 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
         failed: bool,
         canceled: bool,
     ) -> Result<()> {
+        self.is_reader().await?;
         // Get job and worker lists.
         let mut job_infos = self.manager.read().unwrap().get_all_job_infos();
+        for job_info in &mut job_infos {
+            job_info.env_snapshot = None; // only shown to authenticated clients
+        }
 
         // Count total number of pending/offered/running/etc jobs.
         let jobs_pending = job_infos
@@ -428,15 +849,23 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::ShowJob.
     async fn on_show_job(&mut self, job_id: u64) -> Result<()> {
+        self.is_reader().await?;
         // Get job.
         let job = self.manager.read().unwrap().get_job(job_id);
 
         let message = if let Some(job) = job {
             let job_lock = job.lock().unwrap();
+            let (stdout_text, stderr_text) = job_lock.outputs();
+            // The environment may hold secrets of the job's owner.
+            let job_info = if self.authenticated {
+                job_lock.info.clone()
+            } else {
+                job_lock.info.without_env_snapshot()
+            };
             ServerToClientMessage::JobInfo {
-                job_info: job_lock.info.clone(),
-                stdout_text: job_lock.stdout_text.clone(),
-                stderr_text: job_lock.stderr_text.clone(),
+                job_info,
+                stdout_text,
+                stderr_text,
             }
         } else {
             ServerToClientMessage::RequestResponse {
@@ -450,6 +879,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::ObserveJob.
     async fn on_observe_job(&mut self, job_id: u64) -> Result<()> {
+        self.is_reader().await?;
         // Get job.
         let job = self.manager.read().unwrap().get_job(job_id);
 
@@ -459,7 +889,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
             job_lock.observers.push(self.job_updated_tx.clone());
 
             // Send first update immediately (also as confirmation).
-            ServerToClientMessage::JobUpdated(job_lock.info.clone())
+            ServerToClientMessage::JobUpdated(job_lock.info.without_env_snapshot())
         } else {
             ServerToClientMessage::RequestResponse {
                 success: false,
@@ -470,12 +900,314 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
         Ok(())
     }
 
+    /// Called upon receiving ClientToServerMessage::ObserveGroup.
+    async fn on_observe_group(&mut self, group: String) -> Result<()> {
+        self.is_reader().await?;
+        // Register as an observer of the group.
+        let job_infos = self
+            .manager
+            .write()
+            .unwrap()
+            .observe_group(&group, self.job_updated_tx.clone())
+            .iter()
+            .map(JobInfo::without_env_snapshot)
+            .collect();
+
+        // Send current jobs of the group (also as confirmation).
+        let message = ServerToClientMessage::GroupJobs { group, job_infos };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::FollowJob.
+    async fn on_follow_job(&mut self, job_id: u64) -> Result<()> {
+        self.is_reader().await?;
+        let mut worker_tx = None;
+        let message = {
+            let manager = self.manager.read().unwrap();
+            match manager.get_job(job_id) {
+                Some(job) => {
+                    let mut job_lock = job.lock().unwrap();
+                    if job_lock.info.interactive {
+                        ServerToClientMessage::RequestResponse {
+                            success: false,
+                            text: "Output of interactive jobs cannot be followed!".into(),
+                        }
+                    } else if job_lock.info.encrypted_payload.is_some() {
+                        ServerToClientMessage::RequestResponse {
+                            success: false,
+                            text: "Output of encrypted jobs cannot be followed!".into(),
+                        }
+                    } else {
+                        // Register as a follower and an observer.
+                        let follower = LogFollower::new(self.forward_tx.clone());
+                        job_lock.log_followers.push(follower);
+                        job_lock.observers.push(self.job_updated_tx.clone());
+                        let message =
+                            ServerToClientMessage::JobUpdated(job_lock.info.without_env_snapshot());
+
+                        // Let the worker forward the output of the running job.
+                        let worker = job_lock
+                            .info
+                            .status
+                            .is_running()
+                            .then_some(job_lock.worker_id)
+                            .flatten()
+                            .and_then(|worker_id| manager.get_worker(worker_id))
+                            .and_then(|worker| worker.upgrade());
+                        drop(job_lock); // lock order: worker before job
+                        if let Some(worker) = worker {
+                            let worker_lock = worker.lock().unwrap();
+                            if worker_lock.info.has_capability(CAP_LOG_FOLLOW) {
+                                worker_tx = Some(worker_lock.forward_tx.clone());
+                            }
+                        }
+
+                        // Send first update immediately (also as confirmation).
+                        message
+                    }
+                }
+                None => ServerToClientMessage::RequestResponse {
+                    success: false,
+                    text: "Job not found!".into(),
+                },
+            }
+        };
+        self.stream.send(&message).await?;
+
+        if let Some(worker_tx) = worker_tx {
+            let message = ServerToWorkerMessage::FollowJob {
+                job_id,
+                follow: true,
+            };
+            if worker_tx.send(message).await.is_err() {
+                log::debug!("Worker of job {} is gone!", job_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::AttachJob.
+    async fn on_attach_job(&mut self, job_id: u64) -> Result<()> {
+        self.is_authenticated().await?;
+
+        // Get job.
+        let job = self.manager.read().unwrap().get_job(job_id);
+
+        let mut buffered_output = Vec::new();
+        let message = if let Some(job) = job {
+            let mut job_lock = job.lock().unwrap();
+            if !job_lock.info.interactive {
+                ServerToClientMessage::RequestResponse {
+                    success: false,
+                    text: "Job is not interactive!".into(),
+                }
+            } else if job_lock.attached_client.is_some() {
+                ServerToClientMessage::RequestResponse {
+                    success: false,
+                    text: "Another client is already attached to the job!".into(),
+                }
+            } else {
+                // Attach and register as an observer.
+                job_lock.attached_client = Some(self.forward_tx.clone());
+                job_lock.observers.push(self.job_updated_tx.clone());
+                self.attached_jobs.insert(job_id);
+                buffered_output = std::mem::take(&mut job_lock.buffered_output);
+
+                // Send first update immediately (also as confirmation).
+                ServerToClientMessage::JobUpdated(job_lock.info.without_env_snapshot())
+            }
+        } else {
+            ServerToClientMessage::RequestResponse {
+                success: false,
+                text: "Job not found!".into(),
+            }
+        };
+        self.stream.send(&message).await?;
+
+        // Deliver output that has been produced before attaching.
+        if !buffered_output.is_empty() {
+            let message = ServerToClientMessage::JobOutput {
+                job_id,
+                data: buffered_output,
+            };
+            self.stream.send(&message).await?;
+        }
+        Ok(())
+    }
+
+    /// Forward terminal input or resize events of an attached job to its worker.
+    async fn forward_to_worker(
+        &mut self,
+        job_id: u64,
+        message: ServerToWorkerMessage,
+    ) -> Result<()> {
+        if !self.attached_jobs.contains(&job_id) {
+            log::warn!("Client sent terminal data for unattached job {}!", job_id);
+            return Ok(());
+        }
+
+        // Find the worker running the job.
+        let forward_tx = {
+            let manager = self.manager.read().unwrap();
+            let worker_id = manager
+                .get_job(job_id)
+                .and_then(|job| job.lock().unwrap().worker_id);
+            worker_id
+                .and_then(|worker_id| manager.get_worker(worker_id))
+                .and_then(|worker| worker.upgrade())
+                .map(|worker| worker.lock().unwrap().forward_tx.clone())
+        };
+
+        match forward_tx {
+            Some(forward_tx) => {
+                if forward_tx.send(message).await.is_err() {
+                    log::debug!("Worker of job {} is gone!", job_id);
+                }
+            }
+            None => log::debug!("Job {} is not running on a worker!", job_id),
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::OpenTunnel.
+    async fn on_open_tunnel(
+        &mut self,
+        job_id: u64,
+        client_tunnel_id: u64,
+        port: u16,
+    ) -> Result<()> {
+        self.is_authenticated().await?;
+
+        // Find the worker running the job.
+        let worker_tx = {
+            let manager = self.manager.read().unwrap();
+            match manager.get_job(job_id) {
+                Some(job) => {
+                    let job_lock = job.lock().unwrap();
+                    if !job_lock.info.status.is_running() {
+                        Err("Job is not running!")
+                    } else {
+                        let worker = job_lock
+                            .worker_id
+                            .and_then(|worker_id| manager.get_worker(worker_id))
+                            .and_then(|worker| worker.upgrade());
+                        drop(job_lock); // lock order: worker before job
+                        match worker {
+                            Some(worker) => {
+                                let worker_lock = worker.lock().unwrap();
+                                if worker_lock.info.has_capability(CAP_PORT_FORWARDING) {
+                                    Ok(worker_lock.forward_tx.clone())
+                                } else {
+                                    Err("Worker does not support port forwarding!")
+                                }
+                            }
+                            None => Err("Worker no longer available!"),
+                        }
+                    }
+                }
+                None => Err("Job not found!"),
+            }
+        };
+
+        let worker_tx = match worker_tx {
+            Ok(worker_tx) => worker_tx,
+            Err(text) => {
+                let message = ServerToClientMessage::RequestResponse {
+                    success: false,
+                    text: text.into(),
+                };
+                self.stream.send(&message).await?;
+                let message = ServerToClientMessage::CloseTunnel {
+                    tunnel_id: client_tunnel_id,
+                };
+                self.stream.send(&message).await?;
+                return Ok(());
+            }
+        };
+
+        // Register tunnel and let the worker connect to the port.
+        let tunnel_id = self.manager.write().unwrap().add_tunnel(TunnelRoute {
+            client_tx: self.forward_tx.clone(),
+            client_tunnel_id,
+        });
+        self.tunnels
+            .insert(client_tunnel_id, (tunnel_id, worker_tx.clone()));
+        let message = ServerToWorkerMessage::OpenTunnel {
+            job_id,
+            tunnel_id,
+            port,
+        };
+        if worker_tx.send(message).await.is_err() {
+            log::debug!("Worker of job {} is gone!", job_id);
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::TunnelData.
+    async fn on_tunnel_data(&mut self, client_tunnel_id: u64, data: Vec<u8>) -> Result<()> {
+        if let Some((tunnel_id, worker_tx)) = self.tunnels.get(&client_tunnel_id) {
+            let message = ServerToWorkerMessage::TunnelData {
+                tunnel_id: *tunnel_id,
+                data,
+            };
+            if worker_tx.send(message).await.is_err() {
+                // Worker is gone, so is the tunnel.
+                self.on_close_tunnel(client_tunnel_id).await?;
+                let message = ServerToClientMessage::CloseTunnel {
+                    tunnel_id: client_tunnel_id,
+                };
+                self.stream.send(&message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::CloseTunnel.
+    async fn on_close_tunnel(&mut self, client_tunnel_id: u64) -> Result<()> {
+        if let Some((tunnel_id, worker_tx)) = self.tunnels.remove(&client_tunnel_id) {
+            self.manager.write().unwrap().remove_tunnel(tunnel_id);
+            let _ = worker_tx
+                .send(ServerToWorkerMessage::CloseTunnel { tunnel_id })
+                .await;
+        }
+        Ok(())
+    }
+
     /// Called upon receiving ClientToServerMessage::RemoveJob.
-    async fn on_remove_job(&mut self, job_id: u64, kill: bool) -> Result<()> {
+    async fn on_remove_job(
+        &mut self,
+        job_id: u64,
+        kill: bool,
+        dependents: DependentsAction,
+        reason: Option<String>,
+    ) -> Result<()> {
         self.is_authenticated().await?;
 
         // Cancel job and send message back to client.
-        let result = self.manager.write().unwrap().cancel_job(job_id, kill);
+        let (result, affected) = {
+            let mut manager = self.manager.write().unwrap();
+            let result = manager.cancel_job(job_id, kill, reason);
+            let affected = match dependents {
+                _ if result.is_err() => 0,
+                DependentsAction::Resolve => 0,
+                DependentsAction::Cascade => manager.cancel_dependents(job_id),
+                DependentsAction::Orphan => manager.orphan_dependents(job_id),
+            };
+            manager.resolve_dependencies();
+            (result, affected)
+        };
+        let text = match dependents {
+            DependentsAction::Cascade if affected > 0 => format!(
+                "Canceled job ID={} and {} dependent job(s)!",
+                job_id, affected
+            ),
+            DependentsAction::Orphan if affected > 0 => format!(
+                "Canceled job ID={}! {} dependent job(s) remain pending.",
+                job_id, affected
+            ),
+            _ => format!("Canceled job ID={}!", job_id),
+        };
         let message = match result {
             Ok(Some(tx)) => {
                 // Signal kill to the worker.
@@ -484,12 +1216,12 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
                 }
                 ServerToClientMessage::RequestResponse {
                     success: true,
-                    text: format!("Canceled job ID={}!", job_id),
+                    text,
                 }
             }
             Ok(None) => ServerToClientMessage::RequestResponse {
                 success: true,
-                text: format!("Canceled job ID={}!", job_id),
+                text,
             },
             Err(e) => ServerToClientMessage::RequestResponse {
                 success: false,
@@ -515,6 +1247,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::ListWorkers.
     async fn on_list_workers(&mut self) -> Result<()> {
+        self.is_reader().await?;
         // Get worker list.
         let worker_list = self.manager.read().unwrap().get_all_worker_infos();
 
@@ -527,24 +1260,440 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::ShowWorker.
     async fn on_show_worker(&mut self, worker_id: u64) -> Result<()> {
-        // Get worker.
-        let worker = self.manager.read().unwrap().get_worker(worker_id);
-
-        let message = if let Some(worker) = worker {
-            if let Some(worker) = worker.upgrade() {
-                let worker_lock = worker.lock().unwrap();
-                ServerToClientMessage::WorkerInfo(worker_lock.info.clone())
+        self.is_reader().await?;
+        let message = {
+            // Get worker.
+            let manager = self.manager.read().unwrap();
+            if let Some(worker) = manager.get_worker(worker_id) {
+                if let Some(worker) = worker.upgrade() {
+                    let worker_lock = worker.lock().unwrap();
+                    ServerToClientMessage::WorkerInfo(
+                        manager.worker_info_for_client(&worker_lock.info),
+                    )
+                } else {
+                    ServerToClientMessage::RequestResponse {
+                        success: false,
+                        text: "Worker no longer available!".into(),
+                    }
+                }
+            } else if let Some(info) = manager.get_unreachable_worker(worker_id) {
+                ServerToClientMessage::WorkerInfo(info)
             } else {
                 ServerToClientMessage::RequestResponse {
                     success: false,
-                    text: "Worker no longer available!".into(),
+                    text: "Worker not found!".into(),
                 }
             }
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::SetMotd.
+    async fn on_set_motd(&mut self, text: Option<String>) -> Result<()> {
+        self.is_admin("set message of the day".into()).await?;
+
+        let text_response = match &text {
+            Some(_) => "Message of the day has been set!",
+            None => "Message of the day has been cleared!",
+        };
+        self.manager.write().unwrap().set_motd(text);
+
+        let message = ServerToClientMessage::RequestResponse {
+            success: true,
+            text: text_response.into(),
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::Broadcast.
+    async fn on_broadcast(&mut self, text: String, expires_in_minutes: u64) -> Result<()> {
+        self.is_admin("broadcast message".into()).await?;
+
+        // Deliver to connected clients. Busy clients are skipped
+        // rather than blocking this connection.
+        let receivers = self
+            .manager
+            .write()
+            .unwrap()
+            .add_broadcast(text.clone(), expires_in_minutes);
+        let mut delivered = 0;
+        for receiver in receivers {
+            if receiver.same_channel(&self.forward_tx) {
+                continue; // skip the sender
+            }
+            let message = ServerToClientMessage::Announcement {
+                text: text.clone(),
+                broadcast: true,
+            };
+            if receiver.try_send(message).is_ok() {
+                delivered += 1;
+            }
+        }
+
+        let message = ServerToClientMessage::RequestResponse {
+            success: true,
+            text: format!("Broadcast delivered to {} connected client(s)!", delivered),
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::ListConnections.
+    async fn on_list_connections(&mut self) -> Result<()> {
+        self.is_admin("list connections".into()).await?;
+
+        let connection_infos = self.manager.read().unwrap().get_connection_infos();
+        let message = ServerToClientMessage::ConnectionList(connection_infos);
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::ShowSchedulingTrace.
+    async fn on_show_scheduling_trace(&mut self, job_id: u64) -> Result<()> {
+        self.is_admin(format!("show scheduling trace of job {}", job_id))
+            .await?;
+
+        let enabled = self.config.read().unwrap().server_settings.scheduling_trace;
+        let message = if enabled {
+            let events = self.manager.read().unwrap().get_scheduling_trace(job_id);
+            ServerToClientMessage::SchedulingTrace { job_id, events }
         } else {
             ServerToClientMessage::RequestResponse {
                 success: false,
-                text: "Worker not found!".into(),
+                text: "The scheduling trace is disabled on the server! \
+                    Enable it with the scheduling_trace server setting."
+                    .into(),
+            }
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::RemoveWorker.
+    async fn on_remove_worker(
+        &mut self,
+        worker_id: u64,
+        fail_jobs: bool,
+        cooldown_minutes: u64,
+    ) -> Result<()> {
+        self.is_admin(format!("remove worker {}", worker_id))
+            .await?;
+
+        let result =
+            self.manager
+                .write()
+                .unwrap()
+                .remove_worker(worker_id, fail_jobs, cooldown_minutes);
+        let message = match result {
+            Ok((worker_tx, jobs_to_kill)) => {
+                // Kill jobs (if the worker still listens) and drop the connection.
+                let num_jobs = jobs_to_kill.len();
+                for job_info in jobs_to_kill {
+                    let _ = worker_tx
+                        .send(ServerToWorkerMessage::KillJob(job_info))
+                        .await;
+                }
+                let _ = worker_tx.send(ServerToWorkerMessage::Bye).await;
+
+                let action = if fail_jobs { "failed" } else { "requeued" };
+                ServerToClientMessage::RequestResponse {
+                    success: true,
+                    text: format!(
+                        "Removed worker ID={}! {} running job(s) {}.",
+                        worker_id, num_jobs, action
+                    ),
+                }
+            }
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::RenameWorker.
+    async fn on_rename_worker(&mut self, worker_id: u64, worker_name: String) -> Result<()> {
+        self.is_admin(format!("rename worker {} to '{}'", worker_id, worker_name))
+            .await?;
+
+        let result = self
+            .manager
+            .write()
+            .unwrap()
+            .rename_worker(worker_id, &worker_name);
+        let message = match result {
+            Ok(()) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!("Renamed worker ID={} to '{}'!", worker_id, worker_name),
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::DrainWorker.
+    async fn on_drain_worker(&mut self, worker_id: u64, drain: bool) -> Result<()> {
+        let action = if drain { "drain" } else { "undrain" };
+        self.is_admin(format!("{} worker {}", action, worker_id))
+            .await?;
+
+        let result = self.manager.write().unwrap().drain_worker(worker_id, drain);
+        let message = match result {
+            Ok(worker_name) if drain => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!(
+                    "Worker '{}' receives no new jobs! Running jobs are not affected.",
+                    worker_name
+                ),
+            },
+            Ok(worker_name) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!("Worker '{}' receives new jobs again!", worker_name),
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::ReserveWorker.
+    async fn on_reserve_worker(&mut self, worker_id: u64, user: Option<String>) -> Result<()> {
+        let action = match &user {
+            Some(user) => format!("reserve worker {} for user {}", worker_id, user),
+            None => format!("release worker {}", worker_id),
+        };
+        self.is_admin(action).await?;
+
+        let result = self
+            .manager
+            .write()
+            .unwrap()
+            .reserve_worker(worker_id, user.clone());
+        let message = match result {
+            Ok(worker_name) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: match user {
+                    Some(user) => format!("Worker '{}' is reserved for {}!", worker_name, user),
+                    None => format!("Worker '{}' is no longer reserved!", worker_name),
+                },
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::MigrateJob.
+    async fn on_migrate_job(&mut self, job_id: u64, migration: JobMigration) -> Result<()> {
+        self.is_admin(format!("migrate job {}", job_id)).await?;
+
+        let result = self.manager.read().unwrap().migrate_job(job_id, &migration);
+        let result = match result {
+            Ok(worker_tx) => {
+                let message = ServerToWorkerMessage::StopJob {
+                    job_id,
+                    signal: migration.signal,
+                    grace_period_seconds: migration.grace_period_seconds,
+                };
+                let sent = worker_tx.send(message).await;
+                sent.map_err(|_| anyhow!("Worker of job {} is not connected!", job_id))
+            }
+            Err(e) => Err(e),
+        };
+        let message = match result {
+            Ok(()) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!(
+                    "Job {} is being stopped and will be requeued on another worker!",
+                    job_id
+                ),
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::SaveSnapshot.
+    async fn on_save_snapshot(&mut self, path: PathBuf) -> Result<()> {
+        self.is_admin(format!("save snapshot to {}", path.to_string_lossy()))
+            .await?;
+
+        let result = self.manager.read().unwrap().save_snapshot(&path);
+        let message = match result {
+            Ok(()) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!("Saved snapshot to {}!", path.to_string_lossy()),
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: format!("Failed to save snapshot: {}", e),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::SetLogLevel.
+    async fn on_set_log_level(&mut self, level: String) -> Result<()> {
+        self.is_admin(format!("set log level to {}", level)).await?;
+
+        let message = match level.parse::<log::LevelFilter>() {
+            Ok(filter) => {
+                log::set_max_level(filter);
+                self.config.write().unwrap().common_settings.log_level = level.clone();
+                ServerToClientMessage::RequestResponse {
+                    success: true,
+                    text: format!("Log level of the server set to {}!", level),
+                }
             }
+            Err(_) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: format!(
+                    "Invalid log level '{}'! Options: off, error, warn, info, debug, trace.",
+                    level
+                ),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::ShowAuditLog.
+    async fn on_show_audit_log(&mut self) -> Result<()> {
+        self.is_admin("show audit log".into()).await?;
+
+        let entries = self.manager.read().unwrap().get_audit_log();
+        self.stream
+            .send(&ServerToClientMessage::AuditLog(entries))
+            .await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::DiffJobs.
+    async fn on_diff_jobs(&mut self, job_a: u64, job_b: u64) -> Result<()> {
+        self.is_reader().await?;
+        let result = self.manager.read().unwrap().diff_jobs(job_a, job_b);
+        let message = match result {
+            Ok((diff, truncated)) => ServerToClientMessage::JobDiff {
+                job_a,
+                job_b,
+                diff,
+                truncated,
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::FetchJob.
+    async fn on_fetch_job(&mut self, job_id: u64) -> Result<()> {
+        self.is_reader().await?;
+        let job = self.manager.read().unwrap().get_job(job_id);
+        let (encrypted, outputs) = match job {
+            Some(job) => {
+                let job_lock = job.lock().unwrap();
+                let (stdout_text, stderr_text) = job_lock.outputs();
+                (
+                    job_lock.info.encrypted_payload.is_some(),
+                    [stdout_text, stderr_text],
+                )
+            }
+            None => {
+                let message = ServerToClientMessage::RequestResponse {
+                    success: false,
+                    text: "Job not found!".into(),
+                };
+                self.stream.send(&message).await?;
+                return Ok(());
+            }
+        };
+
+        // Send the outputs in chunks, so that large outputs do not block the
+        // connection, followed by their checksums.
+        let streams = [OutputStream::Stdout, OutputStream::Stderr];
+        for (stream, text) in streams.into_iter().zip(&outputs) {
+            let data = text.as_deref().unwrap_or_default().as_bytes();
+            for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+                let message = ServerToClientMessage::FetchChunk {
+                    job_id,
+                    stream,
+                    offset: (index * CHUNK_SIZE) as u64,
+                    data: chunk.to_vec(),
+                };
+                self.stream.send(&message).await?;
+            }
+        }
+        let [stdout_sha256, stderr_sha256] =
+            outputs.map(|text| text.map(|text| sha256_hex(text.as_bytes())));
+        let message = ServerToClientMessage::FetchComplete {
+            job_id,
+            encrypted,
+            stdout_sha256,
+            stderr_sha256,
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::BoostJob.
+    async fn on_boost_job(&mut self, job_id: u64, to_front: bool, user: String) -> Result<()> {
+        self.is_authenticated().await?;
+
+        // Owners may boost their own jobs, if permitted by the server.
+        let owners_can_boost = self.config.read().unwrap().server_settings.owners_can_boost;
+        let owner = self
+            .manager
+            .read()
+            .unwrap()
+            .get_job(job_id)
+            .map(|job| job.lock().unwrap().info.user.clone());
+        if !(owners_can_boost && owner.as_ref() == Some(&user)) {
+            self.is_admin(format!("boost job {}", job_id)).await?;
+        }
+
+        let result = self
+            .manager
+            .write()
+            .unwrap()
+            .boost_job(job_id, to_front, &user);
+        let message = match result {
+            Ok(_) if to_front => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!("Moved job ID={} to the front of the queue!", job_id),
+            },
+            Ok((previous, priority)) => ServerToClientMessage::RequestResponse {
+                success: true,
+                text: format!(
+                    "Boosted priority of job ID={} from {} to {}!",
+                    job_id, previous, priority
+                ),
+            },
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
         };
         self.stream.send(&message).await?;
         Ok(())
@@ -552,17 +1701,82 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> ClientConnection<Stream> {
 
     /// Called upon receiving ClientToServerMessage::ListResources.
     async fn on_list_resources(&mut self) -> Result<()> {
+        self.is_reader().await?;
         // Get global resources.
         let used_resources = self.manager.read().unwrap().get_used_global_resources();
         let total_resources = self.config.read().unwrap().global_resources.clone();
+        let (used_worker_resources, total_worker_resources) =
+            self.manager.read().unwrap().get_worker_resources();
 
         // Send response to client.
         self.stream
             .send(&ServerToClientMessage::ResourceList {
                 used_resources,
                 total_resources,
+                used_worker_resources,
+                total_worker_resources,
             })
             .await?;
         Ok(())
     }
+
+    /// Called upon receiving ClientToServerMessage::GetProjectUsage.
+    async fn on_get_project_usage(&mut self) -> Result<()> {
+        self.is_reader().await?;
+        let usage = self.manager.read().unwrap().get_project_usage();
+        self.stream
+            .send(&ServerToClientMessage::ProjectUsage(usage))
+            .await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::GetIdleResources.
+    async fn on_get_idle_resources(&mut self) -> Result<()> {
+        self.is_reader().await?;
+        let report = self.manager.read().unwrap().get_idle_resources();
+        self.stream
+            .send(&ServerToClientMessage::IdleResources(report))
+            .await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::GetAccounting.
+    async fn on_get_accounting(
+        &mut self,
+        user: Option<String>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        self.is_reader().await?;
+        let result = self
+            .manager
+            .read()
+            .unwrap()
+            .get_user_usage(user.as_deref(), since, until);
+        let message = match result {
+            Ok(usage) => ServerToClientMessage::UserUsage(usage),
+            Err(e) => ServerToClientMessage::RequestResponse {
+                success: false,
+                text: e.to_string(),
+            },
+        };
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
+    /// Called upon receiving ClientToServerMessage::ListTemplates.
+    async fn on_list_templates(&mut self) -> Result<()> {
+        self.is_reader().await?;
+        let templates = self
+            .config
+            .read()
+            .unwrap()
+            .server_settings
+            .job_templates
+            .clone();
+        self.stream
+            .send(&ServerToClientMessage::JobTemplates(templates))
+            .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,164 @@
+//! Line-based unified diffs, e.g., of the outputs of two jobs.
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Largest number of line pairs compared to find a minimal diff, after common
+/// leading and trailing lines have been skipped. Larger changes are shown as
+/// replacing the whole block of lines.
+const MAX_COMPARISONS: usize = 1 << 22;
+
+/// A line of the diff with its indices in the old and new text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Line {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Match the lines of both texts, keeping as many lines as possible.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Line> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_block = &old[prefix..old.len() - suffix];
+    let new_block = &new[prefix..new.len() - suffix];
+    let (n, m) = (old_block.len(), new_block.len());
+
+    let mut lines: Vec<Line> = (0..prefix).map(|i| Line::Same(i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    if n * m <= MAX_COMPARISONS {
+        // Length of the longest common subsequence of the remaining lines.
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[at(i, j)] = if old_block[i] == new_block[j] {
+                    lcs[at(i + 1, j + 1)] + 1
+                } else {
+                    lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+                };
+            }
+        }
+        while i < n && j < m {
+            if old_block[i] == new_block[j] {
+                lines.push(Line::Same(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+                lines.push(Line::Removed(prefix + i));
+                i += 1;
+            } else {
+                lines.push(Line::Added(prefix + j));
+                j += 1;
+            }
+        }
+    }
+    lines.extend((i..n).map(|i| Line::Removed(prefix + i)));
+    lines.extend((j..m).map(|j| Line::Added(prefix + j)));
+    lines.extend((0..suffix).map(|k| Line::Same(old.len() - suffix + k, new.len() - suffix + k)));
+    lines
+}
+
+/// Start (1-based) and length of a hunk's range in one of the texts. Empty
+/// ranges start at the line before, as in `diff -u`.
+fn hunk_range(start: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, len)
+    }
+}
+
+/// Unified diff of two texts, or an empty string if they are equal.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old, &new);
+    let changes: Vec<usize> = (0..lines.len())
+        .filter(|&index| !matches!(lines[index], Line::Same(..)))
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut text = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut next = 0;
+    while next < changes.len() {
+        // Merge changes whose context would overlap into one hunk.
+        let first = changes[next].saturating_sub(CONTEXT_LINES);
+        let mut last = changes[next];
+        next += 1;
+        while next < changes.len() && changes[next] <= last + 2 * CONTEXT_LINES + 1 {
+            last = changes[next];
+            next += 1;
+        }
+        let hunk = &lines[first..(last + CONTEXT_LINES + 1).min(lines.len())];
+
+        let in_old = |line: &Line| !matches!(line, Line::Added(_));
+        let in_new = |line: &Line| !matches!(line, Line::Removed(_));
+        let old_start = lines[..first].iter().filter(|line| in_old(line)).count();
+        let new_start = lines[..first].iter().filter(|line| in_new(line)).count();
+        let old_len = hunk.iter().filter(|line| in_old(line)).count();
+        let new_len = hunk.iter().filter(|line| in_new(line)).count();
+        text += &format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        );
+        for line in hunk {
+            let (sign, content) = match *line {
+                Line::Same(i, _) => (' ', old[i]),
+                Line::Removed(i) => ('-', old[i]),
+                Line::Added(j) => ('+', new[j]),
+            };
+            text.push(sign);
+            text += content;
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Shorten the text to at most `max_len` bytes. Returns true if the text has
+/// been shortened.
+pub fn truncate(text: &mut String, max_len: usize) -> bool {
+    if text.len() <= max_len {
+        return false;
+    }
+    let mut len = max_len;
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    text.truncate(len);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff_of_outputs() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "1", "2"), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        let expected = "--- 1\n+++ 2\n\
+                        @@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+                        @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n";
+        assert_eq!(unified_diff(old, new, "1", "2"), expected);
+
+        let expected = "--- 1\n+++ 2\n@@ -0,0 +1,2 @@\n+x\n+y\n";
+        assert_eq!(unified_diff("", "x\ny\n", "1", "2"), expected);
+
+        let mut text = "äöü".to_string();
+        assert!(truncate(&mut text, 3));
+        assert_eq!(text, "ä");
+        assert!(!truncate(&mut text, 3));
+    }
+}
@@ -1,5 +1,7 @@
 mod client_connection;
+mod diff;
 mod shared_state;
+mod submission_hook;
 mod tcp;
 mod test;
 mod worker_connection;
@@ -8,57 +10,295 @@ pub use tcp::TcpServer;
 pub use test::TestServer;
 
 use crate::{
-    config::Config,
+    config::{Config, DuplicateWorkerNames},
     messages::stream::MessageStream,
-    messages::{HelloMessage, ServerToClientMessage, ServerToWorkerMessage},
+    messages::{
+        version::{capabilities, check_remote_version, KUEUE_VERSION},
+        HelloMessage, LegacyWelcome, ServerToClientMessage, ServerToWorkerMessage,
+    },
     server::{
         client_connection::ClientConnection, shared_state::Manager,
         worker_connection::WorkerConnection,
     },
+    structs::{ConnectionInfo, ConnectionKind, WorkerInfo},
+};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
 };
-use std::sync::{Arc, RwLock};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     sync::mpsc::Sender,
 };
 use tokio_util::sync::CancellationToken;
 
-/// Initiate welcome handshake with new connection
-/// to distinguish between client and worker.
-pub async fn handle_connection<Stream: AsyncReadExt + AsyncWriteExt + Unpin>(
-    stream: Stream,
+/// Logical instance of Kueue served by the server: its settings and its
+/// shared state, i.e., its jobs and workers.
+#[derive(Clone)]
+struct Instance {
     config: Arc<RwLock<Config>>,
     shared: Arc<RwLock<Manager>>,
+}
+
+impl Instance {
+    fn new(config: Config) -> Self {
+        Instance {
+            config: Arc::new(RwLock::new(config.clone())),
+            shared: Arc::new(RwLock::new(Manager::new(config))),
+        }
+    }
+}
+
+/// The server's own instance and the instances of its tenants, which share
+/// the server's listeners.
+struct Instances {
+    default: Instance,
+    tenants: BTreeMap<String, Instance>,
+    /// Names of tenants by the host names that select them.
+    server_names: BTreeMap<String, String>,
+}
+
+impl Instances {
+    fn new(config: Config) -> Self {
+        let mut tenants = BTreeMap::new();
+        let mut server_names = BTreeMap::new();
+        for tenant in &config.server_settings.tenants {
+            let instance = Instance::new(config.for_tenant(tenant));
+            tenants.insert(tenant.name.clone(), instance);
+            for server_name in &tenant.server_names {
+                server_names.insert(server_name.to_lowercase(), tenant.name.clone());
+            }
+        }
+        Instances {
+            default: Instance::new(config),
+            tenants,
+            server_names,
+        }
+    }
+
+    /// All instances with the names of their tenants, the server's own first.
+    fn iter(&self) -> impl Iterator<Item = (Option<&str>, &Instance)> {
+        let tenants = self.tenants.iter();
+        std::iter::once((None, &self.default))
+            .chain(tenants.map(|(name, instance)| (Some(name.as_str()), instance)))
+    }
+
+    /// Select the instance of the tenant requested in the hello message, or
+    /// of the host name the peer connected to. Without either, the peer
+    /// connects to the server's own instance.
+    fn select(&self, tenant: Option<&str>, server_name: Option<&str>) -> Result<&Instance> {
+        let tenant = tenant.map(str::to_string).or_else(|| {
+            server_name
+                .and_then(|server_name| self.server_names.get(&server_name.to_lowercase()).cloned())
+        });
+        match tenant {
+            Some(tenant) => match self.tenants.get(&tenant) {
+                Some(instance) => Ok(instance),
+                None => bail!("Unknown tenant '{}'!", tenant),
+            },
+            None => Ok(&self.default),
+        }
+    }
+}
+
+/// Register the connection with the manager, so it can be listed
+/// with `kueue admin connections`. Returns the connection ID.
+fn register_connection<Stream>(
+    shared: &Arc<RwLock<Manager>>,
+    stream: &MessageStream<Stream>,
+    kind: ConnectionKind,
+    name: Option<String>,
+    remote_address: &str,
+    kueue_version: &str,
+) -> u64 {
+    let info = ConnectionInfo {
+        connection_id: 0, // assigned by manager
+        kind,
+        name,
+        remote_address: remote_address.to_string(),
+        connected_since: Utc::now(),
+        kueue_version: kueue_version.to_string(),
+        bytes_sent: 0,
+        bytes_received: 0,
+    };
+    shared
+        .write()
+        .unwrap()
+        .add_connection(info, stream.traffic())
+}
+
+/// Initiate welcome handshake with new connection
+/// to distinguish between client and worker. The connection is handled by
+/// the instance of the requested tenant. `server_name` is the host name the
+/// peer connected to, if known from the TLS handshake.
+async fn handle_connection<Stream: AsyncReadExt + AsyncWriteExt + Unpin>(
+    stream: Stream,
+    remote_address: String,
+    server_name: Option<String>,
+    instances: Arc<Instances>,
     cancel_token: CancellationToken,
     keep_alive: Sender<()>,
 ) {
     // Read hello message to distinguish between client and worker.
     let mut stream = MessageStream::new(stream);
     match stream.receive::<HelloMessage>().await {
-        Ok(HelloMessage::HelloFromClient) => {
+        Ok(HelloMessage::HelloFromClient {
+            kueue_version,
+            capabilities: client_capabilities,
+            tenant,
+        }) => {
+            // Warn about incompatible clients but give them a chance anyway.
+            check_remote_version("client", &kueue_version);
+
+            let Instance { config, shared } =
+                match instances.select(tenant.as_deref(), server_name.as_deref()) {
+                    Ok(instance) => instance.clone(),
+                    Err(e) => {
+                        log::warn!("Rejected client {}: {}", remote_address, e);
+                        let reject = ServerToClientMessage::RequestResponse {
+                            success: false,
+                            text: e.to_string(),
+                        };
+                        if let Err(e) = stream.send(&reject).await {
+                            log::error!("Failed to send RequestResponse: {}", e);
+                        }
+                        drop(keep_alive);
+                        return;
+                    }
+                };
+
             // Handle client connection.
-            match stream.send(&ServerToClientMessage::WelcomeClient).await {
+            let welcome = ServerToClientMessage::WelcomeClient {
+                kueue_version: KUEUE_VERSION.into(),
+                capabilities: capabilities(),
+            };
+            let sent = if kueue_version.is_empty() {
+                stream.send(&LegacyWelcome::WelcomeClient).await
+            } else {
+                stream.send(&welcome).await
+            };
+            match sent {
                 Ok(()) => {
                     log::trace!("Exchanged welcome handshake with client!");
-                    let mut client = ClientConnection::new(stream, config, shared, cancel_token);
+                    stream.negotiate_format(&client_capabilities);
+                    let connection_id = register_connection(
+                        &shared,
+                        &stream,
+                        ConnectionKind::Client,
+                        None,
+                        &remote_address,
+                        &kueue_version,
+                    );
+                    let mut client = ClientConnection::new(
+                        stream,
+                        remote_address,
+                        client_capabilities,
+                        config,
+                        shared.clone(),
+                        cancel_token,
+                    );
                     client.run().await;
+                    shared.write().unwrap().remove_connection(connection_id);
                 }
                 Err(e) => log::error!("Failed to send WelcomeClient: {}", e),
             }
         }
-        Ok(HelloMessage::HelloFromWorker { worker_name }) => {
+        Ok(HelloMessage::HelloFromWorker {
+            worker_name,
+            kueue_version,
+            capabilities: worker_capabilities,
+            tags,
+            labels,
+            tenant,
+        }) => {
+            // Warn about incompatible workers but give them a chance anyway.
+            check_remote_version(&format!("worker '{}'", worker_name), &kueue_version);
+
+            let Instance { config, shared } =
+                match instances.select(tenant.as_deref(), server_name.as_deref()) {
+                    Ok(instance) => instance.clone(),
+                    Err(e) => {
+                        log::warn!("Rejected worker '{}': {}", worker_name, e);
+                        let reject = ServerToWorkerMessage::RejectWorker {
+                            reason: e.to_string(),
+                        };
+                        if let Err(e) = stream.send(&reject).await {
+                            log::error!("Failed to send RejectWorker: {}", e);
+                        }
+                        drop(keep_alive);
+                        return;
+                    }
+                };
+
+            // Reject workers that have been removed recently.
+            let blocked_until = shared.write().unwrap().worker_blocked_until(&worker_name);
+            if let Some(blocked_until) = blocked_until {
+                log::warn!("Rejected removed worker '{}'!", worker_name);
+                let reject = ServerToWorkerMessage::RejectWorker {
+                    reason: format!(
+                        "Worker has been removed and may not reconnect before {}.",
+                        blocked_until.to_rfc3339()
+                    ),
+                };
+                if let Err(e) = stream.send(&reject).await {
+                    log::error!("Failed to send RejectWorker: {}", e);
+                }
+                drop(keep_alive);
+                return;
+            }
+
+            // Reject workers with the name of a connected worker, if configured.
+            let reject_duplicates = config
+                .read()
+                .unwrap()
+                .server_settings
+                .duplicate_worker_names
+                == DuplicateWorkerNames::Reject;
+            if reject_duplicates && shared.read().unwrap().worker_name_in_use(&worker_name) {
+                log::warn!("Rejected worker '{}' with duplicate name!", worker_name);
+                let reject = ServerToWorkerMessage::RejectWorker {
+                    reason: format!("Another worker named '{}' is connected.", worker_name),
+                };
+                if let Err(e) = stream.send(&reject).await {
+                    log::error!("Failed to send RejectWorker: {}", e);
+                }
+                drop(keep_alive);
+                return;
+            }
+
             // Handle worker connection.
-            match stream.send(&ServerToWorkerMessage::WelcomeWorker).await {
+            let welcome = ServerToWorkerMessage::WelcomeWorker {
+                kueue_version: KUEUE_VERSION.into(),
+                capabilities: capabilities(),
+            };
+            let sent = if kueue_version.is_empty() {
+                stream.send(&LegacyWelcome::WelcomeWorker).await
+            } else {
+                stream.send(&welcome).await
+            };
+            match sent {
                 Ok(()) => {
                     log::trace!("Exchanged welcome handshake with worker '{}'!", worker_name);
-                    let mut worker = WorkerConnection::new(
-                        worker_name.clone(),
-                        stream,
-                        config,
-                        shared,
-                        cancel_token,
+                    stream.negotiate_format(&worker_capabilities);
+                    let connection_id = register_connection(
+                        &shared,
+                        &stream,
+                        ConnectionKind::Worker,
+                        Some(worker_name.clone()),
+                        &remote_address,
+                        &kueue_version,
                     );
+                    let mut info = WorkerInfo::new(worker_name.clone(), kueue_version);
+                    info.capabilities = worker_capabilities;
+                    info.tags = tags;
+                    info.labels = labels;
+                    info.remote_address = Some(remote_address);
+                    let mut worker =
+                        WorkerConnection::new(info, stream, config, shared.clone(), cancel_token);
                     worker.run().await;
+                    shared.write().unwrap().remove_connection(connection_id);
                 }
                 Err(e) => log::error!("Failed to send WelcomeWorker: {}", e),
             }
@@ -1,12 +1,47 @@
-use crate::structs::JobInfo;
+use crate::{
+    messages::ServerToClientMessage,
+    server::shared_state::{
+        spool::Spool,
+        store::{JobStore, StoredJob},
+    },
+    structs::{JobInfo, JobMigration, OutputStream},
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
+/// Terminal output kept for an interactive job until a client attaches to it.
+/// Beyond this size, the oldest output is dropped.
+pub const MAX_BUFFERED_OUTPUT: usize = 1024 * 1024;
+
 pub struct Job {
     pub info: JobInfo,
     pub worker_id: Option<u64>,
+    /// Outputs of the job, unless they are spooled to disk.
     pub stdout_text: Option<String>,
     pub stderr_text: Option<String>,
     pub observers: Vec<mpsc::Sender<u64>>,
+    /// Client attached to an interactive job. Terminal
+    /// output of the job is forwarded to this client.
+    pub attached_client: Option<mpsc::Sender<ServerToClientMessage>>,
+    /// Terminal output received before a client has been attached, up to
+    /// `MAX_BUFFERED_OUTPUT` bytes.
+    pub buffered_output: Vec<u8>,
+    /// Clients following the output of the job.
+    pub log_followers: Vec<LogFollower>,
+    /// Requested migration of the running job. Once the worker has stopped
+    /// the job, it is requeued on another worker.
+    pub migration: Option<JobMigration>,
+    /// The job is stopped to make room for a job of higher priority. Unlike
+    /// migrated jobs, it may run on the same worker again.
+    pub preempted: bool,
+    /// Database the job is persisted in, if configured.
+    pub store: Option<Arc<JobStore>>,
+    /// Directory the outputs of the job are spooled to, if configured.
+    pub spool: Option<Arc<Spool>>,
+    /// Until then, other workers leave the job to its preferred worker. Set
+    /// when another worker skips the job for the first time.
+    pub affinity_deadline: Option<DateTime<Utc>>,
 }
 
 impl Job {
@@ -17,10 +52,96 @@ impl Job {
             stdout_text: None,
             stderr_text: None,
             observers: Vec::new(),
+            attached_client: None,
+            buffered_output: Vec::new(),
+            log_followers: Vec::new(),
+            migration: None,
+            preempted: false,
+            store: None,
+            spool: None,
+            affinity_deadline: None,
+        }
+    }
+
+    /// Recreate a job read from the database, keeping its job ID.
+    pub fn restored(stored: StoredJob, store: Arc<JobStore>) -> Self {
+        Job {
+            info: stored.info,
+            worker_id: None,
+            stdout_text: stored.stdout_text,
+            stderr_text: stored.stderr_text,
+            observers: Vec::new(),
+            attached_client: None,
+            buffered_output: Vec::new(),
+            log_followers: Vec::new(),
+            migration: None,
+            preempted: false,
+            store: Some(store),
+            spool: None,
+            affinity_deadline: None,
+        }
+    }
+
+    /// Keep terminal output until a client attaches. The oldest output is
+    /// dropped if the buffer grows beyond `MAX_BUFFERED_OUTPUT`.
+    pub fn buffer_output(&mut self, data: &[u8]) {
+        self.buffered_output.extend_from_slice(data);
+        let excess = self
+            .buffered_output
+            .len()
+            .saturating_sub(MAX_BUFFERED_OUTPUT);
+        self.buffered_output.drain(..excess);
+    }
+
+    /// Store the outputs of the job, replacing earlier outputs. With a spool
+    /// directory, outputs are written to disk instead of being kept in memory.
+    pub fn set_outputs(&mut self, stdout_text: Option<String>, stderr_text: Option<String>) {
+        if let Some(spool) = &self.spool {
+            let job_id = self.info.job_id;
+            let spooled = spool
+                .write(job_id, OutputStream::Stdout, stdout_text.as_deref())
+                .and_then(|_| spool.write(job_id, OutputStream::Stderr, stderr_text.as_deref()));
+            match spooled {
+                Ok(()) => {
+                    self.stdout_text = None;
+                    self.stderr_text = None;
+                    return;
+                }
+                Err(e) => log::error!("Failed to spool output of job {}: {}", job_id, e),
+            }
+        }
+        self.stdout_text = stdout_text;
+        self.stderr_text = stderr_text;
+    }
+
+    /// Outputs of the job, read from the spool directory if configured.
+    /// Outputs kept in memory, e.g., from before the spool directory has been
+    /// configured, are returned as well.
+    pub fn outputs(&self) -> (Option<String>, Option<String>) {
+        let spooled = |stream| {
+            let spool = self.spool.as_ref()?;
+            spool.read(self.info.job_id, stream)
+        };
+        (
+            spooled(OutputStream::Stdout).or_else(|| self.stdout_text.clone()),
+            spooled(OutputStream::Stderr).or_else(|| self.stderr_text.clone()),
+        )
+    }
+
+    /// Write the current state of the job to the database, if configured.
+    pub fn save(&self) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save(self) {
+                log::error!("Failed to save job {}: {}", self.info.job_id, e);
+            }
         }
     }
 
-    pub fn notify_observers(&self) {
+    /// Let observers know that the job has changed. The job is saved as well.
+    pub fn notify_observers(&mut self) {
+        self.save();
+        // Forget observers whose clients have disconnected.
+        self.observers.retain(|observer| !observer.is_closed());
         for observer in &self.observers {
             if let Err(err) = observer.try_send(self.info.job_id) {
                 log::error!("Failed to notify observer: {err}");
@@ -28,3 +149,103 @@ impl Job {
         }
     }
 }
+
+/// Client following the output of a job with `kueue log -f`.
+pub struct LogFollower {
+    pub client_tx: mpsc::Sender<ServerToClientMessage>,
+    /// Bytes of stdout and stderr forwarded so far, or `None` until the
+    /// output captured before following has been received.
+    positions: [Option<u64>; 2],
+}
+
+impl LogFollower {
+    pub fn new(client_tx: mpsc::Sender<ServerToClientMessage>) -> Self {
+        LogFollower {
+            client_tx,
+            positions: [None, None],
+        }
+    }
+
+    /// Part of the received output that is new to the client. Output is
+    /// skipped until the output captured before following has been received,
+    /// which is sent again whenever another client starts following.
+    pub fn new_output<'a>(
+        &mut self,
+        stream: OutputStream,
+        offset: u64,
+        data: &'a [u8],
+        catch_up: bool,
+    ) -> Option<&'a [u8]> {
+        let position = &mut self.positions[stream as usize];
+        let start = match *position {
+            Some(position) => position.max(offset),
+            None if catch_up => offset,
+            None => return None,
+        };
+        let end = offset + data.len() as u64;
+        *position = Some(end.max(start));
+        if end <= start {
+            return None;
+        }
+        Some(&data[(start - offset) as usize..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+
+    #[test]
+    fn log_follower_positions() {
+        let (client_tx, _client_rx) = mpsc::channel(1);
+        let mut follower = LogFollower::new(client_tx);
+        let stdout = OutputStream::Stdout;
+
+        // Output is skipped until the follower has caught up.
+        assert_eq!(follower.new_output(stdout, 0, b"abc", false), None);
+        assert_eq!(
+            follower.new_output(stdout, 0, b"abc", true),
+            Some(&b"abc"[..])
+        );
+        assert_eq!(
+            follower.new_output(stdout, 3, b"de", false),
+            Some(&b"de"[..])
+        );
+
+        // Output caught up for other followers is not repeated.
+        assert_eq!(follower.new_output(stdout, 0, b"abcd", true), None);
+        assert_eq!(
+            follower.new_output(stdout, 0, b"abcdef", true),
+            Some(&b"f"[..])
+        );
+
+        // Redirected output starts at the current position.
+        let stderr = OutputStream::Stderr;
+        assert_eq!(follower.new_output(stderr, 100, b"", true), None);
+        assert_eq!(
+            follower.new_output(stderr, 100, b"x", false),
+            Some(&b"x"[..])
+        );
+    }
+
+    #[test]
+    fn buffered_output_is_capped() {
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(
+            vec!["sh".into()],
+            "/tmp".into(),
+            resources,
+            None,
+            None,
+            None,
+        );
+        let mut job = Job::from(job_info);
+        job.buffer_output(&vec![b'a'; MAX_BUFFERED_OUTPUT]);
+        job.buffer_output(b"end");
+
+        // The oldest output is dropped first.
+        assert_eq!(job.buffered_output.len(), MAX_BUFFERED_OUTPUT);
+        assert!(job.buffered_output.ends_with(b"aend"));
+    }
+}
@@ -1,60 +1,938 @@
 use crate::{
-    config::Config,
-    server::shared_state::{Job, Worker},
-    structs::{JobInfo, JobStatus, Resources, WorkerInfo},
+    config::{Config, EmergencyPolicy, Project, QosClass, StalledJobAction},
+    messages::{
+        stream::Traffic,
+        version::{
+            CAP_CORE_BINDING, CAP_GPU_SCHEDULING, CAP_INTERACTIVE_JOBS, CAP_JOB_MIGRATION,
+            CAP_PROCESS_LIMITS,
+        },
+        ServerToClientMessage, ServerToWorkerMessage,
+    },
+    server::{
+        diff::{truncate, unified_diff},
+        shared_state::{store::AccountingRecord, Job, JobStore, Spool, Worker},
+    },
+    structs::{
+        reserve_job_ids, AuditEntry, ConnectionInfo, ExitCodeAction, FailureKind, IdleResources,
+        JobEvent, JobInfo, JobMigration, JobReceipt, JobStatus, ProcessLimits, ProjectUsage,
+        ResourceUsage, Resources, SchedulingEvent, SchedulingOutcome, UserUsage, WorkerAffinity,
+        WorkerInfo, EXIT_CODE_STALLED, EXIT_CODE_STOPPED, EXIT_CODE_WORKER_LOST,
+    },
 };
 use anyhow::{bail, Result};
-use chrono::Utc;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use rand::{thread_rng, Rng};
+use regex::Regex;
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::Path,
     sync::{Arc, Mutex, Weak},
+    time::Instant,
 };
-use tokio::sync::{mpsc, Notify};
+use sysinfo::{ProcessExt, System, SystemExt};
+use tokio::sync::{mpsc, Notify, Semaphore};
 
 pub struct Manager {
     config: Config,
     jobs: BTreeMap<u64, Arc<Mutex<Job>>>,
-    jobs_waiting_for_assignment: BTreeSet<u64>,
+    /// Pending jobs that can be offered to workers. The queue has its own
+    /// lock, so that workers can pick jobs under a read lock on the manager.
+    jobs_waiting_for_assignment: Mutex<BTreeSet<u64>>,
+    /// Serializes job assignments while global resources are configured,
+    /// since those are shared among all workers.
+    global_assignment: Mutex<()>,
+    /// Jobs offered to or running on each worker, by user. This is kept up to
+    /// date on assignment and release, so that the per-user limit on workers
+    /// is checked without scanning all jobs.
+    assigned_jobs_per_user: Mutex<BTreeMap<u64, BTreeMap<String, BTreeSet<u64>>>>,
+    /// Recent scheduling decisions per job, if the scheduling trace is enabled.
+    scheduling_trace: Mutex<BTreeMap<u64, VecDeque<SchedulingEvent>>>,
+    /// Pending jobs that wait for other jobs to conclude.
+    jobs_waiting_for_dependencies: BTreeSet<u64>,
     workers: BTreeMap<u64, Weak<Mutex<Worker>>>,
+    /// Clients observing submission groups, including jobs issued later on.
+    group_observers: BTreeMap<String, Vec<mpsc::Sender<u64>>>,
+    /// Last known state of workers that became unreachable.
+    unreachable_workers: BTreeMap<u64, WorkerInfo>,
+    tunnels: BTreeMap<u64, TunnelRoute>,
+    next_tunnel_id: u64,
+    /// Live client and worker connections, with their traffic counters.
+    connections: BTreeMap<u64, (ConnectionInfo, Arc<Traffic>)>,
+    next_connection_id: u64,
+    motd: Option<String>,
+    /// Broadcasts and their expiration dates.
+    broadcasts: Vec<(String, DateTime<Utc>)>,
+    /// Connected clients that receive broadcasts.
+    announcement_receivers: Vec<mpsc::Sender<ServerToClientMessage>>,
+    /// Names of removed workers that may not reconnect until the given time.
+    blocked_workers: BTreeMap<String, DateTime<Utc>>,
+    /// Names of drained workers, kept if the workers reconnect.
+    drained_workers: BTreeSet<String>,
+    /// Users that workers are reserved for by worker name, kept if the
+    /// workers reconnect.
+    reserved_workers: BTreeMap<String, String>,
+    /// Recently requested administrative actions.
+    audit_log: VecDeque<AuditEntry>,
+    /// Measured resource usage of recently succeeded jobs, grouped by similarity.
+    resource_history: BTreeMap<String, VecDeque<ResourceUsage>>,
+    /// Finished jobs and consumed CPU hours per project.
+    project_accounting: BTreeMap<Option<String>, ProjectUsage>,
+    /// Resources that sat idle while pending jobs did not fit, by worker name
+    /// and hour.
+    idle_resources: BTreeMap<(String, DateTime<Utc>), IdleResources>,
+    /// Set while the server exceeds its memory or job limit.
+    emergency: bool,
+    /// Database jobs are persisted in, if configured.
+    store: Option<Arc<JobStore>>,
+    /// Directory outputs of jobs are spooled to, if configured.
+    spool: Option<Arc<Spool>>,
     pub notify_new_jobs: Arc<Notify>,
+    /// Permits for worker connections to scan the queue for jobs to offer.
+    pub offer_scan_permits: Arc<Semaphore>,
+    /// Earliest point in time the next connecting worker is served.
+    next_connection_slot: Instant,
+}
+
+/// Number of similar jobs kept in the resource history.
+const RESOURCE_HISTORY_LEN: usize = 10;
+
+/// Minimum number of similar jobs required to suggest resources.
+const RESOURCE_HISTORY_MIN_RUNS: usize = 3;
+
+/// Number of scheduling decisions kept per job in the scheduling trace.
+const SCHEDULING_TRACE_LEN: usize = 100;
+
+/// Number of administrative actions kept in the audit log.
+const AUDIT_LOG_LEN: usize = 1000;
+
+/// Number of hours kept in the report of idle resources.
+const IDLE_HISTORY_HOURS: i64 = 7 * 24;
+
+/// Maximum length of diffs of job outputs in bytes.
+const MAX_DIFF_LEN: usize = 256 * 1024;
+
+/// Fraction of the server's memory or job limit above which warnings are logged.
+const LIMIT_WARNING_RATIO: f64 = 0.8;
+
+/// Resident memory of the server process in megabytes.
+fn server_memory_mb() -> Option<u64> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = System::new();
+    system.refresh_process(pid);
+    system
+        .process(pid)
+        .map(|process| process.memory() / (1024 * 1024))
+}
+
+/// Jobs are considered similar if they are issued by the same user
+/// and run the same program in the same working directory.
+fn similarity_key(job_info: &JobInfo) -> String {
+    format!(
+        "{}:{}:{}",
+        job_info.user,
+        job_info.cwd.to_string_lossy(),
+        job_info.cmd.first().map(String::as_str).unwrap_or_default()
+    )
+}
+
+/// Route of a port-forwarding tunnel back to the client that opened it.
+#[derive(Clone)]
+pub struct TunnelRoute {
+    /// Channel to the connection of the client.
+    pub client_tx: mpsc::Sender<ServerToClientMessage>,
+    /// Tunnel ID as chosen by the client.
+    pub client_tunnel_id: u64,
 }
 
 impl Manager {
     pub fn new(config: Config) -> Self {
+        let offer_scans = match config.server_settings.max_concurrent_offer_scans {
+            0 => Semaphore::MAX_PERMITS,
+            permits => permits as usize,
+        };
         Self {
+            motd: config.server_settings.motd.clone(),
+            broadcasts: Vec::new(),
+            announcement_receivers: Vec::new(),
+            blocked_workers: BTreeMap::new(),
+            drained_workers: BTreeSet::new(),
+            reserved_workers: BTreeMap::new(),
+            audit_log: VecDeque::new(),
+            resource_history: BTreeMap::new(),
+            project_accounting: BTreeMap::new(),
+            idle_resources: BTreeMap::new(),
+            emergency: false,
+            store: None,
+            spool: None,
             config,
             jobs: BTreeMap::new(),
-            jobs_waiting_for_assignment: BTreeSet::new(),
+            jobs_waiting_for_assignment: Mutex::new(BTreeSet::new()),
+            global_assignment: Mutex::new(()),
+            assigned_jobs_per_user: Mutex::new(BTreeMap::new()),
+            scheduling_trace: Mutex::new(BTreeMap::new()),
+            jobs_waiting_for_dependencies: BTreeSet::new(),
             workers: BTreeMap::new(),
+            unreachable_workers: BTreeMap::new(),
+            group_observers: BTreeMap::new(),
+            tunnels: BTreeMap::new(),
+            next_tunnel_id: 0,
+            connections: BTreeMap::new(),
+            next_connection_id: 0,
             notify_new_jobs: Arc::new(Notify::new()),
+            offer_scan_permits: Arc::new(Semaphore::new(offer_scans)),
+            next_connection_slot: Instant::now(),
         }
     }
 
-    /// Registers a new worker to process jobs.
+    /// Point in time a newly connected worker is served. Workers connecting
+    /// at the same time are queued `reconnect_stagger_ms` apart, with random
+    /// jitter, so that their updates and offer scans are spread out.
+    pub fn reserve_connection_slot(&mut self) -> Instant {
+        let stagger =
+            std::time::Duration::from_millis(self.config.server_settings.reconnect_stagger_ms);
+        let slot = self.next_connection_slot.max(Instant::now());
+        self.next_connection_slot = slot + stagger;
+        slot + stagger.mul_f64(thread_rng().gen())
+    }
+
+    /// Registers a new worker to process jobs. If another connected worker
+    /// has the same name, the worker ID is appended to the new worker's name.
     pub fn add_new_worker(
         &mut self,
-        name: String,
+        mut info: WorkerInfo,
         kill_job_tx: mpsc::Sender<u64>,
+        forward_tx: mpsc::Sender<ServerToWorkerMessage>,
     ) -> Arc<Mutex<Worker>> {
-        let worker = Worker::new(name, kill_job_tx);
+        // A reconnecting worker is no longer unreachable.
+        self.unreachable_workers
+            .retain(|_, unreachable| unreachable.worker_name != info.worker_name);
+
+        if self.worker_name_in_use(&info.worker_name) {
+            let worker_name = format!("{}#{}", info.worker_name, info.worker_id);
+            log::warn!(
+                "Worker '{}' is already connected! Renamed new worker to '{}'.",
+                info.worker_name,
+                worker_name
+            );
+            info.worker_name = worker_name;
+        }
+
+        // Restore settings of administrators from before the reconnect.
+        info.draining = self.drained_workers.contains(&info.worker_name);
+        info.reserved_for = self.reserved_workers.get(&info.worker_name).cloned();
+
+        let worker = Worker::new(info, kill_job_tx, forward_tx);
         let worker_id = worker.info.worker_id;
         let worker = Arc::new(Mutex::new(worker));
         self.workers.insert(worker_id, Arc::downgrade(&worker));
         worker
     }
 
+    /// Drop a worker from the manager. Offered jobs are requeued. Running
+    /// jobs are requeued as well or, if `fail_jobs` is set, marked as failed.
+    /// The worker is blocked from reconnecting for `cooldown_minutes`. Returns
+    /// the worker's forwarding channel and the running jobs to be killed.
+    pub fn remove_worker(
+        &mut self,
+        worker_id: u64,
+        fail_jobs: bool,
+        cooldown_minutes: u64,
+    ) -> Result<(mpsc::Sender<ServerToWorkerMessage>, Vec<JobInfo>)> {
+        let worker = match self
+            .workers
+            .remove(&worker_id)
+            .map(|worker| worker.upgrade())
+        {
+            Some(Some(worker)) => worker,
+            Some(None) => bail!("Worker with ID={} is no longer available!", worker_id),
+            None => bail!("Worker with ID={} not found!", worker_id),
+        };
+        let (worker_name, forward_tx) = {
+            let worker_lock = worker.lock().unwrap();
+            (
+                worker_lock.info.worker_name.clone(),
+                worker_lock.forward_tx.clone(),
+            )
+        };
+        let blocked_until = Utc::now() + Duration::minutes(cooldown_minutes as i64);
+        self.blocked_workers.insert(worker_name, blocked_until);
+
+        // Recover jobs assigned to the worker.
+        let mut jobs_to_kill = Vec::new();
+        for (job_id, job) in &self.jobs {
+            let mut job_lock = job.lock().unwrap();
+            if job_lock.worker_id != Some(worker_id) {
+                continue;
+            }
+            match job_lock.info.status.clone() {
+                JobStatus::Offered { issued, .. } => {
+                    job_lock.info.status = JobStatus::Pending { issued };
+                }
+                JobStatus::Running {
+                    issued,
+                    started,
+                    worker,
+                } => {
+                    let mut kill_info = job_lock.info.clone();
+                    kill_info.status = JobStatus::Canceled {
+                        issued,
+                        canceled: Utc::now(),
+                        reason: None,
+                    };
+                    jobs_to_kill.push(kill_info);
+
+                    job_lock.info.status = if fail_jobs {
+                        let finished = Utc::now();
+                        JobStatus::Finished {
+                            issued,
+                            started,
+                            finished,
+                            return_code: EXIT_CODE_WORKER_LOST,
+                            worker,
+                            run_time_seconds: (finished - started).num_seconds(),
+                            comment: "Worker has been removed by an administrator.".into(),
+                            failure: Some(FailureKind::WorkerLost),
+                        }
+                    } else {
+                        job_lock.info.record_attempt();
+                        JobStatus::Pending { issued }
+                    };
+                }
+                _ => continue, // finished and canceled jobs are left as they are
+            }
+            job_lock.worker_id = None;
+            if job_lock.info.status.is_pending() {
+                self.jobs_waiting_for_assignment
+                    .get_mut()
+                    .unwrap()
+                    .insert(*job_id);
+            }
+            job_lock.notify_observers();
+        }
+        self.assigned_jobs_per_user
+            .get_mut()
+            .unwrap()
+            .remove(&worker_id);
+
+        // Let other workers pick up requeued jobs.
+        self.notify_new_jobs.notify_waiters();
+        self.resolve_dependencies();
+        Ok((forward_tx, jobs_to_kill))
+    }
+
+    /// Keep listing a worker that lost its connection or timed out as
+    /// unreachable. The worker is no longer considered for new jobs.
+    pub fn mark_worker_unreachable(&mut self, info: WorkerInfo) {
+        let mut info = self.worker_info_for_client(&info);
+        info.offline = true;
+        info.unreachable_since.get_or_insert_with(Utc::now);
+        log::warn!("Worker {} became unreachable!", info.worker_name);
+        self.workers.remove(&info.worker_id);
+        self.unreachable_workers.insert(info.worker_id, info);
+    }
+
+    /// Names of the workers preferred by the given jobs, if any. Jobs that
+    /// prefer the worker of another job that has not started yet are missing.
+    fn preferred_workers(&self, job_ids: &[u64]) -> BTreeMap<u64, String> {
+        let mut preferred_workers = BTreeMap::new();
+        for job_id in job_ids {
+            let Some(job) = self.jobs.get(job_id) else {
+                continue;
+            };
+            // Only lock one job at a time.
+            let affinity = job.lock().unwrap().info.affinity.clone();
+            let worker_name = match affinity {
+                Some(WorkerAffinity::Worker(worker_name)) => Some(worker_name),
+                Some(WorkerAffinity::WorkerOf(other_id)) => {
+                    self.jobs.get(&other_id).and_then(|other| {
+                        other
+                            .lock()
+                            .unwrap()
+                            .info
+                            .status
+                            .worker()
+                            .map(str::to_string)
+                    })
+                }
+                None => None,
+            };
+            if let Some(worker_name) = worker_name {
+                preferred_workers.insert(*job_id, worker_name);
+            }
+        }
+        preferred_workers
+    }
+
+    /// Free resources of the connected workers that accept new jobs, by name.
+    fn free_resources_of_workers(&self) -> BTreeMap<String, Resources> {
+        self.workers
+            .values()
+            .filter_map(Weak::upgrade)
+            .filter_map(|worker| {
+                let info = &worker.lock().unwrap().info;
+                (!info.draining).then(|| (info.worker_name.clone(), info.free_resources.clone()))
+            })
+            .collect()
+    }
+
+    /// Returns `true` if a connected worker has the given name.
+    pub fn worker_name_in_use(&self, worker_name: &str) -> bool {
+        self.workers
+            .values()
+            .filter_map(Weak::upgrade)
+            .any(|worker| worker.lock().unwrap().info.worker_name == worker_name)
+    }
+
+    /// Renames a connected worker. The new name must not be in use by
+    /// another connected worker. Jobs on the worker are updated as well.
+    pub fn rename_worker(&mut self, worker_id: u64, worker_name: &str) -> Result<()> {
+        if worker_name.trim().is_empty() {
+            bail!("Worker name must not be empty!");
+        }
+        let worker = match self
+            .get_worker(worker_id)
+            .and_then(|worker| worker.upgrade())
+        {
+            Some(worker) => worker,
+            None => bail!("Worker with ID={} not found!", worker_id),
+        };
+        let old_name = worker.lock().unwrap().info.worker_name.clone();
+        if old_name == worker_name {
+            return Ok(());
+        }
+        if self.worker_name_in_use(worker_name) {
+            bail!("Another worker is already named '{}'!", worker_name);
+        }
+        worker.lock().unwrap().info.worker_name = worker_name.to_string();
+        if self.drained_workers.remove(&old_name) {
+            self.drained_workers.insert(worker_name.to_string());
+        }
+        if let Some(user) = self.reserved_workers.remove(&old_name) {
+            self.reserved_workers.insert(worker_name.to_string(), user);
+        }
+
+        // Offered and running jobs refer to the worker by name.
+        for job in self.jobs.values() {
+            let mut job_lock = job.lock().unwrap();
+            if job_lock.worker_id != Some(worker_id) {
+                continue;
+            }
+            match &mut job_lock.info.status {
+                JobStatus::Offered { worker, .. } | JobStatus::Running { worker, .. } => {
+                    *worker = worker_name.to_string();
+                }
+                _ => continue,
+            }
+            job_lock.notify_observers();
+        }
+        log::info!("Renamed worker '{}' to '{}'!", old_name, worker_name);
+        Ok(())
+    }
+
+    /// Stops (`drain`) or resumes offering new jobs to a connected worker.
+    /// Running jobs are not affected. Returns the name of the worker.
+    pub fn drain_worker(&mut self, worker_id: u64, drain: bool) -> Result<String> {
+        let worker = match self
+            .get_worker(worker_id)
+            .and_then(|worker| worker.upgrade())
+        {
+            Some(worker) => worker,
+            None => bail!("Worker with ID={} not found!", worker_id),
+        };
+        let worker_name = {
+            let mut worker_lock = worker.lock().unwrap();
+            worker_lock.info.draining = drain;
+            worker_lock.info.worker_name.clone()
+        };
+        if drain {
+            self.drained_workers.insert(worker_name.clone());
+        } else {
+            self.drained_workers.remove(&worker_name);
+            self.notify_new_jobs.notify_waiters();
+        }
+        Ok(worker_name)
+    }
+
+    /// Offers a connected worker only jobs of the given user, or any jobs
+    /// again (`None`). Returns the name of the worker.
+    pub fn reserve_worker(&mut self, worker_id: u64, user: Option<String>) -> Result<String> {
+        let worker = match self
+            .get_worker(worker_id)
+            .and_then(|worker| worker.upgrade())
+        {
+            Some(worker) => worker,
+            None => bail!("Worker with ID={} not found!", worker_id),
+        };
+        let worker_name = {
+            let mut worker_lock = worker.lock().unwrap();
+            worker_lock.info.reserved_for = user.clone();
+            worker_lock.info.worker_name.clone()
+        };
+        match user {
+            Some(user) => self.reserved_workers.insert(worker_name.clone(), user),
+            None => self.reserved_workers.remove(&worker_name),
+        };
+        self.notify_new_jobs.notify_waiters();
+        Ok(worker_name)
+    }
+
+    /// Ask the worker running the job to stop it cleanly, so that the job can
+    /// be requeued on another worker. Returns the channel to the worker, to
+    /// which the StopJob message must be sent.
+    pub fn migrate_job(
+        &self,
+        job_id: u64,
+        migration: &JobMigration,
+    ) -> Result<mpsc::Sender<ServerToWorkerMessage>> {
+        let job = match self.get_job(job_id) {
+            Some(job) => job,
+            None => bail!("Job with ID={} not found!", job_id),
+        };
+        let (worker_id, interactive) = {
+            let job_lock = job.lock().unwrap();
+            if !job_lock.info.status.is_running() {
+                bail!("Job with ID={} is not running!", job_id);
+            }
+            (job_lock.worker_id, job_lock.info.interactive)
+        };
+        if interactive {
+            bail!("Interactive jobs cannot be migrated!");
+        }
+        let worker = match worker_id.and_then(|id| self.get_worker(id)?.upgrade()) {
+            Some(worker) => worker,
+            None => bail!("Worker of job {} is not connected!", job_id),
+        };
+
+        let worker_lock = worker.lock().unwrap();
+        if !worker_lock.info.has_capability(CAP_JOB_MIGRATION) {
+            bail!(
+                "Worker '{}' does not support job migration!",
+                worker_lock.info.worker_name
+            );
+        }
+        let mut job_lock = job.lock().unwrap();
+        job_lock.migration = Some(migration.clone());
+        job_lock.preempted = false;
+        job_lock.info.events.push(JobEvent {
+            time: Utc::now(),
+            text: format!(
+                "Migration requested: signal {} with a grace period of {} seconds.",
+                migration.signal, migration.grace_period_seconds
+            ),
+        });
+        Ok(worker_lock.forward_tx.clone())
+    }
+
+    /// Writes a copy of the job database to the given path on the server.
+    pub fn save_snapshot(&self, path: &Path) -> Result<()> {
+        match &self.store {
+            Some(store) => store.snapshot(path),
+            None => bail!("No database configured on the server!"),
+        }
+    }
+
+    /// Saves all jobs to the database, if configured, so that the server can
+    /// be restarted without losing any state. Returns the number of saved jobs.
+    pub fn persist_state(&self) -> Result<usize> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let jobs: Vec<_> = self.jobs.values().map(|job| job.lock().unwrap()).collect();
+        store.save_all(jobs.iter().map(|job| &**job))
+    }
+
+    /// Records a requested administrative action in the audit log.
+    pub fn record_admin_action(&mut self, entry: AuditEntry) {
+        if entry.authorized {
+            log::info!("Admin action by {}: {}", entry.remote_address, entry.action);
+        } else {
+            log::warn!(
+                "Unauthorized admin action by {}: {}",
+                entry.remote_address,
+                entry.action
+            );
+        }
+        self.audit_log.push_back(entry);
+        while self.audit_log.len() > AUDIT_LOG_LEN {
+            self.audit_log.pop_front();
+        }
+    }
+
+    /// Recently requested administrative actions, oldest first.
+    pub fn get_audit_log(&self) -> Vec<AuditEntry> {
+        self.audit_log.iter().cloned().collect()
+    }
+
+    /// Time since the worker has been lost, or `None` if the worker is still
+    /// connected and responsive. Workers that are no longer known have been
+    /// lost for an unknown, long time.
+    fn worker_lost_for(&self, worker_id: Option<u64>) -> Option<Duration> {
+        let worker_id = match worker_id {
+            Some(worker_id) => worker_id,
+            None => return Some(Duration::max_value()),
+        };
+        if let Some(worker) = self.workers.get(&worker_id).and_then(Weak::upgrade) {
+            let timeout = self.config.server_settings.worker_timeout_seconds;
+            // Timed-out workers are marked unreachable right now.
+            return worker
+                .lock()
+                .unwrap()
+                .info
+                .timed_out(timeout)
+                .then(Duration::zero);
+        }
+        match self.unreachable_workers.get(&worker_id) {
+            Some(info) => info.unreachable_since.map(|since| Utc::now() - since),
+            None => Some(Duration::max_value()),
+        }
+    }
+
+    /// Returns the time until which a removed worker may not reconnect.
+    pub fn worker_blocked_until(&mut self, worker_name: &str) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        self.blocked_workers.retain(|_, until| *until > now);
+        self.blocked_workers.get(worker_name).copied()
+    }
+
     /// Adds a new job to be processed.
     pub fn add_new_job(&mut self, job_info: JobInfo) -> Arc<Mutex<Job>> {
         // We create a new JobInfo instance to make sure to not
         // adopt remote (non-unique) job ids or inconsistent states.
-        let job = Job::from(job_info);
+        self.add_job(Job::from(job_info))
+    }
+
+    /// Adds a new job to be processed under an ID that has been reserved
+    /// for it with `reserve_job_id`.
+    pub fn add_new_job_with_reserved_id(
+        &mut self,
+        job_info: JobInfo,
+        job_id: u64,
+    ) -> Arc<Mutex<Job>> {
+        let mut job = Job::from(job_info);
+        job.info.job_id = job_id;
+        self.add_job(job)
+    }
+
+    fn add_job(&mut self, mut job: Job) -> Arc<Mutex<Job>> {
         let job_id = job.info.job_id;
+        let has_dependencies = !job.info.dependencies.is_empty();
+        job.store = self.store.clone();
+        job.spool = self.spool.clone();
+        job.save();
+
+        // Let observers of the job's group know about the new job.
+        if let Some(group) = &job.info.group {
+            if let Some(observers) = self.group_observers.get_mut(group) {
+                observers.retain(|observer| !observer.is_closed());
+                job.observers.extend(observers.iter().cloned());
+                job.notify_observers();
+            }
+        }
+
         let job = Arc::new(Mutex::new(job));
         self.jobs.insert(job_id, Arc::clone(&job));
-        self.jobs_waiting_for_assignment.insert(job_id);
+        if has_dependencies {
+            self.jobs_waiting_for_dependencies.insert(job_id);
+            self.resolve_dependencies();
+        } else {
+            self.jobs_waiting_for_assignment
+                .get_mut()
+                .unwrap()
+                .insert(job_id);
+        }
         job
     }
 
+    /// Persist jobs in the given database from now on. Jobs stored by a
+    /// previous run of the server are restored. Offered jobs are queued again.
+    /// Workers keep running their jobs while the server restarts, so running
+    /// jobs stay assigned to their worker, until the worker reconnects or the
+    /// grace period for lost workers has passed. Returns the number of
+    /// restored jobs.
+    pub fn attach_store(&mut self, store: Arc<JobStore>) -> Result<usize> {
+        let stored_jobs = store.load()?;
+        let restored = stored_jobs.len();
+        // Worker IDs of the previous run are not known, so running jobs are
+        // assigned to an unreachable worker of the same name meanwhile.
+        let mut lost_workers = BTreeMap::new();
+        for stored in stored_jobs {
+            let mut job = Job::restored(stored, Arc::clone(&store));
+            job.spool = self.spool.clone();
+            let job_id = job.info.job_id;
+            reserve_job_ids(job_id + 1);
+            match job.info.status {
+                JobStatus::Offered { issued, .. } => {
+                    log::info!("Requeue job {} after the restart of the server", job_id);
+                    job.info.status = JobStatus::Pending { issued };
+                    job.info.stalled = false;
+                    job.save();
+                }
+                JobStatus::Running { ref worker, .. } => {
+                    let worker_name = worker.clone();
+                    let worker_id = *lost_workers.entry(worker_name).or_insert_with_key(|name| {
+                        let mut info = WorkerInfo::new(name.clone(), String::new());
+                        info.offline = true;
+                        info.unreachable_since = Some(Utc::now());
+                        let worker_id = info.worker_id;
+                        self.unreachable_workers.insert(worker_id, info);
+                        worker_id
+                    });
+                    job.worker_id = Some(worker_id);
+                    self.count_assigned_job(worker_id, &job.info.user, job_id);
+                }
+                JobStatus::Finished { .. } => self.account_job(&job.info),
+                _ => {}
+            }
+            if job.info.status.is_pending() {
+                if job.info.dependencies.is_empty() {
+                    self.jobs_waiting_for_assignment
+                        .get_mut()
+                        .unwrap()
+                        .insert(job_id);
+                } else {
+                    self.jobs_waiting_for_dependencies.insert(job_id);
+                }
+            }
+            self.jobs.insert(job_id, Arc::new(Mutex::new(job)));
+        }
+        self.store = Some(store);
+        self.resolve_dependencies();
+        self.notify_new_jobs.notify_waiters();
+        Ok(restored)
+    }
+
+    /// Spool outputs of jobs to the given directory from now on. Attach the
+    /// spool before the database, so that restored jobs use it as well.
+    pub fn attach_spool(&mut self, spool: Arc<Spool>) {
+        self.spool = Some(spool);
+    }
+
+    /// Remove a job from the jobs known to the server, the database, and the
+    /// spool directory.
+    fn remove_job(&mut self, job_id: u64) {
+        self.jobs.remove(&job_id);
+        if let Some(store) = &self.store {
+            if let Err(e) = store.remove(job_id) {
+                log::error!("Failed to remove job {} from the database: {}", job_id, e);
+            }
+        }
+        if let Some(spool) = &self.spool {
+            spool.remove(job_id);
+        }
+    }
+
+    /// Register an observer for all current and future jobs of a submission
+    /// group. Returns the jobs currently in the group.
+    pub fn observe_group(&mut self, group: &str, observer: mpsc::Sender<u64>) -> Vec<JobInfo> {
+        let mut job_infos = Vec::new();
+        for job in self.jobs.values() {
+            let mut job_lock = job.lock().unwrap();
+            if job_lock.info.group.as_deref() == Some(group) {
+                job_lock.observers.push(observer.clone());
+                job_infos.push(job_lock.info.clone());
+            }
+        }
+
+        let observers = self.group_observers.entry(group.to_string()).or_default();
+        observers.retain(|observer| !observer.is_closed());
+        observers.push(observer);
+        job_infos
+    }
+
+    /// Register a live connection. The connection ID is assigned by the
+    /// manager and returned. Bytes transferred are read from `traffic`.
+    pub fn add_connection(&mut self, mut info: ConnectionInfo, traffic: Arc<Traffic>) -> u64 {
+        let connection_id = self.next_connection_id;
+        self.next_connection_id += 1;
+        info.connection_id = connection_id;
+        self.connections.insert(connection_id, (info, traffic));
+        connection_id
+    }
+
+    /// Unregister a closed connection.
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.connections.remove(&connection_id);
+    }
+
+    /// Returns all live connections, with up-to-date traffic counters.
+    pub fn get_connection_infos(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .values()
+            .map(|(info, traffic)| ConnectionInfo {
+                bytes_sent: traffic.sent(),
+                bytes_received: traffic.received(),
+                ..info.clone()
+            })
+            .collect()
+    }
+
+    /// Returns whether all dependencies of the job are met, or `None` if
+    /// some dependencies have not concluded yet. Dependencies on jobs that
+    /// are no longer known to the server are never met.
+    fn dependencies_met(&self, job_info: &JobInfo) -> Option<bool> {
+        let mut all_met = Some(true);
+        for dependency in &job_info.dependencies {
+            let met = match self.jobs.get(&dependency.job_id) {
+                Some(job) => dependency
+                    .condition
+                    .is_met(&job.lock().unwrap().info.status),
+                None => Some(false),
+            };
+            match met {
+                Some(false) => return Some(false),
+                None => all_met = None,
+                Some(true) => {}
+            }
+        }
+        all_met
+    }
+
+    /// Makes pending jobs available to workers once their dependencies are
+    /// met. Jobs whose dependencies can no longer be met are canceled.
+    pub fn resolve_dependencies(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for job_id in self.jobs_waiting_for_dependencies.clone() {
+                let job = match self.jobs.get(&job_id) {
+                    Some(job) => Arc::clone(job),
+                    None => {
+                        self.jobs_waiting_for_dependencies.remove(&job_id);
+                        continue;
+                    }
+                };
+                let job_info = job.lock().unwrap().info.clone();
+                let issued = match job_info.status {
+                    JobStatus::Pending { issued } => issued,
+                    _ => {
+                        // Job has been canceled in the meantime.
+                        self.jobs_waiting_for_dependencies.remove(&job_id);
+                        continue;
+                    }
+                };
+                match self.dependencies_met(&job_info) {
+                    Some(true) => {
+                        log::debug!("Dependencies of job {} are met!", job_id);
+                        self.jobs_waiting_for_dependencies.remove(&job_id);
+                        self.jobs_waiting_for_assignment
+                            .get_mut()
+                            .unwrap()
+                            .insert(job_id);
+                        self.notify_new_jobs.notify_waiters();
+                    }
+                    // Orphaned jobs keep waiting until they are removed.
+                    Some(false) if job_info.pending_reason.is_some() => {}
+                    Some(false) => {
+                        log::debug!("Dependencies of job {} can no longer be met!", job_id);
+                        self.jobs_waiting_for_dependencies.remove(&job_id);
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.status = JobStatus::Canceled {
+                            issued,
+                            canceled: Utc::now(),
+                            reason: Some("Dependencies can no longer be met.".into()),
+                        };
+                        job_lock.notify_observers();
+                        // Dependents of this job might be affected as well.
+                        changed = true;
+                    }
+                    None => {} // keep waiting
+                }
+            }
+        }
+    }
+
+    /// Returns the pending jobs depending on the given job, directly or via
+    /// other pending jobs.
+    fn dependents_of(&self, job_id: u64) -> BTreeSet<u64> {
+        let mut dependents = BTreeSet::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for dependent_id in &self.jobs_waiting_for_dependencies {
+                if dependents.contains(dependent_id) {
+                    continue;
+                }
+                if let Some(job) = self.jobs.get(dependent_id) {
+                    let depends = job
+                        .lock()
+                        .unwrap()
+                        .info
+                        .dependencies
+                        .iter()
+                        .any(|dependency| {
+                            dependency.job_id == job_id || dependents.contains(&dependency.job_id)
+                        });
+                    if depends {
+                        dependents.insert(*dependent_id);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        dependents
+    }
+
+    /// Cancels all pending jobs depending on the given job, directly or via
+    /// other pending jobs, whatever their dependency conditions. Returns the
+    /// number of canceled jobs.
+    pub fn cancel_dependents(&mut self, job_id: u64) -> usize {
+        let dependents = self.dependents_of(job_id);
+        for dependent_id in &dependents {
+            self.jobs_waiting_for_dependencies.remove(dependent_id);
+            let job = &self.jobs[dependent_id];
+            let mut job_lock = job.lock().unwrap();
+            if let JobStatus::Pending { issued } = job_lock.info.status {
+                let text = format!("Canceled along with job {}.", job_id);
+                job_lock.info.status = JobStatus::Canceled {
+                    issued,
+                    canceled: Utc::now(),
+                    reason: Some(text.clone()),
+                };
+                job_lock.info.events.push(JobEvent {
+                    time: Utc::now(),
+                    text,
+                });
+                job_lock.notify_observers();
+            }
+        }
+        dependents.len()
+    }
+
+    /// Keeps the pending jobs whose dependencies can no longer be met due to
+    /// the canceled job waiting, instead of canceling them. These jobs are
+    /// marked with a pending reason. Returns the number of orphaned jobs.
+    pub fn orphan_dependents(&mut self, job_id: u64) -> usize {
+        let status = match self.jobs.get(&job_id) {
+            Some(job) => job.lock().unwrap().info.status.clone(),
+            None => return 0,
+        };
+        let mut orphaned = 0;
+        for dependent_id in &self.jobs_waiting_for_dependencies {
+            let mut job_lock = match self.jobs.get(dependent_id) {
+                Some(job) => job.lock().unwrap(),
+                None => continue,
+            };
+            let unmet = job_lock.info.dependencies.iter().any(|dependency| {
+                dependency.job_id == job_id && dependency.condition.is_met(&status) == Some(false)
+            });
+            if unmet {
+                let reason = format!("Dependency canceled: job {} has been removed.", job_id);
+                job_lock.info.pending_reason = Some(reason);
+                job_lock.notify_observers();
+                orphaned += 1;
+            }
+        }
+        orphaned
+    }
+
+    /// Returns the priority of the job, or zero if the job is unknown.
+    fn job_priority(&self, job_id: u64) -> i32 {
+        self.jobs
+            .get(&job_id)
+            .map_or(0, |job| job.lock().unwrap().info.priority)
+    }
+
     /// Get job by ID.
     pub fn get_job(&self, job_id: u64) -> Option<Arc<Mutex<Job>>> {
         self.jobs.get(&job_id).map(Arc::clone)
@@ -68,20 +946,101 @@ impl Manager {
             .collect()
     }
 
+    /// Registers a new tunnel and returns its unique ID.
+    pub fn add_tunnel(&mut self, route: TunnelRoute) -> u64 {
+        let tunnel_id = self.next_tunnel_id;
+        self.next_tunnel_id += 1;
+        self.tunnels.insert(tunnel_id, route);
+        tunnel_id
+    }
+
+    /// Get the route of a tunnel back to its client.
+    pub fn get_tunnel(&self, tunnel_id: u64) -> Option<TunnelRoute> {
+        self.tunnels.get(&tunnel_id).cloned()
+    }
+
+    /// Removes a tunnel.
+    pub fn remove_tunnel(&mut self, tunnel_id: u64) -> Option<TunnelRoute> {
+        self.tunnels.remove(&tunnel_id)
+    }
+
+    /// Set or clear the message of the day.
+    pub fn set_motd(&mut self, text: Option<String>) {
+        self.motd = text;
+    }
+
+    /// Message of the day and active broadcasts, to be sent to a new client.
+    /// The client is also registered to receive future broadcasts.
+    pub fn subscribe_announcements(
+        &mut self,
+        client_tx: mpsc::Sender<ServerToClientMessage>,
+    ) -> Vec<ServerToClientMessage> {
+        let now = Utc::now();
+        self.broadcasts.retain(|(_, expires)| *expires > now);
+        self.announcement_receivers.retain(|tx| !tx.is_closed());
+        self.announcement_receivers.push(client_tx);
+
+        let motd = self
+            .motd
+            .iter()
+            .map(|text| ServerToClientMessage::Announcement {
+                text: text.clone(),
+                broadcast: false,
+            });
+        let broadcasts =
+            self.broadcasts
+                .iter()
+                .map(|(text, _)| ServerToClientMessage::Announcement {
+                    text: text.clone(),
+                    broadcast: true,
+                });
+        motd.chain(broadcasts).collect()
+    }
+
+    /// Remember a broadcast for clients connecting before it expires.
+    /// Returns the currently connected clients to send the broadcast to.
+    pub fn add_broadcast(
+        &mut self,
+        text: String,
+        expires_in_minutes: u64,
+    ) -> Vec<mpsc::Sender<ServerToClientMessage>> {
+        let expires = Utc::now() + Duration::minutes(expires_in_minutes as i64);
+        self.broadcasts.push((text, expires));
+        self.announcement_receivers.retain(|tx| !tx.is_closed());
+        self.announcement_receivers.clone()
+    }
+
     /// Get worker by ID.
     pub fn get_worker(&self, worker_id: u64) -> Option<Weak<Mutex<Worker>>> {
         self.workers.get(&worker_id).map(Weak::clone)
     }
 
+    /// Last known information about an unreachable worker.
+    pub fn get_unreachable_worker(&self, worker_id: u64) -> Option<WorkerInfo> {
+        self.unreachable_workers.get(&worker_id).cloned()
+    }
+
     /// Collect worker information about all workers.
     pub fn get_all_worker_infos(&self) -> Vec<WorkerInfo> {
         self.workers
             .values()
             .filter_map(Weak::upgrade)
-            .map(|worker| worker.lock().unwrap().info.clone())
+            .map(|worker| self.worker_info_for_client(&worker.lock().unwrap().info))
+            .chain(self.unreachable_workers.values().cloned())
             .collect()
     }
 
+    /// Copy of the worker information with fields that are derived by the
+    /// server, such as the `offline` flag, filled in.
+    pub fn worker_info_for_client(&self, info: &WorkerInfo) -> WorkerInfo {
+        let mut info = info.clone();
+        info.offline = info.timed_out(self.config.server_settings.worker_timeout_seconds);
+        if info.offline {
+            info.unreachable_since = Some(info.last_updated);
+        }
+        info
+    }
+
     /// Get occupied global resources.
     pub fn get_used_global_resources(&self) -> Option<BTreeMap<String, u64>> {
         // Get resources used by offered and running jobs.
@@ -141,42 +1100,336 @@ impl Manager {
         }
     }
 
-    /// Get a job to be assigned to a worker.
+    /// Counts the job towards the per-user limit of the worker it has been
+    /// assigned to.
+    fn count_assigned_job(&self, worker_id: u64, user: &str, job_id: u64) {
+        let mut assigned = self.assigned_jobs_per_user.lock().unwrap();
+        let users = assigned.entry(worker_id).or_default();
+        users.entry(user.to_string()).or_default().insert(job_id);
+    }
+
+    /// Stops counting the job towards the per-user limit of the worker, e.g.,
+    /// when the job has finished or has been handed back by the worker.
+    pub fn release_assigned_job(&self, worker_id: u64, job_id: u64) {
+        let mut assigned = self.assigned_jobs_per_user.lock().unwrap();
+        if let Some(users) = assigned.get_mut(&worker_id) {
+            for jobs in users.values_mut() {
+                jobs.remove(&job_id);
+            }
+            users.retain(|_, jobs| !jobs.is_empty());
+            if users.is_empty() {
+                assigned.remove(&worker_id);
+            }
+        }
+    }
+
+    /// Makes a job that has been handed back by its worker available to all
+    /// workers again, like jobs recovered from removed workers.
+    pub fn requeue_job(&self, job_id: u64) {
+        self.jobs_waiting_for_assignment
+            .lock()
+            .unwrap()
+            .insert(job_id);
+        self.notify_new_jobs.notify_waiters();
+    }
+
+    /// Number of jobs of the user offered to or running on the worker.
+    fn assigned_jobs_of_user(&self, worker_id: u64, user: &str) -> u64 {
+        let assigned = self.assigned_jobs_per_user.lock().unwrap();
+        let jobs = assigned.get(&worker_id).and_then(|users| users.get(user));
+        jobs.map_or(0, |jobs| jobs.len() as u64)
+    }
+
+    /// Recounts the jobs assigned to workers, in case a job has left its
+    /// worker without being released.
+    fn recount_assigned_jobs(&mut self) {
+        let mut assigned: BTreeMap<u64, BTreeMap<String, BTreeSet<u64>>> = BTreeMap::new();
+        for (job_id, job) in &self.jobs {
+            let job_lock = job.lock().unwrap();
+            if let Some(worker_id) = job_lock.worker_id {
+                if job_lock.info.status.is_offered() || job_lock.info.status.is_running() {
+                    let users = assigned.entry(worker_id).or_default();
+                    users
+                        .entry(job_lock.info.user.clone())
+                        .or_default()
+                        .insert(*job_id);
+                }
+            }
+        }
+        *self.assigned_jobs_per_user.get_mut().unwrap() = assigned;
+    }
+
+    /// Get a job to be assigned to a worker. Only requires a read lock on
+    /// the manager, so that many workers can look for jobs concurrently.
+    /// A job is claimed by removing it from the waiting queue while holding
+    /// the job's lock, so it is never handed out twice.
     pub fn get_job_waiting_for_assignment(
-        &mut self,
+        &self,
         worker_id: u64,
         worker_name: &str,
         exclude: &BTreeSet<u64>,
         resource_limit: &Resources,
+        pool_limits: &BTreeMap<String, Resources>,
     ) -> Option<Arc<Mutex<Job>>> {
-        if self.jobs_waiting_for_assignment.is_empty() {
+        if self.jobs_waiting_for_assignment.lock().unwrap().is_empty() {
             // No jobs marked waiting for assignment.
             None
         } else {
+            // Global resources and project quotas are shared among all
+            // workers and must not be claimed by two workers at once.
+            let projects = &self.config.server_settings.projects;
+            let project_limits = projects
+                .iter()
+                .any(|project| project.max_running_jobs.is_some() || project.cpu_hours.is_some());
+            let qos_limits = self
+                .config
+                .server_settings
+                .qos_classes
+                .iter()
+                .any(|class| class.max_running_jobs_per_user.is_some());
+            let _global_assignment =
+                (self.config.global_resources.is_some() || project_limits || qos_limits)
+                    .then(|| self.global_assignment.lock().unwrap());
+
             // Get available global resources.
             let free_resources = self.get_free_global_resources();
 
-            // Get jobs.
-            let job_ids: Vec<u64> = self
-                .jobs_waiting_for_assignment
-                .iter()
-                .filter(|job_id| !exclude.contains(job_id))
-                .cloned()
-                .collect();
+            // Interactive and GPU jobs can only be assigned to capable workers.
+            let has_capability = |capability| {
+                self.workers
+                    .get(&worker_id)
+                    .and_then(Weak::upgrade)
+                    .is_some_and(|worker| worker.lock().unwrap().info.has_capability(capability))
+            };
+            let interactive_supported = has_capability(CAP_INTERACTIVE_JOBS);
+            let gpus_supported = has_capability(CAP_GPU_SCHEDULING);
+            let core_binding_supported = has_capability(CAP_CORE_BINDING);
+            let process_limits_supported = has_capability(CAP_PROCESS_LIMITS);
 
-            'outer: for job_id in job_ids {
-                if let Some(job) = self.jobs.get(&job_id) {
-                    let mut job_lock = job.lock().unwrap();
-                    // Check required worker resources.
-                    if job_lock.info.worker_resources.fit_into(resource_limit) {
-                        // Also check global resources.
+            // Reserved workers only receive jobs of one user.
+            let reserved_for = self
+                .workers
+                .get(&worker_id)
+                .and_then(Weak::upgrade)
+                .and_then(|worker| worker.lock().unwrap().info.reserved_for.clone());
+
+            // Jobs with constraints only run on workers with matching labels.
+            let labels = self
+                .workers
+                .get(&worker_id)
+                .and_then(Weak::upgrade)
+                .map(|worker| worker.lock().unwrap().info.labels.clone())
+                .unwrap_or_default();
+
+            // Jobs per user already assigned to the worker are counted on
+            // assignment and release.
+            let max_jobs_per_user = self.config.server_settings.max_jobs_per_user_per_worker;
+
+            // Count running jobs per project and find projects out of CPU hours.
+            let mut jobs_per_project: BTreeMap<String, u64> = BTreeMap::new();
+            let mut exhausted_projects = BTreeSet::new();
+            if project_limits {
+                for job in self.jobs.values() {
+                    let job_lock = job.lock().unwrap();
+                    if let Some(project) = &job_lock.info.project {
+                        if job_lock.info.status.is_offered() || job_lock.info.status.is_running() {
+                            *jobs_per_project.entry(project.clone()).or_default() += 1;
+                        }
+                    }
+                }
+                for project in projects {
+                    if self.project_cpu_hours_exhausted(project) {
+                        exhausted_projects.insert(project.name.clone());
+                    }
+                }
+            }
+
+            // Count running jobs per user and QoS class.
+            let mut jobs_per_user_and_qos: BTreeMap<(String, String), u64> = BTreeMap::new();
+            if qos_limits {
+                for job in self.jobs.values() {
+                    let job_lock = job.lock().unwrap();
+                    if let Some(qos) = &job_lock.info.qos {
+                        if job_lock.info.status.is_offered() || job_lock.info.status.is_running() {
+                            let key = (job_lock.info.user.clone(), qos.clone());
+                            *jobs_per_user_and_qos.entry(key).or_default() += 1;
+                        }
+                    }
+                }
+            }
+
+            // Get jobs, highest priority first.
+            let mut job_ids: Vec<u64> = self
+                .jobs_waiting_for_assignment
+                .lock()
+                .unwrap()
+                .iter()
+                .cloned()
+                .collect();
+            job_ids.sort_by_cached_key(|job_id| Reverse(self.job_priority(*job_id)));
+
+            // Jobs with a preferred worker are left to it while it has room.
+            let preferred_workers = self.preferred_workers(&job_ids);
+            let free_workers = if preferred_workers.is_empty() {
+                BTreeMap::new()
+            } else {
+                self.free_resources_of_workers()
+            };
+            let affinity_wait =
+                Duration::seconds(self.config.server_settings.affinity_wait_seconds as i64);
+
+            'outer: for job_id in job_ids {
+                // Records why the job is not offered to the worker.
+                let skip = |reason: &dyn Fn() -> String| {
+                    self.trace_scheduling(job_id, worker_name, SchedulingOutcome::Skipped, reason)
+                };
+                if exclude.contains(&job_id) {
+                    skip(&|| "previously rejected or deferred by the worker".into());
+                    continue;
+                }
+                if let Some(job) = self.jobs.get(&job_id) {
+                    let mut job_lock = job.lock().unwrap();
+                    if job_lock.info.interactive && !interactive_supported {
+                        skip(&|| "worker does not support interactive jobs".into());
+                        continue;
+                    }
+                    if job_lock.info.gpus > 0 && !gpus_supported {
+                        skip(&|| "worker does not support GPU jobs".into());
+                        continue;
+                    }
+                    if job_lock.info.core_binding.is_some() && !core_binding_supported {
+                        skip(&|| "worker does not support core binding".into());
+                        continue;
+                    }
+                    if job_lock.info.process_limits != ProcessLimits::default()
+                        && !process_limits_supported
+                    {
+                        skip(&|| "worker does not support process limits".into());
+                        continue;
+                    }
+                    if job_lock.info.avoid_workers.contains(worker_name) {
+                        skip(&|| "job avoids the worker after a failed run".into());
+                        continue;
+                    }
+                    if let Some(preferred) = preferred_workers.get(&job_id) {
+                        let has_room = free_workers.get(preferred).is_some_and(|free| {
+                            job_lock.info.worker_resources.fit_into(free)
+                                && !job_lock.info.avoid_workers.contains(preferred)
+                        });
+                        if preferred != worker_name && has_room {
+                            let deadline = *job_lock
+                                .affinity_deadline
+                                .get_or_insert_with(|| Utc::now() + affinity_wait);
+                            if Utc::now() < deadline {
+                                skip(&|| {
+                                    format!("job prefers worker {}, which has room", preferred)
+                                });
+                                continue;
+                            }
+                        }
+                    }
+                    let unmet = job_lock
+                        .info
+                        .constraints
+                        .iter()
+                        .find(|(label, value)| labels.get(*label) != Some(*value));
+                    if let Some((label, value)) = unmet {
+                        skip(&|| format!("worker lacks label {}={}", label, value));
+                        continue;
+                    }
+                    if let Some(user) = &reserved_for {
+                        if &job_lock.info.user != user {
+                            skip(&|| format!("worker is reserved for user {}", user));
+                            continue;
+                        }
+                    }
+                    // Respect the per-user limit on the worker.
+                    if let Some(max_jobs) = max_jobs_per_user {
+                        let assigned = self.assigned_jobs_of_user(worker_id, &job_lock.info.user);
+                        if assigned >= max_jobs {
+                            skip(&|| format!("user already has {} jobs on the worker", max_jobs));
+                            continue;
+                        }
+                    }
+                    // Respect the quotas of the job's project.
+                    if let Some(project) = &job_lock.info.project {
+                        if exhausted_projects.contains(project) {
+                            skip(&|| format!("project '{}' has used up its CPU hours", project));
+                            continue;
+                        }
+                        let max_jobs = self.get_project(project).and_then(|p| p.max_running_jobs);
+                        let running = jobs_per_project.get(project).copied().unwrap_or(0);
+                        if max_jobs.is_some_and(|max_jobs| running >= max_jobs) {
+                            skip(&|| format!("project '{}' has {} running jobs", project, running));
+                            continue;
+                        }
+                    }
+                    // Respect the per-user limit of the job's QoS class.
+                    if let Some(qos) = &job_lock.info.qos {
+                        let class = self.get_qos_class(qos);
+                        let max_jobs = class.and_then(|class| class.max_running_jobs_per_user);
+                        let key = (job_lock.info.user.clone(), qos.clone());
+                        let running = jobs_per_user_and_qos.get(&key).copied().unwrap_or(0);
+                        if max_jobs.is_some_and(|max_jobs| running >= max_jobs) {
+                            skip(&|| {
+                                format!("user has {} running jobs of class '{}'", running, qos)
+                            });
+                            continue;
+                        }
+                    }
+                    // Jobs in a pool only run within the pool's resources.
+                    let resource_limit = match &job_lock.info.pool {
+                        Some(pool) => match pool_limits.get(pool) {
+                            Some(pool_limit) => pool_limit,
+                            None => {
+                                skip(&|| format!("worker has no pool '{}'", pool));
+                                continue;
+                            }
+                        },
+                        None => resource_limit,
+                    };
+                    // Check required worker resources.
+                    if !job_lock.info.worker_resources.fit_into(resource_limit) {
+                        let required = &job_lock.info.worker_resources;
+                        let lacking_named = required.named.iter().find(|(name, amount)| {
+                            resource_limit.named.get(*name).copied().unwrap_or(0) < **amount
+                        });
+                        skip(&|| match lacking_named {
+                            Some((name, amount)) => format!(
+                                "requires {}x {} but {} are free",
+                                amount,
+                                name,
+                                resource_limit.named.get(name).copied().unwrap_or(0)
+                            ),
+                            None => format!(
+                                "requires {} slots, {} cpus, {} MB RAM but {} slots, \
+                                {} cpus, {} MB RAM are free",
+                                required.job_slots,
+                                required.cpus,
+                                required.ram_mb,
+                                resource_limit.job_slots,
+                                resource_limit.cpus,
+                                resource_limit.ram_mb
+                            ),
+                        });
+                    } else {
+                        // Also check global resources.
                         if let Some(job_resources) = &job_lock.info.global_resources {
                             if let Some(free_resources) = &free_resources {
                                 for (resource, required) in job_resources {
                                     match free_resources.get(resource) {
                                         Some(free) if free >= required => {} // fulfilled
-                                        _ => {
+                                        free => {
                                             // Requirement failed. Continue with next job in list.
+                                            skip(&|| {
+                                                format!(
+                                                    "requires {} of global resource '{}' but {} \
+                                                    are free",
+                                                    required,
+                                                    resource,
+                                                    free.copied().unwrap_or(0)
+                                                )
+                                            });
                                             continue 'outer;
                                         }
                                     }
@@ -187,8 +1440,17 @@ impl Manager {
                             }
                         }
 
-                        // Found matching job.
-                        self.jobs_waiting_for_assignment.remove(&job_id);
+                        // Found matching job. Skip it, if another worker
+                        // claimed it in the meantime.
+                        let claimed = self
+                            .jobs_waiting_for_assignment
+                            .lock()
+                            .unwrap()
+                            .remove(&job_id);
+                        if !claimed {
+                            skip(&|| "claimed by another worker".into());
+                            continue;
+                        }
 
                         // To avoid the returned job being immediately picked
                         // up again by the maintenance routine, we update the
@@ -214,10 +1476,18 @@ impl Manager {
 
                         // Set worker reference.
                         job_lock.worker_id = Some(worker_id);
+                        job_lock.info.stalled = false;
+                        self.count_assigned_job(worker_id, &job_lock.info.user, job_id);
 
                         // Notify observers of the job.
                         job_lock.notify_observers();
 
+                        self.trace_scheduling(
+                            job_id,
+                            worker_name,
+                            SchedulingOutcome::Offered,
+                            &String::new,
+                        );
                         return Some(Arc::clone(job));
                     }
                 }
@@ -227,267 +1497,3353 @@ impl Manager {
         }
     }
 
-    /// Cancel and remove a job from the queue. If the job is running and a
-    /// worker is associated with the job, a sender is returned that can be
-    /// used to signal a kill instruction to the worker. The job_id sent over
-    /// the returned sender indicates the job to be killed on the worker.
-    pub fn cancel_job(&mut self, job_id: u64, kill: bool) -> Result<Option<mpsc::Sender<u64>>> {
-        match self.get_job(job_id) {
-            Some(job) => {
-                let job_info = job.lock().unwrap().info.clone();
-                match job_info.status {
-                    JobStatus::Pending { issued } => {
-                        // Do not attempt to offer the job to workers.
-                        self.jobs_waiting_for_assignment.remove(&job_id);
-                        job.lock().unwrap().info.status = JobStatus::Canceled {
-                            issued,
-                            canceled: Utc::now(),
-                        };
-                        Ok(None)
-                    }
-                    JobStatus::Offered { issued, .. } => {
-                        // Offer will be withdrawn by the server on worker's response.
-                        job.lock().unwrap().info.status = JobStatus::Canceled {
-                            issued,
-                            canceled: Utc::now(),
-                        };
-                        Ok(None)
-                    }
-                    JobStatus::Running { issued, .. } => {
-                        if !kill {
-                            // Makes no sense to set the job to canceled if the
-                            // worker proceeds anyway.
-                            bail!("Job ID={} has already started!", job_id);
-                        }
-                        // Update job status
-                        job.lock().unwrap().info.status = JobStatus::Canceled {
-                            issued,
-                            canceled: Utc::now(),
-                        };
-                        // If worker is assigned and alive, get the kill job sender.
-                        let worker_id = job.lock().unwrap().worker_id;
-                        if let Some(worker_id) = worker_id {
-                            if let Some(worker) = self.workers.get(&worker_id) {
-                                if let Some(worker) = worker.upgrade() {
-                                    let tx = worker.lock().unwrap().kill_job_tx.clone();
-                                    return Ok(Some(tx));
-                                }
-                            }
-                        }
-                        bail!(
-                            "Job with ID={} was running but worker could not be acquired!",
-                            job_id
-                        )
-                    }
-                    JobStatus::Finished { .. } => bail!("Job ID={} has already finished!", job_id),
-                    JobStatus::Canceled { .. } => bail!("Job ID={} is already canceled!", job_id),
-                }
+    /// Records a scheduling decision about the job, if the scheduling trace
+    /// is enabled. The reason is only evaluated when it is recorded.
+    pub fn trace_scheduling(
+        &self,
+        job_id: u64,
+        worker_name: &str,
+        outcome: SchedulingOutcome,
+        reason: &dyn Fn() -> String,
+    ) {
+        if !self.config.server_settings.scheduling_trace {
+            return;
+        }
+        let event = SchedulingEvent {
+            time: Utc::now(),
+            worker_name: worker_name.to_string(),
+            outcome,
+            reason: reason(),
+        };
+        log::info!(
+            "scheduling_trace job={} worker={} outcome={} reason={:?}",
+            job_id,
+            event.worker_name,
+            event.outcome,
+            event.reason
+        );
+
+        let mut scheduling_trace = self.scheduling_trace.lock().unwrap();
+        let events = scheduling_trace.entry(job_id).or_default();
+        events.push_back(event);
+        if events.len() > SCHEDULING_TRACE_LEN {
+            events.pop_front();
+        }
+    }
+
+    /// Returns the recorded scheduling decisions about the job, oldest first.
+    pub fn get_scheduling_trace(&self, job_id: u64) -> Vec<SchedulingEvent> {
+        match self.scheduling_trace.lock().unwrap().get(&job_id) {
+            Some(events) => events.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remembers the measured resource usage of a succeeded job.
+    pub fn record_resource_usage(&mut self, job_info: &JobInfo) {
+        if !job_info.status.has_succeeded() {
+            return; // failed jobs might have ended early
+        }
+        if let Some(usage) = &job_info.resource_usage {
+            let history = self
+                .resource_history
+                .entry(similarity_key(job_info))
+                .or_default();
+            history.push_back(usage.clone());
+            if history.len() > RESOURCE_HISTORY_LEN {
+                history.pop_front();
             }
-            None => bail!("Job with ID={} not found!", job_id),
         }
     }
 
-    /// Remove jobs that have been finished or canceled.
-    /// If `all` is true, also remove failed jobs.
-    pub fn clean_jobs(&mut self, all: bool) {
-        let clean_pred = if all {
-            |status: &JobStatus| status.is_finished() || status.is_canceled()
-        } else {
-            |status: &JobStatus| status.has_succeeded() || status.is_canceled()
+    /// Accounts a finished job to its project.
+    pub fn account_job(&mut self, job_info: &JobInfo) {
+        if let JobStatus::Finished {
+            run_time_seconds, ..
+        } = job_info.status
+        {
+            let usage = self
+                .project_accounting
+                .entry(job_info.project.clone())
+                .or_default();
+            if job_info.status.has_succeeded() {
+                usage.jobs_succeeded += 1;
+            } else {
+                usage.jobs_failed += 1;
+            }
+            usage.cpu_hours +=
+                job_info.worker_resources.cpus as f64 * run_time_seconds.max(0) as f64 / 3600.0;
+        }
+    }
+
+    /// Writes the accounting of a finished job to the database, if any.
+    pub fn record_accounting(&self, job_info: &JobInfo) {
+        let (Some(store), Some(record)) = (&self.store, AccountingRecord::from_job_info(job_info))
+        else {
+            return;
         };
+        if let Err(e) = store.record_accounting(&record) {
+            log::error!(
+                "Failed to record accounting of job {}: {}",
+                job_info.job_id,
+                e
+            );
+        }
+    }
 
-        self.jobs
-            .retain(|_, job| !clean_pred(&job.lock().unwrap().info.status));
+    /// Returns the usage per user from the accounting in the database.
+    pub fn get_user_usage(
+        &self,
+        user: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UserUsage>> {
+        match &self.store {
+            Some(store) => store.query_accounting(user, since, until),
+            None => bail!("No database configured on the server!"),
+        }
     }
 
-    /// Perform regular maintenance.
-    pub fn run_maintenance(&mut self) {
-        // Inspect every job and "repair" if needed.
-        let mut jobs_to_be_removed: Vec<u64> = Vec::new();
-        let mut new_jobs_pending = false;
+    /// Returns the configured QoS class with the given name.
+    pub fn get_qos_class(&self, name: &str) -> Option<&QosClass> {
+        let classes = &self.config.server_settings.qos_classes;
+        classes.iter().find(|class| class.name == name)
+    }
 
-        for (job_id, job) in &self.jobs {
-            let info = job.lock().unwrap().info.clone();
-            match &info.status {
-                JobStatus::Pending { .. } => {
-                    // Pending jobs should be available for workers.
-                    let newly_inserted = self.jobs_waiting_for_assignment.insert(*job_id);
-                    if newly_inserted {
-                        log::warn!("Job {} was pending but not available for workers!", job_id);
-                    }
+    /// Assign the job to its QoS class, or the default class, and apply the
+    /// class's priority and run-time limit. Fails for unknown classes.
+    pub fn apply_qos_class(&self, job_info: &mut JobInfo) -> Result<()> {
+        let name = match job_info
+            .qos
+            .clone()
+            .or(self.config.server_settings.default_qos.clone())
+        {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let class = match self.get_qos_class(&name) {
+            Some(class) => class,
+            None => {
+                let classes = &self.config.server_settings.qos_classes;
+                let names: Vec<&str> = classes.iter().map(|class| class.name.as_str()).collect();
+                if names.is_empty() {
+                    bail!("No QoS classes configured on the server!");
                 }
-                JobStatus::Offered {
-                    issued,
-                    offered,
-                    worker: _,
-                } => {
-                    // A job should only be briefly in this state.
-                    let offer_timed_out = (Utc::now() - *offered).num_seconds()
-                        > self.config.server_settings.job_offer_timeout_seconds as i64;
-                    let worker_id = job.lock().unwrap().worker_id;
-                    let worker_alive = match worker_id {
-                        Some(id) => match self.workers.get(&id) {
-                            Some(weak_worker) => match weak_worker.upgrade() {
-                                Some(worker) => {
-                                    // Worker is still with us.
-                                    !worker.lock().unwrap().info.timed_out(
-                                        self.config.server_settings.worker_timeout_seconds,
-                                    )
-                                }
-                                None => false,
-                            },
-                            None => false,
-                        },
-                        None => false,
-                    };
+                bail!(
+                    "Unknown QoS class '{}'! Known classes: {}",
+                    name,
+                    names.join(", ")
+                )
+            }
+        };
+        job_info.priority = job_info.priority.saturating_add(class.priority);
+        if let Some(max_runtime) = class.max_runtime_seconds {
+            let timeout = job_info.wall_time_limit_seconds.unwrap_or(max_runtime);
+            job_info.wall_time_limit_seconds = Some(timeout.min(max_runtime));
+        }
+        job_info.qos = Some(name);
+        Ok(())
+    }
 
-                    // Recover if offer timed out or worker died.
-                    if offer_timed_out || !worker_alive {
-                        log::warn!("Job {:?} got stuck in offered state. Recover...", info);
-                        let mut job_lock = job.lock().unwrap();
-                        job_lock.info.status = JobStatus::Pending { issued: *issued };
-                        job_lock.worker_id = None;
-                        self.jobs_waiting_for_assignment.insert(*job_id);
-                        new_jobs_pending = true; // notify at the end
-                    }
-                }
-                JobStatus::Running {
-                    issued,
-                    started: _,
-                    worker,
-                } => {
-                    // If the job is running, the worker should still be alive.
-                    let worker_id = job.lock().unwrap().worker_id;
-                    let worker_alive = match worker_id {
-                        Some(id) => match self.workers.get(&id) {
-                            Some(weak_worker) => match weak_worker.upgrade() {
-                                Some(worker) => {
-                                    // Worker is still with us.
-                                    !worker.lock().unwrap().info.timed_out(
-                                        self.config.server_settings.worker_timeout_seconds,
-                                    )
-                                }
-                                None => false,
-                            },
-                            None => false,
-                        },
-                        None => false,
-                    };
+    /// Returns `true` if running jobs of the QoS class can be preempted.
+    fn is_preemptible(&self, qos: &Option<String>) -> bool {
+        qos.as_deref()
+            .and_then(|qos| self.get_qos_class(qos))
+            .is_some_and(|class| class.preemptible)
+    }
 
-                    // Recover if worker died while the job was still running.
-                    if !worker_alive {
-                        log::warn!(
-                            "Worker {} died while job {:?} was still running. Recover...",
-                            worker,
-                            info
-                        );
-                        let mut job_lock = job.lock().unwrap();
-                        job_lock.info.status = JobStatus::Pending { issued: *issued };
-                        job_lock.worker_id = None;
-                        self.jobs_waiting_for_assignment.insert(*job_id);
-                        new_jobs_pending = true; // notify at the end
-                    }
-                }
-                JobStatus::Finished { finished, .. } => {
-                    // Finished jobs should be cleaned up after some time.
-                    let cleanup_job = (Utc::now() - *finished).num_minutes()
-                        > self.config.server_settings.job_cleanup_after_minutes as i64;
-                    if cleanup_job {
-                        log::debug!("Clean up old finished job: {:?}", info);
-                        jobs_to_be_removed.push(info.job_id);
-                    }
-                }
-                JobStatus::Canceled { canceled, .. } => {
-                    // Canceled jobs should be cleaned up after some time.
-                    let cleanup_job = (Utc::now() - *canceled).num_minutes()
-                        > self.config.server_settings.job_cleanup_after_minutes as i64;
-                    if cleanup_job {
-                        log::debug!("Clean up old canceled job: {:?}", info);
-                        jobs_to_be_removed.push(info.job_id);
+    /// Cancel interactive jobs that have been running for longer than
+    /// `unattached_job_timeout_minutes` without a client attached to them.
+    /// Returns the channels to kill the jobs on their workers.
+    pub fn cancel_unattached_jobs(&mut self) -> Vec<(mpsc::Sender<u64>, u64)> {
+        let minutes = self.config.server_settings.unattached_job_timeout_minutes;
+        if minutes == 0 {
+            return Vec::new();
+        }
+        let timeout = Duration::minutes(minutes as i64);
+        let unattached_jobs: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| {
+                let job_lock = job.lock().unwrap();
+                match job_lock.info.status {
+                    JobStatus::Running { started, .. } => {
+                        job_lock.info.interactive
+                            && job_lock.attached_client.is_none()
+                            && Utc::now() - started > timeout
                     }
+                    _ => false,
                 }
+            })
+            .map(|(job_id, _)| *job_id)
+            .collect();
+
+        let mut kills = Vec::new();
+        for job_id in unattached_jobs {
+            log::warn!("No client attached to interactive job {job_id}. Cancel...");
+            let reason = Some("No client has attached to the interactive job.".into());
+            match self.cancel_job(job_id, true, reason) {
+                Ok(Some(kill_job_tx)) => kills.push((kill_job_tx, job_id)),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to cancel interactive job {job_id}: {e}"),
             }
         }
+        if !kills.is_empty() {
+            self.resolve_dependencies();
+        }
+        kills
+    }
 
-        // Clean up jobs.
-        for job_id in jobs_to_be_removed {
-            self.jobs.remove(&job_id);
+    /// Stop running jobs of preemptible QoS classes, so that pending jobs of
+    /// other classes with a higher priority can start. Returns the channels
+    /// to the workers and the StopJob messages to be sent to them.
+    pub fn preempt_jobs(
+        &self,
+    ) -> Vec<(mpsc::Sender<ServerToWorkerMessage>, ServerToWorkerMessage)> {
+        let classes = &self.config.server_settings.qos_classes;
+        if !classes.iter().any(|class| class.preemptible) {
+            return Vec::new();
         }
 
-        let mut workers_to_be_removed: Vec<u64> = Vec::new();
+        // Pending jobs that may preempt others, highest priority first. Jobs
+        // in pools only start within the pool's resources and are left out.
+        let waiting: Vec<u64> = self
+            .jobs_waiting_for_assignment
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        let mut candidates: Vec<JobInfo> = waiting
+            .iter()
+            .filter_map(|job_id| self.jobs.get(job_id))
+            .map(|job| job.lock().unwrap().info.clone())
+            .filter(|info| info.qos.is_some() && !self.is_preemptible(&info.qos))
+            .filter(|info| info.pool.is_none() && !info.interactive)
+            .collect();
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+        candidates.sort_by_key(|info| Reverse(info.priority));
 
-        // Remove workers that are no longer alive.
-        for (worker_id, weak_worker) in &self.workers {
-            let worker_alive = match weak_worker.upgrade() {
-                Some(worker) => {
-                    // Worker is still with us.
-                    !worker
-                        .lock()
-                        .unwrap()
-                        .info
-                        .timed_out(self.config.server_settings.worker_timeout_seconds)
+        // Workers and their running jobs that can be preempted. Workers that
+        // are still stopping jobs are left out, so that the freed resources
+        // are not claimed twice.
+        struct PreemptionTarget {
+            worker_name: String,
+            reserved_for: Option<String>,
+            free: Resources,
+            forward_tx: mpsc::Sender<ServerToWorkerMessage>,
+            running: Vec<(Arc<Mutex<Job>>, JobInfo)>,
+        }
+        let mut targets = Vec::new();
+        for worker in self.workers.values().filter_map(Weak::upgrade) {
+            let worker_lock = worker.lock().unwrap();
+            let info = &worker_lock.info;
+            if info.draining || !info.has_capability(CAP_JOB_MIGRATION) {
+                continue;
+            }
+            let mut running = Vec::new();
+            let mut stopping = false;
+            for job in info.jobs_running.iter().filter_map(|id| self.jobs.get(id)) {
+                let job_lock = job.lock().unwrap();
+                stopping |= job_lock.migration.is_some();
+                if job_lock.info.status.is_running() && self.is_preemptible(&job_lock.info.qos) {
+                    running.push((Arc::clone(job), job_lock.info.clone()));
                 }
-                None => false,
-            };
-            if !worker_alive {
-                workers_to_be_removed.push(*worker_id);
             }
+            if stopping || running.is_empty() {
+                continue;
+            }
+            // Jobs with the lowest priority are preempted first.
+            running.sort_by_key(|(_, info)| info.priority);
+            targets.push(PreemptionTarget {
+                worker_name: info.worker_name.clone(),
+                reserved_for: info.reserved_for.clone(),
+                free: info.free_resources.clone(),
+                forward_tx: worker_lock.forward_tx.clone(),
+                running,
+            });
         }
 
-        // Clean up disconnected workers.
-        for id in workers_to_be_removed {
-            self.workers.remove(&id);
+        let mut messages = Vec::new();
+        'candidates: for candidate in candidates {
+            let required = &candidate.worker_resources;
+            let eligible = |target: &PreemptionTarget| {
+                !candidate.avoid_workers.contains(&target.worker_name)
+                    && target
+                        .reserved_for
+                        .as_ref()
+                        .is_none_or(|user| user == &candidate.user)
+            };
+            // Jobs that fit onto a worker are started without preemption.
+            if targets
+                .iter()
+                .any(|target| eligible(target) && required.fit_into(&target.free))
+            {
+                continue;
+            }
+            for (index, target) in targets.iter().enumerate() {
+                if !eligible(target) {
+                    continue;
+                }
+                let mut available = target.free.clone();
+                let mut victims = Vec::new();
+                for (job, info) in &target.running {
+                    if info.priority >= candidate.priority || required.fit_into(&available) {
+                        break;
+                    }
+                    available.add(&info.worker_resources);
+                    victims.push((job, info));
+                }
+                if victims.is_empty() || !required.fit_into(&available) {
+                    continue;
+                }
+
+                for (job, info) in victims {
+                    let grace_period_seconds = info
+                        .qos
+                        .as_deref()
+                        .and_then(|qos| self.get_qos_class(qos))
+                        .map_or(0, |class| class.preemption_grace_seconds);
+                    let mut job_lock = job.lock().unwrap();
+                    if !job_lock.info.status.is_running() {
+                        continue;
+                    }
+                    log::debug!(
+                        "Preempt job {} on {} for job {}!",
+                        info.job_id,
+                        target.worker_name,
+                        candidate.job_id
+                    );
+                    job_lock.migration = Some(JobMigration {
+                        signal: 15, // SIGTERM
+                        grace_period_seconds,
+                        checkpoint: None,
+                    });
+                    job_lock.preempted = true;
+                    job_lock.info.events.push(JobEvent {
+                        time: Utc::now(),
+                        text: format!("Preempted for job {}.", candidate.job_id),
+                    });
+                    let message = ServerToWorkerMessage::StopJob {
+                        job_id: info.job_id,
+                        signal: 15,
+                        grace_period_seconds,
+                    };
+                    messages.push((target.forward_tx.clone(), message));
+                }
+                targets.remove(index);
+                continue 'candidates;
+            }
+        }
+        messages
+    }
+
+    /// Withdraw offers the workers have not answered yet if a strictly better
+    /// placement has appeared since the offer was made: a job of higher
+    /// priority that only fits onto the worker in place of the offered job,
+    /// or the offered job's preferred worker having room for it. Withdrawn
+    /// jobs are queued again. Returns the channels to the workers and the
+    /// WithdrawJobOffer messages to be sent to them.
+    pub fn rebalance_offers(
+        &mut self,
+    ) -> Vec<(mpsc::Sender<ServerToWorkerMessage>, ServerToWorkerMessage)> {
+        if !self.config.server_settings.rebalance_offers {
+            return Vec::new();
+        }
+
+        // Unanswered offers by worker.
+        struct Offer {
+            info: JobInfo,
+            offered: DateTime<Utc>,
+            affinity_deadline: Option<DateTime<Utc>>,
+        }
+        let mut offers: BTreeMap<u64, Vec<Offer>> = BTreeMap::new();
+        for job in self.jobs.values() {
+            let job_lock = job.lock().unwrap();
+            if let (JobStatus::Offered { offered, .. }, Some(worker_id)) =
+                (&job_lock.info.status, job_lock.worker_id)
+            {
+                offers.entry(worker_id).or_default().push(Offer {
+                    info: job_lock.info.clone(),
+                    offered: *offered,
+                    affinity_deadline: job_lock.affinity_deadline,
+                });
+            }
+        }
+        if offers.is_empty() {
+            return Vec::new();
+        }
+
+        // Pending jobs that may take the place of an offered job, highest
+        // priority first. Jobs in pools only start within the pool's resources.
+        let waiting: Vec<u64> = self
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        let mut candidates: Vec<(JobInfo, DateTime<Utc>)> = waiting
+            .iter()
+            .filter_map(|job_id| self.jobs.get(job_id))
+            .filter_map(|job| {
+                let info = job.lock().unwrap().info.clone();
+                match info.status {
+                    JobStatus::Pending { issued } => Some((info, issued)),
+                    _ => None,
+                }
+            })
+            .filter(|(info, _)| info.pool.is_none() && !info.interactive)
+            .collect();
+        candidates.sort_by_key(|(info, _)| Reverse(info.priority));
+
+        // Preferred workers of the offered jobs and their free resources.
+        let offered_ids: Vec<u64> = offers
+            .values()
+            .flatten()
+            .map(|offer| offer.info.job_id)
+            .collect();
+        let preferred_workers = self.preferred_workers(&offered_ids);
+        let free_workers = if preferred_workers.is_empty() {
+            BTreeMap::new()
+        } else {
+            self.free_resources_of_workers()
+        };
+
+        let now = Utc::now();
+        let mut claimed = BTreeSet::new();
+        let mut withdrawals = Vec::new();
+        let mut messages = Vec::new();
+        for (worker_id, mut offered) in offers {
+            let Some(worker) = self.workers.get(&worker_id).and_then(Weak::upgrade) else {
+                continue; // offer is recovered when it times out
+            };
+            let mut worker_lock = worker.lock().unwrap();
+            let worker_name = worker_lock.info.worker_name.clone();
+
+            // Resources left on the worker once it has accepted all offers.
+            let mut left = worker_lock.info.free_resources.clone();
+            for offer in offered.iter().filter(|offer| offer.info.pool.is_none()) {
+                left.subtract(&offer.info.worker_resources);
+            }
+
+            // Offers of the lowest priority are withdrawn first.
+            offered.sort_by_key(|offer| offer.info.priority);
+            for Offer {
+                info,
+                offered: offered_at,
+                affinity_deadline,
+            } in offered
+            {
+                let prefers_other = preferred_workers.get(&info.job_id).filter(|preferred| {
+                    **preferred != worker_name
+                        && !info.avoid_workers.contains(*preferred)
+                        && free_workers
+                            .get(*preferred)
+                            .is_some_and(|free| info.worker_resources.fit_into(free))
+                        && affinity_deadline.is_none_or(|deadline| now < deadline)
+                });
+                let reason = if let Some(preferred) = prefers_other {
+                    format!("preferred worker {} has room", preferred)
+                } else if info.pool.is_some() {
+                    continue;
+                } else {
+                    let mut freed = left.clone();
+                    freed.add(&info.worker_resources);
+                    let better = candidates.iter().find(|(candidate, issued)| {
+                        candidate.priority > info.priority
+                            && *issued > offered_at
+                            && !claimed.contains(&candidate.job_id)
+                            && !candidate.avoid_workers.contains(&worker_name)
+                            && worker_lock.info.satisfies(&candidate.constraints)
+                            && worker_lock
+                                .info
+                                .reserved_for
+                                .as_ref()
+                                .is_none_or(|user| user == &candidate.user)
+                            && !candidate.worker_resources.fit_into(&left)
+                            && candidate.worker_resources.fit_into(&freed)
+                    });
+                    let Some((candidate, _)) = better else {
+                        continue;
+                    };
+                    claimed.insert(candidate.job_id);
+                    left = freed;
+                    left.subtract(&candidate.worker_resources);
+                    format!(
+                        "job {} of higher priority {} fits in its place",
+                        candidate.job_id, candidate.priority
+                    )
+                };
+
+                // The worker may have answered the offer meanwhile.
+                let Some(job) = self.jobs.get(&info.job_id) else {
+                    continue;
+                };
+                let mut job_lock = job.lock().unwrap();
+                let JobStatus::Offered {
+                    issued, offered, ..
+                } = job_lock.info.status
+                else {
+                    continue;
+                };
+                if job_lock.worker_id != Some(worker_id) {
+                    continue;
+                }
+                log::debug!(
+                    "Withdraw offer of job {} to {}: {}",
+                    info.job_id,
+                    worker_name,
+                    reason
+                );
+                job_lock.info.status = JobStatus::Pending { issued };
+                job_lock.worker_id = None;
+                self.release_assigned_job(worker_id, info.job_id);
+                job_lock.info.events.push(JobEvent {
+                    time: now,
+                    text: format!("Offer to {} withdrawn, {}.", worker_name, reason),
+                });
+                job_lock.notify_observers();
+                worker_lock.info.jobs_offered.remove(&info.job_id);
+                worker_lock.withdrawn_offers.insert(info.job_id, offered);
+                let message = ServerToWorkerMessage::WithdrawJobOffer(job_lock.info.clone());
+                messages.push((worker_lock.forward_tx.clone(), message));
+                withdrawals.push((info.job_id, worker_name.clone(), reason));
+            }
+        }
+
+        if !withdrawals.is_empty() {
+            for (job_id, worker_name, reason) in withdrawals {
+                self.jobs_waiting_for_assignment
+                    .get_mut()
+                    .unwrap()
+                    .insert(job_id);
+                self.trace_scheduling(job_id, &worker_name, SchedulingOutcome::Withdrawn, &|| {
+                    reason.clone()
+                });
+            }
+            self.notify_new_jobs.notify_waiters();
+        }
+        messages
+    }
+
+    /// Returns the configured project with the given name.
+    pub fn get_project(&self, name: &str) -> Option<&Project> {
+        let projects = &self.config.server_settings.projects;
+        projects.iter().find(|project| project.name == name)
+    }
+
+    /// Returns `true` if the project has consumed all of its CPU hours.
+    fn project_cpu_hours_exhausted(&self, project: &Project) -> bool {
+        let used = self
+            .project_accounting
+            .get(&Some(project.name.clone()))
+            .map_or(0.0, |usage| usage.cpu_hours);
+        project.cpu_hours.is_some_and(|cpu_hours| used >= cpu_hours)
+    }
+
+    /// Checks the project of a newly issued job. Returns an error if the
+    /// project is unknown or has used up its CPU hours.
+    pub fn check_project(&self, job_info: &JobInfo) -> Result<()> {
+        let Some(name) = &job_info.project else {
+            return Ok(());
+        };
+        match self.get_project(name) {
+            Some(project) if self.project_cpu_hours_exhausted(project) => {
+                bail!("Project '{}' has used up its CPU hours!", name)
+            }
+            Some(_) => Ok(()),
+            None => {
+                let names: Vec<&str> = self
+                    .config
+                    .server_settings
+                    .projects
+                    .iter()
+                    .map(|project| project.name.as_str())
+                    .collect();
+                if names.is_empty() {
+                    bail!("No projects configured on the server!");
+                }
+                bail!(
+                    "Unknown project '{}'! Known projects: {}",
+                    name,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Returns the usage of the cluster per project, including all configured
+    /// projects and jobs without project.
+    pub fn get_project_usage(&self) -> Vec<ProjectUsage> {
+        let mut usage = self.project_accounting.clone();
+        for project in &self.config.server_settings.projects {
+            usage.entry(Some(project.name.clone())).or_default();
+        }
+        for job in self.jobs.values() {
+            let job_lock = job.lock().unwrap();
+            let status = &job_lock.info.status;
+            if status.is_pending() || status.is_running() || status.is_offered() {
+                let entry = usage.entry(job_lock.info.project.clone()).or_default();
+                if status.is_pending() {
+                    entry.jobs_pending += 1;
+                } else {
+                    entry.jobs_running += 1;
+                }
+            }
+        }
+        usage
+            .into_iter()
+            .map(|(project, mut usage)| {
+                if let Some(config) = project.as_deref().and_then(|name| self.get_project(name)) {
+                    usage.max_running_jobs = config.max_running_jobs;
+                    usage.cpu_hours_quota = config.cpu_hours;
+                }
+                usage.project = project;
+                usage
+            })
+            .collect()
+    }
+
+    /// Move requested resources that are not configured globally on the
+    /// server to the resources required from the worker, e.g., licenses
+    /// configured with `[worker_settings.resources]`.
+    pub fn assign_named_resources(&self, job_info: &mut JobInfo) {
+        let Some(requested) = job_info.global_resources.take() else {
+            return;
+        };
+        let global = self.config.global_resources.as_ref();
+        let (global_resources, named): (BTreeMap<_, _>, BTreeMap<_, _>) = requested
+            .into_iter()
+            .partition(|(name, _)| global.is_some_and(|global| global.contains_key(name)));
+        job_info.worker_resources.named.extend(named);
+        job_info.global_resources = (!global_resources.is_empty()).then_some(global_resources);
+    }
+
+    /// Named resources of all connected workers, used and in total.
+    pub fn get_worker_resources(&self) -> (BTreeMap<String, u64>, BTreeMap<String, u64>) {
+        let mut used = BTreeMap::new();
+        let mut total = BTreeMap::new();
+        for worker in self.workers.values().filter_map(Weak::upgrade) {
+            let info = &worker.lock().unwrap().info;
+            for (name, amount) in &info.system_info.resources {
+                let free = info.free_resources.named.get(name).copied().unwrap_or(0);
+                *used.entry(name.clone()).or_default() += amount.saturating_sub(free);
+                *total.entry(name.clone()).or_default() += amount;
+            }
+        }
+        (used, total)
+    }
+
+    /// Record resources of workers that sit idle while none of the pending
+    /// jobs fit into them. The given time span is attributed to the hour of
+    /// `now`, along with the resource that most pending jobs lack.
+    pub fn sample_idle_resources(&mut self, now: DateTime<Utc>, seconds: u64) {
+        let jobs = &self.jobs;
+        let required: Vec<Resources> = self
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .iter()
+            .filter_map(|job_id| jobs.get(job_id))
+            .map(|job| job.lock().unwrap().info.worker_resources.clone())
+            .collect();
+        if required.is_empty() {
+            return;
+        }
+
+        let hour = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+        let hours = seconds as f64 / 3600.0;
+        for worker in self.workers.values().filter_map(Weak::upgrade) {
+            let (worker_name, free, draining) = {
+                let info = &worker.lock().unwrap().info;
+                (
+                    info.worker_name.clone(),
+                    info.free_resources.clone(),
+                    info.draining,
+                )
+            };
+            let idle = free.cpus > 0 || free.ram_mb > 0;
+            if draining || !idle || required.iter().any(|job| job.fit_into(&free)) {
+                continue;
+            }
+
+            let mut lacking = [("job slots", 0), ("CPU cores", 0), ("RAM", 0)];
+            for job in &required {
+                let index = if job.job_slots > free.job_slots {
+                    0
+                } else if job.cpus > free.cpus {
+                    1
+                } else {
+                    2
+                };
+                lacking[index].1 += 1;
+            }
+            let limiting = lacking.iter().max_by_key(|(_, jobs)| *jobs).unwrap().0;
+
+            let entry = self
+                .idle_resources
+                .entry((worker_name.clone(), hour))
+                .or_insert_with(|| IdleResources {
+                    worker_name,
+                    hour,
+                    ..Default::default()
+                });
+            entry.idle_hours += hours;
+            entry.cpu_hours += free.cpus as f64 * hours;
+            entry.ram_gb_hours += free.ram_mb as f64 / 1024.0 * hours;
+            *entry.limited_by.entry(limiting.into()).or_default() += hours;
+        }
+
+        let oldest = hour - Duration::hours(IDLE_HISTORY_HOURS);
+        self.idle_resources.retain(|(_, hour), _| *hour > oldest);
+    }
+
+    /// Report of idle resources, ordered by worker name and hour.
+    pub fn get_idle_resources(&self) -> Vec<IdleResources> {
+        self.idle_resources.values().cloned().collect()
+    }
+
+    /// Suggests resources for a job based on the measured usage of similar
+    /// jobs in the past. Returns `None` if there is not enough history.
+    pub fn suggest_resources(&self, job_info: &JobInfo) -> Option<Resources> {
+        let history = self.resource_history.get(&similarity_key(job_info))?;
+        if history.len() < RESOURCE_HISTORY_MIN_RUNS {
+            return None;
+        }
+        let cpus = history
+            .iter()
+            .map(|usage| usage.avg_cpus)
+            .fold(0.0, f64::max);
+        let ram_mb = history.iter().map(|usage| usage.peak_ram_mb).max()?;
+        // Leave some headroom above the highest measured usage.
+        Some(Resources::new(
+            job_info.worker_resources.job_slots,
+            ((cpus * 1.1).ceil() as u64).max(1),
+            ((ram_mb as f64 * 1.2).ceil() as u64).max(1),
+        ))
+    }
+
+    /// Takes over jobs that a reconnected worker reports as running, if they
+    /// have been requeued meanwhile or are still assigned to a worker that is
+    /// no longer connected. Returns the IDs of adopted jobs.
+    pub fn adopt_worker_jobs(
+        &mut self,
+        worker_id: u64,
+        worker_name: &str,
+        running: &BTreeSet<u64>,
+    ) -> Vec<u64> {
+        let mut adopted_jobs = Vec::new();
+        for job_id in running {
+            let job = match self.jobs.get(job_id) {
+                Some(job) => job,
+                None => continue, // reported as unknown by the reconciliation
+            };
+            let mut job_lock = job.lock().unwrap();
+            let (issued, started) = match job_lock.info.status {
+                JobStatus::Pending { issued } => {
+                    let waiting = self.jobs_waiting_for_assignment.get_mut().unwrap();
+                    if !waiting.remove(job_id) {
+                        continue;
+                    }
+                    (issued, Utc::now())
+                }
+                JobStatus::Running {
+                    issued, started, ..
+                } => {
+                    let connected = job_lock.worker_id.is_some_and(|id| {
+                        self.workers
+                            .get(&id)
+                            .is_some_and(|worker| worker.strong_count() > 0)
+                    });
+                    if connected {
+                        continue;
+                    }
+                    (issued, started)
+                }
+                _ => continue,
+            };
+            log::info!("Job {job_id} is still running on worker {worker_name}. Adopt...");
+            job_lock.info.status = JobStatus::Running {
+                issued,
+                started,
+                worker: worker_name.into(),
+            };
+            if let Some(previous_id) = job_lock.worker_id {
+                self.release_assigned_job(previous_id, *job_id);
+            }
+            job_lock.worker_id = Some(worker_id);
+            self.count_assigned_job(worker_id, &job_lock.info.user, *job_id);
+            job_lock.save();
+            job_lock.notify_observers();
+            adopted_jobs.push(*job_id);
+        }
+        adopted_jobs
+    }
+
+    /// Compares the jobs reported by a worker with the server's bookkeeping.
+    /// Jobs assigned to the worker but unknown to it are requeued. Returns
+    /// the IDs of requeued jobs, accepted jobs whose offer must be withdrawn,
+    /// and running jobs to be killed, as they are not assigned to the worker.
+    pub fn reconcile_worker_jobs(
+        &mut self,
+        worker_id: u64,
+        accepted: &BTreeSet<u64>,
+        running: &BTreeSet<u64>,
+    ) -> (Vec<u64>, Vec<JobInfo>, Vec<JobInfo>) {
+        let assigned_to_worker = |job: &Job| {
+            job.worker_id == Some(worker_id)
+                && (job.info.status.is_offered() || job.info.status.is_running())
+        };
+
+        // Requeue jobs the worker has lost track of. Offers might still be on
+        // their way to the worker and are covered by the offer timeout.
+        let mut requeued_jobs = Vec::new();
+        for (job_id, job) in &self.jobs {
+            if accepted.contains(job_id) || running.contains(job_id) {
+                continue;
+            }
+            let mut job_lock = job.lock().unwrap();
+            if job_lock.worker_id == Some(worker_id) {
+                if let JobStatus::Running { issued, .. } = job_lock.info.status {
+                    log::warn!("Job {job_id} is not running on its worker. Recover...");
+                    job_lock.info.record_attempt();
+                    job_lock.info.status = JobStatus::Pending { issued };
+                    job_lock.worker_id = None;
+                    self.release_assigned_job(worker_id, *job_id);
+                    job_lock.notify_observers();
+                    requeued_jobs.push(*job_id);
+                }
+            }
+        }
+        if !requeued_jobs.is_empty() {
+            self.jobs_waiting_for_assignment
+                .get_mut()
+                .unwrap()
+                .extend(&requeued_jobs);
+            self.notify_new_jobs.notify_waiters();
+        }
+
+        // Collect jobs the worker holds without being assigned to them.
+        let unassigned_jobs = |job_ids: &BTreeSet<u64>| {
+            let mut job_infos = Vec::new();
+            for job_id in job_ids {
+                match self.jobs.get(job_id) {
+                    Some(job) => {
+                        let job_lock = job.lock().unwrap();
+                        if !assigned_to_worker(&job_lock) {
+                            log::warn!("Job {job_id} is not assigned to its worker. Recover...");
+                            job_infos.push(job_lock.info.clone());
+                        }
+                    }
+                    None => log::warn!("Worker reported unknown job {job_id}!"),
+                }
+            }
+            job_infos
+        };
+        let offers_to_withdraw = unassigned_jobs(accepted);
+        let jobs_to_kill = unassigned_jobs(running);
+
+        (requeued_jobs, offers_to_withdraw, jobs_to_kill)
+    }
+
+    /// Flags the running jobs of the worker that it reports as stalled.
+    /// Returns the newly stalled jobs that are to be killed according to the
+    /// server's `stalled_job_action`.
+    pub fn update_stalled_jobs(&self, worker_id: u64, stalled: &BTreeSet<u64>) -> Vec<JobInfo> {
+        let action = self.config.server_settings.stalled_job_action;
+        let mut jobs_to_kill = Vec::new();
+        for (job_id, job) in &self.jobs {
+            let mut job_lock = job.lock().unwrap();
+            if job_lock.worker_id != Some(worker_id) || !job_lock.info.status.is_running() {
+                continue;
+            }
+            let is_stalled = stalled.contains(job_id);
+            if is_stalled && !job_lock.info.stalled {
+                log::warn!("Job {job_id} has stalled!");
+                if action != StalledJobAction::Flag {
+                    jobs_to_kill.push(job_lock.info.clone());
+                }
+            }
+            job_lock.info.stalled = is_stalled;
+        }
+        jobs_to_kill
+    }
+
+    /// Marks the failure of a finished job that has been killed, because it
+    /// has stalled.
+    pub fn classify_stalled_job(&self, job: &Arc<Mutex<Job>>) {
+        if self.config.server_settings.stalled_job_action == StalledJobAction::Flag {
+            return;
+        }
+        let mut job_lock = job.lock().unwrap();
+        if !job_lock.info.stalled {
+            return;
+        }
+        if let JobStatus::Finished {
+            return_code,
+            comment,
+            failure: failure @ Some(FailureKind::KilledByUser),
+            ..
+        } = &mut job_lock.info.status
+        {
+            *return_code = EXIT_CODE_STALLED;
+            *comment = "Killed, since the job neither consumed CPU time nor produced output \
+                for a long time."
+                .into();
+            *failure = Some(FailureKind::Stalled);
+        }
+    }
+
+    /// Applies the configured routing rules to a newly issued job. Settings
+    /// chosen by the user take precedence over the rules. Returns an error
+    /// if a rule's regular expression is invalid.
+    pub fn apply_routing_rules(&self, job_info: &mut JobInfo) -> Result<()> {
+        let command = job_info.cmd.join(" ");
+        for rule in &self.config.server_settings.routing_rules {
+            let regex = match Regex::new(&rule.command) {
+                Ok(regex) => regex,
+                Err(e) => bail!("Invalid routing rule '{}': {}", rule.command, e),
+            };
+            if !regex.is_match(&command) {
+                continue;
+            }
+            log::debug!("Routing rule '{}' matches job: {}", rule.command, command);
+            if job_info.pool.is_none() {
+                job_info.pool = rule.pool.clone();
+            }
+            if !rule.global_resources.is_empty() {
+                let global_resources = job_info.global_resources.get_or_insert_with(BTreeMap::new);
+                for (resource, amount) in &rule.global_resources {
+                    global_resources.entry(resource.clone()).or_insert(*amount);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates a newly added job against the connected workers and
+    /// estimates how long it will wait before being started.
+    pub fn job_receipt(&self, job_info: &JobInfo) -> JobReceipt {
+        let mut warnings = Vec::new();
+        let resources = &job_info.worker_resources;
+
+        // Count workers that could run the job, now or later.
+        let mut connected_workers = 0;
+        let mut candidate_workers = 0;
+        let mut available_workers = 0;
+        let mut jobs_running = 0;
+        let mut pool_offered = false;
+        for worker in self.workers.values().filter_map(Weak::upgrade) {
+            let worker_lock = worker.lock().unwrap();
+            let info = &worker_lock.info;
+            if info.timed_out(self.config.server_settings.worker_timeout_seconds) {
+                continue;
+            }
+            connected_workers += 1;
+            jobs_running += info.jobs_running.len() as u64;
+            if info.self_test_failed() {
+                continue; // worker does not receive any jobs
+            }
+            if job_info.interactive && !info.has_capability(CAP_INTERACTIVE_JOBS) {
+                continue;
+            }
+            if !info.satisfies(&job_info.constraints) {
+                continue;
+            }
+            let free_resources = match &job_info.pool {
+                Some(pool) => match info.pool_resources.get(pool) {
+                    Some(pool_resources) => {
+                        pool_offered = true;
+                        pool_resources
+                    }
+                    None => continue, // worker has no such pool
+                },
+                None => &info.free_resources,
+            };
+            let fits_system = resources.cpus <= info.system_info.cpu_cores
+                && resources.ram_mb <= info.system_info.total_ram_mb
+                && (info.system_info.max_job_slots == 0
+                    || resources.job_slots <= info.system_info.max_job_slots)
+                && resources.named.iter().all(|(name, amount)| {
+                    info.system_info.resources.get(name).copied().unwrap_or(0) >= *amount
+                });
+            if fits_system {
+                candidate_workers += 1;
+                if resources.fit_into(free_resources) {
+                    available_workers += 1;
+                }
+            }
+        }
+
+        if connected_workers == 0 {
+            warnings.push("No workers are connected to the server.".to_string());
+        } else if job_info.pool.is_some() && !pool_offered {
+            warnings.push(format!(
+                "No connected worker provides the resource pool '{}'.",
+                job_info.pool.as_deref().unwrap_or_default()
+            ));
+        } else if candidate_workers == 0 {
+            warnings.push(
+                "No connected worker has enough CPU cores, RAM, or job slots for this job."
+                    .to_string(),
+            );
+        }
+        if let (Some(required), Some(free)) =
+            (&job_info.global_resources, self.get_free_global_resources())
+        {
+            if required
+                .iter()
+                .any(|(resource, amount)| free.get(resource).is_some_and(|free| free < amount))
+            {
+                warnings.push("Global resources are currently used by other jobs.".to_string());
+            }
+        }
+
+        // Estimate waiting time from the average run time of finished jobs
+        // and the number of jobs queued before this one.
+        let finished_run_times: Vec<i64> = self
+            .jobs
+            .values()
+            .filter_map(|job| match job.lock().unwrap().info.status {
+                JobStatus::Finished {
+                    run_time_seconds, ..
+                } => Some(run_time_seconds),
+                _ => None,
+            })
+            .collect();
+        let waiting_jobs = self.jobs_waiting_for_assignment.lock().unwrap().clone();
+        let jobs_ahead = waiting_jobs
+            .into_iter()
+            .filter(|job_id| {
+                let priority = self.job_priority(*job_id);
+                priority > job_info.priority
+                    || (priority == job_info.priority && *job_id < job_info.job_id)
+            })
+            .count() as i64;
+        let estimated_wait_seconds = if candidate_workers == 0 || !job_info.dependencies.is_empty()
+        {
+            None // not predictable
+        } else if jobs_ahead == 0 && available_workers > 0 {
+            Some(0)
+        } else if finished_run_times.is_empty() {
+            None
+        } else {
+            let avg_run_time =
+                finished_run_times.iter().sum::<i64>() / finished_run_times.len() as i64;
+            let parallel_jobs = jobs_running.max(1) as i64;
+            Some((jobs_ahead / parallel_jobs + 1) * avg_run_time)
+        };
+
+        JobReceipt {
+            job_id: job_info.job_id,
+            worker_resources: resources.clone(),
+            global_resources: job_info.global_resources.clone(),
+            queue: job_info
+                .pool
+                .clone()
+                .unwrap_or_else(|| "default".to_string()),
+            candidate_workers,
+            available_workers,
+            estimated_wait_seconds,
+            warnings,
+        }
+    }
+
+    /// Requeues a job that has been stopped for migration, so that another
+    /// worker picks it up. Returns `true` if the job has been requeued.
+    pub fn requeue_migrated_job(&mut self, job: &Arc<Mutex<Job>>) -> bool {
+        let mut job_lock = job.lock().unwrap();
+        let migration = match job_lock.migration.take() {
+            Some(migration) => migration,
+            None => return false,
+        };
+        let preempted = std::mem::take(&mut job_lock.preempted);
+        // Jobs that concluded before they could be stopped are kept finished.
+        let (issued, worker) = match &job_lock.info.status {
+            JobStatus::Finished {
+                issued,
+                return_code: EXIT_CODE_STOPPED,
+                worker,
+                ..
+            } => (*issued, worker.clone()),
+            _ => return false,
+        };
+
+        let job_id = job_lock.info.job_id;
+        let text = match &migration.checkpoint {
+            _ if preempted => format!("Preempted on {}. Restarts from the start.", worker),
+            Some(checkpoint) => format!(
+                "Stopped on {} for migration. Resumes from {}.",
+                worker,
+                checkpoint.to_string_lossy()
+            ),
+            None => format!(
+                "Stopped on {} for migration. Restarts from the start.",
+                worker
+            ),
+        };
+        log::debug!("Job {}: {}", job_id, text);
+        job_lock.info.events.push(JobEvent {
+            time: Utc::now(),
+            text,
+        });
+        job_lock.info.checkpoint = migration.checkpoint;
+        job_lock.info.migrations += 1;
+        if !preempted {
+            job_lock.info.avoid_workers.insert(worker);
+        }
+        job_lock.info.stalled = false;
+        job_lock.info.record_attempt();
+        job_lock.info.status = JobStatus::Pending { issued };
+        job_lock.worker_id = None;
+        job_lock.notify_observers();
+
+        self.jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .insert(job_id);
+        self.notify_new_jobs.notify_waiters();
+        true
+    }
+
+    /// Requeues a finished job if its exit code policies ask for a retry and
+    /// retries are left. Returns `true` if the job has been requeued.
+    pub fn retry_finished_job(&mut self, job: &Arc<Mutex<Job>>) -> bool {
+        let mut job_lock = job.lock().unwrap();
+        let (issued, return_code, worker) = match &job_lock.info.status {
+            JobStatus::Finished {
+                issued,
+                return_code,
+                worker,
+                ..
+            } => (*issued, *return_code, worker.clone()),
+            _ => return false,
+        };
+        // Stalled jobs are requeued elsewhere without counting as a retry.
+        let requeue_stalled = self.config.server_settings.stalled_job_action
+            == StalledJobAction::Requeue
+            && matches!(
+                job_lock.info.status,
+                JobStatus::Finished {
+                    failure: Some(FailureKind::Stalled),
+                    ..
+                }
+            );
+        let action = match job_lock.info.exit_code_action(return_code) {
+            _ if requeue_stalled => ExitCodeAction::RetryElsewhere,
+            Some(action) if action != ExitCodeAction::Fail => action,
+            _ => return false,
+        };
+        if !requeue_stalled && job_lock.info.retries >= job_lock.info.max_retries {
+            return false;
+        }
+
+        let job_id = job_lock.info.job_id;
+        if !requeue_stalled {
+            job_lock.info.retries += 1;
+        }
+        job_lock.info.stalled = false;
+        log::debug!(
+            "Retry job {} after exit code {} ({}/{})",
+            job_id,
+            return_code,
+            job_lock.info.retries,
+            job_lock.info.max_retries
+        );
+        if action == ExitCodeAction::RetryElsewhere {
+            job_lock.info.avoid_workers.insert(worker);
+        }
+        job_lock.info.record_attempt();
+        job_lock.info.status = JobStatus::Pending { issued };
+        job_lock.worker_id = None;
+        job_lock.notify_observers();
+
+        self.jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .insert(job_id);
+        self.notify_new_jobs.notify_waiters();
+        true
+    }
+
+    /// Cancel and remove a job from the queue. If the job is running and a
+    /// worker is associated with the job, a sender is returned that can be
+    /// used to signal a kill instruction to the worker. The job_id sent over
+    /// the returned sender indicates the job to be killed on the worker. The
+    /// reason is stored in the job's canceled status.
+    pub fn cancel_job(
+        &mut self,
+        job_id: u64,
+        kill: bool,
+        reason: Option<String>,
+    ) -> Result<Option<mpsc::Sender<u64>>> {
+        match self.get_job(job_id) {
+            Some(job) => {
+                let job_info = job.lock().unwrap().info.clone();
+                match job_info.status {
+                    JobStatus::Pending { issued } => {
+                        // Do not attempt to offer the job to workers.
+                        self.jobs_waiting_for_assignment
+                            .get_mut()
+                            .unwrap()
+                            .remove(&job_id);
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.status = JobStatus::Canceled {
+                            issued,
+                            canceled: Utc::now(),
+                            reason: reason.clone(),
+                        };
+                        job_lock.save();
+                        Ok(None)
+                    }
+                    JobStatus::Offered { issued, .. } => {
+                        // Offer will be withdrawn by the server on worker's response.
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.status = JobStatus::Canceled {
+                            issued,
+                            canceled: Utc::now(),
+                            reason: reason.clone(),
+                        };
+                        if let Some(worker_id) = job_lock.worker_id {
+                            self.release_assigned_job(worker_id, job_id);
+                        }
+                        job_lock.save();
+                        Ok(None)
+                    }
+                    JobStatus::Running { issued, .. } => {
+                        if !kill {
+                            // Makes no sense to set the job to canceled if the
+                            // worker proceeds anyway.
+                            bail!("Job ID={} has already started!", job_id);
+                        }
+                        // Update job status
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.status = JobStatus::Canceled {
+                            issued,
+                            canceled: Utc::now(),
+                            reason: reason.clone(),
+                        };
+                        job_lock.save();
+                        drop(job_lock);
+                        // If worker is assigned and alive, get the kill job sender.
+                        let worker_id = job.lock().unwrap().worker_id;
+                        if let Some(worker_id) = worker_id {
+                            self.release_assigned_job(worker_id, job_id);
+                            if let Some(worker) = self.workers.get(&worker_id) {
+                                if let Some(worker) = worker.upgrade() {
+                                    let tx = worker.lock().unwrap().kill_job_tx.clone();
+                                    return Ok(Some(tx));
+                                }
+                            }
+                        }
+                        bail!(
+                            "Job with ID={} was running but worker could not be acquired!",
+                            job_id
+                        )
+                    }
+                    JobStatus::Finished { .. } => bail!("Job ID={} has already finished!", job_id),
+                    JobStatus::Canceled { .. } => bail!("Job ID={} is already canceled!", job_id),
+                }
+            }
+            None => bail!("Job with ID={} not found!", job_id),
+        }
+    }
+
+    /// Raise the priority of a pending job to the highest priority of all
+    /// jobs waiting for a worker, so that it is offered before the jobs of
+    /// the same priority issued earlier. With `to_front`, the job is offered
+    /// before all other waiting jobs. The boost is recorded in the job's
+    /// events. Returns the previous and the new priority.
+    pub fn boost_job(&mut self, job_id: u64, to_front: bool, user: &str) -> Result<(i32, i32)> {
+        let job = match self.get_job(job_id) {
+            Some(job) => job,
+            None => bail!("Job with ID={} not found!", job_id),
+        };
+        if !job.lock().unwrap().info.status.is_pending() {
+            bail!("Job ID={} is not pending!", job_id);
+        }
+        let highest = self
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .filter(|other_id| *other_id != job_id)
+            .map(|other_id| self.job_priority(other_id))
+            .max();
+
+        let mut job_lock = job.lock().unwrap();
+        let previous = job_lock.info.priority;
+        let priority = match highest {
+            Some(highest) if to_front => previous.max(highest.saturating_add(1)),
+            Some(highest) => previous.max(highest),
+            None => previous,
+        };
+        job_lock.info.priority = priority;
+        let text = if to_front {
+            format!("Moved to the front of the queue by {}.", user)
+        } else {
+            format!(
+                "Priority boosted from {} to {} by {}.",
+                previous, priority, user
+            )
+        };
+        job_lock.info.events.push(JobEvent {
+            time: Utc::now(),
+            text,
+        });
+        job_lock.save();
+        Ok((previous, priority))
+    }
+
+    /// Unified diff of the outputs of two jobs, stdout followed by stderr.
+    /// Returns the diff and whether it has been shortened to the size limit.
+    pub fn diff_jobs(&self, job_a: u64, job_b: u64) -> Result<(String, bool)> {
+        let outputs = |job_id: u64| match self.get_job(job_id) {
+            Some(job) => {
+                let job_lock = job.lock().unwrap();
+                if job_lock.info.encrypted_payload.is_some() {
+                    bail!("Outputs of encrypted job ID={} cannot be compared!", job_id);
+                }
+                let (stdout_text, stderr_text) = job_lock.outputs();
+                Ok([
+                    stdout_text.unwrap_or_default(),
+                    stderr_text.unwrap_or_default(),
+                ])
+            }
+            None => bail!("Job with ID={} not found!", job_id),
+        };
+        let (outputs_a, outputs_b) = (outputs(job_a)?, outputs(job_b)?);
+
+        let mut diff = String::new();
+        for (index, name) in ["stdout", "stderr"].iter().enumerate() {
+            diff += &unified_diff(
+                &outputs_a[index],
+                &outputs_b[index],
+                &format!("job {} {}", job_a, name),
+                &format!("job {} {}", job_b, name),
+            );
+        }
+        let truncated = truncate(&mut diff, MAX_DIFF_LEN);
+        Ok((diff, truncated))
+    }
+
+    /// Checks the configured limits of pending jobs before the given job is
+    /// added. Returns warnings if soft limits are reached, or an error with
+    /// the reason to reject the job if hard limits are reached.
+    pub fn check_pending_job_limits(&self, job_info: &JobInfo) -> Result<Vec<String>> {
+        let settings = &self.config.server_settings;
+        let limits = [
+            settings.pending_jobs_soft_limit,
+            settings.pending_jobs_hard_limit,
+            settings.pending_jobs_per_user_soft_limit,
+            settings.pending_jobs_per_user_hard_limit,
+        ];
+        if limits.iter().all(Option::is_none) {
+            return Ok(Vec::new());
+        }
+
+        // Pending jobs either wait for a worker or for their dependencies.
+        let mut pending_jobs = self.jobs_waiting_for_assignment.lock().unwrap().clone();
+        pending_jobs.extend(&self.jobs_waiting_for_dependencies);
+        let pending = pending_jobs.len() as u64;
+        let pending_of_user = if settings.pending_jobs_per_user_soft_limit.is_some()
+            || settings.pending_jobs_per_user_hard_limit.is_some()
+        {
+            pending_jobs
+                .iter()
+                .filter_map(|job_id| self.jobs.get(job_id))
+                .filter(|job| job.lock().unwrap().info.user == job_info.user)
+                .count() as u64
+        } else {
+            0
+        };
+
+        if let Some(limit) = settings.pending_jobs_hard_limit {
+            if pending >= limit {
+                bail!(
+                    "The server's queue is full with {} pending jobs. Please try again later.",
+                    pending
+                );
+            }
+        }
+        if let Some(limit) = settings.pending_jobs_per_user_hard_limit {
+            if pending_of_user >= limit {
+                bail!(
+                    "You have {} pending jobs, which is the limit per user. \
+                    Please wait for some of them to start.",
+                    pending_of_user
+                );
+            }
+        }
+
+        let mut warnings = Vec::new();
+        if let Some(limit) = settings.pending_jobs_soft_limit {
+            if pending >= limit {
+                log::warn!("Server holds {} pending jobs!", pending + 1);
+                warnings.push(format!(
+                    "The server's queue holds {} pending jobs, more than the soft limit of {}.",
+                    pending + 1,
+                    limit
+                ));
+            }
+        }
+        if let Some(limit) = settings.pending_jobs_per_user_soft_limit {
+            if pending_of_user >= limit {
+                log::warn!(
+                    "User {} has {} pending jobs!",
+                    job_info.user,
+                    pending_of_user + 1
+                );
+                warnings.push(format!(
+                    "You have {} pending jobs, more than the soft limit of {} per user.",
+                    pending_of_user + 1,
+                    limit
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Returns `true` if newly issued jobs are rejected, because the server
+    /// exceeds its memory or job limit.
+    pub fn refuses_new_jobs(&self) -> bool {
+        self.emergency
+            && (self.config.server_settings.emergency_policies)
+                .contains(&EmergencyPolicy::RefuseNewJobs)
+    }
+
+    /// Check the server's memory usage and number of jobs against the
+    /// configured limits. Logs warnings when getting close to a limit and
+    /// applies the emergency policies while a limit is exceeded.
+    pub fn check_limits(&mut self, memory_mb: Option<u64>) {
+        /// Compare a measured value to its limit and log the outcome.
+        /// Returns `true` if the limit is exceeded.
+        fn check(what: &str, value: Option<u64>, limit: Option<u64>) -> bool {
+            match (value, limit) {
+                (Some(value), Some(limit)) if value > limit => {
+                    log::error!(
+                        "Server {} of {} exceeds the limit of {}!",
+                        what,
+                        value,
+                        limit
+                    );
+                    true
+                }
+                (Some(value), Some(limit)) => {
+                    if value as f64 > limit as f64 * LIMIT_WARNING_RATIO {
+                        log::warn!(
+                            "Server {} of {} is close to the limit of {}!",
+                            what,
+                            value,
+                            limit
+                        );
+                    }
+                    false
+                }
+                _ => false,
+            }
+        }
+
+        let settings = &self.config.server_settings;
+        let job_count = Some(self.jobs.len() as u64);
+        let memory_exceeded = check("memory usage (MB)", memory_mb, settings.memory_limit_mb);
+        let jobs_exceeded = check("number of jobs", job_count, settings.job_limit);
+        let exceeded = memory_exceeded || jobs_exceeded;
+        if exceeded && !self.emergency {
+            log::error!("Server limits exceeded, applying emergency policies!");
+        } else if !exceeded && self.emergency {
+            log::info!("Server is below its limits again.");
+        }
+        self.emergency = exceeded;
+
+        let evict = (settings.emergency_policies).contains(&EmergencyPolicy::EvictFinishedJobs);
+        if exceeded && evict {
+            // Shrink the job list below the warning level. If memory is
+            // short, remove all concluded jobs.
+            let target = match settings.job_limit {
+                Some(limit) if !memory_exceeded => (limit as f64 * LIMIT_WARNING_RATIO) as usize,
+                _ => 0,
+            };
+            let evicted = self.evict_concluded_jobs(target);
+            log::warn!("Evicted {} finished or canceled jobs!", evicted);
+        }
+    }
+
+    /// Remove finished and canceled jobs, oldest first, until at most `target`
+    /// jobs are left or no more concluded jobs remain. Returns the number of
+    /// removed jobs.
+    fn evict_concluded_jobs(&mut self, target: usize) -> usize {
+        let excess = self.jobs.len().saturating_sub(target);
+        let concluded: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| {
+                let status = &job.lock().unwrap().info.status;
+                status.is_finished() || status.is_canceled()
+            })
+            .map(|(job_id, _)| *job_id)
+            .take(excess)
+            .collect();
+        for job_id in &concluded {
+            self.remove_job(*job_id);
+        }
+        concluded.len()
+    }
+
+    /// Remove jobs that have been finished or canceled.
+    /// If `all` is true, also remove failed jobs.
+    pub fn clean_jobs(&mut self, all: bool) {
+        let clean_pred = if all {
+            |status: &JobStatus| status.is_finished() || status.is_canceled()
+        } else {
+            |status: &JobStatus| status.has_succeeded() || status.is_canceled()
+        };
+
+        let cleaned: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| clean_pred(&job.lock().unwrap().info.status))
+            .map(|(job_id, _)| *job_id)
+            .collect();
+        for job_id in cleaned {
+            self.remove_job(job_id);
+        }
+    }
+
+    /// Perform regular maintenance.
+    pub fn run_maintenance(&mut self) {
+        // Inspect every job and "repair" if needed.
+        let mut jobs_to_be_removed: Vec<u64> = Vec::new();
+        let mut jobs_to_be_failed: Vec<Arc<Mutex<Job>>> = Vec::new();
+        let mut new_jobs_pending = false;
+
+        for (job_id, job) in &self.jobs {
+            let info = job.lock().unwrap().info.clone();
+            match &info.status {
+                JobStatus::Pending { .. }
+                    if self.jobs_waiting_for_dependencies.contains(job_id) =>
+                {
+                    // Jobs waiting for dependencies are resolved below.
+                }
+                JobStatus::Pending { .. } => {
+                    // Pending jobs should be available for workers.
+                    let newly_inserted = self
+                        .jobs_waiting_for_assignment
+                        .get_mut()
+                        .unwrap()
+                        .insert(*job_id);
+                    if newly_inserted {
+                        log::warn!("Job {} was pending but not available for workers!", job_id);
+                    }
+                }
+                JobStatus::Offered {
+                    issued,
+                    offered,
+                    worker: _,
+                } => {
+                    // A job should only be briefly in this state.
+                    let offer_timed_out = (Utc::now() - *offered).num_seconds()
+                        > self.config.server_settings.job_offer_timeout_seconds as i64;
+                    let worker_id = job.lock().unwrap().worker_id;
+                    let worker_alive = match worker_id {
+                        Some(id) => match self.workers.get(&id) {
+                            Some(weak_worker) => match weak_worker.upgrade() {
+                                Some(worker) => {
+                                    // Worker is still with us.
+                                    !worker.lock().unwrap().info.timed_out(
+                                        self.config.server_settings.worker_timeout_seconds,
+                                    )
+                                }
+                                None => false,
+                            },
+                            None => false,
+                        },
+                        None => false,
+                    };
+
+                    // Recover if offer timed out or worker died.
+                    if offer_timed_out || !worker_alive {
+                        log::warn!("Job {:?} got stuck in offered state. Recover...", info);
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.status = JobStatus::Pending { issued: *issued };
+                        job_lock.worker_id = None;
+                        job_lock.save();
+                        self.jobs_waiting_for_assignment
+                            .get_mut()
+                            .unwrap()
+                            .insert(*job_id);
+                        new_jobs_pending = true; // notify at the end
+                    }
+                }
+                JobStatus::Running {
+                    issued,
+                    started: _,
+                    worker,
+                } => {
+                    // If the job is running, the worker should still be alive.
+                    // Otherwise, give the worker some time to reconnect.
+                    let worker_id = job.lock().unwrap().worker_id;
+                    let grace = Duration::seconds(
+                        self.config.server_settings.worker_loss_grace_seconds as i64,
+                    );
+                    let worker_lost = self
+                        .worker_lost_for(worker_id)
+                        .is_some_and(|lost_for| lost_for >= grace);
+
+                    // Recover if worker died while the job was still running.
+                    if worker_lost && self.config.server_settings.requeue_on_worker_loss {
+                        log::warn!(
+                            "Worker {} died while job {:?} was still running. Recover...",
+                            worker,
+                            info
+                        );
+                        let mut job_lock = job.lock().unwrap();
+                        job_lock.info.record_attempt();
+                        job_lock.info.status = JobStatus::Pending { issued: *issued };
+                        job_lock.worker_id = None;
+                        job_lock.save();
+                        self.jobs_waiting_for_assignment
+                            .get_mut()
+                            .unwrap()
+                            .insert(*job_id);
+                        new_jobs_pending = true; // notify at the end
+                    } else if worker_lost {
+                        log::warn!(
+                            "Worker {} died while job {:?} was still running. Fail...",
+                            worker,
+                            info
+                        );
+                        jobs_to_be_failed.push(Arc::clone(job));
+                    }
+                }
+                JobStatus::Finished { finished, .. } => {
+                    // Finished jobs should be cleaned up after some time.
+                    let cleanup_job = (Utc::now() - *finished).num_minutes()
+                        > self.config.server_settings.job_cleanup_after_minutes as i64;
+                    if cleanup_job {
+                        log::debug!("Clean up old finished job: {:?}", info);
+                        jobs_to_be_removed.push(info.job_id);
+                    }
+                }
+                JobStatus::Canceled { canceled, .. } => {
+                    // Canceled jobs should be cleaned up after some time.
+                    let cleanup_job = (Utc::now() - *canceled).num_minutes()
+                        > self.config.server_settings.job_cleanup_after_minutes as i64;
+                    if cleanup_job {
+                        log::debug!("Clean up old canceled job: {:?}", info);
+                        jobs_to_be_removed.push(info.job_id);
+                    }
+                }
+            }
+        }
+
+        // Clean up jobs.
+        for job_id in jobs_to_be_removed {
+            self.remove_job(job_id);
+        }
+
+        // Delete expired outputs, even of jobs that are still listed.
+        let retention = self.config.server_settings.spool_retention_minutes;
+        if let (Some(spool), Some(minutes)) = (&self.spool, retention) {
+            let removed = spool.remove_expired(std::time::Duration::from_secs(minutes * 60));
+            if removed > 0 {
+                log::debug!(
+                    "Deleted {} expired outputs from the spool directory",
+                    removed
+                );
+            }
+        }
+
+        // Fail jobs of lost workers, unless they are retried.
+        for job in jobs_to_be_failed {
+            {
+                let mut job_lock = job.lock().unwrap();
+                if let JobStatus::Running {
+                    issued,
+                    started,
+                    worker,
+                } = job_lock.info.status.clone()
+                {
+                    let finished = Utc::now();
+                    job_lock.info.status = JobStatus::Finished {
+                        issued,
+                        started,
+                        finished,
+                        return_code: EXIT_CODE_WORKER_LOST,
+                        worker,
+                        run_time_seconds: (finished - started).num_seconds(),
+                        comment: "Connection to the worker has been lost.".into(),
+                        failure: Some(FailureKind::WorkerLost),
+                    };
+                    job_lock.worker_id = None;
+                    job_lock.save();
+                }
+            }
+            let job_info = job.lock().unwrap().info.clone();
+            self.account_job(&job_info);
+            if self.retry_finished_job(&job) {
+                new_jobs_pending = true; // notify at the end
+            } else {
+                job.lock().unwrap().notify_observers();
+            }
+        }
+
+        let mut workers_to_be_removed: Vec<u64> = Vec::new();
+        let mut workers_timed_out: Vec<WorkerInfo> = Vec::new();
+
+        // Remove workers that are no longer alive.
+        for (worker_id, weak_worker) in &self.workers {
+            match weak_worker.upgrade() {
+                Some(worker) => {
+                    // Worker is still connected but might not respond. Its
+                    // connection is closed, so that the worker reconnects.
+                    let worker_lock = worker.lock().unwrap();
+                    let info = &worker_lock.info;
+                    if info.timed_out(self.config.server_settings.worker_timeout_seconds) {
+                        workers_timed_out.push(info.clone());
+                        worker_lock.close_connection.notify_one();
+                    }
+                }
+                None => workers_to_be_removed.push(*worker_id),
+            }
+        }
+
+        // Clean up disconnected workers.
+        for id in workers_to_be_removed {
+            self.workers.remove(&id);
+        }
+        for info in workers_timed_out {
+            self.mark_worker_unreachable(info);
+        }
+
+        // Forget workers that have been unreachable for too long.
+        let retention = Duration::minutes(
+            self.config
+                .server_settings
+                .unreachable_worker_retention_minutes as i64,
+        );
+        self.unreachable_workers.retain(|_, info| {
+            info.unreachable_since
+                .is_some_and(|since| Utc::now() - since < retention)
+        });
+
+        // If jobs have been marked as pending, notify workers.
+        if new_jobs_pending {
+            self.notify_new_jobs.notify_waiters();
+        }
+
+        // Dependencies should have been resolved when jobs concluded.
+        self.resolve_dependencies();
+
+        // Keep an eye on the server's own resources.
+        self.check_limits(server_memory_mb());
+
+        // Record workers that cannot take any of the pending jobs.
+        let interval = self.config.server_settings.maintenance_interval_seconds;
+        self.sample_idle_resources(Utc::now(), interval);
+
+        // Forget scheduling decisions about removed jobs.
+        let jobs = &self.jobs;
+        self.scheduling_trace
+            .get_mut()
+            .unwrap()
+            .retain(|job_id, _| jobs.contains_key(job_id));
+
+        // Jobs might have been requeued above.
+        self.recount_assigned_jobs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{QosClass, RoutingRule},
+        structs::{DependencyCondition, ExitCodePolicy, JobDependency},
+    };
+    use std::{path::PathBuf, sync::RwLock};
+
+    #[test]
+    fn add_new_job() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["ls".to_string(), "-la".to_string()];
+        let cwd: PathBuf = "/tmp".into();
+        let resources = Resources::new(1, 8, 8 * 1024);
+        let job_info = JobInfo::new(cmd, cwd, resources, None, None, None);
+        manager.add_new_job(job_info);
+        assert_eq!(manager.get_all_job_infos().len(), 1);
+    }
+
+    #[test]
+    fn connection_slots() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.reconnect_stagger_ms = 50;
+        let mut manager = Manager::new(config);
+        let stagger = std::time::Duration::from_millis(50);
+
+        // Workers connecting at once are served one after another.
+        let start = Instant::now();
+        let slots: Vec<_> = (0..10).map(|_| manager.reserve_connection_slot()).collect();
+        assert!(slots[0] < start + stagger * 2);
+        for (index, slot) in slots.iter().enumerate() {
+            let earliest = start + stagger * index as u32;
+            assert!(*slot >= earliest && *slot <= earliest + stagger * 2);
+        }
+
+        // Disabled staggering serves workers immediately.
+        manager.config.server_settings.reconnect_stagger_ms = 0;
+        manager.next_connection_slot = Instant::now();
+        assert!(manager.reserve_connection_slot() <= Instant::now());
+    }
+
+    #[test]
+    fn get_job_waiting_for_assignment() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["ls".to_string(), "-la".to_string()];
+        let cwd: PathBuf = "/tmp".into();
+        let resources = Resources::new(1, 8, 8 * 1024);
+        let job_info = JobInfo::new(cmd, cwd, resources.clone(), None, None, None);
+        let job = manager.add_new_job(job_info);
+
+        // Put job on exclude list.
+        let mut exclude = BTreeSet::new();
+        exclude.insert(job.lock().unwrap().info.job_id);
+
+        // Now, we should not get it.
+        let job = manager.get_job_waiting_for_assignment(
+            0,
+            "no worker",
+            &exclude,
+            &resources,
+            &BTreeMap::new(),
+        );
+        assert!(job.is_none());
+
+        // Now we want any job. One is waiting to be assigned.
+        exclude.clear();
+        let job = manager.get_job_waiting_for_assignment(
+            0,
+            "no worker",
+            &exclude,
+            &resources,
+            &BTreeMap::new(),
+        );
+        assert!(job.is_some());
+
+        // We want any job, again. But none are left.
+        let job = manager.get_job_waiting_for_assignment(
+            0,
+            "no worker",
+            &exclude,
+            &resources,
+            &BTreeMap::new(),
+        );
+        assert!(job.is_none());
+    }
+
+    #[test]
+    fn named_resources() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.global_resources = Some(BTreeMap::from([("network".to_string(), 10)]));
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let requested = BTreeMap::from([("network".to_string(), 1), ("matlab".to_string(), 2)]);
+        let mut job_info = JobInfo::new(
+            vec!["ls".to_string()],
+            "/tmp".into(),
+            resources.clone(),
+            Some(requested),
+            None,
+            None,
+        );
+
+        // Resources unknown to the server are required from the worker.
+        manager.assign_named_resources(&mut job_info);
+        let global = BTreeMap::from([("network".to_string(), 1)]);
+        assert_eq!(job_info.global_resources, Some(global));
+        assert_eq!(job_info.worker_resources.named.get("matlab"), Some(&2));
+        manager.add_new_job(job_info);
+
+        // Workers without enough licenses do not get the job.
+        let mut free = resources;
+        free.named.insert("matlab".to_string(), 1);
+        let none = BTreeSet::new();
+        let no_pools = BTreeMap::new();
+        let job = manager.get_job_waiting_for_assignment(0, "worker", &none, &free, &no_pools);
+        assert!(job.is_none());
+        free.named.insert("matlab".to_string(), 2);
+        let job = manager.get_job_waiting_for_assignment(0, "worker", &none, &free, &no_pools);
+        assert!(job.is_some());
+    }
+
+    #[test]
+    fn announcements() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        manager.set_motd(Some("hello".into()));
+        manager.add_broadcast("expired".into(), 0);
+        manager.add_broadcast("restart soon".into(), 10);
+
+        // New clients get the motd and active broadcasts only.
+        let (client_tx, _client_rx) = mpsc::channel(1);
+        let announcements = manager.subscribe_announcements(client_tx);
+        let texts: Vec<_> = announcements
+            .into_iter()
+            .map(|message| match message {
+                ServerToClientMessage::Announcement { text, broadcast } => (text, broadcast),
+                other => panic!("Unexpected message: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec![("hello".into(), false), ("restart soon".into(), true)]
+        );
+
+        // Subscribed clients receive future broadcasts.
+        assert_eq!(manager.add_broadcast("now".into(), 1).len(), 1);
+    }
+
+    #[test]
+    fn remove_worker() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let worker_id = worker.lock().unwrap().info.worker_id;
+
+        // Run a job on the worker.
+        let cmd = vec!["sleep".to_string(), "10".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        let job = manager.add_new_job(job_info);
+        {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.worker_id = Some(worker_id);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+        }
+        manager
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .clear();
+
+        // The running job is requeued and the worker is blocked.
+        let (_, jobs_to_kill) = manager.remove_worker(worker_id, false, 10).unwrap();
+        assert_eq!(jobs_to_kill.len(), 1);
+        assert!(job.lock().unwrap().info.status.is_pending());
+        assert!(job.lock().unwrap().worker_id.is_none());
+        assert!(manager.worker_blocked_until("worker").is_some());
+        assert!(manager.worker_blocked_until("other").is_none());
+        assert!(manager.remove_worker(worker_id, false, 10).is_err());
+    }
+
+    #[test]
+    fn retry_finished_job() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        job_info.exit_code_policies = vec![
+            ExitCodePolicy {
+                exit_code: None,
+                action: ExitCodeAction::RetryElsewhere,
+            },
+            ExitCodePolicy {
+                exit_code: Some(64),
+                action: ExitCodeAction::Fail,
+            },
+        ];
+        job_info.max_retries = 1;
+        let job = manager.add_new_job(job_info);
+
+        let finish = |return_code| {
+            job.lock().unwrap().info.status = JobStatus::Finished {
+                issued: Utc::now(),
+                started: Utc::now(),
+                finished: Utc::now(),
+                return_code,
+                worker: "worker".into(),
+                run_time_seconds: 0,
+                comment: String::new(),
+                failure: None,
+            };
+        };
+
+        // Exit code 64 fails immediately.
+        finish(64);
+        assert!(!manager.retry_finished_job(&job));
+
+        // Other exit codes are retried on other workers until no retries are left.
+        finish(1);
+        assert!(manager.retry_finished_job(&job));
+        assert!(job.lock().unwrap().info.status.is_pending());
+        assert!(job.lock().unwrap().info.avoid_workers.contains("worker"));
+        finish(1);
+        assert!(!manager.retry_finished_job(&job));
+
+        // Only the retried run has been recorded as a previous attempt.
+        let job_info = job.lock().unwrap().info.clone();
+        assert_eq!(job_info.attempt(), 2);
+        assert_eq!(job_info.attempts[0].worker, "worker");
+        assert_eq!(job_info.attempts[0].exit_code, Some(1));
+    }
+
+    #[test]
+    fn requeue_migrated_job() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        let job = manager.add_new_job(job_info);
+
+        let finish = |return_code| {
+            job.lock().unwrap().info.status = JobStatus::Finished {
+                issued: Utc::now(),
+                started: Utc::now(),
+                finished: Utc::now(),
+                return_code,
+                worker: "worker".into(),
+                run_time_seconds: 0,
+                comment: String::new(),
+                failure: None,
+            };
+        };
+        let migration = JobMigration {
+            signal: 15,
+            grace_period_seconds: 60,
+            checkpoint: Some("/tmp/checkpoint".into()),
+        };
+
+        // Jobs that finished before they could be stopped are not requeued.
+        job.lock().unwrap().migration = Some(migration.clone());
+        finish(0);
+        assert!(!manager.requeue_migrated_job(&job));
+        assert!(job.lock().unwrap().migration.is_none());
+
+        // Stopped jobs resume from the checkpoint on another worker.
+        job.lock().unwrap().migration = Some(migration);
+        finish(EXIT_CODE_STOPPED);
+        assert!(manager.requeue_migrated_job(&job));
+        let info = job.lock().unwrap().info.clone();
+        assert!(info.status.is_pending());
+        assert!(info.avoid_workers.contains("worker"));
+        assert_eq!(info.checkpoint, Some("/tmp/checkpoint".into()));
+        assert_eq!(info.migrations, 1);
+    }
+
+    #[test]
+    fn persistence() {
+        let store = Arc::new(JobStore::open_in_memory().unwrap());
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config.clone());
+        manager.attach_store(Arc::clone(&store)).unwrap();
+        let resources = Resources::new(1, 1, 1024);
+        let mut job_ids = Vec::new();
+        for _ in 0..3 {
+            let cmd = vec!["true".to_string()];
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            let job = manager.add_new_job(job_info);
+            job_ids.push(job.lock().unwrap().info.job_id);
+        }
+        let running = manager.get_job(job_ids[1]).unwrap();
+        running.lock().unwrap().info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        running.lock().unwrap().notify_observers();
+        manager
+            .cancel_job(job_ids[2], false, Some("wrong parameters".into()))
+            .unwrap();
+
+        // Unsaved changes are persisted when the server shuts down.
+        let pending = manager.get_job(job_ids[0]).unwrap();
+        pending.lock().unwrap().info.project = Some("unsaved".into());
+        assert_eq!(manager.persist_state().unwrap(), 3);
+
+        // After a restart, the running job awaits its worker to reconnect.
+        let mut manager = Manager::new(config);
+        assert_eq!(manager.attach_store(store).unwrap(), 3);
+        let status = |job_id| {
+            manager
+                .get_job(job_id)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .info
+                .status
+                .clone()
+        };
+        assert!(status(job_ids[0]).is_pending());
+        let project = manager
+            .get_job(job_ids[0])
+            .unwrap()
+            .lock()
+            .unwrap()
+            .info
+            .project
+            .clone();
+        assert_eq!(project.as_deref(), Some("unsaved"));
+        assert!(status(job_ids[1]).is_running());
+        assert!(matches!(
+            status(job_ids[2]),
+            JobStatus::Canceled { reason: Some(reason), .. } if reason == "wrong parameters"
+        ));
+        assert_eq!(manager.jobs_waiting_for_assignment.lock().unwrap().len(), 1);
+        manager.run_maintenance();
+        let job = manager.get_job(job_ids[1]).unwrap();
+        assert!(job.lock().unwrap().info.status.is_running());
+        let running = BTreeSet::from([job_ids[1]]);
+        let adopted = manager.adopt_worker_jobs(u64::MAX, "worker", &running);
+        assert_eq!(adopted, vec![job_ids[1]]);
+
+        // New jobs do not reuse the IDs of restored jobs.
+        let cmd = vec!["true".to_string()];
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        assert!(job_info.job_id > job_ids[2]);
+    }
+
+    #[test]
+    fn stalled_jobs() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.stalled_job_action = StalledJobAction::Requeue;
+        let mut manager = Manager::new(config);
+        let cmd = vec!["sleep".to_string(), "100".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        let job = manager.add_new_job(job_info);
+        let job_id = job.lock().unwrap().info.job_id;
+        job.lock().unwrap().info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        job.lock().unwrap().worker_id = Some(7);
+
+        // Only newly stalled jobs are killed.
+        let stalled = BTreeSet::from([job_id]);
+        assert!(manager.update_stalled_jobs(8, &stalled).is_empty());
+        assert_eq!(manager.update_stalled_jobs(7, &stalled).len(), 1);
+        assert!(manager.update_stalled_jobs(7, &stalled).is_empty());
+        assert!(job.lock().unwrap().info.stalled);
+
+        // The kill is reported as a stall and requeued without using a retry.
+        job.lock().unwrap().info.status = JobStatus::Finished {
+            issued: Utc::now(),
+            started: Utc::now(),
+            finished: Utc::now(),
+            return_code: -9,
+            worker: "worker".into(),
+            run_time_seconds: 0,
+            comment: String::new(),
+            failure: Some(FailureKind::KilledByUser),
+        };
+        manager.classify_stalled_job(&job);
+        assert!(matches!(
+            job.lock().unwrap().info.status,
+            JobStatus::Finished {
+                return_code: EXIT_CODE_STALLED,
+                failure: Some(FailureKind::Stalled),
+                ..
+            }
+        ));
+        assert!(manager.retry_finished_job(&job));
+        let job_lock = job.lock().unwrap();
+        assert!(job_lock.info.status.is_pending());
+        assert!(!job_lock.info.stalled);
+        assert_eq!(job_lock.info.retries, 0);
+    }
+
+    #[test]
+    fn conditional_dependencies() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let new_job = |dependencies: Vec<(u64, DependencyCondition)>| {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.dependencies = dependencies
+                .into_iter()
+                .map(|(job_id, condition)| JobDependency { job_id, condition })
+                .collect();
+            job_info
+        };
+
+        let first = manager.add_new_job(new_job(vec![]));
+        let first_id = first.lock().unwrap().info.job_id;
+        let on_success =
+            manager.add_new_job(new_job(vec![(first_id, DependencyCondition::Success)]));
+        let on_failure =
+            manager.add_new_job(new_job(vec![(first_id, DependencyCondition::Failure)]));
+        let on_success_id = on_success.lock().unwrap().info.job_id;
+        let chained = manager.add_new_job(new_job(vec![(on_success_id, DependencyCondition::Any)]));
+        let chained_ok =
+            manager.add_new_job(new_job(vec![(on_success_id, DependencyCondition::Success)]));
+        assert_eq!(manager.jobs_waiting_for_assignment.lock().unwrap().len(), 1);
+
+        // The first job fails.
+        first.lock().unwrap().info.status = JobStatus::Finished {
+            issued: Utc::now(),
+            started: Utc::now(),
+            finished: Utc::now(),
+            return_code: 1,
+            worker: "worker".into(),
+            run_time_seconds: 0,
+            comment: String::new(),
+            failure: None,
+        };
+        manager.resolve_dependencies();
+
+        // Only the failure handler is started. The canceled job still
+        // releases the job depending on any outcome.
+        assert!(on_success.lock().unwrap().info.status.is_canceled());
+        assert!(on_failure.lock().unwrap().info.status.is_pending());
+        assert!(chained.lock().unwrap().info.status.is_pending());
+        // The cancellation propagates along the chain of successful jobs.
+        assert!(chained_ok.lock().unwrap().info.status.is_canceled());
+        assert_eq!(manager.jobs_waiting_for_assignment.lock().unwrap().len(), 3);
+        assert!(manager.jobs_waiting_for_dependencies.is_empty());
+    }
+
+    #[test]
+    fn dependency_chains() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut add_job = |dependency: Option<(u64, DependencyCondition)>| {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.dependencies = dependency
+                .into_iter()
+                .map(|(job_id, condition)| JobDependency { job_id, condition })
+                .collect();
+            manager.add_new_job(job_info).lock().unwrap().info.job_id
+        };
+
+        // Two chains: first <- on_success <- any, and root <- any_root <- ok_root.
+        let first = add_job(None);
+        let on_success = add_job(Some((first, DependencyCondition::Success)));
+        let any = add_job(Some((on_success, DependencyCondition::Any)));
+        let root = add_job(None);
+        let any_root = add_job(Some((root, DependencyCondition::Any)));
+        let ok_root = add_job(Some((any_root, DependencyCondition::Success)));
+
+        // Orphaned jobs stay pending with a reason, along with their dependents.
+        manager.cancel_job(first, false, None).unwrap();
+        assert_eq!(manager.orphan_dependents(first), 1);
+        manager.resolve_dependencies();
+        let info = |manager: &Manager, job_id| {
+            manager
+                .get_job(job_id)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .info
+                .clone()
+        };
+        assert!(info(&manager, on_success).status.is_pending());
+        assert!(info(&manager, on_success).pending_reason.is_some());
+        assert!(info(&manager, any).status.is_pending());
+
+        // Cascading cancels all downstream jobs, whatever their conditions.
+        manager.cancel_job(root, false, None).unwrap();
+        assert_eq!(manager.cancel_dependents(root), 2);
+        manager.resolve_dependencies();
+        assert!(info(&manager, any_root).status.is_canceled());
+        assert!(info(&manager, ok_root).status.is_canceled());
+        assert_eq!(info(&manager, ok_root).events.len(), 1);
+    }
+
+    #[test]
+    fn suggest_resources() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["simulate".to_string()];
+        let resources = Resources::new(1, 8, 16 * 1024);
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+
+        // Record usage of succeeded jobs only.
+        for (return_code, avg_cpus) in [(0, 1.5), (1, 7.0), (0, 1.8), (0, 0.9)] {
+            job_info.status = JobStatus::Finished {
+                issued: Utc::now(),
+                started: Utc::now(),
+                finished: Utc::now(),
+                return_code,
+                worker: "worker".into(),
+                run_time_seconds: 0,
+                comment: String::new(),
+                failure: None,
+            };
+            job_info.resource_usage = Some(ResourceUsage {
+                avg_cpus,
+                peak_ram_mb: 1000,
+            });
+            assert!(manager.suggest_resources(&job_info).is_none());
+            manager.record_resource_usage(&job_info);
         }
 
-        // If jobs have been marked as pending, notify workers.
-        if new_jobs_pending {
-            self.notify_new_jobs.notify_waiters();
+        let suggested = manager.suggest_resources(&job_info).unwrap();
+        assert_eq!(suggested, Resources::new(1, 2, 1200));
+    }
+
+    #[test]
+    fn resource_pools() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 4, 1024);
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        job_info.pool = Some("ci".into());
+        manager.add_new_job(job_info);
+
+        // Jobs in a pool are not offered to workers without the pool, nor
+        // based on the worker's resources outside the pool.
+        let exclude = BTreeSet::new();
+        let mut pools = BTreeMap::new();
+        let job = manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &pools);
+        assert!(job.is_none());
+        pools.insert("ci".to_string(), Resources::new(1, 2, 1024));
+        let job = manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &pools);
+        assert!(job.is_none());
+        pools.insert("ci".to_string(), Resources::new(1, 8, 1024));
+        let job = manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &pools);
+        assert!(job.is_some());
+    }
+
+    #[test]
+    fn max_jobs_per_user_per_worker() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.max_jobs_per_user_per_worker = Some(1);
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        for user in ["alice", "alice", "bob"] {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.user = user.into();
+            manager.add_new_job(job_info);
         }
+
+        // Only one job per user is offered to the same worker.
+        let exclude = BTreeSet::new();
+        let mut users = Vec::new();
+        while let Some(job) =
+            manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &BTreeMap::new())
+        {
+            users.push(job.lock().unwrap().info.user.clone());
+        }
+        users.sort();
+        assert_eq!(users, vec!["alice", "bob"]);
+
+        // Another worker picks up the remaining job.
+        let job =
+            manager.get_job_waiting_for_assignment(1, "v", &exclude, &resources, &BTreeMap::new());
+        assert_eq!(job.unwrap().lock().unwrap().info.user, "alice");
+        assert_eq!(manager.assigned_jobs_of_user(0, "alice"), 1);
+        assert_eq!(manager.assigned_jobs_of_user(1, "alice"), 1);
+
+        // Another job of the user waits until the user's job on the worker is released.
+        let mut job_info = JobInfo::new(
+            vec!["true".into()],
+            "/tmp".into(),
+            resources.clone(),
+            None,
+            None,
+            None,
+        );
+        job_info.user = "alice".into();
+        let job_id = manager.add_new_job(job_info).lock().unwrap().info.job_id;
+        let next = |manager: &Manager| {
+            manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &BTreeMap::new())
+        };
+        assert!(next(&manager).is_none());
+        let alice_job = manager.jobs.values().find(|job| {
+            let job_lock = job.lock().unwrap();
+            job_lock.worker_id == Some(0) && job_lock.info.user == "alice"
+        });
+        let alice_job_id = alice_job.unwrap().lock().unwrap().info.job_id;
+        manager.release_assigned_job(0, alice_job_id);
+        assert_eq!(next(&manager).unwrap().lock().unwrap().info.job_id, job_id);
+
+        // Both jobs are still offered to the worker, according to their status.
+        manager.recount_assigned_jobs();
+        assert_eq!(manager.assigned_jobs_of_user(0, "alice"), 2);
+        assert_eq!(manager.assigned_jobs_of_user(0, "bob"), 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::PathBuf;
+    #[test]
+    fn requeue_on_worker_loss() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut jobs = Vec::new();
+        for _ in 0..2 {
+            let cmd = vec!["true".to_string()];
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            jobs.push(manager.add_new_job(job_info));
+        }
+        manager
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .clear();
+
+        // Both jobs run on a worker that has just lost its connection.
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let worker_id = info.worker_id;
+        manager.mark_worker_unreachable(info);
+        for job in &jobs {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.worker_id = Some(worker_id);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+        }
+
+        // The worker might still reconnect within the grace period.
+        manager.run_maintenance();
+        assert!(jobs[0].lock().unwrap().info.status.is_running());
+
+        // Afterwards, jobs are requeued or, if disabled, marked as failed.
+        manager.config.server_settings.worker_loss_grace_seconds = 0;
+        manager.run_maintenance();
+        assert!(jobs[0].lock().unwrap().info.status.is_pending());
+
+        manager.config.server_settings.requeue_on_worker_loss = false;
+        {
+            let mut job_lock = jobs[1].lock().unwrap();
+            job_lock.worker_id = Some(worker_id);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+        }
+        manager.run_maintenance();
+        let status = jobs[1].lock().unwrap().info.status.clone();
+        assert!(matches!(
+            status,
+            JobStatus::Finished {
+                failure: Some(FailureKind::WorkerLost),
+                ..
+            }
+        ));
+    }
 
     #[test]
-    fn add_new_job() {
+    fn unattached_interactive_jobs() {
         let config = Config::new(Some("no-config".into())).unwrap();
         let mut manager = Manager::new(config);
-        let cmd = vec!["ls".to_string(), "-la".to_string()];
-        let cwd: PathBuf = "/tmp".into();
-        let resources = Resources::new(1, 8, 8 * 1024);
-        let job_info = JobInfo::new(cmd, cwd, resources, None, None, None);
+        let cmd = vec!["sh".to_string()];
+        let mut job_info = JobInfo::new(
+            cmd,
+            "/tmp".into(),
+            Resources::new(1, 1, 1024),
+            None,
+            None,
+            None,
+        );
+        job_info.interactive = true;
+        let job = manager.add_new_job(job_info);
+        let job_id = job.lock().unwrap().info.job_id;
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let (kill_job_tx, mut kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let worker_id = worker.lock().unwrap().info.worker_id;
+        let start = |started| {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started,
+                worker: "worker".into(),
+            };
+            job_lock.worker_id = Some(worker_id);
+        };
+
+        // Jobs that have just started are given time for the client to attach.
+        start(Utc::now());
+        assert!(manager.cancel_unattached_jobs().is_empty());
+
+        // Attached jobs are never canceled.
+        start(Utc::now() - Duration::hours(1));
+        let (client_tx, _client_rx) = mpsc::channel(1);
+        job.lock().unwrap().attached_client = Some(client_tx);
+        assert!(manager.cancel_unattached_jobs().is_empty());
+
+        // Jobs without client are canceled after the timeout.
+        job.lock().unwrap().attached_client = None;
+        let kills = manager.cancel_unattached_jobs();
+        assert_eq!(kills.len(), 1);
+        kills[0].0.try_send(kills[0].1).unwrap();
+        assert_eq!(kill_job_rx.try_recv().unwrap(), job_id);
+        assert!(job.lock().unwrap().info.status.is_canceled());
+    }
+
+    #[test]
+    fn reserve_and_drain_workers() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        for user in ["alice", "bob"] {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.user = user.into();
+            manager.add_new_job(job_info);
+        }
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let worker = manager.add_new_worker(info, kill_job_tx.clone(), forward_tx.clone());
+        let worker_id = worker.lock().unwrap().info.worker_id;
+
+        // A reserved worker only receives jobs of the user.
+        manager
+            .reserve_worker(worker_id, Some("bob".into()))
+            .unwrap();
+        let exclude = BTreeSet::new();
+        let pools = BTreeMap::new();
+        let job = manager
+            .get_job_waiting_for_assignment(worker_id, "worker", &exclude, &resources, &pools);
+        assert_eq!(job.unwrap().lock().unwrap().info.user, "bob");
+        let job = manager
+            .get_job_waiting_for_assignment(worker_id, "worker", &exclude, &resources, &pools);
+        assert!(job.is_none());
+
+        // Reservation and draining are restored when the worker reconnects.
+        manager.drain_worker(worker_id, true).unwrap();
+        drop(worker);
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let info = &worker.lock().unwrap().info;
+        assert!(info.draining);
+        assert_eq!(info.reserved_for.as_deref(), Some("bob"));
+        assert!(manager.drain_worker(worker_id, false).is_err());
+    }
+
+    #[test]
+    fn worker_labels() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let cmd = vec!["true".to_string()];
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        job_info.constraints = BTreeMap::from([("arch".to_string(), "avx512".to_string())]);
         manager.add_new_job(job_info);
-        assert_eq!(manager.get_all_job_infos().len(), 1);
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let exclude = BTreeSet::new();
+        let pools = BTreeMap::new();
+
+        // Only workers with matching labels receive the job.
+        for (arch, matches) in [("x86", false), ("avx512", true)] {
+            let mut info = WorkerInfo::new(arch.into(), "0.0.0".into());
+            info.labels.insert("arch".into(), arch.into());
+            let worker = manager.add_new_worker(info, kill_job_tx.clone(), forward_tx.clone());
+            let worker_id = worker.lock().unwrap().info.worker_id;
+            let job = manager
+                .get_job_waiting_for_assignment(worker_id, arch, &exclude, &resources, &pools);
+            assert_eq!(job.is_some(), matches);
+        }
     }
 
     #[test]
-    fn get_job_waiting_for_assignment() {
+    fn worker_affinity() {
         let config = Config::new(Some("no-config".into())).unwrap();
         let mut manager = Manager::new(config);
-        let cmd = vec!["ls".to_string(), "-la".to_string()];
-        let cwd: PathBuf = "/tmp".into();
-        let resources = Resources::new(1, 8, 8 * 1024);
-        let job_info = JobInfo::new(cmd, cwd, resources.clone(), None, None, None);
+        let resources = Resources::new(1, 1, 1024);
+        let cmd = vec!["true".to_string()];
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        job_info.affinity = Some(WorkerAffinity::Worker("warm".into()));
+        let job_id = manager.add_new_job(job_info).lock().unwrap().info.job_id;
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let exclude = BTreeSet::new();
+        let pools = BTreeMap::new();
+        let mut workers = BTreeMap::new();
+        for name in ["warm", "cold"] {
+            let mut info = WorkerInfo::new(name.into(), "0.0.0".into());
+            info.free_resources = resources.clone();
+            let worker = manager.add_new_worker(info, kill_job_tx.clone(), forward_tx.clone());
+            workers.insert(name, worker);
+        }
+        let get_job = |manager: &Manager, name| {
+            let worker_id = workers[name].lock().unwrap().info.worker_id;
+            manager.get_job_waiting_for_assignment(worker_id, name, &exclude, &resources, &pools)
+        };
+
+        // Other workers leave the job to the preferred worker while it has room.
+        assert!(get_job(&manager, "cold").is_none());
+
+        // Busy preferred workers are not waited for.
+        workers["warm"].lock().unwrap().info.free_resources = Resources::new(0, 0, 0);
+        let job = get_job(&manager, "cold").unwrap();
+        assert_eq!(job.lock().unwrap().info.job_id, job_id);
+    }
+
+    #[test]
+    fn rebalance_offers() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let mut info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        info.free_resources = resources.clone();
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let worker_id = worker.lock().unwrap().info.worker_id;
+        let exclude = BTreeSet::new();
+        let pools = BTreeMap::new();
+
+        // Offer a job of low priority to the worker.
+        let cmd = vec!["true".to_string()];
+        let job_info = JobInfo::new(
+            cmd.clone(),
+            "/tmp".into(),
+            resources.clone(),
+            None,
+            None,
+            None,
+        );
+        manager.add_new_job(job_info);
+        let offered = manager
+            .get_job_waiting_for_assignment(worker_id, "worker", &exclude, &resources, &pools)
+            .unwrap();
+        let offered_id = {
+            let mut job_lock = offered.lock().unwrap();
+            job_lock.info.status = JobStatus::Offered {
+                issued: Utc::now(),
+                offered: Utc::now() - Duration::seconds(10),
+                worker: "worker".into(),
+            };
+            job_lock.worker_id = Some(worker_id);
+            job_lock.info.job_id
+        };
+        worker.lock().unwrap().info.jobs_offered.insert(offered_id);
+        assert!(manager.rebalance_offers().is_empty());
+
+        // A job of higher priority that only fits in its place takes over.
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        job_info.priority = 10;
+        manager.add_new_job(job_info);
+        assert_eq!(manager.rebalance_offers().len(), 1);
+        assert!(offered.lock().unwrap().info.status.is_pending());
+        assert!(worker
+            .lock()
+            .unwrap()
+            .withdrawn_offers
+            .contains_key(&offered_id));
+        let waiting = manager.jobs_waiting_for_assignment.lock().unwrap().clone();
+        assert!(waiting.contains(&offered_id));
+    }
+
+    #[test]
+    fn job_priorities() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut added = Vec::new();
+        for priority in [0, 10, -5, 10] {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.priority = priority;
+            added.push(manager.add_new_job(job_info).lock().unwrap().info.job_id);
+        }
+
+        // Jobs are offered by priority, then in the order they were issued.
+        let exclude = BTreeSet::new();
+        let mut job_ids = Vec::new();
+        while let Some(job) =
+            manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &BTreeMap::new())
+        {
+            job_ids.push(job.lock().unwrap().info.job_id);
+        }
+        assert_eq!(job_ids, vec![added[1], added[3], added[0], added[2]]);
+    }
+
+    #[test]
+    fn boost_jobs() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut added = Vec::new();
+        for priority in [0, 10, -5] {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.priority = priority;
+            added.push(manager.add_new_job(job_info).lock().unwrap().info.job_id);
+        }
+
+        // A boosted job catches up with the highest priority, a job moved to
+        // the front overtakes all other jobs.
+        assert_eq!(
+            manager.boost_job(added[2], false, "alice").unwrap(),
+            (-5, 10)
+        );
+        assert_eq!(manager.boost_job(added[0], true, "bob").unwrap(), (0, 11));
+        let job = manager.get_job(added[0]).unwrap();
+        let events = job.lock().unwrap().info.events.clone();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].text.contains("bob"));
+
+        let exclude = BTreeSet::new();
+        let mut job_ids = Vec::new();
+        while let Some(job) =
+            manager.get_job_waiting_for_assignment(0, "w", &exclude, &resources, &BTreeMap::new())
+        {
+            job_ids.push(job.lock().unwrap().info.job_id);
+        }
+        assert_eq!(job_ids, vec![added[0], added[1], added[2]]);
+
+        // Offered jobs cannot be boosted anymore.
+        assert!(manager.boost_job(added[1], true, "alice").is_err());
+    }
+
+    #[test]
+    fn idle_resources() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        worker.lock().unwrap().info.free_resources = Resources::new(2, 4, 2048);
+
+        // Nothing is idle without pending jobs.
+        let now = Utc::now();
+        manager.sample_idle_resources(now, 1800);
+        assert!(manager.get_idle_resources().is_empty());
+
+        // Resources are idle while the pending job needs more RAM.
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 2, 4096);
+        let job_info = JobInfo::new(cmd.clone(), "/tmp".into(), resources, None, None, None);
+        manager.add_new_job(job_info);
+        manager.sample_idle_resources(now, 1800);
+        let report = manager.get_idle_resources();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].idle_hours, 0.5);
+        assert_eq!(report[0].cpu_hours, 2.0);
+        assert_eq!(report[0].ram_gb_hours, 1.0);
+        assert_eq!(report[0].limiting_resource(), Some("RAM"));
+
+        // Resources are not idle if a pending job fits.
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        manager.add_new_job(job_info);
+        manager.sample_idle_resources(now, 1800);
+        assert_eq!(manager.get_idle_resources()[0].idle_hours, 0.5);
+    }
+
+    #[test]
+    fn qos_classes() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.qos_classes = vec![
+            QosClass {
+                name: "best-effort".into(),
+                priority: -10,
+                preemptible: true,
+                preemption_grace_seconds: 30,
+                max_runtime_seconds: None,
+                max_running_jobs_per_user: None,
+            },
+            QosClass {
+                name: "urgent".into(),
+                priority: 10,
+                preemptible: false,
+                preemption_grace_seconds: 0,
+                max_runtime_seconds: Some(3600),
+                max_running_jobs_per_user: Some(1),
+            },
+        ];
+        config.server_settings.default_qos = Some("best-effort".into());
+        let mut manager = Manager::new(config);
+        let new_job = |qos: Option<&str>, cpus| {
+            let resources = Resources::new(1, cpus, 1024);
+            let mut job_info = JobInfo::new(
+                vec!["true".into()],
+                "/tmp".into(),
+                resources,
+                None,
+                None,
+                None,
+            );
+            job_info.qos = qos.map(String::from);
+            job_info.wall_time_limit_seconds = Some(7200);
+            job_info
+        };
+
+        // Classes set the priority and limit the run time of their jobs.
+        let mut best_effort = new_job(None, 4);
+        manager.apply_qos_class(&mut best_effort).unwrap();
+        assert_eq!(best_effort.qos.as_deref(), Some("best-effort"));
+        assert_eq!(best_effort.priority, -10);
+        let mut urgent = new_job(Some("urgent"), 2);
+        manager.apply_qos_class(&mut urgent).unwrap();
+        assert_eq!(urgent.priority, 10);
+        assert_eq!(urgent.wall_time_limit_seconds, Some(3600));
+        assert!(manager
+            .apply_qos_class(&mut new_job(Some("vip"), 1))
+            .is_err());
+
+        // A best-effort job occupies the only worker.
+        let mut info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        info.capabilities.insert(CAP_JOB_MIGRATION.into());
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let worker_id = worker.lock().unwrap().info.worker_id;
+        let running = manager.add_new_job(best_effort);
+        manager
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .clear();
+        let running_id = {
+            let mut job_lock = running.lock().unwrap();
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+            job_lock.worker_id = Some(worker_id);
+            job_lock.info.job_id
+        };
+        worker.lock().unwrap().info.jobs_running.insert(running_id);
+        worker.lock().unwrap().info.free_resources = Resources::new(1, 0, 1024);
+
+        // The urgent job preempts it, but only once.
+        manager.add_new_job(urgent);
+        let preemptions = manager.preempt_jobs();
+        assert_eq!(preemptions.len(), 1);
+        assert!(matches!(
+            preemptions[0].1,
+            ServerToWorkerMessage::StopJob {
+                job_id,
+                grace_period_seconds: 30,
+                ..
+            } if job_id == running_id
+        ));
+        assert!(running.lock().unwrap().preempted);
+        assert!(manager.preempt_jobs().is_empty());
+    }
+
+    #[test]
+    fn project_quotas() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.projects = vec![Project {
+            name: "Grant".into(),
+            max_running_jobs: Some(1),
+            cpu_hours: Some(2.0),
+        }];
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 2, 1024);
+        let new_job = |project: Option<&str>| {
+            let cmd = vec!["true".to_string()];
+            let mut job_info =
+                JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_info.project = project.map(String::from);
+            job_info
+        };
+        assert!(manager.check_project(&new_job(Some("other"))).is_err());
+        manager.add_new_job(new_job(Some("Grant")));
+        manager.add_new_job(new_job(Some("Grant")));
+        manager.add_new_job(new_job(None));
+
+        // Only one job of the project runs at a time.
+        let exclude = BTreeSet::new();
+        let limit = Resources::new(10, 10, 10 * 1024);
+        let get_job =
+            || manager.get_job_waiting_for_assignment(0, "w", &exclude, &limit, &BTreeMap::new());
+        let job = get_job().unwrap();
+        assert_eq!(get_job().unwrap().lock().unwrap().info.project, None);
+        assert!(get_job().is_none());
+
+        // One hour on two cores uses up the project's CPU hours.
+        let mut job_info = job.lock().unwrap().info.clone();
+        job_info.status = JobStatus::Finished {
+            issued: Utc::now(),
+            started: Utc::now(),
+            finished: Utc::now(),
+            return_code: 0,
+            worker: "w".into(),
+            run_time_seconds: 3600,
+            comment: String::new(),
+            failure: None,
+        };
+        job.lock().unwrap().info.status = job_info.status.clone();
+        manager.account_job(&job_info);
+        assert!(manager.check_project(&new_job(Some("Grant"))).is_err());
+        assert!(manager
+            .get_job_waiting_for_assignment(0, "w", &exclude, &limit, &BTreeMap::new())
+            .is_none());
+
+        let usage = manager.get_project_usage();
+        let grant = usage
+            .iter()
+            .find(|usage| usage.project.as_deref() == Some("Grant"))
+            .unwrap();
+        assert_eq!(grant.jobs_pending, 1);
+        assert_eq!(grant.jobs_succeeded, 1);
+        assert_eq!(grant.cpu_hours, 2.0);
+        assert_eq!(grant.cpu_hours_quota, Some(2.0));
+    }
+
+    #[test]
+    fn apply_routing_rules() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.routing_rules = vec![RoutingRule {
+            command: r"^vivado\b".into(),
+            pool: Some("licensed".into()),
+            global_resources: BTreeMap::from([("vivado".to_string(), 1)]),
+        }];
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+
+        // Matching jobs get the pool and resources of the rule.
+        let cmd = vec![
+            "vivado".to_string(),
+            "-mode".to_string(),
+            "batch".to_string(),
+        ];
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        manager.apply_routing_rules(&mut job_info).unwrap();
+        assert_eq!(job_info.pool.as_deref(), Some("licensed"));
+        assert_eq!(job_info.global_resources.unwrap().get("vivado"), Some(&1));
+
+        // Other jobs are left alone.
+        let cmd = vec!["make".to_string()];
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        manager.apply_routing_rules(&mut job_info).unwrap();
+        assert!(job_info.pool.is_none());
+        assert!(job_info.global_resources.is_none());
+
+        // Invalid rules are reported.
+        manager.config.server_settings.routing_rules[0].command = "(".into();
+        assert!(manager.apply_routing_rules(&mut job_info).is_err());
+    }
+
+    #[test]
+    fn unreachable_workers() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        manager.mark_worker_unreachable(info);
+
+        // The lost worker is still listed, but marked unreachable.
+        let infos = manager.get_all_worker_infos();
+        assert_eq!(infos.len(), 1);
+        assert!(infos[0].offline);
+        assert!(infos[0].unreachable_since.is_some());
+
+        // Once the worker reconnects, it is listed as usual.
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let _worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let infos = manager.get_all_worker_infos();
+        assert_eq!(infos.len(), 1);
+        assert!(infos[0].unreachable_since.is_none());
+    }
+
+    #[test]
+    fn reconcile_worker_jobs() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut jobs = Vec::new();
+        for _ in 0..3 {
+            let cmd = vec!["true".to_string()];
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            jobs.push(manager.add_new_job(job_info));
+        }
+        manager
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .clear();
+
+        // The server believes jobs 0 and 1 to be running on worker 7,
+        // but the worker is running jobs 1 and 2.
+        for job in &jobs[..2] {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.worker_id = Some(7);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+        }
+        let job_ids: Vec<u64> = jobs
+            .iter()
+            .map(|job| job.lock().unwrap().info.job_id)
+            .collect();
+        let running = BTreeSet::from([job_ids[1], job_ids[2]]);
+        let (requeued, withdraw, kill) =
+            manager.reconcile_worker_jobs(7, &BTreeSet::new(), &running);
+
+        // The lost job is requeued and the unassigned job is killed.
+        assert_eq!(requeued, vec![job_ids[0]]);
+        assert!(jobs[0].lock().unwrap().info.status.is_pending());
+        assert!(manager
+            .jobs_waiting_for_assignment
+            .lock()
+            .unwrap()
+            .contains(&job_ids[0]));
+        assert!(withdraw.is_empty());
+        assert_eq!(kill.len(), 1);
+        assert_eq!(kill[0].job_id, job_ids[2]);
+    }
+
+    #[test]
+    fn adopt_worker_jobs() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        let mut job_ids = Vec::new();
+        for _ in 0..3 {
+            let cmd = vec!["true".to_string()];
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            job_ids.push(manager.add_new_job(job_info).lock().unwrap().info.job_id);
+        }
+
+        // Job 0 has been requeued, job 1 is assigned to a lost worker, and
+        // job 2 has been offered to another worker meanwhile.
+        let job = manager.get_job(job_ids[1]).unwrap();
+        {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.worker_id = Some(3);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "old".into(),
+            };
+        }
+        manager
+            .jobs_waiting_for_assignment
+            .get_mut()
+            .unwrap()
+            .remove(&job_ids[1]);
+        manager
+            .get_job(job_ids[2])
+            .unwrap()
+            .lock()
+            .unwrap()
+            .info
+            .status = JobStatus::Offered {
+            issued: Utc::now(),
+            offered: Utc::now(),
+            worker: "other".into(),
+        };
+
+        let running = job_ids.iter().copied().collect();
+        let adopted = manager.adopt_worker_jobs(7, "worker", &running);
+        assert_eq!(adopted, job_ids[..2]);
+        for job_id in &job_ids[..2] {
+            let job = manager.get_job(*job_id).unwrap();
+            let job_lock = job.lock().unwrap();
+            assert_eq!(job_lock.worker_id, Some(7));
+            assert!(job_lock.info.status.is_running());
+        }
+        assert!(!manager
+            .jobs_waiting_for_assignment
+            .lock()
+            .unwrap()
+            .contains(&job_ids[0]));
+    }
+
+    #[test]
+    fn job_receipt() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 4, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        let job_info = manager.add_new_job(job_info).lock().unwrap().info.clone();
+
+        // Without workers, the job cannot be placed.
+        let receipt = manager.job_receipt(&job_info);
+        assert_eq!(receipt.queue, "default");
+        assert_eq!(receipt.candidate_workers, 0);
+        assert_eq!(receipt.estimated_wait_seconds, None);
+        assert_eq!(receipt.warnings.len(), 1);
+
+        // A worker with enough free resources can start the job right away.
+        let mut info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        info.system_info.cpu_cores = 8;
+        info.system_info.total_ram_mb = 4096;
+        info.free_resources = Resources::new(1, 8, 4096);
+        let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+        let (forward_tx, _forward_rx) = mpsc::channel(1);
+        let _worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+        let receipt = manager.job_receipt(&job_info);
+        assert_eq!(receipt.candidate_workers, 1);
+        assert_eq!(receipt.available_workers, 1);
+        assert_eq!(receipt.estimated_wait_seconds, Some(0));
+        assert!(receipt.warnings.is_empty());
+
+        // A job larger than the worker is flagged.
+        let mut job_info = job_info;
+        job_info.worker_resources.cpus = 16;
+        let receipt = manager.job_receipt(&job_info);
+        assert_eq!(receipt.candidate_workers, 0);
+        assert_eq!(receipt.warnings.len(), 1);
+    }
+
+    #[test]
+    fn observe_group() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let new_job = |group: Option<&str>| {
+            let cmd = vec!["true".to_string()];
+            let resources = Resources::new(1, 1, 1024);
+            let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+            job_info.group = group.map(str::to_string);
+            job_info
+        };
+        manager.add_new_job(new_job(Some("sweep")));
+        manager.add_new_job(new_job(None));
+
+        // Existing jobs of the group are returned.
+        let (observer_tx, mut observer_rx) = mpsc::channel(10);
+        let job_infos = manager.observe_group("sweep", observer_tx);
+        assert_eq!(job_infos.len(), 1);
+
+        // Jobs added to the group later are announced to the observer.
+        let job = manager.add_new_job(new_job(Some("sweep")));
+        let job_id = job.lock().unwrap().info.job_id;
+        assert_eq!(observer_rx.try_recv(), Ok(job_id));
+        manager.add_new_job(new_job(Some("other")));
+        assert!(observer_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn concurrent_assignment() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        for _ in 0..100 {
+            let cmd = vec!["true".to_string()];
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+            manager.add_new_job(job_info);
+        }
+
+        // Workers pick jobs concurrently under the read lock.
+        let manager = Arc::new(RwLock::new(manager));
+        let threads: Vec<_> = (0..8)
+            .map(|worker_id| {
+                let manager = manager.clone();
+                let resources = resources.clone();
+                std::thread::spawn(move || {
+                    let mut job_ids = Vec::new();
+                    let exclude = BTreeSet::new();
+                    let pools = BTreeMap::new();
+                    while let Some(job) = manager.read().unwrap().get_job_waiting_for_assignment(
+                        worker_id, "w", &exclude, &resources, &pools,
+                    ) {
+                        job_ids.push(job.lock().unwrap().info.job_id);
+                    }
+                    job_ids
+                })
+            })
+            .collect();
+
+        // Every job has been handed out exactly once.
+        let job_ids: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(job_ids.len(), 100);
+        assert_eq!(job_ids.iter().collect::<BTreeSet<_>>().len(), 100);
+    }
+
+    #[test]
+    fn scheduling_trace() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.scheduling_trace = true;
+        let mut manager = Manager::new(config);
+        let cmd = vec!["true".to_string()];
+        let job_info = JobInfo::new(
+            cmd,
+            "/tmp".into(),
+            Resources::new(1, 8, 1024),
+            None,
+            None,
+            None,
+        );
+        let job_id = manager.add_new_job(job_info).lock().unwrap().info.job_id;
+
+        // The job does not fit on the small worker but on the large one.
+        let (exclude, pools) = (BTreeSet::new(), BTreeMap::new());
+        let small = Resources::new(4, 4, 8 * 1024);
+        let large = Resources::new(4, 16, 8 * 1024);
+        assert!(manager
+            .get_job_waiting_for_assignment(0, "small", &exclude, &small, &pools)
+            .is_none());
+        assert!(manager
+            .get_job_waiting_for_assignment(1, "large", &exclude, &large, &pools)
+            .is_some());
+
+        let events = manager.get_scheduling_trace(job_id);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].worker_name, "small");
+        assert_eq!(events[0].outcome, SchedulingOutcome::Skipped);
+        assert!(events[0].reason.contains("8 cpus"));
+        assert_eq!(events[1].worker_name, "large");
+        assert_eq!(events[1].outcome, SchedulingOutcome::Offered);
+    }
+
+    #[test]
+    fn duplicate_worker_names() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let mut manager = Manager::new(config);
+        let mut add_worker = |name: &str| {
+            let info = WorkerInfo::new(name.into(), "0.0.0".into());
+            let (kill_job_tx, _kill_job_rx) = mpsc::channel(1);
+            let (forward_tx, _forward_rx) = mpsc::channel(1);
+            manager.add_new_worker(info, kill_job_tx, forward_tx)
+        };
+        let first = add_worker("vm");
+        let second = add_worker("vm");
+        let second_id = second.lock().unwrap().info.worker_id;
+        assert_eq!(first.lock().unwrap().info.worker_name, "vm");
+        assert_eq!(
+            second.lock().unwrap().info.worker_name,
+            format!("vm#{}", second_id)
+        );
+
+        // Jobs on the worker follow the new name.
+        let cmd = vec!["true".to_string()];
+        let job_info = JobInfo::new(
+            cmd,
+            "/tmp".into(),
+            Resources::new(1, 1, 1024),
+            None,
+            None,
+            None,
+        );
         let job = manager.add_new_job(job_info);
+        {
+            let mut job_lock = job.lock().unwrap();
+            job_lock.worker_id = Some(second_id);
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: format!("vm#{}", second_id),
+            };
+        }
+        assert!(manager.rename_worker(second_id, "vm").is_err());
+        manager.rename_worker(second_id, "vm2").unwrap();
+        assert_eq!(second.lock().unwrap().info.worker_name, "vm2");
+        assert!(matches!(
+            &job.lock().unwrap().info.status,
+            JobStatus::Running { worker, .. } if worker == "vm2"
+        ));
+    }
 
-        // Put job on exclude list.
-        let mut exclude = BTreeSet::new();
-        exclude.insert(job.lock().unwrap().info.job_id);
+    #[test]
+    fn check_limits() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.job_limit = Some(20);
+        config.server_settings.memory_limit_mb = Some(1024);
+        let mut manager = Manager::new(config);
+        let resources = Resources::new(1, 1, 1024);
+        for index in 0..12 {
+            let job_info = JobInfo::new(
+                vec!["true".into()],
+                "/tmp".into(),
+                resources.clone(),
+                None,
+                None,
+                None,
+            );
+            let job = manager.add_new_job(job_info);
+            if index < 6 {
+                job.lock().unwrap().info.status = JobStatus::Canceled {
+                    issued: Utc::now(),
+                    canceled: Utc::now(),
+                    reason: None,
+                };
+            }
+        }
 
-        // Now, we should not get it.
-        let job = manager.get_job_waiting_for_assignment(0, "no worker", &exclude, &resources);
-        assert!(job.is_none());
+        // Within limits, nothing happens.
+        manager.check_limits(Some(512));
+        assert!(!manager.refuses_new_jobs());
+        assert_eq!(manager.get_all_job_infos().len(), 12);
 
-        // Now we want any job. One is waiting to be assigned.
-        exclude.clear();
-        let job = manager.get_job_waiting_for_assignment(0, "no worker", &exclude, &resources);
-        assert!(job.is_some());
+        // Too many jobs: concluded jobs are evicted down to the warning level.
+        manager.config.server_settings.job_limit = Some(10);
+        manager.check_limits(Some(512));
+        assert!(manager.refuses_new_jobs());
+        assert_eq!(manager.get_all_job_infos().len(), 8);
 
-        // We want any job, again. But none are left.
-        let job = manager.get_job_waiting_for_assignment(0, "no worker", &exclude, &resources);
-        assert!(job.is_none());
+        // Short on memory: all concluded jobs are evicted.
+        manager.config.server_settings.job_limit = None;
+        manager.check_limits(Some(2048));
+        assert!(manager.refuses_new_jobs());
+        assert_eq!(manager.get_all_job_infos().len(), 6);
+
+        // Back to normal.
+        manager.check_limits(Some(512));
+        assert!(!manager.refuses_new_jobs());
+    }
+
+    #[test]
+    fn pending_job_limits() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.pending_jobs_soft_limit = Some(3);
+        config.server_settings.pending_jobs_hard_limit = Some(5);
+        config.server_settings.pending_jobs_per_user_hard_limit = Some(2);
+        let mut manager = Manager::new(config);
+        let job_of = |user: &str| {
+            let cmd = vec!["true".to_string()];
+            let resources = Resources::new(1, 1, 1024);
+            let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+            job_info.user = user.into();
+            job_info
+        };
+
+        // Each user may have two pending jobs.
+        for user in ["alice", "alice", "bob"] {
+            let job_info = job_of(user);
+            assert!(manager
+                .check_pending_job_limits(&job_info)
+                .unwrap()
+                .is_empty());
+            manager.add_new_job(job_info);
+        }
+        assert!(manager.check_pending_job_limits(&job_of("alice")).is_err());
+
+        // Above the soft limit, jobs are accepted with a warning.
+        let job_info = job_of("bob");
+        assert_eq!(
+            manager.check_pending_job_limits(&job_info).unwrap().len(),
+            1
+        );
+        manager.add_new_job(job_info);
+        manager.add_new_job(job_of("carol"));
+        assert!(manager.check_pending_job_limits(&job_of("dave")).is_err());
     }
 }
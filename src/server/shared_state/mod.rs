@@ -3,8 +3,12 @@
 
 pub mod job;
 pub mod manager;
+pub mod spool;
+pub mod store;
 pub mod worker;
 
 pub use job::Job;
-pub use manager::Manager;
+pub use manager::{Manager, TunnelRoute};
+pub use spool::Spool;
+pub use store::JobStore;
 pub use worker::Worker;
@@ -0,0 +1,169 @@
+//! Outputs of jobs spooled to files on the server, so that large outputs are
+//! not kept in memory. Each output is stored in its own file, named after the
+//! job ID and the stream, e.g., "42.stdout".
+
+use crate::structs::OutputStream;
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Minimum time between two sweeps for expired outputs.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct Spool {
+    directory: PathBuf,
+    /// Outputs longer than this are shortened to their end.
+    max_output_len: Option<usize>,
+    /// Time of the last sweep for expired outputs.
+    last_sweep: Mutex<Option<Instant>>,
+}
+
+impl Spool {
+    /// Spool outputs to the given directory, which is created if it does not
+    /// exist yet.
+    pub fn open(directory: &Path, max_output_kib: Option<u64>) -> Result<Self> {
+        fs::create_dir_all(directory).map_err(|e| {
+            anyhow!(
+                "Failed to create spool directory {}: {}",
+                directory.to_string_lossy(),
+                e
+            )
+        })?;
+        Ok(Spool {
+            directory: directory.to_path_buf(),
+            max_output_len: max_output_kib.map(|kib| kib as usize * 1024),
+            last_sweep: Mutex::new(None),
+        })
+    }
+
+    fn path(&self, job_id: u64, stream: OutputStream) -> PathBuf {
+        let extension = match stream {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        };
+        self.directory.join(format!("{}.{}", job_id, extension))
+    }
+
+    /// Write the output of the job, replacing earlier output. Without output,
+    /// the file is removed.
+    pub fn write(&self, job_id: u64, stream: OutputStream, text: Option<&str>) -> Result<()> {
+        let path = self.path(job_id, stream);
+        match text {
+            Some(text) => match self.max_output_len {
+                Some(max_len) => fs::write(path, shorten(text, max_len))?,
+                None => fs::write(path, text)?,
+            },
+            None => match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Read the output of the job, if any has been spooled.
+    pub fn read(&self, job_id: u64, stream: OutputStream) -> Option<String> {
+        let path = self.path(job_id, stream);
+        match fs::read(&path) {
+            Ok(data) => Some(String::from_utf8_lossy(&data).into_owned()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                log::error!("Failed to read {}: {}", path.to_string_lossy(), e);
+                None
+            }
+        }
+    }
+
+    /// Remove the outputs of the job.
+    pub fn remove(&self, job_id: u64) {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            if let Err(e) = self.write(job_id, stream, None) {
+                log::error!("Failed to remove output of job {}: {}", job_id, e);
+            }
+        }
+    }
+
+    /// Remove outputs that have not been written for longer than `retention`.
+    /// The directory is swept at most once a minute. Returns the number of
+    /// removed files.
+    pub fn remove_expired(&self, retention: Duration) -> usize {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if last_sweep.is_some_and(|last_sweep| last_sweep.elapsed() < SWEEP_INTERVAL) {
+                return 0;
+            }
+            *last_sweep = Some(Instant::now());
+        }
+
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to sweep spool directory: {}", e);
+                return 0;
+            }
+        };
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    now.duration_since(modified).unwrap_or_default() >= retention
+                });
+            if expired && fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+/// Shorten the text to its last `max_len` bytes, with a note about the
+/// skipped beginning. The end of an output usually tells why a job failed.
+fn shorten(text: &str, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text.to_string();
+    }
+    let mut start = text.len() - max_len;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[... {} bytes skipped ...]\n{}", start, &text[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spool_outputs() {
+        let directory = std::env::temp_dir().join(format!("kueue-spool-{}", std::process::id()));
+        let spool = Spool::open(&directory, Some(1)).unwrap();
+        let stdout = OutputStream::Stdout;
+
+        spool.write(1, stdout, Some("hello")).unwrap();
+        assert_eq!(spool.read(1, stdout).as_deref(), Some("hello"));
+        assert_eq!(spool.read(1, OutputStream::Stderr), None);
+
+        // Long outputs are shortened to their end.
+        let text = "a".repeat(1000) + &"b".repeat(1024);
+        spool.write(2, stdout, Some(&text)).unwrap();
+        let spooled = spool.read(2, stdout).unwrap();
+        assert!(spooled.starts_with("[... 1000 bytes skipped ...]\n"));
+        assert!(spooled.ends_with(&"b".repeat(1024)));
+
+        // Removed and expired outputs are gone.
+        spool.remove(1);
+        assert_eq!(spool.read(1, stdout), None);
+        assert_eq!(spool.remove_expired(Duration::ZERO), 1);
+        assert_eq!(spool.read(2, stdout), None);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
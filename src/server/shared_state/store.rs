@@ -0,0 +1,428 @@
+//! Persistence of jobs in an SQLite database, so that the queue survives
+//! restarts of the server. Each job is stored as one row and rewritten on
+//! every transition by a background thread, so that the scheduler does not
+//! wait for the database. The database also keeps the accounting of finished
+//! jobs, which outlives the jobs themselves.
+
+use crate::{
+    server::shared_state::Job,
+    structs::{JobInfo, JobStatus, UserUsage},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::{
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+};
+
+/// Job as stored in the database.
+pub struct StoredJob {
+    pub info: JobInfo,
+    pub stdout_text: Option<String>,
+    pub stderr_text: Option<String>,
+}
+
+/// Accounting of a finished job, written when the job concludes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountingRecord {
+    pub job_id: u64,
+    pub user: String,
+    pub command: String,
+    pub worker: String,
+    pub cpus: u64,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub exit_code: i32,
+    /// CPU cores reserved by the job, multiplied by its run time.
+    pub core_hours: f64,
+}
+
+impl AccountingRecord {
+    /// Accounting of the job, if it has finished.
+    pub fn from_job_info(job_info: &JobInfo) -> Option<Self> {
+        match &job_info.status {
+            JobStatus::Finished {
+                started,
+                finished,
+                return_code,
+                worker,
+                run_time_seconds,
+                ..
+            } => Some(AccountingRecord {
+                job_id: job_info.job_id,
+                user: job_info.user.clone(),
+                command: job_info.cmd.join(" "),
+                worker: worker.clone(),
+                cpus: job_info.worker_resources.cpus,
+                started: *started,
+                finished: *finished,
+                exit_code: *return_code,
+                core_hours: job_info.worker_resources.cpus as f64
+                    * (*run_time_seconds).max(0) as f64
+                    / 3600.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Insert or update a job with its outputs.
+const SAVE_JOB: &str = "INSERT OR REPLACE INTO jobs (job_id, info, stdout_text, stderr_text)
+    VALUES (?1, ?2, ?3, ?4)";
+
+/// Change of the jobs table, applied in order by the background writer.
+enum JobWrite {
+    Save {
+        job_id: i64,
+        info: String,
+        stdout_text: Option<String>,
+        stderr_text: Option<String>,
+    },
+    Remove {
+        job_id: i64,
+    },
+    /// Answered once all earlier writes have been applied.
+    Flush(mpsc::Sender<()>),
+}
+
+pub struct JobStore {
+    connection: Arc<Mutex<Connection>>,
+    /// Queue of the background writer. Jobs are saved while their lock is
+    /// held, e.g., while they are assigned to workers, so the database is
+    /// not written right away.
+    writes: mpsc::Sender<JobWrite>,
+}
+
+impl JobStore {
+    /// Open the database at the given path. The database is created if it
+    /// does not exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow!("Failed to open database {}: {}", path.to_string_lossy(), e))?;
+        Self::init(connection)
+    }
+
+    /// Open a database that only lives in memory, for testing.
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self> {
+        // The write-ahead log keeps writes cheap, since every job
+        // transition is written.
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                job_id INTEGER PRIMARY KEY,
+                info TEXT NOT NULL,
+                stdout_text TEXT,
+                stderr_text TEXT
+            )",
+            [],
+        )?;
+        // Every attempt of a job is accounted, e.g., when a job is retried.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS accounting (
+                job_id INTEGER NOT NULL,
+                user TEXT NOT NULL,
+                command TEXT NOT NULL,
+                worker TEXT NOT NULL,
+                cpus INTEGER NOT NULL,
+                started INTEGER NOT NULL,
+                finished INTEGER NOT NULL,
+                exit_code INTEGER NOT NULL,
+                core_hours REAL NOT NULL
+            )",
+            [],
+        )?;
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS accounting_finished ON accounting (finished)",
+            [],
+        )?;
+        let connection = Arc::new(Mutex::new(connection));
+        let (writes, writes_rx) = mpsc::channel();
+        let writer_connection = Arc::clone(&connection);
+        std::thread::Builder::new()
+            .name("job-store".into())
+            .spawn(move || run_writer(&writer_connection, writes_rx))?;
+        Ok(JobStore { connection, writes })
+    }
+
+    /// Insert or update the job. The job is written in the background.
+    pub fn save(&self, job: &Job) -> Result<()> {
+        let write = JobWrite::Save {
+            job_id: job.info.job_id as i64,
+            info: serde_json::to_string(&job.info)?,
+            stdout_text: job.stdout_text.clone(),
+            stderr_text: job.stderr_text.clone(),
+        };
+        self.writes
+            .send(write)
+            .map_err(|_| anyhow!("Database writer has stopped!"))
+    }
+
+    /// Wait until all queued writes have been applied.
+    fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self.writes.send(JobWrite::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv(); // also fails if the writer has stopped
+        }
+    }
+
+    /// Save all given jobs in a single transaction and move the write-ahead
+    /// log into the database file, e.g., before the server shuts down.
+    pub fn save_all<'a>(&self, jobs: impl IntoIterator<Item = &'a Job>) -> Result<usize> {
+        // Queued writes are older and must not overwrite the jobs saved now.
+        self.flush();
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let mut saved = 0;
+        {
+            let mut statement = transaction.prepare(SAVE_JOB)?;
+            for job in jobs {
+                let info = serde_json::to_string(&job.info)?;
+                statement.execute(params![
+                    job.info.job_id as i64,
+                    info,
+                    job.stdout_text,
+                    job.stderr_text
+                ])?;
+                saved += 1;
+            }
+        }
+        transaction.commit()?;
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(saved)
+    }
+
+    /// Remove the job, e.g., when finished jobs are cleaned up.
+    pub fn remove(&self, job_id: u64) -> Result<()> {
+        let job_id = job_id as i64;
+        self.writes
+            .send(JobWrite::Remove { job_id })
+            .map_err(|_| anyhow!("Database writer has stopped!"))
+    }
+
+    /// Add the accounting of a finished job.
+    pub fn record_accounting(&self, record: &AccountingRecord) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO accounting (job_id, user, command, worker, cpus, started, finished,
+                exit_code, core_hours) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                record.job_id as i64,
+                record.user,
+                record.command,
+                record.worker,
+                record.cpus as i64,
+                record.started.timestamp(),
+                record.finished.timestamp(),
+                record.exit_code,
+                record.core_hours
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Sum up the accounting per user, for jobs that finished within the
+    /// given time range. Optionally, only the given user is included.
+    pub fn query_accounting(
+        &self,
+        user: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UserUsage>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT user, SUM(exit_code = 0), SUM(exit_code != 0), SUM(core_hours),
+                MIN(started), MAX(finished)
+                FROM accounting
+                WHERE (?1 IS NULL OR user = ?1) AND finished >= ?2 AND finished < ?3
+                GROUP BY user ORDER BY user",
+        )?;
+        let since = since.map_or(i64::MIN, |since| since.timestamp());
+        let until = until.map_or(i64::MAX, |until| until.timestamp());
+        let rows = statement.query_map(params![user, since, until], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, f64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+            ))
+        })?;
+        let mut usage = Vec::new();
+        for row in rows {
+            let (user, jobs_succeeded, jobs_failed, core_hours, first_started, last_finished) =
+                row?;
+            usage.push(UserUsage {
+                user,
+                jobs_succeeded: jobs_succeeded as u64,
+                jobs_failed: jobs_failed as u64,
+                core_hours,
+                first_started: DateTime::from_timestamp(first_started, 0).unwrap_or_default(),
+                last_finished: DateTime::from_timestamp(last_finished, 0).unwrap_or_default(),
+            });
+        }
+        Ok(usage)
+    }
+
+    /// Write a consistent copy of the database to the given path, e.g., for
+    /// backups while the server is running. The file must not exist yet.
+    pub fn snapshot(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            bail!("File {} already exists!", path.to_string_lossy());
+        }
+        self.flush();
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Read all stored jobs, ordered by job ID.
+    pub fn load(&self) -> Result<Vec<StoredJob>> {
+        self.flush();
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT info, stdout_text, stderr_text FROM jobs ORDER BY job_id")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+        let mut jobs = Vec::new();
+        for row in rows {
+            let (info, stdout_text, stderr_text) = row?;
+            jobs.push(StoredJob {
+                info: serde_json::from_str(&info)?,
+                stdout_text,
+                stderr_text,
+            });
+        }
+        Ok(jobs)
+    }
+}
+
+/// Apply queued writes in order, until the store is dropped. Writes queued
+/// meanwhile are applied together in a single transaction.
+fn run_writer(connection: &Mutex<Connection>, writes: mpsc::Receiver<JobWrite>) {
+    while let Ok(write) = writes.recv() {
+        let batch: Vec<_> = std::iter::once(write).chain(writes.try_iter()).collect();
+        let mut flushed = Vec::new();
+        if let Err(e) = apply_writes(&mut connection.lock().unwrap(), batch, &mut flushed) {
+            log::error!("Failed to write jobs to the database: {}", e);
+        }
+        for done_tx in flushed {
+            let _ = done_tx.send(());
+        }
+    }
+}
+
+/// Apply the writes in a single transaction. Flush requests are collected to
+/// be answered once the transaction has been committed.
+fn apply_writes(
+    connection: &mut Connection,
+    batch: Vec<JobWrite>,
+    flushed: &mut Vec<mpsc::Sender<()>>,
+) -> Result<()> {
+    let transaction = connection.transaction()?;
+    for write in batch {
+        match write {
+            JobWrite::Save {
+                job_id,
+                info,
+                stdout_text,
+                stderr_text,
+            } => {
+                let mut statement = transaction.prepare_cached(SAVE_JOB)?;
+                statement.execute(params![job_id, info, stdout_text, stderr_text])?;
+            }
+            JobWrite::Remove { job_id } => {
+                transaction.execute("DELETE FROM jobs WHERE job_id = ?1", params![job_id])?;
+            }
+            JobWrite::Flush(done_tx) => flushed.push(done_tx),
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+    use chrono::Duration;
+
+    #[test]
+    fn background_writes() {
+        let store = JobStore::open_in_memory().unwrap();
+        let resources = Resources::new(1, 1, 1024);
+        let mut jobs: Vec<_> = (0..3)
+            .map(|_| {
+                let cmd = vec!["true".to_string()];
+                Job::from(JobInfo::new(
+                    cmd,
+                    "/tmp".into(),
+                    resources.clone(),
+                    None,
+                    None,
+                    None,
+                ))
+            })
+            .collect();
+        for job in &jobs {
+            store.save(job).unwrap();
+        }
+        jobs[0].info.project = Some("latest".into());
+        store.save(&jobs[0]).unwrap();
+        store.remove(jobs[1].info.job_id).unwrap();
+
+        // Queued writes are applied in order before reading.
+        let stored = store.load().unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].info.project.as_deref(), Some("latest"));
+        assert_eq!(stored[1].info.job_id, jobs[2].info.job_id);
+    }
+
+    #[test]
+    fn accounting() {
+        let store = JobStore::open_in_memory().unwrap();
+        let finished = Utc::now();
+        let record = |job_id, user: &str, exit_code, hours_ago| AccountingRecord {
+            job_id,
+            user: user.to_string(),
+            command: "make".into(),
+            worker: "worker".into(),
+            cpus: 4,
+            started: finished - Duration::hours(hours_ago + 2),
+            finished: finished - Duration::hours(hours_ago),
+            exit_code,
+            core_hours: 8.0,
+        };
+        store.record_accounting(&record(1, "alice", 0, 48)).unwrap();
+        store.record_accounting(&record(2, "alice", 1, 0)).unwrap();
+        store.record_accounting(&record(3, "bob", 0, 0)).unwrap();
+
+        let usage = store.query_accounting(None, None, None).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].user, "alice");
+        assert_eq!((usage[0].jobs_succeeded, usage[0].jobs_failed), (1, 1));
+        assert_eq!(usage[0].core_hours, 16.0);
+
+        // Only jobs finished within the time range of the given user.
+        let since = Some(finished - Duration::hours(24));
+        let usage = store.query_accounting(Some("alice"), since, None).unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].jobs_succeeded, usage[0].jobs_failed), (0, 1));
+        let until = Some(finished - Duration::hours(24));
+        let usage = store.query_accounting(None, None, until).unwrap();
+        assert_eq!(usage[0].jobs_succeeded, 1);
+    }
+}
@@ -1,5 +1,10 @@
-use crate::structs::WorkerInfo;
-use tokio::sync::mpsc;
+use crate::{
+    messages::ServerToWorkerMessage,
+    structs::{JobInfo, WorkerInfo},
+};
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::{mpsc, Notify};
 
 /// Representation of the connected worker in the `job_manager`.
 pub struct Worker {
@@ -7,13 +12,44 @@ pub struct Worker {
     /// Channel can be used to issue the termination of
     /// the job with the submitted id on the remote worker.
     pub kill_job_tx: mpsc::Sender<u64>,
+    /// Channel to forward messages from other connections to
+    /// the remote worker, e.g., terminal input of interactive jobs.
+    pub forward_tx: mpsc::Sender<ServerToWorkerMessage>,
+    /// Offers withdrawn before the worker has answered them, by job ID and
+    /// the time of the offer. The answer, which may still be on its way, is
+    /// ignored. Entries are dropped when the job is offered again.
+    pub withdrawn_offers: BTreeMap<u64, DateTime<Utc>>,
+    /// Signals the connection to close, e.g., when the worker has timed out
+    /// and is listed as unreachable.
+    pub close_connection: Arc<Notify>,
 }
 
 impl Worker {
-    pub fn new(name: String, kill_job_tx: mpsc::Sender<u64>) -> Self {
+    pub fn new(
+        info: WorkerInfo,
+        kill_job_tx: mpsc::Sender<u64>,
+        forward_tx: mpsc::Sender<ServerToWorkerMessage>,
+    ) -> Self {
         Worker {
-            info: WorkerInfo::new(name),
+            info,
             kill_job_tx,
+            forward_tx,
+            withdrawn_offers: BTreeMap::new(),
+            close_connection: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns `true` if the worker answered an offer that has been
+    /// withdrawn, which is forgotten then. Answers to other offers of the
+    /// same job are not affected.
+    pub fn forget_withdrawn_offer(&mut self, job_info: &JobInfo) -> bool {
+        let answered = job_info.status.offered();
+        match self.withdrawn_offers.get(&job_info.job_id) {
+            Some(withdrawn) if answered.is_none_or(|answered| answered == *withdrawn) => {
+                self.withdrawn_offers.remove(&job_info.job_id);
+                true
+            }
+            _ => false,
         }
     }
 }
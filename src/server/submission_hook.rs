@@ -0,0 +1,101 @@
+//! Submission hook, run by the server for every newly issued job to enforce
+//! site policy, e.g., to cap resources or to reject certain jobs.
+
+use crate::structs::JobInfo;
+use anyhow::{anyhow, bail, Result};
+use std::process::Stdio;
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+    time::{timeout, Duration},
+};
+
+/// Run the hook in a shell with the job as JSON on stdin. If the hook prints
+/// a job on stdout, it replaces the issued job, except for the job's ID, user,
+/// and status. If the hook fails, the returned error holds the reason to
+/// reject the job.
+pub async fn run_submission_hook(
+    hook: &str,
+    timeout_seconds: u64,
+    job_info: &mut JobInfo,
+) -> Result<()> {
+    let input = serde_json::to_vec(&*job_info)?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to run submission hook: {}", e))?;
+
+    // Feed the job while collecting the output. Hooks may not read their
+    // input at all, so failing to write it is no error.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let write_input = async move {
+        let _ = stdin.write_all(&input).await;
+    };
+    let run = async { tokio::join!(write_input, child.wait_with_output()).1 };
+    let time_limit = Duration::from_secs(timeout_seconds);
+    let output = match timeout(time_limit, run).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => bail!("Failed to run submission hook: {}", e),
+        Err(_) => bail!("Submission hook timed out after {:?}", time_limit),
+    };
+
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if reason.is_empty() {
+            bail!("Rejected by submission hook ({})", output.status);
+        }
+        bail!("{}", reason);
+    }
+
+    // Without output, the job is accepted as it is.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(());
+    }
+    let adjusted: JobInfo = serde_json::from_str(&stdout)
+        .map_err(|e| anyhow!("Submission hook printed an invalid job: {}", e))?;
+    *job_info = JobInfo {
+        job_id: job_info.job_id,
+        user: job_info.user.clone(),
+        status: job_info.status.clone(),
+        ..adjusted
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+
+    #[tokio::test]
+    async fn submission_hooks() {
+        let resources = Resources::new(1, 1, 1024);
+        let cmd = vec!["make".to_string()];
+        let mut job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+        let user = job_info.user.clone();
+
+        // Hooks without output accept the job as it is.
+        run_submission_hook("true", 1, &mut job_info).await.unwrap();
+        assert_eq!(job_info.priority, 0);
+
+        // Hooks can adjust the job, but not its owner.
+        let hook = r#"sed -e 's/"priority":0/"priority":5/' -e 's/"user":"[^"]*"/"user":"x"/'"#;
+        run_submission_hook(hook, 1, &mut job_info).await.unwrap();
+        assert_eq!(job_info.priority, 5);
+        assert_eq!(job_info.user, user);
+
+        // Failing hooks reject the job with their error output.
+        let hook = "echo 'Jobs need a project!' >&2; exit 1";
+        let result = run_submission_hook(hook, 1, &mut job_info).await;
+        assert_eq!(result.unwrap_err().to_string(), "Jobs need a project!");
+        assert!(run_submission_hook("sleep 5", 1, &mut job_info)
+            .await
+            .is_err());
+    }
+}
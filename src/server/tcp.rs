@@ -1,19 +1,24 @@
 use crate::{
     config::Config,
-    server::{handle_connection, shared_state::Manager},
+    server::{
+        handle_connection,
+        shared_state::{JobStore, Spool},
+        Instance, Instances,
+    },
+    tls,
 };
 use anyhow::{bail, Result};
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use tokio::{
     net::TcpListener,
     sync::mpsc::{channel, Receiver, Sender},
     time::{sleep, Duration},
 };
+use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 
 pub struct TcpServer {
-    config: Arc<RwLock<Config>>,
-    shared: Arc<RwLock<Manager>>,
+    instances: Arc<Instances>,
     /// Handles for graceful shutdown of the server.
     shutdown: Option<(CancellationToken, Receiver<()>)>,
 }
@@ -22,8 +27,7 @@ impl TcpServer {
     /// Creates a new server to handle client and worker connections.
     pub fn new(config: Config) -> Self {
         Self {
-            config: Arc::new(RwLock::new(config.clone())),
-            shared: Arc::new(RwLock::new(Manager::new(config))),
+            instances: Arc::new(Instances::new(config)),
             shutdown: None,
         }
     }
@@ -35,8 +39,9 @@ impl TcpServer {
         }
 
         // Get bind addresses to listen on.
+        let config = self.instances.default.config.clone();
         let bind_addresses = {
-            let config = self.config.read().unwrap();
+            let config = config.read().unwrap();
             let port = config.common_settings.server_port;
             config
                 .server_settings
@@ -50,6 +55,17 @@ impl TcpServer {
             bail!("No bind addresses configured!");
         }
 
+        // Restore the jobs of all instances before accepting any connections.
+        for (tenant, instance) in self.instances.iter() {
+            open_storage(tenant, instance)?;
+        }
+
+        // Load the certificate before accepting any connections as well.
+        let acceptor = tls::acceptor(&config.read().unwrap().server_settings)?;
+        if acceptor.is_some() {
+            log::info!("Connections are encrypted with TLS.");
+        }
+
         // Prepare handles for graceful shutdown later.
         let cancel_token = CancellationToken::new();
         let (keep_alive, shutdown) = channel::<()>(1);
@@ -63,8 +79,8 @@ impl TcpServer {
                     // Start accepting incoming connections.
                     tokio::spawn(listen_on(
                         listener,
-                        self.config.clone(),
-                        self.shared.clone(),
+                        acceptor.clone(),
+                        self.instances.clone(),
                         cancel_token.clone(),
                         keep_alive.clone(),
                     ));
@@ -80,36 +96,17 @@ impl TcpServer {
             bail!("Failed to bind any address!");
         }
 
-        // Start maintenance routine for the shared state.
-        let cancel_maintenance = cancel_token.clone();
-        let keep_alive_maintenance = keep_alive.clone();
-        let maintenance_interval = self
-            .config
-            .read()
-            .unwrap()
-            .server_settings
-            .maintenance_interval_seconds;
-        let shared_state = self.shared.clone();
-        tokio::spawn(async move {
-            loop {
-                let wake_up = sleep(Duration::from_secs(maintenance_interval));
-                tokio::select! {
-                    _ = cancel_maintenance.cancelled() => { break; }
-                    _ = wake_up => {
-                        log::trace!("Performing job maintenance...");
-                        shared_state.write().unwrap().run_maintenance();
-                    }
-                }
-            }
-            drop(keep_alive_maintenance);
-        });
+        for (_, instance) in self.instances.iter() {
+            start_routines(instance, &cancel_token, &keep_alive);
+        }
 
         // Save handles for stop function.
         self.shutdown = Some((cancel_token, shutdown));
         Ok(())
     }
 
-    // Close all connections and stop listening.
+    // Stop listening, close all connections, and persist the state. Workers
+    // are told to keep their jobs running until the server is back.
     pub async fn stop(&mut self) -> Result<()> {
         match self.shutdown.take() {
             Some((cancel_token, mut shutdown)) => {
@@ -118,6 +115,15 @@ impl TcpServer {
                 cancel_token.cancel();
                 // Wait for all senders in the spawned tasks to be dropped.
                 shutdown.recv().await;
+                // No connection can change the state any more.
+                for (tenant, instance) in self.instances.iter() {
+                    let tenant =
+                        tenant.map_or_else(String::new, |name| format!(" of tenant '{}'", name));
+                    match instance.shared.read().unwrap().persist_state() {
+                        Ok(saved) => log::info!("Saved {} jobs{} to the database", saved, tenant),
+                        Err(e) => log::error!("Failed to save jobs{}: {}", tenant, e),
+                    }
+                }
                 Ok(())
             }
             None => bail!("Server is not running!"),
@@ -125,11 +131,128 @@ impl TcpServer {
     }
 }
 
-/// Runs asynchronously and accepts new TCP connections.
-pub async fn listen_on(
+/// Attach the spool directory and the database of the instance, if
+/// configured. Jobs stored in the database are restored.
+fn open_storage(tenant: Option<&str>, instance: &Instance) -> Result<()> {
+    let tenant = tenant.map_or_else(String::new, |name| format!(" of tenant '{}'", name));
+
+    // Spool outputs to disk, if configured. Restored jobs use it, too.
+    let (spool_directory, max_output_kib) = {
+        let settings = &instance.config.read().unwrap().server_settings;
+        (
+            settings.spool_directory.clone(),
+            settings.max_spooled_output_kib,
+        )
+    };
+    if let Some(directory) = spool_directory {
+        let spool = Spool::open(&directory, max_output_kib)?;
+        instance
+            .shared
+            .write()
+            .unwrap()
+            .attach_spool(Arc::new(spool));
+        log::info!(
+            "Spooling outputs{} to {}",
+            tenant,
+            directory.to_string_lossy()
+        );
+    }
+
+    let database = instance
+        .config
+        .read()
+        .unwrap()
+        .server_settings
+        .database
+        .clone();
+    if let Some(path) = database {
+        let store = JobStore::open(&path)?;
+        let restored = instance
+            .shared
+            .write()
+            .unwrap()
+            .attach_store(Arc::new(store))?;
+        log::info!(
+            "Restored {} jobs{} from database {}",
+            restored,
+            tenant,
+            path.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+/// Start the maintenance and scheduling routines of the instance.
+fn start_routines(instance: &Instance, cancel_token: &CancellationToken, keep_alive: &Sender<()>) {
+    // Start maintenance routine for the shared state.
+    let cancel_maintenance = cancel_token.clone();
+    let keep_alive_maintenance = keep_alive.clone();
+    let maintenance_interval = instance
+        .config
+        .read()
+        .unwrap()
+        .server_settings
+        .maintenance_interval_seconds;
+    let shared_state = instance.shared.clone();
+    tokio::spawn(async move {
+        loop {
+            let wake_up = sleep(Duration::from_secs(maintenance_interval));
+            tokio::select! {
+                _ = cancel_maintenance.cancelled() => { break; }
+                _ = wake_up => {
+                    log::trace!("Performing job maintenance...");
+                    shared_state.write().unwrap().run_maintenance();
+
+                    // Interactive jobs are of no use without their client.
+                    let kills = shared_state.write().unwrap().cancel_unattached_jobs();
+                    for (kill_job_tx, job_id) in kills {
+                        let _ = kill_job_tx.send(job_id).await;
+                    }
+
+                    // Make room for pending jobs of higher QoS classes.
+                    let preemptions = shared_state.read().unwrap().preempt_jobs();
+                    for (worker_tx, message) in preemptions {
+                        let _ = worker_tx.send(message).await;
+                    }
+
+                    // Withdraw stale offers in favor of better placements.
+                    let withdrawals = shared_state.write().unwrap().rebalance_offers();
+                    for (worker_tx, message) in withdrawals {
+                        let _ = worker_tx.send(message).await;
+                    }
+                }
+            }
+        }
+        drop(keep_alive_maintenance);
+    });
+
+    // Let workers look for assignable jobs regularly, in case no event
+    // has made them assignable, e.g., when their start time has come.
+    let cancel_scheduling = cancel_token.clone();
+    let scheduling_interval = instance
+        .config
+        .read()
+        .unwrap()
+        .server_settings
+        .scheduling_interval_seconds;
+    let notify_new_jobs = instance.shared.read().unwrap().notify_new_jobs.clone();
+    tokio::spawn(async move {
+        loop {
+            let wake_up = sleep(Duration::from_secs(scheduling_interval.max(1)));
+            tokio::select! {
+                _ = cancel_scheduling.cancelled() => { break; }
+                _ = wake_up => notify_new_jobs.notify_waiters(),
+            }
+        }
+    });
+}
+
+/// Runs asynchronously and accepts new TCP connections. With an `acceptor`,
+/// the TLS handshake is performed before the connection is handled.
+async fn listen_on(
     listener: TcpListener,
-    config: Arc<RwLock<Config>>,
-    shared: Arc<RwLock<Manager>>,
+    acceptor: Option<TlsAcceptor>,
+    instances: Arc<Instances>,
     cancel_token: CancellationToken,
     keep_alive: Sender<()>,
 ) -> Result<()> {
@@ -140,13 +263,46 @@ pub async fn listen_on(
                 let (stream, address) = result?;
                 log::trace!("Established connection from {}!", address);
 
-                tokio::spawn(handle_connection(
-                    stream,
-                    config.clone(),
-                    shared.clone(),
-                    cancel_token.clone(),
-                    keep_alive.clone(),
-                ));
+                let Some(acceptor) = acceptor.clone() else {
+                    tokio::spawn(handle_connection(
+                        stream,
+                        address.to_string(),
+                        None,
+                        instances.clone(),
+                        cancel_token.clone(),
+                        keep_alive.clone(),
+                    ));
+                    continue;
+                };
+
+                // Perform the handshake in its own task, so that a slow
+                // peer does not hold up other connections.
+                let instances = instances.clone();
+                let cancel_token = cancel_token.clone();
+                let keep_alive = keep_alive.clone();
+                tokio::spawn(async move {
+                    let stream = tokio::select! {
+                        _ = cancel_token.cancelled() => { return; }
+                        result = acceptor.accept(stream) => match result {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                log::warn!("TLS handshake with {} failed: {}", address, e);
+                                return;
+                            }
+                        }
+                    };
+                    // The host name the peer connected to may select a tenant.
+                    let server_name = stream.get_ref().1.server_name().map(str::to_string);
+                    handle_connection(
+                        stream,
+                        address.to_string(),
+                        server_name,
+                        instances,
+                        cancel_token,
+                        keep_alive,
+                    )
+                    .await;
+                });
             }
         }
     }
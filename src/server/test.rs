@@ -1,9 +1,9 @@
 use crate::{
     config::Config,
-    server::{handle_connection, shared_state::Manager},
+    server::{handle_connection, Instances},
 };
 use anyhow::{bail, Result};
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     sync::mpsc::{channel, Receiver, Sender},
@@ -11,8 +11,7 @@ use tokio::{
 use tokio_util::sync::CancellationToken;
 
 pub struct TestServer {
-    config: Arc<RwLock<Config>>,
-    shared: Arc<RwLock<Manager>>,
+    instances: Arc<Instances>,
     cancel_token: CancellationToken,
     keep_alive: Option<Sender<()>>,
     shutdown: Receiver<()>,
@@ -24,8 +23,7 @@ impl TestServer {
         let (keep_alive, shutdown) = channel::<()>(1);
 
         Self {
-            config: Arc::new(RwLock::new(config.clone())),
-            shared: Arc::new(RwLock::new(Manager::new(config))),
+            instances: Arc::new(Instances::new(config)),
             cancel_token,
             keep_alive: Some(keep_alive),
             shutdown,
@@ -40,8 +38,9 @@ impl TestServer {
             Some(keep_alive) => {
                 tokio::spawn(handle_connection(
                     stream,
-                    self.config.clone(),
-                    self.shared.clone(),
+                    "test".into(),
+                    None,
+                    self.instances.clone(),
                     self.cancel_token.clone(),
                     keep_alive.clone(),
                 ));
@@ -67,14 +66,16 @@ impl TestServer {
 #[cfg(test)]
 mod tests {
     use crate::{
-        config::Config,
+        config::{Config, Tenant},
         messages::{
             stream::{MessageError, MessageStream},
+            version::{capabilities, KUEUE_VERSION},
             ClientToServerMessage, HelloMessage, ServerToClientMessage,
         },
-        server::test::TestServer,
+        server::{test::TestServer, Instance, Instances},
     };
     use simple_logger::SimpleLogger;
+    use std::sync::Arc;
     use tokio::io::duplex;
 
     #[tokio::test]
@@ -96,11 +97,20 @@ mod tests {
         let mut stream = MessageStream::new(client_stream);
 
         // Send hello, receive welcome.
-        assert!(stream.send(&HelloMessage::HelloFromClient).await.is_ok());
+        let hello = HelloMessage::HelloFromClient {
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tenant: None,
+        };
+        assert!(stream.send(&hello).await.is_ok());
         assert_eq!(
             stream.receive::<ServerToClientMessage>().await,
-            Ok(ServerToClientMessage::WelcomeClient)
+            Ok(ServerToClientMessage::WelcomeClient {
+                kueue_version: KUEUE_VERSION.into(),
+                capabilities: capabilities(),
+            })
         );
+        stream.negotiate_format(&capabilities());
 
         // Send bye, connection closed by server.
         assert!(stream.send(&ClientToServerMessage::Bye).await.is_ok());
@@ -112,4 +122,53 @@ mod tests {
         // Shutdown server.
         server.stop().await;
     }
+
+    #[tokio::test]
+    async fn select_tenants() {
+        let mut config = Config::new(None).unwrap();
+        config.server_settings.tenants = vec![Tenant {
+            name: "lab-b".into(),
+            server_names: vec!["kueue.lab-b.example.com".into()],
+            shared_secret: "secret-b".into(),
+            admin_secret: None,
+            observer_secret: None,
+            database: None,
+            spool_directory: None,
+        }];
+        let instances = Instances::new(config);
+        let secret = |instance: &Instance| {
+            let config = instance.config.read().unwrap();
+            config.common_settings.shared_secret.clone()
+        };
+
+        // Tenants are selected by name or by the host name of the server.
+        let instance = instances.select(Some("lab-b"), None).unwrap();
+        assert_eq!(secret(instance), "secret-b");
+        let instance = instances
+            .select(None, Some("Kueue.Lab-B.example.com"))
+            .unwrap();
+        assert_eq!(secret(instance), "secret-b");
+        let instance = instances.select(None, Some("localhost")).unwrap();
+        assert_ne!(secret(instance), "secret-b");
+        assert!(instances.select(Some("lab-c"), None).is_err());
+
+        // Clients of unknown tenants are rejected during the handshake.
+        let server = TestServer {
+            instances: Arc::new(instances),
+            ..TestServer::new(Config::new(None).unwrap())
+        };
+        let (server_stream, client_stream) = duplex(1024);
+        assert!(server.connect(server_stream).is_ok());
+        let mut stream = MessageStream::new(client_stream);
+        let hello = HelloMessage::HelloFromClient {
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tenant: Some("lab-c".into()),
+        };
+        assert!(stream.send(&hello).await.is_ok());
+        assert!(matches!(
+            stream.receive::<ServerToClientMessage>().await,
+            Ok(ServerToClientMessage::RequestResponse { success: false, .. })
+        ));
+    }
 }
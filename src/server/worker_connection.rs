@@ -1,11 +1,16 @@
 use crate::{
     config::Config,
     messages::{
+        chunks::ResultsAssembler,
         stream::{MessageError, MessageStream},
-        ServerToWorkerMessage, WorkerToServerMessage,
+        version::CAP_LOG_FOLLOW,
+        ServerToClientMessage, ServerToWorkerMessage, WorkerToServerMessage,
     },
     server::shared_state::{Manager, Worker},
-    structs::{JobInfo, JobStatus, Resources, SystemInfo},
+    structs::{
+        JobInfo, JobStatus, OutputStream, ProbeResult, Resources, SchedulingOutcome, SystemInfo,
+        WorkerInfo,
+    },
 };
 use anyhow::{bail, Result};
 use base64::{engine::general_purpose, Engine};
@@ -13,19 +18,23 @@ use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use sha2::{Digest, Sha256};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     sync::{Arc, Mutex, RwLock},
+    time::Instant,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::mpsc::{channel, Receiver},
+    sync::{
+        mpsc::{channel, Receiver},
+        Semaphore,
+    },
     task::yield_now,
+    time::sleep_until,
 };
 use tokio_util::sync::CancellationToken;
 
 pub struct WorkerConnection<Stream> {
     worker_id: u64,
-    worker_name: String,
     stream: MessageStream<Stream>,
     config: Arc<RwLock<Config>>,
     manager: Arc<RwLock<Manager>>,
@@ -33,29 +42,46 @@ pub struct WorkerConnection<Stream> {
     /// Information about the worker in the shared state.
     worker: Arc<Mutex<Worker>>,
     free_resources: Resources,
+    pool_resources: BTreeMap<String, Resources>,
     rejected_jobs: BTreeSet<u64>,
     deferred_jobs: BTreeSet<u64>,
     kill_job_rx: Receiver<u64>,
+    /// Messages forwarded from other connections to the remote worker.
+    forward_rx: Receiver<ServerToWorkerMessage>,
+    /// Shared among all worker connections to stagger offer scans.
+    offer_scan_permits: Arc<Semaphore>,
+    /// The connection is served from this point in time on, so that workers
+    /// reconnecting at once are served one after another.
+    connection_slot: Instant,
+    /// Results of finished jobs that are received in segments.
+    results: ResultsAssembler,
     authenticated: bool,
     salt: String,
     connection_closed: bool,
+    /// Set if the connection has been closed on purpose, i.e., the worker
+    /// shut down, has been removed or has timed out, or the server is
+    /// shutting down.
+    closed_gracefully: bool,
 }
 
 impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
     /// Construct a new WorkerConnection.
     pub fn new(
-        worker_name: String,
+        info: WorkerInfo,
         stream: MessageStream<Stream>,
         config: Arc<RwLock<Config>>,
         manager: Arc<RwLock<Manager>>,
         cancel: CancellationToken,
     ) -> Self {
+        let worker_id = info.worker_id;
         let (kill_job_tx, kill_job_rx) = channel::<u64>(10);
-        let worker = manager
-            .write()
-            .unwrap()
-            .add_new_worker(worker_name.clone(), kill_job_tx);
-        let worker_id = worker.lock().unwrap().info.worker_id;
+        let (forward_tx, forward_rx) = channel::<ServerToWorkerMessage>(64);
+        let (worker, offer_scan_permits, connection_slot) = {
+            let mut manager = manager.write().unwrap();
+            let worker = manager.add_new_worker(info, kill_job_tx, forward_tx);
+            let slot = manager.reserve_connection_slot();
+            (worker, manager.offer_scan_permits.clone(), slot)
+        };
 
         // Salt is generated for each worker connection.
         let salt: String = thread_rng()
@@ -66,19 +92,24 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
 
         WorkerConnection {
             worker_id,
-            worker_name,
             stream,
             config,
             manager,
             cancel,
             worker,
             free_resources: Resources::new(0, 0, 0),
+            pool_resources: BTreeMap::new(),
             rejected_jobs: BTreeSet::new(),
             deferred_jobs: BTreeSet::new(),
             kill_job_rx,
+            forward_rx,
+            offer_scan_permits,
+            connection_slot,
+            results: ResultsAssembler::default(),
             authenticated: false,
             salt,
             connection_closed: false,
+            closed_gracefully: false,
         }
     }
 
@@ -95,11 +126,24 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
             return;
         }
 
-        log::info!("Established connection to worker '{}'!", self.worker_name);
-
-        // Notify for newly available jobs.
+        log::info!("Established connection to worker '{}'!", self.worker_name());
 
+        // Notify for newly available jobs. The notification is registered
+        // before handling any message, so that jobs becoming assignable while
+        // the connection is busy are not missed.
         let notify_new_jobs = self.manager.read().unwrap().notify_new_jobs.clone();
+        let new_jobs = notify_new_jobs.notified();
+        tokio::pin!(new_jobs);
+        new_jobs.as_mut().enable();
+
+        // Wait for our turn, if many workers have connected at once.
+        tokio::select! {
+            _ = sleep_until(self.connection_slot.into()) => {}
+            _ = self.cancel.cancelled() => {} // handled below
+        }
+
+        // Signaled when the worker has timed out and is listed as unreachable.
+        let close_connection = self.worker.lock().unwrap().close_connection.clone();
 
         while !self.connection_closed {
             tokio::select! {
@@ -112,6 +156,9 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                                 self.connection_closed = true; // end worker session
                             }
                         }
+                        Err(MessageError::UnknownMessage) => {
+                            log::warn!("Ignoring unknown message from worker '{}'!", self.worker_name());
+                        }
                         Err(e) => {
                             log::error!("{}", e);
                             self.connection_closed = true; // end worker session
@@ -119,17 +166,25 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                     }
                 }
                 // Or, get active when notified about new jobs.
-                _ = notify_new_jobs.notified() => {
+                _ = &mut new_jobs => {
+                    new_jobs.set(notify_new_jobs.notified());
+                    new_jobs.as_mut().enable();
+                    // Removed workers are not offered any more jobs. The
+                    // connection is closed when the forwarded Bye is sent.
+                    let removed = self.manager.read().unwrap().get_worker(self.worker_id).is_none();
+                    if removed {
+                        continue;
+                    }
                     // First, check if this worker is still alive.
                     if self.worker.lock().unwrap().info.timed_out(self.config.read().unwrap().server_settings.worker_timeout_seconds) {
                         self.connection_closed = true; // end worker session
                     } else {
-                        // Offer new job, if no job is currently offered.
-                        if self.worker.lock().unwrap().info.jobs_offered.is_empty() {
+                        // Offer new jobs, unless enough offers are in flight.
+                        if self.can_offer_more() {
                             // Give less-busy workers the chance to pick up the job.
                             self.yield_if_busy().await;
-                            // Now, check for available jobs and pick up the next one.
-                            if let Err(e) = self.offer_pending_job().await {
+                            // Now, check for available jobs and pick up the next ones.
+                            if let Err(e) = self.offer_pending_jobs().await {
                                 log::error!("Failed to offer new job: {}", e);
                                 self.connection_closed = true; // end worker session
                             }
@@ -154,16 +209,48 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                         log::error!("Job to be killed with ID={} not found!", job_id);
                     }
                 }
+                // Or, close the connection of a worker that has timed out.
+                _ = close_connection.notified() => {
+                    log::warn!("Closing connection to unresponsive worker '{}'!", self.worker_name());
+                    self.connection_closed = true; // end worker session
+                    // Already listed as unreachable by the maintenance.
+                    self.closed_gracefully = true;
+                }
+                // Or, forward messages from other connections to the worker.
+                Some(message) = self.forward_rx.recv() => {
+                    if let Err(e) = self.stream.send(&message).await {
+                        log::error!("Failed to forward message: {}", e);
+                        self.connection_closed = true; // end worker session
+                    }
+                    if message == ServerToWorkerMessage::Bye {
+                        log::info!("Worker '{}' has been removed!", self.worker_name());
+                        self.connection_closed = true; // end worker session
+                        self.closed_gracefully = true;
+                    }
+                }
                 // Or, close the connection if server is shutting down.
                 _ = self.cancel.cancelled() => {
                     log::info!("Closing connection to worker!");
+                    // The worker keeps its jobs running and reconnects later.
                     if let Err(e) = self.stream.send(&ServerToWorkerMessage::Bye).await {
                         log::error!("Failed to send bye: {}", e);
                     }
                     self.connection_closed = true; // end worker session
+                    self.closed_gracefully = true;
                 }
             }
         }
+
+        // Keep listing the worker if the connection has been lost.
+        if self.authenticated && !self.closed_gracefully {
+            let info = self.worker.lock().unwrap().info.clone();
+            self.manager.write().unwrap().mark_worker_unreachable(info);
+        }
+    }
+
+    /// Current name of the worker, which can be changed by administrators.
+    fn worker_name(&self) -> String {
+        self.worker.lock().unwrap().info.worker_name.clone()
     }
 
     /// Dispatch incoming message based on variant.
@@ -179,9 +266,19 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 stdout_text,
                 stderr_text,
             } => self.on_update_job_results(job_id, stdout_text, stderr_text),
+            WorkerToServerMessage::JobResultsChunk {
+                job_id,
+                sequence,
+                stream,
+                data,
+                last,
+            } => self.on_job_results_chunk(job_id, sequence, stream, data, last),
             WorkerToServerMessage::UpdateResources(resources) => {
                 self.on_update_resources(resources).await
             }
+            WorkerToServerMessage::UpdatePoolResources(pool_resources) => {
+                self.on_update_pool_resources(pool_resources)
+            }
             WorkerToServerMessage::AcceptJobOffer(job_info) => {
                 self.on_accept_job_offer(job_info).await
             }
@@ -191,9 +288,35 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
             WorkerToServerMessage::RejectJobOffer(job_info) => {
                 self.on_reject_job_offer(job_info).await
             }
+            WorkerToServerMessage::ReportJobs {
+                accepted,
+                running,
+                stalled,
+            } => self.on_report_jobs(accepted, running, stalled).await,
+            WorkerToServerMessage::ReportSelfTest(results) => self.on_report_self_test(results),
+            WorkerToServerMessage::JobOutput { job_id, data } => {
+                self.on_job_output(job_id, data).await
+            }
+            WorkerToServerMessage::TunnelData { tunnel_id, data } => {
+                self.on_tunnel_data(tunnel_id, data).await
+            }
+            WorkerToServerMessage::JobLog {
+                job_id,
+                stream,
+                offset,
+                data,
+                catch_up,
+            } => {
+                self.on_job_log(job_id, stream, offset, data, catch_up)
+                    .await
+            }
+            WorkerToServerMessage::CloseTunnel { tunnel_id } => {
+                self.on_close_tunnel(tunnel_id).await
+            }
             WorkerToServerMessage::Bye => {
                 log::trace!("Connection closed by worker!");
                 self.connection_closed = true;
+                self.closed_gracefully = true;
                 Ok(())
             }
         }
@@ -205,7 +328,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
             Ok(())
         } else {
             // Close connection with an error message.
-            bail!("Worker {} is not authenticated!", self.worker_name)
+            bail!("Worker {} is not authenticated!", self.worker_name())
         }
     }
 
@@ -237,7 +360,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
             Ok(())
         } else {
             // Close connection with an error. No second chance for workers.
-            bail!("Worker {} failed to authenticate!", self.worker_name);
+            bail!("Worker {} failed to authenticate!", self.worker_name());
         }
     }
 
@@ -264,7 +387,11 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 Some(worker_id) if worker_id == self.worker_id => {
                     // Update job and worker if the job has finished.
                     if job_info.status.is_finished() || job_info.status.is_canceled() {
-                        log::debug!("Job {} finished on {}!", job_info.job_id, self.worker_name);
+                        log::debug!(
+                            "Job {} finished on {}!",
+                            job_info.job_id,
+                            self.worker_name()
+                        );
                         self.worker
                             .lock()
                             .unwrap()
@@ -272,11 +399,36 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                             .jobs_running
                             .remove(&job_info.job_id);
 
-                        let mut job = job.lock().unwrap();
-                        job.info.status = job_info.status.clone();
+                        {
+                            let mut job_lock = job.lock().unwrap();
+                            job_lock.info.status = job_info.status.clone();
+                            job_lock.info.step_results = job_info.step_results.clone();
+                            job_lock.info.resource_usage = job_info.resource_usage.clone();
+                            job_lock.info.provenance = job_info.provenance.clone();
+                            job_lock.info.environment = job_info.environment.clone();
+                        }
 
-                        // Notify observers of the job
-                        job.notify_observers();
+                        // Requeue the job, if it has been stopped for migration
+                        // or if requested by its exit code policies.
+                        let mut manager = self.manager.write().unwrap();
+                        manager.release_assigned_job(self.worker_id, job_info.job_id);
+                        manager.classify_stalled_job(&job);
+                        manager.record_resource_usage(&job_info);
+                        // Older workers do not report the project of the job.
+                        let info = job.lock().unwrap().info.clone();
+                        manager.account_job(&info);
+                        manager.record_accounting(&info);
+                        if !manager.requeue_migrated_job(&job) && !manager.retry_finished_job(&job)
+                        {
+                            // Notify observers of the job
+                            job.lock().unwrap().notify_observers();
+                            // Start or cancel dependent jobs.
+                            manager.resolve_dependencies();
+                        }
+                        // Global resources and quotas held by the job are
+                        // free again, which might let other workers pick up
+                        // waiting jobs.
+                        manager.notify_new_jobs.notify_waiters();
                     } else {
                         // At the moment, the worker will only send updates on completed jobs.
                         log::error!("Expected updated job to be finished: {:?}", job_info);
@@ -285,7 +437,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 _ => {
                     log::error!(
                         "Job not associated with worker {}: {:?}",
-                        self.worker_name,
+                        self.worker_name(),
                         job_info
                     );
                 }
@@ -304,26 +456,30 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
         stderr_text: Option<String>,
     ) -> Result<()> {
         self.check_authenticated()?;
+        let worker_name = self.worker_name();
 
         // Update job results with whatever the worker sends us.
         let job = self.manager.read().unwrap().get_job(job_id);
         if let Some(job) = job {
             let mut job_lock = job.lock().unwrap();
 
-            // Just a small check: See if job is associated with worker.
-            match job_lock.worker_id {
-                Some(worker_id) if worker_id == self.worker_id => {
-                    // Update results.
-                    job_lock.stdout_text = stdout_text;
-                    job_lock.stderr_text = stderr_text;
-                }
-                _ => {
-                    log::error!(
-                        "Job not associated with worker {}: {:?}",
-                        self.worker_name,
-                        job_lock.info
-                    );
-                }
+            // Just a small check: See if job is associated with worker. Jobs
+            // that have been requeued for a retry or migration keep the
+            // previous results.
+            let associated = job_lock.worker_id == Some(self.worker_id);
+            let retried = job_lock.worker_id.is_none()
+                && (job_lock.info.retries > 0 || job_lock.info.migrations > 0)
+                && job_lock.info.status.is_pending();
+            if associated || retried {
+                // Update results.
+                job_lock.set_outputs(stdout_text, stderr_text);
+                job_lock.save();
+            } else {
+                log::error!(
+                    "Job not associated with worker {}: {:?}",
+                    worker_name,
+                    job_lock.info
+                );
             }
         } else {
             log::error!("Updated job not found: ID={}", job_id);
@@ -331,6 +487,141 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
         Ok(())
     }
 
+    /// Called upon receiving WorkerToServerMessage::JobResultsChunk.
+    fn on_job_results_chunk(
+        &mut self,
+        job_id: u64,
+        sequence: u64,
+        stream: OutputStream,
+        data: String,
+        last: bool,
+    ) -> Result<()> {
+        self.check_authenticated()?;
+
+        // Results are updated once the last segment has been received.
+        match self.results.add(job_id, sequence, stream, data, last) {
+            Ok(Some((stdout_text, stderr_text))) => {
+                self.on_update_job_results(job_id, stdout_text, stderr_text)
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                log::error!("Dropped results from worker {}: {}", self.worker_name(), e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Called upon receiving WorkerToServerMessage::JobOutput.
+    async fn on_job_output(&mut self, job_id: u64, data: Vec<u8>) -> Result<()> {
+        self.check_authenticated()?;
+
+        // Forward terminal output to the attached client. Until
+        // a client is attached, the output is kept on the server.
+        let client_tx = match self.manager.read().unwrap().get_job(job_id) {
+            Some(job) => {
+                let mut job_lock = job.lock().unwrap();
+                if job_lock.attached_client.is_none() {
+                    job_lock.buffer_output(&data);
+                }
+                job_lock.attached_client.clone()
+            }
+            None => None,
+        };
+        if let Some(client_tx) = client_tx {
+            let message = ServerToClientMessage::JobOutput { job_id, data };
+            if client_tx.send(message).await.is_err() {
+                log::debug!("Client attached to job {} is gone!", job_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving WorkerToServerMessage::JobLog.
+    async fn on_job_log(
+        &mut self,
+        job_id: u64,
+        stream: OutputStream,
+        offset: u64,
+        data: Vec<u8>,
+        catch_up: bool,
+    ) -> Result<()> {
+        self.check_authenticated()?;
+
+        // Forward the part of the output that is new to each follower.
+        let (deliveries, followed) = match self.manager.read().unwrap().get_job(job_id) {
+            Some(job) => {
+                let mut job_lock = job.lock().unwrap();
+                job_lock
+                    .log_followers
+                    .retain(|follower| !follower.client_tx.is_closed());
+                let deliveries: Vec<_> = job_lock
+                    .log_followers
+                    .iter_mut()
+                    .filter_map(|follower| {
+                        let data = follower.new_output(stream, offset, &data, catch_up)?;
+                        let message = ServerToClientMessage::JobLog {
+                            job_id,
+                            stream,
+                            data: data.to_vec(),
+                        };
+                        Some((follower.client_tx.clone(), message))
+                    })
+                    .collect();
+                (deliveries, !job_lock.log_followers.is_empty())
+            }
+            None => (Vec::new(), false),
+        };
+        for (client_tx, message) in deliveries {
+            if client_tx.send(message).await.is_err() {
+                log::debug!("Client following job {} is gone!", job_id);
+            }
+        }
+
+        // Stop forwarding once all followers are gone.
+        if !followed {
+            let message = ServerToWorkerMessage::FollowJob {
+                job_id,
+                follow: false,
+            };
+            self.stream.send(&message).await?;
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving WorkerToServerMessage::TunnelData.
+    async fn on_tunnel_data(&mut self, tunnel_id: u64, data: Vec<u8>) -> Result<()> {
+        self.check_authenticated()?;
+
+        // Forward data to the client that opened the tunnel.
+        let route = self.manager.read().unwrap().get_tunnel(tunnel_id);
+        if let Some(route) = route {
+            let message = ServerToClientMessage::TunnelData {
+                tunnel_id: route.client_tunnel_id,
+                data,
+            };
+            if route.client_tx.send(message).await.is_err() {
+                log::debug!("Client of tunnel {} is gone!", tunnel_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called upon receiving WorkerToServerMessage::CloseTunnel.
+    async fn on_close_tunnel(&mut self, tunnel_id: u64) -> Result<()> {
+        self.check_authenticated()?;
+
+        let route = self.manager.write().unwrap().remove_tunnel(tunnel_id);
+        if let Some(route) = route {
+            let message = ServerToClientMessage::CloseTunnel {
+                tunnel_id: route.client_tunnel_id,
+            };
+            if route.client_tx.send(message).await.is_err() {
+                log::debug!("Client of tunnel {} is gone!", tunnel_id);
+            }
+        }
+        Ok(())
+    }
+
     /// Called upon receiving WorkerToServerMessage::UpdateResources.
     async fn on_update_resources(&mut self, resources: Resources) -> Result<()> {
         self.check_authenticated()?;
@@ -341,50 +632,72 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
         // Forget all deferred jobs.
         self.deferred_jobs.clear();
 
-        let no_job_offered = {
-            let mut worker_lock = self.worker.lock().unwrap();
+        // Keep copy of free resources in info.
+        self.worker.lock().unwrap().info.free_resources = resources;
 
-            // Keep copy of free resources in info.
-            worker_lock.info.free_resources = resources;
+        // Offer new jobs.
+        self.offer_pending_jobs().await?;
+        Ok(())
+    }
 
-            worker_lock.info.jobs_offered.is_empty()
-        };
+    /// Called upon receiving WorkerToServerMessage::UpdatePoolResources.
+    fn on_update_pool_resources(
+        &mut self,
+        pool_resources: BTreeMap<String, Resources>,
+    ) -> Result<()> {
+        self.check_authenticated()?;
 
-        // Offer new job.
-        if no_job_offered {
-            self.offer_pending_job().await?;
-        }
+        // Jobs are offered on the subsequent UpdateResources message.
+        self.pool_resources = pool_resources.clone();
+        self.worker.lock().unwrap().info.pool_resources = pool_resources;
         Ok(())
     }
 
     /// Called upon receiving WorkerToServerMessage::AcceptJobOffer.
     async fn on_accept_job_offer(&mut self, job_info: JobInfo) -> Result<()> {
         self.check_authenticated()?;
+        let worker_name = self.worker_name();
 
         let job = self.manager.read().unwrap().get_job(job_info.job_id);
         if let Some(job) = job {
-            let job_info = {
+            let (job_info, followed) = {
                 // Perform small check and update job status.
+                let answered = job_info.status.offered();
+                let mut worker_lock = self.worker.lock().unwrap();
                 let mut job_lock = job.lock().unwrap();
                 match &job_lock.info.status {
                     JobStatus::Offered {
                         issued,
-                        offered: _,
+                        offered,
                         worker,
-                    } if worker == &self.worker_name => {
+                    } if job_lock.worker_id == Some(self.worker_id)
+                        && answered.is_none_or(|answered| answered == *offered) =>
+                    {
                         job_lock.info.status = JobStatus::Running {
                             issued: *issued,
                             started: Utc::now(),
-                            worker: self.worker_name.clone(),
+                            worker: worker.clone(),
                         };
                     }
+                    _ if worker_lock.forget_withdrawn_offer(&job_info) => {
+                        log::debug!("Ignoring acceptance of withdrawn job {}!", job_info.job_id);
+                        return Ok(());
+                    }
+                    // The job has been offered again since the worker answered.
+                    JobStatus::Offered { .. } if job_lock.worker_id == Some(self.worker_id) => {
+                        log::debug!(
+                            "Ignoring acceptance of earlier offer of job {}!",
+                            job_info.job_id
+                        );
+                        return Ok(());
+                    }
                     JobStatus::Canceled { .. } => {
                         log::debug!("Offered job has been canceled in the meantime!")
                         // TODO: Withdraw!
                     }
                     _ => bail!(
                         "Accepted job was not offered to worker {}: {:?}",
-                        self.worker_name,
+                        worker_name,
                         job_lock.info.status
                     ),
                 }
@@ -392,16 +705,40 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 // Notify observers of the job
                 job_lock.notify_observers();
 
-                job_lock.info.clone()
+                (
+                    job_lock.info.without_env_snapshot(),
+                    !job_lock.log_followers.is_empty(),
+                )
             };
 
-            log::debug!("Job {} accepted by {}!", job_info.job_id, self.worker_name);
+            log::debug!("Job {} accepted by {}!", job_info.job_id, worker_name);
+            self.manager.read().unwrap().trace_scheduling(
+                job_info.job_id,
+                &worker_name,
+                SchedulingOutcome::Accepted,
+                &String::new,
+            );
 
             // Confirm job -> Worker will start execution
             let job_id = job_info.job_id; // copy before move
             let message = ServerToWorkerMessage::ConfirmJobOffer(job_info);
             self.stream.send(&message).await?;
 
+            // Clients may have started following the job while it was pending.
+            let supported = self
+                .worker
+                .lock()
+                .unwrap()
+                .info
+                .has_capability(CAP_LOG_FOLLOW);
+            if followed && supported {
+                let message = ServerToWorkerMessage::FollowJob {
+                    job_id,
+                    follow: true,
+                };
+                self.stream.send(&message).await?;
+            }
+
             // Update worker.
             let mut worker_lock = self.worker.lock().unwrap();
             worker_lock.info.jobs_offered.remove(&job_id);
@@ -415,18 +752,23 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
     /// Called upon receiving WorkerToServerMessage::DeferJobOffer.
     async fn on_defer_job_offer(&mut self, job_info: JobInfo) -> Result<()> {
         self.check_authenticated()?;
+        let worker_name = self.worker_name();
 
         let job = self.manager.read().unwrap().get_job(job_info.job_id);
         if let Some(job) = job {
             // Perform small check and update job status.
             {
+                let answered = job_info.status.offered();
+                let mut worker_lock = self.worker.lock().unwrap();
                 let mut job_lock = job.lock().unwrap();
                 match &job_lock.info.status {
                     JobStatus::Offered {
                         issued,
-                        offered: _,
+                        offered,
                         worker,
-                    } if worker == &self.worker_name => {
+                    } if job_lock.worker_id == Some(self.worker_id)
+                        && answered.is_none_or(|answered| answered == *offered) =>
+                    {
                         job_lock.info.status = JobStatus::Pending { issued: *issued };
                         job_lock.worker_id = None;
                         // TODO: The job should also be made available again!
@@ -434,9 +776,21 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                         // Remember defer and avoid fetching the same job again soon.
                         self.deferred_jobs.insert(job_lock.info.job_id);
                     }
+                    _ if worker_lock.forget_withdrawn_offer(&job_info) => {
+                        log::debug!("Ignoring deferral of withdrawn job {}!", job_info.job_id);
+                        return Ok(());
+                    }
+                    // The job has been offered again since the worker answered.
+                    JobStatus::Offered { .. } if job_lock.worker_id == Some(self.worker_id) => {
+                        log::debug!(
+                            "Ignoring deferral of earlier offer of job {}!",
+                            job_info.job_id
+                        );
+                        return Ok(());
+                    }
                     _ => bail!(
                         "Deferred job was not offered to worker {}: {:?}",
-                        self.worker_name,
+                        worker_name,
                         job_lock.info.status
                     ),
                 }
@@ -445,19 +799,28 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 job_lock.notify_observers();
             };
 
-            log::debug!("Job {} deferred by {}!", job_info.job_id, self.worker_name);
+            log::debug!("Job {} deferred by {}!", job_info.job_id, worker_name);
+            {
+                let manager = self.manager.read().unwrap();
+                manager.release_assigned_job(self.worker_id, job_info.job_id);
+                manager.trace_scheduling(
+                    job_info.job_id,
+                    &worker_name,
+                    SchedulingOutcome::Deferred,
+                    &String::new,
+                );
+            }
 
             // Update worker.
-            let no_jobs_offered = {
-                let mut worker_lock = self.worker.lock().unwrap();
-                worker_lock.info.jobs_offered.remove(&job_info.job_id);
-                worker_lock.info.jobs_offered.is_empty()
-            };
+            self.worker
+                .lock()
+                .unwrap()
+                .info
+                .jobs_offered
+                .remove(&job_info.job_id);
 
-            // Offer new job.
-            if no_jobs_offered {
-                self.offer_pending_job().await?;
-            }
+            // Offer new jobs.
+            self.offer_pending_jobs().await?;
         } else {
             bail!("Deferred job not found: {:?}", job_info);
         }
@@ -467,18 +830,24 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
     /// Called upon receiving WorkerToServerMessage::RejectJobOffer.
     async fn on_reject_job_offer(&mut self, job_info: JobInfo) -> Result<()> {
         self.check_authenticated()?;
+        let worker_name = self.worker_name();
 
         let job = self.manager.read().unwrap().get_job(job_info.job_id);
         if let Some(job) = job {
             // Perform small check and update job status.
+            let mut requeue = false;
             {
+                let answered = job_info.status.offered();
+                let mut worker_lock = self.worker.lock().unwrap();
                 let mut job_lock = job.lock().unwrap();
                 match &job_lock.info.status {
                     JobStatus::Offered {
                         issued,
-                        offered: _,
+                        offered,
                         worker,
-                    } if worker == &self.worker_name => {
+                    } if job_lock.worker_id == Some(self.worker_id)
+                        && answered.is_none_or(|answered| answered == *offered) =>
+                    {
                         job_lock.info.status = JobStatus::Pending { issued: *issued };
                         job_lock.worker_id = None;
                         // TODO: The job should also be made available again!
@@ -486,9 +855,35 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                         // Remember reject and avoid fetching the same job again.
                         self.rejected_jobs.insert(job_lock.info.job_id);
                     }
-                    _ => bail!(
+                    _ if worker_lock.forget_withdrawn_offer(&job_info) => {
+                        log::debug!("Ignoring rejection of withdrawn job {}!", job_info.job_id);
+                        return Ok(());
+                    }
+                    // The job has been offered again since the worker answered.
+                    JobStatus::Offered { .. } if job_lock.worker_id == Some(self.worker_id) => {
+                        log::debug!(
+                            "Ignoring rejection of earlier offer of job {}!",
+                            job_info.job_id
+                        );
+                        return Ok(());
+                    }
+                    // The worker dropped a stale offer while our confirmation
+                    // was still on its way. The job has never been started.
+                    JobStatus::Running {
+                        issued,
+                        started: _,
+                        worker,
+                    } if job_lock.worker_id == Some(self.worker_id) => {
+                        job_lock.info.status = JobStatus::Pending { issued: *issued };
+                        job_lock.worker_id = None;
+                        // Other workers may pick up the job, like jobs of
+                        // removed workers.
+                        requeue = true;
+                    }
+                    // The offer of a stale job might have been recovered already.
+                    _ => log::warn!(
                         "Rejected job was not offered to worker {}: {:?}",
-                        self.worker_name,
+                        worker_name,
                         job_lock.info.status
                     ),
                 }
@@ -497,25 +892,103 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
                 job_lock.notify_observers();
             };
 
-            log::debug!("Job {} rejected by {}!", job_info.job_id, self.worker_name);
+            log::debug!("Job {} rejected by {}!", job_info.job_id, worker_name);
+            {
+                let manager = self.manager.read().unwrap();
+                manager.release_assigned_job(self.worker_id, job_info.job_id);
+                if requeue {
+                    manager.requeue_job(job_info.job_id);
+                }
+                manager.trace_scheduling(
+                    job_info.job_id,
+                    &worker_name,
+                    SchedulingOutcome::Rejected,
+                    &String::new,
+                );
+            }
 
             // Update worker.
-            let no_jobs_offered = {
+            {
                 let mut worker_lock = self.worker.lock().unwrap();
                 worker_lock.info.jobs_offered.remove(&job_info.job_id);
-                worker_lock.info.jobs_offered.is_empty()
-            };
-
-            // Offer new job.
-            if no_jobs_offered {
-                self.offer_pending_job().await?;
+                worker_lock.info.jobs_running.remove(&job_info.job_id);
             }
+
+            // Offer new jobs.
+            self.offer_pending_jobs().await?;
         } else {
             bail!("Rejected job not found: {:?}", job_info);
         }
         Ok(())
     }
 
+    /// Called upon receiving WorkerToServerMessage::ReportSelfTest.
+    fn on_report_self_test(&mut self, results: Vec<ProbeResult>) -> Result<()> {
+        self.check_authenticated()?;
+
+        for result in results.iter().filter(|result| !result.passed) {
+            log::warn!(
+                "Worker {} failed self-test probe '{}': {}",
+                self.worker_name(),
+                result.name,
+                result.output
+            );
+        }
+        self.worker.lock().unwrap().info.self_test = results;
+        Ok(())
+    }
+
+    /// Called upon receiving WorkerToServerMessage::ReportJobs.
+    async fn on_report_jobs(
+        &mut self,
+        accepted: BTreeSet<u64>,
+        running: BTreeSet<u64>,
+        stalled: BTreeSet<u64>,
+    ) -> Result<()> {
+        self.check_authenticated()?;
+
+        let worker_id = self.worker.lock().unwrap().info.worker_id;
+        let worker_name = self.worker_name();
+        let permit = self.offer_scan_permits.clone().acquire_owned().await?;
+        let (adopted_jobs, (requeued_jobs, offers_to_withdraw, jobs_to_kill)) = {
+            let mut manager = self.manager.write().unwrap();
+            // Jobs might still run on a worker that reconnected after losing
+            // its connection, e.g., during a restart of the server.
+            let adopted_jobs = manager.adopt_worker_jobs(worker_id, &worker_name, &running);
+            let reconciled = manager.reconcile_worker_jobs(worker_id, &accepted, &running);
+            (adopted_jobs, reconciled)
+        };
+        drop(permit);
+
+        // Flag stalled jobs and kill them, if configured.
+        let stalled_jobs_to_kill = self
+            .manager
+            .read()
+            .unwrap()
+            .update_stalled_jobs(worker_id, &stalled);
+
+        // Update worker.
+        {
+            let mut worker_lock = self.worker.lock().unwrap();
+            worker_lock.info.jobs_running.extend(adopted_jobs);
+            for job_id in requeued_jobs {
+                worker_lock.info.jobs_offered.remove(&job_id);
+                worker_lock.info.jobs_running.remove(&job_id);
+            }
+        }
+
+        // Resolve jobs held by the worker without being assigned to it.
+        for job_info in offers_to_withdraw {
+            let message = ServerToWorkerMessage::WithdrawJobOffer(job_info);
+            self.stream.send(&message).await?;
+        }
+        for job_info in jobs_to_kill.into_iter().chain(stalled_jobs_to_kill) {
+            let message = ServerToWorkerMessage::KillJob(job_info);
+            self.stream.send(&message).await?;
+        }
+        Ok(())
+    }
+
     async fn yield_if_busy(&self) {
         let load = self.worker.lock().unwrap().info.resource_load();
 
@@ -533,7 +1006,64 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
         }
     }
 
-    async fn offer_pending_job(&mut self) -> Result<(), MessageError> {
+    /// Returns `true` if fewer than `offer_pipeline_depth` job offers
+    /// are waiting for an answer of the worker.
+    fn can_offer_more(&self) -> bool {
+        let depth = self
+            .config
+            .read()
+            .unwrap()
+            .server_settings
+            .offer_pipeline_depth
+            .max(1);
+        let offered = self.worker.lock().unwrap().info.jobs_offered.len() as u64;
+        offered < depth
+    }
+
+    /// Free resources of the worker and its pools, minus the resources
+    /// required by jobs that have been offered but not answered yet.
+    fn resources_left_for_offers(&self) -> (Resources, BTreeMap<String, Resources>) {
+        let mut free_resources = self.free_resources.clone();
+        let mut pool_resources = self.pool_resources.clone();
+        let jobs_offered = self.worker.lock().unwrap().info.jobs_offered.clone();
+        let manager = self.manager.read().unwrap();
+        for job_id in jobs_offered {
+            if let Some(job) = manager.get_job(job_id) {
+                let job_lock = job.lock().unwrap();
+                let available = match &job_lock.info.pool {
+                    Some(pool) => pool_resources.get_mut(pool),
+                    None => Some(&mut free_resources),
+                };
+                if let Some(available) = available {
+                    available.subtract(&job_lock.info.worker_resources);
+                }
+            }
+        }
+        (free_resources, pool_resources)
+    }
+
+    /// Offer pending jobs to the worker. Further jobs are offered while the
+    /// worker still decides on earlier offers, up to `offer_pipeline_depth`.
+    async fn offer_pending_jobs(&mut self) -> Result<(), MessageError> {
+        while self.can_offer_more() {
+            if !self.offer_pending_job().await? {
+                break; // no suitable job left
+            }
+        }
+        Ok(())
+    }
+
+    /// Offer the next suitable job to the worker. Returns `false`
+    /// if no job has been offered.
+    async fn offer_pending_job(&mut self) -> Result<bool, MessageError> {
+        // Workers that failed their self-test or are drained do not receive any jobs.
+        {
+            let info = &self.worker.lock().unwrap().info;
+            if info.self_test_failed() || info.draining {
+                return Ok(false);
+            }
+        }
+
         let excluded_jobs: BTreeSet<u64> = self
             .rejected_jobs
             .iter()
@@ -541,33 +1071,297 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> WorkerConnection<Stream> {
             .cloned()
             .collect();
 
-        let available_job = self
-            .manager
-            .write()
-            .unwrap()
-            .get_job_waiting_for_assignment(
+        // Resources of offers in flight are not available for this offer.
+        let (free_resources, pool_resources) = self.resources_left_for_offers();
+        let available_job = {
+            // Wait for our turn, if many workers are looking for jobs at once.
+            // The permit is held while scanning the queue, but not while
+            // sending the offer, which might take long on a slow connection.
+            let Ok(_permit) = self.offer_scan_permits.clone().acquire_owned().await else {
+                return Ok(false); // semaphore closed
+            };
+            // The name cannot change while the manager is locked.
+            let manager = self.manager.read().unwrap();
+            manager.get_job_waiting_for_assignment(
                 self.worker_id,
-                &self.worker_name,
+                &self.worker_name(),
                 &excluded_jobs,
-                &self.free_resources,
-            );
+                &free_resources,
+                &pool_resources,
+            )
+        };
 
         if let Some(job) = available_job {
-            let job_info = job.lock().unwrap().info.clone();
+            // Workers have no use for the environment recorded by the client.
+            let job_info = job.lock().unwrap().info.without_env_snapshot();
 
-            // Worker has one more job reserved. Prevents over-offering.
-            self.worker
-                .lock()
-                .unwrap()
-                .info
-                .jobs_offered
-                .insert(job_info.job_id);
+            // Worker has one more job reserved. Prevents over-offering. A
+            // withdrawal of an earlier offer of the job is superseded.
+            {
+                let mut worker_lock = self.worker.lock().unwrap();
+                worker_lock.info.jobs_offered.insert(job_info.job_id);
+                worker_lock.withdrawn_offers.remove(&job_info.job_id);
+            }
 
             // Finally, send offer to worker.
             let job_offer = ServerToWorkerMessage::OfferJob(job_info);
-            self.stream.send(&job_offer).await
+            self.stream.send(&job_offer).await?;
+            Ok(true)
         } else {
-            Ok(()) // no job offered
+            Ok(false) // no job offered
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn offers_in_flight() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.offer_pipeline_depth = 3;
+        let manager = Arc::new(RwLock::new(Manager::new(config.clone())));
+        for _ in 0..10 {
+            let cmd = vec!["true".to_string()];
+            let resources = Resources::new(1, 2, 1024);
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+            manager.write().unwrap().add_new_job(job_info);
+        }
+        let (stream, _worker_stream) = duplex(1 << 20);
+        let mut connection = WorkerConnection::new(
+            WorkerInfo::new("worker".into(), "0.0.0".into()),
+            MessageStream::new(stream),
+            Arc::new(RwLock::new(config)),
+            manager,
+            CancellationToken::new(),
+        );
+        let offered = |connection: &WorkerConnection<_>| {
+            connection.worker.lock().unwrap().info.jobs_offered.clone()
+        };
+
+        // Offers fill the pipeline, but do not exceed its depth.
+        connection.free_resources = Resources::new(100, 100, 100 * 1024);
+        connection.offer_pending_jobs().await.unwrap();
+        assert_eq!(offered(&connection).len(), 3);
+        connection.offer_pending_jobs().await.unwrap();
+        assert_eq!(offered(&connection).len(), 3);
+
+        // Offers in flight together never exceed the free resources.
+        connection.worker.lock().unwrap().info.jobs_offered.clear();
+        connection.free_resources = Resources::new(100, 5, 100 * 1024);
+        connection.offer_pending_jobs().await.unwrap();
+        assert_eq!(offered(&connection).len(), 2);
+        let (left, _) = connection.resources_left_for_offers();
+        assert_eq!(left.cpus, 1);
+    }
+
+    #[tokio::test]
+    async fn offer_scan_permits() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.max_concurrent_offer_scans = 1;
+        let manager = Arc::new(RwLock::new(Manager::new(config.clone())));
+        for _ in 0..2 {
+            let cmd = vec!["true".to_string()];
+            let resources = Resources::new(1, 1, 1024);
+            let job_info = JobInfo::new(cmd, "/tmp".into(), resources, None, None, None);
+            manager.write().unwrap().add_new_job(job_info);
+        }
+
+        // The worker does not read its offers, so that sending them stalls.
+        let (stream, _worker_stream) = duplex(64);
+        let mut connection = WorkerConnection::new(
+            WorkerInfo::new("worker".into(), "0.0.0".into()),
+            MessageStream::new(stream),
+            Arc::new(RwLock::new(config)),
+            Arc::clone(&manager),
+            CancellationToken::new(),
+        );
+        connection.free_resources = Resources::new(100, 100, 100 * 1024);
+        let worker = Arc::clone(&connection.worker);
+        let offering = tokio::spawn(async move { connection.offer_pending_jobs().await });
+        while worker.lock().unwrap().info.jobs_offered.is_empty() {
+            yield_now().await;
+        }
+
+        // Other workers may still scan the queue meanwhile.
+        let permits = manager.read().unwrap().offer_scan_permits.clone();
+        assert_eq!(permits.available_permits(), 1);
+        assert!(!offering.is_finished());
+        offering.abort();
+    }
+
+    #[tokio::test]
+    async fn close_timed_out_worker() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.server_settings.reconnect_stagger_ms = 0;
+        let manager = Arc::new(RwLock::new(Manager::new(config.clone())));
+        let (stream, worker_stream) = duplex(1 << 20);
+        let mut worker_stream = MessageStream::new(worker_stream);
+        let mut connection = WorkerConnection::new(
+            WorkerInfo::new("worker".into(), "0.0.0".into()),
+            MessageStream::new(stream),
+            Arc::new(RwLock::new(config)),
+            Arc::clone(&manager),
+            CancellationToken::new(),
+        );
+        connection.authenticated = true;
+        let worker_id = connection.worker_id;
+        let worker = Arc::clone(&connection.worker);
+        let session = tokio::spawn(async move { connection.run().await });
+        let challenge = worker_stream
+            .receive::<ServerToWorkerMessage>()
+            .await
+            .unwrap();
+        assert!(matches!(
+            challenge,
+            ServerToWorkerMessage::AuthChallenge { .. }
+        ));
+
+        // The worker times out while its connection is still open.
+        worker.lock().unwrap().info.last_updated = Utc::now() - chrono::Duration::hours(1);
+        manager.write().unwrap().run_maintenance();
+        assert!(manager.read().unwrap().get_worker(worker_id).is_none());
+        assert!(manager
+            .read()
+            .unwrap()
+            .get_unreachable_worker(worker_id)
+            .is_some());
+
+        // Late messages of the worker do not keep the session alive.
+        let message = WorkerToServerMessage::UpdateSystemInfo(SystemInfo::default());
+        let _ = worker_stream.send(&message).await;
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(5), session).await;
+        assert!(closed.is_ok());
+
+        // The worker reconnects under its own name.
+        let (kill_job_tx, _) = channel(1);
+        let (forward_tx, _) = channel(1);
+        let info = WorkerInfo::new("worker".into(), "0.0.0".into());
+        let worker = manager
+            .write()
+            .unwrap()
+            .add_new_worker(info, kill_job_tx, forward_tx);
+        assert_eq!(worker.lock().unwrap().info.worker_name, "worker");
+        assert!(manager
+            .read()
+            .unwrap()
+            .get_unreachable_worker(worker_id)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn reject_confirmed_offer() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new(config.clone())));
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        let job = manager.write().unwrap().add_new_job(job_info);
+        let (stream, _worker_stream) = duplex(1 << 20);
+        let mut connection = WorkerConnection::new(
+            WorkerInfo::new("worker".into(), "0.0.0".into()),
+            MessageStream::new(stream),
+            Arc::new(RwLock::new(config)),
+            Arc::clone(&manager),
+            CancellationToken::new(),
+        );
+        connection.authenticated = true;
+
+        // The job has been confirmed, but the worker dropped the stale offer.
+        connection.free_resources = resources.clone();
+        connection.offer_pending_jobs().await.unwrap();
+        let job_info = {
+            let mut job_lock = job.lock().unwrap();
+            assert_eq!(job_lock.worker_id, Some(connection.worker_id));
+            job_lock.info.status = JobStatus::Running {
+                issued: Utc::now(),
+                started: Utc::now(),
+                worker: "worker".into(),
+            };
+            job_lock.info.clone()
+        };
+        let notify_new_jobs = manager.read().unwrap().notify_new_jobs.clone();
+        let new_jobs = notify_new_jobs.notified();
+        tokio::pin!(new_jobs);
+        new_jobs.as_mut().enable();
+        connection.free_resources = Resources::new(0, 0, 0);
+        connection.on_reject_job_offer(job_info).await.unwrap();
+
+        // The job is available to other workers again, which are notified.
+        assert!(job.lock().unwrap().info.status.is_pending());
+        assert!(new_jobs.now_or_never().is_some());
+        let exclude = BTreeSet::new();
+        let offered = manager.read().unwrap().get_job_waiting_for_assignment(
+            connection.worker_id + 1,
+            "other",
+            &exclude,
+            &resources,
+            &BTreeMap::new(),
+        );
+        assert!(offered.is_some_and(|offered| Arc::ptr_eq(&offered, &job)));
+    }
+
+    #[tokio::test]
+    async fn answer_withdrawn_offer() {
+        let config = Config::new(Some("no-config".into())).unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new(config.clone())));
+        let cmd = vec!["true".to_string()];
+        let resources = Resources::new(1, 1, 1024);
+        let job_info = JobInfo::new(cmd, "/tmp".into(), resources.clone(), None, None, None);
+        let job = manager.write().unwrap().add_new_job(job_info);
+        let (stream, _worker_stream) = duplex(1 << 20);
+        let mut connection = WorkerConnection::new(
+            WorkerInfo::new("worker".into(), "0.0.0".into()),
+            MessageStream::new(stream),
+            Arc::new(RwLock::new(config)),
+            Arc::clone(&manager),
+            CancellationToken::new(),
+        );
+        connection.authenticated = true;
+        connection.free_resources = resources.clone();
+
+        // The offer is withdrawn before the worker has answered it.
+        connection.offer_pending_jobs().await.unwrap();
+        let withdrawn_info = {
+            let mut job_lock = job.lock().unwrap();
+            let withdrawn_info = job_lock.info.clone();
+            let offered = withdrawn_info.status.offered().unwrap();
+            job_lock.info.status = JobStatus::Pending { issued: Utc::now() };
+            job_lock.worker_id = None;
+            let mut worker_lock = connection.worker.lock().unwrap();
+            worker_lock.info.jobs_offered.clear();
+            worker_lock
+                .withdrawn_offers
+                .insert(withdrawn_info.job_id, offered);
+            withdrawn_info
+        };
+        {
+            let manager = manager.read().unwrap();
+            manager.release_assigned_job(connection.worker_id, withdrawn_info.job_id);
+            manager.requeue_job(withdrawn_info.job_id);
         }
+
+        // The job is offered again, which supersedes the withdrawal.
+        connection.offer_pending_jobs().await.unwrap();
+        let offered_info = job.lock().unwrap().info.clone();
+        assert_ne!(offered_info.status, withdrawn_info.status);
+        assert!(connection
+            .worker
+            .lock()
+            .unwrap()
+            .withdrawn_offers
+            .is_empty());
+
+        // The late answer to the withdrawn offer does not accept the new one.
+        connection
+            .on_accept_job_offer(withdrawn_info)
+            .await
+            .unwrap();
+        assert!(job.lock().unwrap().info.status.is_offered());
+        connection.on_accept_job_offer(offered_info).await.unwrap();
+        assert!(job.lock().unwrap().info.status.is_running());
     }
 }
@@ -1,10 +1,13 @@
 //! Structs that are shared among binary crates and part of messages.
 
+use crate::crypto;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fmt,
     path::PathBuf,
+    str::FromStr,
     sync::atomic::{AtomicU64, Ordering},
 };
 
@@ -29,15 +32,240 @@ pub struct JobInfo {
     pub stdout_path: Option<String>,
     /// If Some(path), redirect stderr to given file path.
     pub stderr_path: Option<String>,
+    /// Commands executed before `cmd` within the same resource allocation.
+    /// If a setup step fails, `cmd` is not executed.
+    #[serde(default)]
+    pub setup_steps: Vec<Vec<String>>,
+    /// Commands executed after `cmd` within the same resource allocation.
+    /// Teardown steps are executed even if previous steps have failed.
+    #[serde(default)]
+    pub teardown_steps: Vec<Vec<String>>,
+    /// Exit codes and run times of the individual steps. Only reported by the
+    /// worker for jobs with setup or teardown steps.
+    #[serde(default)]
+    pub step_results: Vec<StepResult>,
+    /// Interactive jobs run in a terminal on the worker, which is relayed
+    /// through the server to the attached client.
+    #[serde(default)]
+    pub interactive: bool,
+    /// Name of the user who issued the job. It is taken from the client's
+    /// `$USER` and not verified by the server, so it must not be relied on
+    /// to enforce limits against dishonest users.
+    #[serde(default)]
+    pub user: String,
+    /// Maximum CPU time (in seconds) each process of the job may consume
+    /// before being terminated, independent of the elapsed wall-clock time.
+    #[serde(default)]
+    pub cpu_time_limit_seconds: Option<u64>,
+    /// Maximum elapsed wall-clock time (in seconds) of the job. The worker
+    /// kills the job once the limit is exceeded.
+    #[serde(default)]
+    pub wall_time_limit_seconds: Option<u64>,
+    /// Policies evaluated by the server when the job finishes with a
+    /// non-zero exit code, e.g., to retry on temporary failures.
+    #[serde(default)]
+    pub exit_code_policies: Vec<ExitCodePolicy>,
+    /// Maximum number of retries triggered by exit code policies.
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Number of times the job has been retried so far.
+    #[serde(default)]
+    pub retries: u32,
+    /// Names of workers the job should not be offered to anymore.
+    #[serde(default)]
+    pub avoid_workers: BTreeSet<String>,
+    /// Jobs that must conclude before this job can be started.
+    #[serde(default)]
+    pub dependencies: Vec<JobDependency>,
+    /// Resources measured by the worker while the job was running.
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+    /// Let the server adjust the requested resources based on the measured
+    /// usage of similar jobs in the past.
+    #[serde(default)]
+    pub auto_resources: bool,
+    /// Resources suggested by the server based on similar jobs in the past.
+    #[serde(default)]
+    pub suggested_resources: Option<Resources>,
+    /// Name of the resource pool on the worker the job should run in.
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// Name of the submission batch the job belongs to, e.g., a parameter sweep.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Project the job is accounted to, e.g., a grant or a team. Must be one
+    /// of the projects configured on the server.
+    #[serde(default)]
+    pub project: Option<String>,
+    /// Jobs with higher priority are offered to workers first. Jobs of the
+    /// same priority are offered in the order they have been issued.
+    #[serde(default)]
+    pub priority: i32,
+    /// Set while the running job has neither consumed CPU time nor produced
+    /// output for longer than the worker's `stalled_job_timeout_minutes`.
+    #[serde(default)]
+    pub stalled: bool,
+    /// Environment of the user's shell at submission time, if requested.
+    #[serde(default)]
+    pub env_snapshot: Option<EnvSnapshot>,
+    /// Command and steps of the job, encrypted with the payload key shared by
+    /// client and worker. If set, `cmd` only holds a placeholder.
+    #[serde(default)]
+    pub encrypted_payload: Option<String>,
+    /// Signed record of where and how the job has been executed, created by
+    /// the worker when the job finishes.
+    #[serde(default)]
+    pub provenance: Option<ProvenanceRecord>,
+    /// Number of GPU devices (or MIG slices) required by the job.
+    #[serde(default)]
+    pub gpus: u64,
+    /// Whether the job's GPUs may be shared with other jobs.
+    #[serde(default)]
+    pub gpu_mode: GpuMode,
+    /// Pin the job to whole physical cores, e.g., for reproducible benchmarks.
+    #[serde(default)]
+    pub core_binding: Option<CoreBinding>,
+    /// Let the worker create the working directory if it does not exist,
+    /// instead of rejecting the job.
+    #[serde(default)]
+    pub create_cwd: bool,
+    /// Limits applied to each process of the job, e.g., on open files.
+    #[serde(default)]
+    pub process_limits: ProcessLimits,
+    /// Labels a worker must have, with the given values, to run the job.
+    #[serde(default)]
+    pub constraints: BTreeMap<String, String>,
+    /// Notable changes of the job, e.g., priority boosts, oldest first.
+    #[serde(default)]
+    pub events: Vec<JobEvent>,
+    /// Why the pending job cannot be started, e.g., because a job it
+    /// depends on has been canceled with `kueue remove-job --orphan`.
+    #[serde(default)]
+    pub pending_reason: Option<String>,
+    /// Checkpoint to resume the job from, passed to its processes as
+    /// `KUEUE_CHECKPOINT`. Set when the job has been migrated with a checkpoint.
+    #[serde(default)]
+    pub checkpoint: Option<PathBuf>,
+    /// Number of times the running job has been stopped and requeued, i.e.,
+    /// migrated or preempted.
+    #[serde(default)]
+    pub migrations: u32,
+    /// Quality of service class of the job, e.g., "urgent".
+    #[serde(default)]
+    pub qos: Option<String>,
+    /// Previous runs of the job that have been requeued, e.g., retried after a
+    /// non-zero exit code or a lost worker, oldest first.
+    #[serde(default)]
+    pub attempts: Vec<JobAttempt>,
+    /// Environment of the worker the job finished on, if the worker captures
+    /// it (`capture_environment`).
+    #[serde(default)]
+    pub environment: Option<ExecutionEnvironment>,
+    /// Worker the job should preferably run on, e.g., because it has warm
+    /// caches or staged inputs of a previous job.
+    #[serde(default)]
+    pub affinity: Option<WorkerAffinity>,
 }
 
+/// Environment of the user's shell recorded at submission time. It is not
+/// applied to the job, but helps to debug jobs that behave differently
+/// than in the user's shell.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EnvSnapshot {
+    /// Environment variables of the shell.
+    pub variables: BTreeMap<String, String>,
+    /// File mode creation mask, if available on the platform.
+    pub umask: Option<u32>,
+    /// Set if variables have been left out to keep the snapshot small.
+    pub truncated: bool,
+}
+
+/// Execution environment of a worker, recorded with finished jobs, so that
+/// results can be tied to the system and toolchain that produced them.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExecutionEnvironment {
+    /// Point in time when the environment has been captured.
+    pub captured: DateTime<Utc>,
+    /// Name and version of the OS distribution.
+    pub os: String,
+    pub kernel: String,
+    pub cpu_model: String,
+    /// Output of the worker's `environment_probe`, e.g., loaded modules and
+    /// compiler versions.
+    pub probe_output: Option<String>,
+}
+
+/// Record of a job's execution for reproducibility, signed by the worker with
+/// the shared secret, so that the record cannot be altered unnoticed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProvenanceRecord {
+    pub job_id: u64,
+    /// Name of the user who issued the job.
+    pub submitter: String,
+    /// SHA-256 hash of the executed command and steps, hex-encoded.
+    pub command_sha256: String,
+    pub cwd: PathBuf,
+    /// Name of the worker that executed the job.
+    pub worker: String,
+    /// Version of Kueue running on the worker.
+    pub kueue_version: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub exit_code: i32,
+    /// HMAC-SHA256 of all other fields, base64-encoded.
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl ProvenanceRecord {
+    /// Content covered by the signature.
+    fn signed_content(&self) -> Vec<u8> {
+        let unsigned = ProvenanceRecord {
+            signature: String::new(),
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+
+    /// Sign the record with the given secret.
+    pub fn sign(&mut self, secret: &str) {
+        self.signature = crypto::sign(secret, &self.signed_content());
+    }
+
+    /// Returns `true` if the record has been signed with the given secret
+    /// and has not been altered since.
+    pub fn verify(&self, secret: &str) -> bool {
+        crypto::verify(secret, &self.signed_content(), &self.signature)
+    }
+}
+
+/// Keeps track of generated job IDs.
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 /// Generate a unique job ID.
 fn next_job_id() -> u64 {
-    /// Keeps track of generated job IDs.
-    static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
     JOB_COUNTER.fetch_add(1, Ordering::Relaxed)
 }
 
+/// Reserve the ID of a job before it is issued, e.g., to bind the encrypted
+/// payload of the job to its ID.
+pub fn reserve_job_id() -> u64 {
+    next_job_id()
+}
+
+/// Make sure that job IDs generated from now on are at least `job_id`,
+/// e.g., after restoring jobs from the server's database.
+pub fn reserve_job_ids(job_id: u64) {
+    JOB_COUNTER.fetch_max(job_id, Ordering::Relaxed);
+}
+
+/// Name of the user running this process, as given by the environment.
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
 impl JobInfo {
     /// Creates a new, pending job.
     pub fn new(
@@ -62,6 +290,43 @@ impl JobInfo {
             status: JobStatus::Pending { issued: Utc::now() },
             stdout_path,
             stderr_path,
+            setup_steps: Vec::new(),
+            teardown_steps: Vec::new(),
+            step_results: Vec::new(),
+            interactive: false,
+            user: current_user(),
+            cpu_time_limit_seconds: None,
+            wall_time_limit_seconds: None,
+            exit_code_policies: Vec::new(),
+            max_retries: 0,
+            retries: 0,
+            avoid_workers: BTreeSet::new(),
+            dependencies: Vec::new(),
+            resource_usage: None,
+            auto_resources: false,
+            suggested_resources: None,
+            pool: None,
+            group: None,
+            project: None,
+            priority: 0,
+            stalled: false,
+            env_snapshot: None,
+            encrypted_payload: None,
+            provenance: None,
+            gpus: 0,
+            gpu_mode: GpuMode::Exclusive,
+            core_binding: None,
+            create_cwd: false,
+            process_limits: ProcessLimits::default(),
+            constraints: BTreeMap::new(),
+            events: Vec::new(),
+            pending_reason: None,
+            checkpoint: None,
+            migrations: 0,
+            qos: None,
+            attempts: Vec::new(),
+            environment: None,
+            affinity: None,
         }
     }
 
@@ -81,10 +346,462 @@ impl JobInfo {
             status: JobStatus::Pending { issued: Utc::now() },
             stdout_path: job_info.stdout_path,
             stderr_path: job_info.stderr_path,
+            setup_steps: job_info.setup_steps,
+            teardown_steps: job_info.teardown_steps,
+            step_results: Vec::new(),
+            interactive: job_info.interactive,
+            user: job_info.user,
+            cpu_time_limit_seconds: job_info.cpu_time_limit_seconds,
+            wall_time_limit_seconds: job_info.wall_time_limit_seconds,
+            exit_code_policies: job_info.exit_code_policies,
+            max_retries: job_info.max_retries,
+            retries: 0,
+            avoid_workers: BTreeSet::new(),
+            dependencies: job_info.dependencies,
+            resource_usage: None,
+            auto_resources: job_info.auto_resources,
+            suggested_resources: None,
+            pool: job_info.pool,
+            group: job_info.group,
+            project: job_info.project,
+            priority: job_info.priority,
+            stalled: job_info.stalled,
+            env_snapshot: job_info.env_snapshot,
+            encrypted_payload: job_info.encrypted_payload,
+            provenance: None,
+            gpus: job_info.gpus,
+            gpu_mode: job_info.gpu_mode,
+            core_binding: job_info.core_binding,
+            create_cwd: job_info.create_cwd,
+            process_limits: job_info.process_limits,
+            constraints: job_info.constraints,
+            events: Vec::new(),
+            pending_reason: None,
+            checkpoint: None,
+            migrations: 0,
+            qos: job_info.qos,
+            attempts: Vec::new(),
+            environment: None,
+            affinity: job_info.affinity,
+        }
+    }
+
+    /// Copy of the job information without the environment snapshot, which
+    /// may hold secrets and is only sent to authenticated clients.
+    pub fn without_env_snapshot(&self) -> JobInfo {
+        JobInfo {
+            env_snapshot: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns `true` if the job consists of more than the main command.
+    pub fn has_steps(&self) -> bool {
+        !self.setup_steps.is_empty() || !self.teardown_steps.is_empty()
+    }
+
+    /// Returns the action to take when the job finishes with the given exit
+    /// code. Policies for a specific exit code take precedence over policies
+    /// for any non-zero exit code. Successful jobs are never retried.
+    pub fn exit_code_action(&self, exit_code: i32) -> Option<ExitCodeAction> {
+        if exit_code == 0 {
+            return None;
+        }
+        let find = |code: Option<i32>| {
+            self.exit_code_policies
+                .iter()
+                .find(|policy| policy.exit_code == code)
+                .map(|policy| policy.action)
+        };
+        find(Some(exit_code)).or_else(|| find(None))
+    }
+
+    /// Number of the current run of the job, starting at 1.
+    pub fn attempt(&self) -> usize {
+        self.attempts.len() + 1
+    }
+
+    /// Records the current run of the job before it is requeued. Running jobs
+    /// have been interrupted, e.g., because their worker has been lost.
+    pub fn record_attempt(&mut self) {
+        let attempt = match &self.status {
+            JobStatus::Running {
+                started, worker, ..
+            } => JobAttempt {
+                worker: worker.clone(),
+                started: *started,
+                finished: Utc::now(),
+                exit_code: None,
+                comment: "Interrupted, e.g., by the loss of the worker.".into(),
+            },
+            JobStatus::Finished {
+                started,
+                finished,
+                return_code,
+                worker,
+                comment,
+                ..
+            } => JobAttempt {
+                worker: worker.clone(),
+                started: *started,
+                finished: *finished,
+                exit_code: Some(*return_code),
+                comment: comment.clone(),
+            },
+            _ => return,
+        };
+        self.attempts.push(attempt);
+    }
+}
+
+/// Previous run of a job that has been requeued afterwards.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobAttempt {
+    /// Name of the worker the job ran on.
+    pub worker: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    /// Exit code of the job, or `None` if the run has been interrupted.
+    pub exit_code: Option<i32>,
+    pub comment: String,
+}
+
+/// Policy applied by the server when a job finishes with a certain exit code.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExitCodePolicy {
+    /// Exit code the policy applies to. `None` matches any non-zero exit code.
+    pub exit_code: Option<i32>,
+    /// Action to take when the exit code matches.
+    pub action: ExitCodeAction,
+}
+
+/// Resources actually used by a job, as measured by the worker.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    /// Average number of busy CPU cores.
+    pub avg_cpus: f64,
+    /// Peak memory usage in megabytes.
+    pub peak_ram_mb: u64,
+}
+
+impl ResourceUsage {
+    /// Combines the usage of two steps of a job by taking the maximum of both.
+    pub fn combine(self, other: ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            avg_cpus: self.avg_cpus.max(other.avg_cpus),
+            peak_ram_mb: self.peak_ram_mb.max(other.peak_ram_mb),
+        }
+    }
+}
+
+/// Validation report of a newly issued job, telling the user early on
+/// whether the job is plausible to run at all.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobReceipt {
+    /// ID assigned to the job.
+    pub job_id: u64,
+    /// Resources reserved for the job on the worker, as accepted by the server.
+    pub worker_resources: Resources,
+    /// Global resources required by the job.
+    pub global_resources: Option<BTreeMap<String, u64>>,
+    /// Queue the job has been assigned to, i.e., its resource pool.
+    pub queue: String,
+    /// Number of connected workers that are able to run the job at all.
+    pub candidate_workers: u64,
+    /// Number of candidate workers with enough free resources right now.
+    pub available_workers: u64,
+    /// Estimated time until the job starts, if there is enough history.
+    pub estimated_wait_seconds: Option<i64>,
+    /// Potential problems that could keep the job from running.
+    pub warnings: Vec<String>,
+}
+
+/// Dependency of a job on the outcome of another job.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobDependency {
+    /// ID of the job this job depends on.
+    pub job_id: u64,
+    /// Outcome of the other job required to start this job.
+    pub condition: DependencyCondition,
+}
+
+/// Worker a job prefers to run on. The preference is dropped if the worker is
+/// busy or offline, so that the job is not held up.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum WorkerAffinity {
+    /// The worker with the given name.
+    Worker(String),
+    /// The worker the job with the given ID runs or has run on.
+    WorkerOf(u64),
+}
+
+impl fmt::Display for WorkerAffinity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerAffinity::Worker(worker) => write!(f, "worker {}", worker),
+            WorkerAffinity::WorkerOf(job_id) => write!(f, "worker of job {}", job_id),
+        }
+    }
+}
+
+/// How a job uses the GPU devices assigned to it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum GpuMode {
+    /// Devices are not used by any other job.
+    #[default]
+    Exclusive,
+    /// Devices may be used by other jobs in shared mode at the same time,
+    /// e.g., with the Multi-Process Service (MPS).
+    Shared,
+    /// Slices of partitioned devices (Multi-Instance GPU) with the given
+    /// profile, e.g., "1g.5gb".
+    Mig(String),
+}
+
+impl FromStr for GpuMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "exclusive" => Ok(GpuMode::Exclusive),
+            "shared" => Ok(GpuMode::Shared),
+            _ => match text.strip_prefix("mig:") {
+                Some(profile) if !profile.is_empty() => Ok(GpuMode::Mig(profile.into())),
+                _ => Err(format!(
+                    "Invalid GPU mode '{}'! Expected 'exclusive', 'shared', or 'mig:<profile>'.",
+                    text
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for GpuMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuMode::Exclusive => write!(f, "exclusive"),
+            GpuMode::Shared => write!(f, "shared"),
+            GpuMode::Mig(profile) => write!(f, "mig:{}", profile),
+        }
+    }
+}
+
+/// Limits of the operating system (rlimits) applied to each process of a job,
+/// so that a single job cannot exhaust resources shared by all jobs of a worker.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ProcessLimits {
+    /// Maximum number of open file descriptors per process.
+    #[serde(default)]
+    pub nofile: Option<u64>,
+    /// Maximum number of processes. Note that the operating system counts all
+    /// processes of the user, not only those of the job.
+    #[serde(default)]
+    pub nproc: Option<u64>,
+    /// Maximum size of core dumps in bytes. Zero disables core dumps.
+    #[serde(default)]
+    pub core: Option<u64>,
+}
+
+impl ProcessLimits {
+    /// Fill limits that are not set with the given defaults.
+    pub fn or(self, defaults: ProcessLimits) -> ProcessLimits {
+        ProcessLimits {
+            nofile: self.nofile.or(defaults.nofile),
+            nproc: self.nproc.or(defaults.nproc),
+            core: self.core.or(defaults.core),
+        }
+    }
+}
+
+impl fmt::Display for ProcessLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits: Vec<String> = [
+            ("nofile", self.nofile),
+            ("nproc", self.nproc),
+            ("core", self.core),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{}={}", name, value?)))
+        .collect();
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+/// How a job is pinned to the physical cores of the worker. Either way, the
+/// job's cores are not shared with other bound jobs.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum CoreBinding {
+    /// Each requested CPU is a physical core with all its hardware threads.
+    PhysicalCores,
+    /// Each requested CPU is a physical core, of which the job uses a single
+    /// hardware thread. The other threads of the core stay idle.
+    NoSmt,
+}
+
+impl fmt::Display for CoreBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreBinding::PhysicalCores => write!(f, "physical cores"),
+            CoreBinding::NoSmt => write!(f, "physical cores without SMT"),
+        }
+    }
+}
+
+/// Outcome of a job required by its dependents.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum DependencyCondition {
+    /// The job must have finished with exit code 0.
+    Success,
+    /// The job must have finished with a non-zero exit code.
+    Failure,
+    /// The job must have finished or been canceled.
+    Any,
+}
+
+impl DependencyCondition {
+    /// Returns whether the condition is met by a job with the given status,
+    /// or `None` if the job has not concluded yet.
+    pub fn is_met(&self, status: &JobStatus) -> Option<bool> {
+        match self {
+            _ if !status.is_finished() && !status.is_canceled() => None,
+            DependencyCondition::Success => Some(status.has_succeeded()),
+            DependencyCondition::Failure => Some(status.has_failed()),
+            DependencyCondition::Any => Some(true),
+        }
+    }
+}
+
+/// Handling of the pending jobs depending on a removed job.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum DependentsAction {
+    /// Cancel the jobs whose dependencies can no longer be met.
+    #[default]
+    Resolve,
+    /// Cancel all downstream jobs, whatever their dependency conditions.
+    Cascade,
+    /// Leave all downstream jobs pending, so that they can be inspected or
+    /// removed later on.
+    Orphan,
+}
+
+/// Output stream of a job, used to index per-stream data.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum OutputStream {
+    Stdout = 0,
+    Stderr = 1,
+}
+
+impl fmt::Display for DependencyCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyCondition::Success => write!(f, "after-ok"),
+            DependencyCondition::Failure => write!(f, "after-failed"),
+            DependencyCondition::Any => write!(f, "after-any"),
+        }
+    }
+}
+
+/// Action to take when a job finishes with a certain exit code.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ExitCodeAction {
+    /// Requeue the job to run again on any worker.
+    Retry,
+    /// Requeue the job to run again on a different worker.
+    RetryElsewhere,
+    /// Do not retry the job.
+    Fail,
+}
+
+/// Exit code of jobs that could not be started.
+pub const EXIT_CODE_SPAWN_FAILURE: i32 = -43;
+/// Exit code of jobs terminated by a signal, e.g., killed by the system when
+/// running out of memory.
+pub const EXIT_CODE_KILLED_BY_SIGNAL: i32 = -44;
+/// Exit code of jobs whose process could not be waited for.
+pub const EXIT_CODE_WAIT_FAILURE: i32 = -45;
+/// Exit code of jobs killed on request.
+pub const EXIT_CODE_KILLED: i32 = -46;
+/// Exit code of jobs whose worker has been lost or removed.
+pub const EXIT_CODE_WORKER_LOST: i32 = -47;
+/// Exit code of jobs that exceeded their CPU time limit.
+pub const EXIT_CODE_CPU_TIME_EXCEEDED: i32 = -48;
+/// Exit code of stalled jobs killed by the server.
+pub const EXIT_CODE_STALLED: i32 = -49;
+/// Exit code of jobs that exceeded their wall-time limit.
+pub const EXIT_CODE_WALL_TIME_EXCEEDED: i32 = -50;
+/// Exit code of jobs stopped on request, e.g., to be migrated.
+pub const EXIT_CODE_STOPPED: i32 = -51;
+
+/// Reason a job failed, as classified by the worker.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum FailureKind {
+    /// The command could not be started, e.g., because it does not exist.
+    SpawnFailure,
+    /// The job has been killed by the system, most likely because it ran
+    /// out of memory.
+    OutOfMemory,
+    /// The job exceeded its time limit.
+    Timeout,
+    /// The job has been killed on request, e.g., by `kueue remove-job --kill`.
+    KilledByUser,
+    /// The command exited with a nonzero exit code or has been terminated
+    /// by a signal.
+    NonzeroExit,
+    /// The worker running the job has been lost or removed.
+    WorkerLost,
+    /// The job has been killed, because it neither consumed CPU time nor
+    /// produced output for a long time.
+    Stalled,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::SpawnFailure => write!(f, "spawn failure"),
+            FailureKind::OutOfMemory => write!(f, "out of memory"),
+            FailureKind::Timeout => write!(f, "timeout"),
+            FailureKind::KilledByUser => write!(f, "killed by user"),
+            FailureKind::NonzeroExit => write!(f, "nonzero exit"),
+            FailureKind::WorkerLost => write!(f, "worker lost"),
+            FailureKind::Stalled => write!(f, "stalled"),
         }
     }
 }
 
+/// Outcome of a single step of a job.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StepResult {
+    /// Name of the step, e.g., "setup 1", "run", or "teardown 1".
+    pub name: String,
+    /// Command executed in this step.
+    pub cmd: Vec<String>,
+    /// Exit code of the step or `None` if the step has been skipped.
+    pub exit_code: Option<i32>,
+    /// Run time of the step in seconds.
+    pub run_time_seconds: i64,
+}
+
+/// Request to stop a running job cleanly and to move it to another worker.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobMigration {
+    /// Signal sent to the job's process, asking it to write a checkpoint and
+    /// to exit, e.g., 15 for SIGTERM.
+    pub signal: i32,
+    /// Seconds the job may take to exit after the signal, before it is killed.
+    pub grace_period_seconds: u64,
+    /// Checkpoint written by the job, to resume from on the next worker.
+    /// Without a checkpoint, the job is run again from the start.
+    pub checkpoint: Option<PathBuf>,
+}
+
+/// Entry of a job's event timeline, shown by `kueue show-job`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobEvent {
+    pub time: DateTime<Utc>,
+    /// What happened, e.g., "Priority boosted from 0 to 5 by alice."
+    pub text: String,
+}
+
 /// Represents a combination of resources, either
 /// available on a worker or required by a job.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -95,6 +812,9 @@ pub struct Resources {
     pub cpus: u64,
     /// RAM (in megabytes), available on worker or required to run the command.
     pub ram_mb: u64,
+    /// Named resources configured per worker, e.g., licenses or scratch disk.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub named: BTreeMap<String, u64>,
 }
 
 impl Resources {
@@ -104,6 +824,7 @@ impl Resources {
             job_slots,
             cpus,
             ram_mb,
+            named: BTreeMap::new(),
         }
     }
 
@@ -113,6 +834,32 @@ impl Resources {
         (self.job_slots <= required.job_slots)
             && (self.cpus <= required.cpus)
             && (self.ram_mb <= required.ram_mb)
+            && self
+                .named
+                .iter()
+                .all(|(name, amount)| *amount <= required.named.get(name).copied().unwrap_or(0))
+    }
+
+    /// Adds the `released` resources.
+    pub fn add(&mut self, released: &Resources) {
+        self.job_slots += released.job_slots;
+        self.cpus += released.cpus;
+        self.ram_mb += released.ram_mb;
+        for (name, amount) in &released.named {
+            *self.named.entry(name.clone()).or_default() += amount;
+        }
+    }
+
+    /// Subtracts the `used` resources, stopping at zero.
+    pub fn subtract(&mut self, used: &Resources) {
+        self.job_slots = self.job_slots.saturating_sub(used.job_slots);
+        self.cpus = self.cpus.saturating_sub(used.cpus);
+        self.ram_mb = self.ram_mb.saturating_sub(used.ram_mb);
+        for (name, amount) in &used.named {
+            if let Some(available) = self.named.get_mut(name) {
+                *available = available.saturating_sub(*amount);
+            }
+        }
     }
 }
 
@@ -159,6 +906,9 @@ pub enum JobStatus {
         /// Additional information about the execution of the job. This
         /// comment might include helpful information to debug failed jobs.
         comment: String,
+        /// Reason the job failed, if it did not succeed.
+        #[serde(default)]
+        failure: Option<FailureKind>,
     },
     /// The job has been canceled.
     Canceled {
@@ -166,6 +916,9 @@ pub enum JobStatus {
         issued: DateTime<Utc>,
         /// Point in time when the job has been canceled on the server.
         canceled: DateTime<Utc>,
+        /// Why the job has been canceled, e.g., as given by its owner.
+        #[serde(default)]
+        reason: Option<String>,
     },
 }
 
@@ -204,6 +957,25 @@ impl JobStatus {
     pub fn is_canceled(&self) -> bool {
         matches!(self, Self::Canceled { .. })
     }
+
+    /// Point in time when the job has been offered to a worker, if it is in
+    /// "offered" state. Identifies the offer when the worker answers it.
+    pub fn offered(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Offered { offered, .. } => Some(*offered),
+            _ => None,
+        }
+    }
+
+    /// Name of the worker the job has been offered to, runs, or has run on.
+    pub fn worker(&self) -> Option<&str> {
+        match self {
+            Self::Offered { worker, .. }
+            | Self::Running { worker, .. }
+            | Self::Finished { worker, .. } => Some(worker),
+            _ => None,
+        }
+    }
 }
 
 /// Stores all information about a worker.
@@ -213,8 +985,32 @@ pub struct WorkerInfo {
     pub worker_id: u64,
     /// Name of the worker, usually including host name.
     pub worker_name: String,
+    /// Version of Kueue running on the worker. Empty for older workers.
+    #[serde(default)]
+    pub kueue_version: String,
+    /// Optional protocol features supported by the worker.
+    #[serde(default)]
+    pub capabilities: BTreeSet<String>,
+    /// Tags describing the worker, as configured on the worker.
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Labels of the worker, e.g., `arch=avx512`, matched against the
+    /// constraints of jobs.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Set by the server if the worker did not send updates for longer than
+    /// the server's `worker_timeout_seconds`.
+    #[serde(default)]
+    pub offline: bool,
+    /// Set by the server to the point in time the worker became unreachable,
+    /// i.e., its connection has been lost or it stopped sending updates.
+    #[serde(default)]
+    pub unreachable_since: Option<DateTime<Utc>>,
     /// Point in time the worker connected to the server.
     pub connected_since: DateTime<Utc>,
+    /// Network address the worker connected from, as seen by the server.
+    #[serde(default)]
+    pub remote_address: Option<String>,
     /// System/hardware information about the worker.
     pub system_info: SystemInfo,
     /// Last point in time the worker sent an system update message.
@@ -225,6 +1021,29 @@ pub struct WorkerInfo {
     pub jobs_running: BTreeSet<u64>,
     /// Free resources, available for job assignments.
     pub free_resources: Resources,
+    /// Free resources of each resource pool on the worker.
+    #[serde(default)]
+    pub pool_resources: BTreeMap<String, Resources>,
+    /// Results of the worker's self-test. Empty, if no self-test was run.
+    #[serde(default)]
+    pub self_test: Vec<ProbeResult>,
+    /// Set by administrators to let running jobs finish without offering
+    /// new jobs to the worker, e.g., before maintenance.
+    #[serde(default)]
+    pub draining: bool,
+    /// Set by administrators to offer the worker only jobs of this user.
+    #[serde(default)]
+    pub reserved_for: Option<String>,
+}
+
+/// Outcome of a single self-test probe on a worker.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProbeResult {
+    /// Name of the probe, as configured on the worker.
+    pub name: String,
+    pub passed: bool,
+    /// Last line of the probe's output, or the reason it failed to run.
+    pub output: String,
 }
 
 /// Generate a unique worker ID.
@@ -236,19 +1055,48 @@ fn next_worker_id() -> u64 {
 
 impl WorkerInfo {
     /// Create a new worker.
-    pub fn new(worker_name: String) -> Self {
+    pub fn new(worker_name: String, kueue_version: String) -> Self {
         WorkerInfo {
             worker_id: next_worker_id(),
             worker_name,
+            kueue_version,
+            capabilities: BTreeSet::new(),
+            tags: BTreeSet::new(),
+            labels: BTreeMap::new(),
+            offline: false,
+            unreachable_since: None,
             connected_since: Utc::now(),
+            remote_address: None,
             system_info: SystemInfo::default(),
             last_updated: Utc::now(),
             jobs_offered: BTreeSet::new(),
             jobs_running: BTreeSet::new(),
             free_resources: Resources::new(0, 0, 0),
+            pool_resources: BTreeMap::new(),
+            self_test: Vec::new(),
+            draining: false,
+            reserved_for: None,
         }
     }
 
+    /// Returns `true` if any probe of the worker's self-test failed.
+    pub fn self_test_failed(&self) -> bool {
+        self.self_test.iter().any(|probe| !probe.passed)
+    }
+
+    /// Returns `true` if the worker has all labels with the values required
+    /// by the job.
+    pub fn satisfies(&self, constraints: &BTreeMap<String, String>) -> bool {
+        constraints
+            .iter()
+            .all(|(label, value)| self.labels.get(label) == Some(value))
+    }
+
+    /// Returns `true` if the worker announced the given capability.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+
     /// Total number of job offered or running on the worker.
     pub fn jobs_total(&self) -> usize {
         self.jobs_offered.len() + self.jobs_running.len()
@@ -293,10 +1141,27 @@ pub struct SystemInfo {
     pub distribution: String,
     /// Number of CPU cores.
     pub cpu_cores: u64,
+    /// Number of physical CPU cores. Logical cores of the same physical core
+    /// (SMT) are only counted once. Zero, if unknown.
+    #[serde(default)]
+    pub physical_cores: u64,
+    /// Number of performance cores among the physical cores.
+    #[serde(default)]
+    pub performance_cores: u64,
+    /// Number of efficiency cores among the physical cores. Zero, unless the
+    /// processor is hybrid.
+    #[serde(default)]
+    pub efficiency_cores: u64,
     /// Average CPU frequency across cores.
     pub cpu_frequency: u64,
     /// Total amount of system memory (RAM) in megabytes.
     pub total_ram_mb: u64,
+    /// Maximum number of job slots on the worker. Zero, if unknown.
+    #[serde(default)]
+    pub max_job_slots: u64,
+    /// Named resources configured on the worker, e.g., licenses.
+    #[serde(default)]
+    pub resources: BTreeMap<String, u64>,
     /// System load information.
     pub load_info: LoadInfo,
 }
@@ -307,8 +1172,13 @@ impl Default for SystemInfo {
             kernel: "unknown".into(),
             distribution: "unknown".into(),
             cpu_cores: 0,
+            physical_cores: 0,
+            performance_cores: 0,
+            efficiency_cores: 0,
             cpu_frequency: 0,
             total_ram_mb: 0,
+            max_job_slots: 0,
+            resources: BTreeMap::new(),
             load_info: LoadInfo::default(),
         }
     }
@@ -324,3 +1194,180 @@ pub struct LoadInfo {
     /// Fifteen-minute average load.
     pub fifteen: f64,
 }
+
+/// Kind of peer on the other end of a connection to the server.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ConnectionKind {
+    Client,
+    Worker,
+}
+
+impl fmt::Display for ConnectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionKind::Client => write!(f, "client"),
+            ConnectionKind::Worker => write!(f, "worker"),
+        }
+    }
+}
+
+/// Administrative action requested from the server, as recorded in the audit log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// Point in time the action has been requested.
+    pub time: DateTime<Utc>,
+    /// Network address of the client, as seen by the server.
+    pub remote_address: String,
+    /// Description of the action, e.g., "drain worker 3".
+    pub action: String,
+    /// Whether the client was authorized to perform the action.
+    pub authorized: bool,
+}
+
+/// Metadata about a live connection to the server, for debugging.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConnectionInfo {
+    /// Unique connection ID, assigned by the server.
+    pub connection_id: u64,
+    pub kind: ConnectionKind,
+    /// Name of the worker. Clients do not identify by name.
+    pub name: Option<String>,
+    /// Network address of the peer, as seen by the server.
+    pub remote_address: String,
+    /// Point in time the connection has been established.
+    pub connected_since: DateTime<Utc>,
+    /// Version of Kueue running on the peer.
+    pub kueue_version: String,
+    /// Bytes sent by the server over the connection.
+    pub bytes_sent: u64,
+    /// Bytes received by the server over the connection.
+    pub bytes_received: u64,
+}
+
+/// Outcome of evaluating a job for a worker, as recorded by the scheduling trace.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum SchedulingOutcome {
+    /// The job did not fit the worker and has not been offered.
+    Skipped,
+    Offered,
+    Accepted,
+    Deferred,
+    Rejected,
+    /// The unanswered offer has been withdrawn in favor of a better placement.
+    Withdrawn,
+}
+
+impl fmt::Display for SchedulingOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulingOutcome::Skipped => write!(f, "skipped"),
+            SchedulingOutcome::Offered => write!(f, "offered"),
+            SchedulingOutcome::Accepted => write!(f, "accepted"),
+            SchedulingOutcome::Deferred => write!(f, "deferred"),
+            SchedulingOutcome::Rejected => write!(f, "rejected"),
+            SchedulingOutcome::Withdrawn => write!(f, "withdrawn"),
+        }
+    }
+}
+
+/// Single scheduling decision about a job, recorded by the scheduling trace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SchedulingEvent {
+    pub time: DateTime<Utc>,
+    /// Name of the worker the job has been evaluated for.
+    pub worker_name: String,
+    pub outcome: SchedulingOutcome,
+    /// Why the job has been skipped. Empty for other outcomes.
+    pub reason: String,
+}
+
+/// Usage of the cluster by the jobs of a project, as reported by
+/// `kueue stats`. Finished jobs are accounted for, even after they have been
+/// cleaned up on the server.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ProjectUsage {
+    /// Name of the project. `None` for jobs without project.
+    pub project: Option<String>,
+    pub jobs_pending: u64,
+    pub jobs_running: u64,
+    pub jobs_succeeded: u64,
+    pub jobs_failed: u64,
+    /// CPU cores reserved by finished jobs, multiplied by their run time.
+    pub cpu_hours: f64,
+    /// Upper limit of concurrently running jobs of the project, if any.
+    pub max_running_jobs: Option<u64>,
+    /// CPU hours granted to the project, if limited.
+    pub cpu_hours_quota: Option<f64>,
+}
+
+/// Usage of the cluster by a user's finished jobs, as reported by
+/// `kueue acct`. Summed up from the accounting in the server's database.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UserUsage {
+    pub user: String,
+    pub jobs_succeeded: u64,
+    pub jobs_failed: u64,
+    /// CPU cores reserved by the jobs, multiplied by their run time.
+    pub core_hours: f64,
+    /// Start of the user's first job within the queried time range.
+    pub first_started: DateTime<Utc>,
+    /// End of the user's last job within the queried time range.
+    pub last_finished: DateTime<Utc>,
+}
+
+/// Named job defaults published by the server, e.g., the agreed resources for
+/// a common tool. Used with `kueue cmd --template`; options given by the user
+/// take precedence over the template.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct JobTemplate {
+    /// Name of the template, e.g., "vivado".
+    pub name: String,
+    /// Short description shown by `kueue templates list`.
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub job_slots: Option<u64>,
+    #[serde(default)]
+    pub cpus: Option<u64>,
+    #[serde(default)]
+    pub ram_mb: Option<u64>,
+    /// Global resources, such as licenses.
+    #[serde(default)]
+    pub global_resources: BTreeMap<String, u64>,
+    /// Resource pool of a worker to run the job in.
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// Labels a worker must have, like `--require`.
+    #[serde(default)]
+    pub constraints: BTreeMap<String, String>,
+    /// Wall-time limit in seconds, like `--timeout`.
+    #[serde(default)]
+    pub wall_time_limit_seconds: Option<u64>,
+}
+
+/// Resources of a worker that sat idle during an hour while jobs were pending
+/// that did not fit into them. Shown by `kueue stats --idle`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct IdleResources {
+    pub worker_name: String,
+    /// Start of the hour.
+    pub hour: DateTime<Utc>,
+    /// Time the worker sat idle during the hour.
+    pub idle_hours: f64,
+    /// Idle CPU cores, multiplied by the time they sat idle.
+    pub cpu_hours: f64,
+    /// Idle RAM in gigabytes, multiplied by the time it sat idle.
+    pub ram_gb_hours: f64,
+    /// Time each resource, e.g., "RAM", kept the pending jobs from fitting.
+    pub limited_by: BTreeMap<String, f64>,
+}
+
+impl IdleResources {
+    /// The resource that most often kept the pending jobs from fitting.
+    pub fn limiting_resource(&self) -> Option<&str> {
+        self.limited_by
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(resource, _)| resource.as_str())
+    }
+}
@@ -0,0 +1,241 @@
+//! Utilities for testing protocol interactions without network sockets.
+//!
+//! All connections are in-memory duplex streams. A [`FakeServer`] plays the
+//! server for a client or worker under test and answers messages as scripted
+//! by the test. A [`FakeWorker`] connects to a real server, e.g., a
+//! [`TestServer`](crate::server::TestServer), and lets the test drive the
+//! worker's side of the protocol.
+
+use crate::{
+    config::Config,
+    messages::{
+        stream::{MessageError, MessageStream},
+        version::{capabilities, KUEUE_VERSION},
+        ClientToServerMessage, HelloMessage, ServerToClientMessage, ServerToWorkerMessage,
+        WorkerToServerMessage,
+    },
+};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
+use tokio::io::{duplex, DuplexStream};
+
+/// Message stream over one end of an in-memory connection.
+pub type TestStream = MessageStream<DuplexStream>;
+
+/// Bytes buffered in each direction of an in-memory connection.
+const DUPLEX_BUFFER_LEN: usize = 64 * 1024;
+
+/// Create two message streams that are connected to each other.
+pub fn message_stream_pair() -> (TestStream, TestStream) {
+    let (a, b) = duplex(DUPLEX_BUFFER_LEN);
+    (MessageStream::new(a), MessageStream::new(b))
+}
+
+/// Expected answer to an authentication challenge.
+fn auth_response(shared_secret: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((shared_secret.to_string() + salt).into_bytes());
+    general_purpose::STANDARD_NO_PAD.encode(hasher.finalize())
+}
+
+/// Plays the server for a client or worker under test.
+pub struct FakeServer {
+    stream: TestStream,
+    shared_secret: String,
+    salt: String,
+}
+
+impl FakeServer {
+    /// Create the fake server and the stream to pass to the client or worker
+    /// under test. Authentication uses the shared secret of the config.
+    pub fn new(config: &Config) -> (Self, DuplexStream) {
+        let (server, remote) = duplex(DUPLEX_BUFFER_LEN);
+        let fake = FakeServer {
+            stream: MessageStream::new(server),
+            shared_secret: config.common_settings.shared_secret.clone(),
+            salt: "fake-server-salt".into(),
+        };
+        (fake, remote)
+    }
+
+    /// Receive the next message.
+    pub async fn receive<T: for<'a> Deserialize<'a> + Debug>(&mut self) -> Result<T> {
+        Ok(self.stream.receive::<T>().await?)
+    }
+
+    /// Send a message.
+    pub async fn send<T: Serialize + Debug>(&mut self, message: &T) -> Result<()> {
+        Ok(self.stream.send(message).await?)
+    }
+
+    /// Welcome a client. Returns the capabilities of the client.
+    pub async fn accept_client(&mut self) -> Result<BTreeSet<String>> {
+        match self.receive::<HelloMessage>().await? {
+            HelloMessage::HelloFromClient {
+                capabilities: client_capabilities,
+                ..
+            } => {
+                let welcome = ServerToClientMessage::WelcomeClient {
+                    kueue_version: KUEUE_VERSION.into(),
+                    capabilities: capabilities(),
+                };
+                self.send(&welcome).await?;
+                self.stream.negotiate_format(&client_capabilities);
+                Ok(client_capabilities)
+            }
+            other => bail!("Expected HelloFromClient, received: {:?}", other),
+        }
+    }
+
+    /// Answer the authentication request of a client. Returns whether the
+    /// client knew the shared secret.
+    pub async fn authenticate_client(&mut self) -> Result<bool> {
+        match self.receive::<ClientToServerMessage>().await? {
+            ClientToServerMessage::AuthRequest => {}
+            other => bail!("Expected AuthRequest, received: {:?}", other),
+        }
+        let challenge = ServerToClientMessage::AuthChallenge {
+            salt: self.salt.clone(),
+        };
+        self.send(&challenge).await?;
+        let accepted = match self.receive::<ClientToServerMessage>().await? {
+            ClientToServerMessage::AuthResponse(response) => {
+                response == auth_response(&self.shared_secret, &self.salt)
+            }
+            other => bail!("Expected AuthResponse, received: {:?}", other),
+        };
+        self.send(&ServerToClientMessage::AuthAccepted(accepted))
+            .await?;
+        Ok(accepted)
+    }
+
+    /// Welcome and authenticate a worker. Returns the name of the worker.
+    pub async fn accept_worker(&mut self) -> Result<String> {
+        let (worker_name, worker_capabilities) = match self.receive::<HelloMessage>().await? {
+            HelloMessage::HelloFromWorker {
+                worker_name,
+                capabilities,
+                ..
+            } => (worker_name, capabilities),
+            other => bail!("Expected HelloFromWorker, received: {:?}", other),
+        };
+        let welcome = ServerToWorkerMessage::WelcomeWorker {
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+        };
+        self.send(&welcome).await?;
+        self.stream.negotiate_format(&worker_capabilities);
+        let challenge = ServerToWorkerMessage::AuthChallenge {
+            salt: self.salt.clone(),
+        };
+        self.send(&challenge).await?;
+        let accepted = match self.receive::<WorkerToServerMessage>().await? {
+            WorkerToServerMessage::AuthResponse(response) => {
+                response == auth_response(&self.shared_secret, &self.salt)
+            }
+            other => bail!("Expected AuthResponse, received: {:?}", other),
+        };
+        self.send(&ServerToWorkerMessage::AuthAccepted(accepted))
+            .await?;
+        if !accepted {
+            bail!("Worker '{}' failed to authenticate!", worker_name);
+        }
+        Ok(worker_name)
+    }
+
+    /// Answer each received message with the messages returned by `script`,
+    /// until the other side closes the connection. Use `ClientToServerMessage`
+    /// and `ServerToClientMessage` to serve a client, or the worker messages
+    /// to serve a worker.
+    pub async fn serve<In, Out>(&mut self, mut script: impl FnMut(In) -> Vec<Out>) -> Result<()>
+    where
+        In: for<'a> Deserialize<'a> + Debug,
+        Out: Serialize + Debug,
+    {
+        loop {
+            let message = match self.stream.receive::<In>().await {
+                Ok(message) => message,
+                Err(MessageError::StreamClosed) => return Ok(()),
+                Err(e) => return Err(anyhow!(e)),
+            };
+            for response in script(message) {
+                self.send(&response).await?;
+            }
+        }
+    }
+}
+
+/// Drives the worker's side of the protocol against a server under test.
+pub struct FakeWorker {
+    name: String,
+    config: Config,
+    stream: TestStream,
+}
+
+impl FakeWorker {
+    /// Create the fake worker and the stream to pass to the server under
+    /// test, e.g., with [`TestServer::connect`](crate::server::TestServer::connect).
+    pub fn new(name: &str, config: Config) -> (Self, DuplexStream) {
+        let (worker, remote) = duplex(DUPLEX_BUFFER_LEN);
+        let fake = FakeWorker {
+            name: name.into(),
+            config,
+            stream: MessageStream::new(worker),
+        };
+        (fake, remote)
+    }
+
+    /// Receive the next message.
+    pub async fn receive(&mut self) -> Result<ServerToWorkerMessage> {
+        Ok(self.stream.receive::<ServerToWorkerMessage>().await?)
+    }
+
+    /// Send a message.
+    pub async fn send(&mut self, message: &WorkerToServerMessage) -> Result<()> {
+        Ok(self.stream.send(message).await?)
+    }
+
+    /// Perform the hello/welcome handshake and authenticate with the shared
+    /// secret of the config.
+    pub async fn connect(&mut self) -> Result<()> {
+        let hello = HelloMessage::HelloFromWorker {
+            worker_name: self.name.clone(),
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tags: BTreeSet::new(),
+            labels: BTreeMap::new(),
+            tenant: None,
+        };
+        self.stream.send(&hello).await?;
+        match self.receive().await? {
+            ServerToWorkerMessage::WelcomeWorker { capabilities, .. } => {
+                self.stream.negotiate_format(&capabilities);
+            }
+            other => bail!("Expected WelcomeWorker, received: {:?}", other),
+        }
+        match self.receive().await? {
+            ServerToWorkerMessage::AuthChallenge { salt } => {
+                let response = auth_response(&self.config.common_settings.shared_secret, &salt);
+                self.send(&WorkerToServerMessage::AuthResponse(response))
+                    .await?;
+            }
+            other => bail!("Expected AuthChallenge, received: {:?}", other),
+        }
+        match self.receive().await? {
+            ServerToWorkerMessage::AuthAccepted(true) => Ok(()),
+            ServerToWorkerMessage::AuthAccepted(false) => bail!("Authentication failed!"),
+            other => bail!("Expected AuthAccepted, received: {:?}", other),
+        }
+    }
+
+    /// Close the connection gracefully.
+    pub async fn disconnect(mut self) -> Result<()> {
+        self.send(&WorkerToServerMessage::Bye).await
+    }
+}
@@ -0,0 +1,132 @@
+//! Optional TLS encryption of the connections to the server. Without TLS,
+//! all messages, including job outputs, are sent over plain TCP.
+
+use crate::config::{Config, ServerSettings};
+use anyhow::{anyhow, bail, Result};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client,
+    rustls::{
+        Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig,
+        ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use tokio_util::either::Either;
+
+/// Connection of a client or worker to the server, encrypted if `tls` is
+/// enabled in the config.
+pub type ServerStream = Either<TcpStream, client::TlsStream<TcpStream>>;
+
+/// Connect to the server, using TLS if enabled in the common settings.
+pub async fn connect(config: &Config) -> Result<ServerStream> {
+    let stream = config.connect_to_server().await?;
+    if !config.common_settings.tls {
+        return Ok(Either::Left(stream));
+    }
+
+    let mut roots = RootCertStore::empty();
+    match &config.common_settings.tls_ca_certificate {
+        Some(path) => {
+            for certificate in read_certificates(path)? {
+                roots.add(&certificate).map_err(|e| {
+                    anyhow!("Invalid certificate in {}: {}", path.to_string_lossy(), e)
+                })?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        })),
+    }
+    let tls_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let server_name = config.common_settings.server_name.trim();
+    let server_name = server_name.trim_start_matches('[').trim_end_matches(']');
+    let server_name = ServerName::try_from(server_name)
+        .map_err(|_| anyhow!("Invalid server name for TLS: {}", server_name))?;
+    let stream = TlsConnector::from(Arc::new(tls_config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| anyhow!("TLS handshake with server failed: {}", e))?;
+    Ok(Either::Right(stream))
+}
+
+/// Describe the server's end of the connection, e.g., "10.0.0.1:11236 (TLS)".
+pub fn describe_peer(stream: &ServerStream) -> String {
+    let (address, encryption) = match stream {
+        Either::Left(stream) => (stream.peer_addr(), "plain TCP"),
+        Either::Right(stream) => (stream.get_ref().0.peer_addr(), "TLS"),
+    };
+    match address {
+        Ok(address) => format!("{} ({})", address, encryption),
+        Err(_) => format!("the server ({})", encryption),
+    }
+}
+
+/// Create the TLS acceptor of the server, if a certificate is configured.
+pub fn acceptor(settings: &ServerSettings) -> Result<Option<TlsAcceptor>> {
+    let (certificate, private_key) = match (&settings.tls_certificate, &settings.tls_private_key) {
+        (Some(certificate), Some(private_key)) => (certificate, private_key),
+        (None, None) => return Ok(None),
+        _ => bail!("Both tls_certificate and tls_private_key are required for TLS!"),
+    };
+    let tls_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            read_certificates(certificate)?,
+            read_private_key(private_key)?,
+        )
+        .map_err(|e| anyhow!("Invalid TLS certificate or private key: {}", e))?;
+    Ok(Some(TlsAcceptor::from(Arc::new(tls_config))))
+}
+
+/// Read all certificates of a PEM file.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path)
+        .map_err(|e| anyhow!("Failed to open {}: {}", path.to_string_lossy(), e))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))?;
+    if certificates.is_empty() {
+        bail!("No certificates found in {}", path.to_string_lossy());
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+/// Read the first private key of a PEM file.
+fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path)
+        .map_err(|e| anyhow!("Failed to open {}: {}", path.to_string_lossy(), e))?;
+    let mut reader = BufReader::new(file);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    bail!("No private key found in {}", path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tls_acceptor() {
+        let mut config = Config::new(None).unwrap();
+        assert!(acceptor(&config.server_settings).unwrap().is_none());
+
+        // A certificate without private key is a configuration error.
+        config.server_settings.tls_certificate = Some("cert.pem".into());
+        assert!(acceptor(&config.server_settings).is_err());
+    }
+}
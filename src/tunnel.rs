@@ -0,0 +1,99 @@
+//! Relay of TCP connections through Kueue messages. Tunnels are used to
+//! forward ports opened by running jobs to the client, since workers usually
+//! cannot be reached directly from the user's machine.
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::{Receiver, Sender},
+};
+
+/// Size of the buffer used to read from the TCP stream.
+const READ_BUFFER_LEN: usize = 16 * 1024;
+
+/// Relay data between a TCP stream and messages of type `M`. Data read from
+/// the stream is wrapped with `data_message` and sent to `message_tx`. Data
+/// received on `data_rx` is written to the stream. If the stream is closed
+/// locally, `close_message` is sent. The relay ends when either side closes.
+pub async fn relay<M>(
+    mut stream: TcpStream,
+    tunnel_id: u64,
+    mut data_rx: Receiver<Vec<u8>>,
+    message_tx: Sender<M>,
+    data_message: fn(u64, Vec<u8>) -> M,
+    close_message: fn(u64) -> M,
+) {
+    let mut buffer = vec![0u8; READ_BUFFER_LEN];
+    loop {
+        tokio::select! {
+            bytes_read = stream.read(&mut buffer) => match bytes_read {
+                Ok(bytes_read) if bytes_read > 0 => {
+                    let message = data_message(tunnel_id, buffer[..bytes_read].to_vec());
+                    if message_tx.send(message).await.is_err() {
+                        return; // other side is gone
+                    }
+                }
+                _ => break, // closed locally
+            },
+            data = data_rx.recv() => match data {
+                Some(data) => {
+                    if stream.write_all(&data).await.is_err() {
+                        break; // closed locally
+                    }
+                }
+                None => return, // closed remotely
+            },
+        }
+    }
+
+    log::trace!("Tunnel {} closed.", tunnel_id);
+    let _ = message_tx.send(close_message(tunnel_id)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{net::TcpListener, sync::mpsc::channel};
+
+    #[derive(Debug, PartialEq)]
+    enum TestMessage {
+        Data(u64, Vec<u8>),
+        Close(u64),
+    }
+
+    #[tokio::test]
+    async fn relay_both_directions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut remote = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local, _) = listener.accept().await.unwrap();
+
+        let (data_tx, data_rx) = channel(8);
+        let (message_tx, mut message_rx) = channel(8);
+        let relay = tokio::spawn(relay(
+            local,
+            3,
+            data_rx,
+            message_tx,
+            TestMessage::Data,
+            TestMessage::Close,
+        ));
+
+        // Data from the channel is written to the stream.
+        data_tx.send(b"ping".to_vec()).await.unwrap();
+        let mut buffer = [0u8; 4];
+        remote.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        // Data from the stream is wrapped into messages.
+        remote.write_all(b"pong").await.unwrap();
+        let message = message_rx.recv().await.unwrap();
+        assert_eq!(message, TestMessage::Data(3, b"pong".to_vec()));
+
+        // Closing the stream ends the relay with a close message.
+        drop(remote);
+        assert_eq!(message_rx.recv().await.unwrap(), TestMessage::Close(3));
+        relay.await.unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Resource limits imposed on the worker by control groups (cgroups), e.g.,
+//! when running inside a container. System information reports the host's
+//! totals, which might be more than the worker is allowed to use.
+
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Mount point of the cgroup file system.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Effective resource limits of the worker process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Number of CPU cores the worker may use, considering CPU affinity
+    /// and CPU quotas. `None`, if not limited or unknown.
+    pub cpus: Option<u64>,
+    /// Memory limit in megabytes. `None`, if not limited or unknown.
+    pub ram_mb: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Detect the limits of the current process.
+    pub fn detect() -> Self {
+        ResourceLimits {
+            cpus: std::thread::available_parallelism()
+                .ok()
+                .map(|cpus| cpus.get() as u64),
+            ram_mb: memory_limit_bytes().map(|bytes| bytes / 1024 / 1024),
+        }
+    }
+}
+
+/// Memory currently used within the worker's cgroup in megabytes. `None`,
+/// if the worker does not run in a cgroup with a memory limit.
+pub fn memory_usage_mb() -> Option<u64> {
+    memory_limit_bytes()?;
+    cgroup_files("memory.current", "memory.usage_in_bytes")
+        .into_iter()
+        .find_map(|path| read_to_string(path).ok())
+        .and_then(|text| parse_memory_value(&text))
+        .map(|bytes| bytes / 1024 / 1024)
+}
+
+/// Smallest memory limit of the worker's cgroup and its ancestors.
+fn memory_limit_bytes() -> Option<u64> {
+    cgroup_files("memory.max", "memory.limit_in_bytes")
+        .into_iter()
+        .filter_map(|path| read_to_string(path).ok())
+        .filter_map(|text| parse_memory_value(&text))
+        .min()
+}
+
+/// Parse the content of a cgroup memory file. Returns `None` for "max" and
+/// for the huge values that cgroup v1 reports for unlimited memory.
+fn parse_memory_value(text: &str) -> Option<u64> {
+    let value: u64 = text.trim().parse().ok()?;
+    // Unlimited cgroup v1 memory is reported as (almost) i64::MAX.
+    (value < 1 << 62).then_some(value)
+}
+
+/// Candidate paths of a memory controller file for the worker's cgroup and
+/// its ancestors, for cgroup v2 (`v2_file`) and cgroup v1 (`v1_file`).
+fn cgroup_files(v2_file: &str, v1_file: &str) -> Vec<PathBuf> {
+    let content = read_to_string("/proc/self/cgroup").unwrap_or_default();
+    let mut files = Vec::new();
+    for line in content.lines() {
+        // Format: hierarchy-ID:controller-list:cgroup-path
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(cgroup)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let (mount, file) = if controllers.is_empty() {
+            (PathBuf::from(CGROUP_ROOT), v2_file)
+        } else if controllers.split(',').any(|name| name == "memory") {
+            (Path::new(CGROUP_ROOT).join("memory"), v1_file)
+        } else {
+            continue;
+        };
+        // Inside containers, the cgroup is usually mounted as the root.
+        let cgroup = Path::new(cgroup.trim_start_matches('/'));
+        files.extend(cgroup.ancestors().map(|dir| mount.join(dir).join(file)));
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_memory_values() {
+        assert_eq!(parse_memory_value("2147483648\n"), Some(2 << 30));
+        assert_eq!(parse_memory_value("max\n"), None);
+        assert_eq!(parse_memory_value("9223372036854771712\n"), None);
+        assert_eq!(parse_memory_value(""), None);
+    }
+}
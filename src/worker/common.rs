@@ -1,15 +1,37 @@
 use crate::{
     config::Config,
+    crypto::{PayloadCipher, Sealed},
     messages::stream::{MessageStream, MessageError},
-    messages::{HelloMessage, ServerToWorkerMessage, WorkerToServerMessage},
-    worker::job::Job, structs::{JobInfo, Resources, LoadInfo, SystemInfo, JobStatus},
+    messages::{
+        chunks,
+        version::{
+            capabilities, check_remote_version, CAP_CHUNKED_RESULTS, CAP_JOB_RECONCILIATION,
+            CAP_RESOURCE_POOLS, CAP_WORKER_SELF_TEST, KUEUE_VERSION,
+        },
+        HelloMessage, ServerToWorkerMessage, WorkerToServerMessage,
+    },
+    worker::{
+        cgroup::{self, ResourceLimits},
+        environment,
+        gpu::{GpuAllocation, GpuAllocations},
+        job::Job,
+        sandbox::Sandboxes,
+        topology::{CoreAllocation, CpuTopology},
+    },
+    structs::{
+        JobInfo, Resources, LoadInfo, SystemInfo, JobStatus, ProbeResult, FailureKind,
+        ProvenanceRecord, ExecutionEnvironment, EXIT_CODE_SPAWN_FAILURE,
+    },
+    tunnel,
 };
-use anyhow::{bail, Result};
-use std::{cmp::{max, min},sync::Arc};
+use anyhow::{anyhow, bail, Result};
+use std::{cmp::{max, min},collections::{BTreeMap, BTreeSet, VecDeque},sync::Arc};
 use sysinfo::{CpuExt, System, SystemExt};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::{mpsc::Sender, Notify},
+    net::TcpStream,
+    sync::{mpsc::{channel, Receiver, Sender}, Notify},
+    time::{sleep_until, Duration, Instant},
 };
 use tokio_util::sync::CancellationToken;
 use base64::{engine::general_purpose, Engine};
@@ -23,9 +45,15 @@ pub struct Worker<Stream> {
     worker_name: String,
     stream: MessageStream<Stream>,
     cancel_token: CancellationToken,
+    /// Dropped together with the worker to signal that it has shut down.
+    #[allow(dead_code)]
     keep_alive: Sender<()>,
     /// Handle to query system information.
     system_info: System,
+    /// Limits of the worker's cgroup, which might be less than the system's totals.
+    limits: ResourceLimits,
+    /// Physical cores of the worker, to pin jobs with core binding.
+    topology: CpuTopology,
     /// Regularly notified by a timer to trigger sending a message
     /// about updated system and hardware information to the server.
     pub notify_system_update: Arc<Notify>,
@@ -36,6 +64,25 @@ pub struct Worker<Stream> {
     accepted_jobs: Vec<Job>,
     /// Jobs currently running on the worker.
     running_jobs: Vec<Job>,
+    /// Status updates and results of finished jobs, kept until they have
+    /// been sent to the server, e.g., after reconnecting.
+    unreported_jobs: VecDeque<WorkerToServerMessage>,
+    /// Recent job starts, to limit the number of jobs started at once.
+    job_starts: VecDeque<Instant>,
+    /// Messages from job and tunnel tasks, e.g., terminal output of
+    /// interactive jobs, to be forwarded to the server.
+    message_tx: Sender<WorkerToServerMessage>,
+    message_rx: Receiver<WorkerToServerMessage>,
+    /// Open tunnels to ports of running jobs.
+    tunnels: BTreeMap<u64, Sender<Vec<u8>>>,
+    /// Optional protocol features supported by the server.
+    server_capabilities: BTreeSet<String>,
+    /// Private scratch directories of jobs, if enabled.
+    sandboxes: Option<Sandboxes>,
+    /// Execution environment recorded with finished jobs, if enabled.
+    environment: Option<ExecutionEnvironment>,
+    /// Cipher for encrypted jobs, if a payload key is configured.
+    payload_cipher: Option<PayloadCipher>,
     /// State of the worker.
     running: bool,
 }
@@ -50,6 +97,20 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
     ) -> Self {
         // Initialize system resources.
         let system_info = System::new_all();
+        let limits = ResourceLimits::detect();
+        log::debug!("Detected resource limits: {:?}", limits);
+        let topology = CpuTopology::detect();
+        log::debug!(
+            "Detected {} physical cores with {} logical CPUs",
+            topology.cores.len(),
+            topology.logical_cpus()
+        );
+        let (message_tx, message_rx) = channel(64);
+        let sandboxes = Sandboxes::from_settings(&config.worker_settings);
+        let settings = &config.common_settings;
+        let payload_cipher = settings.payload_key.as_deref().map(|key| {
+            PayloadCipher::new(key, settings.tenant.as_deref())
+        });
 
         Self {
             config,
@@ -57,10 +118,21 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
             stream,
             cancel_token,
             keep_alive,system_info,
+            limits,
+            topology,
             notify_system_update: Arc::new(Notify::new()),
             notify_job_status: Arc::new(Notify::new()),
             accepted_jobs: Vec::new(),
             running_jobs: Vec::new(),
+            unreported_jobs: VecDeque::new(),
+            job_starts: VecDeque::new(),
+            message_tx,
+            message_rx,
+            tunnels: BTreeMap::new(),
+            server_capabilities: BTreeSet::new(),
+            sandboxes,
+            environment: None,
+            payload_cipher,
             running: true,
         }
     }
@@ -70,15 +142,26 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
         // Send hello from worker.
         let hello = HelloMessage::HelloFromWorker {
             worker_name: self.worker_name.clone(),
+            kueue_version: KUEUE_VERSION.into(),
+            capabilities: capabilities(),
+            tags: self.config.worker_settings.worker_tags.iter().cloned().collect(),
+            labels: self.config.worker_settings.worker_labels.clone(),
+            tenant: self.config.common_settings.tenant.clone(),
         };
         self.stream.send(&hello).await?;
 
         // Await welcoming response from server.
         match self.stream.receive::<ServerToWorkerMessage>().await? {
-            ServerToWorkerMessage::WelcomeWorker => {
+            ServerToWorkerMessage::WelcomeWorker { kueue_version, capabilities } => {
                 log::trace!("Established connection to server...");
+                check_remote_version("server", &kueue_version);
+                self.stream.negotiate_format(&capabilities);
+                self.server_capabilities = capabilities;
                 Ok(()) // continue
             }
+            ServerToWorkerMessage::RejectWorker { reason } => {
+                bail!("Connection rejected by server: {}", reason)
+            }
             other => bail!("Expected WelcomeWorker, received: {:?}", other),
         }
     }
@@ -120,30 +203,92 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
         }
     }
 
+    /// Continue with a new connection after the previous one has been lost.
+    /// Tunnels and job offers of the lost connection are dropped, since the
+    /// server does not know about them anymore.
+    pub fn replace_stream(&mut self, stream: MessageStream<Stream>) {
+        self.stream = stream;
+        self.server_capabilities.clear();
+        self.tunnels.clear();
+        for job in self.accepted_jobs.drain(..) {
+            log::warn!("Dropping job {} offered by the lost connection!", job.info.job_id);
+        }
+        self.running = true;
+    }
+
+    /// Announce jobs that are still running after reconnecting to the
+    /// server, and report jobs that have finished meanwhile.
+    pub async fn announce_jobs(&mut self) -> Result<(), MessageError> {
+        if self.server_capabilities.contains(CAP_JOB_RECONCILIATION) {
+            self.report_jobs().await?;
+        }
+        self.update_job_status().await?;
+        // Send system info and resources right away to receive new jobs.
+        self.notify_system_update.notify_one();
+        Ok(())
+    }
+
+    /// Report the results of the self-test to the server.
+    pub async fn report_self_test(&mut self, results: Vec<ProbeResult>) -> Result<()> {
+        if !self.server_capabilities.contains(CAP_WORKER_SELF_TEST) {
+            log::warn!("Server does not support self-test results!");
+            return Ok(());
+        }
+        let message = WorkerToServerMessage::ReportSelfTest(results);
+        self.stream.send(&message).await?;
+        Ok(())
+    }
+
     /// Handle messages and interrupts.
     pub async fn run(&mut self) -> Result<(), MessageError> {
         while self.running {
+            let job_starts_expire = self.next_job_start_expiry();
             tokio::select! {
                 // Read and handle incoming messages.
                 message = self.stream.receive::<ServerToWorkerMessage>() => {
-                    self.handle_message(message?).await?;
+                    match message {
+                        Ok(message) => self.handle_message(message).await?,
+                        Err(MessageError::UnknownMessage) => {
+                            log::warn!("Ignoring unknown message from server!");
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
                 // Or, get active when notified by timer.
                 _ = self.notify_system_update.notified() => {
                     // Update server about system and load.
                     // Also used as "keep alive" signal by the server.
                     self.update_system_info().await?;
+                    // Release resources of job offers the server never confirmed.
+                    self.drop_stale_accepted_jobs().await?;
+                    // Remove sandboxes of concluded jobs that have expired.
+                    self.clean_up_sandboxes();
+                    // Let the server compare its view of our jobs.
+                    if self.server_capabilities.contains(CAP_JOB_RECONCILIATION) {
+                        self.report_jobs().await?;
+                    }
                     // Also send an update on available resources. This is
                     // important when "dynamic resources" are used. Otherwise,
                     // the server might see an outdated, full-loaded worker
                     // with no running jobs and will never offer any new jobs.
-                    let message = WorkerToServerMessage::UpdateResources(self.get_available_resources());
+                    self.send_available_resources().await?;
+                }
+                // Or, forward messages from job and tunnel tasks.
+                Some(message) = self.message_rx.recv() => {
+                    if let WorkerToServerMessage::CloseTunnel { tunnel_id } = &message {
+                        self.tunnels.remove(tunnel_id); // closed locally
+                    }
                     self.stream.send(&message).await?;
                 }
                 // Or, get active when a job finishes.
                 _ = self.notify_job_status.notified() => {
                     self.update_job_status().await?;
                 }
+                // Or, ask for new jobs again when further jobs may be started.
+                _ = sleep_until(job_starts_expire.unwrap_or_else(Instant::now)),
+                    if job_starts_expire.is_some() => {
+                    self.send_available_resources().await?;
+                }
                 // Or, close the connection if worker is shutting down.
                 _ = self.cancel_token.cancelled() => {
                     log::info!("Closing connection to server!");
@@ -160,7 +305,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
     /// Called in the main loop to handle different incoming messages from the server.
     pub async fn handle_message(&mut self, message: ServerToWorkerMessage) -> Result<(), MessageError> {
         match message {
-            ServerToWorkerMessage::WelcomeWorker => {
+            ServerToWorkerMessage::WelcomeWorker { .. } => {
                 // This is already handled before the main loop begins.
                 log::warn!("Received duplicate welcome message!");
                 Ok(())
@@ -175,6 +320,11 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                 log::warn!("Received duplicate authentication acceptance!");
                 Ok(())
             }
+            ServerToWorkerMessage::RejectWorker { .. } => {
+                // This is already handled before the main loop begins.
+                log::warn!("Received unexpected rejection!");
+                Ok(())
+            }
             ServerToWorkerMessage::OfferJob(job_info) => self.on_offer_job(job_info).await,
             ServerToWorkerMessage::ConfirmJobOffer(job_info) => {
                 self.on_confirm_job_offer(job_info).await
@@ -183,9 +333,65 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                 self.on_withdraw_job_offer(job_info).await
             }
             ServerToWorkerMessage::KillJob(job_info) => self.on_kill_job(job_info).await,
+            ServerToWorkerMessage::StopJob { job_id, signal, grace_period_seconds } => {
+                match self.running_jobs.iter().find(|job| job.info.job_id == job_id) {
+                    Some(job) => job.stop(signal, grace_period_seconds),
+                    None => log::warn!("Job to be stopped with ID={} is not running!", job_id),
+                }
+                Ok(())
+            }
+            ServerToWorkerMessage::JobInput { job_id, data } => {
+                match self.running_jobs.iter().find(|job| job.info.job_id == job_id) {
+                    Some(job) => job.write_input(data),
+                    None => log::warn!("Received input for job {} which is not running!", job_id),
+                }
+                Ok(())
+            }
+            ServerToWorkerMessage::ResizeTerminal { job_id, rows, cols } => {
+                if let Some(job) = self.running_jobs.iter().find(|job| job.info.job_id == job_id) {
+                    job.resize_terminal(rows, cols);
+                }
+                Ok(())
+            }
+            ServerToWorkerMessage::OpenTunnel { job_id, tunnel_id, port } => {
+                self.on_open_tunnel(job_id, tunnel_id, port).await
+            }
+            ServerToWorkerMessage::TunnelData { tunnel_id, data } => {
+                if let Some(data_tx) = self.tunnels.get(&tunnel_id) {
+                    if data_tx.send(data).await.is_err() {
+                        self.tunnels.remove(&tunnel_id);
+                    }
+                }
+                Ok(())
+            }
+            ServerToWorkerMessage::CloseTunnel { tunnel_id } => {
+                // Dropping the channel ends the relay.
+                self.tunnels.remove(&tunnel_id);
+                Ok(())
+            }
+            ServerToWorkerMessage::FollowJob { job_id, follow } => {
+                let follower = follow.then(|| self.message_tx.clone());
+                let job = self.running_jobs.iter().find(|job| job.info.job_id == job_id);
+                let catch_up = match job.map(|job| (job.payload.is_some(), &job.live_output)) {
+                    // Output of encrypted jobs must not pass the server as plaintext.
+                    Some((true, _)) => {
+                        log::warn!("Refused to forward output of encrypted job {}!", job_id);
+                        Vec::new()
+                    }
+                    Some((false, live_output)) => live_output.follow(job_id, follower),
+                    None => Vec::new(), // job has concluded meanwhile
+                };
+                for message in catch_up {
+                    self.stream.send(&message).await?;
+                }
+                Ok(())
+            }
             ServerToWorkerMessage::Bye => {
-                log::debug!("Connection closed by server!");
-                self.running = false; // stop worker
+                log::info!(
+                    "Connection closed by server, keeping {} running jobs!",
+                    self.running_jobs.len()
+                );
+                self.running = false; // reconnect later
                 Ok(())
             }
         }
@@ -193,8 +399,9 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
 
     /// Called upon receiving ServerToWorkerMessage::OfferJob.
     async fn on_offer_job(&mut self, job_info: JobInfo) -> Result<(), MessageError> {
-        // Reject job when the worker cannot see the working directory.
-        if !job_info.cwd.is_dir() {
+        // Reject job when the worker cannot see the working directory. If
+        // requested, the directory is created when the job is started.
+        if !job_info.create_cwd && !job_info.cwd.is_dir() {
             log::debug!(
                 "Rejected job {} because working directory {} is not found!",
                 job_info.job_id,
@@ -208,15 +415,94 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                 .await;
         }
 
+        // Reject job when the requested resource pool does not exist.
+        if let Some(pool) = &job_info.pool {
+            if !self.config.worker_settings.resource_pools.contains_key(pool) {
+                log::debug!("Rejected job {} because pool {} is not found!", job_info.job_id, pool);
+                return self
+                    .stream
+                    .send(&WorkerToServerMessage::RejectJobOffer(job_info))
+                    .await;
+            }
+        }
+
+        // Reject encrypted jobs that this worker cannot decrypt.
+        let mut payload = None;
+        if job_info.encrypted_payload.is_some() {
+            let decrypted = match &self.payload_cipher {
+                Some(cipher) => cipher.open_job(&job_info),
+                None => Err(anyhow!("No payload_key configured!")),
+            };
+            match decrypted {
+                Ok(decrypted) => payload = Some(decrypted),
+                Err(e) => {
+                    log::debug!("Rejected encrypted job {}: {}", job_info.job_id, e);
+                    return self
+                        .stream
+                        .send(&WorkerToServerMessage::RejectJobOffer(job_info))
+                        .await;
+                }
+            }
+        }
+
+        // Assign GPU devices, which must not be used by other exclusive jobs.
+        let gpu_devices = match self.allocate_gpus(&job_info) {
+            GpuAllocation::Granted(devices) => devices,
+            GpuAllocation::Busy => {
+                log::debug!("Deferred job {} until GPUs become free!", job_info.job_id);
+                return self
+                    .stream
+                    .send(&WorkerToServerMessage::DeferJobOffer(job_info))
+                    .await;
+            }
+            GpuAllocation::Impossible => {
+                log::debug!("Rejected job {} because of missing GPUs!", job_info.job_id);
+                return self
+                    .stream
+                    .send(&WorkerToServerMessage::RejectJobOffer(job_info))
+                    .await;
+            }
+        };
+
+        // Assign physical cores to jobs with core binding.
+        let (cpu_affinity, reserved_cpus) = match self.allocate_cores(&job_info) {
+            CoreAllocation::Granted { affinity, reserved } => (affinity, reserved),
+            CoreAllocation::Busy => {
+                log::debug!("Deferred job {} until cores become free!", job_info.job_id);
+                return self
+                    .stream
+                    .send(&WorkerToServerMessage::DeferJobOffer(job_info))
+                    .await;
+            }
+            CoreAllocation::Impossible => {
+                log::debug!("Rejected job {} because of missing cores!", job_info.job_id);
+                return self
+                    .stream
+                    .send(&WorkerToServerMessage::RejectJobOffer(job_info))
+                    .await;
+            }
+        };
+
         // Accept job if required resources can be acquired.
-        if self.resources_available(&job_info.worker_resources) {
+        if self.resources_available(&job_info, reserved_cpus.len() as u64) {
             log::debug!("Accepted job {}!", job_info.job_id);
 
             // Remember accepted job for later confirmation.
-            self.accepted_jobs.push(Job::new(
-                job_info.clone(),
-                Arc::clone(&self.notify_job_status),
-            ));
+            let mut job = Job::new(job_info.clone(), Arc::clone(&self.notify_job_status));
+            if job_info.interactive {
+                job.output_tx = Some(self.message_tx.clone());
+            }
+            job.thread_count_variables =
+                self.config.worker_settings.thread_count_variables.clone();
+            job.process_limits =
+                job_info.process_limits.or(self.config.worker_settings.default_process_limits);
+            job.payload = payload;
+            if self.has_gpus() {
+                job.gpu_devices = Some(gpu_devices);
+            }
+            job.cpu_affinity = cpu_affinity;
+            job.reserved_cpus = reserved_cpus;
+            self.accepted_jobs.push(job);
             // Notify server about accepted job offer.
             self.stream
                 .send(&WorkerToServerMessage::AcceptJobOffer(job_info))
@@ -239,25 +525,36 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
             .position(|job| job.info.job_id == job_info.job_id);
         match accepted_job_index {
             Some(index) => {
-                // Move job to running processes.
+                // Move job to running processes. The confirmed job must be the
+                // job that has been accepted, apart from the status.
                 let mut job = self.accepted_jobs.remove(index);
-                // Also update job status. (Should now be "running".)
-                job.info.status = job_info.status;
+                if let Err(e) = job.reconcile_confirmation(job_info.clone()) {
+                    log::error!("Confirmed job {} differs from offer: {}", job_info.job_id, e);
+                    // Reject the job, so that the server requeues it, and
+                    // release the resources reserved for it.
+                    self.stream
+                        .send(&WorkerToServerMessage::RejectJobOffer(job_info))
+                        .await?;
+                    return self.send_available_resources().await;
+                }
+                if let Some(sandboxes) = &self.sandboxes {
+                    match sandboxes.create(job_info.job_id) {
+                        Ok(sandbox) => job.sandbox = Some(sandbox),
+                        Err(e) => log::error!("Failed to create sandbox: {}", e),
+                    }
+                }
                 self.running_jobs.push(job);
 
-                // TODO: Also compare entire "job_info"s for consistency?
-
                 // Run job as child process
                 match self.running_jobs.last_mut().unwrap().run().await {
                     Ok(()) => {
                         log::debug!("Started job {}!", job_info.job_id);
+                        self.record_job_start();
 
                         // Inform server about available resources.
                         // This information triggers the server to
                         // send new job offers to this worker.
-                        let message =
-                            WorkerToServerMessage::UpdateResources(self.get_available_resources());
-                        self.stream.send(&message).await
+                        self.send_available_resources().await
                     }
                     Err(e) => {
                         log::error!("Failed to start job: {}", e);
@@ -265,9 +562,10 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                         {
                             let mut job_result = job.result.lock().unwrap();
                             job_result.finished = true;
-                            job_result.exit_code = -43;
+                            job_result.exit_code = EXIT_CODE_SPAWN_FAILURE;
                             job_result.run_time = chrono::Duration::seconds(0);
                             job_result.comment = format!("Failed to start job: {}", e);
+                            job_result.failure = Some(FailureKind::SpawnFailure);
                         }
 
                         // Update server. This will also send an update on
@@ -275,9 +573,6 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                         self.update_job_status().await
                     }
                 }
-
-                // TODO: There is some checking we could do here. We do
-                // not want jobs to remain in accepted_jobs indefinitely!
             }
             None => {
                 log::error!(
@@ -289,6 +584,61 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
         }
     }
 
+    /// Drop accepted jobs that have been neither confirmed nor withdrawn by the
+    /// server in time, e.g., because the server recovered the offer meanwhile.
+    /// The offer is rejected, so that the server can requeue the job.
+    async fn drop_stale_accepted_jobs(&mut self) -> Result<(), MessageError> {
+        let timeout = chrono::Duration::seconds(
+            self.config.worker_settings.accepted_job_timeout_seconds as i64,
+        );
+        let now = Utc::now();
+        let (stale_jobs, accepted_jobs): (Vec<Job>, Vec<Job>) = self
+            .accepted_jobs
+            .drain(..)
+            .partition(|job| now - job.accepted > timeout);
+        self.accepted_jobs = accepted_jobs;
+
+        for job in stale_jobs {
+            log::warn!(
+                "Accepted job {} has not been confirmed by the server. Dropping...",
+                job.info.job_id
+            );
+            self.stream
+                .send(&WorkerToServerMessage::RejectJobOffer(job.info))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Report all accepted and running jobs to the server, including jobs
+    /// that have stalled. Interactive jobs may idle and are never stalled.
+    async fn report_jobs(&mut self) -> Result<(), MessageError> {
+        let accepted = self.accepted_jobs.iter().map(|job| job.info.job_id).collect();
+        // Finished jobs are running as far as the server knows.
+        let unreported = self.unreported_jobs.iter().filter_map(|message| match message {
+            WorkerToServerMessage::UpdateJobStatus(job_info) => Some(job_info.job_id),
+            _ => None,
+        });
+        let running = self.running_jobs.iter().map(|job| job.info.job_id);
+        let running = running.chain(unreported).collect();
+        let stalled = match self.config.worker_settings.stalled_job_timeout_minutes {
+            Some(minutes) => self
+                .running_jobs
+                .iter()
+                .filter(|job| !job.info.interactive)
+                .filter(|job| job.activity.idle_seconds() >= minutes as i64 * 60)
+                .map(|job| job.info.job_id)
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        let message = WorkerToServerMessage::ReportJobs {
+            accepted,
+            running,
+            stalled,
+        };
+        self.stream.send(&message).await
+    }
+
     /// Called upon receiving ServerToWorkerMessage::WithdrawJobOffer.
     async fn on_withdraw_job_offer(&mut self, job_info: JobInfo) -> Result<(), MessageError> {
         // Remove job from offered list
@@ -304,11 +654,9 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                 Ok(())
             }
             None => {
-                log::error!(
-                    "Withdrawn job with ID={} that has not been offered previously!",
-                    job_info.job_id
-                );
-                Ok(()) // Error occurred, but we can continue running.
+                // The offer might have been deferred or rejected meanwhile.
+                log::debug!("Withdrawn job with ID={} has not been accepted!", job_info.job_id);
+                Ok(())
             }
         }
     }
@@ -341,32 +689,139 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
         }
     }
 
-    /// Returns available, unused resources of the worker.
+    /// Called upon receiving ServerToWorkerMessage::OpenTunnel.
+    async fn on_open_tunnel(&mut self, job_id: u64, tunnel_id: u64, port: u16) -> Result<(), MessageError> {
+        // Only ports of running jobs can be forwarded.
+        if !self.running_jobs.iter().any(|job| job.info.job_id == job_id) {
+            log::warn!("Rejected tunnel to job {} which is not running!", job_id);
+            return self
+                .stream
+                .send(&WorkerToServerMessage::CloseTunnel { tunnel_id })
+                .await;
+        }
+
+        // Connect to the port and relay data in the background.
+        let (data_tx, data_rx) = channel(64);
+        self.tunnels.insert(tunnel_id, data_tx);
+        let message_tx = self.message_tx.clone();
+        tokio::spawn(async move {
+            match TcpStream::connect(("localhost", port)).await {
+                Ok(stream) => {
+                    log::debug!("Opened tunnel {} to port {} of job {}.", tunnel_id, port, job_id);
+                    tunnel::relay(
+                        stream,
+                        tunnel_id,
+                        data_rx,
+                        message_tx,
+                        |tunnel_id, data| WorkerToServerMessage::TunnelData { tunnel_id, data },
+                        |tunnel_id| WorkerToServerMessage::CloseTunnel { tunnel_id },
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    log::warn!("Failed to connect to port {} of job {}: {}", port, job_id, e);
+                    let _ = message_tx
+                        .send(WorkerToServerMessage::CloseTunnel { tunnel_id })
+                        .await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Number of CPU cores of the system, unless the worker is limited to less.
+    fn total_cpus(&self) -> u64 {
+        let cpus = self.system_info.cpus().len() as u64;
+        self.limits.cpus.map_or(cpus, |limit| min(cpus, limit))
+    }
+
+    /// Total memory of the system in megabytes, unless the worker is limited to less.
+    fn total_ram_mb(&self) -> u64 {
+        let ram_mb = self.system_info.total_memory() / 1024 / 1024;
+        self.limits.ram_mb.map_or(ram_mb, |limit| min(ram_mb, limit))
+    }
+
+    /// Memory currently available in megabytes, within the worker's memory limit.
+    fn available_ram_mb(&self) -> u64 {
+        let available_ram_mb = self.system_info.available_memory() / 1024 / 1024;
+        match (self.limits.ram_mb, cgroup::memory_usage_mb()) {
+            (Some(limit), Some(used)) => min(available_ram_mb, limit.saturating_sub(used)),
+            _ => available_ram_mb,
+        }
+    }
+
+    /// Remember the start of a job, if the number of job starts is limited.
+    fn record_job_start(&mut self) {
+        if self.config.worker_settings.max_job_starts_per_window.is_some() {
+            let window = Duration::from_secs(self.config.worker_settings.job_start_window_seconds);
+            let now = Instant::now();
+            self.job_starts.retain(|start| *start + window > now);
+            self.job_starts.push_back(now);
+        }
+    }
+
+    /// Job starts within the current window, if the number of job starts is limited.
+    fn recent_job_starts(&self) -> impl Iterator<Item = Instant> + '_ {
+        let window = Duration::from_secs(self.config.worker_settings.job_start_window_seconds);
+        let now = Instant::now();
+        self.job_starts
+            .iter()
+            .map(move |start| *start + window)
+            .filter(move |expiry| *expiry > now)
+    }
+
+    /// Number of jobs that may still be started within the current window,
+    /// if `max_job_starts_per_window` is set. Accepted jobs are about to be
+    /// started and count as well.
+    fn job_starts_left(&self) -> Option<u64> {
+        let limit = self.config.worker_settings.max_job_starts_per_window?;
+        let starts = self.recent_job_starts().count() + self.accepted_jobs.len();
+        Some(limit.saturating_sub(starts as u64))
+    }
+
+    /// Time at which the next recent job start leaves the window,
+    /// so that another job may be started.
+    fn next_job_start_expiry(&self) -> Option<Instant> {
+        self.config.worker_settings.max_job_starts_per_window?;
+        self.recent_job_starts().next()
+    }
+
+    /// Returns available, unused resources of the worker. Resources
+    /// reserved for resource pools are not available to other jobs.
     fn get_available_resources(&mut self) -> Resources {
         // Refresh relevant system information.
         self.system_info.refresh_cpu();
         self.system_info.refresh_memory();
 
-        // Calculate available job slots.
-        let allocated_job_slots: u64 = self
-            .accepted_jobs
-            .iter()
-            .chain(self.running_jobs.iter())
-            .map(|job| job.info.worker_resources.job_slots)
-            .sum();
-        assert!(self.config.worker_settings.worker_max_parallel_jobs >= allocated_job_slots);
-        let available_job_slots =
-            self.config.worker_settings.worker_max_parallel_jobs - allocated_job_slots;
+        // Sum up resources reserved for pools and allocated by their jobs.
+        let pools = &self.config.worker_settings.resource_pools;
+        let reserved = pools.values().fold(Resources::new(0, 0, 0), |sum, pool| {
+            Resources::new(
+                sum.job_slots + pool.job_slots,
+                sum.cpus + pool.cpus,
+                sum.ram_mb + pool.ram_mb,
+            )
+        });
+        let allocated_in_pools = self.get_allocated_resources(|pool| pool.is_some());
 
-        // Calculate available cpus.
-        let total_cpus = self.system_info.cpus().len() as i64;
+        // Calculate available job slots, considering the limit of job starts.
+        let allocated = self.get_allocated_resources(|pool| pool.is_none());
+        let available_job_slots = self
+            .config
+            .worker_settings
+            .worker_max_parallel_jobs
+            .saturating_sub(reserved.job_slots)
+            .saturating_sub(allocated.job_slots);
+        let available_job_slots = self
+            .job_starts_left()
+            .map_or(available_job_slots, |left| min(available_job_slots, left));
 
-        let allocated_cpus: i64 = self
-            .accepted_jobs
-            .iter()
-            .chain(self.running_jobs.iter())
-            .map(|job| job.info.worker_resources.cpus as i64)
-            .sum();
+        // Calculate available cpus. Overcommitting lets the worker accept
+        // more jobs than its cores, as long as they are not busy.
+        let settings = &self.config.worker_settings;
+        let overcommitted_cpus = self.total_cpus() as f64 * settings.cpu_overcommit_factor;
+        let total_cpus = overcommitted_cpus as i64 - reserved.cpus as i64;
+        let allocated_cpus = allocated.cpus as i64;
 
         let available_cpus = if self.config.worker_settings.dynamic_check_free_resources {
             let busy_cpus = self.system_info.load_average().one.ceil() as i64;
@@ -383,61 +838,181 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
             let busy_cpus = (busy_cpus as f64
                 * self.config.worker_settings.dynamic_cpu_load_scale_factor)
                 .ceil() as i64;
+            // Load caused by jobs in pools is accounted for by the pools.
+            let busy_cpus = busy_cpus - allocated_in_pools.cpus as i64;
             max(0, total_cpus - max(allocated_cpus, busy_cpus))
         } else {
             max(0, total_cpus - allocated_cpus)
         };
 
-        // Calculate available memory.
-        let total_ram_mb = (self.system_info.total_memory() / 1024 / 1024) as i64;
-
-        let allocated_ram_mb: i64 = self
-            .accepted_jobs
-            .iter()
-            .chain(self.running_jobs.iter())
-            .map(|job| job.info.worker_resources.ram_mb as i64)
-            .sum();
+        // Calculate available memory. Memory actually in use still limits
+        // overcommitted workers if free resources are checked dynamically.
+        let settings = &self.config.worker_settings;
+        let overcommitted_ram_mb = self.total_ram_mb() as f64 * settings.ram_overcommit_factor;
+        let total_ram_mb = overcommitted_ram_mb as i64 - reserved.ram_mb as i64;
+        let allocated_ram_mb = allocated.ram_mb as i64;
 
         let available_ram_mb = if self.config.worker_settings.dynamic_check_free_resources {
-            let available_ram_mb = (self.system_info.available_memory() / 1024 / 1024) as i64;
+            let available_ram_mb = self.available_ram_mb() as i64;
+            // Memory reserved for pools but not yet used by their jobs.
+            let unused_pool_ram_mb = reserved.ram_mb as i64 - allocated_in_pools.ram_mb as i64;
+            let available_ram_mb = available_ram_mb - unused_pool_ram_mb;
             max(0, min(total_ram_mb - allocated_ram_mb, available_ram_mb))
         } else {
             max(0, total_ram_mb - allocated_ram_mb)
         };
 
-        Resources::new(
+        let mut available = Resources::new(
             available_job_slots,
             available_cpus as u64,
             available_ram_mb as u64,
+        );
+        available.named = self.get_free_named_resources();
+        available
+    }
+
+    /// Returns named resources of the worker that are not allocated by
+    /// accepted or running jobs. Named resources are shared by all pools.
+    fn get_free_named_resources(&self) -> BTreeMap<String, u64> {
+        let mut free = self.config.worker_settings.resources.clone();
+        for job in self.accepted_jobs.iter().chain(self.running_jobs.iter()) {
+            for (name, amount) in &job.info.worker_resources.named {
+                if let Some(available) = free.get_mut(name) {
+                    *available = available.saturating_sub(*amount);
+                }
+            }
+        }
+        free
+    }
+
+    /// Returns available, unused resources of each resource pool.
+    fn get_pool_resources(&self) -> BTreeMap<String, Resources> {
+        let job_starts_left = self.job_starts_left();
+        self.config
+            .worker_settings
+            .resource_pools
+            .iter()
+            .map(|(name, pool)| {
+                let allocated =
+                    self.get_allocated_resources(|job_pool| job_pool == Some(name.as_str()));
+                let available_job_slots = pool.job_slots.saturating_sub(allocated.job_slots);
+                let available_job_slots = job_starts_left
+                    .map_or(available_job_slots, |left| min(available_job_slots, left));
+                let mut available = Resources::new(
+                    available_job_slots,
+                    pool.cpus.saturating_sub(allocated.cpus),
+                    pool.ram_mb.saturating_sub(allocated.ram_mb),
+                );
+                available.named = self.get_free_named_resources();
+                (name.clone(), available)
+            })
+            .collect()
+    }
+
+    /// Sums up resources of accepted and running jobs whose pool matches.
+    fn get_allocated_resources(&self, pool_filter: impl Fn(Option<&str>) -> bool) -> Resources {
+        self.accepted_jobs
+            .iter()
+            .chain(self.running_jobs.iter())
+            .filter(|job| pool_filter(job.info.pool.as_deref()))
+            .fold(Resources::new(0, 0, 0), |sum, job| {
+                let required = &job.info.worker_resources;
+                Resources::new(
+                    sum.job_slots + required.job_slots,
+                    sum.cpus + max(required.cpus, job.reserved_cpus.len() as u64),
+                    sum.ram_mb + required.ram_mb,
+                )
+            })
+    }
+
+    /// Returns "true" if there are enough resources free to fit the
+    /// demand of the given job, within the job's pool if it has one.
+    /// Returns `true` if GPU devices are configured for this worker.
+    fn has_gpus(&self) -> bool {
+        let settings = &self.config.worker_settings;
+        !settings.gpu_devices.is_empty() || !settings.gpu_mig_slices.is_empty()
+    }
+
+    /// Select GPU devices for the job, considering the devices assigned to
+    /// jobs that have been accepted or are running.
+    fn allocate_gpus(&self, job_info: &JobInfo) -> GpuAllocation {
+        let mut allocations = GpuAllocations::default();
+        for job in self.accepted_jobs.iter().chain(&self.running_jobs) {
+            allocations.add(&job.info.gpu_mode, job.gpu_devices.iter().flatten());
+        }
+        allocations.allocate(
+            &self.config.worker_settings.gpu_devices,
+            &self.config.worker_settings.gpu_mig_slices,
+            job_info.gpus,
+            &job_info.gpu_mode,
         )
     }
 
-    /// Returns "true" if there are enough resources free to
-    /// fit the demand of the given "required" resources.
-    fn resources_available(&mut self, required: &Resources) -> bool {
-        let available = self.get_available_resources();
+    /// Select CPUs for the job, considering the cores reserved by bound jobs
+    /// that have been accepted or are running.
+    fn allocate_cores(&self, job_info: &JobInfo) -> CoreAllocation {
+        let reserved: BTreeSet<usize> = self
+            .accepted_jobs
+            .iter()
+            .chain(&self.running_jobs)
+            .flat_map(|job| job.reserved_cpus.iter().copied())
+            .collect();
+        let cpus = job_info.worker_resources.cpus;
+        self.topology.allocate(cpus, job_info.core_binding, &reserved)
+    }
+
+    /// Returns `true` if the job fits into the available resources. Bound
+    /// jobs occupy all `reserved_cpus` of their physical cores.
+    fn resources_available(&mut self, job_info: &JobInfo, reserved_cpus: u64) -> bool {
+        let available = match &job_info.pool {
+            Some(pool) => match self.get_pool_resources().remove(pool) {
+                Some(available) => available,
+                None => return false,
+            },
+            None => self.get_available_resources(),
+        };
+        let mut required = job_info.worker_resources.clone();
+        required.cpus = max(required.cpus, reserved_cpus);
         required.fit_into(&available)
     }
 
+    /// Inform the server about available resources. This information
+    /// triggers the server to send new job offers to this worker.
+    async fn send_available_resources(&mut self) -> Result<(), MessageError> {
+        if self.server_capabilities.contains(CAP_RESOURCE_POOLS)
+            && !self.config.worker_settings.resource_pools.is_empty()
+        {
+            let message = WorkerToServerMessage::UpdatePoolResources(self.get_pool_resources());
+            self.stream.send(&message).await?;
+        }
+        let message = WorkerToServerMessage::UpdateResources(self.get_available_resources());
+        self.stream.send(&message).await
+    }
+
     /// Update and report hardware information and system load.
     async fn update_system_info(&mut self) -> Result<(), MessageError> {
         // Refresh relevant system information.
         self.system_info.refresh_cpu();
         self.system_info.refresh_memory();
 
+        // Capture the environment recorded with finished jobs, if enabled.
+        let settings = &self.config.worker_settings;
+        if settings.capture_environment && environment::needs_refresh(self.environment.as_ref()) {
+            let probe = settings.environment_probe.as_deref();
+            self.environment = Some(environment::capture(&self.system_info, probe).await);
+        }
+
         // Get CPU cores, frequency, and RAM.
-        let cpu_cores = self.system_info.cpus().len() as u64;
-        let cpu_frequency = if cpu_cores > 0 {
-            self.system_info
-                .cpus()
-                .iter()
-                .map(|cpu| cpu.frequency())
-                .sum::<u64>()
-                / cpu_cores
-        } else {
-            0
-        };
-        let total_ram_mb = self.system_info.total_memory() / 1024 / 1024;
+        let cpu_frequency = self
+            .system_info
+            .cpus()
+            .iter()
+            .map(|cpu| cpu.frequency())
+            .sum::<u64>()
+            .checked_div(self.system_info.cpus().len() as u64)
+            .unwrap_or(0);
+        let cpu_cores = self.total_cpus();
+        let total_ram_mb = self.total_ram_mb();
 
         // Read system load.
         let load_avg = self.system_info.load_average();
@@ -458,8 +1033,14 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                 .long_os_version()
                 .unwrap_or("unknown".into()),
             cpu_cores,
+            physical_cores: self.topology.cores.len() as u64,
+            performance_cores: self.topology.cores.len() as u64
+                - self.topology.efficiency_cores(),
+            efficiency_cores: self.topology.efficiency_cores(),
             cpu_frequency,
             total_ram_mb,
+            max_job_slots: self.config.worker_settings.worker_max_parallel_jobs,
+            resources: self.config.worker_settings.resources.clone(),
             load_info,
         };
 
@@ -471,6 +1052,17 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
 
     /// Find jobs that have concluded and update the server about the new status.
     async fn update_job_status(&mut self) -> Result<(), MessageError> {
+        // Report jobs that finished while the server was unreachable.
+        self.send_unreported_jobs().await?;
+
+        // Forward remaining terminal output before reporting finished jobs.
+        while let Ok(message) = self.message_rx.try_recv() {
+            if let WorkerToServerMessage::CloseTunnel { tunnel_id } = &message {
+                self.tunnels.remove(tunnel_id); // closed locally
+            }
+            self.stream.send(&message).await?;
+        }
+
         // We check all running processes for exit codes
         let mut index = 0;
         while index < self.running_jobs.len() {
@@ -496,6 +1088,7 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                                 worker: self.worker_name.clone(),
                                 run_time_seconds: result_lock.run_time.num_seconds(),
                                 comment: result_lock.comment.clone(),
+                                failure: result_lock.failure,
                             };
                         }
                         JobStatus::Canceled { .. } => {} // leave status as it is (canceled)
@@ -504,6 +1097,27 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                             job.info.status
                         ),
                     }
+                    job.info.step_results = result_lock.step_results.clone();
+                    job.info.resource_usage = result_lock.resource_usage.clone();
+                    if let JobStatus::Finished { started, finished, return_code, .. } =
+                        job.info.status
+                    {
+                        let mut record = ProvenanceRecord {
+                            job_id: job.info.job_id,
+                            submitter: job.info.user.clone(),
+                            command_sha256: job.command_sha256(),
+                            cwd: job.info.cwd.clone(),
+                            worker: self.worker_name.clone(),
+                            kueue_version: KUEUE_VERSION.into(),
+                            started,
+                            finished,
+                            exit_code: return_code,
+                            signature: String::new(),
+                        };
+                        record.sign(&self.config.common_settings.shared_secret);
+                        job.info.provenance = Some(record);
+                        job.info.environment = self.environment.clone();
+                    }
                     if !result_lock.stdout_text.is_empty() {
                         stdout_text = Some(result_lock.stdout_text.clone());
                     }
@@ -512,23 +1126,48 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
                     }
                 }
 
-                // Send update to server
-                let job_status = WorkerToServerMessage::UpdateJobStatus(job.info.clone());
-                self.stream.send(&job_status).await?;
+                // Remove the sandbox of a successful job, or keep it for inspection.
+                if let (Some(sandboxes), Some(sandbox)) = (&self.sandboxes, &job.sandbox) {
+                    let succeeded =
+                        matches!(job.info.status, JobStatus::Finished { return_code: 0, .. });
+                    sandboxes.conclude(sandbox, succeeded);
+                }
+
+                // Outputs of encrypted jobs are only readable by the client.
+                if let (Some(_), Some(cipher)) = (&job.payload, &self.payload_cipher) {
+                    let job_id = job.info.job_id;
+                    let outputs = [
+                        (Sealed::Stdout, &mut stdout_text),
+                        (Sealed::Stderr, &mut stderr_text),
+                    ];
+                    for (sealed, text) in outputs {
+                        let ciphertext = text.as_deref().map(|text| {
+                            cipher.encrypt(sealed, job_id, text)
+                        });
+                        *text = match ciphertext {
+                            Some(Ok(ciphertext)) => Some(ciphertext),
+                            Some(Err(e)) => {
+                                log::error!("Failed to encrypt output: {}", e);
+                                None
+                            }
+                            None => None,
+                        };
+                    }
+                }
 
-                // Send stdout/stderr to server
+                // Send update and stdout/stderr to server
+                let job_status = WorkerToServerMessage::UpdateJobStatus(job.info.clone());
                 let job_results = WorkerToServerMessage::UpdateJobResults {
                     job_id: job.info.job_id,
                     stdout_text,
                     stderr_text,
                 };
-                self.stream.send(&job_results).await?;
+                self.unreported_jobs.extend([job_status, job_results]);
+                self.send_unreported_jobs().await?;
 
                 // Inform server about available resources. This information
                 // triggers the server to send new job offers to the worker.
-                let message =
-                    WorkerToServerMessage::UpdateResources(self.get_available_resources());
-                self.stream.send(&message).await?;
+                self.send_available_resources().await?;
 
                 // TODO: Store/remember finished jobs?
             } else {
@@ -538,4 +1177,147 @@ impl<Stream: AsyncReadExt + AsyncWriteExt + Unpin> Worker<Stream> {
         }
         Ok(())
     }
+
+    /// Remove expired sandboxes and enforce their quota, sparing those of
+    /// accepted and running jobs.
+    fn clean_up_sandboxes(&mut self) {
+        if let Some(sandboxes) = &mut self.sandboxes {
+            let active_jobs = self.accepted_jobs.iter().chain(&self.running_jobs);
+            let active_jobs = active_jobs.map(|job| job.info.job_id).collect();
+            let removed = sandboxes.clean_up(&active_jobs);
+            if removed > 0 {
+                log::debug!("Removed {} retained sandboxes!", removed);
+            }
+        }
+    }
+
+    /// Send status updates and results of finished jobs. Messages are only
+    /// dropped from the queue once they have been sent.
+    async fn send_unreported_jobs(&mut self) -> Result<(), MessageError> {
+        while let Some(message) = self.unreported_jobs.front() {
+            match message {
+                WorkerToServerMessage::UpdateJobResults { job_id, stdout_text, stderr_text }
+                    if self.server_capabilities.contains(CAP_CHUNKED_RESULTS) =>
+                {
+                    // Results are sent in segments. If the connection is
+                    // lost midway, the transfer starts over after reconnecting.
+                    let stdout_text = stdout_text.as_deref();
+                    let stderr_text = stderr_text.as_deref();
+                    for chunk in chunks::split_results(*job_id, stdout_text, stderr_text) {
+                        self.stream.send(&chunk).await?;
+                    }
+                }
+                message => self.stream.send(message).await?,
+            }
+            self.unreported_jobs.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Worker connected to a stream standing in for the server.
+    fn test_worker(config: Config) -> (Worker<DuplexStream>, MessageStream<DuplexStream>) {
+        let (worker_stream, server_stream) = duplex(64 * 1024);
+        let (keep_alive, _) = channel(1);
+        let worker = Worker::new(
+            config,
+            "worker".into(),
+            MessageStream::new(worker_stream),
+            CancellationToken::new(),
+            keep_alive,
+        );
+        (worker, MessageStream::new(server_stream))
+    }
+
+    #[tokio::test]
+    async fn create_cwd_on_start() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.worker_settings.dynamic_check_free_resources = false;
+        let (mut worker, mut server) = test_worker(config);
+        let cwd = std::env::temp_dir().join(format!("kueue-create-cwd-{}", std::process::id()));
+        let mut job_info = JobInfo::new(
+            vec!["true".into()],
+            cwd.clone(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        job_info.create_cwd = true;
+
+        // The offer is accepted, but the directory is not created yet.
+        worker.on_offer_job(job_info.clone()).await.unwrap();
+        let message = server.receive::<WorkerToServerMessage>().await.unwrap();
+        assert!(matches!(message, WorkerToServerMessage::AcceptJobOffer(_)));
+        assert!(!cwd.exists());
+
+        // The directory is created when the job is started.
+        job_info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        worker.on_confirm_job_offer(job_info).await.unwrap();
+        assert!(cwd.is_dir());
+        std::fs::remove_dir_all(&cwd).unwrap();
+    }
+
+    #[tokio::test]
+    async fn job_starts_left() {
+        let mut config = Config::new(Some("no-config".into())).unwrap();
+        config.worker_settings.dynamic_check_free_resources = false;
+        let (mut worker, mut server) = test_worker(config.clone());
+        assert_eq!(worker.job_starts_left(), None);
+        assert_eq!(worker.next_job_start_expiry(), None);
+
+        config.worker_settings.max_job_starts_per_window = Some(3);
+        config.worker_settings.job_start_window_seconds = 10;
+        worker.config = config;
+        assert_eq!(worker.job_starts_left(), Some(3));
+        assert_eq!(worker.next_job_start_expiry(), None);
+
+        // Starts that have left the window do not count.
+        let window = Duration::from_secs(10);
+        let expired = Instant::now() - window - Duration::from_secs(1);
+        worker.job_starts.push_back(expired);
+        assert_eq!(worker.job_starts_left(), Some(3));
+        assert_eq!(worker.next_job_start_expiry(), None);
+
+        // Accepted jobs count, but their start is recorded when they start.
+        let mut job_info = JobInfo::new(
+            vec!["true".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        worker.on_offer_job(job_info.clone()).await.unwrap();
+        let message = server.receive::<WorkerToServerMessage>().await.unwrap();
+        assert!(matches!(message, WorkerToServerMessage::AcceptJobOffer(_)));
+        assert_eq!(worker.job_starts_left(), Some(2));
+        assert_eq!(worker.next_job_start_expiry(), None);
+
+        let before = Instant::now();
+        job_info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        worker.on_confirm_job_offer(job_info).await.unwrap();
+        assert_eq!(worker.job_starts_left(), Some(2));
+        let expiry = worker.next_job_start_expiry().unwrap();
+        assert!(expiry >= before + window && expiry <= Instant::now() + window);
+
+        // No more jobs may be started once the limit is reached.
+        worker.record_job_start();
+        worker.record_job_start();
+        assert_eq!(worker.job_starts_left(), Some(0));
+        assert_eq!(worker.next_job_start_expiry(), Some(expiry));
+    }
 }
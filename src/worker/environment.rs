@@ -0,0 +1,104 @@
+//! Capture of the worker's execution environment, which is recorded with
+//! finished jobs, so that results can be reproduced later.
+
+use crate::structs::ExecutionEnvironment;
+use chrono::Utc;
+use std::process::Stdio;
+use sysinfo::{CpuExt, System, SystemExt};
+use tokio::{
+    process::Command,
+    time::{timeout, Duration},
+};
+
+/// Time after which the environment is captured again, e.g., to notice
+/// updated toolchains.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Time the environment probe may take.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outputs of the probe longer than this (in bytes) are shortened.
+const MAX_PROBE_OUTPUT_LEN: usize = 16 * 1024;
+
+/// Returns `true` if the environment has not been captured yet or is due to
+/// be captured again.
+pub fn needs_refresh(environment: Option<&ExecutionEnvironment>) -> bool {
+    !environment.is_some_and(|environment| {
+        let age = Utc::now() - environment.captured;
+        age.num_seconds() < REFRESH_INTERVAL.as_secs() as i64
+    })
+}
+
+/// Capture the environment of the worker. The output of the probe command,
+/// if any, is recorded as well.
+pub async fn capture(system: &System, probe: Option<&str>) -> ExecutionEnvironment {
+    let probe_output = match probe {
+        Some(probe) => Some(run_probe(probe).await),
+        None => None,
+    };
+    let cpu_model = system
+        .cpus()
+        .first()
+        .map(|cpu| cpu.brand().trim().to_string())
+        .filter(|brand| !brand.is_empty());
+    ExecutionEnvironment {
+        captured: Utc::now(),
+        os: system.long_os_version().unwrap_or("unknown".into()),
+        kernel: system.kernel_version().unwrap_or("unknown".into()),
+        cpu_model: cpu_model.unwrap_or("unknown".into()),
+        probe_output,
+    }
+}
+
+/// Run the probe in a shell and return its output, including errors.
+async fn run_probe(probe: &str) -> String {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(probe)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    match timeout(PROBE_TIMEOUT, output).await {
+        Ok(Ok(output)) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text += &String::from_utf8_lossy(&output.stderr);
+            if text.len() > MAX_PROBE_OUTPUT_LEN {
+                let mut end = MAX_PROBE_OUTPUT_LEN;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text.truncate(end);
+                text += "\n[... output shortened ...]";
+            }
+            if !output.status.success() {
+                log::warn!("Environment probe exited with {}", output.status);
+                text += &format!("\n[probe exited with {}]", output.status);
+            }
+            text
+        }
+        Ok(Err(e)) => format!("[failed to run probe: {}]", e),
+        Err(_) => format!("[probe timed out after {:?}]", PROBE_TIMEOUT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn capture_environment() {
+        let system = System::new();
+        let environment = capture(&system, Some("echo gcc 12.2; echo warning >&2")).await;
+        assert_eq!(
+            environment.probe_output.as_deref(),
+            Some("gcc 12.2\nwarning\n")
+        );
+        assert!(!needs_refresh(Some(&environment)));
+        assert!(needs_refresh(None));
+
+        let environment = capture(&system, Some("exit 3")).await;
+        let output = environment.probe_output.unwrap();
+        assert!(output.contains("[probe exited with"));
+    }
+}
@@ -0,0 +1,160 @@
+//! Assignment of the worker's GPU devices to jobs. The current use of the
+//! devices is derived from the jobs accepted or running on the worker, so
+//! that devices are released together with their jobs.
+
+use crate::structs::GpuMode;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Devices in use by accepted and running jobs.
+#[derive(Debug, Default)]
+pub struct GpuAllocations {
+    /// Devices used by exactly one job.
+    exclusive: BTreeSet<String>,
+    /// Devices used in shared mode and the number of jobs sharing them.
+    shared: BTreeMap<String, u64>,
+    /// MIG slices in use.
+    mig_slices: BTreeSet<String>,
+}
+
+/// Outcome of assigning GPUs to a job.
+#[derive(Debug, PartialEq)]
+pub enum GpuAllocation {
+    /// The job may use the given devices.
+    Granted(Vec<String>),
+    /// Not enough devices are free at the moment.
+    Busy,
+    /// The worker does not have enough devices at all.
+    Impossible,
+}
+
+impl GpuAllocations {
+    /// Register the devices assigned to a job.
+    pub fn add<'a>(&mut self, mode: &GpuMode, devices: impl Iterator<Item = &'a String>) {
+        for device in devices {
+            match mode {
+                GpuMode::Exclusive => {
+                    self.exclusive.insert(device.clone());
+                }
+                GpuMode::Shared => *self.shared.entry(device.clone()).or_default() += 1,
+                GpuMode::Mig(_) => {
+                    self.mig_slices.insert(device.clone());
+                }
+            }
+        }
+    }
+
+    /// Select `gpus` devices for a job with the given mode. Shared jobs are
+    /// placed on the devices with the fewest other jobs first.
+    pub fn allocate(
+        &self,
+        devices: &[String],
+        mig_slices: &BTreeMap<String, Vec<String>>,
+        gpus: u64,
+        mode: &GpuMode,
+    ) -> GpuAllocation {
+        let gpus = gpus as usize;
+        if gpus == 0 {
+            return GpuAllocation::Granted(Vec::new());
+        }
+        let (total, mut free) = match mode {
+            GpuMode::Exclusive => (
+                devices.len(),
+                devices
+                    .iter()
+                    .filter(|device| {
+                        !self.exclusive.contains(*device) && !self.shared.contains_key(*device)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            ),
+            GpuMode::Shared => {
+                let mut free: Vec<&String> = devices
+                    .iter()
+                    .filter(|device| !self.exclusive.contains(*device))
+                    .collect();
+                free.sort_by_key(|device| self.shared.get(*device).copied().unwrap_or(0));
+                (devices.len(), free.into_iter().cloned().collect())
+            }
+            GpuMode::Mig(profile) => {
+                let slices = mig_slices
+                    .get(profile)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                (
+                    slices.len(),
+                    slices
+                        .iter()
+                        .filter(|slice| !self.mig_slices.contains(*slice))
+                        .cloned()
+                        .collect(),
+                )
+            }
+        };
+        if total < gpus {
+            GpuAllocation::Impossible
+        } else if free.len() < gpus {
+            GpuAllocation::Busy
+        } else {
+            free.truncate(gpus);
+            GpuAllocation::Granted(free)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_allocation() {
+        let devices = vec!["0".to_string(), "1".to_string()];
+        let mig_slices = BTreeMap::from([("1g.5gb".to_string(), vec!["MIG-a".to_string()])]);
+        let mig = GpuMode::Mig("1g.5gb".into());
+        let mut allocations = GpuAllocations::default();
+
+        // Two exclusive jobs never get the same device.
+        let allocate = |allocations: &GpuAllocations, gpus, mode| {
+            allocations.allocate(&devices, &mig_slices, gpus, mode)
+        };
+        assert_eq!(
+            allocate(&allocations, 1, &GpuMode::Exclusive),
+            GpuAllocation::Granted(vec!["0".into()])
+        );
+        allocations.add(&GpuMode::Exclusive, ["0".to_string()].iter());
+        assert_eq!(
+            allocate(&allocations, 1, &GpuMode::Exclusive),
+            GpuAllocation::Granted(vec!["1".into()])
+        );
+        assert_eq!(
+            allocate(&allocations, 2, &GpuMode::Exclusive),
+            GpuAllocation::Busy
+        );
+        assert_eq!(
+            allocate(&allocations, 3, &GpuMode::Exclusive),
+            GpuAllocation::Impossible
+        );
+
+        // Shared jobs only use devices that are not used exclusively.
+        allocations.add(&GpuMode::Shared, ["1".to_string()].iter());
+        assert_eq!(
+            allocate(&allocations, 1, &GpuMode::Shared),
+            GpuAllocation::Granted(vec!["1".into()])
+        );
+        assert_eq!(
+            allocate(&allocations, 1, &GpuMode::Exclusive),
+            GpuAllocation::Busy
+        );
+
+        // MIG slices are assigned by profile.
+        assert_eq!(
+            allocate(&allocations, 1, &mig),
+            GpuAllocation::Granted(vec!["MIG-a".into()])
+        );
+        allocations.add(&mig, ["MIG-a".to_string()].iter());
+        assert_eq!(allocate(&allocations, 1, &mig), GpuAllocation::Busy);
+        assert_eq!(
+            allocate(&allocations, 1, &GpuMode::Mig("2g.10gb".into())),
+            GpuAllocation::Impossible
+        );
+    }
+}
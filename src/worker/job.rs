@@ -1,21 +1,196 @@
 //! This module takes care of executing the jobs on the worker.
 
-use crate::structs::JobInfo;
-use anyhow::{bail, Result};
-use chrono::{Duration, Utc};
+use crate::{
+    crypto::{sha256_hex, JobPayload},
+    messages::WorkerToServerMessage,
+    structs::{
+        FailureKind, JobInfo, JobStatus, OutputStream, ProcessLimits, ResourceUsage, Resources,
+        StepResult, EXIT_CODE_CPU_TIME_EXCEEDED, EXIT_CODE_KILLED, EXIT_CODE_KILLED_BY_SIGNAL,
+        EXIT_CODE_SPAWN_FAILURE, EXIT_CODE_STOPPED, EXIT_CODE_WAIT_FAILURE,
+        EXIT_CODE_WALL_TIME_EXCEEDED,
+    },
+    worker::usage::{Activity, UsageMonitor},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
 use futures::future::try_join3;
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
 use std::{
+    fmt,
+    io::{Read, Write},
     path::{Path, PathBuf},
-    process::Stdio,
+    process::{ExitStatus, Stdio},
     sync::{Arc, Mutex},
 };
+use thiserror::Error;
 use tokio::{
     fs::File,
-    io::{copy, AsyncRead, AsyncReadExt, AsyncWrite},
-    process::Command,
-    sync::Notify,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    process::{Child, Command},
+    sync::{mpsc, Notify},
+    time::{sleep_until, Instant},
 };
 
+/// Limits the CPU time of the process to be spawned. When the soft limit is
+/// reached, the kernel sends SIGXCPU. Processes ignoring the signal are
+/// killed after a few more seconds.
+#[cfg(unix)]
+fn limit_cpu_time(cmd: &mut Command, seconds: u64) {
+    let limit = libc::rlimit {
+        rlim_cur: seconds as libc::rlim_t,
+        rlim_max: (seconds + 10) as libc::rlim_t,
+    };
+    // Safety: setrlimit is async-signal-safe and does not allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_CPU, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn limit_cpu_time(_cmd: &mut Command, _seconds: u64) {
+    log::warn!("CPU time limits are not supported on this platform!");
+}
+
+/// Applies limits on open files, processes, and core dumps to the process to
+/// be spawned. Soft and hard limits are set to the same value.
+#[cfg(unix)]
+fn set_process_limits(cmd: &mut Command, limits: &ProcessLimits) {
+    let rlimits: Vec<_> = [
+        (libc::RLIMIT_NOFILE, limits.nofile),
+        (libc::RLIMIT_NPROC, limits.nproc),
+        (libc::RLIMIT_CORE, limits.core),
+    ]
+    .into_iter()
+    .filter_map(|(resource, value)| {
+        let value = value? as libc::rlim_t;
+        Some((
+            resource,
+            libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value,
+            },
+        ))
+    })
+    .collect();
+    if rlimits.is_empty() {
+        return;
+    }
+    // Safety: setrlimit is async-signal-safe and does not allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, limit) in &rlimits {
+                if libc::setrlimit(*resource, limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn set_process_limits(_cmd: &mut Command, limits: &ProcessLimits) {
+    if *limits != ProcessLimits::default() {
+        log::warn!("Process limits are not supported on this platform!");
+    }
+}
+
+/// Restricts the process to be spawned to the given logical CPUs.
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cmd: &mut Command, cpus: &[usize]) {
+    // Safety: The set is fully initialized before it is used.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+    // Safety: sched_setaffinity is async-signal-safe and does not allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cmd: &mut Command, _cpus: &[usize]) {
+    log::warn!("CPU affinity is not supported on this platform!");
+}
+
+/// Returns `true` if the process has been terminated for exceeding its CPU time limit.
+#[cfg(unix)]
+fn cpu_time_exceeded(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(libc::SIGXCPU)
+}
+
+#[cfg(not(unix))]
+fn cpu_time_exceeded(_status: &ExitStatus) -> bool {
+    false
+}
+
+/// Wait until the deadline has been reached, e.g., the wall-time limit of a
+/// job. Waits forever without deadline.
+async fn deadline_reached(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Returns the time at which a job started now exceeds its wall-time limit.
+fn wall_time_deadline(limit_seconds: Option<u64>) -> Option<Instant> {
+    limit_seconds.map(|seconds| Instant::now() + std::time::Duration::from_secs(seconds))
+}
+
+/// Comment of jobs killed for exceeding their wall-time limit.
+fn wall_time_comment(limit_seconds: Option<u64>) -> String {
+    format!(
+        "Timeout: wall-time limit of {} seconds exceeded!",
+        limit_seconds.unwrap_or_default()
+    )
+}
+
+/// Send the signal to the process, e.g., to let a job write a checkpoint.
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: i32) {
+    // Safety: kill does not access any memory of this process.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        let error = std::io::Error::last_os_error();
+        log::warn!(
+            "Failed to send signal {} to process {}: {}",
+            signal,
+            pid,
+            error
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn send_signal(_pid: u32, _signal: i32) {
+    log::warn!("Signals are not supported on this platform!");
+}
+
+/// Returns `true` if the process has been terminated by SIGKILL. Unless the
+/// worker killed the process, this is usually the kernel's out-of-memory killer.
+#[cfg(unix)]
+fn killed_by_sigkill(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(libc::SIGKILL)
+}
+
+#[cfg(not(unix))]
+fn killed_by_sigkill(_status: &ExitStatus) -> bool {
+    false
+}
+
 /// Struct, representing a job on the worker.
 #[derive(Debug)]
 pub struct Job {
@@ -27,6 +202,137 @@ pub struct Job {
     pub result: Arc<Mutex<JobResult>>,
     /// Will be notified by the worker thread when job should be killed.
     pub notify_kill_job: Arc<Notify>,
+    /// Requests to stop the job cleanly, see `stop`.
+    stop_job_tx: mpsc::UnboundedSender<StopRequest>,
+    /// Receiving end of `stop_job_tx`, taken by the job-execution thread.
+    stop_job_rx: Option<mpsc::UnboundedReceiver<StopRequest>>,
+    /// Terminal output of interactive jobs is sent to this channel.
+    pub output_tx: Option<mpsc::Sender<WorkerToServerMessage>>,
+    /// Pseudo terminal of a running interactive job.
+    terminal: Option<Terminal>,
+    /// Point in time the job has been set up, i.e., its offer was accepted.
+    pub accepted: DateTime<Utc>,
+    /// Variables set to the job's number of CPU cores, e.g., `OMP_NUM_THREADS`.
+    pub thread_count_variables: Vec<String>,
+    /// Decrypted command and steps of an encrypted job. Kept separately, so
+    /// that `info` never carries the plaintext back to the server.
+    pub payload: Option<JobPayload>,
+    /// GPU devices assigned to the job, if the worker has any GPUs.
+    pub gpu_devices: Option<Vec<String>>,
+    /// Logical CPUs the job's processes are restricted to, if any.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// Logical CPUs of the physical cores held by a job with core binding.
+    pub reserved_cpus: Vec<usize>,
+    /// Limits of the job's processes, including the worker's defaults.
+    pub process_limits: ProcessLimits,
+    /// Last progress of the running job, to detect stalled jobs.
+    pub activity: Activity,
+    /// Output of the running job, forwarded while a client follows the job.
+    pub live_output: LiveOutput,
+    /// Private scratch directory of the job, if the worker provides any.
+    pub sandbox: Option<PathBuf>,
+}
+
+/// Request to stop a running job, e.g., before it is moved to another worker.
+#[derive(Debug)]
+struct StopRequest {
+    /// Signal sent to the job's process.
+    signal: i32,
+    /// Seconds the job may take to exit, before it is killed.
+    grace_period_seconds: u64,
+}
+
+/// Master side of the pseudo terminal of an interactive job.
+struct Terminal {
+    master: Box<dyn MasterPty + Send>,
+    /// Input is written to the terminal by a dedicated thread.
+    input_tx: std::sync::mpsc::Sender<Vec<u8>>,
+}
+
+impl fmt::Debug for Terminal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Terminal").finish_non_exhaustive()
+    }
+}
+
+/// Output of a running job. While a client follows the job with `kueue log
+/// -f`, new output is forwarded to the server as it is produced.
+#[derive(Clone, Debug, Default)]
+pub struct LiveOutput(Arc<Mutex<LiveOutputState>>);
+
+#[derive(Debug, Default)]
+struct LiveOutputState {
+    /// Output of stdout and stderr to be sent with the job's results, unless
+    /// it is redirected to a file.
+    captured: [Vec<u8>; 2],
+    /// Number of bytes read from stdout and stderr, including redirected output.
+    read: [u64; 2],
+    /// Forwarded output is sent to this channel.
+    follower: Option<mpsc::Sender<WorkerToServerMessage>>,
+}
+
+impl LiveOutput {
+    /// Record output of the job. Returns the message to be forwarded, if the
+    /// job is followed.
+    fn append(
+        &self,
+        job_id: u64,
+        stream: OutputStream,
+        data: &[u8],
+        capture: bool,
+    ) -> Option<(mpsc::Sender<WorkerToServerMessage>, WorkerToServerMessage)> {
+        let mut state = self.0.lock().unwrap();
+        let index = stream as usize;
+        let offset = state.read[index];
+        state.read[index] += data.len() as u64;
+        if capture {
+            state.captured[index].extend_from_slice(data);
+        }
+        let follower = state.follower.clone()?;
+        let message = WorkerToServerMessage::JobLog {
+            job_id,
+            stream,
+            offset,
+            data: data.to_vec(),
+            catch_up: false,
+        };
+        Some((follower, message))
+    }
+
+    /// Start or stop forwarding output to the given channel. When starting,
+    /// returns the output captured so far, so that the server can catch up.
+    pub fn follow(
+        &self,
+        job_id: u64,
+        follower: Option<mpsc::Sender<WorkerToServerMessage>>,
+    ) -> Vec<WorkerToServerMessage> {
+        let mut state = self.0.lock().unwrap();
+        let follow = follower.is_some();
+        state.follower = follower;
+        if !follow {
+            return Vec::new();
+        }
+        [OutputStream::Stdout, OutputStream::Stderr]
+            .into_iter()
+            .map(|stream| {
+                let captured = &state.captured[stream as usize];
+                WorkerToServerMessage::JobLog {
+                    job_id,
+                    stream,
+                    offset: state.read[stream as usize] - captured.len() as u64,
+                    data: captured.clone(),
+                    catch_up: true,
+                }
+            })
+            .collect()
+    }
+
+    /// Take the captured stdout and stderr after the job has concluded.
+    fn take(&self) -> [Vec<u8>; 2] {
+        let mut state = self.0.lock().unwrap();
+        state.follower = None;
+        std::mem::take(&mut state.captured)
+    }
 }
 
 /// Status and outputs of the job after execution is concluded.
@@ -45,12 +351,82 @@ pub struct JobResult {
     pub stdout_text: String,
     /// All text that has been sent to stderr by the underlying process.
     pub stderr_text: String,
+    /// Outcome of the individual steps, if the job consists of multiple steps.
+    pub step_results: Vec<StepResult>,
+    /// Resources measured while the job was running.
+    pub resource_usage: Option<ResourceUsage>,
+    /// Reason the job failed, if it did not succeed.
+    pub failure: Option<FailureKind>,
+}
+
+/// Difference between the job confirmed by the server and the job offer the
+/// worker has accepted. The job must not be started in this case.
+#[derive(Debug, Error, PartialEq)]
+pub enum OfferMismatch {
+    /// The job would run in another directory than the one checked on offer.
+    #[error("working directory changed from {accepted:?} to {confirmed:?}")]
+    WorkingDirectory {
+        accepted: PathBuf,
+        confirmed: PathBuf,
+    },
+    /// The job requires more resources than have been reserved for it.
+    #[error("required resources grew from {accepted:?} to {confirmed:?}")]
+    Resources {
+        accepted: Resources,
+        confirmed: Resources,
+    },
+    /// Another property that defines how the job is run has changed.
+    #[error("{0} changed")]
+    Property(&'static str),
+}
+
+/// Replace placeholders like `{worker}` in an argument of a command with the
+/// given values. Doubled braces, e.g., `{{worker}}`, yield the placeholder
+/// itself. Unknown placeholders are left untouched, so that commands like
+/// `find -exec ls {} +` keep working.
+fn expand_placeholders(arg: &str, values: &[(&str, String)]) -> String {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut rest = arg;
+    'outer: while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            for (name, value) in values {
+                let escaped = format!("{{{{{}}}}}", name);
+                let placeholder = format!("{{{}}}", name);
+                if rest.starts_with(&escaped) {
+                    expanded.push_str(&placeholder);
+                    rest = &rest[escaped.len()..];
+                    continue 'outer;
+                }
+                if rest.starts_with(&placeholder) {
+                    expanded.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                    continue 'outer;
+                }
+            }
+        }
+        expanded.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    expanded
+}
+
+/// A single command executed as part of the job.
+#[derive(Clone, Debug)]
+struct Step {
+    /// Name of the step, e.g., "setup 1", "run", or "teardown 1".
+    name: String,
+    /// Command to be executed.
+    cmd: Vec<String>,
+    /// Run this step even if previous steps have failed.
+    always_run: bool,
 }
 
 impl Job {
     /// Setup a new job for execution.
     pub fn new(info: JobInfo, notify_job_status: Arc<Notify>) -> Self {
+        let (stop_job_tx, stop_job_rx) = mpsc::unbounded_channel();
         Job {
+            process_limits: info.process_limits,
             info,
             notify_job_status,
             result: Arc::new(Mutex::new(JobResult {
@@ -60,31 +436,296 @@ impl Job {
                 comment: String::new(),
                 stdout_text: String::new(),
                 stderr_text: String::new(),
+                step_results: Vec::new(),
+                resource_usage: None,
+                failure: None,
             })),
             notify_kill_job: Arc::new(Notify::new()),
+            stop_job_tx,
+            stop_job_rx: Some(stop_job_rx),
+            output_tx: None,
+            thread_count_variables: Vec::new(),
+            terminal: None,
+            accepted: Utc::now(),
+            payload: None,
+            gpu_devices: None,
+            cpu_affinity: None,
+            reserved_cpus: Vec::new(),
+            activity: Activity::new(),
+            live_output: LiveOutput::default(),
+            sandbox: None,
         }
     }
 
+    /// Reconcile the accepted job with the job info of the server's
+    /// confirmation. Status, priority, and other bookkeeping of the server
+    /// are taken over. Lower resource requirements are taken over as well,
+    /// while anything that changes how or where the job runs is an error.
+    pub fn reconcile_confirmation(&mut self, confirmed: JobInfo) -> Result<(), OfferMismatch> {
+        let accepted = &self.info;
+        if confirmed.cwd != accepted.cwd {
+            return Err(OfferMismatch::WorkingDirectory {
+                accepted: accepted.cwd.clone(),
+                confirmed: confirmed.cwd,
+            });
+        }
+        if !confirmed
+            .worker_resources
+            .fit_into(&accepted.worker_resources)
+        {
+            return Err(OfferMismatch::Resources {
+                accepted: accepted.worker_resources.clone(),
+                confirmed: confirmed.worker_resources,
+            });
+        }
+        let unchanged = [
+            ("job ID", confirmed.job_id == accepted.job_id),
+            ("command", confirmed.cmd == accepted.cmd),
+            ("user", confirmed.user == accepted.user),
+            ("setup steps", confirmed.setup_steps == accepted.setup_steps),
+            (
+                "teardown steps",
+                confirmed.teardown_steps == accepted.teardown_steps,
+            ),
+            (
+                "interactive mode",
+                confirmed.interactive == accepted.interactive,
+            ),
+            ("stdout path", confirmed.stdout_path == accepted.stdout_path),
+            ("stderr path", confirmed.stderr_path == accepted.stderr_path),
+            (
+                "encrypted payload",
+                confirmed.encrypted_payload == accepted.encrypted_payload,
+            ),
+            (
+                "environment",
+                confirmed.env_snapshot == accepted.env_snapshot,
+            ),
+            ("resource pool", confirmed.pool == accepted.pool),
+            ("checkpoint", confirmed.checkpoint == accepted.checkpoint),
+            (
+                "GPUs",
+                confirmed.gpus == accepted.gpus && confirmed.gpu_mode == accepted.gpu_mode,
+            ),
+            (
+                "core binding",
+                confirmed.core_binding == accepted.core_binding,
+            ),
+            (
+                "CPU time limit",
+                confirmed.cpu_time_limit_seconds == accepted.cpu_time_limit_seconds,
+            ),
+            (
+                "wall-time limit",
+                confirmed.wall_time_limit_seconds == accepted.wall_time_limit_seconds,
+            ),
+            (
+                "process limits",
+                confirmed.process_limits == accepted.process_limits,
+            ),
+            (
+                "exit code policies",
+                confirmed.exit_code_policies == accepted.exit_code_policies,
+            ),
+        ];
+        if let Some((property, _)) = unchanged.iter().find(|(_, unchanged)| !unchanged) {
+            return Err(OfferMismatch::Property(property));
+        }
+
+        if confirmed.worker_resources != accepted.worker_resources {
+            log::debug!(
+                "Job {} requires {:?} instead of {:?} as accepted.",
+                confirmed.job_id,
+                confirmed.worker_resources,
+                accepted.worker_resources
+            );
+        }
+        self.info = confirmed;
+        Ok(())
+    }
+
+    /// Send the signal to the running job, e.g., to let it write a checkpoint,
+    /// and kill it if it has not exited after the grace period. The job then
+    /// concludes with [`EXIT_CODE_STOPPED`].
+    pub fn stop(&self, signal: i32, grace_period_seconds: u64) {
+        let request = StopRequest {
+            signal,
+            grace_period_seconds,
+        };
+        if self.stop_job_tx.send(request).is_err() {
+            log::warn!("Job {} can no longer be stopped!", self.info.job_id);
+        }
+    }
+
+    /// Write input to the terminal of an interactive job.
+    pub fn write_input(&self, data: Vec<u8>) {
+        match &self.terminal {
+            Some(terminal) => {
+                if terminal.input_tx.send(data).is_err() {
+                    log::debug!("Terminal of job {} is closed!", self.info.job_id);
+                }
+            }
+            None => log::warn!("Job {} has no terminal!", self.info.job_id),
+        }
+    }
+
+    /// Resize the terminal of an interactive job.
+    pub fn resize_terminal(&self, rows: u16, cols: u16) {
+        if let Some(terminal) = &self.terminal {
+            let size = PtySize {
+                rows,
+                cols,
+                ..Default::default()
+            };
+            if let Err(e) = terminal.master.resize(size) {
+                log::warn!(
+                    "Failed to resize terminal of job {}: {}",
+                    self.info.job_id,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Hash of the commands of all steps, as recorded in provenance records.
+    pub fn command_sha256(&self) -> String {
+        let commands: Vec<Vec<String>> = self.steps().into_iter().map(|step| step.cmd).collect();
+        sha256_hex(&serde_json::to_vec(&commands).unwrap_or_default())
+    }
+
+    /// Steps of the job in order of execution: setup, run, and teardown.
+    /// Placeholders in the commands are expanded.
+    fn steps(&self) -> Vec<Step> {
+        let (cmd, setup_steps, teardown_steps) = match &self.payload {
+            Some(payload) => (&payload.cmd, &payload.setup_steps, &payload.teardown_steps),
+            None => (
+                &self.info.cmd,
+                &self.info.setup_steps,
+                &self.info.teardown_steps,
+            ),
+        };
+        let setup = setup_steps.iter().enumerate().map(|(i, cmd)| Step {
+            name: format!("setup {}", i + 1),
+            cmd: self.expand_command(cmd),
+            always_run: false,
+        });
+        let run = std::iter::once(Step {
+            name: "run".into(),
+            cmd: self.expand_command(cmd),
+            always_run: false,
+        });
+        let teardown = teardown_steps.iter().enumerate().map(|(i, cmd)| Step {
+            name: format!("teardown {}", i + 1),
+            cmd: self.expand_command(cmd),
+            always_run: true,
+        });
+        setup.chain(run).chain(teardown).collect()
+    }
+
+    /// Values of the placeholders in commands, which are only known once the
+    /// job has been placed on a worker. The worker name is unknown when the
+    /// job is run locally, so `{worker}` is left untouched then.
+    fn placeholders(&self) -> Vec<(&'static str, String)> {
+        let info = &self.info;
+        let mut values = vec![
+            ("job_id", info.job_id.to_string()),
+            ("cpus", info.worker_resources.cpus.to_string()),
+            ("ram_mb", info.worker_resources.ram_mb.to_string()),
+            (
+                "gpus",
+                self.gpu_devices.clone().unwrap_or_default().join(","),
+            ),
+        ];
+        if let JobStatus::Running { worker, .. } = &info.status {
+            values.push(("worker", worker.clone()));
+        }
+        values
+    }
+
+    /// Expand the placeholders in all arguments of the command.
+    fn expand_command(&self, cmd: &[String]) -> Vec<String> {
+        let values = self.placeholders();
+        cmd.iter()
+            .map(|arg| expand_placeholders(arg, &values))
+            .collect()
+    }
+
+    /// Standard variables describing the job, set in the environment of all
+    /// its processes. Scripts can use them to adapt thread counts or to write
+    /// output to per-job paths.
+    fn environment(&self) -> Vec<(String, String)> {
+        let info = &self.info;
+        let cpus = info.worker_resources.cpus.to_string();
+        let mut env = vec![
+            ("KUEUE_JOB_ID".into(), info.job_id.to_string()),
+            ("KUEUE_CPUS".into(), cpus.clone()),
+            (
+                "KUEUE_RAM_MB".into(),
+                info.worker_resources.ram_mb.to_string(),
+            ),
+        ];
+        if let JobStatus::Running { worker, .. } = &info.status {
+            env.push(("KUEUE_WORKER_NAME".into(), worker.clone()));
+        }
+        if let Some(group) = &info.group {
+            env.push(("KUEUE_GROUP".into(), group.clone()));
+        }
+        if let Some(checkpoint) = &info.checkpoint {
+            let checkpoint = checkpoint.to_string_lossy().into_owned();
+            env.push(("KUEUE_CHECKPOINT".into(), checkpoint));
+        }
+        if let Some(sandbox) = &self.sandbox {
+            let sandbox = sandbox.to_string_lossy().into_owned();
+            env.push(("KUEUE_SANDBOX".into(), sandbox));
+        }
+        // Hide GPUs of the worker that have not been assigned to the job.
+        if let Some(devices) = &self.gpu_devices {
+            env.push(("CUDA_VISIBLE_DEVICES".into(), devices.join(",")));
+        }
+        // Keep multi-threaded libraries from spawning a thread per core.
+        for variable in &self.thread_count_variables {
+            env.push((variable.clone(), cpus.clone()));
+        }
+        env
+    }
+
     /// Start executing the job.
     pub async fn run(&mut self) -> Result<()> {
-        if self.info.cmd.is_empty() {
-            bail!("Empty command!");
+        // Create the working directory if requested. This is not done before
+        // the job is started, so that declined offers leave nothing behind.
+        if self.info.create_cwd && !self.info.cwd.is_dir() {
+            if let Err(e) = std::fs::create_dir_all(&self.info.cwd) {
+                bail!(
+                    "Failed to create working directory {}: {}",
+                    self.info.cwd.to_string_lossy(),
+                    e
+                );
+            }
+            log::debug!(
+                "Created working directory {} for job {}!",
+                self.info.cwd.to_string_lossy(),
+                self.info.job_id
+            );
         }
 
-        // Set up command as subprocess.
-        let mut cmd = Command::new(self.info.cmd.first().unwrap());
-        cmd.current_dir(self.info.cwd.clone());
-        cmd.args(&self.info.cmd[1..]);
+        if self.info.interactive {
+            return self.run_interactive();
+        }
 
-        /// Pipe output or redirect to files.
+        let steps = self.steps();
+        if steps.iter().any(|step| step.cmd.is_empty()) {
+            bail!("Empty command!");
+        }
+
+        /// Discard output or redirect to files.
         async fn get_path_and_file(
             path: &Option<String>,
             cwd: &Path,
-        ) -> Result<(Stdio, Option<PathBuf>, Option<File>)> {
+        ) -> Result<(bool, Option<PathBuf>, Option<File>)> {
             match path {
                 Some(path) if path.to_lowercase().trim() == "null" => {
                     log::trace!("Redirect to null!");
-                    Ok((Stdio::null(), None, None))
+                    Ok((true, None, None))
                 }
                 Some(path) => {
                     log::trace!("Redirect to file!");
@@ -104,30 +745,85 @@ impl Job {
                         ),
                     };
 
-                    Ok((Stdio::piped(), Some(full_path), Some(file)))
+                    Ok((false, Some(full_path), Some(file)))
                 }
-                None => Ok((Stdio::piped(), None, None)),
+                None => Ok((false, None, None)),
             }
         }
 
         // FIXME: What happens if stdout_path == stderr_path?
-        cmd.stdin(Stdio::null());
-        let (cfg, stdout_path, mut stdout_file) =
+        let (stdout_null, stdout_path, mut stdout_file) =
             get_path_and_file(&self.info.stdout_path, &self.info.cwd).await?;
-        cmd.stdout(cfg);
-        let (cfg, stderr_path, mut stderr_file) =
+        let (stderr_null, stderr_path, mut stderr_file) =
             get_path_and_file(&self.info.stderr_path, &self.info.cwd).await?;
-        cmd.stderr(cfg);
 
-        // Spawn child process.
-        log::trace!("Running command: {}", self.info.cmd.join(" "));
+        /// Set up command of a step as subprocess and spawn it.
+        #[allow(clippy::too_many_arguments)]
+        fn spawn_step(
+            step: &Step,
+            cwd: &Path,
+            stdout_null: bool,
+            stderr_null: bool,
+            cpu_time_limit: Option<u64>,
+            process_limits: &ProcessLimits,
+            cpu_affinity: Option<&[usize]>,
+            env: &[(String, String)],
+        ) -> std::io::Result<Child> {
+            let stdio = |null: bool| if null { Stdio::null() } else { Stdio::piped() };
+            let mut cmd = Command::new(step.cmd.first().unwrap());
+            cmd.current_dir(cwd);
+            cmd.args(&step.cmd[1..]);
+            cmd.envs(env.iter().map(|(key, value)| (key, value)));
+            cmd.stdin(Stdio::null());
+            cmd.stdout(stdio(stdout_null));
+            cmd.stderr(stdio(stderr_null));
+            if let Some(seconds) = cpu_time_limit {
+                limit_cpu_time(&mut cmd, seconds);
+            }
+            set_process_limits(&mut cmd, process_limits);
+            if let Some(cpus) = cpu_affinity {
+                set_cpu_affinity(&mut cmd, cpus);
+            }
+            log::trace!("Running command: {}", step.cmd.join(" "));
+            cmd.spawn()
+        }
+
+        // Spawn first child process. Errors are reported to the caller.
         let start_time = Utc::now();
-        let mut child = cmd.spawn()?;
+        let cpu_time_limit = self.info.cpu_time_limit_seconds;
+        let wall_time_limit = self.info.wall_time_limit_seconds;
+        let deadline = wall_time_deadline(wall_time_limit);
+        let env = self.environment();
+        let cpu_affinity = self.cpu_affinity.clone();
+        let process_limits = self.process_limits;
+        let child = spawn_step(
+            &steps[0],
+            &self.info.cwd,
+            stdout_null,
+            stderr_null,
+            cpu_time_limit,
+            &process_limits,
+            cpu_affinity.as_deref(),
+            &env,
+        )?;
 
         let notify_job_status = Arc::clone(&self.notify_job_status);
         let job_result = Arc::clone(&self.result);
         let notify_kill_job = Arc::clone(&self.notify_kill_job);
         let job_id = self.info.job_id;
+        let cwd = self.info.cwd.clone();
+        // The steps of encrypted jobs are only known from their payload.
+        let report_steps = match &self.payload {
+            Some(payload) => payload.has_steps(),
+            None => self.info.has_steps(),
+        };
+        let activity = self.activity.clone();
+        activity.touch();
+        let live_output = self.live_output.clone();
+        let mut stop_job_rx = self
+            .stop_job_rx
+            .take()
+            .ok_or_else(|| anyhow!("Job {} has already been started!", job_id))?;
 
         tokio::spawn(async move {
             /// This is based on the implementation of wait_with_output from
@@ -135,81 +831,392 @@ impl Job {
             /// The problem with calling that function directly is that it
             /// _moves_ the child into the function, making it impossible to
             /// borrow it later for killing, if needed.
+            /// Any output is recorded as `activity` of the job and forwarded
+            /// while the job is followed.
             async fn read_or_copy<A: AsyncRead + Unpin, B: AsyncWrite + Unpin>(
                 io: &mut Option<A>,
                 file: &mut Option<B>,
-                path: Option<PathBuf>,
-            ) -> std::io::Result<Vec<u8>> {
-                let mut vec = Vec::new();
+                activity: &Activity,
+                live_output: &LiveOutput,
+                job_id: u64,
+                stream: OutputStream,
+            ) -> std::io::Result<()> {
                 if let Some(io) = io.as_mut() {
-                    // If input is available, read and...
-                    if let Some(file) = file.as_mut() {
-                        // ...copy it to redirect file.
-                        copy(io, file).await?;
-                        if let Some(path) = path {
-                            // Leave a hint that input has been redirected.
-                            let hint = format!("Redirected to {}", path.to_string_lossy());
-                            vec.extend(hint.as_bytes());
+                    let mut buffer = [0; 8192];
+                    loop {
+                        // If input is available, read and...
+                        let read = io.read(&mut buffer).await?;
+                        if read == 0 {
+                            break;
+                        }
+                        activity.touch();
+                        if let Some(file) = file.as_mut() {
+                            // ...copy it to redirect file.
+                            file.write_all(&buffer[..read]).await?;
                         }
-                    } else {
                         // ...or append input to buffer to send later.
-                        io.read_to_end(&mut vec).await?;
+                        let capture = file.is_none();
+                        let forward = live_output.append(job_id, stream, &buffer[..read], capture);
+                        if let Some((follower, message)) = forward {
+                            let _ = follower.send(message).await;
+                        }
+                    }
+                    if let Some(file) = file.as_mut() {
+                        file.flush().await?;
                     }
                 }
-                Ok(vec)
-            }
-
-            let mut stdout_pipe = child.stdout.take();
-            let mut stderr_pipe = child.stderr.take();
-
-            let stdout_fut = read_or_copy(&mut stdout_pipe, &mut stdout_file, stdout_path);
-            let stderr_fut = read_or_copy(&mut stderr_pipe, &mut stderr_file, stderr_path);
-
-            let combined_fut = try_join3(child.wait(), stdout_fut, stderr_fut);
-
-            log::trace!("Waiting for job {} to finish...", job_id);
-            tokio::select! {
-                combined_result = combined_fut => {
-                    log::trace!("Job {} finished orderly!",job_id);
-                    let finish_time = Utc::now();
-
-                    // When done, set exit status
-                    match combined_result {
-                        Ok((status, stdout, stderr)) => {
-                            let mut result_lock = job_result.lock().unwrap();
-                            result_lock.finished = true;
-                            result_lock.exit_code = status.code().unwrap_or(-44);
-                            result_lock.run_time = finish_time - start_time;
-                            result_lock.comment = "Job finished orderly.".into();
-                            result_lock.stdout_text = String::from_utf8(stdout)
-                                .unwrap_or("failed to parse stdout into utf-8 string".into());
-                            result_lock.stderr_text = String::from_utf8(stderr)
-                                .unwrap_or("failed to parse stderr into utf-8 string".into());
+                Ok(())
+            }
+
+            let mut step_results = Vec::new();
+            let mut exit_code = 0;
+            let mut comment = "Job finished orderly.".to_string();
+            let mut failed = false;
+            let mut failure = None;
+            let mut killed = false;
+            let mut timed_out = false;
+            let mut resource_usage: Option<ResourceUsage> = None;
+            let mut stop_deadline = None;
+
+            // Execute steps in order. The first step is already running.
+            let mut next_child = Some(Ok(child));
+            for (index, step) in steps.iter().enumerate() {
+                let step_start = Utc::now();
+
+                // Spawn the step's process, unless it is already running.
+                let spawned = match next_child.take() {
+                    Some(child) => child,
+                    None if killed || (failed && !step.always_run) => {
+                        // Skip remaining steps.
+                        step_results.push(StepResult {
+                            name: step.name.clone(),
+                            cmd: step.cmd.clone(),
+                            exit_code: None,
+                            run_time_seconds: 0,
+                        });
+                        continue;
+                    }
+                    None => spawn_step(
+                        step,
+                        &cwd,
+                        stdout_null,
+                        stderr_null,
+                        cpu_time_limit,
+                        &process_limits,
+                        cpu_affinity.as_deref(),
+                        &env,
+                    ),
+                };
+                let mut child = match spawned {
+                    Ok(child) => child,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to start step '{}' of job {}: {}",
+                            step.name,
+                            job_id,
+                            e
+                        );
+                        if !failed {
+                            failed = true;
+                            exit_code = EXIT_CODE_SPAWN_FAILURE;
+                            comment = format!("Failed to start step '{}': {}", step.name, e);
+                            failure = Some(FailureKind::SpawnFailure);
                         }
-                        Err(e) => {
-                            log::error!("Error while waiting for child process: {}", e);
-                            let mut result_lock = job_result.lock().unwrap();
-                            result_lock.finished = true;
-                            result_lock.exit_code = -45;
-                            result_lock.comment = format!("Error while waiting for child process: {}", e);
-                            result_lock.run_time = finish_time - start_time;
+                        step_results.push(StepResult {
+                            name: step.name.clone(),
+                            cmd: step.cmd.clone(),
+                            exit_code: Some(EXIT_CODE_SPAWN_FAILURE),
+                            run_time_seconds: 0,
+                        });
+                        continue;
+                    }
+                };
+
+                let pid = child.id();
+                let usage_monitor = pid.map(|pid| UsageMonitor::spawn(pid, activity.clone()));
+                let mut stdout_pipe = child.stdout.take();
+                let mut stderr_pipe = child.stderr.take();
+
+                log::trace!("Waiting for step {} of job {} to finish...", index, job_id);
+                let (step_exit_code, step_comment, step_failure) = loop {
+                    let stdout_fut = read_or_copy(
+                        &mut stdout_pipe,
+                        &mut stdout_file,
+                        &activity,
+                        &live_output,
+                        job_id,
+                        OutputStream::Stdout,
+                    );
+                    let stderr_fut = read_or_copy(
+                        &mut stderr_pipe,
+                        &mut stderr_file,
+                        &activity,
+                        &live_output,
+                        job_id,
+                        OutputStream::Stderr,
+                    );
+                    let combined_fut = try_join3(child.wait(), stdout_fut, stderr_fut);
+
+                    break tokio::select! {
+                        combined_result = combined_fut => {
+                            log::trace!("Step {} of job {} finished orderly!", index, job_id);
+
+                            // When done, collect exit status and outputs.
+                            match combined_result {
+                                Ok((status, (), ())) => {
+                                    if cpu_time_exceeded(&status) {
+                                        let limit = cpu_time_limit.unwrap_or_default();
+                                        let comment = format!("CPU time limit of {} seconds exceeded!", limit);
+                                        (EXIT_CODE_CPU_TIME_EXCEEDED, Some(comment), Some(FailureKind::Timeout))
+                                    } else if killed_by_sigkill(&status) {
+                                        let comment = "Killed by the system, most likely out of memory!";
+                                        (EXIT_CODE_KILLED_BY_SIGNAL, Some(comment.to_string()), Some(FailureKind::OutOfMemory))
+                                    } else {
+                                        (status.code().unwrap_or(EXIT_CODE_KILLED_BY_SIGNAL), None, None)
+                                    }
+                                }
+                                Err(e) => {
+                                    log::error!("Error while waiting for child process: {}", e);
+                                    (EXIT_CODE_WAIT_FAILURE, Some(format!("Error while waiting for child process: {}", e)), None)
+                                }
+                            }
+                        }
+                        _ = notify_kill_job.notified() => {
+                            log::trace!("Kill job {}!", job_id);
+                            if let Err(e) = child.kill().await {
+                                log::error!("Failed to kill job {}: {}", job_id, e);
+                            }
+                            killed = true;
+                            (EXIT_CODE_KILLED, Some("Job killed!".to_string()), Some(FailureKind::KilledByUser))
+                        }
+                        // Teardown steps still run after a timeout, without limit.
+                        _ = deadline_reached(deadline), if !timed_out => {
+                            log::debug!("Job {} exceeded its wall-time limit!", job_id);
+                            if let Err(e) = child.kill().await {
+                                log::error!("Failed to kill job {}: {}", job_id, e);
+                            }
+                            timed_out = true;
+                            (EXIT_CODE_WALL_TIME_EXCEEDED, Some(wall_time_comment(wall_time_limit)), Some(FailureKind::Timeout))
+                        }
+                        // Stopped jobs get a grace period to exit on their own.
+                        Some(request) = stop_job_rx.recv(), if stop_deadline.is_none() => {
+                            log::debug!("Stop job {} with signal {}!", job_id, request.signal);
+                            if let Some(pid) = pid {
+                                send_signal(pid, request.signal);
+                            }
+                            let grace_period = std::time::Duration::from_secs(request.grace_period_seconds);
+                            stop_deadline = Some(Instant::now() + grace_period);
+                            continue;
+                        }
+                        _ = deadline_reached(stop_deadline), if stop_deadline.is_some() => {
+                            log::debug!("Job {} did not exit within its grace period!", job_id);
+                            if let Err(e) = child.kill().await {
+                                log::error!("Failed to kill job {}: {}", job_id, e);
+                            }
+                            (EXIT_CODE_STOPPED, None, None)
+                        }
+                    };
+                };
+
+                // Stopped jobs conclude with the same exit code, however they exited.
+                let (step_exit_code, step_comment, step_failure) = if stop_deadline.is_some() {
+                    killed = true;
+                    let comment = "Job stopped, e.g., to be moved to another worker!".to_string();
+                    (
+                        EXIT_CODE_STOPPED,
+                        Some(comment),
+                        Some(FailureKind::KilledByUser),
+                    )
+                } else {
+                    (step_exit_code, step_comment, step_failure)
+                };
+
+                // Keep the highest usage of all steps.
+                if let Some(usage) = usage_monitor.and_then(UsageMonitor::stop) {
+                    resource_usage = Some(match resource_usage {
+                        Some(previous) => previous.combine(usage),
+                        None => usage,
+                    });
+                }
+
+                // The first failing step determines the exit code of the job.
+                if step_exit_code != 0 && (!failed || killed) {
+                    failed = true;
+                    exit_code = step_exit_code;
+                    failure = Some(step_failure.unwrap_or(FailureKind::NonzeroExit));
+                    if let Some(step_comment) = step_comment {
+                        comment = step_comment;
+                    } else if steps.len() > 1 {
+                        comment = format!(
+                            "Step '{}' failed with exit code {}.",
+                            step.name, step_exit_code
+                        );
+                    }
+                }
+
+                step_results.push(StepResult {
+                    name: step.name.clone(),
+                    cmd: step.cmd.clone(),
+                    exit_code: Some(step_exit_code),
+                    run_time_seconds: (Utc::now() - step_start).num_seconds(),
+                });
+            }
+
+            // Leave a hint that outputs have been redirected.
+            let [mut stdout, mut stderr] = live_output.take();
+            if let Some(path) = stdout_path {
+                stdout = format!("Redirected to {}", path.to_string_lossy()).into_bytes();
+            }
+            if let Some(path) = stderr_path {
+                stderr = format!("Redirected to {}", path.to_string_lossy()).into_bytes();
+            }
+
+            // When done, set exit status
+            {
+                let finish_time = Utc::now();
+                let mut result_lock = job_result.lock().unwrap();
+                result_lock.finished = true;
+                result_lock.exit_code = exit_code;
+                result_lock.run_time = finish_time - start_time;
+                result_lock.comment = comment;
+                result_lock.failure = failure;
+                result_lock.stdout_text = String::from_utf8(stdout)
+                    .unwrap_or("failed to parse stdout into utf-8 string".into());
+                result_lock.stderr_text = String::from_utf8(stderr)
+                    .unwrap_or("failed to parse stderr into utf-8 string".into());
+                if report_steps {
+                    result_lock.step_results = step_results;
+                }
+                result_lock.resource_usage = resource_usage;
+            }
+
+            // Notify main thread
+            log::trace!("Notify job {} done!", job_id);
+            notify_job_status.notify_one();
+        });
+
+        Ok(())
+    }
+
+    /// Start an interactive job inside a pseudo terminal. Terminal output
+    /// is sent to `output_tx` and input is passed in with `write_input`.
+    fn run_interactive(&mut self) -> Result<()> {
+        if self.info.cmd.is_empty() {
+            bail!("Empty command!");
+        }
+        let output_tx = self
+            .output_tx
+            .clone()
+            .ok_or_else(|| anyhow!("No output channel for interactive job!"))?;
+
+        // Open terminal and spawn the command in it.
+        let pair = native_pty_system().openpty(PtySize::default())?;
+        let command = self.expand_command(&self.info.cmd);
+        let mut cmd = CommandBuilder::new(&command[0]);
+        cmd.args(&command[1..]);
+        cmd.cwd(&self.info.cwd);
+        for (key, value) in self.environment() {
+            cmd.env(key, value);
+        }
+        log::trace!("Running interactive command: {}", command.join(" "));
+        let start_time = Utc::now();
+        let mut child = pair.slave.spawn_command(cmd)?;
+        drop(pair.slave); // otherwise, reading never ends
+
+        let mut reader = pair.master.try_clone_reader()?;
+        let mut writer = pair.master.take_writer()?;
+        let (input_tx, input_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        self.terminal = Some(Terminal {
+            master: pair.master,
+            input_tx,
+        });
+
+        let job_id = self.info.job_id;
+        let wall_time_limit = self.info.wall_time_limit_seconds;
+        let deadline = wall_time_deadline(wall_time_limit);
+
+        // Forward input to the terminal until the job is dropped.
+        std::thread::spawn(move || {
+            while let Ok(data) = input_rx.recv() {
+                if writer
+                    .write_all(&data)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        // Forward terminal output until the terminal is closed.
+        let output_reader = tokio::task::spawn_blocking(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        let data = buffer[..n].to_vec();
+                        let message = WorkerToServerMessage::JobOutput { job_id, data };
+                        if output_tx.blocking_send(message).is_err() {
+                            break;
                         }
                     }
                 }
+            }
+        });
+
+        let notify_job_status = Arc::clone(&self.notify_job_status);
+        let job_result = Arc::clone(&self.result);
+        let notify_kill_job = Arc::clone(&self.notify_kill_job);
+
+        tokio::spawn(async move {
+            let mut killer = child.clone_killer();
+            let wait = tokio::task::spawn_blocking(move || child.wait());
+
+            let (exit_code, comment, failure) = tokio::select! {
+                status = wait => match status {
+                    Ok(Ok(status)) => {
+                        let failure = (!status.success()).then_some(FailureKind::NonzeroExit);
+                        (status.exit_code() as i32, "Interactive session ended.".to_string(), failure)
+                    }
+                    Ok(Err(e)) => {
+                        log::error!("Error while waiting for interactive job: {}", e);
+                        let comment = format!("Error while waiting for child process: {}", e);
+                        (EXIT_CODE_WAIT_FAILURE, comment, Some(FailureKind::NonzeroExit))
+                    }
+                    Err(e) => {
+                        log::error!("Error while waiting for interactive job: {}", e);
+                        let comment = format!("Error while waiting for child process: {}", e);
+                        (EXIT_CODE_WAIT_FAILURE, comment, Some(FailureKind::NonzeroExit))
+                    }
+                },
                 _ = notify_kill_job.notified() => {
                     log::trace!("Kill job {}!", job_id);
-                    if let Err(e) = child.kill().await {
+                    if let Err(e) = killer.kill() {
                         log::error!("Failed to kill job {}: {}", job_id, e);
                     }
-
-                    // Update job result
-                    let finish_time = Utc::now();
-                    let mut result_lock = job_result.lock().unwrap();
-                    result_lock.finished = true;
-                    result_lock.exit_code = -46;
-                    result_lock.comment = "Job killed!".to_string();
-                    result_lock.run_time = finish_time - start_time;
+                    (EXIT_CODE_KILLED, "Job killed!".to_string(), Some(FailureKind::KilledByUser))
                 }
+                _ = deadline_reached(deadline) => {
+                    log::debug!("Job {} exceeded its wall-time limit!", job_id);
+                    if let Err(e) = killer.kill() {
+                        log::error!("Failed to kill job {}: {}", job_id, e);
+                    }
+                    (EXIT_CODE_WALL_TIME_EXCEEDED, wall_time_comment(wall_time_limit), Some(FailureKind::Timeout))
+                }
+            };
+
+            // Give the remaining output some time to be forwarded.
+            let _ = tokio::time::timeout(std::time::Duration::from_secs(1), output_reader).await;
+
+            // When done, set exit status
+            {
+                let mut result_lock = job_result.lock().unwrap();
+                result_lock.finished = true;
+                result_lock.exit_code = exit_code;
+                result_lock.run_time = Utc::now() - start_time;
+                result_lock.comment = comment;
+                result_lock.failure = failure;
             }
 
             // Notify main thread
@@ -220,3 +1227,269 @@ impl Job {
         Ok(())
     }
 }
+
+/// Run a job on the local machine, exactly like a worker would, and wait for
+/// it to conclude. This allows users to debug job definitions without a server.
+pub async fn run_locally(info: JobInfo, thread_count_variables: Vec<String>) -> Result<JobResult> {
+    if info.interactive {
+        bail!("Interactive jobs cannot be run locally!");
+    }
+    let notify = Arc::new(Notify::new());
+    let mut job = Job::new(info, Arc::clone(&notify));
+    job.thread_count_variables = thread_count_variables;
+    job.run().await?;
+    notify.notified().await;
+    let result = job.result.lock().unwrap().clone();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Resources;
+
+    #[tokio::test]
+    async fn run_job_steps() {
+        let mut info = JobInfo::new(
+            vec!["echo".into(), "run".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        info.setup_steps = vec![vec!["false".into()]];
+        info.teardown_steps = vec![vec!["echo".into(), "teardown".into()]];
+
+        let notify = Arc::new(Notify::new());
+        let mut job = Job::new(info, Arc::clone(&notify));
+        job.run().await.unwrap();
+        notify.notified().await;
+
+        // Failed setup skips the main command, but not the teardown.
+        let result = job.result.lock().unwrap();
+        assert_eq!(result.exit_code, 1);
+        assert_eq!(result.failure, Some(FailureKind::NonzeroExit));
+        assert_eq!(result.stdout_text, "teardown\n");
+        let exit_codes: Vec<_> = result.step_results.iter().map(|s| s.exit_code).collect();
+        assert_eq!(exit_codes, vec![Some(1), None, Some(0)]);
+    }
+
+    #[tokio::test]
+    async fn job_environment_variables() {
+        let mut info = JobInfo::new(
+            vec![
+                "sh".into(),
+                "-c".into(),
+                "echo $KUEUE_JOB_ID $KUEUE_CPUS $KUEUE_RAM_MB $KUEUE_WORKER_NAME".into(),
+            ],
+            std::env::temp_dir(),
+            Resources::new(1, 2, 512),
+            None,
+            None,
+            None,
+        );
+        info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        info.cmd[2] += " $OMP_NUM_THREADS";
+
+        let notify = Arc::new(Notify::new());
+        let mut job = Job::new(info.clone(), Arc::clone(&notify));
+        job.thread_count_variables = vec!["OMP_NUM_THREADS".into()];
+        job.run().await.unwrap();
+        notify.notified().await;
+
+        let result = job.result.lock().unwrap();
+        let expected = format!("{} 2 512 worker 2\n", info.job_id);
+        assert_eq!(result.stdout_text, expected);
+    }
+
+    #[test]
+    fn command_placeholders() {
+        let mut info = JobInfo::new(
+            vec![
+                "run.sh".into(),
+                "--out=results/{worker}/{job_id}".into(),
+                "--devices={gpus}".into(),
+                "{{worker}}".into(),
+                "{}".into(),
+            ],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        info.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        let mut job = Job::new(info.clone(), Arc::new(Notify::new()));
+        job.gpu_devices = Some(vec!["0".into(), "2".into()]);
+
+        let steps = job.steps();
+        assert_eq!(
+            steps[0].cmd,
+            vec![
+                "run.sh".to_string(),
+                format!("--out=results/worker/{}", info.job_id),
+                "--devices=0,2".into(),
+                "{worker}".into(),
+                "{}".into(),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cpu_time_limit() {
+        let mut info = JobInfo::new(
+            vec!["sh".into(), "-c".into(), "while :; do :; done".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        info.cpu_time_limit_seconds = Some(1);
+
+        let notify = Arc::new(Notify::new());
+        let mut job = Job::new(info, Arc::clone(&notify));
+        job.run().await.unwrap();
+        notify.notified().await;
+
+        // The busy loop is terminated after one second of CPU time.
+        let result = job.result.lock().unwrap();
+        assert_eq!(result.exit_code, EXIT_CODE_CPU_TIME_EXCEEDED);
+        assert_eq!(result.failure, Some(FailureKind::Timeout));
+    }
+
+    #[tokio::test]
+    async fn wall_time_limit() {
+        let mut info = JobInfo::new(
+            vec!["sleep".into(), "30".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        info.teardown_steps = vec![vec!["true".into()]];
+        info.wall_time_limit_seconds = Some(1);
+
+        let notify = Arc::new(Notify::new());
+        let mut job = Job::new(info, Arc::clone(&notify));
+        job.run().await.unwrap();
+        notify.notified().await;
+
+        // The job is killed after one second, but the teardown step still runs.
+        let result = job.result.lock().unwrap();
+        assert_eq!(result.exit_code, EXIT_CODE_WALL_TIME_EXCEEDED);
+        assert_eq!(result.failure, Some(FailureKind::Timeout));
+        assert!(result.comment.starts_with("Timeout"));
+        assert_eq!(result.step_results[1].exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn stop_job() {
+        let mut info = JobInfo::new(
+            vec!["sleep".into(), "30".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 1, 1),
+            None,
+            None,
+            None,
+        );
+        info.teardown_steps = vec![vec!["true".into()]];
+
+        let notify = Arc::new(Notify::new());
+        let mut job = Job::new(info, Arc::clone(&notify));
+        job.run().await.unwrap();
+        // Signal 0 is not delivered, so the job is killed after the grace period.
+        job.stop(0, 1);
+        notify.notified().await;
+
+        let result = job.result.lock().unwrap();
+        assert_eq!(result.exit_code, EXIT_CODE_STOPPED);
+        assert!(result.run_time.num_seconds() < 30);
+        assert_eq!(result.step_results[1].exit_code, None);
+    }
+
+    #[test]
+    fn live_output_catch_up() {
+        let output = LiveOutput::default();
+        assert!(output
+            .append(1, OutputStream::Stdout, b"abc", true)
+            .is_none());
+        assert!(output
+            .append(1, OutputStream::Stderr, b"redirected", false)
+            .is_none());
+
+        // Captured output is sent first, redirected output is skipped.
+        let (follower, _messages) = mpsc::channel(1);
+        let log = |stream, offset, data: &[u8], catch_up| WorkerToServerMessage::JobLog {
+            job_id: 1,
+            stream,
+            offset,
+            data: data.to_vec(),
+            catch_up,
+        };
+        let catch_up = output.follow(1, Some(follower));
+        assert_eq!(catch_up[0], log(OutputStream::Stdout, 0, b"abc", true));
+        assert_eq!(catch_up[1], log(OutputStream::Stderr, 10, b"", true));
+
+        let (_, message) = output.append(1, OutputStream::Stdout, b"d", true).unwrap();
+        assert_eq!(message, log(OutputStream::Stdout, 3, b"d", false));
+        assert_eq!(output.take(), [b"abcd".to_vec(), Vec::new()]);
+    }
+
+    #[test]
+    fn reconcile_confirmation() {
+        let info = JobInfo::new(
+            vec!["echo".into(), "run".into()],
+            std::env::temp_dir(),
+            Resources::new(1, 4, 1024),
+            None,
+            None,
+            None,
+        );
+        let mut job = Job::new(info.clone(), Arc::new(Notify::new()));
+
+        // Status, priority, and lower resources are taken over.
+        let mut confirmed = info.clone();
+        confirmed.status = JobStatus::Running {
+            issued: Utc::now(),
+            started: Utc::now(),
+            worker: "worker".into(),
+        };
+        confirmed.priority = 5;
+        confirmed.worker_resources.ram_mb = 512;
+        assert_eq!(job.reconcile_confirmation(confirmed.clone()), Ok(()));
+        assert_eq!(job.info, confirmed);
+
+        // More resources than reserved, other directories or commands are not.
+        let mut grown = confirmed.clone();
+        grown.worker_resources.cpus = 8;
+        assert!(matches!(
+            job.reconcile_confirmation(grown),
+            Err(OfferMismatch::Resources { .. })
+        ));
+        let mut moved = confirmed.clone();
+        moved.cwd = "/elsewhere".into();
+        assert!(matches!(
+            job.reconcile_confirmation(moved),
+            Err(OfferMismatch::WorkingDirectory { .. })
+        ));
+        let mut changed = confirmed.clone();
+        changed.cmd = vec!["rm".into(), "-rf".into()];
+        assert_eq!(
+            job.reconcile_confirmation(changed),
+            Err(OfferMismatch::Property("command"))
+        );
+        assert_eq!(job.info, confirmed);
+    }
+}
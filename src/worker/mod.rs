@@ -1,7 +1,15 @@
 mod job;
+mod cgroup;
 mod common;
+mod environment;
+mod gpu;
+mod sandbox;
+mod self_test;
 mod tcp;
 mod test;
+mod topology;
+mod usage;
 
+pub use job::{run_locally, JobResult};
 pub use tcp::TcpWorker;
 pub use test::TestWorker;
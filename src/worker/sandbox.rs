@@ -0,0 +1,197 @@
+//! Private scratch directories of jobs on the worker. Each job gets its own
+//! sandbox, named after the job ID, which is removed after the job succeeded.
+//! Sandboxes of failed jobs are kept for inspection until they expire or the
+//! retained sandboxes exceed their disk quota.
+
+use crate::config::WorkerSettings;
+use anyhow::Result;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Minimum time between two sweeps for expired sandboxes.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Written to sandboxes of concluded jobs. Its modification time tells when
+/// the job concluded.
+const FINISHED_MARKER: &str = ".kueue_finished";
+
+pub struct Sandboxes {
+    directory: PathBuf,
+    /// Keep sandboxes of successful jobs like those of failed jobs.
+    keep_succeeded: bool,
+    /// Time retained sandboxes are kept after their job has concluded.
+    retention: Duration,
+    /// Quota of all retained sandboxes. The oldest are removed first.
+    max_retained_bytes: Option<u64>,
+    /// Time of the last sweep for expired sandboxes.
+    last_sweep: Option<Instant>,
+}
+
+impl Sandboxes {
+    /// Sandboxes as configured in the worker settings, if enabled.
+    pub fn from_settings(settings: &WorkerSettings) -> Option<Self> {
+        let directory = settings.sandbox_directory.clone()?;
+        Some(Sandboxes {
+            directory,
+            keep_succeeded: settings.keep_succeeded_sandboxes,
+            retention: Duration::from_secs(settings.sandbox_retention_days * 24 * 60 * 60),
+            max_retained_bytes: settings
+                .max_retained_sandbox_mib
+                .map(|mib| mib * 1024 * 1024),
+            last_sweep: None,
+        })
+    }
+
+    /// Create an empty sandbox for the job. Leftovers of an earlier attempt
+    /// of the job are removed.
+    pub fn create(&self, job_id: u64) -> Result<PathBuf> {
+        let path = self.directory.join(job_id.to_string());
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+
+    /// Remove the sandbox of a successful job, or mark it as retained.
+    pub fn conclude(&self, sandbox: &Path, succeeded: bool) {
+        let result = if succeeded && !self.keep_succeeded {
+            fs::remove_dir_all(sandbox)
+        } else {
+            fs::write(sandbox.join(FINISHED_MARKER), "")
+        };
+        if let Err(e) = result {
+            log::error!("Failed to clean up {}: {}", sandbox.to_string_lossy(), e);
+        }
+    }
+
+    /// Remove retained sandboxes that have expired, then the oldest ones
+    /// until the retained sandboxes fit into their quota. Sandboxes of the
+    /// given active jobs are never removed. The directory is swept at most
+    /// once a minute. Returns the number of removed sandboxes.
+    pub fn clean_up(&mut self, active_jobs: &BTreeSet<u64>) -> usize {
+        if self
+            .last_sweep
+            .is_some_and(|last_sweep| last_sweep.elapsed() < SWEEP_INTERVAL)
+        {
+            return 0;
+        }
+        self.last_sweep = Some(Instant::now());
+
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to sweep sandbox directory: {}", e);
+                return 0;
+            }
+        };
+
+        // Sandboxes of jobs that are no longer active, oldest first.
+        let now = SystemTime::now();
+        let mut retained = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match name.parse::<u64>() {
+                Ok(job_id) if !active_jobs.contains(&job_id) => {}
+                _ => continue,
+            }
+            // Sandboxes left behind without marker, e.g., by a crashed worker,
+            // count from their last modification.
+            let path = entry.path();
+            let modified = fs::metadata(path.join(FINISHED_MARKER))
+                .or_else(|_| entry.metadata())
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(now);
+            retained.push((modified, path));
+        }
+        retained.sort();
+
+        let mut removed = 0;
+        let mut remove = |path: &Path| match fs::remove_dir_all(path) {
+            Ok(()) => removed += 1,
+            Err(e) => log::error!("Failed to remove {}: {}", path.to_string_lossy(), e),
+        };
+        retained.retain(|(modified, path)| {
+            let expired = now.duration_since(*modified).unwrap_or_default() >= self.retention;
+            if expired {
+                remove(path);
+            }
+            !expired
+        });
+        if let Some(max_bytes) = self.max_retained_bytes {
+            let sizes: Vec<u64> = retained.iter().map(|(_, path)| disk_usage(path)).collect();
+            let mut total: u64 = sizes.iter().sum();
+            for ((_, path), size) in retained.iter().zip(sizes) {
+                if total <= max_bytes {
+                    break;
+                }
+                remove(path);
+                total -= size;
+            }
+        }
+        removed
+    }
+}
+
+/// Total size of the files in the directory, without following symlinks.
+fn disk_usage(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| disk_usage(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_retention() {
+        let directory = std::env::temp_dir().join(format!("kueue-sandbox-{}", std::process::id()));
+        let mut sandboxes = Sandboxes {
+            directory: directory.clone(),
+            keep_succeeded: false,
+            retention: Duration::from_secs(3600),
+            max_retained_bytes: Some(1000),
+            last_sweep: None,
+        };
+
+        // Sandboxes of successful jobs are removed right away.
+        let succeeded = sandboxes.create(1).unwrap();
+        sandboxes.conclude(&succeeded, true);
+        assert!(!succeeded.exists());
+
+        // Retained sandboxes exceeding the quota are removed, oldest first.
+        let failed = sandboxes.create(2).unwrap();
+        fs::write(failed.join("data"), [0; 800]).unwrap();
+        sandboxes.conclude(&failed, false);
+        let running = sandboxes.create(3).unwrap();
+        fs::write(running.join("data"), [0; 800]).unwrap();
+        assert_eq!(sandboxes.clean_up(&BTreeSet::from([3])), 0);
+        sandboxes.conclude(&running, false);
+        sandboxes.last_sweep = None;
+        assert_eq!(sandboxes.clean_up(&BTreeSet::new()), 1);
+        assert!(!failed.exists());
+        assert!(running.exists());
+
+        // Expired sandboxes are removed.
+        sandboxes.retention = Duration::ZERO;
+        sandboxes.last_sweep = None;
+        assert_eq!(sandboxes.clean_up(&BTreeSet::new()), 1);
+        assert!(!running.exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+//! Self-test of the worker's environment, run before accepting any jobs.
+
+use crate::{config::SelfTestProbe, structs::ProbeResult};
+use std::process::Stdio;
+use tokio::{
+    process::Command,
+    time::{timeout, Duration},
+};
+
+/// Run all probes one after another and collect their results.
+pub async fn run_self_test(probes: &[SelfTestProbe], timeout_seconds: u64) -> Vec<ProbeResult> {
+    let mut results = Vec::new();
+    for probe in probes {
+        let result = run_probe(probe, Duration::from_secs(timeout_seconds)).await;
+        if result.passed {
+            log::info!(
+                "Self-test probe '{}' passed: {}",
+                result.name,
+                result.output
+            );
+        } else {
+            log::warn!(
+                "Self-test probe '{}' failed: {}",
+                result.name,
+                result.output
+            );
+        }
+        results.push(result);
+    }
+    results
+}
+
+/// Run a single probe in a shell. The probe passes if it exits successfully.
+async fn run_probe(probe: &SelfTestProbe, time_limit: Duration) -> ProbeResult {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&probe.command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let (passed, output) = match timeout(time_limit, output).await {
+        Ok(Ok(output)) => {
+            let status = output.status;
+            // Prefer the error message, if the probe printed any.
+            let text = if output.stderr.is_empty() {
+                output.stdout
+            } else {
+                output.stderr
+            };
+            let text = String::from_utf8_lossy(&text);
+            let last_line = text.lines().last().unwrap_or_default().trim();
+            let output = if last_line.is_empty() {
+                format!("exited with {}", status)
+            } else {
+                last_line.to_string()
+            };
+            (status.success(), output)
+        }
+        Ok(Err(e)) => (false, format!("failed to run: {}", e)),
+        Err(_) => (false, format!("timed out after {:?}", time_limit)),
+    };
+
+    ProbeResult {
+        name: probe.name.clone(),
+        passed,
+        output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probes_pass_and_fail() {
+        let probe = |name: &str, command: &str| SelfTestProbe {
+            name: name.into(),
+            command: command.into(),
+        };
+        let probes = vec![
+            probe("scratch", "echo writable"),
+            probe("compiler", "echo 'not found' >&2; exit 127"),
+            probe("license", "sleep 5"),
+        ];
+        let results = run_self_test(&probes, 1).await;
+
+        assert!(results[0].passed);
+        assert_eq!(results[0].output, "writable");
+        assert!(!results[1].passed);
+        assert_eq!(results[1].output, "not found");
+        assert!(!results[2].passed);
+    }
+}
@@ -1,9 +1,14 @@
 //! This module handles the communication with the server.
 
-use crate::{config::Config, messages::stream::MessageStream, worker::common::Worker};
+use crate::{
+    config::Config,
+    messages::stream::MessageStream,
+    tls::{self, ServerStream},
+    worker::{common::Worker, self_test::run_self_test},
+};
 use anyhow::{bail, Result};
+use std::cmp::min;
 use tokio::{
-    net::TcpStream,
     sync::mpsc::{channel, Receiver}, time::{sleep, Duration},
 };
 use tokio_util::sync::CancellationToken;
@@ -15,6 +20,8 @@ pub struct TcpWorker {
     worker_name: String,
     /// Handles for graceful shutdown of the worker.
     shutdown: Option<(CancellationToken, Receiver<()>)>,
+    /// Run the self-test before accepting any jobs.
+    self_test: bool,
 }
 
 impl TcpWorker {
@@ -28,19 +35,28 @@ impl TcpWorker {
             config,
             worker_name: hostname,
             shutdown: None,
+            self_test: false,
         }
     }
 
+    /// Run the configured self-test probes after connecting to the server
+    /// and report their results before accepting any jobs.
+    pub fn enable_self_test(&mut self) {
+        self.self_test = true;
+    }
+
     /// Connect to the server and start processing jobs.
     pub async fn start(&mut self) -> Result<()> {
         if self.shutdown.is_some() {
             bail!("Worker is already started!");
         }
+        if self.self_test && self.config.worker_settings.self_test_probes.is_empty() {
+            bail!("No self-test probes configured!");
+        }
 
         // Connect to the server.
-        let server_addr = self.config.get_server_address().await?;
-        let stream = TcpStream::connect(server_addr).await?;
-        let stream = MessageStream::new(stream);
+        let stream = tls::connect(&self.config).await?;
+        let stream = message_stream(stream, &self.config);
 
         // Prepare handles for graceful shutdown later.
         let cancel_token = CancellationToken::new();
@@ -55,10 +71,7 @@ impl TcpWorker {
             keep_alive.clone(),
         );
 
-        // Perform hello/welcome handshake and challenge-response authentication.
-        worker.connect_to_server().await?;
-        worker.authenticate().await?;
-
+        establish(&mut worker, &self.config, self.self_test).await?;
         log::info!("Established connection to server!");
 
         // Send regular updates about system, load, and resources to the server.
@@ -69,22 +82,30 @@ impl TcpWorker {
         tokio::spawn(async move {
             loop {
                 // Send the first system update immediately.
-                let mut wake_up = sleep(Duration::from_secs(0));
+                notify_system_update.notify_one();
                 tokio::select! {
                     _ = cancel_system_update.cancelled() => { break; }
-                    _ = wake_up => {
-                        notify_system_update.notify_one();
-                        wake_up = sleep(Duration::from_secs(update_interval));
-                    }
+                    _ = sleep(Duration::from_secs(update_interval)) => {}
                 }
             }
             drop(keep_alive_system_update);
         });
 
-        // Handle messages and interrupts.
+        // Handle messages and interrupts. Reconnect if the connection is lost.
+        let config = self.config.clone();
+        let self_test = self.self_test;
+        let cancel_reconnect = cancel_token.clone();
         tokio::spawn(async move {
-            if let Err(e) = worker.run().await {
-                log::error!("Worker stopped due to error: {e}");
+            loop {
+                match worker.run().await {
+                    Ok(()) => log::warn!("Connection closed by server!"),
+                    Err(e) => log::error!("Lost connection to server: {e}"),
+                }
+                if cancel_reconnect.is_cancelled()
+                    || !reconnect(&mut worker, &config, self_test, &cancel_reconnect).await
+                {
+                    break; // worker is shutting down
+                }
             }
         });
 
@@ -110,3 +131,72 @@ impl TcpWorker {
         Ok(())
     }
 }
+
+/// Perform hello/welcome handshake and challenge-response authentication.
+async fn establish(
+    worker: &mut Worker<ServerStream>,
+    config: &Config,
+    self_test: bool,
+) -> Result<()> {
+    worker.connect_to_server().await?;
+    worker.authenticate().await?;
+
+    // Report the self-test results before the first resource update,
+    // so that no jobs are offered before the server knows the results.
+    if self_test {
+        let settings = &config.worker_settings;
+        let probes = &settings.self_test_probes;
+        let results = run_self_test(probes, settings.self_test_timeout_seconds).await;
+        worker.report_self_test(results).await?;
+    }
+    Ok(())
+}
+
+/// Wrap the connection to the server, limiting the upload bandwidth if set.
+fn message_stream(stream: ServerStream, config: &Config) -> MessageStream<ServerStream> {
+    let stream = MessageStream::new(stream);
+    match config.worker_settings.max_upload_kib_per_second {
+        Some(limit) => stream.with_rate_limit(limit * 1024),
+        None => stream,
+    }
+}
+
+/// Try to reconnect to the server, doubling the delay between attempts up to
+/// the configured maximum. Jobs keep running meanwhile and are announced to
+/// the server once reconnected. Returns `false` if the worker is shut down.
+async fn reconnect(
+    worker: &mut Worker<ServerStream>,
+    config: &Config,
+    self_test: bool,
+    cancel_token: &CancellationToken,
+) -> bool {
+    let settings = &config.worker_settings;
+    let mut delay = Duration::from_secs(settings.reconnect_initial_delay_seconds.max(1));
+    let max_delay = Duration::from_secs(settings.reconnect_max_delay_seconds).max(delay);
+    loop {
+        log::info!("Reconnecting to server in {} seconds...", delay.as_secs());
+        tokio::select! {
+            _ = cancel_token.cancelled() => return false,
+            _ = sleep(delay) => {}
+        }
+
+        let attempt = async {
+            let stream = tls::connect(config).await?;
+            worker.replace_stream(message_stream(stream, config));
+            establish(worker, config, self_test).await?;
+            worker.announce_jobs().await?;
+            Ok::<(), anyhow::Error>(())
+        };
+        tokio::select! {
+            _ = cancel_token.cancelled() => return false,
+            result = attempt => match result {
+                Ok(()) => {
+                    log::info!("Reconnected to server!");
+                    return true;
+                }
+                Err(e) => log::warn!("Failed to reconnect to server: {e}"),
+            }
+        }
+        delay = min(delay * 2, max_delay);
+    }
+}
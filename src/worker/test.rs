@@ -1,6 +1,7 @@
 use config::Config;
 
 pub struct TestWorker {
+    #[allow(dead_code)]
     config: Config,
 }
 
@@ -13,12 +14,11 @@ impl TestWorker {
 #[cfg(test)]
 mod tests {
     use crate::{config::Config, server::TestServer};
-    use tokio::io::duplex;
 
     #[tokio::test]
     async fn server_and_worker() {
         let config = Config::new(None).unwrap();
-        let server = TestServer::new(config);
+        let _server = TestServer::new(config);
         // let worker = TestWorker::new(config);
 
         // let (mut server_stream, mut worker_stream) = duplex(64);
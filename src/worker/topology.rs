@@ -0,0 +1,280 @@
+//! CPU topology of the worker: physical cores with their hardware threads
+//! (SMT) and, on hybrid processors, performance and efficiency cores. Jobs
+//! with core binding are pinned to whole physical cores.
+
+use crate::structs::CoreBinding;
+use std::{collections::BTreeSet, fs::read_to_string, path::Path};
+
+/// Directory of the CPU devices in the sysfs.
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+/// List of the efficiency cores of hybrid Intel processors.
+const SYSFS_ATOM_CPUS: &str = "/sys/devices/cpu_atom/cpus";
+
+/// A physical CPU core.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicalCore {
+    /// Logical CPUs (hardware threads) of the core.
+    pub threads: Vec<usize>,
+    /// Set for efficiency cores of hybrid processors.
+    pub efficiency: bool,
+}
+
+/// Physical cores the worker may use.
+#[derive(Clone, Debug, Default)]
+pub struct CpuTopology {
+    pub cores: Vec<PhysicalCore>,
+}
+
+/// Outcome of assigning CPUs to a job.
+#[derive(Debug, PartialEq)]
+pub enum CoreAllocation {
+    Granted {
+        /// CPUs the job is pinned to. `None`, if the job is not pinned.
+        affinity: Option<Vec<usize>>,
+        /// CPUs held by the job and not available to other bound jobs.
+        reserved: Vec<usize>,
+    },
+    /// Not enough physical cores are free at the moment.
+    Busy,
+    /// The worker does not have enough physical cores at all.
+    Impossible,
+}
+
+impl CpuTopology {
+    /// Detect the topology of the CPUs the worker may run on. Without
+    /// topology information, each logical CPU is treated as a physical core.
+    pub fn detect() -> Self {
+        let cpus = allowed_cpus();
+        let efficiency_cpus: BTreeSet<usize> = match read_to_string(SYSFS_ATOM_CPUS) {
+            Ok(text) => parse_cpu_list(&text).into_iter().collect(),
+            Err(_) => low_capacity_cpus(&cpus),
+        };
+        let mut cores: Vec<PhysicalCore> = Vec::new();
+        for cpu in &cpus {
+            if cores.iter().any(|core| core.threads.contains(cpu)) {
+                continue; // sibling of a previous CPU
+            }
+            let siblings_path = format!("{}/cpu{}/topology/thread_siblings_list", SYSFS_CPU, cpu);
+            let threads = match read_to_string(siblings_path) {
+                Ok(text) => parse_cpu_list(&text)
+                    .into_iter()
+                    .filter(|sibling| cpus.contains(sibling))
+                    .collect(),
+                Err(_) => vec![*cpu],
+            };
+            cores.push(PhysicalCore {
+                threads,
+                efficiency: efficiency_cpus.contains(cpu),
+            });
+        }
+        CpuTopology { cores }
+    }
+
+    /// Number of logical CPUs.
+    pub fn logical_cpus(&self) -> u64 {
+        self.cores
+            .iter()
+            .map(|core| core.threads.len() as u64)
+            .sum()
+    }
+
+    /// Number of efficiency cores. Zero, unless the processor is hybrid.
+    pub fn efficiency_cores(&self) -> u64 {
+        self.cores.iter().filter(|core| core.efficiency).count() as u64
+    }
+
+    /// Select CPUs for a job with `cpus` cores, considering the CPUs
+    /// `reserved` by other jobs. Bound jobs get whole physical cores,
+    /// preferably performance cores. Other jobs are pinned to the CPUs that
+    /// are not reserved, if any are.
+    pub fn allocate(
+        &self,
+        cpus: u64,
+        binding: Option<CoreBinding>,
+        reserved: &BTreeSet<usize>,
+    ) -> CoreAllocation {
+        let Some(binding) = binding else {
+            if reserved.is_empty() {
+                return CoreAllocation::Granted {
+                    affinity: None,
+                    reserved: Vec::new(),
+                };
+            }
+            let free: Vec<usize> = self
+                .cores
+                .iter()
+                .flat_map(|core| core.threads.iter().copied())
+                .filter(|cpu| !reserved.contains(cpu))
+                .collect();
+            if free.is_empty() {
+                return CoreAllocation::Busy;
+            }
+            return CoreAllocation::Granted {
+                affinity: Some(free),
+                reserved: Vec::new(),
+            };
+        };
+
+        if cpus as usize > self.cores.len() {
+            return CoreAllocation::Impossible;
+        }
+        let mut free: Vec<&PhysicalCore> = self
+            .cores
+            .iter()
+            .filter(|core| core.threads.iter().all(|cpu| !reserved.contains(cpu)))
+            .collect();
+        if free.len() < cpus as usize {
+            return CoreAllocation::Busy;
+        }
+        free.sort_by_key(|core| core.efficiency);
+        free.truncate(cpus as usize);
+        let affinity = match binding {
+            CoreBinding::PhysicalCores => free
+                .iter()
+                .flat_map(|core| &core.threads)
+                .copied()
+                .collect(),
+            CoreBinding::NoSmt => free
+                .iter()
+                .filter_map(|core| core.threads.first())
+                .copied()
+                .collect(),
+        };
+        CoreAllocation::Granted {
+            affinity: Some(affinity),
+            reserved: free
+                .iter()
+                .flat_map(|core| &core.threads)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+/// Logical CPUs the worker process may run on.
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Vec<usize> {
+    // Safety: The set is initialized by sched_getaffinity.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) == 0 {
+            return (0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+                .collect();
+        }
+    }
+    fallback_cpus()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Vec<usize> {
+    fallback_cpus()
+}
+
+/// Logical CPUs according to the available parallelism.
+fn fallback_cpus() -> Vec<usize> {
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    (0..cpus).collect()
+}
+
+/// CPUs with less than the maximum capacity, e.g., the little cores of ARM
+/// big.LITTLE processors.
+fn low_capacity_cpus(cpus: &[usize]) -> BTreeSet<usize> {
+    let capacities: Vec<(usize, u64)> = cpus
+        .iter()
+        .filter_map(|cpu| {
+            let path = Path::new(SYSFS_CPU).join(format!("cpu{}/cpu_capacity", cpu));
+            let capacity = read_to_string(path).ok()?.trim().parse().ok()?;
+            Some((*cpu, capacity))
+        })
+        .collect();
+    let max_capacity = capacities.iter().map(|(_, capacity)| *capacity).max();
+    capacities
+        .into_iter()
+        .filter(|(_, capacity)| Some(*capacity) < max_capacity)
+        .map(|(cpu, _)| cpu)
+        .collect()
+}
+
+/// Parse a list of CPUs as used by the sysfs, e.g., "0-3,8,10-11".
+fn parse_cpu_list(text: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in text.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut bounds = range
+            .splitn(2, '-')
+            .map(|bound| bound.trim().parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), None) => cpus.push(first),
+            (Some(Ok(first)), Some(Ok(last))) => cpus.extend(first..=last),
+            _ => log::warn!("Invalid CPU list: {}", text.trim()),
+        }
+    }
+    cpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_allocation() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+
+        // Two cores with SMT and one efficiency core.
+        let topology = CpuTopology {
+            cores: vec![
+                PhysicalCore {
+                    threads: vec![4],
+                    efficiency: true,
+                },
+                PhysicalCore {
+                    threads: vec![0, 2],
+                    efficiency: false,
+                },
+                PhysicalCore {
+                    threads: vec![1, 3],
+                    efficiency: false,
+                },
+            ],
+        };
+        assert_eq!(topology.logical_cpus(), 5);
+        assert_eq!(topology.efficiency_cores(), 1);
+
+        let mut reserved = BTreeSet::new();
+        let granted = topology.allocate(1, Some(CoreBinding::NoSmt), &reserved);
+        assert_eq!(
+            granted,
+            CoreAllocation::Granted {
+                affinity: Some(vec![0]),
+                reserved: vec![0, 2]
+            }
+        );
+        reserved.extend([0, 2]);
+        let granted = topology.allocate(2, Some(CoreBinding::PhysicalCores), &reserved);
+        assert_eq!(
+            granted,
+            CoreAllocation::Granted {
+                affinity: Some(vec![1, 3, 4]),
+                reserved: vec![1, 3, 4]
+            }
+        );
+        assert_eq!(
+            topology.allocate(3, Some(CoreBinding::NoSmt), &reserved),
+            CoreAllocation::Busy
+        );
+        assert_eq!(
+            topology.allocate(4, Some(CoreBinding::NoSmt), &reserved),
+            CoreAllocation::Impossible
+        );
+
+        // Unbound jobs avoid the cores of bound jobs.
+        assert_eq!(
+            topology.allocate(2, None, &reserved),
+            CoreAllocation::Granted {
+                affinity: Some(vec![4, 1, 3]),
+                reserved: vec![]
+            }
+        );
+    }
+}
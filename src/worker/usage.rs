@@ -0,0 +1,135 @@
+//! Measures the resources actually used by running jobs.
+
+use crate::structs::ResourceUsage;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+use sysinfo::{Pid, PidExt, Process, ProcessExt, System, SystemExt};
+use tokio::{
+    task::JoinHandle,
+    time::{interval, Duration},
+};
+
+/// Time between two samples of a job's resource usage.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// CPU usage (in percent of a core) above which a job is considered active.
+const ACTIVE_CPU_USAGE: f32 = 1.0;
+
+/// Point in time a job has last shown progress, i.e., consumed CPU time or
+/// produced output. Used to detect stalled jobs.
+#[derive(Clone, Debug)]
+pub struct Activity(Arc<Mutex<DateTime<Utc>>>);
+
+impl Activity {
+    pub fn new() -> Self {
+        Activity(Arc::new(Mutex::new(Utc::now())))
+    }
+
+    /// Record progress of the job.
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Utc::now();
+    }
+
+    /// Seconds since the job has last shown progress.
+    pub fn idle_seconds(&self) -> i64 {
+        (Utc::now() - *self.0.lock().unwrap()).num_seconds()
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Periodically samples CPU and memory usage of a process and its children.
+pub struct UsageMonitor {
+    usage: Arc<Mutex<UsageSamples>>,
+    sampler: JoinHandle<()>,
+}
+
+/// Accumulated samples of a process tree.
+#[derive(Default)]
+struct UsageSamples {
+    peak_ram_bytes: u64,
+    cpus_sum: f64,
+    cpu_samples: u32,
+}
+
+impl UsageMonitor {
+    /// Start sampling the process with the given ID. CPU usage of the process
+    /// is recorded as `activity`.
+    pub fn spawn(pid: u32, activity: Activity) -> Self {
+        let usage = Arc::new(Mutex::new(UsageSamples::default()));
+        let samples = Arc::clone(&usage);
+        let sampler = tokio::spawn(async move {
+            let root = Pid::from_u32(pid);
+            let mut system = System::new();
+            let mut timer = interval(SAMPLE_INTERVAL);
+            let mut known_pids = HashSet::new();
+            loop {
+                timer.tick().await;
+                system.refresh_processes();
+                let tree = process_tree(&system, root);
+                let mut samples = samples.lock().unwrap();
+                let ram_bytes = tree.iter().map(|(_, process)| process.memory()).sum();
+                samples.peak_ram_bytes = samples.peak_ram_bytes.max(ram_bytes);
+                // CPU usage is computed between two refreshes, so it is only
+                // valid for processes that have been seen before.
+                if !known_pids.is_empty() {
+                    let cpu_usage: f32 = tree
+                        .iter()
+                        .filter(|(pid, _)| known_pids.contains(pid))
+                        .map(|(_, process)| process.cpu_usage())
+                        .sum();
+                    samples.cpus_sum += cpu_usage as f64 / 100.0;
+                    samples.cpu_samples += 1;
+                    if cpu_usage > ACTIVE_CPU_USAGE {
+                        activity.touch();
+                    }
+                }
+                known_pids = tree.into_iter().map(|(pid, _)| pid).collect();
+            }
+        });
+        UsageMonitor { usage, sampler }
+    }
+
+    /// Stop sampling and return the measured usage, if any samples were taken.
+    pub fn stop(self) -> Option<ResourceUsage> {
+        self.sampler.abort();
+        let samples = self.usage.lock().unwrap();
+        if samples.cpu_samples == 0 {
+            return None;
+        }
+        Some(ResourceUsage {
+            avg_cpus: samples.cpus_sum / samples.cpu_samples as f64,
+            peak_ram_mb: samples.peak_ram_bytes / (1024 * 1024),
+        })
+    }
+}
+
+/// Returns the process and all its descendants.
+fn process_tree(system: &System, root: Pid) -> Vec<(Pid, &Process)> {
+    let is_descendant = |mut pid: Pid| {
+        // Limit the depth to not get stuck on cyclic parent relations.
+        for _ in 0..32 {
+            if pid == root {
+                return true;
+            }
+            match system.process(pid).and_then(|process| process.parent()) {
+                Some(parent) => pid = parent,
+                None => return false,
+            }
+        }
+        false
+    };
+    system
+        .processes()
+        .iter()
+        .filter(|(pid, _)| is_descendant(**pid))
+        .map(|(pid, process)| (*pid, process))
+        .collect()
+}
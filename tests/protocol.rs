@@ -0,0 +1,180 @@
+use base64::{engine::general_purpose, Engine};
+use kueue_lib::{
+    config::Config,
+    messages::{
+        stream::{MessageStream, WireFormat},
+        version::{capabilities, KUEUE_VERSION},
+        ClientToServerMessage, HelloMessage, ServerToClientMessage,
+    },
+    server::TestServer,
+    structs::{JobInfo, Resources},
+    testing::{message_stream_pair, FakeServer, FakeWorker},
+};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use tokio::io::duplex;
+
+fn hello_from_client(capabilities: BTreeSet<String>) -> HelloMessage {
+    HelloMessage::HelloFromClient {
+        kueue_version: KUEUE_VERSION.into(),
+        capabilities,
+        tenant: None,
+    }
+}
+
+#[tokio::test]
+async fn worker_registers_with_server() {
+    let config = Config::new(None).unwrap();
+    let mut server = TestServer::new(config.clone());
+
+    // Connect fake worker.
+    let (mut worker, worker_stream) = FakeWorker::new("fake-worker", config.clone());
+    server.connect(worker_stream).unwrap();
+    worker.connect().await.unwrap();
+
+    // The worker is listed to clients.
+    let (client_stream, server_stream) = duplex(1024);
+    server.connect(server_stream).unwrap();
+    let mut client = MessageStream::new(client_stream);
+    client
+        .send(&hello_from_client(capabilities()))
+        .await
+        .unwrap();
+    let welcome = client.receive::<ServerToClientMessage>().await.unwrap();
+    assert!(matches!(
+        welcome,
+        ServerToClientMessage::WelcomeClient { .. }
+    ));
+    client.negotiate_format(&capabilities());
+    assert_eq!(client.format(), WireFormat::BinaryFrames);
+    client
+        .send(&ClientToServerMessage::ListWorkers)
+        .await
+        .unwrap();
+    match client.receive::<ServerToClientMessage>().await.unwrap() {
+        ServerToClientMessage::WorkerList(workers) => {
+            assert_eq!(workers.len(), 1);
+            assert_eq!(workers[0].worker_name, "fake-worker");
+        }
+        other => panic!("Expected WorkerList, received: {:?}", other),
+    }
+
+    client.send(&ClientToServerMessage::Bye).await.unwrap();
+    worker.disconnect().await.unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn scripted_fake_server() {
+    let config = Config::new(None).unwrap();
+    let (mut server, stream) = FakeServer::new(&config);
+    let fake = tokio::spawn(async move {
+        server.accept_client().await?;
+        server
+            .serve(|message| match message {
+                ClientToServerMessage::ListWorkers => {
+                    vec![ServerToClientMessage::WorkerList(vec![])]
+                }
+                _ => vec![],
+            })
+            .await
+    });
+
+    // A client without binary frames is served with JSON.
+    let mut client = MessageStream::new(stream);
+    client
+        .send(&hello_from_client(BTreeSet::new()))
+        .await
+        .unwrap();
+    let welcome = client.receive::<ServerToClientMessage>().await.unwrap();
+    assert!(matches!(
+        welcome,
+        ServerToClientMessage::WelcomeClient { .. }
+    ));
+    client
+        .send(&ClientToServerMessage::ListWorkers)
+        .await
+        .unwrap();
+    assert_eq!(
+        client.receive::<ServerToClientMessage>().await,
+        Ok(ServerToClientMessage::WorkerList(vec![]))
+    );
+
+    // The fake server stops when the connection is closed.
+    drop(client);
+    assert!(fake.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn message_stream_pair_round_trip() {
+    let (mut a, mut b) = message_stream_pair();
+    a.send(&ClientToServerMessage::Bye).await.unwrap();
+    assert_eq!(
+        b.receive::<ClientToServerMessage>().await,
+        Ok(ClientToServerMessage::Bye)
+    );
+}
+
+#[tokio::test]
+async fn observer_cannot_issue_jobs() {
+    let mut config = Config::new(None).unwrap();
+    config.common_settings.observer_secret = Some("observer".into());
+    let mut server = TestServer::new(config.clone());
+
+    let (client_stream, server_stream) = duplex(1024);
+    server.connect(server_stream).unwrap();
+    let mut client = MessageStream::new(client_stream);
+    client
+        .send(&hello_from_client(BTreeSet::new()))
+        .await
+        .unwrap();
+    client.receive::<ServerToClientMessage>().await.unwrap();
+
+    // Authenticate with the observer secret.
+    client
+        .send(&ClientToServerMessage::AuthRequest)
+        .await
+        .unwrap();
+    let salt = match client.receive::<ServerToClientMessage>().await.unwrap() {
+        ServerToClientMessage::AuthChallenge { salt } => salt,
+        other => panic!("Expected AuthChallenge, received: {:?}", other),
+    };
+    let response = Sha256::digest(format!("observer{salt}"));
+    let response = general_purpose::STANDARD_NO_PAD.encode(response);
+    client
+        .send(&ClientToServerMessage::AuthResponse(response))
+        .await
+        .unwrap();
+    assert_eq!(
+        client.receive::<ServerToClientMessage>().await,
+        Ok(ServerToClientMessage::AuthAccepted(true))
+    );
+
+    // Observers can list workers, but not issue jobs.
+    client
+        .send(&ClientToServerMessage::ListWorkers)
+        .await
+        .unwrap();
+    assert_eq!(
+        client.receive::<ServerToClientMessage>().await,
+        Ok(ServerToClientMessage::WorkerList(vec![]))
+    );
+    let job_info = JobInfo::new(
+        vec!["true".into()],
+        "/tmp".into(),
+        Resources::new(1, 1, 1024),
+        None,
+        None,
+        None,
+    );
+    client
+        .send(&ClientToServerMessage::IssueJob(job_info))
+        .await
+        .unwrap();
+    assert!(matches!(
+        client.receive::<ServerToClientMessage>().await,
+        Ok(ServerToClientMessage::RequestResponse { success: false, .. })
+    ));
+
+    server.stop().await;
+}